@@ -0,0 +1,191 @@
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::HttpMessage;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+
+use crate::auth::Identity;
+use crate::request_id::REQUEST_ID_HEADER;
+
+/// The share of successful requests to a path which get logged, errors are always logged.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SamplingRule {
+    pub path: String,
+    /// Between `0.0` (log none) and `1.0` (log all)
+    pub rate: f64,
+}
+
+impl SamplingRule {
+    pub fn is_valid(&self) -> bool {
+        (0.0..=1.0).contains(&self.rate)
+    }
+}
+
+/// Sampling rules of the access log, which can be changed at runtime.
+pub struct LogSampling {
+    rules: RwLock<Vec<SamplingRule>>,
+}
+
+impl LogSampling {
+    pub fn new(rules: Vec<SamplingRule>) -> Self {
+        Self {
+            rules: RwLock::new(rules),
+        }
+    }
+
+    pub fn rules(&self) -> Vec<SamplingRule> {
+        self.rules.read().unwrap().clone()
+    }
+
+    pub fn set_rules(&self, rules: Vec<SamplingRule>) {
+        *self.rules.write().unwrap() = rules;
+    }
+
+    fn should_log(&self, path: &str, status: u16) -> bool {
+        if status >= 400 {
+            return true;
+        }
+        match self.rules.read().unwrap().iter().find(|r| r.path == path) {
+            Some(rule) => rand::random::<f64>() < rule.rate,
+            None => true,
+        }
+    }
+}
+
+/// Log the requests, in the format `%a "%r" %s %T %{x-request-id}i`, applying the sampling rules.
+///
+/// The route, status, latency in milliseconds, caller, authenticated subject and request id are
+/// fields of the event as well, for the JSON log format. Must run inside the authentication, to
+/// know the identity of the caller.
+pub fn middleware<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+    sampling: Arc<LogSampling>,
+) -> impl Future<Output = Result<ServiceResponse<B>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+{
+    let start = Instant::now();
+    let peer = req
+        .connection_info()
+        .realip_remote_addr()
+        .unwrap_or("-")
+        .to_string();
+    let method = req.method().to_string();
+    let path = req.path().to_string();
+    let line = format!("{} {} {:?}", req.method(), req.uri(), req.version());
+    let request_id = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("-")
+        .to_string();
+    // the cost middleware runs inside, so the caller is not known here yet
+    let caller = crate::cost::caller_of(&req);
+
+    let fut = srv.call(req);
+    async move {
+        let res = fut.await?;
+        let status = res.status().as_u16();
+        let subject = res
+            .request()
+            .extensions()
+            .get::<Identity>()
+            .map(|i| i.subject.clone());
+        if sampling.should_log(&path, status) {
+            let elapsed = start.elapsed().as_secs_f64();
+            let route = res
+                .request()
+                .match_pattern()
+                .unwrap_or_else(|| path.clone());
+            tracing::info!(
+                target: "trust_api::access",
+                peer = %peer,
+                method = %method,
+                path = %path,
+                route = %route,
+                status,
+                latency_ms = elapsed * 1000.0,
+                caller = %caller,
+                subject = subject.as_deref().unwrap_or("-"),
+                request_id = %request_id,
+                "{} \"{}\" {} {:.6} {}",
+                peer,
+                line,
+                status,
+                elapsed,
+                request_id
+            );
+        }
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::{self, TestRequest};
+    use actix_web::{web, App, HttpResponse};
+    use std::io::Write;
+    use std::sync::Mutex;
+
+    /// Collects the output of a subscriber.
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[actix_web::test]
+    async fn json_lines_carry_the_fields_of_the_request() {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        // as installed by `crate::logging::init` for the JSON format
+        let subscriber = tracing_subscriber::fmt()
+            .json()
+            .flatten_event(true)
+            .with_writer(move || writer.clone())
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let sampling = Arc::new(LogSampling::new(vec![]));
+        let app = test::init_service(
+            App::new()
+                .wrap_fn(move |req, srv| middleware(req, srv, sampling.clone()))
+                .route(
+                    "/api/v1/package/{purl}",
+                    web::get().to(|| async { HttpResponse::Ok().finish() }),
+                ),
+        )
+        .await;
+        let request = TestRequest::get()
+            .uri("/api/v1/package/vertx")
+            .insert_header((REQUEST_ID_HEADER, "a-request"))
+            .to_request();
+        test::call_service(&app, request).await;
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let line: serde_json::Value = output
+            .lines()
+            .filter_map(|l| serde_json::from_str(l).ok())
+            .find(|l: &serde_json::Value| l["target"] == "trust_api::access")
+            .expect("an access log line");
+        assert_eq!(line["method"], "GET");
+        assert_eq!(line["path"], "/api/v1/package/vertx");
+        assert_eq!(line["route"], "/api/v1/package/{purl}");
+        assert_eq!(line["status"], 200);
+        assert!(line["latency_ms"].as_f64().is_some());
+        assert_eq!(line["caller"], "anonymous");
+        assert_eq!(line["subject"], "-");
+        assert_eq!(line["request_id"], "a-request");
+        assert!(line["peer"].is_string());
+    }
+}
@@ -0,0 +1,566 @@
+use crate::access_log::{LogSampling, SamplingRule};
+use crate::audit::Audit;
+use crate::auth::Authenticator;
+use crate::cache::{Cache, CacheBackend};
+use crate::cdn::Cdn;
+use crate::ingestion::{Ingestion, IngestionEvent};
+use crate::rbac::Role;
+use crate::replication::Journal;
+use crate::sbom::{SbomRegistry, SbomSource};
+use crate::secrets::{self, Secrets};
+use crate::snyk::SnykClient;
+use crate::subgraph::Subgraphs;
+use crate::webhook::{DeliveryStatus, Webhooks};
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::{
+    delete, error, get, http::header, http::StatusCode, post, put, web, web::ServiceConfig,
+    HttpResponse,
+};
+use chrono::{DateTime, Utc};
+use futures::future::{ready, Either};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::Arc;
+use subtle::ConstantTimeEq;
+use thiserror::Error;
+
+pub(crate) fn configure() -> impl FnOnce(&mut ServiceConfig) {
+    |config: &mut ServiceConfig| {
+        config.service(purge_cache);
+        config.service(stats);
+        config.service(invalidate_subgraphs);
+        config.service(ingested);
+        config.service(purge_cdn);
+        config.service(metrics);
+        config.service(list_deliveries);
+        config.service(replay_deliveries);
+        config.service(list_sbom_sources);
+        config.service(add_sbom_source);
+        config.service(remove_sbom_source);
+        config.service(reindex_sboms);
+        config.service(get_log_sampling);
+        config.service(set_log_sampling);
+        config.service(get_costs);
+        config.service(reset_costs);
+        config.service(replication_delta);
+        config.service(set_snyk_token);
+        config.service(get_audit);
+    }
+}
+
+/// Check the bearer token of a request made to the admin listener.
+///
+/// The admin token is accepted, and ID tokens granting the `admin` role if RBAC is enabled. If
+/// neither an admin token is configured nor RBAC enabled, all requests are accepted.
+pub fn authorize(
+    req: &ServiceRequest,
+    token: Option<&str>,
+    authenticator: Option<&Authenticator>,
+) -> Result<(), ApiError> {
+    if token.is_none() && authenticator.is_none() {
+        return Ok(());
+    }
+
+    let provided = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .ok_or(ApiError::Unauthorized)?;
+
+    if let Some(token) = token {
+        if bool::from(provided.as_bytes().ct_eq(token.as_bytes())) {
+            return Ok(());
+        }
+    }
+    match authenticator.map(|a| a.authenticate(req)) {
+        Some(Ok(identity)) if Role::Admin.is_granted(&identity.roles) => Ok(()),
+        _ => Err(ApiError::Forbidden),
+    }
+}
+
+/// Reject requests to the admin listener which are not [authorized](authorize).
+pub fn middleware<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+    token: Option<&str>,
+    authenticator: Option<&Authenticator>,
+) -> impl Future<Output = Result<ServiceResponse<B>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+{
+    match authorize(&req, token, authenticator) {
+        Ok(()) => Either::Left(srv.call(req)),
+        Err(e) => Either::Right(ready(Err(e.into()))),
+    }
+}
+
+#[post("/api/admin/cache/purge")]
+pub async fn purge_cache(cache: web::Data<Arc<Cache>>) -> Result<HttpResponse, ApiError> {
+    cache.purge().await;
+    log::info!("Cache purged");
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SbomStats {
+    pub total: usize,
+    /// By the id of their source, `builtin` for the SBOMs shipped with the service
+    pub sources: BTreeMap<String, usize>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct CacheStats {
+    pub backend: CacheBackend,
+    /// `None` if the backend could not be asked
+    pub entries: Option<usize>,
+}
+
+/// Calls to an upstream service since the process started.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpstreamStats {
+    pub calls: u64,
+    /// Calls failing after all retries
+    pub failures: u64,
+    pub error_rate: f64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Stats {
+    pub sboms: SbomStats,
+    pub cache: CacheStats,
+    pub upstreams: BTreeMap<String, UpstreamStats>,
+}
+
+/// The state of the registry and the cache, and the error rates of upstream services.
+#[get("/api/admin/stats")]
+pub async fn stats(
+    cache: web::Data<Arc<Cache>>,
+    sboms: web::Data<Arc<SbomRegistry>>,
+) -> Result<HttpResponse, ApiError> {
+    let sources = sboms.counts();
+    let failures = crate::metrics::by_upstream(&crate::metrics::UPSTREAM_FAILURES);
+    let upstreams = crate::metrics::by_upstream(&crate::metrics::UPSTREAM_CALLS)
+        .into_iter()
+        .map(|(upstream, calls)| {
+            let failures = failures.get(&upstream).copied().unwrap_or_default();
+            let error_rate = match calls {
+                0 => 0.0,
+                calls => failures as f64 / calls as f64,
+            };
+            let stats = UpstreamStats {
+                calls,
+                failures,
+                error_rate,
+            };
+            (upstream, stats)
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(Stats {
+        sboms: SbomStats {
+            total: sources.values().sum(),
+            sources,
+        },
+        cache: CacheStats {
+            backend: cache.backend(),
+            entries: cache.len().await,
+        },
+        upstreams,
+    }))
+}
+
+/// Drop cached dependency subgraphs, to be called when documents were ingested into Guac.
+#[post("/api/admin/subgraphs/invalidate")]
+pub async fn invalidate_subgraphs(
+    subgraphs: web::Data<Arc<Subgraphs>>,
+) -> Result<HttpResponse, ApiError> {
+    subgraphs.invalidate().await;
+    log::info!("Cached subgraphs invalidated");
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Invalidate and refresh the cached data of packages, after documents about them were ingested.
+#[post("/api/admin/ingestion")]
+pub async fn ingested(
+    ingestion: web::Data<Arc<Ingestion>>,
+    body: web::Json<IngestionEvent>,
+) -> Result<HttpResponse, ApiError> {
+    let ingestion = ingestion.get_ref().clone();
+    let event = body.into_inner();
+    log::info!("Refreshing {} ingested packages", event.purls.len());
+    tokio::spawn(async move { ingestion.handle(event).await });
+    Ok(HttpResponse::Accepted().finish())
+}
+
+#[derive(serde::Deserialize)]
+pub struct CdnPurgeRequest {
+    #[serde(default)]
+    purls: Vec<String>,
+    /// Purge all responses, instead of those about some purls
+    #[serde(default)]
+    all: bool,
+}
+
+#[post("/api/admin/cdn/purge")]
+pub async fn purge_cdn(
+    cdn: web::Data<Arc<Cdn>>,
+    body: web::Json<CdnPurgeRequest>,
+) -> Result<HttpResponse, ApiError> {
+    if body.all {
+        cdn.purge_all().await;
+    } else {
+        cdn.purge_purls(&body.purls).await;
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[get("/metrics")]
+pub async fn metrics() -> Result<HttpResponse, ApiError> {
+    let metrics = crate::metrics::render().map_err(|_| ApiError::InternalError)?;
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(metrics))
+}
+
+#[derive(serde::Deserialize)]
+pub struct DeliveryQuery {
+    status: Option<DeliveryStatus>,
+}
+
+#[get("/api/admin/webhooks/deliveries")]
+pub async fn list_deliveries(
+    webhooks: web::Data<Arc<Webhooks>>,
+    query: web::Query<DeliveryQuery>,
+) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok().json(webhooks.deliveries(query.status).await))
+}
+
+#[derive(serde::Deserialize)]
+pub struct ReplayRequest {
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+}
+
+#[post("/api/admin/webhooks/replay")]
+pub async fn replay_deliveries(
+    webhooks: web::Data<Arc<Webhooks>>,
+    body: web::Json<ReplayRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let replayed = webhooks
+        .replay(body.from, body.to)
+        .await
+        .map_err(|_| ApiError::InternalError)?;
+    log::info!("Replaying {} webhook deliveries", replayed);
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "replayed": replayed })))
+}
+
+#[get("/api/admin/sbom/sources")]
+pub async fn list_sbom_sources(
+    sboms: web::Data<Arc<SbomRegistry>>,
+) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok().json(sboms.sources()))
+}
+
+/// Add a new SBOM source, indexing it in the background.
+#[post("/api/admin/sbom/sources")]
+pub async fn add_sbom_source(
+    sboms: web::Data<Arc<SbomRegistry>>,
+    body: web::Json<SbomSource>,
+) -> Result<HttpResponse, ApiError> {
+    let source = body.into_inner();
+    if sboms.sources().iter().any(|s| s.id == source.id) {
+        return Err(ApiError::SourceExists { id: source.id });
+    }
+
+    let sboms = sboms.get_ref().clone();
+    let response = HttpResponse::Accepted().json(&source);
+    tokio::spawn(async move {
+        let id = source.id.clone();
+        if let Err(e) = sboms.add_source(source).await {
+            log::warn!("Error indexing SBOM source {}: {:?}", id, e);
+        }
+    });
+    Ok(response)
+}
+
+#[delete("/api/admin/sbom/sources/{id}")]
+pub async fn remove_sbom_source(
+    sboms: web::Data<Arc<SbomRegistry>>,
+    id: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    if sboms.remove_source(&id) {
+        log::info!("Removed SBOM source {}", id);
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Err(ApiError::SourceNotFound {
+            id: id.into_inner(),
+        })
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct ReindexQuery {
+    /// Only reindex the source with this id
+    source: Option<String>,
+}
+
+/// Load the SBOMs of all sources again, or of one, in the background.
+#[post("/api/admin/sbom/reindex")]
+pub async fn reindex_sboms(
+    sboms: web::Data<Arc<SbomRegistry>>,
+    query: web::Query<ReindexQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let source = query.into_inner().source;
+    if let Some(id) = &source {
+        if !sboms.sources().iter().any(|s| &s.id == id) {
+            return Err(ApiError::SourceNotFound { id: id.clone() });
+        }
+    }
+
+    let sboms = sboms.get_ref().clone();
+    tokio::spawn(async move {
+        let indexed = sboms.reindex(source.as_deref()).await;
+        log::info!("Reindexed {} SBOMs", indexed);
+    });
+    Ok(HttpResponse::Accepted().finish())
+}
+
+#[get("/api/admin/logging/sampling")]
+pub async fn get_log_sampling(
+    sampling: web::Data<Arc<LogSampling>>,
+) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok().json(sampling.rules()))
+}
+
+/// Replace the access log sampling rules.
+#[put("/api/admin/logging/sampling")]
+pub async fn set_log_sampling(
+    sampling: web::Data<Arc<LogSampling>>,
+    body: web::Json<Vec<SamplingRule>>,
+) -> Result<HttpResponse, ApiError> {
+    let rules = body.into_inner();
+    if let Some(rule) = rules.iter().find(|r| !r.is_valid()) {
+        return Err(ApiError::InvalidSamplingRate {
+            path: rule.path.clone(),
+        });
+    }
+    log::info!("Updated log sampling rules: {:?}", rules);
+    sampling.set_rules(rules.clone());
+    Ok(HttpResponse::Ok().json(rules))
+}
+
+#[derive(serde::Deserialize)]
+pub struct CostQuery {
+    caller: Option<String>,
+}
+
+/// Report the accumulated cost of provider calls, by caller and provider.
+#[get("/api/admin/costs")]
+pub async fn get_costs(query: web::Query<CostQuery>) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok().json(crate::cost::report(query.caller.as_deref())))
+}
+
+#[delete("/api/admin/costs")]
+pub async fn reset_costs() -> Result<HttpResponse, ApiError> {
+    crate::cost::reset();
+    log::info!("Cost accounting reset");
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(serde::Deserialize)]
+pub struct DeltaQuery {
+    since: Option<DateTime<Utc>>,
+    /// Only the evaluations and SBOMs the callers of the tenant see
+    tenant: Option<String>,
+}
+
+/// The package evaluations and SBOMs changed since a previous delta, for followers to sync.
+#[get("/api/admin/replication/delta")]
+pub async fn replication_delta(
+    journal: web::Data<Option<Arc<Journal>>>,
+    sboms: web::Data<Arc<SbomRegistry>>,
+    query: web::Query<DeltaQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let journal = journal.as_ref().as_ref().ok_or(ApiError::NotPrimary)?;
+    Ok(HttpResponse::Ok().json(journal.delta(query.since, &sboms, query.tenant.as_deref())))
+}
+
+#[derive(serde::Deserialize)]
+pub struct SnykTokenRequest {
+    token: String,
+}
+
+/// Replace the Snyk token, e.g. after rotating it, without restarting.
+///
+/// The token is stored encrypted if a key file is configured, and kept across restarts.
+#[put("/api/admin/snyk/token")]
+pub async fn set_snyk_token(
+    snyk: web::Data<Arc<SnykClient>>,
+    secrets: web::Data<Arc<Secrets>>,
+    body: web::Json<SnykTokenRequest>,
+) -> Result<HttpResponse, ApiError> {
+    if body.token.is_empty() {
+        return Err(ApiError::EmptyToken);
+    }
+    let token = body.into_inner().token;
+    if secrets.is_enabled() {
+        secrets
+            .put(secrets::SNYK_TOKEN, &token)
+            .await
+            .map_err(|e| {
+                log::warn!("Error storing the Snyk token: {:?}", e);
+                ApiError::InternalError
+            })?;
+    }
+    snyk.set_token(token);
+    log::info!("Replaced the Snyk token");
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(serde::Deserialize)]
+pub struct AuditQuery {
+    /// The caller (API key fingerprint) or the subject of the ID token
+    who: Option<String>,
+    purl: Option<String>,
+    #[serde(default = "default_audit_limit")]
+    limit: usize,
+}
+
+fn default_audit_limit() -> usize {
+    100
+}
+
+/// The most recent entries of the audit log, newest first.
+#[get("/api/admin/audit")]
+pub async fn get_audit(
+    audit: web::Data<Arc<Audit>>,
+    query: web::Query<AuditQuery>,
+) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok().json(audit.recent(
+        query.limit,
+        query.who.as_deref(),
+        query.purl.as_deref(),
+    )))
+}
+
+#[derive(Debug, Error, Serialize, Deserialize)]
+pub enum ApiError {
+    #[error("Missing admin credentials")]
+    Unauthorized,
+    #[error("Invalid admin credentials")]
+    Forbidden,
+    #[error("Error processing error internally")]
+    InternalError,
+    #[error("SBOM source {id} already exists")]
+    SourceExists { id: String },
+    #[error("SBOM source {id} was not found")]
+    SourceNotFound { id: String },
+    #[error("Sampling rate of {path} must be between 0 and 1")]
+    InvalidSamplingRate { path: String },
+    #[error("This instance is not a replication primary")]
+    NotPrimary,
+    #[error("The Snyk token must not be empty")]
+    EmptyToken,
+}
+
+impl error::ResponseError for ApiError {
+    fn error_response(&self) -> HttpResponse {
+        crate::problem::response(self.status_code(), self, serde_json::json!({}))
+    }
+
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden => StatusCode::FORBIDDEN,
+            ApiError::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::SourceExists { id: _ } => StatusCode::CONFLICT,
+            ApiError::SourceNotFound { id: _ } => StatusCode::NOT_FOUND,
+            ApiError::InvalidSamplingRate { path: _ } => StatusCode::BAD_REQUEST,
+            ApiError::NotPrimary => StatusCode::NOT_FOUND,
+            ApiError::EmptyToken => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::{AuthConfig, IssuerConfig};
+    use actix_web::{test, App};
+
+    async fn call(
+        token: Option<&'static str>,
+        authenticator: Option<Arc<Authenticator>>,
+        authorization: Option<&str>,
+    ) -> Result<StatusCode, StatusCode> {
+        let app = test::init_service(
+            App::new()
+                .wrap_fn(move |req, srv| middleware(req, srv, token, authenticator.as_deref()))
+                .route(
+                    "/api/admin/stats",
+                    web::get().to(|| async { HttpResponse::Ok().finish() }),
+                ),
+        )
+        .await;
+        let mut req = test::TestRequest::get().uri("/api/admin/stats");
+        if let Some(authorization) = authorization {
+            req = req.insert_header((header::AUTHORIZATION, authorization));
+        }
+        match app.call(req.to_request()).await {
+            Ok(response) => Ok(response.status()),
+            Err(e) => Err(e.as_response_error().status_code()),
+        }
+    }
+
+    fn authenticator() -> Arc<Authenticator> {
+        Arc::new(Authenticator::new(&AuthConfig {
+            issuers: vec![IssuerConfig {
+                issuer: "https://sso.example.com".to_string(),
+                audience: "trust".to_string(),
+                jwks_url: None,
+                roles: Vec::new(),
+                roles_claim: None,
+                tenant: None,
+                algorithms: vec![jsonwebtoken::Algorithm::RS256],
+            }],
+            ..Default::default()
+        }))
+    }
+
+    #[actix_web::test]
+    async fn the_admin_token_is_required() {
+        let token = Some("secret");
+        assert_eq!(
+            call(token, None, Some("Bearer secret")).await,
+            Ok(StatusCode::OK)
+        );
+        assert_eq!(
+            call(token, None, Some("Bearer other")).await,
+            Err(StatusCode::FORBIDDEN)
+        );
+        assert_eq!(
+            call(token, None, Some("secret")).await,
+            Err(StatusCode::UNAUTHORIZED)
+        );
+        assert_eq!(call(token, None, None).await, Err(StatusCode::UNAUTHORIZED));
+    }
+
+    #[actix_web::test]
+    async fn rbac_is_enforced_without_an_admin_token() {
+        assert_eq!(
+            call(None, Some(authenticator()), None).await,
+            Err(StatusCode::UNAUTHORIZED)
+        );
+        assert_eq!(
+            call(None, Some(authenticator()), Some("Bearer not-a-token")).await,
+            Err(StatusCode::FORBIDDEN)
+        );
+        assert_eq!(
+            call(Some("secret"), Some(authenticator()), Some("Bearer secret")).await,
+            Ok(StatusCode::OK)
+        );
+    }
+}
@@ -0,0 +1,466 @@
+use actix_web::{error, http::StatusCode, post, web, web::ServiceConfig, HttpResponse};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use thiserror::Error;
+use utoipa::{OpenApi, ToSchema};
+
+use crate::error::TrustError;
+use crate::package::{batch_item, with_ids, Package, TrustedContent};
+
+pub use trust_api_model::sbom::*;
+
+pub(crate) fn configure() -> impl FnOnce(&mut ServiceConfig) {
+    |config: &mut ServiceConfig| {
+        config.service(analyze_sbom);
+        config.service(annotate_sbom);
+    }
+}
+
+/// The documentation of the SBOM endpoints.
+#[derive(OpenApi)]
+#[openapi(
+    paths(analyze_sbom, annotate_sbom),
+    components(schemas(AffectingVulnerability, SbomAnalysis, ApiError))
+)]
+pub(crate) struct ApiDoc;
+
+/// The namespace of the component properties added by annotating an SBOM.
+const PROPERTY_PREFIX: &str = "trust-api:";
+
+#[derive(serde::Deserialize)]
+pub struct AnalyzeQuery {
+    /// Include the identifiers native to the providers reporting a fact
+    #[serde(default)]
+    include_ids: bool,
+}
+
+/// Analyze all components of an uploaded CycloneDX or SPDX SBOM, as `POST /api/package` does.
+#[utoipa::path(
+    context_path = "/api/v1",
+    request_body(content = serde_json::Value, description = "A CycloneDX or SPDX SBOM, in JSON", example = json!({
+        "bomFormat": "CycloneDX",
+        "specVersion": "1.4",
+        "version": 1,
+        "metadata": {
+            "component": {"type": "application", "name": "my-app", "version": "1.0.0", "purl": "pkg:maven/com.example/my-app@1.0.0"}
+        },
+        "components": [
+            {"type": "library", "name": "openssl", "version": "1.1.1k-7.el8_6", "purl": "pkg:rpm/redhat/openssl@1.1.1k-7.el8_6"},
+            {"type": "library", "name": "zlib", "version": "1.2.11-40.el9", "purl": "pkg:rpm/redhat/zlib@1.2.11-40.el9"}
+        ]
+    })),
+    responses(
+        (status = 200, description = "The analysis of the components", body = SbomAnalysis),
+        (status = BAD_REQUEST, description = "The SBOM lists no components with package URLs", body = Problem, example = json!(crate::problem::example(ApiError::NoComponents))),
+    ),
+    params(
+        ("include_ids" = Option<bool>, Query, description = "Include provider identifiers of vulnerabilities"),
+    )
+)]
+#[post("/sbom/analyze")]
+pub async fn analyze_sbom(
+    data: web::Data<TrustedContent>,
+    query: web::Query<AnalyzeQuery>,
+    body: web::Json<serde_json::Value>,
+) -> Result<HttpResponse, ApiError> {
+    let purls = crate::sbom::component_purls(&body);
+    if purls.is_empty() {
+        return Err(ApiError::NoComponents);
+    }
+    Ok(HttpResponse::Ok().json(analyze(&data, &purls, query.include_ids).await))
+}
+
+/// Look up all purls, consolidating the vulnerabilities affecting them.
+pub(crate) async fn analyze(
+    trusted: &TrustedContent,
+    purls: &[String],
+    include_ids: bool,
+) -> SbomAnalysis {
+    let mut packages = Vec::new();
+    let mut vulnerabilities: BTreeMap<String, AffectingVulnerability> = BTreeMap::new();
+    let mut vulnerable = 0;
+    for purl in purls.iter() {
+        let canonical = trusted.canonicalize(purl);
+        let result = trusted
+            .get_trusted(&canonical)
+            .await
+            .map(|p| with_ids(p, include_ids));
+        if let Ok(package) = &result {
+            if !package.vulnerabilities.is_empty() {
+                vulnerable += 1;
+            }
+            for vuln in package.vulnerabilities.iter() {
+                vulnerabilities
+                    .entry(vuln.cve.clone())
+                    .or_insert_with(|| AffectingVulnerability {
+                        cve: vuln.cve.clone(),
+                        href: vuln.href.clone(),
+                        purls: Vec::new(),
+                    })
+                    .purls
+                    .push(purl.clone());
+            }
+        }
+        packages.push(batch_item(purl, &canonical, result));
+    }
+    trusted.batch().limit(&mut packages);
+
+    SbomAnalysis {
+        components: purls.len(),
+        vulnerable,
+        vulnerabilities: vulnerabilities.into_values().collect(),
+        packages,
+    }
+}
+
+/// Annotate the components of an uploaded CycloneDX SBOM with their trust and vulnerabilities.
+///
+/// Each component with a purl gets `trust-api:` properties, the vulnerabilities affecting
+/// components are merged into the `vulnerabilities` of the document.
+#[utoipa::path(
+    context_path = "/api/v1",
+    request_body(content = serde_json::Value, description = "A CycloneDX SBOM, in JSON", example = json!({
+        "bomFormat": "CycloneDX",
+        "specVersion": "1.4",
+        "version": 1,
+        "metadata": {
+            "component": {"type": "application", "name": "my-app", "version": "1.0.0", "purl": "pkg:maven/com.example/my-app@1.0.0"}
+        },
+        "components": [
+            {"type": "library", "name": "openssl", "version": "1.1.1k-7.el8_6", "purl": "pkg:rpm/redhat/openssl@1.1.1k-7.el8_6"},
+            {"type": "library", "name": "zlib", "version": "1.2.11-40.el9", "purl": "pkg:rpm/redhat/zlib@1.2.11-40.el9"}
+        ]
+    })),
+    responses(
+        (status = 200, description = "The annotated SBOM", body = serde_json::Value, example = json!({
+            "bomFormat": "CycloneDX",
+            "specVersion": "1.4",
+            "version": 1,
+            "components": [
+                {
+                    "bom-ref": "pkg:rpm/redhat/openssl@1.1.1k-7.el8_6",
+                    "type": "library",
+                    "name": "openssl",
+                    "version": "1.1.1k-7.el8_6",
+                    "purl": "pkg:rpm/redhat/openssl@1.1.1k-7.el8_6",
+                    "properties": [
+                        {"name": "trust-api:trusted", "value": "true"},
+                        {"name": "trust-api:vulnerabilities", "value": "1"}
+                    ]
+                }
+            ],
+            "vulnerabilities": [{
+                "id": "cve-2023-0286",
+                "source": {"url": "https://access.redhat.com/security/cve/cve-2023-0286"},
+                "affects": [{"ref": "pkg:rpm/redhat/openssl@1.1.1k-7.el8_6"}]
+            }]
+        })),
+        (status = BAD_REQUEST, description = "Not a CycloneDX SBOM, or no components with package URLs", body = Problem, example = json!(crate::problem::example(ApiError::NotCycloneDx))),
+        (status = UNPROCESSABLE_ENTITY, description = "There are more distinct purls than `api.batch.max_items`", body = Problem, example = json!(crate::problem::example(ApiError::TooManyComponents { count: 1200, limit: 1000 }))),
+    )
+)]
+#[post("/sbom/annotate")]
+pub async fn annotate_sbom(
+    data: web::Data<TrustedContent>,
+    body: web::Json<Value>,
+) -> Result<HttpResponse, ApiError> {
+    let mut document = body.into_inner();
+    if document.get("bomFormat").and_then(|f| f.as_str()) != Some("CycloneDX") {
+        return Err(ApiError::NotCycloneDx);
+    }
+    let mut seen = HashSet::new();
+    let purls: Vec<String> = crate::sbom::component_purls(&document)
+        .into_iter()
+        .filter(|purl| seen.insert(purl.clone()))
+        .collect();
+    if purls.is_empty() {
+        return Err(ApiError::NoComponents);
+    }
+    let limit = data.batch().max_items;
+    if purls.len() > limit {
+        return Err(ApiError::TooManyComponents {
+            count: purls.len(),
+            limit,
+        });
+    }
+
+    let mut results = HashMap::new();
+    for purl in purls {
+        let result = data.get_trusted(&data.canonicalize(&purl)).await;
+        results.insert(purl, result);
+    }
+
+    let mut vulnerabilities = BTreeMap::new();
+    if let Some(components) = document.get_mut("components") {
+        let mut refs = HashSet::new();
+        bom_refs(components, &mut refs);
+        annotate(components, &results, &mut refs, &mut vulnerabilities);
+    }
+    merge_vulnerabilities(&mut document, vulnerabilities);
+    Ok(HttpResponse::Ok().json(document))
+}
+
+/// A vulnerability to record in the SBOM, with the `bom-ref`s of the components it affects.
+struct Affects {
+    href: String,
+    refs: BTreeSet<String>,
+}
+
+fn property(name: &str, value: impl ToString) -> Value {
+    json!({ "name": format!("{}{}", PROPERTY_PREFIX, name), "value": value.to_string() })
+}
+
+fn properties(result: &Result<Package, TrustError>) -> Vec<Value> {
+    let package = match result {
+        Ok(package) => package,
+        Err(e) => return vec![property("error", e)],
+    };
+    let mut properties = Vec::new();
+    if let Some(trusted) = package.trusted {
+        properties.push(property("trusted", trusted));
+    }
+    for version in package.trusted_versions.iter() {
+        properties.push(property("trustedVersion", &version.purl));
+    }
+    properties.push(property("vulnerabilities", package.vulnerabilities.len()));
+    for provider in package.degraded.iter() {
+        properties.push(property("degraded", provider));
+    }
+    properties
+}
+
+/// Collect the `bom-ref`s the components already have, recursing into nested components.
+fn bom_refs(components: &Value, refs: &mut HashSet<String>) {
+    for component in components.as_array().into_iter().flatten() {
+        if let Some(bom_ref) = component.get("bom-ref").and_then(|r| r.as_str()) {
+            refs.insert(bom_ref.to_string());
+        }
+        if let Some(nested) = component.get("components") {
+            bom_refs(nested, refs);
+        }
+    }
+}
+
+/// A `bom-ref` for a component without one: its purl, or if another component uses that
+/// already, the purl with the first free `~<n>` suffix.
+fn new_bom_ref(purl: &str, refs: &mut HashSet<String>) -> String {
+    let mut bom_ref = purl.to_string();
+    let mut n = 2;
+    while refs.contains(&bom_ref) {
+        bom_ref = format!("{}~{}", purl, n);
+        n += 1;
+    }
+    refs.insert(bom_ref.clone());
+    bom_ref
+}
+
+/// Replace the `trust-api:` properties of all components, recursing into nested components.
+///
+/// `refs` are the `bom-ref`s in use, those given to components without one are added.
+fn annotate(
+    components: &mut Value,
+    results: &HashMap<String, Result<Package, TrustError>>,
+    refs: &mut HashSet<String>,
+    vulnerabilities: &mut BTreeMap<String, Affects>,
+) {
+    for component in components
+        .as_array_mut()
+        .into_iter()
+        .flatten()
+        .filter_map(|c| c.as_object_mut())
+    {
+        let purl = component
+            .get("purl")
+            .and_then(|p| p.as_str())
+            .map(ToString::to_string);
+        if let Some((purl, result)) = purl.and_then(|p| results.get(&p).map(|r| (p, r))) {
+            let bom_ref = match component.get("bom-ref").and_then(|r| r.as_str()) {
+                Some(bom_ref) => bom_ref.to_string(),
+                None => new_bom_ref(&purl, refs),
+            };
+            component.insert("bom-ref".to_string(), json!(bom_ref));
+
+            let mut annotated: Vec<Value> = component
+                .get("properties")
+                .and_then(|p| p.as_array())
+                .into_iter()
+                .flatten()
+                .filter(|p| {
+                    !p.get("name")
+                        .and_then(|n| n.as_str())
+                        .map_or(false, |n| n.starts_with(PROPERTY_PREFIX))
+                })
+                .cloned()
+                .collect();
+            annotated.extend(properties(result));
+            component.insert("properties".to_string(), Value::Array(annotated));
+
+            for vuln in result.iter().flat_map(|p| p.vulnerabilities.iter()) {
+                vulnerabilities
+                    .entry(vuln.cve.clone())
+                    .or_insert_with(|| Affects {
+                        href: vuln.href.clone(),
+                        refs: BTreeSet::new(),
+                    })
+                    .refs
+                    .insert(bom_ref.clone());
+            }
+        }
+        if let Some(nested) = component.get_mut("components") {
+            annotate(nested, results, refs, vulnerabilities);
+        }
+    }
+}
+
+/// Add the vulnerabilities to those of the document, extending the `affects` of known ones.
+fn merge_vulnerabilities(document: &mut Value, vulnerabilities: BTreeMap<String, Affects>) {
+    if vulnerabilities.is_empty() {
+        return;
+    }
+    let existing = match document.as_object_mut().and_then(|d| {
+        d.entry("vulnerabilities")
+            .or_insert_with(|| json!([]))
+            .as_array_mut()
+    }) {
+        Some(existing) => existing,
+        None => return,
+    };
+
+    for (cve, affects) in vulnerabilities {
+        let refs = affects.refs.iter().map(|r| json!({ "ref": r }));
+        match existing
+            .iter_mut()
+            .find(|v| v.get("id").and_then(|id| id.as_str()) == Some(cve.as_str()))
+        {
+            Some(vuln) => {
+                let entry = vuln
+                    .as_object_mut()
+                    .map(|v| v.entry("affects").or_insert_with(|| json!([])));
+                if let Some(known) = entry.and_then(|a| a.as_array_mut()) {
+                    for r in refs {
+                        if !known.contains(&r) {
+                            known.push(r);
+                        }
+                    }
+                }
+            }
+            None => existing.push(json!({
+                "id": cve,
+                "source": { "url": affects.href },
+                "affects": refs.collect::<Vec<_>>(),
+            })),
+        }
+    }
+}
+
+#[derive(Debug, Error, Serialize, Deserialize, ToSchema)]
+#[schema(as = AnalyzeApiError)]
+pub enum ApiError {
+    #[error("The SBOM lists no components with package URLs")]
+    NoComponents,
+    #[error("Only CycloneDX SBOMs can be annotated")]
+    NotCycloneDx,
+    #[error("{count} components are more than the {limit} looked up at once")]
+    TooManyComponents { count: usize, limit: usize },
+}
+
+impl error::ResponseError for ApiError {
+    fn error_response(&self) -> HttpResponse {
+        crate::problem::response(self.status_code(), self, serde_json::json!({}))
+    }
+
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::NoComponents => StatusCode::BAD_REQUEST,
+            ApiError::NotCycloneDx => StatusCode::BAD_REQUEST,
+            ApiError::TooManyComponents { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn package(cves: &[&str]) -> Result<Package, TrustError> {
+        let vulnerabilities: Vec<Value> = cves
+            .iter()
+            .map(|cve| json!({ "cve": cve, "href": format!("https://example.com/{}", cve) }))
+            .collect();
+        Ok(
+            serde_json::from_value(json!({ "trusted": false, "vulnerabilities": vulnerabilities }))
+                .unwrap(),
+        )
+    }
+
+    #[test]
+    fn components_sharing_a_purl_get_their_own_bom_refs() {
+        let purl = "pkg:npm/foo@1.0.0";
+        let mut document = json!({
+            "bomFormat": "CycloneDX",
+            "components": [
+                {"name": "foo", "purl": purl},
+                {"name": "foo", "purl": purl, "components": [{"name": "foo", "purl": purl}]},
+                {"name": "bar", "bom-ref": format!("{}~2", purl), "purl": "pkg:npm/bar@1.0.0"},
+                {"name": "baz", "bom-ref": "baz", "purl": purl},
+            ],
+        });
+        let results = HashMap::from([
+            (purl.to_string(), package(&["cve-2023-0001"])),
+            ("pkg:npm/bar@1.0.0".to_string(), package(&[])),
+        ]);
+
+        let mut vulnerabilities = BTreeMap::new();
+        let components = document.get_mut("components").unwrap();
+        let mut refs = HashSet::new();
+        bom_refs(components, &mut refs);
+        annotate(components, &results, &mut refs, &mut vulnerabilities);
+
+        let components = &document["components"];
+        assert_eq!(components[0]["bom-ref"], purl);
+        assert_eq!(components[1]["bom-ref"], format!("{}~3", purl));
+        assert_eq!(
+            components[1]["components"][0]["bom-ref"],
+            format!("{}~4", purl)
+        );
+        assert_eq!(components[2]["bom-ref"], format!("{}~2", purl));
+        assert_eq!(components[3]["bom-ref"], "baz");
+
+        merge_vulnerabilities(&mut document, vulnerabilities);
+        let affects: Vec<&str> = document["vulnerabilities"][0]["affects"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|a| a["ref"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            affects,
+            [
+                "baz".to_string(),
+                purl.to_string(),
+                format!("{}~3", purl),
+                format!("{}~4", purl)
+            ]
+        );
+    }
+
+    #[test]
+    fn known_vulnerabilities_are_extended_once() {
+        let mut document = json!({
+            "vulnerabilities": [{"id": "cve-2023-0001", "affects": [{"ref": "a"}]}],
+        });
+        let vulnerabilities = BTreeMap::from([(
+            "cve-2023-0001".to_string(),
+            Affects {
+                href: "https://example.com/cve-2023-0001".to_string(),
+                refs: BTreeSet::from(["a".to_string(), "b".to_string()]),
+            },
+        )]);
+        merge_vulnerabilities(&mut document, vulnerabilities);
+        assert_eq!(
+            document["vulnerabilities"],
+            json!([{"id": "cve-2023-0001", "affects": [{"ref": "a"}, {"ref": "b"}]}])
+        );
+    }
+}
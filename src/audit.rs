@@ -0,0 +1,369 @@
+use actix_web::dev::{Payload, Service, ServiceRequest, ServiceResponse};
+use actix_web::error::PayloadError;
+use actix_web::http::Method;
+use actix_web::web::Bytes;
+use actix_web::HttpMessage;
+use chrono::{DateTime, Utc};
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+
+use crate::auth::Identity;
+use crate::request_id::REQUEST_ID_HEADER;
+
+/// Recording who queried which packages and uploaded which SBOMs.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuditConfig {
+    pub enabled: bool,
+    /// Where entries are written, they are only kept in memory if unset
+    pub sink: Option<AuditSink>,
+    /// How many recent entries are kept in memory, for `GET /api/admin/audit`
+    pub recent: usize,
+    /// How many entries wait to be written to the sink at most, more are dropped
+    pub queue: usize,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            sink: None,
+            recent: 1000,
+            queue: 10000,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum AuditSink {
+    /// One JSON entry per line, appended to a file
+    File { path: PathBuf },
+    /// Requires the `kafka` feature
+    Kafka { brokers: String, topic: String },
+}
+
+impl AuditSink {
+    /// Whether support for the configured sink was built in.
+    pub fn is_supported(&self) -> bool {
+        match self {
+            Self::File { .. } => true,
+            Self::Kafka { .. } => cfg!(feature = "kafka"),
+        }
+    }
+}
+
+/// A request to the public API, and who made it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub time: DateTime<Utc>,
+    /// The fingerprint of the API key, or `anonymous`
+    pub caller: String,
+    /// The subject of the ID token, if authenticated
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub issuer: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    pub method: String,
+    pub path: String,
+    pub status: u16,
+    /// The purls of the query
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub purls: Vec<String>,
+    /// The SHA-256 digest of the request body, as far as the endpoint read it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body_digest: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+enum Sink {
+    File(tokio::fs::File),
+    #[cfg(feature = "kafka")]
+    Kafka {
+        producer: rdkafka::producer::FutureProducer,
+        topic: String,
+    },
+}
+
+impl Sink {
+    async fn new(sink: &AuditSink) -> Result<Self, anyhow::Error> {
+        match sink {
+            AuditSink::File { path } => Ok(Self::File(
+                tokio::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .await?,
+            )),
+            #[cfg(feature = "kafka")]
+            AuditSink::Kafka { brokers, topic } => Ok(Self::Kafka {
+                producer: rdkafka::ClientConfig::new()
+                    .set("bootstrap.servers", brokers)
+                    .create()?,
+                topic: topic.clone(),
+            }),
+            #[allow(unreachable_patterns)]
+            sink => anyhow::bail!("Auditing to {:?} is not supported by this build", sink),
+        }
+    }
+
+    /// Write the entries sent to the channel, until it is closed.
+    async fn run(mut self, mut entries: mpsc::Receiver<AuditEntry>) {
+        while let Some(entry) = entries.recv().await {
+            if let Err(e) = self.write(&entry).await {
+                log::warn!("Error writing audit entry of {}: {:?}", entry.path, e);
+            }
+        }
+    }
+
+    async fn write(&mut self, entry: &AuditEntry) -> Result<(), anyhow::Error> {
+        match self {
+            Self::File(file) => {
+                let mut line = serde_json::to_vec(entry)?;
+                line.push(b'\n');
+                file.write_all(&line).await?;
+                file.flush().await?;
+                Ok(())
+            }
+            #[cfg(feature = "kafka")]
+            Self::Kafka { producer, topic } => {
+                let payload = serde_json::to_vec(entry)?;
+                producer
+                    .send(
+                        rdkafka::producer::FutureRecord::to(topic.as_str())
+                            .key(&entry.caller)
+                            .payload(&payload),
+                        std::time::Duration::from_secs(5),
+                    )
+                    .await
+                    .map_err(|(e, _)| e)?;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The audit log, kept in memory for the admin API and written to the configured sink by a task
+/// of its own, so requests don't wait for it.
+pub struct Audit {
+    enabled: bool,
+    limit: usize,
+    recent: Mutex<VecDeque<AuditEntry>>,
+    sink: Option<mpsc::Sender<AuditEntry>>,
+}
+
+impl Audit {
+    pub async fn new(config: &AuditConfig) -> Result<Self, anyhow::Error> {
+        let sink = match (&config.sink, config.enabled) {
+            (Some(sink), true) => {
+                let sink = Sink::new(sink).await?;
+                let (sender, receiver) = mpsc::channel(config.queue.max(1));
+                tokio::spawn(sink.run(receiver));
+                Some(sender)
+            }
+            _ => None,
+        };
+        Ok(Self {
+            enabled: config.enabled,
+            limit: config.recent,
+            recent: Mutex::new(VecDeque::new()),
+            sink,
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Record an entry, it is dropped with a warning if the sink falls behind.
+    pub fn record(&self, entry: AuditEntry) {
+        if let Some(sink) = &self.sink {
+            if let Err(e) = sink.try_send(entry.clone()) {
+                log::warn!("Dropping the audit entry of {}: {}", entry.path, e);
+            }
+        }
+        let mut recent = self.recent.lock().unwrap();
+        recent.push_back(entry);
+        let excess = recent.len().saturating_sub(self.limit);
+        recent.drain(..excess);
+    }
+
+    /// The most recent entries, newest first, optionally of a caller or subject, or about a purl.
+    pub fn recent(&self, limit: usize, who: Option<&str>, purl: Option<&str>) -> Vec<AuditEntry> {
+        self.recent
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .filter(|e| {
+                who.map_or(true, |who| {
+                    e.caller == who || e.subject.as_deref() == Some(who)
+                })
+            })
+            .filter(|e| purl.map_or(true, |purl| e.purls.iter().any(|p| p == purl)))
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+}
+
+#[derive(Deserialize)]
+struct PurlQuery {
+    purl: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+}
+
+/// Record the requests to `/api/`, with the purls of their query and the digest of their body, if
+/// enabled.
+///
+/// Must run inside the authentication, to know the identity of the caller.
+pub fn middleware<S, B>(
+    mut req: ServiceRequest,
+    srv: &S,
+    audit: Arc<Audit>,
+) -> impl Future<Output = Result<ServiceResponse<B>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+{
+    let audited = audit.is_enabled() && req.path().starts_with("/api/");
+    let digest = Arc::new(Mutex::new(None::<Sha256>));
+    if audited && req.method() == Method::POST {
+        // digest the body while the endpoint reads it, without buffering it
+        let hasher = digest.clone();
+        let payload = req.take_payload().map(move |chunk| {
+            if let Ok(chunk) = &chunk {
+                hasher
+                    .lock()
+                    .unwrap()
+                    .get_or_insert_with(Sha256::new)
+                    .update(chunk);
+            }
+            chunk
+        });
+        let payload: Pin<Box<dyn Stream<Item = Result<Bytes, PayloadError>>>> = Box::pin(payload);
+        req.set_payload(Payload::from(payload));
+    }
+
+    let entry = audited.then(|| {
+        let identity = req.extensions().get::<Identity>().cloned();
+        let mut purls = Vec::new();
+        if let Ok(query) = actix_web::web::Query::<PurlQuery>::from_query(req.query_string()) {
+            let query = query.into_inner();
+            purls.extend([query.purl, query.from, query.to].into_iter().flatten());
+        }
+        AuditEntry {
+            time: Utc::now(),
+            caller: crate::cost::caller(),
+            subject: identity.as_ref().map(|i| i.subject.clone()),
+            issuer: identity.as_ref().map(|i| i.issuer.clone()),
+            tenant: identity.and_then(|i| i.tenant),
+            method: req.method().to_string(),
+            path: req.path().to_string(),
+            status: 0,
+            purls,
+            body_digest: None,
+            request_id: req
+                .headers()
+                .get(REQUEST_ID_HEADER)
+                .and_then(|v| v.to_str().ok())
+                .map(ToString::to_string),
+        }
+    });
+
+    let fut = srv.call(req);
+    async move {
+        let res = fut.await?;
+        if let Some(mut entry) = entry {
+            entry.status = res.status().as_u16();
+            let hasher = digest.lock().unwrap().take();
+            entry.body_digest = hasher.map(|hasher| hex::encode(hasher.finalize()));
+            audit.record(entry);
+        }
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str) -> AuditEntry {
+        AuditEntry {
+            time: Utc::now(),
+            caller: "anonymous".to_string(),
+            subject: None,
+            issuer: None,
+            tenant: None,
+            method: "GET".to_string(),
+            path: path.to_string(),
+            status: 200,
+            purls: vec![],
+            body_digest: None,
+            request_id: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn entries_are_written_in_the_background() {
+        let path = std::env::temp_dir().join(format!("trust-api-audit-{}", uuid::Uuid::new_v4()));
+        let audit = Audit::new(&AuditConfig {
+            enabled: true,
+            sink: Some(AuditSink::File { path: path.clone() }),
+            recent: 1,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        audit.record(entry("/api/v1/a"));
+        audit.record(entry("/api/v1/b"));
+
+        let paths: Vec<_> = audit
+            .recent(10, None, None)
+            .into_iter()
+            .map(|e| e.path)
+            .collect();
+        assert_eq!(paths, ["/api/v1/b"]);
+        let mut written = Vec::new();
+        for _ in 0..100 {
+            written = std::fs::read_to_string(&path)
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str::<AuditEntry>(line).unwrap().path)
+                .collect();
+            if written.len() == 2 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(written, ["/api/v1/a", "/api/v1/b"]);
+        std::fs::remove_file(path).ok();
+    }
+
+    #[tokio::test]
+    async fn entries_are_dropped_if_the_sink_falls_behind() {
+        let (sender, _receiver) = mpsc::channel(1);
+        let audit = Audit {
+            enabled: true,
+            limit: 10,
+            recent: Mutex::new(VecDeque::new()),
+            sink: Some(sender.clone()),
+        };
+        audit.record(entry("/api/v1/a"));
+        audit.record(entry("/api/v1/b"));
+        // the queue holds one entry, the others are still kept in memory
+        assert_eq!(sender.capacity(), 0);
+        assert_eq!(audit.recent(10, None, None).len(), 2);
+    }
+}
@@ -0,0 +1,304 @@
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::{error, http::header, http::Method, http::StatusCode, HttpMessage, HttpResponse};
+use base64::Engine;
+use futures::future::{ready, Either};
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use thiserror::Error;
+
+use crate::rbac::{RbacConfig, RoleRule};
+
+/// Accepted identity providers, the public API requires no authentication if there are none.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    pub issuers: Vec<IssuerConfig>,
+    /// How often the signing keys of the issuers are refreshed
+    pub refresh_secs: u64,
+    pub rbac: RbacConfig,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            issuers: Vec::new(),
+            refresh_secs: 300,
+            rbac: Default::default(),
+        }
+    }
+}
+
+/// An OIDC issuer whose ID tokens are accepted, and what its users may access.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct IssuerConfig {
+    /// The `iss` claim of its tokens
+    pub issuer: String,
+    /// The `aud` claim its tokens must contain
+    pub audience: String,
+    /// Where to find the signing keys, discovered from the issuer by default
+    #[serde(default)]
+    pub jwks_url: Option<String>,
+    /// Roles granted to all users of the issuer
+    #[serde(default)]
+    pub roles: Vec<String>,
+    /// A claim listing additional roles of a user (e.g. `groups`)
+    #[serde(default)]
+    pub roles_claim: Option<String>,
+    /// The tenant users of the issuer belong to
+    #[serde(default)]
+    pub tenant: Option<String>,
+    /// The signature algorithms its tokens may use, whatever their header claims
+    #[serde(default = "default_algorithms")]
+    pub algorithms: Vec<Algorithm>,
+}
+
+fn default_algorithms() -> Vec<Algorithm> {
+    vec![Algorithm::RS256]
+}
+
+/// The authenticated user of a request, available from the request extensions.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Identity {
+    pub issuer: String,
+    pub subject: String,
+    pub roles: Vec<String>,
+    pub tenant: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Discovery {
+    jwks_uri: String,
+}
+
+struct Issuer {
+    config: IssuerConfig,
+    keys: RwLock<JwkSet>,
+}
+
+/// Validates ID tokens of multiple OIDC issuers.
+///
+/// Signing keys are fetched in the background, so requests are validated without waiting for
+/// the issuers.
+pub struct Authenticator {
+    issuers: Vec<Issuer>,
+    rules: Vec<RoleRule>,
+    client: reqwest::Client,
+}
+
+impl Authenticator {
+    pub fn new(config: &AuthConfig) -> Self {
+        Self {
+            issuers: config
+                .issuers
+                .iter()
+                .map(|config| Issuer {
+                    config: config.clone(),
+                    keys: RwLock::new(JwkSet { keys: Vec::new() }),
+                })
+                .collect(),
+            rules: config.rbac.rules.clone(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.issuers.is_empty()
+    }
+
+    /// Refresh the signing keys of all issuers periodically.
+    pub async fn run(self: Arc<Self>, interval: Duration) {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+            for issuer in self.issuers.iter() {
+                match self.fetch_keys(&issuer.config).await {
+                    Ok(keys) => *issuer.keys.write().unwrap() = keys,
+                    Err(e) => log::warn!(
+                        "Error fetching signing keys of {}: {:?}",
+                        issuer.config.issuer,
+                        e
+                    ),
+                }
+            }
+        }
+    }
+
+    async fn fetch_keys(&self, config: &IssuerConfig) -> Result<JwkSet, anyhow::Error> {
+        let url = match &config.jwks_url {
+            Some(url) => url.clone(),
+            None => {
+                let discovery: Discovery = self
+                    .client
+                    .get(format!(
+                        "{}/.well-known/openid-configuration",
+                        config.issuer.trim_end_matches('/')
+                    ))
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await?;
+                discovery.jwks_uri
+            }
+        };
+        Ok(self
+            .client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+
+    pub(crate) fn authenticate(&self, req: &ServiceRequest) -> Result<Identity, ApiError> {
+        self.authenticate_header(
+            req.headers()
+                .get(header::AUTHORIZATION)
+                .and_then(|v| v.to_str().ok()),
+        )
+    }
+
+    /// Validate the bearer token of an `Authorization` header, of HTTP or gRPC requests.
+    pub(crate) fn authenticate_header(
+        &self,
+        authorization: Option<&str>,
+    ) -> Result<Identity, ApiError> {
+        let token = authorization
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .ok_or(ApiError::Unauthorized)?;
+
+        // the issuer decides which keys the signature is checked with
+        let iss = unverified_issuer(token).ok_or(ApiError::InvalidToken)?;
+        let issuer = self
+            .issuers
+            .iter()
+            .find(|i| i.config.issuer == iss)
+            .ok_or(ApiError::UnknownIssuer)?;
+
+        let header = jsonwebtoken::decode_header(token).map_err(|_| ApiError::InvalidToken)?;
+        if !issuer.config.algorithms.contains(&header.alg) {
+            return Err(ApiError::InvalidToken);
+        }
+        let key = {
+            let keys = issuer.keys.read().unwrap();
+            // without a key id, the key is only unambiguous if the issuer has a single one
+            let jwk = match &header.kid {
+                Some(kid) => keys.find(kid),
+                None if keys.keys.len() == 1 => keys.keys.first(),
+                None => None,
+            }
+            .filter(|jwk| jwk.common.algorithm.map_or(true, |alg| alg == header.alg))
+            .ok_or(ApiError::InvalidToken)?;
+            DecodingKey::from_jwk(jwk).map_err(|_| ApiError::InvalidToken)?
+        };
+
+        let mut validation = Validation::new(header.alg);
+        validation.algorithms = issuer.config.algorithms.clone();
+        validation.set_issuer(&[&issuer.config.issuer]);
+        validation.set_audience(&[&issuer.config.audience]);
+        let claims = jsonwebtoken::decode::<serde_json::Value>(token, &key, &validation)
+            .map_err(|_| ApiError::InvalidToken)?
+            .claims;
+
+        let mut roles = issuer.config.roles.clone();
+        if let Some(claim) = &issuer.config.roles_claim {
+            roles.extend(
+                claims
+                    .get(claim)
+                    .and_then(|r| r.as_array())
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|r| r.as_str().map(ToString::to_string)),
+            );
+        }
+        for rule in self.rules.iter() {
+            if rule.matches(&issuer.config.issuer, &claims) {
+                roles.extend(rule.roles.iter().cloned());
+            }
+        }
+        roles.sort();
+        roles.dedup();
+        Ok(Identity {
+            issuer: issuer.config.issuer.clone(),
+            subject: claims
+                .get("sub")
+                .and_then(|s| s.as_str())
+                .unwrap_or_default()
+                .to_string(),
+            roles,
+            tenant: issuer.config.tenant.clone(),
+        })
+    }
+}
+
+/// The `iss` claim of a token, before its signature was checked.
+fn unverified_issuer(token: &str) -> Option<String> {
+    let payload = token.split('.').nth(1)?;
+    let payload = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(payload)
+        .ok()?;
+    let claims: serde_json::Value = serde_json::from_slice(&payload).ok()?;
+    claims.get("iss")?.as_str().map(ToString::to_string)
+}
+
+/// Require a valid ID token for requests to `/api/`, if issuers are configured.
+pub fn middleware<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+    authenticator: &Authenticator,
+) -> impl Future<Output = Result<ServiceResponse<B>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+{
+    // preflight requests carry no credentials
+    if !authenticator.is_enabled()
+        || req.method() == Method::OPTIONS
+        || !req.path().starts_with("/api/")
+    {
+        return Either::Left(Either::Left(srv.call(req)));
+    }
+    match authenticator.authenticate(&req) {
+        Ok(identity) => {
+            log::debug!(
+                "Authenticated {} of {} with roles {:?} (tenant {:?})",
+                identity.subject,
+                identity.issuer,
+                identity.roles,
+                identity.tenant
+            );
+            let tenant = identity.tenant.clone();
+            req.extensions_mut().insert(identity);
+            Either::Left(Either::Right(crate::tenant::scoped(tenant, srv.call(req))))
+        }
+        Err(e) => Either::Right(ready(Err(e.into()))),
+    }
+}
+
+#[derive(Debug, Error, Serialize, Deserialize)]
+pub enum ApiError {
+    #[error("Missing bearer token")]
+    Unauthorized,
+    #[error("Invalid bearer token")]
+    InvalidToken,
+    #[error("Tokens of this issuer are not accepted")]
+    UnknownIssuer,
+}
+
+impl error::ResponseError for ApiError {
+    fn error_response(&self) -> HttpResponse {
+        crate::problem::response(self.status_code(), self, serde_json::json!({}))
+    }
+
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ApiError::InvalidToken => StatusCode::UNAUTHORIZED,
+            ApiError::UnknownIssuer => StatusCode::UNAUTHORIZED,
+        }
+    }
+}
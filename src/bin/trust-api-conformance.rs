@@ -0,0 +1,414 @@
+//! Verifies the endpoint contracts of a deployed instance, so mirrors and forks can check they
+//! stay compatible.
+
+use anyhow::{anyhow, bail, ensure};
+use clap::Parser;
+use reqwest::{header, StatusCode};
+use serde::de::DeserializeOwned;
+use std::process::ExitCode;
+use trust_api_model::prelude::*;
+
+const PROBLEM_JSON: &str = "application/problem+json";
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+#[derive(clap::Parser, Debug)]
+#[command(
+    author,
+    version = env!("CARGO_PKG_VERSION"),
+    about = "Verify the API contracts of a trusted content API instance",
+    long_about = None
+)]
+struct Cli {
+    /// Base URL of the instance, e.g. `http://localhost:8080`
+    url: String,
+
+    /// A package URL the instance knows
+    #[arg(
+        long = "purl",
+        default_value = "pkg:maven/io.vertx/vertx-web@4.3.4.redhat-00007"
+    )]
+    purl: String,
+
+    /// Sent as bearer token, for instances requiring authentication
+    #[arg(long = "token")]
+    token: Option<String>,
+}
+
+struct Response {
+    status: StatusCode,
+    headers: header::HeaderMap,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn expect_status(&self, status: StatusCode) -> anyhow::Result<&Self> {
+        ensure!(
+            self.status == status,
+            "expected status {}, got {}: {}",
+            status,
+            self.status,
+            String::from_utf8_lossy(&self.body)
+        );
+        Ok(self)
+    }
+
+    fn json<T: DeserializeOwned>(&self) -> anyhow::Result<T> {
+        serde_json::from_slice(&self.body)
+            .map_err(|e| anyhow!("response does not match the schema: {}", e))
+    }
+
+    /// Check the body is an RFC 7807 problem for the status.
+    fn expect_problem(&self, status: StatusCode) -> anyhow::Result<()> {
+        self.expect_status(status)?;
+        let content_type = self
+            .headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or_default();
+        ensure!(
+            content_type.starts_with(PROBLEM_JSON),
+            "expected content type {}, got {}",
+            PROBLEM_JSON,
+            content_type
+        );
+        let problem: serde_json::Value = self.json()?;
+        for member in ["type", "title", "detail"] {
+            ensure!(
+                problem.get(member).map_or(false, |m| m.is_string()),
+                "problem has no {} member",
+                member
+            );
+        }
+        ensure!(
+            problem.get("status").and_then(|s| s.as_u64()) == Some(status.as_u16().into()),
+            "problem status does not match the response status"
+        );
+        ensure!(
+            problem.get("error") == problem.get("detail"),
+            "problem error member does not match its detail"
+        );
+        Ok(())
+    }
+}
+
+struct Conformance {
+    client: reqwest::Client,
+    url: String,
+    token: Option<String>,
+    purl: String,
+}
+
+impl Conformance {
+    async fn send(&self, request: reqwest::RequestBuilder) -> anyhow::Result<Response> {
+        let request = match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        };
+        let response = request.send().await?;
+        Ok(Response {
+            status: response.status(),
+            headers: response.headers().clone(),
+            body: response.bytes().await?.to_vec(),
+        })
+    }
+
+    async fn get(&self, path: &str) -> anyhow::Result<Response> {
+        self.send(self.client.get(format!("{}{}", self.url, path)))
+            .await
+    }
+
+    async fn post(&self, path: &str, body: &serde_json::Value) -> anyhow::Result<Response> {
+        self.send(self.client.post(format!("{}{}", self.url, path)).json(body))
+            .await
+    }
+
+    fn package_path(&self, purl: &str) -> String {
+        format!("/api/v1/package?purl={}", urlencoding::encode(purl))
+    }
+
+    async fn liveness(&self) -> anyhow::Result<()> {
+        self.get("/health/live")
+            .await?
+            .expect_status(StatusCode::OK)?;
+        Ok(())
+    }
+
+    async fn capabilities(&self) -> anyhow::Result<()> {
+        let capabilities: serde_json::Value = self
+            .get("/api/v1")
+            .await?
+            .expect_status(StatusCode::OK)?
+            .json()?;
+        for member in ["providers", "features", "limits", "links"] {
+            ensure!(
+                capabilities.get(member).map_or(false, |m| m.is_object()),
+                "capabilities have no {} object",
+                member
+            );
+        }
+        Ok(())
+    }
+
+    async fn openapi(&self) -> anyhow::Result<()> {
+        let openapi: serde_json::Value = self
+            .get("/openapi.json")
+            .await?
+            .expect_status(StatusCode::OK)?
+            .json()?;
+        ensure!(
+            openapi.get("openapi").map_or(false, |v| v.is_string()),
+            "no openapi version"
+        );
+        // every resource the capability document links to is documented
+        let capabilities: serde_json::Value = self
+            .get("/api/v1")
+            .await?
+            .expect_status(StatusCode::OK)?
+            .json()?;
+        let links = capabilities["links"]
+            .as_object()
+            .ok_or_else(|| anyhow!("capabilities have no links object"))?;
+        let mut paths = vec!["/api/v1".to_string()];
+        for link in links.values().filter_map(|link| link.as_str()) {
+            paths.push(reqwest::Url::parse(link)?.path().to_string());
+        }
+        for path in paths.iter().filter(|path| *path != "/openapi.json") {
+            ensure!(
+                openapi
+                    .pointer(&format!("/paths/{}", path.replace('/', "~1")))
+                    .is_some(),
+                "{} is not documented",
+                path
+            );
+        }
+        Ok(())
+    }
+
+    async fn request_id(&self) -> anyhow::Result<()> {
+        let id = "trust-api-conformance";
+        let response = self
+            .send(
+                self.client
+                    .get(format!("{}/health/live", self.url))
+                    .header(REQUEST_ID_HEADER, id),
+            )
+            .await?;
+        ensure!(
+            response
+                .headers
+                .get(REQUEST_ID_HEADER)
+                .and_then(|h| h.to_str().ok())
+                == Some(id),
+            "the request id is not echoed"
+        );
+        Ok(())
+    }
+
+    async fn package(&self) -> anyhow::Result<()> {
+        let package: Package = self
+            .get(&self.package_path(&self.purl))
+            .await?
+            .expect_status(StatusCode::OK)?
+            .json()?;
+        ensure!(
+            package.purl.as_deref() == Some(self.purl.as_str()),
+            "the package is {:?}, not {}",
+            package.purl,
+            self.purl
+        );
+        Ok(())
+    }
+
+    async fn package_missing_purl(&self) -> anyhow::Result<()> {
+        self.get("/api/v1/package")
+            .await?
+            .expect_problem(StatusCode::BAD_REQUEST)
+    }
+
+    async fn package_invalid_purl(&self) -> anyhow::Result<()> {
+        self.get(&self.package_path("not-a-purl"))
+            .await?
+            .expect_problem(StatusCode::BAD_REQUEST)
+    }
+
+    /// Unknown packages are not found, unless the instance is configured to answer them as empty.
+    async fn package_unknown(&self) -> anyhow::Result<()> {
+        let response = self
+            .get(&self.package_path("pkg:npm/trust-api-conformance-unknown@0.0.0"))
+            .await?;
+        match response.status {
+            StatusCode::OK => response.json::<Package>().map(|_| ()),
+            _ => response.expect_problem(StatusCode::NOT_FOUND),
+        }
+    }
+
+    async fn batch_empty(&self) -> anyhow::Result<()> {
+        self.post("/api/v1/package", &serde_json::json!([]))
+            .await?
+            .expect_problem(StatusCode::BAD_REQUEST)
+    }
+
+    /// Batch responses have an item for each purl, in request order, with the status it would have
+    /// on its own.
+    async fn batch_items(&self) -> anyhow::Result<()> {
+        let items: Vec<BatchItem<Package>> = self
+            .post(
+                "/api/v1/package",
+                &serde_json::json!([self.purl, "not-a-purl"]),
+            )
+            .await?
+            .expect_status(StatusCode::OK)?
+            .json()?;
+        let purls: Vec<&str> = items.iter().map(|i| i.purl.as_str()).collect();
+        ensure!(
+            purls == [self.purl.as_str(), "not-a-purl"],
+            "items are {:?}, not in request order",
+            purls
+        );
+        let (found, invalid) = (&items[0], &items[1]);
+        ensure!(
+            found.status == 200 && found.result.is_some() && found.error.is_none(),
+            "the known package has status {} and no result",
+            found.status
+        );
+        ensure!(
+            invalid.status == 400 && invalid.result.is_none() && invalid.error.is_some(),
+            "the invalid purl has status {} and no error",
+            invalid.status
+        );
+        Ok(())
+    }
+
+    async fn dependencies(&self) -> anyhow::Result<()> {
+        let items: Vec<BatchItem<PackageDependencies>> = self
+            .post(
+                "/api/v1/package/dependencies",
+                &serde_json::json!([self.purl]),
+            )
+            .await?
+            .expect_status(StatusCode::OK)?
+            .json()?;
+        ensure!(items.len() == 1, "expected 1 item, got {}", items.len());
+        Ok(())
+    }
+
+    async fn dependencies_invalid_depth(&self) -> anyhow::Result<()> {
+        self.post(
+            "/api/v1/package/dependencies?depth=0",
+            &serde_json::json!([self.purl]),
+        )
+        .await?
+        .expect_problem(StatusCode::BAD_REQUEST)
+    }
+
+    async fn versions(&self) -> anyhow::Result<()> {
+        let items: Vec<BatchItem<Vec<PackageRef>>> = self
+            .post("/api/v1/package/versions", &serde_json::json!([self.purl]))
+            .await?
+            .expect_status(StatusCode::OK)?
+            .json()?;
+        match items.as_slice() {
+            [item] if item.status == 200 => Ok(()),
+            [item] => bail!("the known package has status {}", item.status),
+            _ => bail!("expected 1 item, got {}", items.len()),
+        }
+    }
+
+    /// Pages of search results report the total, and don't exceed the limit.
+    async fn search_pagination(&self) -> anyhow::Result<()> {
+        let page: serde_json::Value = self
+            .get("/api/v1/search?limit=1&offset=0")
+            .await?
+            .expect_status(StatusCode::OK)?
+            .json()?;
+        let total = page
+            .get("total")
+            .and_then(|t| t.as_u64())
+            .ok_or_else(|| anyhow!("search results have no total"))?;
+        let results = page
+            .get("results")
+            .and_then(|r| r.as_array())
+            .ok_or_else(|| anyhow!("search results have no results"))?;
+        ensure!(
+            results.len() as u64 == total.min(1),
+            "expected {} results, got {}",
+            total.min(1),
+            results.len()
+        );
+        Ok(())
+    }
+
+    async fn search_invalid_limit(&self) -> anyhow::Result<()> {
+        self.get("/api/v1/search?limit=0")
+            .await?
+            .expect_problem(StatusCode::BAD_REQUEST)
+    }
+
+    async fn vulnerability_missing_cve(&self) -> anyhow::Result<()> {
+        self.get("/api/v1/vulnerability")
+            .await?
+            .expect_problem(StatusCode::BAD_REQUEST)
+    }
+
+    async fn run(&self) -> Vec<(&'static str, anyhow::Result<()>)> {
+        vec![
+            ("liveness probe", self.liveness().await),
+            ("capability document", self.capabilities().await),
+            ("OpenAPI document", self.openapi().await),
+            ("request id", self.request_id().await),
+            ("package", self.package().await),
+            ("package without purl", self.package_missing_purl().await),
+            (
+                "package with invalid purl",
+                self.package_invalid_purl().await,
+            ),
+            ("unknown package", self.package_unknown().await),
+            ("empty batch", self.batch_empty().await),
+            ("batch items", self.batch_items().await),
+            ("dependencies", self.dependencies().await),
+            (
+                "dependencies with invalid depth",
+                self.dependencies_invalid_depth().await,
+            ),
+            ("versions", self.versions().await),
+            ("search pagination", self.search_pagination().await),
+            (
+                "search with invalid limit",
+                self.search_invalid_limit().await,
+            ),
+            (
+                "vulnerability without cve",
+                self.vulnerability_missing_cve().await,
+            ),
+        ]
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let conformance = Conformance {
+        client: reqwest::Client::new(),
+        url: cli.url.trim_end_matches('/').to_string(),
+        token: cli.token,
+        purl: cli.purl,
+    };
+
+    let mut failures = 0;
+    for (name, result) in conformance.run().await {
+        match result {
+            Ok(()) => println!("ok     {}", name),
+            Err(e) => {
+                failures += 1;
+                println!("FAILED {}: {}", name, e);
+            }
+        }
+    }
+
+    if failures > 0 {
+        println!("{} checks failed", failures);
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
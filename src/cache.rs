@@ -0,0 +1,318 @@
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use redis::aio::ConnectionManager;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+use crate::config::CacheConfig;
+
+const KEY_PREFIX: &str = "trust-api:";
+
+/// How many keys are asked for at once when scanning the Redis cache.
+const SCAN_COUNT: usize = 1000;
+
+tokio::task_local! {
+    static BYPASS: bool;
+}
+
+/// Run a future without reading cached entries, fresh results are still written to the cache.
+pub async fn bypassing<F: Future>(f: F) -> F::Output {
+    BYPASS.scope(true, f).await
+}
+
+/// Whether cached entries are bypassed by the running task.
+pub(crate) fn bypassed() -> bool {
+    BYPASS.try_with(|bypass| *bypass).unwrap_or(false)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheBackend {
+    Memory,
+    Redis,
+}
+
+/// Cache for package and vulnerability lookups.
+///
+/// The in-memory backend is local to a single instance, while the Redis backend allows multiple
+/// replicas to share the same cache state.
+pub enum Cache {
+    Memory(MemoryCache),
+    Redis(RedisCache),
+}
+
+impl Cache {
+    pub async fn new(config: &CacheConfig) -> Result<Self, anyhow::Error> {
+        let ttl = Duration::from_secs(config.ttl);
+        Ok(match config.backend {
+            CacheBackend::Memory => Self::Memory(MemoryCache::new(ttl, config.max_entries)),
+            CacheBackend::Redis => {
+                let url = config
+                    .redis_url
+                    .as_deref()
+                    .ok_or_else(|| anyhow::anyhow!("The Redis cache requires cache.redis_url"))?;
+                Self::Redis(RedisCache::new(url, ttl).await?)
+            }
+        })
+    }
+
+    pub async fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        if bypassed() {
+            return None;
+        }
+        let value = match self {
+            Self::Memory(cache) => cache.get(key).await,
+            Self::Redis(cache) => cache.get(key).await,
+        }?;
+        serde_json::from_value(value).ok()
+    }
+
+    pub async fn put<T: Serialize>(&self, key: &str, value: &T) {
+        if let Ok(value) = serde_json::to_value(value) {
+            match self {
+                Self::Memory(cache) => cache.put(key, value).await,
+                Self::Redis(cache) => cache.put(key, value).await,
+            }
+        }
+    }
+
+    pub async fn remove(&self, key: &str) {
+        match self {
+            Self::Memory(cache) => cache.remove(key).await,
+            Self::Redis(cache) => cache.remove(key).await,
+        }
+    }
+
+    pub async fn purge(&self) {
+        match self {
+            Self::Memory(cache) => cache.purge().await,
+            Self::Redis(cache) => cache.purge().await,
+        }
+    }
+
+    pub fn backend(&self) -> CacheBackend {
+        match self {
+            Self::Memory(_) => CacheBackend::Memory,
+            Self::Redis(_) => CacheBackend::Redis,
+        }
+    }
+
+    /// The number of cached entries, `None` if it could not be determined.
+    pub async fn len(&self) -> Option<usize> {
+        match self {
+            Self::Memory(cache) => Some(cache.len().await),
+            Self::Redis(cache) => cache.len().await,
+        }
+    }
+}
+
+/// Keeps up to `capacity` entries, evicting the oldest first.
+pub struct MemoryCache {
+    ttl: Duration,
+    capacity: usize,
+    entries: RwLock<MemoryEntries>,
+}
+
+#[derive(Default)]
+struct MemoryEntries {
+    values: HashMap<String, (Instant, serde_json::Value)>,
+    /// The keys in the order they were written, including those overwritten or removed since,
+    /// which no longer match the time in `values`
+    order: VecDeque<(String, Instant)>,
+}
+
+impl MemoryCache {
+    fn new(ttl: Duration, capacity: usize) -> Self {
+        Self {
+            ttl,
+            capacity,
+            entries: Default::default(),
+        }
+    }
+
+    async fn get(&self, key: &str) -> Option<serde_json::Value> {
+        let entries = self.entries.read().await;
+        match entries.values.get(key) {
+            Some((inserted, value)) if inserted.elapsed() < self.ttl => Some(value.clone()),
+            _ => None,
+        }
+    }
+
+    async fn put(&self, key: &str, value: serde_json::Value) {
+        if self.capacity == 0 {
+            return;
+        }
+        let now = Instant::now();
+        let mut entries = self.entries.write().await;
+        let MemoryEntries { values, order } = &mut *entries;
+        values.insert(key.to_string(), (now, value));
+        order.push_back((key.to_string(), now));
+        while let Some((key, inserted)) = order.front() {
+            if order.len() <= self.capacity && now.duration_since(*inserted) < self.ttl {
+                break;
+            }
+            if values.get(key).map(|(i, _)| i) == Some(inserted) {
+                values.remove(key);
+            }
+            order.pop_front();
+        }
+    }
+
+    async fn remove(&self, key: &str) {
+        self.entries.write().await.values.remove(key);
+    }
+
+    async fn purge(&self) {
+        *self.entries.write().await = Default::default();
+    }
+
+    async fn len(&self) -> usize {
+        let entries = self.entries.read().await;
+        entries
+            .values
+            .values()
+            .filter(|(inserted, _)| inserted.elapsed() < self.ttl)
+            .count()
+    }
+}
+
+pub struct RedisCache {
+    ttl: Duration,
+    connection: ConnectionManager,
+}
+
+impl RedisCache {
+    async fn new(url: &str, ttl: Duration) -> Result<Self, anyhow::Error> {
+        let client = redis::Client::open(url)?;
+        let connection = client.get_tokio_connection_manager().await?;
+        Ok(Self { ttl, connection })
+    }
+
+    async fn get(&self, key: &str) -> Option<serde_json::Value> {
+        let mut connection = self.connection.clone();
+        let value: Option<String> = redis::cmd("GET")
+            .arg(format!("{}{}", KEY_PREFIX, key))
+            .query_async(&mut connection)
+            .await
+            .map_err(|e| log::warn!("Error reading from Redis cache: {:?}", e))
+            .ok()?;
+        value.and_then(|v| serde_json::from_str(&v).ok())
+    }
+
+    async fn put(&self, key: &str, value: serde_json::Value) {
+        let mut connection = self.connection.clone();
+        let result: Result<(), _> = redis::cmd("SET")
+            .arg(format!("{}{}", KEY_PREFIX, key))
+            .arg(value.to_string())
+            .arg("EX")
+            .arg(self.ttl.as_secs())
+            .query_async(&mut connection)
+            .await;
+        if let Err(e) = result {
+            log::warn!("Error writing to Redis cache: {:?}", e);
+        }
+    }
+
+    async fn remove(&self, key: &str) {
+        let mut connection = self.connection.clone();
+        let result: Result<(), _> = redis::cmd("DEL")
+            .arg(format!("{}{}", KEY_PREFIX, key))
+            .query_async(&mut connection)
+            .await;
+        if let Err(e) = result {
+            log::warn!("Error removing from Redis cache: {:?}", e);
+        }
+    }
+
+    /// One batch of the keys of cache entries and the cursor of the next, `0` after the last
+    /// one. Scanning doesn't block Redis like listing all keys at once does.
+    async fn scan(&self, cursor: u64) -> Result<(u64, Vec<String>), redis::RedisError> {
+        let mut connection = self.connection.clone();
+        redis::cmd("SCAN")
+            .arg(cursor)
+            .arg("MATCH")
+            .arg(format!("{}*", KEY_PREFIX))
+            .arg("COUNT")
+            .arg(SCAN_COUNT)
+            .query_async(&mut connection)
+            .await
+    }
+
+    async fn len(&self) -> Option<usize> {
+        let mut cursor = 0;
+        let mut len = 0;
+        loop {
+            let (next, keys) = self
+                .scan(cursor)
+                .await
+                .map_err(|e| log::warn!("Error listing Redis cache entries: {:?}", e))
+                .ok()?;
+            len += keys.len();
+            if next == 0 {
+                return Some(len);
+            }
+            cursor = next;
+        }
+    }
+
+    async fn purge(&self) {
+        let mut connection = self.connection.clone();
+        let mut cursor = 0;
+        loop {
+            let (next, keys) = match self.scan(cursor).await {
+                Ok(batch) => batch,
+                Err(e) => {
+                    log::warn!("Error listing Redis cache entries: {:?}", e);
+                    return;
+                }
+            };
+            if !keys.is_empty() {
+                let result: Result<(), _> = redis::cmd("DEL")
+                    .arg(keys)
+                    .query_async(&mut connection)
+                    .await;
+                if let Err(e) = result {
+                    log::warn!("Error purging Redis cache: {:?}", e);
+                    return;
+                }
+            }
+            if next == 0 {
+                return;
+            }
+            cursor = next;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn the_oldest_entries_are_evicted_beyond_the_capacity() {
+        let cache = MemoryCache::new(Duration::from_secs(60), 2);
+        cache.put("a", json!(1)).await;
+        cache.put("b", json!(2)).await;
+        // overwriting keeps the entry, as the newest
+        cache.put("a", json!(3)).await;
+        assert_eq!(cache.get("a").await, Some(json!(3)));
+        assert_eq!(cache.get("b").await, Some(json!(2)));
+
+        cache.put("c", json!(4)).await;
+        assert_eq!(cache.get("b").await, None);
+        assert_eq!(cache.get("a").await, Some(json!(3)));
+        assert_eq!(cache.len().await, 2);
+        assert!(cache.entries.read().await.order.len() <= 2);
+    }
+
+    #[tokio::test]
+    async fn expired_entries_are_not_served() {
+        let cache = MemoryCache::new(Duration::ZERO, 10);
+        cache.put("a", json!(1)).await;
+        assert_eq!(cache.get("a").await, None);
+        assert_eq!(cache.len().await, 0);
+    }
+}
@@ -0,0 +1,267 @@
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderName, HeaderValue};
+use actix_web::http::Method;
+use actix_web::HttpMessage;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::auth::Identity;
+use crate::cost::API_KEY_HEADER;
+use crate::purl::PurlParsing;
+
+/// Endpoints whose responses may be cached by a CDN, tagged by their `purl` or `cve` parameter.
+///
+/// Unversioned, matching the paths of all API versions.
+const CACHEABLE: &[&str] = &[
+    "/api/package",
+    "/api/package/licenses",
+    "/api/package/provenance",
+    "/api/package/sbom",
+    "/api/vulnerability",
+];
+
+/// How to tag responses for a CDN in front of the service, and how to purge them.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CdnConfig {
+    pub enabled: bool,
+    /// The header carrying the tags, e.g. `Surrogate-Key` (Fastly) or `Cache-Tag` (Cloudflare)
+    pub header: String,
+    pub max_age_secs: u64,
+    /// Where to send purge requests, with the tags to purge in the tag header
+    pub purge_url: Option<String>,
+    /// Sent as bearer token with purge requests
+    pub purge_token: Option<String>,
+}
+
+impl Default for CdnConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            header: "Surrogate-Key".to_string(),
+            max_age_secs: 300,
+            purge_url: None,
+            purge_token: None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct TagQuery {
+    purl: Option<String>,
+    cve: Option<String>,
+}
+
+/// Tags cacheable responses by purl and data version, and purges tags from the CDN.
+pub struct Cdn {
+    config: CdnConfig,
+    purl_parsing: PurlParsing,
+    client: reqwest::Client,
+    /// Increased when all data is purged
+    version: AtomicU64,
+}
+
+impl Cdn {
+    pub fn new(config: CdnConfig, purl_parsing: PurlParsing) -> Self {
+        Self {
+            config,
+            purl_parsing,
+            client: reqwest::Client::new(),
+            version: AtomicU64::new(0),
+        }
+    }
+
+    /// The tag of the responses about a purl, which may contain characters not allowed in tags.
+    pub fn purl_tag(&self, purl: &str) -> String {
+        let digest = Sha256::digest(self.purl_parsing.canonicalize(purl, false));
+        format!("purl-{}", &hex::encode(digest)[..16])
+    }
+
+    fn version_tag(&self) -> String {
+        format!("data-v{}", self.version.load(Ordering::Relaxed))
+    }
+
+    fn tags(&self, req: &ServiceRequest) -> Option<String> {
+        let path = crate::versioning::unversioned(req.path());
+        if req.method() != Method::GET || !CACHEABLE.contains(&path.as_str()) {
+            return None;
+        }
+        let query = actix_web::web::Query::<TagQuery>::from_query(req.query_string()).ok()?;
+        let subject = match (&query.purl, &query.cve) {
+            (Some(purl), _) => self.purl_tag(purl),
+            (None, Some(cve)) => format!("cve-{}", cve.to_lowercase()),
+            (None, None) => return None,
+        };
+        Some(format!("{} {}", subject, self.version_tag()))
+    }
+
+    /// Purge tagged responses from the CDN, failures are logged only.
+    pub async fn purge(&self, tags: Vec<String>) {
+        let url = match (&self.config.purge_url, self.config.enabled) {
+            (Some(url), true) if !tags.is_empty() => url,
+            _ => return,
+        };
+        let mut request = self
+            .client
+            .post(url)
+            .header(self.config.header.as_str(), tags.join(" "));
+        if let Some(token) = &self.config.purge_token {
+            request = request.bearer_auth(token);
+        }
+        match request.send().await.and_then(|r| r.error_for_status()) {
+            Ok(_) => log::info!("Purged {} CDN tags", tags.len()),
+            Err(e) => log::warn!("Error purging CDN tags {:?}: {:?}", tags, e),
+        }
+    }
+
+    /// Purge the responses about some purls.
+    pub async fn purge_purls(&self, purls: &[String]) {
+        self.purge(purls.iter().map(|p| self.purl_tag(p)).collect())
+            .await
+    }
+
+    /// Purge all responses, by the current data version, and move on to the next version.
+    pub async fn purge_all(&self) {
+        let tag = self.version_tag();
+        self.version.fetch_add(1, Ordering::Relaxed);
+        self.purge(vec![tag]).await
+    }
+}
+
+/// Add the tag and cache headers to successful responses of cacheable endpoints.
+///
+/// Responses to authenticated callers may depend on their tenant or key, they may only be cached
+/// privately, whether the credentials were verified or only sent.
+pub fn middleware<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+    cdn: Arc<Cdn>,
+) -> impl Future<Output = Result<ServiceResponse<B>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+{
+    let tags = cdn.config.enabled.then(|| cdn.tags(&req)).flatten();
+    let scoped = req.extensions().get::<Identity>().is_some()
+        || req.headers().contains_key(header::AUTHORIZATION)
+        || req.headers().contains_key(API_KEY_HEADER);
+    let fut = srv.call(req);
+    async move {
+        let mut res = fut.await?;
+        if let Some(tags) = tags.filter(|_| res.status().is_success()) {
+            let headers = res.headers_mut();
+            let visibility = if scoped {
+                headers.append(
+                    header::VARY,
+                    HeaderValue::from_static("Authorization, X-Api-Key"),
+                );
+                "private"
+            } else {
+                if let (Ok(name), Ok(value)) = (
+                    HeaderName::try_from(cdn.config.header.as_str()),
+                    HeaderValue::try_from(tags),
+                ) {
+                    headers.insert(name, value);
+                }
+                "public"
+            };
+            if let Ok(value) = HeaderValue::try_from(format!(
+                "{}, max-age={}",
+                visibility, cdn.config.max_age_secs
+            )) {
+                headers.insert(header::CACHE_CONTROL, value);
+            }
+        }
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App, HttpResponse};
+
+    async fn headers(
+        identity: Option<Identity>,
+        credentials: Option<(&str, &str)>,
+    ) -> header::HeaderMap {
+        let cdn = Arc::new(Cdn::new(
+            CdnConfig {
+                enabled: true,
+                ..Default::default()
+            },
+            PurlParsing::default(),
+        ));
+        let app = test::init_service(
+            App::new()
+                .wrap_fn(move |req, srv| middleware(req, srv, cdn.clone()))
+                .wrap_fn(move |req, srv| {
+                    if let Some(identity) = identity.clone() {
+                        req.extensions_mut().insert(identity);
+                    }
+                    srv.call(req)
+                })
+                .route(
+                    "/api/v1/package",
+                    web::get().to(|| async { HttpResponse::Ok().finish() }),
+                ),
+        )
+        .await;
+        let mut req =
+            test::TestRequest::get().uri("/api/v1/package?purl=pkg:maven/io.vertx/vertx-web@4.3.7");
+        if let Some(credentials) = credentials {
+            req = req.insert_header(credentials);
+        }
+        test::call_service(&app, req.to_request())
+            .await
+            .headers()
+            .clone()
+    }
+
+    #[actix_web::test]
+    async fn anonymous_responses_are_public() {
+        let headers = headers(None, None).await;
+        assert_eq!(
+            headers.get(header::CACHE_CONTROL).unwrap(),
+            "public, max-age=300"
+        );
+        assert!(headers.contains_key("surrogate-key"));
+    }
+
+    #[actix_web::test]
+    async fn tenant_scoped_responses_are_private() {
+        let headers = headers(
+            Some(Identity {
+                issuer: "https://sso.example.com".to_string(),
+                subject: "user".to_string(),
+                roles: Vec::new(),
+                tenant: Some("a".to_string()),
+            }),
+            None,
+        )
+        .await;
+        assert_eq!(
+            headers.get(header::CACHE_CONTROL).unwrap(),
+            "private, max-age=300"
+        );
+        assert_eq!(
+            headers.get(header::VARY).unwrap(),
+            "Authorization, X-Api-Key"
+        );
+        assert!(!headers.contains_key("surrogate-key"));
+    }
+
+    #[actix_web::test]
+    async fn responses_to_requests_with_credentials_are_private() {
+        for credentials in [("Authorization", "Bearer token"), ("X-Api-Key", "key")] {
+            let headers = headers(None, Some(credentials)).await;
+            assert_eq!(
+                headers.get(header::CACHE_CONTROL).unwrap(),
+                "private, max-age=300"
+            );
+            assert!(!headers.contains_key("surrogate-key"));
+        }
+    }
+}
@@ -0,0 +1,277 @@
+use cel_interpreter::extractors::{Identifier, This};
+use cel_interpreter::{Context, ExecutionError, Expression, FunctionContext, Program, Value};
+use packageurl::PackageUrl;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use crate::gate::GateViolation;
+use crate::policy::PolicyError;
+
+/// CEL expressions deciding trust and gate policies, compiled once.
+pub struct Cel {
+    trusted: Option<(String, Program)>,
+    gate: BTreeMap<String, (String, Program)>,
+}
+
+impl Cel {
+    pub fn new(
+        trusted: Option<&String>,
+        gate: &BTreeMap<String, String>,
+    ) -> Result<Self, anyhow::Error> {
+        let compile = |expression: &String| match Program::compile(expression) {
+            Ok(program) => Ok((expression.clone(), program)),
+            Err(e) => Err(anyhow::anyhow!("Invalid expression {}: {}", expression, e)),
+        };
+        Ok(Self {
+            trusted: trusted.map(compile).transpose()?,
+            gate: gate
+                .iter()
+                .map(|(name, expression)| {
+                    compile(expression).map(|compiled| (name.clone(), compiled))
+                })
+                .collect::<Result<_, anyhow::Error>>()?,
+        })
+    }
+
+    /// Whether the expression trusts a package, `None` if there is none.
+    ///
+    /// The expression is given the `purl` of the package, and the `vulnerabilities` of its
+    /// analysis, empty if it is not looked up on its own.
+    pub fn trusted(
+        &self,
+        purl: &serde_json::Value,
+        analysis: Option<&serde_json::Value>,
+    ) -> Result<Option<bool>, PolicyError> {
+        let (expression, program) = match &self.trusted {
+            Some(trusted) => trusted,
+            None => return Ok(None),
+        };
+        let mut variables = serde_json::Map::new();
+        variables.insert("purl".to_string(), purl.clone());
+        variables.insert(
+            "vulnerabilities".to_string(),
+            vulnerabilities(analysis.and_then(|a| a.get("vulnerabilities"))),
+        );
+        execute(expression, program, &variables).map(Some)
+    }
+
+    /// The packages of an analysis which do not satisfy the expression of a gate policy, `None` if
+    /// it is not configured.
+    pub fn violations(
+        &self,
+        policy: &str,
+        analysis: &serde_json::Value,
+    ) -> Result<Option<Vec<GateViolation>>, PolicyError> {
+        let (expression, program) = match self.gate.get(policy) {
+            Some(gate) => gate,
+            None => return Ok(None),
+        };
+        let mut violations = Vec::new();
+        let items = analysis["packages"].as_array().map(Vec::as_slice);
+        for item in items.unwrap_or(&[]) {
+            let (purl, package) = match (item["purl"].as_str(), item["result"].as_object()) {
+                (Some(purl), Some(package)) => (purl, package),
+                _ => continue,
+            };
+            let mut variables = package.clone();
+            variables.insert(
+                "vulnerabilities".to_string(),
+                vulnerabilities(package.get("vulnerabilities")),
+            );
+            // fields the responses leave out if empty are still defined for expressions
+            for (name, default) in [
+                ("trusted", serde_json::json!(false)),
+                ("trustedVersions", serde_json::json!([])),
+                ("signatureVerified", serde_json::Value::Null),
+                ("provenance", serde_json::Value::Null),
+                ("certifications", serde_json::json!([])),
+                ("scorecard", serde_json::Value::Null),
+                ("licenses", serde_json::json!([])),
+            ] {
+                variables.entry(name).or_insert(default);
+            }
+            if let Ok(parsed) = PackageUrl::from_str(purl) {
+                variables.insert("purl".to_string(), crate::policy::purl_input(&parsed));
+            }
+            if !execute(expression, program, &variables)? {
+                violations.push(GateViolation {
+                    purl: purl.to_string(),
+                    rule: "expression".to_string(),
+                    reason: format!("The package does not satisfy {}", expression),
+                });
+            }
+        }
+        Ok(Some(violations))
+    }
+}
+
+/// The vulnerabilities of a package for expressions, with their `severity` `null` rather than
+/// left out if unknown, so expressions comparing it don't fail on missing fields.
+fn vulnerabilities(vulnerabilities: Option<&serde_json::Value>) -> serde_json::Value {
+    let items = vulnerabilities
+        .and_then(|v| v.as_array())
+        .map(Vec::as_slice);
+    items
+        .unwrap_or(&[])
+        .iter()
+        .map(|vulnerability| {
+            let mut vulnerability = vulnerability.clone();
+            if let Some(fields) = vulnerability.as_object_mut() {
+                fields.entry("severity").or_insert(serde_json::Value::Null);
+            }
+            vulnerability
+        })
+        .collect()
+}
+
+/// Run a program with the variables of a package, which must evaluate to a boolean.
+fn execute(
+    expression: &str,
+    program: &Program,
+    variables: &serde_json::Map<String, serde_json::Value>,
+) -> Result<bool, PolicyError> {
+    let failed = |message: String| PolicyError::Evaluation {
+        rule: expression.to_string(),
+        message,
+    };
+    let mut context = Context::default();
+    context.add_function("none", none);
+    for (name, value) in variables {
+        context
+            .add_variable(name.as_str(), value)
+            .map_err(|e| failed(e.to_string()))?;
+    }
+    match program.execute(&context) {
+        Ok(Value::Bool(result)) => Ok(result),
+        Ok(other) => Err(PolicyError::Result {
+            rule: expression.to_string(),
+            message: format!("Expected a boolean, got {:?}", other),
+        }),
+        Err(e) => Err(failed(e.to_string())),
+    }
+}
+
+/// `list.none(x, predicate)`, whether no item satisfies the predicate.
+fn none(
+    ftx: &FunctionContext,
+    This(this): This<Value>,
+    ident: Identifier,
+    expr: Expression,
+) -> Result<bool, ExecutionError> {
+    match this {
+        Value::List(items) => {
+            let mut ptx = ftx.ptx.new_inner_scope();
+            for item in items.iter() {
+                ptx.add_variable_from_value(ident.0.as_str(), item.clone());
+                if let Value::Bool(true) = ptx.resolve(&expr)? {
+                    return Ok(false);
+                }
+            }
+            Ok(true)
+        }
+        other => Err(ftx.error(format!("none() expects a list, got {:?}", other))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn cel(trusted: &str, gate: &str) -> Cel {
+        let gate = BTreeMap::from([("release".to_string(), gate.to_string())]);
+        Cel::new(Some(&trusted.to_string()), &gate).unwrap()
+    }
+
+    fn purl(purl: &str) -> serde_json::Value {
+        crate::policy::purl_input(&PackageUrl::from_str(purl).unwrap())
+    }
+
+    #[test]
+    fn packages_are_trusted_by_their_purl() {
+        let cel = cel(
+            "purl.namespace == 'io.vertx' && purl.version.endsWith('redhat-00007')",
+            "true",
+        );
+        let redhat = purl("pkg:maven/io.vertx/vertx-web@4.3.4.redhat-00007?type=jar");
+        assert_eq!(cel.trusted(&redhat, None).unwrap(), Some(true));
+        let upstream = purl("pkg:maven/io.vertx/vertx-web@4.3.7?type=jar");
+        assert_eq!(cel.trusted(&upstream, None).unwrap(), Some(false));
+
+        let undecided = Cel::new(None, &BTreeMap::new()).unwrap();
+        assert_eq!(undecided.trusted(&redhat, None).unwrap(), None);
+    }
+
+    #[test]
+    fn packages_are_trusted_by_their_vulnerabilities() {
+        let cel = cel("vulnerabilities.none(v, v.severity == 'critical')", "true");
+        let package = purl("pkg:maven/io.vertx/vertx-web@4.3.7");
+        assert_eq!(cel.trusted(&package, None).unwrap(), Some(true));
+
+        let critical = json!({"vulnerabilities": [
+            {"cve": "CVE-2023-0001", "severity": "low"},
+            {"cve": "CVE-2023-0002", "severity": "critical"},
+        ]});
+        assert_eq!(cel.trusted(&package, Some(&critical)).unwrap(), Some(false));
+
+        // vulnerabilities of an unknown severity have it null rather than missing
+        let unknown = json!({"vulnerabilities": [{"cve": "CVE-2023-0003"}]});
+        assert_eq!(cel.trusted(&package, Some(&unknown)).unwrap(), Some(true));
+        let cel = self::cel("vulnerabilities.all(v, v.severity == null)", "true");
+        assert_eq!(cel.trusted(&package, Some(&unknown)).unwrap(), Some(true));
+    }
+
+    #[test]
+    fn packages_not_satisfying_a_gate_are_violations() {
+        let cel = cel(
+            "true",
+            "trusted && vulnerabilities.none(v, v.severity == 'critical')",
+        );
+        let analysis = json!({"packages": [
+            {"purl": "pkg:maven/io.vertx/vertx-web@4.3.4.redhat-00007", "result": {
+                "trusted": true,
+                "vulnerabilities": [{"cve": "CVE-2023-0003"}],
+            }},
+            {"purl": "pkg:maven/io.vertx/vertx-web@4.3.7", "result": {}},
+            {"purl": "pkg:maven/io.vertx/vertx-core@4.3.7", "result": {
+                "trusted": true,
+                "vulnerabilities": [{"cve": "CVE-2023-0002", "severity": "critical"}],
+            }},
+        ]});
+        let violations: Vec<_> = cel
+            .violations("release", &analysis)
+            .unwrap()
+            .unwrap()
+            .into_iter()
+            .map(|v| v.purl)
+            .collect();
+        assert_eq!(
+            violations,
+            [
+                "pkg:maven/io.vertx/vertx-web@4.3.7",
+                "pkg:maven/io.vertx/vertx-core@4.3.7"
+            ]
+        );
+        assert!(cel.violations("other", &analysis).unwrap().is_none());
+    }
+
+    #[test]
+    fn none_requires_a_list() {
+        let cel = cel("purl.name.none(c, c == 'a')", "true");
+        let package = purl("pkg:maven/io.vertx/vertx-web@4.3.7");
+        assert!(matches!(
+            cel.trusted(&package, None),
+            Err(PolicyError::Evaluation { .. })
+        ));
+    }
+
+    #[test]
+    fn expressions_must_be_boolean() {
+        let cel = cel("purl.name", "true");
+        let package = purl("pkg:maven/io.vertx/vertx-web@4.3.7");
+        assert!(matches!(
+            cel.trusted(&package, None),
+            Err(PolicyError::Result { .. })
+        ));
+    }
+}
@@ -0,0 +1,43 @@
+//! Faults injected into the calls to upstream services, to validate retries, timeouts, circuit
+//! breakers and partial results in staging.
+//!
+//! Faults are only injected when built with the `chaos` feature, calls are never delayed nor
+//! failed otherwise.
+
+use once_cell::sync::OnceCell;
+use std::collections::HashMap;
+
+use crate::config::ChaosConfig;
+
+static CONFIG: OnceCell<HashMap<String, ChaosConfig>> = OnceCell::new();
+
+/// A call failed on purpose.
+#[derive(Debug, thiserror::Error)]
+#[error("injected fault")]
+pub struct InjectedFault;
+
+/// Set the faults injected by upstream service, must be called before the first call.
+pub fn init(config: HashMap<String, ChaosConfig>) {
+    for (upstream, chaos) in config.iter().filter(|(_, chaos)| chaos.is_enabled()) {
+        log::warn!("Injecting faults into calls to {}: {:?}", upstream, chaos);
+    }
+    if CONFIG.set(config).is_err() {
+        log::warn!("The injected faults were already initialized");
+    }
+}
+
+/// Delay a call to an upstream service and fail it at random, as configured for the service.
+pub async fn inject(upstream: &str) -> Result<(), InjectedFault> {
+    #[cfg(feature = "chaos")]
+    if let Some(config) = CONFIG.get().and_then(|config| config.get(upstream)) {
+        if config.latency_ms > 0 {
+            tokio::time::sleep(std::time::Duration::from_millis(config.latency_ms)).await;
+        }
+        if rand::random::<f64>() < config.error_rate {
+            return Err(InjectedFault);
+        }
+    }
+    #[cfg(not(feature = "chaos"))]
+    let _ = upstream;
+    Ok(())
+}
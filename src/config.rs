@@ -0,0 +1,893 @@
+use anyhow::{anyhow, bail};
+use chrono::{DateTime, Utc};
+use figment::providers::{Env, Format, Serialized, Toml, Yaml};
+use figment::Figment;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::access_log::SamplingRule;
+use crate::audit::AuditConfig;
+use crate::auth::AuthConfig;
+use crate::cache::CacheBackend;
+use crate::cdn::CdnConfig;
+use crate::cost::CostConfig;
+use crate::cpe::CpeConfig;
+use crate::fresh::FreshConfig;
+use crate::gate::GateConfig;
+use crate::ghsa::GhsaConfig;
+use crate::image::ImageConfig;
+use crate::ingestion::IngestionConfig;
+use crate::license::LicensePolicy;
+use crate::logging::LogFormat;
+use crate::nvd::NvdConfig;
+use crate::osv::OsvConfig;
+use crate::package::{BatchConfig, UnknownPurl};
+use crate::policy::PolicyConfig;
+use crate::product::Product;
+use crate::provider::ProviderTimeouts;
+use crate::publish::PublishConfig;
+use crate::purl::PurlParsing;
+use crate::reanalysis::ReanalysisConfig;
+use crate::reload::ReloadConfig;
+use crate::replication::ReplicationConfig;
+use crate::sbom::SbomSource;
+use crate::scorecard::ScorecardConfig;
+use crate::search::SearchConfig;
+use crate::secrets::SecretsConfig;
+use crate::signing::SigningKey;
+use crate::sigstore::SigstoreConfig;
+use crate::storage::StorageConfig;
+use crate::trusted::TrustedVersionsConfig;
+
+const ENV_PREFIX: &str = "TRUST_API_";
+const REDACTED: &str = "***";
+
+/// The configuration of the server.
+///
+/// The configuration is layered: built-in defaults, overridden by an optional configuration file
+/// (YAML or TOML), overridden by environment variables (`TRUST_API_` prefixed, nested keys
+/// separated by `__`, e.g. `TRUST_API_GUAC__URL`), overridden by command line arguments.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub bind: String,
+    pub port: u16,
+    pub tls: Option<TlsConfig>,
+    pub cors: CorsConfig,
+    pub api: ApiConfig,
+    pub guac: GuacConfig,
+    pub snyk: Snyk,
+    pub cache: CacheConfig,
+    pub admin: AdminConfig,
+    pub webhook: WebhookConfig,
+    pub signing: SigningConfig,
+    pub sbom: SbomConfig,
+    pub shutdown: ShutdownConfig,
+    pub logging: LoggingConfig,
+    pub grpc: GrpcConfig,
+    /// Analyzing the packages of the SBOM registry again, for events and cached responses
+    pub reanalysis: ReanalysisConfig,
+    pub cost: CostConfig,
+    pub watch: WatchConfig,
+    /// Publish SBOM and vulnerability events to Kafka or NATS
+    pub publish: Option<PublishConfig>,
+    pub deps_dev: DepsDevConfig,
+    pub sigstore: SigstoreConfig,
+    pub scorecard: ScorecardConfig,
+    /// Listen for Guac ingestion events, to invalidate cached data
+    pub ingestion: Option<IngestionConfig>,
+    /// OIDC issuers whose ID tokens are accepted by the public API
+    pub auth: AuthConfig,
+    /// Tag responses for a CDN in front of the service
+    pub cdn: CdnConfig,
+    /// Registries to pull container images from, for image analysis
+    pub image: ImageConfig,
+    pub search: SearchConfig,
+    /// Additional mappings of CPEs to packages
+    pub cpe: CpeConfig,
+    /// Vulnerabilities from the NVD, matched to packages by CPE
+    pub nvd: NvdConfig,
+    /// Vulnerabilities from the GitHub Security Advisories
+    pub ghsa: GhsaConfig,
+    /// Vulnerabilities from the OSV database
+    pub osv: OsvConfig,
+    /// Sync read replicas from a primary instance
+    pub replication: ReplicationConfig,
+    /// Where SBOMs, job results, attestations and exports are persisted
+    pub storage: StorageConfig,
+    /// Encryption of the secrets persisted to the storage
+    pub secrets: SecretsConfig,
+    /// Record who queried which packages and uploaded which SBOMs
+    pub audit: AuditConfig,
+    /// Named product versions, grouping SBOMs
+    pub products: Vec<Product>,
+    /// Policies releases are checked against by CI pipelines
+    pub gate: GateConfig,
+    /// Delegate trust and gate decisions to Rego policies
+    pub policy: Option<PolicyConfig>,
+    /// Faults injected into the calls to upstream services by their name, e.g. `snyk`, requires
+    /// the `chaos` feature
+    pub chaos: HashMap<String, ChaosConfig>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            bind: "0.0.0.0".to_string(),
+            port: 8080,
+            tls: None,
+            cors: Default::default(),
+            api: Default::default(),
+            guac: Default::default(),
+            snyk: Default::default(),
+            cache: Default::default(),
+            admin: Default::default(),
+            webhook: Default::default(),
+            signing: Default::default(),
+            sbom: Default::default(),
+            shutdown: Default::default(),
+            logging: Default::default(),
+            grpc: Default::default(),
+            reanalysis: Default::default(),
+            cost: Default::default(),
+            watch: Default::default(),
+            publish: None,
+            deps_dev: Default::default(),
+            sigstore: Default::default(),
+            scorecard: Default::default(),
+            ingestion: None,
+            auth: Default::default(),
+            cdn: Default::default(),
+            image: Default::default(),
+            search: Default::default(),
+            cpe: Default::default(),
+            nvd: Default::default(),
+            ghsa: Default::default(),
+            osv: Default::default(),
+            replication: Default::default(),
+            storage: Default::default(),
+            secrets: Default::default(),
+            audit: Default::default(),
+            products: Vec::new(),
+            gate: Default::default(),
+            policy: None,
+            chaos: HashMap::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Load the configuration from the defaults, the configuration file and the environment.
+    pub fn load(file: Option<&Path>) -> Result<Self, anyhow::Error> {
+        let mut figment = Figment::from(Serialized::defaults(Config::default()));
+
+        if let Some(file) = file {
+            if !file.exists() {
+                bail!("Configuration file {} does not exist", file.display());
+            }
+            figment = match file.extension().and_then(|e| e.to_str()) {
+                Some("yaml") | Some("yml") => figment.merge(Yaml::file(file)),
+                Some("toml") => figment.merge(Toml::file(file)),
+                _ => bail!(
+                    "Unsupported configuration file format: {}, use YAML or TOML",
+                    file.display()
+                ),
+            };
+        }
+
+        let defaults = serde_json::to_value(Config::default())?;
+        if let Some((var, meant)) = misspelled_env(&defaults, std::env::vars().map(|(k, _)| k))
+            .into_iter()
+            .next()
+        {
+            bail!(
+                "{} sets nothing, nested keys are separated by `__`, did you mean {}?",
+                var,
+                meant
+            );
+        }
+
+        Ok(figment
+            .merge(Env::prefixed(ENV_PREFIX).split("__"))
+            .extract()?)
+    }
+
+    pub fn validate(&self) -> Result<(), anyhow::Error> {
+        self.guac
+            .url
+            .parse::<http::Uri>()
+            .map_err(|e| anyhow!("Invalid Guac URL {}: {}", self.guac.url, e))?;
+
+        if self.cache.backend == CacheBackend::Redis && self.cache.redis_url.is_none() {
+            bail!("The Redis cache backend requires cache.redis_url");
+        }
+
+        if self.admin.token.as_deref() == Some("") {
+            bail!("admin.token must not be empty");
+        }
+
+        for issuer in &self.auth.issuers {
+            if issuer.algorithms.is_empty() {
+                bail!("auth.issuers of {} must accept an algorithm", issuer.issuer);
+            }
+        }
+
+        if self.snyk.org.is_some() != self.snyk.token.is_some() {
+            bail!("Snyk requires both snyk.org and snyk.token");
+        }
+
+        if self.guac.resilience.breaker_threshold == 0 {
+            bail!("guac.resilience.breaker_threshold must be greater than zero");
+        }
+
+        for (upstream, chaos) in &self.chaos {
+            if !(0.0..=1.0).contains(&chaos.error_rate) {
+                bail!("chaos.{}.error_rate must be between 0 and 1", upstream);
+            }
+            if chaos.is_enabled() && !cfg!(feature = "chaos") {
+                bail!(
+                    "chaos.{} requires building with the chaos feature",
+                    upstream
+                );
+            }
+        }
+
+        if let Some(publish) = self.publish.as_ref().filter(|p| !p.is_supported()) {
+            bail!("Publishing to {:?} is not supported by this build", publish);
+        }
+        if let Some(ingestion) = self.ingestion.as_ref().filter(|i| !i.is_supported()) {
+            bail!(
+                "Listening for ingestion events on {:?} is not supported by this build",
+                ingestion
+            );
+        }
+        if let Some(sink) = self.audit.sink.as_ref().filter(|s| !s.is_supported()) {
+            bail!("Auditing to {:?} is not supported by this build", sink);
+        }
+        if let Some(policy) = self.policy.as_ref().filter(|p| !p.is_supported()) {
+            bail!("Policy engine {:?} is not supported by this build", policy);
+        }
+        if !self.storage.is_supported() {
+            bail!("Storage {:?} is not supported by this build", self.storage);
+        }
+
+        if let Some(key_file) = self.secrets.key_file.as_ref().filter(|f| !f.exists()) {
+            bail!("Key file {} does not exist", key_file.display());
+        }
+
+        if let Some(id) = self
+            .api
+            .disabled_providers
+            .iter()
+            .find(|id| !crate::provider::BUILT_IN.contains(&id.as_str()))
+        {
+            bail!(
+                "Unknown vulnerability provider {} in api.disabled_providers, expected one of {:?}",
+                id,
+                crate::provider::BUILT_IN
+            );
+        }
+
+        if let Some(follow) = &self.replication.follow {
+            follow
+                .url
+                .parse::<http::Uri>()
+                .map_err(|e| anyhow!("Invalid primary URL {}: {}", follow.url, e))?;
+        }
+
+        if self.bind == self.admin.bind && self.port == self.admin.port {
+            bail!(
+                "The admin listener must not use the same address as the API ({}:{})",
+                self.bind,
+                self.port
+            );
+        }
+
+        if self.grpc.enabled
+            && [(&self.bind, self.port), (&self.admin.bind, self.admin.port)]
+                .contains(&(&self.grpc.bind, self.grpc.port))
+        {
+            bail!(
+                "The gRPC listener must not use the same address as the API or the admin listener ({}:{})",
+                self.grpc.bind,
+                self.grpc.port
+            );
+        }
+
+        if let Some(tls) = &self.tls {
+            for path in [Some(&tls.cert), Some(&tls.key), tls.client_ca.as_ref()]
+                .into_iter()
+                .flatten()
+            {
+                if !path.exists() {
+                    bail!("TLS file {} does not exist", path.display());
+                }
+            }
+        }
+
+        for origin in self.cors.allowed_origins.iter().filter(|o| *o != "*") {
+            match origin.parse::<http::Uri>() {
+                Ok(uri) if uri.scheme().is_some() && uri.host().is_some() => {}
+                _ => bail!("Invalid CORS origin: {}", origin),
+            }
+        }
+
+        let mut ids: Vec<&str> = self.sbom.sources.iter().map(|s| s.id.as_str()).collect();
+        ids.sort_unstable();
+        if let Some(id) = ids.windows(2).find(|w| w[0] == w[1]) {
+            bail!("Duplicate SBOM source id: {}", id[0]);
+        }
+
+        if let Some(rule) = self.logging.sampling.iter().find(|r| !r.is_valid()) {
+            bail!(
+                "Invalid log sampling rate for {}: {}, must be between 0 and 1",
+                rule.path,
+                rule.rate
+            );
+        }
+
+        Ok(())
+    }
+
+    /// A copy of the configuration with all secrets replaced, safe for printing.
+    pub fn redacted(&self) -> Self {
+        let mut config = self.clone();
+        if config.snyk.token.is_some() {
+            config.snyk.token = Some(REDACTED.to_string());
+        }
+        if config.admin.token.is_some() {
+            config.admin.token = Some(REDACTED.to_string());
+        }
+        config.signing.keys = config
+            .signing
+            .keys
+            .iter()
+            .map(|key| key.redacted(REDACTED))
+            .collect();
+        config.nvd = config.nvd.redacted(REDACTED);
+        config.ghsa = config.ghsa.redacted(REDACTED);
+        config.replication = config.replication.redacted(REDACTED);
+        config.storage = config.storage.redacted(REDACTED);
+        for registry in config.image.registries.values_mut() {
+            *registry = registry.redacted(REDACTED);
+        }
+        config
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TlsConfig {
+    /// PEM encoded certificate chain
+    pub cert: PathBuf,
+    /// PEM encoded private key
+    pub key: PathBuf,
+    /// PEM encoded CA certificates, requiring clients to present a certificate signed by one of them
+    #[serde(default)]
+    pub client_ca: Option<PathBuf>,
+}
+
+/// CORS settings, `*` allows any origin, method or header.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CorsConfig {
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    pub allowed_headers: Vec<String>,
+    /// How long browsers may cache the results of preflight requests, in seconds
+    pub max_age: usize,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            allowed_origins: vec!["*".to_string()],
+            allowed_methods: vec!["*".to_string()],
+            allowed_headers: vec!["*".to_string()],
+            max_age: 3600,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ApiConfig {
+    /// How to respond to purls unknown to Guac and the SBOM registry
+    pub unknown_purl: UnknownPurl,
+    /// Whether to repair malformed purls sent by clients
+    pub purl_parsing: PurlParsing,
+    /// How trusted rebuilds are recognized
+    pub trusted_versions: TrustedVersionsConfig,
+    /// Who may bypass the caches using `?fresh=true` or `Cache-Control: no-cache`
+    pub fresh: FreshConfig,
+    /// SLSA build levels of known builders, by builder id
+    pub slsa_levels: HashMap<String, u8>,
+    /// Licenses allowed by `POST /api/package/license-check`
+    pub license_policy: LicensePolicy,
+    /// Bounds of the errors reported by batch requests
+    pub batch: BatchConfig,
+    /// Vulnerability providers not to consult, e.g. `snyk`
+    pub disabled_providers: Vec<String>,
+    /// How long vulnerability providers are waited for
+    pub provider_timeouts: ProviderTimeouts,
+    /// When the unversioned `/api` paths will be removed, announced in their `Sunset` header
+    pub sunset: Option<DateTime<Utc>>,
+    pub compression: CompressionConfig,
+    pub docs: DocsConfig,
+}
+
+/// The OpenAPI document and the Swagger UI.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DocsConfig {
+    /// The path a reverse proxy serves the API below, e.g. `/trust`, the Swagger UI and the
+    /// document are served below it, and the UI sends its requests through the proxy
+    pub base_path: Option<String>,
+    /// Keep the tokens entered with "Authorize" in the local storage of the browser, across
+    /// reloads
+    pub persist_authorization: bool,
+}
+
+impl DocsConfig {
+    /// The base path without a trailing slash, empty if the API is served at the root.
+    pub fn base_path(&self) -> &str {
+        self.base_path
+            .as_deref()
+            .unwrap_or_default()
+            .trim_end_matches('/')
+    }
+
+    /// Where the OpenAPI document is served.
+    pub fn document_path(&self) -> String {
+        format!("{}/openapi.json", self.base_path())
+    }
+
+    /// Where the Swagger UI is served.
+    pub fn ui_path(&self) -> String {
+        format!("{}/swagger-ui/", self.base_path())
+    }
+}
+
+/// Compression of responses, for clients accepting it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CompressionConfig {
+    /// Compress responses with gzip, brotli or zstd, as negotiated by `Accept-Encoding`
+    pub enabled: bool,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self { enabled: true }
+    }
+}
+
+impl ApiConfig {
+    /// Whether a configured vulnerability provider is consulted.
+    pub fn is_provider_enabled(&self, id: &str) -> bool {
+        !self.disabled_providers.iter().any(|p| p == id)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GuacConfig {
+    pub url: String,
+    pub resilience: ResilienceConfig,
+    /// How often to probe if Guac is available
+    pub probe_interval_secs: u64,
+    /// How long purls unknown to Guac are remembered as unknown, in seconds, `0` disables it
+    pub negative_ttl_secs: u64,
+    /// How many purls unknown to Guac are remembered at most, the oldest are forgotten first
+    pub negative_capacity: usize,
+    /// How long establishing a connection to Guac may take, requests time out after
+    /// `resilience.timeout_ms`
+    pub connect_timeout_ms: u64,
+    /// How many idle connections to Guac are kept open for reuse
+    pub pool_size: usize,
+    /// How often idle connections are pinged to keep them alive, in seconds, `0` disables it
+    pub keep_alive_secs: u64,
+    /// The value of the `Authorization` header sent to Guac, e.g. `Bearer <TOKEN>`
+    pub authorization: Option<String>,
+    /// How many packages are looked up by a single query
+    pub batch_size: usize,
+    /// The security data API the details of vulnerabilities are fetched from
+    pub security_data_url: String,
+}
+
+impl Default for GuacConfig {
+    fn default() -> Self {
+        Self {
+            url: "http://localhost:8080/query".to_string(),
+            resilience: Default::default(),
+            probe_interval_secs: 30,
+            negative_ttl_secs: 30,
+            negative_capacity: 10_000,
+            connect_timeout_ms: 5000,
+            pool_size: 32,
+            keep_alive_secs: 60,
+            authorization: None,
+            batch_size: 50,
+            security_data_url: "https://access.redhat.com/hydra/rest/securitydata".to_string(),
+        }
+    }
+}
+
+/// Fallback to deps.dev for packages unknown to Guac.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DepsDevConfig {
+    pub enabled: bool,
+    pub url: String,
+    /// Where to send the purls found through the fallback, for ingestion into Guac
+    pub ingest_url: Option<String>,
+    /// The most purls waiting to be sent for ingestion, further ones are dropped until they were
+    /// sent
+    pub max_queued: usize,
+}
+
+impl Default for DepsDevConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            url: "https://api.deps.dev/v3alpha".to_string(),
+            ingest_url: None,
+            max_queued: 1000,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Snyk {
+    pub org: Option<String>,
+    /// Can be replaced at runtime, using the admin API
+    pub token: Option<String>,
+    /// The REST API of Snyk
+    pub url: String,
+    /// Timeout of a call, in seconds
+    pub timeout_secs: u64,
+    /// Retries of calls which were rate limited, timed out or failed on the server side
+    pub retries: u32,
+    /// Backoff before the first retry, doubled for every further retry, if Snyk doesn't send a
+    /// `Retry-After`
+    pub backoff_ms: u64,
+    /// Failed calls in a row opening the circuit breaker, after their retries
+    pub breaker_threshold: u32,
+    /// How long the circuit breaker stays open, in seconds
+    pub breaker_reset_secs: u64,
+}
+
+impl Default for Snyk {
+    fn default() -> Self {
+        Self {
+            org: None,
+            token: None,
+            url: "https://api.snyk.io/rest".to_string(),
+            timeout_secs: 10,
+            retries: 3,
+            backoff_ms: 500,
+            breaker_threshold: 5,
+            breaker_reset_secs: 30,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    pub backend: CacheBackend,
+    pub redis_url: Option<String>,
+    /// Time to live of cache entries, in seconds
+    pub ttl: u64,
+    /// How many entries the in-memory cache keeps at most, the oldest are evicted first
+    pub max_entries: usize,
+    /// How often cached dependency subgraphs are invalidated at most, in seconds, invalidations in
+    /// between are applied once it passed
+    pub subgraph_invalidation_secs: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            backend: CacheBackend::Memory,
+            redis_url: None,
+            ttl: 300,
+            max_entries: 100_000,
+            subgraph_invalidation_secs: 10,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AdminConfig {
+    pub bind: String,
+    pub port: u16,
+    pub token: Option<String>,
+    /// Serve the admin listener without authentication if neither a token is set nor RBAC is
+    /// enabled, e.g. behind an authenticating proxy, the server refuses to start otherwise
+    pub allow_unauthenticated: bool,
+}
+
+impl Default for AdminConfig {
+    fn default() -> Self {
+        Self {
+            bind: "127.0.0.1".to_string(),
+            port: 9090,
+            token: None,
+            allow_unauthenticated: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ResilienceConfig {
+    pub retries: u32,
+    pub backoff_ms: u64,
+    pub timeout_ms: u64,
+    pub breaker_threshold: u32,
+    pub breaker_reset_secs: u64,
+}
+
+impl Default for ResilienceConfig {
+    fn default() -> Self {
+        Self {
+            retries: 3,
+            backoff_ms: 100,
+            timeout_ms: 10000,
+            breaker_threshold: 5,
+            breaker_reset_secs: 30,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChaosConfig {
+    /// Latency added to each call, in milliseconds
+    pub latency_ms: u64,
+    /// The share of calls failing, between `0.0` and `1.0`
+    pub error_rate: f64,
+}
+
+impl ChaosConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.latency_ms > 0 || self.error_rate > 0.0
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WebhookConfig {
+    /// Whether notifications are delivered, they are only queued otherwise
+    pub enabled: bool,
+    pub max_attempts: u32,
+    pub interval_secs: u64,
+    /// How long delivered and failed deliveries are kept, in seconds, `0` keeps them until
+    /// there are more than `max_deliveries`
+    pub retention_secs: u64,
+    /// How many deliveries are kept at most, the oldest delivered or failed ones are removed first
+    pub max_deliveries: usize,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_attempts: 5,
+            interval_secs: 10,
+            retention_secs: 7 * 24 * 60 * 60,
+            max_deliveries: 10_000,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WatchConfig {
+    /// How often the vulnerabilities of watched purls are re-evaluated, in seconds
+    pub interval_secs: u64,
+    /// If not empty, the only hosts callbacks may be sent to, `.example.com` allowing all its
+    /// subdomains. Callbacks must reach public addresses otherwise
+    pub allowed_callback_hosts: Vec<String>,
+}
+
+impl Default for WatchConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: 3600,
+            allowed_callback_hosts: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SigningConfig {
+    /// Keys used to sign webhooks and exports, in the format `<id>:<secret>`
+    pub keys: Vec<SigningKey>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SbomConfig {
+    /// Locations SBOMs are loaded from at startup, more can be added using the admin API
+    pub sources: Vec<SbomSource>,
+    pub indexing: IndexingLimits,
+    /// Reloading SBOMs of directory sources when their files change
+    pub reload: ReloadConfig,
+    /// The name of downloaded SBOMs, with the placeholders `{type}`, `{namespace}`, `{name}`,
+    /// `{version}` of the purl and `{format}` (`cdx` or `spdx`)
+    pub filename: String,
+}
+
+impl Default for SbomConfig {
+    fn default() -> Self {
+        Self {
+            sources: Vec::new(),
+            indexing: IndexingLimits::default(),
+            reload: ReloadConfig::default(),
+            filename: "{name}-{version}.{format}.json".to_string(),
+        }
+    }
+}
+
+/// Limits of background SBOM indexing, `0` meaning unlimited.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IndexingLimits {
+    pub files_per_sec: u64,
+    pub bytes_per_sec: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ShutdownConfig {
+    /// How long readiness fails before the listeners are closed, in seconds
+    pub delay_secs: u64,
+    /// How long in-flight requests may take to complete, in seconds
+    pub timeout_secs: u64,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self {
+            delay_secs: 5,
+            timeout_secs: 30,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LoggingConfig {
+    /// `human` (the default) or `json`
+    pub format: LogFormat,
+    /// Access log sampling of successful requests, can be changed using the admin API
+    pub sampling: Vec<SamplingRule>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GrpcConfig {
+    pub enabled: bool,
+    pub bind: String,
+    pub port: u16,
+}
+
+impl Default for GrpcConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bind: "0.0.0.0".to_string(),
+            port: 50051,
+        }
+    }
+}
+
+/// The environment variables which set nothing, as a nested key is separated by `_` instead of
+/// `__`, with the variable they were meant to be, e.g. `TRUST_API_ADMIN__TOKEN` for
+/// `TRUST_API_ADMIN_TOKEN`.
+fn misspelled_env(
+    defaults: &serde_json::Value,
+    vars: impl IntoIterator<Item = String>,
+) -> Vec<(String, String)> {
+    vars.into_iter()
+        .filter_map(|var| {
+            let key = var.strip_prefix(ENV_PREFIX)?.to_ascii_lowercase();
+            let mut segments: Vec<String> = key.split("__").map(str::to_string).collect();
+            let mut value = defaults;
+            let mut misspelled = false;
+            let mut i = 0;
+            while i < segments.len() {
+                let object = value.as_object()?;
+                if let Some(nested) = object.get(&segments[i]) {
+                    value = nested;
+                    i += 1;
+                    continue;
+                }
+                // the longest field the segment starts with, e.g. `admin` of `admin_token`
+                let (field, rest) = object
+                    .keys()
+                    .filter_map(|field| {
+                        let rest = segments[i]
+                            .strip_prefix(field.as_str())?
+                            .strip_prefix('_')?;
+                        (!rest.is_empty()).then(|| (field.clone(), rest.to_string()))
+                    })
+                    .max_by_key(|(field, _)| field.len())?;
+                segments[i] = field;
+                segments.insert(i + 1, rest);
+                misspelled = true;
+            }
+            misspelled.then(|| {
+                let meant = format!("{}{}", ENV_PREFIX, segments.join("__").to_ascii_uppercase());
+                (var, meant)
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_admin_tokens_are_rejected() {
+        let mut config = Config::default();
+        config.validate().unwrap();
+        config.admin.token = Some(String::new());
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn chaos_error_rates_must_be_shares() {
+        let mut config = Config::default();
+        let chaos = ChaosConfig {
+            latency_ms: 0,
+            error_rate: 1.5,
+        };
+        config.chaos.insert("snyk".to_string(), chaos);
+        let error = config.validate().unwrap_err();
+        assert!(error.to_string().contains("chaos.snyk.error_rate"));
+    }
+
+    fn misspelled(vars: &[&str]) -> Vec<(String, String)> {
+        let defaults = serde_json::to_value(Config::default()).unwrap();
+        misspelled_env(&defaults, vars.iter().map(|v| v.to_string()))
+    }
+
+    #[test]
+    fn nested_keys_separated_by_a_single_underscore_are_reported() {
+        assert_eq!(
+            misspelled(&[
+                "TRUST_API_ADMIN_TOKEN",
+                "TRUST_API_GUAC_RESILIENCE__RETRIES"
+            ]),
+            vec![
+                (
+                    "TRUST_API_ADMIN_TOKEN".to_string(),
+                    "TRUST_API_ADMIN__TOKEN".to_string()
+                ),
+                (
+                    "TRUST_API_GUAC_RESILIENCE__RETRIES".to_string(),
+                    "TRUST_API_GUAC__RESILIENCE__RETRIES".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn keys_containing_underscores_are_not_reported() {
+        assert!(misspelled(&[
+            "TRUST_API_ADMIN__TOKEN",
+            "TRUST_API_GUAC__NEGATIVE_TTL_SECS",
+            "TRUST_API_GUAC__RESILIENCE__TIMEOUT_MS",
+            "TRUST_API_UNRELATED",
+            "PATH",
+        ])
+        .is_empty());
+    }
+}
@@ -0,0 +1,781 @@
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Lists the fields of the original SBOM lost in its conversion.
+pub const LOSSY_FIELDS_HEADER: &str = "X-Trust-Lossy-Fields";
+
+/// The formats SBOMs are converted between.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SbomFormat {
+    CycloneDx,
+    Spdx,
+}
+
+impl SbomFormat {
+    /// The format of a document, `None` if it is neither CycloneDX nor SPDX.
+    pub fn of(document: &Value) -> Option<Self> {
+        if document.get("bomFormat").and_then(|f| f.as_str()) == Some("CycloneDX") {
+            Some(Self::CycloneDx)
+        } else if document.get("spdxVersion").is_some() {
+            Some(Self::Spdx)
+        } else {
+            None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::CycloneDx => "cyclonedx",
+            Self::Spdx => "spdx",
+        }
+    }
+}
+
+/// A converted SBOM, with the fields of the original which have no counterpart in its format.
+pub struct Converted {
+    pub document: Value,
+    /// Paths of the dropped fields, like `components[].properties`
+    pub lossy: BTreeSet<String>,
+}
+
+/// Convert an SBOM, `None` if its format is unknown.
+///
+/// Documents already in the format are returned unchanged.
+pub fn convert(document: &Value, format: SbomFormat) -> Option<Converted> {
+    match (SbomFormat::of(document)?, format) {
+        (SbomFormat::CycloneDx, SbomFormat::Spdx) => Some(to_spdx(document)),
+        (SbomFormat::Spdx, SbomFormat::CycloneDx) => Some(to_cyclonedx(document)),
+        _ => Some(Converted {
+            document: document.clone(),
+            lossy: BTreeSet::new(),
+        }),
+    }
+}
+
+/// Documents with more components or packages are converted on a thread for blocking work.
+const BLOCKING_ITEMS: usize = 1000;
+
+/// Convert an SBOM like [`convert`], large documents on a thread for blocking work, so they don't
+/// hold up the other requests of the worker.
+pub async fn convert_async(document: Value, format: SbomFormat) -> Option<Converted> {
+    let items = list(document.get("components")).len() + list(document.get("packages")).len();
+    if items <= BLOCKING_ITEMS {
+        return convert(&document, format);
+    }
+    tokio::task::spawn_blocking(move || convert(&document, format))
+        .await
+        .unwrap_or_else(|e| {
+            log::warn!("Error converting an SBOM: {}", e);
+            None
+        })
+}
+
+/// The CycloneDX fields of components which are carried over to SPDX packages.
+const COMPONENT_FIELDS: &[&str] = &[
+    "bom-ref",
+    "type",
+    "name",
+    "group",
+    "version",
+    "description",
+    "supplier",
+    "publisher",
+    "author",
+    "purl",
+    "cpe",
+    "hashes",
+    "licenses",
+    "copyright",
+    "components",
+    "externalReferences",
+];
+
+/// The top level CycloneDX fields which are carried over to SPDX.
+const BOM_FIELDS: &[&str] = &[
+    "bomFormat",
+    "specVersion",
+    "serialNumber",
+    "version",
+    "metadata",
+    "components",
+    "dependencies",
+];
+
+/// The SPDX fields of packages which are carried over to CycloneDX components.
+const PACKAGE_FIELDS: &[&str] = &[
+    "SPDXID",
+    "name",
+    "versionInfo",
+    "supplier",
+    "originator",
+    "description",
+    "summary",
+    "externalRefs",
+    "checksums",
+    "licenseDeclared",
+    "licenseConcluded",
+    "copyrightText",
+    "downloadLocation",
+    "homepage",
+    "primaryPackagePurpose",
+    "filesAnalyzed",
+];
+
+/// The top level SPDX fields which are carried over to CycloneDX.
+const DOCUMENT_FIELDS: &[&str] = &[
+    "spdxVersion",
+    "SPDXID",
+    "dataLicense",
+    "name",
+    "documentNamespace",
+    "creationInfo",
+    "documentDescribes",
+    "packages",
+    "relationships",
+];
+
+fn to_spdx(document: &Value) -> Converted {
+    let mut lossy = BTreeSet::new();
+    unknown_fields(document, BOM_FIELDS, "", &mut lossy);
+    if let Some(metadata) = document.get("metadata") {
+        unknown_fields(
+            metadata,
+            &["timestamp", "tools", "component"],
+            "metadata",
+            &mut lossy,
+        );
+    }
+
+    let mut components = Vec::new();
+    let root = document.pointer("/metadata/component");
+    if let Some(root) = root {
+        flatten(root, "metadata.component", &mut components);
+    }
+    for component in list(document.get("components")) {
+        flatten(component, "components[]", &mut components);
+    }
+
+    let mut ids = BTreeMap::new();
+    let mut packages = Vec::new();
+    // the names of the licenses referenced as `LicenseRef-`, by their reference
+    let mut extracted = BTreeMap::new();
+    for (n, (path, component)) in components.iter().enumerate() {
+        unknown_fields(component, COMPONENT_FIELDS, path, &mut lossy);
+        let id = format!("SPDXRef-{}", n);
+        if let Some(bom_ref) = component.get("bom-ref").and_then(|r| r.as_str()) {
+            ids.insert(bom_ref.to_string(), id.clone());
+        }
+        packages.push(package(component, &id, &mut extracted));
+    }
+
+    let root_id = root.map(|_| "SPDXRef-0".to_string());
+    let mut relationships = Vec::new();
+    if let Some(root_id) = &root_id {
+        relationships.push(relationship("SPDXRef-DOCUMENT", "DESCRIBES", root_id));
+    }
+    let dependencies = list(document.get("dependencies"));
+    for dependency in dependencies {
+        let from = dependency
+            .get("ref")
+            .and_then(|r| r.as_str())
+            .and_then(|r| ids.get(r));
+        for to in list(dependency.get("dependsOn")) {
+            let to = to.as_str().and_then(|r| ids.get(r));
+            if let (Some(from), Some(to)) = (from, to) {
+                relationships.push(relationship(from, "DEPENDS_ON", to));
+            }
+        }
+    }
+    if dependencies.is_empty() {
+        // without a dependency graph, the components are at least part of the product
+        if let Some(root_id) = &root_id {
+            for n in 1..packages.len() {
+                relationships.push(relationship(root_id, "CONTAINS", &format!("SPDXRef-{}", n)));
+            }
+        }
+    }
+
+    let name = root
+        .and_then(|r| r.get("name"))
+        .and_then(|n| n.as_str())
+        .unwrap_or("sbom");
+    let namespace = match document.get("serialNumber").and_then(|s| s.as_str()) {
+        Some(serial) => serial.trim_start_matches("urn:uuid:").to_string(),
+        None => crate::sbom::sha256_of(document),
+    };
+    let mut creators: Vec<String> = tools(document)
+        .into_iter()
+        .map(|t| format!("Tool: {}", t))
+        .collect();
+    creators.push("Tool: trust-api".to_string());
+    let created = document
+        .pointer("/metadata/timestamp")
+        .cloned()
+        .unwrap_or_else(|| json!(chrono::Utc::now().to_rfc3339()));
+
+    let mut spdx = json!({
+        "spdxVersion": "SPDX-2.3",
+        "dataLicense": "CC0-1.0",
+        "SPDXID": "SPDXRef-DOCUMENT",
+        "name": name,
+        "documentNamespace": format!("https://trust-api/spdx/{}-{}", name, namespace),
+        "creationInfo": {"created": created, "creators": creators},
+        "packages": packages,
+        "relationships": relationships,
+    });
+    if let Some(root_id) = root_id {
+        spdx["documentDescribes"] = json!([root_id]);
+    }
+    if !extracted.is_empty() {
+        spdx["hasExtractedLicensingInfos"] = extracted
+            .into_iter()
+            .map(|(id, name)| json!({"licenseId": id, "name": name, "extractedText": name}))
+            .collect();
+    }
+    Converted {
+        document: spdx,
+        lossy,
+    }
+}
+
+/// The components of a component, depth first, with the path they were found at.
+fn flatten<'a>(component: &'a Value, path: &str, found: &mut Vec<(String, &'a Value)>) {
+    found.push((path.to_string(), component));
+    for nested in list(component.get("components")) {
+        flatten(nested, &format!("{}.components[]", path), found);
+    }
+}
+
+fn package(component: &Value, id: &str, extracted: &mut BTreeMap<String, String>) -> Value {
+    let text = |field: &str| component.get(field).and_then(|v| v.as_str());
+    let name = match (text("group"), text("name")) {
+        (Some(group), Some(name)) if !group.is_empty() => format!("{}:{}", group, name),
+        (_, name) => name.unwrap_or_default().to_string(),
+    };
+    let mut package = json!({
+        "SPDXID": id,
+        "name": name,
+        "downloadLocation": "NOASSERTION",
+        "filesAnalyzed": false,
+        "licenseConcluded": "NOASSERTION",
+        "licenseDeclared": licenses(component, extracted).unwrap_or_else(|| "NOASSERTION".to_string()),
+        "copyrightText": text("copyright").unwrap_or("NOASSERTION"),
+    });
+    if let Some(version) = text("version") {
+        package["versionInfo"] = json!(version);
+    }
+    if let Some(description) = text("description") {
+        package["description"] = json!(description);
+    }
+    let supplier = component
+        .pointer("/supplier/name")
+        .and_then(|s| s.as_str())
+        .or_else(|| text("publisher"));
+    if let Some(supplier) = supplier {
+        package["supplier"] = json!(format!("Organization: {}", supplier));
+    }
+    if let Some(author) = text("author") {
+        package["originator"] = json!(format!("Person: {}", author));
+    }
+    if let Some(purpose) = text("type").and_then(purpose_of) {
+        package["primaryPackagePurpose"] = json!(purpose);
+    }
+
+    let mut refs = Vec::new();
+    if let Some(purl) = text("purl") {
+        refs.push(json!({"referenceCategory": "PACKAGE_MANAGER", "referenceType": "purl", "referenceLocator": purl}));
+    }
+    if let Some(cpe) = text("cpe") {
+        refs.push(json!({"referenceCategory": "SECURITY", "referenceType": "cpe23Type", "referenceLocator": cpe}));
+    }
+    if !refs.is_empty() {
+        package["externalRefs"] = json!(refs);
+    }
+    for reference in list(component.get("externalReferences")) {
+        let url = reference.get("url").and_then(|u| u.as_str());
+        match (reference.get("type").and_then(|t| t.as_str()), url) {
+            (Some("website"), Some(url)) => package["homepage"] = json!(url),
+            (Some("distribution"), Some(url)) => package["downloadLocation"] = json!(url),
+            _ => {}
+        }
+    }
+
+    let checksums: Vec<Value> = list(component.get("hashes"))
+        .iter()
+        .filter_map(|h| {
+            let algorithm = match h.get("alg")?.as_str()? {
+                "SHA-1" => "SHA1",
+                "SHA-256" => "SHA256",
+                "SHA-384" => "SHA384",
+                "SHA-512" => "SHA512",
+                alg => alg,
+            };
+            Some(json!({"algorithm": algorithm, "checksumValue": h.get("content")?}))
+        })
+        .collect();
+    if !checksums.is_empty() {
+        package["checksums"] = json!(checksums);
+    }
+    package
+}
+
+/// The SPDX license expression of a CycloneDX component, its licenses joined with `AND`.
+///
+/// Licenses named rather than identified by an SPDX id are referenced as `LicenseRef-`, their
+/// names added to `extracted`. `NOASSERTION` if an expression isn't a valid SPDX expression.
+fn licenses(component: &Value, extracted: &mut BTreeMap<String, String>) -> Option<String> {
+    let mut licenses = Vec::new();
+    for license in list(component.get("licenses")) {
+        let text = |pointer: &str| license.pointer(pointer).and_then(|v| v.as_str());
+        if let Some(expression) = text("/expression") {
+            if spdx::Expression::parse(expression).is_err() {
+                return Some("NOASSERTION".to_string());
+            }
+            licenses.push(expression.to_string());
+        } else if let Some(id) = text("/license/id").filter(|id| spdx::license_id(id).is_some()) {
+            licenses.push(id.to_string());
+        } else if let Some(name) = text("/license/name").or_else(|| text("/license/id")) {
+            let id = license_ref(name);
+            extracted.insert(id.clone(), name.to_string());
+            licenses.push(id);
+        }
+    }
+    match licenses.len() {
+        0 => None,
+        1 => Some(licenses[0].to_string()),
+        _ => Some(
+            licenses
+                .iter()
+                .map(|l| format!("({})", l))
+                .collect::<Vec<_>>()
+                .join(" AND "),
+        ),
+    }
+}
+
+/// The `LicenseRef-` of a license name, with the characters other than letters, digits, `.` and
+/// `-` replaced by `-`.
+fn license_ref(name: &str) -> String {
+    let id: String = name
+        .trim()
+        .chars()
+        .map(
+            |c| match c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                true => c,
+                false => '-',
+            },
+        )
+        .collect();
+    format!("LicenseRef-{}", id)
+}
+
+fn purpose_of(ty: &str) -> Option<&'static str> {
+    match ty {
+        "application" => Some("APPLICATION"),
+        "framework" => Some("FRAMEWORK"),
+        "library" => Some("LIBRARY"),
+        "container" => Some("CONTAINER"),
+        "operating-system" => Some("OPERATING-SYSTEM"),
+        "device" => Some("DEVICE"),
+        "firmware" => Some("FIRMWARE"),
+        "file" => Some("FILE"),
+        _ => None,
+    }
+}
+
+fn relationship(from: &str, ty: &str, to: &str) -> Value {
+    json!({"spdxElementId": from, "relationshipType": ty, "relatedSpdxElement": to})
+}
+
+/// The names of the tools which created a CycloneDX SBOM, of both the 1.4 and 1.5 layouts.
+fn tools(document: &Value) -> Vec<String> {
+    let tools = document.pointer("/metadata/tools");
+    let tools = tools
+        .and_then(|t| t.get("components"))
+        .or(tools)
+        .and_then(|t| t.as_array());
+    tools
+        .map(|tools| {
+            tools
+                .iter()
+                .filter_map(|t| t.get("name")?.as_str().map(ToString::to_string))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn to_cyclonedx(document: &Value) -> Converted {
+    let mut lossy = BTreeSet::new();
+    unknown_fields(document, DOCUMENT_FIELDS, "", &mut lossy);
+
+    let described = crate::sbom::described_package(document)
+        .and_then(|p| p.get("SPDXID"))
+        .and_then(|id| id.as_str());
+    let mut root = None;
+    let mut components = Vec::new();
+    for package in list(document.get("packages")) {
+        unknown_fields(package, PACKAGE_FIELDS, "packages[]", &mut lossy);
+        let component = component(package, &mut lossy);
+        match package.get("SPDXID").and_then(|id| id.as_str()) {
+            Some(id) if Some(id) == described => root = Some(component),
+            _ => components.push(component),
+        }
+    }
+
+    let mut dependencies: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for relationship in list(document.get("relationships")) {
+        let text = |field: &str| relationship.get(field).and_then(|v| v.as_str());
+        let (from, ty, to) = match (
+            text("spdxElementId"),
+            text("relationshipType"),
+            text("relatedSpdxElement"),
+        ) {
+            (Some(from), Some(ty), Some(to)) => (from, ty, to),
+            _ => continue,
+        };
+        let (from, to) = match ty {
+            "DEPENDS_ON" | "CONTAINS" => (from, to),
+            "DESCRIBES" => continue,
+            ty if ty.ends_with("DEPENDENCY_OF") => (to, from),
+            ty => {
+                lossy.insert(format!("relationships[].{}", ty));
+                continue;
+            }
+        };
+        dependencies
+            .entry(from.to_string())
+            .or_default()
+            .push(to.to_string());
+    }
+
+    let mut metadata = Map::new();
+    if let Some(created) = document.pointer("/creationInfo/created") {
+        metadata.insert("timestamp".to_string(), created.clone());
+    }
+    let tools: Vec<Value> = list(document.pointer("/creationInfo/creators"))
+        .iter()
+        .filter_map(|c| c.as_str()?.strip_prefix("Tool:"))
+        .map(|name| json!({"name": name.trim()}))
+        .chain(std::iter::once(json!({"name": "trust-api"})))
+        .collect();
+    metadata.insert("tools".to_string(), json!(tools));
+    if let Some(root) = root {
+        metadata.insert("component".to_string(), root);
+    }
+
+    Converted {
+        document: json!({
+            "bomFormat": "CycloneDX",
+            "specVersion": "1.4",
+            "version": 1,
+            "metadata": metadata,
+            "components": components,
+            "dependencies": dependencies
+                .into_iter()
+                .map(|(r, depends_on)| json!({"ref": r, "dependsOn": depends_on}))
+                .collect::<Vec<_>>(),
+        }),
+        lossy,
+    }
+}
+
+fn component(package: &Value, lossy: &mut BTreeSet<String>) -> Value {
+    let text = |field: &str| {
+        package
+            .get(field)
+            .and_then(|v| v.as_str())
+            .filter(|v| *v != "NOASSERTION" && *v != "NONE")
+    };
+    let ty = match text("primaryPackagePurpose") {
+        Some("APPLICATION") => "application",
+        Some("FRAMEWORK") => "framework",
+        Some("CONTAINER") => "container",
+        Some("OPERATING-SYSTEM") => "operating-system",
+        Some("DEVICE") => "device",
+        Some("FIRMWARE") => "firmware",
+        Some("FILE") => "file",
+        _ => "library",
+    };
+    let mut component = json!({
+        "type": ty,
+        "name": text("name").unwrap_or_default(),
+    });
+    if let Some(id) = text("SPDXID") {
+        component["bom-ref"] = json!(id);
+    }
+    if let Some(version) = text("versionInfo") {
+        component["version"] = json!(version);
+    }
+    if let Some(description) = text("description").or_else(|| text("summary")) {
+        component["description"] = json!(description);
+    }
+    if let Some(supplier) = text("supplier") {
+        component["supplier"] = json!({"name": party(supplier)});
+    }
+    if let Some(originator) = text("originator") {
+        component["author"] = json!(party(originator));
+    }
+    if let Some(copyright) = text("copyrightText") {
+        component["copyright"] = json!(copyright);
+    }
+    if let Some(license) = text("licenseDeclared").or_else(|| text("licenseConcluded")) {
+        component["licenses"] = json!([{ "expression": license }]);
+    }
+    if text("licenseDeclared").is_some() && text("licenseConcluded").is_some() {
+        lossy.insert("packages[].licenseConcluded".to_string());
+    }
+
+    for reference in list(package.get("externalRefs")) {
+        let locator = reference.get("referenceLocator").and_then(|l| l.as_str());
+        match (
+            reference.get("referenceType").and_then(|t| t.as_str()),
+            locator,
+        ) {
+            (Some("purl"), Some(purl)) if component.get("purl").is_none() => {
+                component["purl"] = json!(purl)
+            }
+            (Some("cpe23Type") | Some("cpe22Type"), Some(cpe))
+                if component.get("cpe").is_none() =>
+            {
+                component["cpe"] = json!(cpe)
+            }
+            (Some(ty), _) => {
+                lossy.insert(format!("packages[].externalRefs[].{}", ty));
+            }
+            _ => {}
+        }
+    }
+
+    let mut references = Vec::new();
+    if let Some(homepage) = text("homepage") {
+        references.push(json!({"type": "website", "url": homepage}));
+    }
+    if let Some(location) = text("downloadLocation") {
+        references.push(json!({"type": "distribution", "url": location}));
+    }
+    if !references.is_empty() {
+        component["externalReferences"] = json!(references);
+    }
+
+    let hashes: Vec<Value> = list(package.get("checksums"))
+        .iter()
+        .filter_map(|c| {
+            let alg = hash_algorithm(c.get("algorithm")?.as_str()?)?;
+            Some(json!({"alg": alg, "content": c.get("checksumValue")?}))
+        })
+        .collect();
+    if !hashes.is_empty() {
+        component["hashes"] = json!(hashes);
+    }
+    component
+}
+
+/// The name of an SPDX `Organization: ` or `Person: ` party, without its email address.
+fn party(party: &str) -> &str {
+    let name = party
+        .strip_prefix("Organization:")
+        .or_else(|| party.strip_prefix("Person:"))
+        .unwrap_or(party);
+    name.split(" (").next().unwrap_or(name).trim()
+}
+
+fn hash_algorithm(algorithm: &str) -> Option<&'static str> {
+    match algorithm {
+        "MD5" => Some("MD5"),
+        "SHA1" => Some("SHA-1"),
+        "SHA256" => Some("SHA-256"),
+        "SHA384" => Some("SHA-384"),
+        "SHA512" => Some("SHA-512"),
+        "SHA3-256" => Some("SHA3-256"),
+        "SHA3-384" => Some("SHA3-384"),
+        "SHA3-512" => Some("SHA3-512"),
+        "BLAKE2b-256" => Some("BLAKE2b-256"),
+        "BLAKE2b-384" => Some("BLAKE2b-384"),
+        "BLAKE2b-512" => Some("BLAKE2b-512"),
+        "BLAKE3" => Some("BLAKE3"),
+        _ => None,
+    }
+}
+
+/// Record the fields of an object which are not converted.
+fn unknown_fields(value: &Value, known: &[&str], path: &str, lossy: &mut BTreeSet<String>) {
+    if let Some(fields) = value.as_object() {
+        for field in fields.keys().filter(|f| !known.contains(&f.as_str())) {
+            lossy.insert(match path {
+                "" => field.clone(),
+                path => format!("{}.{}", path, field),
+            });
+        }
+    }
+}
+
+fn list(value: Option<&Value>) -> &[Value] {
+    value
+        .and_then(|v| v.as_array())
+        .map(|v| v.as_slice())
+        .unwrap_or(&[])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cyclonedx() -> Value {
+        json!({
+            "bomFormat": "CycloneDX",
+            "specVersion": "1.4",
+            "serialNumber": "urn:uuid:3e671687-395b-41f5-a30f-a58921a69b79",
+            "version": 1,
+            "metadata": {
+                "timestamp": "2023-03-01T10:00:00Z",
+                "component": {"bom-ref": "app", "type": "application", "name": "app", "version": "1.0", "purl": "pkg:maven/io.example/app@1.0"},
+            },
+            "components": [
+                {
+                    "bom-ref": "web",
+                    "type": "library",
+                    "group": "io.vertx",
+                    "name": "vertx-web",
+                    "version": "4.3.7",
+                    "purl": "pkg:maven/io.vertx/vertx-web@4.3.7",
+                    "hashes": [{"alg": "SHA-256", "content": "abc"}],
+                    "licenses": [{"license": {"id": "Apache-2.0"}}, {"license": {"id": "EPL-2.0"}}],
+                    "scope": "required",
+                },
+            ],
+            "dependencies": [{"ref": "app", "dependsOn": ["web"]}],
+        })
+    }
+
+    #[test]
+    fn cyclonedx_is_converted_to_spdx() {
+        let converted = convert(&cyclonedx(), SbomFormat::Spdx).unwrap();
+        let spdx = &converted.document;
+        assert_eq!(SbomFormat::of(spdx), Some(SbomFormat::Spdx));
+        assert_eq!(spdx["documentDescribes"], json!(["SPDXRef-0"]));
+
+        let web = &spdx["packages"][1];
+        assert_eq!(web["name"], "io.vertx:vertx-web");
+        assert_eq!(web["versionInfo"], "4.3.7");
+        assert_eq!(web["licenseDeclared"], "(Apache-2.0) AND (EPL-2.0)");
+        assert_eq!(
+            web["checksums"],
+            json!([{"algorithm": "SHA256", "checksumValue": "abc"}])
+        );
+        assert_eq!(
+            web["externalRefs"][0]["referenceLocator"],
+            "pkg:maven/io.vertx/vertx-web@4.3.7"
+        );
+        assert!(spdx["relationships"]
+            .as_array()
+            .unwrap()
+            .contains(&relationship("SPDXRef-0", "DEPENDS_ON", "SPDXRef-1")));
+        assert_eq!(
+            converted.lossy,
+            BTreeSet::from(["components[].scope".to_string()])
+        );
+    }
+
+    #[test]
+    fn license_names_are_license_refs() {
+        let mut extracted = BTreeMap::new();
+        let component = |licenses: Value| json!({ "licenses": licenses });
+
+        let named =
+            component(json!([{"license": {"name": "The Apache Software License, Version 2.0"}}]));
+        assert_eq!(
+            licenses(&named, &mut extracted).as_deref(),
+            Some("LicenseRef-The-Apache-Software-License--Version-2.0")
+        );
+        assert_eq!(
+            extracted["LicenseRef-The-Apache-Software-License--Version-2.0"],
+            "The Apache Software License, Version 2.0"
+        );
+
+        // ids which are not SPDX ids are names
+        let unknown = component(json!([{"license": {"id": "Custom 1"}}]));
+        assert_eq!(
+            licenses(&unknown, &mut extracted).as_deref(),
+            Some("LicenseRef-Custom-1")
+        );
+
+        let expression = component(json!([{"expression": "Apache-2.0 OR EPL-2.0"}]));
+        assert_eq!(
+            licenses(&expression, &mut extracted).as_deref(),
+            Some("Apache-2.0 OR EPL-2.0")
+        );
+        let invalid = component(json!([{"expression": "Apache License or whatever"}]));
+        assert_eq!(
+            licenses(&invalid, &mut extracted).as_deref(),
+            Some("NOASSERTION")
+        );
+        assert_eq!(licenses(&component(json!([])), &mut extracted), None);
+    }
+
+    #[test]
+    fn license_refs_are_extracted_licensing_infos() {
+        let mut document = cyclonedx();
+        document["components"][0]["licenses"] = json!([{"license": {"name": "Vert.x license"}}]);
+        let spdx = convert(&document, SbomFormat::Spdx).unwrap().document;
+        assert_eq!(
+            spdx["packages"][1]["licenseDeclared"],
+            "LicenseRef-Vert.x-license"
+        );
+        assert_eq!(
+            spdx["hasExtractedLicensingInfos"],
+            json!([{
+                "licenseId": "LicenseRef-Vert.x-license",
+                "name": "Vert.x license",
+                "extractedText": "Vert.x license",
+            }])
+        );
+    }
+
+    #[test]
+    fn spdx_is_converted_to_cyclonedx() {
+        let spdx = convert(&cyclonedx(), SbomFormat::Spdx).unwrap().document;
+        let converted = convert(&spdx, SbomFormat::CycloneDx).unwrap();
+        let cyclonedx = &converted.document;
+        assert_eq!(SbomFormat::of(cyclonedx), Some(SbomFormat::CycloneDx));
+        assert_eq!(cyclonedx["metadata"]["component"]["name"], "app");
+        assert_eq!(
+            cyclonedx["metadata"]["component"]["purl"],
+            "pkg:maven/io.example/app@1.0"
+        );
+
+        let web = &cyclonedx["components"][0];
+        assert_eq!(web["purl"], "pkg:maven/io.vertx/vertx-web@4.3.7");
+        assert_eq!(
+            web["licenses"],
+            json!([{"expression": "(Apache-2.0) AND (EPL-2.0)"}])
+        );
+        assert_eq!(web["hashes"], json!([{"alg": "SHA-256", "content": "abc"}]));
+        assert_eq!(
+            cyclonedx["dependencies"],
+            json!([{"ref": "SPDXRef-0", "dependsOn": ["SPDXRef-1"]}])
+        );
+    }
+
+    #[test]
+    fn documents_in_the_format_are_unchanged() {
+        let converted = convert(&cyclonedx(), SbomFormat::CycloneDx).unwrap();
+        assert_eq!(converted.document, cyclonedx());
+        assert!(converted.lossy.is_empty());
+        assert!(convert(&json!({}), SbomFormat::Spdx).is_none());
+    }
+
+    #[tokio::test]
+    async fn large_documents_are_converted() {
+        let mut document = cyclonedx();
+        document["components"] = (0..=BLOCKING_ITEMS)
+            .map(|n| json!({"type": "library", "name": format!("c{}", n)}))
+            .collect();
+        let converted = convert_async(document, SbomFormat::Spdx).await.unwrap();
+        assert_eq!(
+            converted.document["packages"].as_array().map(Vec::len),
+            Some(BLOCKING_ITEMS + 2)
+        );
+    }
+}
@@ -0,0 +1,213 @@
+use actix_web::{error, get, http::StatusCode, web, web::ServiceConfig, HttpResponse};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use utoipa::{OpenApi, ToSchema};
+
+use crate::package::{self, with_ids, Package, TrustedContent};
+
+pub(crate) fn configure() -> impl FnOnce(&mut ServiceConfig) {
+    |config: &mut ServiceConfig| {
+        config.service(get_by_coordinates);
+    }
+}
+
+/// The documentation of the coordinates endpoint.
+#[derive(OpenApi)]
+#[openapi(paths(get_by_coordinates), components(schemas(ApiError)))]
+pub(crate) struct ApiDoc;
+
+/// Architectures ending the NEVRA of an RPM.
+const RPM_ARCHITECTURES: &[&str] = &[
+    "x86_64", "aarch64", "ppc64le", "s390x", "i686", "noarch", "src",
+];
+
+/// The purl of Maven coordinates, `groupId:artifactId[:packaging[:classifier]]:version`.
+fn maven(coordinates: &str) -> Option<String> {
+    let parts: Vec<&str> = coordinates.split(':').collect();
+    if parts.iter().any(|p| p.is_empty()) {
+        return None;
+    }
+    let (group, artifact, version, packaging, classifier) = match parts.as_slice() {
+        [group, artifact, version] => (group, artifact, version, None, None),
+        [group, artifact, packaging, version] => (group, artifact, version, Some(packaging), None),
+        [group, artifact, packaging, classifier, version] => {
+            (group, artifact, version, Some(packaging), Some(classifier))
+        }
+        _ => return None,
+    };
+
+    let mut qualifiers = Vec::new();
+    if let Some(classifier) = classifier {
+        qualifiers.push(format!("classifier={}", urlencoding::encode(classifier)));
+    }
+    if let Some(packaging) = packaging {
+        qualifiers.push(format!("type={}", urlencoding::encode(packaging)));
+    }
+    let mut purl = format!(
+        "pkg:maven/{}/{}@{}",
+        urlencoding::encode(group),
+        urlencoding::encode(artifact),
+        urlencoding::encode(version)
+    );
+    if !qualifiers.is_empty() {
+        purl.push('?');
+        purl.push_str(&qualifiers.join("&"));
+    }
+    Some(purl)
+}
+
+/// The purl of an npm package, `name@version` or `@scope/name@version`.
+fn npm(coordinates: &str) -> Option<String> {
+    let (package, version) = coordinates.rsplit_once('@')?;
+    if version.is_empty() {
+        return None;
+    }
+    let (scope, name) = match package.strip_prefix('@') {
+        Some(scoped) => {
+            let (scope, name) = scoped.split_once('/')?;
+            (Some(scope), name)
+        }
+        None => (None, package),
+    };
+    if name.is_empty() || name.contains('/') || scope.map_or(false, str::is_empty) {
+        return None;
+    }
+    Some(match scope {
+        Some(scope) => format!(
+            "pkg:npm/%40{}/{}@{}",
+            urlencoding::encode(scope),
+            urlencoding::encode(name),
+            urlencoding::encode(version)
+        ),
+        None => format!(
+            "pkg:npm/{}@{}",
+            urlencoding::encode(name),
+            urlencoding::encode(version)
+        ),
+    })
+}
+
+/// The purl of an RPM, by its NEVRA `name-[epoch:]version-release.arch` and the vendor
+/// namespace.
+fn rpm(coordinates: &str, namespace: &str) -> Option<String> {
+    let (rest, arch) = coordinates.rsplit_once('.')?;
+    if !RPM_ARCHITECTURES.contains(&arch) {
+        return None;
+    }
+    let (rest, release) = rest.rsplit_once('-')?;
+    let (name, version) = rest.rsplit_once('-')?;
+    let (epoch, version) = match version.split_once(':') {
+        Some((epoch, version)) => (Some(epoch), version),
+        None => (None, version),
+    };
+    if name.is_empty()
+        || version.is_empty()
+        || release.is_empty()
+        || epoch.map_or(false, |e| e.parse::<u32>().is_err())
+    {
+        return None;
+    }
+
+    let mut purl = format!(
+        "pkg:rpm/{}/{}@{}-{}?arch={}",
+        urlencoding::encode(namespace),
+        urlencoding::encode(name),
+        urlencoding::encode(version),
+        urlencoding::encode(release),
+        arch
+    );
+    if let Some(epoch) = epoch {
+        purl.push_str(&format!("&epoch={}", epoch));
+    }
+    Some(purl)
+}
+
+#[derive(serde::Deserialize)]
+pub struct CoordinatesQuery {
+    ecosystem: Option<String>,
+    coordinates: Option<String>,
+    /// The vendor of RPMs
+    #[serde(default = "default_namespace")]
+    namespace: String,
+    #[serde(default)]
+    include_ids: bool,
+}
+
+fn default_namespace() -> String {
+    "redhat".to_string()
+}
+
+#[utoipa::path(
+    context_path = "/api/v1",
+    responses(
+        (status = 200, description = "Package found", body = Package),
+        (status = NOT_FOUND, description = "Package not found", body = Problem, example = json!(crate::problem::example(ApiError::Upstream(package::ApiError::PackageNotFound { purl: "pkg:maven/org.example/unknown@1.0".to_string() })))),
+        (status = BAD_REQUEST, description = "Invalid coordinates or unsupported ecosystem, or missing query argument", body = Problem, example = json!(crate::problem::example(ApiError::InvalidCoordinates { ecosystem: "maven".to_string(), coordinates: "vertx-web".to_string() })))
+    ),
+    params(
+        ("ecosystem" = String, Query, description = "The ecosystem of the coordinates: maven, npm or rpm"),
+        ("coordinates" = String, Query, description = "Coordinates native to the ecosystem, e.g. groupId:artifactId:version, name@version or a NEVRA"),
+        ("namespace" = Option<String>, Query, description = "The vendor of RPMs, defaults to redhat"),
+        ("include_ids" = Option<bool>, Query, description = "Include provider identifiers of vulnerabilities"),
+    )
+)]
+#[get("/package/by-coordinates")]
+pub async fn get_by_coordinates(
+    data: web::Data<TrustedContent>,
+    query: web::Query<CoordinatesQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let (ecosystem, coordinates) = match (&query.ecosystem, &query.coordinates) {
+        (Some(ecosystem), Some(coordinates)) => (ecosystem.to_lowercase(), coordinates.trim()),
+        _ => return Err(ApiError::MissingQueryArgument),
+    };
+    let purl = match ecosystem.as_str() {
+        "maven" => maven(coordinates),
+        "npm" => npm(coordinates),
+        "rpm" => rpm(coordinates, &query.namespace),
+        _ => return Err(ApiError::UnsupportedEcosystem { ecosystem }),
+    }
+    .ok_or_else(|| ApiError::InvalidCoordinates {
+        ecosystem: ecosystem.clone(),
+        coordinates: coordinates.to_string(),
+    })?;
+
+    let package: Package = data
+        .get_trusted(&data.canonicalize(&purl))
+        .await
+        .map_err(|e| ApiError::Upstream(e.into()))?;
+    Ok(HttpResponse::Ok().json(with_ids(package, query.include_ids)))
+}
+
+#[derive(Debug, Error, Serialize, Deserialize, ToSchema)]
+#[schema(as = CoordinatesApiError)]
+pub enum ApiError {
+    #[error("No query argument was specified")]
+    MissingQueryArgument,
+    #[error("Unsupported ecosystem {ecosystem}, use maven, npm or rpm")]
+    UnsupportedEcosystem { ecosystem: String },
+    #[error("{coordinates} are not valid {ecosystem} coordinates")]
+    InvalidCoordinates {
+        ecosystem: String,
+        coordinates: String,
+    },
+    #[error(transparent)]
+    Upstream(#[schema(inline)] package::ApiError),
+}
+
+impl error::ResponseError for ApiError {
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            ApiError::Upstream(e) => e.error_response(),
+            _ => crate::problem::response(self.status_code(), self, serde_json::json!({})),
+        }
+    }
+
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::MissingQueryArgument => StatusCode::BAD_REQUEST,
+            ApiError::UnsupportedEcosystem { ecosystem: _ } => StatusCode::BAD_REQUEST,
+            ApiError::InvalidCoordinates { .. } => StatusCode::BAD_REQUEST,
+            ApiError::Upstream(e) => e.status_code(),
+        }
+    }
+}
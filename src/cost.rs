@@ -0,0 +1,136 @@
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use once_cell::sync::{Lazy, OnceCell};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+const ANONYMOUS: &str = "anonymous";
+
+tokio::task_local! {
+    static CALLER: String;
+}
+
+/// The cost of calls to the providers, in arbitrary units.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CostConfig {
+    /// Cost of a call to the Snyk API
+    pub snyk_call: f64,
+    /// Cost of a Guac query, unless overridden by operation
+    pub guac_query: f64,
+    /// Cost of Guac queries by operation (e.g. `get_dependencies`), reflecting their complexity
+    pub guac_operations: HashMap<String, f64>,
+}
+
+impl Default for CostConfig {
+    fn default() -> Self {
+        Self {
+            snyk_call: 1.0,
+            guac_query: 1.0,
+            guac_operations: HashMap::new(),
+        }
+    }
+}
+
+impl CostConfig {
+    fn cost(&self, provider: &str, operation: &str) -> f64 {
+        match provider {
+            "snyk" => self.snyk_call,
+            "guac" => *self
+                .guac_operations
+                .get(operation)
+                .unwrap_or(&self.guac_query),
+            _ => 0.0,
+        }
+    }
+}
+
+/// The accumulated cost of a caller for a provider.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct CostEntry {
+    pub caller: String,
+    pub provider: String,
+    pub calls: u64,
+    pub cost: f64,
+}
+
+static CONFIG: OnceCell<CostConfig> = OnceCell::new();
+static LEDGER: Lazy<Mutex<HashMap<(String, String), CostEntry>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Set the cost model, must be called before recording the first call.
+pub fn init(config: CostConfig) {
+    if CONFIG.set(config).is_err() {
+        log::warn!("The cost model was already initialized");
+    }
+}
+
+/// The caller of the request currently being processed.
+pub fn caller() -> String {
+    CALLER
+        .try_with(|caller| caller.clone())
+        .unwrap_or_else(|_| ANONYMOUS.to_string())
+}
+
+/// Attribute a call to a provider to the current caller.
+pub fn record(provider: &str, operation: &str) {
+    let cost = CONFIG
+        .get_or_init(CostConfig::default)
+        .cost(provider, operation);
+    let caller = caller();
+    let mut ledger = LEDGER.lock().unwrap();
+    let entry = ledger
+        .entry((caller.clone(), provider.to_string()))
+        .or_insert_with(|| CostEntry {
+            caller,
+            provider: provider.to_string(),
+            ..Default::default()
+        });
+    entry.calls += 1;
+    entry.cost += cost;
+}
+
+/// The accumulated costs, optionally of a single caller, ordered by caller and provider.
+pub fn report(caller: Option<&str>) -> Vec<CostEntry> {
+    let mut entries: Vec<CostEntry> = LEDGER
+        .lock()
+        .unwrap()
+        .values()
+        .filter(|e| caller.map_or(true, |c| e.caller == c))
+        .cloned()
+        .collect();
+    entries.sort_by(|a, b| (&a.caller, &a.provider).cmp(&(&b.caller, &b.provider)));
+    entries
+}
+
+pub fn reset() {
+    LEDGER.lock().unwrap().clear();
+}
+
+/// The caller of a request, a fingerprint of its API key, so keys don't end up in reports.
+pub fn caller_of(req: &ServiceRequest) -> String {
+    req.headers()
+        .get(API_KEY_HEADER)
+        .filter(|key| !key.is_empty())
+        .map(|key| {
+            let digest = Sha256::digest(key.as_bytes());
+            format!("key:{}", &hex::encode(digest)[..16])
+        })
+        .unwrap_or_else(|| ANONYMOUS.to_string())
+}
+
+/// Identify the caller of a request, see [`caller_of`].
+pub fn middleware<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+{
+    let caller = caller_of(&req);
+    CALLER.scope(caller, srv.call(req))
+}
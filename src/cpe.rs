@@ -0,0 +1,390 @@
+use actix_web::{error, get, http::StatusCode, web, web::ServiceConfig, HttpResponse};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use thiserror::Error;
+use utoipa::{OpenApi, ToSchema};
+
+use crate::error::TrustError;
+use crate::package::{self, with_ids, Package, TrustedContent};
+
+pub(crate) fn configure() -> impl FnOnce(&mut ServiceConfig) {
+    |config: &mut ServiceConfig| {
+        config.service(get_by_cpe);
+    }
+}
+
+/// The documentation of the CPE endpoint.
+#[derive(OpenApi)]
+#[openapi(paths(get_by_cpe), components(schemas(ApiError)))]
+pub(crate) struct ApiDoc;
+
+/// Well-known products, by CPE vendor and product, and the packages they are published as.
+const MAPPINGS: &[(&str, &str, &[&str])] = &[
+    (
+        "apache",
+        "log4j",
+        &[
+            "pkg:maven/org.apache.logging.log4j/log4j-core",
+            "pkg:maven/org.apache.logging.log4j/log4j-api",
+        ],
+    ),
+    (
+        "apache",
+        "commons_text",
+        &["pkg:maven/org.apache.commons/commons-text"],
+    ),
+    (
+        "apache",
+        "tomcat",
+        &["pkg:maven/org.apache.tomcat/tomcat-catalina"],
+    ),
+    (
+        "eclipse",
+        "vert.x",
+        &[
+            "pkg:maven/io.vertx/vertx-core",
+            "pkg:maven/io.vertx/vertx-web",
+        ],
+    ),
+    (
+        "fasterxml",
+        "jackson-databind",
+        &["pkg:maven/com.fasterxml.jackson.core/jackson-databind"],
+    ),
+    ("netty", "netty", &["pkg:maven/io.netty/netty-codec-http"]),
+    (
+        "vmware",
+        "spring_framework",
+        &["pkg:maven/org.springframework/spring-core"],
+    ),
+    ("openssl", "openssl", &["pkg:rpm/redhat/openssl"]),
+    ("haxx", "curl", &["pkg:rpm/redhat/curl"]),
+    ("gnu", "glibc", &["pkg:rpm/redhat/glibc"]),
+    ("lodash", "lodash", &["pkg:npm/lodash"]),
+];
+
+/// A CPE, parsed from the 2.3 formatted string or the 2.2 URI binding.
+///
+/// Attributes are lowercased, `None` stands for any (`*`) or not applicable (`-`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cpe {
+    /// `a` for applications, `o` for operating systems, `h` for hardware
+    pub part: String,
+    pub vendor: String,
+    pub product: String,
+    pub version: Option<String>,
+    /// Whether the version is not applicable (`-`) rather than any, e.g. of products without
+    /// versions
+    pub version_not_applicable: bool,
+    /// The software environment of the product, e.g. `node.js` or `python`
+    pub target_sw: Option<String>,
+}
+
+impl Cpe {
+    pub fn parse(cpe: &str) -> Option<Self> {
+        let cpe = cpe.trim();
+        let attributes = if let Some(rest) = cpe.strip_prefix("cpe:2.3:") {
+            split_formatted(rest)
+        } else if let Some(rest) = cpe.strip_prefix("cpe:/") {
+            split_uri(rest)?
+        } else {
+            return None;
+        };
+
+        let value = |i: usize| {
+            attributes
+                .get(i)
+                .map(|v| v.to_lowercase())
+                .filter(|v| !v.is_empty() && v != "*" && v != "-")
+        };
+        let part = value(0).filter(|p| matches!(p.as_str(), "a" | "o" | "h"))?;
+        Some(Self {
+            part,
+            vendor: value(1)?,
+            product: value(2)?,
+            version: value(3),
+            version_not_applicable: attributes.get(3).map_or(false, |v| v == "-"),
+            target_sw: value(8),
+        })
+    }
+}
+
+/// Split the attributes of a 2.3 formatted string, at colons which are not escaped.
+fn split_formatted(cpe: &str) -> Vec<String> {
+    let mut attributes = vec![String::new()];
+    let mut chars = cpe.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some(c) = chars.next() {
+                    attributes.last_mut().unwrap().push(c);
+                }
+            }
+            ':' => attributes.push(String::new()),
+            c => attributes.last_mut().unwrap().push(c),
+        }
+    }
+    attributes
+}
+
+/// Split the attributes of a 2.2 URI, unpacking the extended attributes of its edition.
+fn split_uri(cpe: &str) -> Option<Vec<String>> {
+    let mut attributes = cpe
+        .split(':')
+        .map(|a| urlencoding::decode(a).map(|a| a.into_owned()))
+        .collect::<Result<Vec<_>, _>>()
+        .ok()?;
+    // ~edition~sw_edition~target_sw~target_hw~other
+    if let Some(packed) = attributes
+        .get(5)
+        .and_then(|e| e.strip_prefix('~'))
+        .map(ToString::to_string)
+    {
+        let language = attributes.get(6).cloned().unwrap_or_default();
+        attributes.truncate(5);
+        let mut extended = packed.split('~').map(ToString::to_string);
+        attributes.push(extended.next().unwrap_or_default());
+        // keep the indices of the formatted string, with the language before the extended ones
+        attributes.push(language);
+        attributes.extend(extended);
+    }
+    Some(attributes)
+}
+
+/// Maps CPEs to the package URLs of the product they identify.
+///
+/// Deployments knowing products beyond the built-in tables can provide their own implementation,
+/// see [`crate::engine::EngineBuilder::cpe_mapper`].
+pub trait CpeMapper: Send + Sync {
+    /// The package URLs of the product, without a version.
+    fn map(&self, cpe: &Cpe) -> Vec<String>;
+}
+
+/// An additional mapping of a product to packages.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ProductMapping {
+    pub vendor: String,
+    pub product: String,
+    /// Package URLs without a version, e.g. `pkg:maven/io.vertx/vertx-core`
+    pub purls: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CpeConfig {
+    /// Mappings taking precedence over the built-in ones
+    pub mappings: Vec<ProductMapping>,
+}
+
+/// Maps products listed in the configuration or the built-in tables.
+pub struct StaticMappings {
+    mappings: Vec<ProductMapping>,
+}
+
+impl StaticMappings {
+    pub fn new(config: &CpeConfig) -> Self {
+        let mut mappings = config.mappings.clone();
+        mappings.extend(
+            MAPPINGS
+                .iter()
+                .map(|(vendor, product, purls)| ProductMapping {
+                    vendor: vendor.to_string(),
+                    product: product.to_string(),
+                    purls: purls.iter().map(ToString::to_string).collect(),
+                }),
+        );
+        Self { mappings }
+    }
+}
+
+impl CpeMapper for StaticMappings {
+    fn map(&self, cpe: &Cpe) -> Vec<String> {
+        self.mappings
+            .iter()
+            .find(|m| {
+                m.vendor.eq_ignore_ascii_case(&cpe.vendor)
+                    && m.product.eq_ignore_ascii_case(&cpe.product)
+            })
+            .map(|m| m.purls.clone())
+            .unwrap_or_default()
+    }
+}
+
+/// Guesses packages from the software environment of a product, and Red Hat RPMs from the name of
+/// the product.
+///
+/// Packages are only guessed from the software environment for products published by a vendor
+/// of the same name, or by the `<product>_project` the NVD uses for projects without a vendor,
+/// as products of other vendors may share their names with unrelated packages.
+pub struct Heuristics;
+
+impl Heuristics {
+    fn is_own_vendor(cpe: &Cpe) -> bool {
+        let vendor = cpe.vendor.strip_suffix("_project").unwrap_or(&cpe.vendor);
+        vendor == cpe.product
+    }
+}
+
+impl CpeMapper for Heuristics {
+    fn map(&self, cpe: &Cpe) -> Vec<String> {
+        let product = cpe.product.replace('_', "-");
+        if cpe.vendor == "redhat" && cpe.part == "a" {
+            return vec![format!("pkg:rpm/redhat/{}", product)];
+        }
+        if !Self::is_own_vendor(cpe) {
+            return Vec::new();
+        }
+        match cpe.target_sw.as_deref() {
+            Some("node.js") => vec![format!("pkg:npm/{}", product)],
+            Some("python") => vec![format!("pkg:pypi/{}", product)],
+            Some("ruby") | Some("rails") => vec![format!("pkg:gem/{}", product)],
+            Some("rust") => vec![format!("pkg:cargo/{}", product)],
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// The chain of mappers, the first one knowing a product wins.
+pub struct CpeMapping {
+    mappers: Vec<Box<dyn CpeMapper>>,
+}
+
+impl CpeMapping {
+    /// Custom mappers are consulted before the configured and built-in tables and the heuristics.
+    pub fn new(config: &CpeConfig, custom: Vec<Box<dyn CpeMapper>>) -> Self {
+        let mut mappers = custom;
+        mappers.push(Box::new(StaticMappings::new(config)));
+        mappers.push(Box::new(Heuristics));
+        Self { mappers }
+    }
+
+    /// The package URLs of the product, with the version of the CPE if it has one.
+    pub fn map(&self, cpe: &Cpe) -> Vec<String> {
+        let purls = self
+            .mappers
+            .iter()
+            .map(|m| m.map(cpe))
+            .find(|purls| !purls.is_empty())
+            .unwrap_or_default();
+        match &cpe.version {
+            Some(version) => purls
+                .into_iter()
+                .map(|purl| format!("{}@{}", purl, version))
+                .collect(),
+            None => purls,
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct CpeQuery {
+    cpe: Option<String>,
+    #[serde(default)]
+    include_ids: bool,
+}
+
+#[utoipa::path(
+    context_path = "/api/v1",
+    responses(
+        (status = 200, description = "Packages of the product", body = Vec<Package>),
+        (status = NOT_FOUND, description = "No package is known for the CPE", body = Problem, example = json!(crate::problem::example(ApiError::NotMapped { cpe: "cpe:/a:example:unknown:1.0".to_string() }))),
+        (status = BAD_REQUEST, description = "Invalid CPE, or missing query argument", body = Problem, example = json!(crate::problem::example(ApiError::InvalidCpe { cpe: "openssl".to_string() })))
+    ),
+    params(
+        ("cpe" = String, Query, description = "CPE 2.3 formatted string or 2.2 URI to query"),
+        ("include_ids" = Option<bool>, Query, description = "Include provider identifiers of vulnerabilities"),
+    )
+)]
+#[get("/package/by-cpe")]
+pub async fn get_by_cpe(
+    mapping: web::Data<Arc<CpeMapping>>,
+    data: web::Data<TrustedContent>,
+    query: web::Query<CpeQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let id = query.cpe.as_ref().ok_or(ApiError::MissingQueryArgument)?;
+    let cpe = Cpe::parse(id).ok_or_else(|| ApiError::InvalidCpe { cpe: id.clone() })?;
+
+    let mut packages: Vec<Package> = Vec::new();
+    for purl in mapping.map(&cpe) {
+        match data.get_trusted(&data.canonicalize(&purl)).await {
+            Ok(package) => packages.push(with_ids(package, query.include_ids)),
+            Err(TrustError::PackageNotFound { .. }) => {}
+            Err(e) => return Err(ApiError::Upstream(e.into())),
+        }
+    }
+
+    if packages.is_empty() {
+        return Err(ApiError::NotMapped { cpe: id.clone() });
+    }
+    Ok(HttpResponse::Ok().json(packages))
+}
+
+#[derive(Debug, Error, Serialize, Deserialize, ToSchema)]
+#[schema(as = CpeApiError)]
+pub enum ApiError {
+    #[error("No query argument was specified")]
+    MissingQueryArgument,
+    #[error("{cpe} is not a valid CPE")]
+    InvalidCpe { cpe: String },
+    #[error("No package is known for {cpe}")]
+    NotMapped { cpe: String },
+    #[error(transparent)]
+    Upstream(#[schema(inline)] package::ApiError),
+}
+
+impl error::ResponseError for ApiError {
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            ApiError::Upstream(e) => e.error_response(),
+            _ => crate::problem::response(self.status_code(), self, serde_json::json!({})),
+        }
+    }
+
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::MissingQueryArgument => StatusCode::BAD_REQUEST,
+            ApiError::InvalidCpe { cpe: _ } => StatusCode::BAD_REQUEST,
+            ApiError::NotMapped { cpe: _ } => StatusCode::NOT_FOUND,
+            ApiError::Upstream(e) => e.status_code(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(cpe: &str) -> Vec<String> {
+        CpeMapping::new(&CpeConfig::default(), vec![]).map(&Cpe::parse(cpe).unwrap())
+    }
+
+    #[test]
+    fn versions_which_dont_apply_are_told_apart() {
+        let cpe = Cpe::parse("cpe:2.3:a:redhat:openssl:-:*:*:*:*:*:*:*").unwrap();
+        assert!(cpe.version.is_none() && cpe.version_not_applicable);
+        let cpe = Cpe::parse("cpe:2.3:a:redhat:openssl:*:*:*:*:*:*:*:*").unwrap();
+        assert!(cpe.version.is_none() && !cpe.version_not_applicable);
+        let cpe = Cpe::parse("cpe:/a:redhat:openssl").unwrap();
+        assert!(!cpe.version_not_applicable);
+    }
+
+    #[test]
+    fn packages_are_guessed_for_products_of_their_own_vendor() {
+        assert_eq!(
+            map("cpe:2.3:a:eclipse:vert.x:4.3.4:*:*:*:*:*:*:*"),
+            vec![
+                "pkg:maven/io.vertx/vertx-core@4.3.4",
+                "pkg:maven/io.vertx/vertx-web@4.3.4"
+            ]
+        );
+        assert_eq!(
+            map("cpe:2.3:a:minimist_project:minimist:1.2.5:*:*:*:*:node.js:*:*"),
+            vec!["pkg:npm/minimist@1.2.5"]
+        );
+        assert_eq!(
+            map("cpe:2.3:a:redhat:keycloak:21.0:*:*:*:*:*:*:*"),
+            vec!["pkg:rpm/redhat/keycloak@21.0"]
+        );
+        assert!(map("cpe:2.3:a:example:minimist:1.2.5:*:*:*:*:node.js:*:*").is_empty());
+    }
+}
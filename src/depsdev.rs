@@ -0,0 +1,408 @@
+use chrono::{DateTime, Utc};
+use packageurl::PackageUrl;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use crate::config::DepsDevConfig;
+use crate::package::{DependencyEdge, PackageDependencies, PackageRef};
+use crate::webhook::Webhooks;
+
+/// The package types deps.dev knows.
+pub const ECOSYSTEMS: &[&str] = &["maven", "npm", "golang", "cargo", "pypi", "nuget"];
+
+/// How many queued purls are remembered, so they are not queued again. The oldest are forgotten
+/// first, and may be queued once more.
+const QUEUED_CAPACITY: usize = 100_000;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VersionKey {
+    version: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Version {
+    version_key: VersionKey,
+}
+
+#[derive(Deserialize)]
+struct PackageResponse {
+    #[serde(default)]
+    versions: Vec<Version>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VersionResponse {
+    published_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    links: Vec<Link>,
+}
+
+#[derive(Deserialize)]
+struct Link {
+    label: String,
+    url: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NodeKey {
+    system: String,
+    name: String,
+    version: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Node {
+    version_key: NodeKey,
+    relation: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Edge {
+    from_node: usize,
+    to_node: usize,
+    #[serde(default)]
+    requirement: String,
+}
+
+#[derive(Deserialize)]
+struct DependenciesResponse {
+    #[serde(default)]
+    nodes: Vec<Node>,
+    #[serde(default)]
+    edges: Vec<Edge>,
+}
+
+/// Fallback to deps.dev for packages Guac has never seen.
+///
+/// Results are marked as unverified, and the purls are queued for ingestion into Guac, so that
+/// future queries are answered by Guac.
+pub struct DepsDev {
+    url: String,
+    ingest_url: Option<String>,
+    client: reqwest::Client,
+    webhooks: Arc<Webhooks>,
+    max_queued: usize,
+    queued: Mutex<Queued>,
+}
+
+/// The purls queued for ingestion, in the order they were queued.
+#[derive(Default)]
+struct Queued {
+    purls: HashSet<String>,
+    order: VecDeque<String>,
+}
+
+impl Queued {
+    /// Remember a purl, `false` if it was queued already.
+    fn insert(&mut self, purl: String) -> bool {
+        if !self.purls.insert(purl.clone()) {
+            return false;
+        }
+        self.order.push_back(purl);
+        while self.order.len() > QUEUED_CAPACITY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.purls.remove(&oldest);
+            }
+        }
+        true
+    }
+
+    fn remove(&mut self, purl: &str) {
+        if self.purls.remove(purl) {
+            self.order.retain(|p| p != purl);
+        }
+    }
+}
+
+impl DepsDev {
+    pub fn new(config: &DepsDevConfig, webhooks: Arc<Webhooks>) -> Self {
+        Self {
+            url: config.url.trim_end_matches('/').to_string(),
+            ingest_url: config.ingest_url.clone(),
+            client: reqwest::Client::new(),
+            webhooks,
+            max_queued: config.max_queued,
+            queued: Mutex::new(Queued::default()),
+        }
+    }
+
+    /// The versions of a package, `None` if deps.dev doesn't know it either.
+    pub async fn get_versions(
+        &self,
+        purl: &PackageUrl<'_>,
+    ) -> Result<Option<Vec<PackageRef>>, anyhow::Error> {
+        let (system, name) = match coordinates(purl) {
+            Some(coordinates) => coordinates,
+            None => return Ok(None),
+        };
+        let url = format!(
+            "{}/systems/{}/packages/{}",
+            self.url,
+            system,
+            urlencoding::encode(&name)
+        );
+        let response: PackageResponse = match self.get(&url).await? {
+            Some(response) => response,
+            None => return Ok(None),
+        };
+        self.queue_ingestion(purl).await;
+
+        let base = without_version(purl);
+        Ok(Some(
+            response
+                .versions
+                .into_iter()
+                .map(|v| unverified(format!("{}@{}", base, v.version_key.version)))
+                .collect(),
+        ))
+    }
+
+    /// The direct dependencies of a package version, `None` if deps.dev doesn't know it either.
+    pub async fn get_dependencies(
+        &self,
+        purl: &PackageUrl<'_>,
+    ) -> Result<Option<PackageDependencies>, anyhow::Error> {
+        let (system, name) = match coordinates(purl) {
+            Some(coordinates) => coordinates,
+            None => return Ok(None),
+        };
+        let version = match purl.version() {
+            Some(version) => version,
+            None => return Ok(None),
+        };
+        let url = format!(
+            "{}/systems/{}/packages/{}/versions/{}:dependencies",
+            self.url,
+            system,
+            urlencoding::encode(&name),
+            urlencoding::encode(version)
+        );
+        let response: DependenciesResponse = match self.get(&url).await? {
+            Some(response) => response,
+            None => return Ok(None),
+        };
+        self.queue_ingestion(purl).await;
+
+        // the requirements of the package itself, the first node, on its direct dependencies
+        let requirements: HashMap<usize, String> = response
+            .edges
+            .into_iter()
+            .filter(|e| e.from_node == 0 && !e.requirement.is_empty())
+            .map(|e| (e.to_node, e.requirement))
+            .collect();
+        Ok(Some(PackageDependencies(
+            response
+                .nodes
+                .into_iter()
+                .enumerate()
+                .filter(|(_, n)| n.relation == "DIRECT")
+                .filter_map(|(i, n)| {
+                    let key = n.version_key;
+                    let mut dependency =
+                        to_purl(&key.system, &key.name, &key.version).map(unverified)?;
+                    dependency.dependency =
+                        requirements.get(&i).map(|requirement| DependencyEdge {
+                            version_range: Some(requirement.clone()),
+                            ..Default::default()
+                        });
+                    Some(dependency)
+                })
+                .collect(),
+        )))
+    }
+
+    /// When a version of a package was published, `None` if deps.dev doesn't know.
+    pub async fn get_published(
+        &self,
+        purl: &PackageUrl<'_>,
+        version: &str,
+    ) -> Result<Option<DateTime<Utc>>, anyhow::Error> {
+        Ok(self
+            .get_version(purl, version)
+            .await?
+            .and_then(|v| v.published_at))
+    }
+
+    /// The source repository of a package version, `None` if deps.dev doesn't know.
+    pub async fn get_source_repo(
+        &self,
+        purl: &PackageUrl<'_>,
+    ) -> Result<Option<String>, anyhow::Error> {
+        let version = match purl.version() {
+            Some(version) => version,
+            None => return Ok(None),
+        };
+        Ok(self
+            .get_version(purl, version)
+            .await?
+            .and_then(|v| v.links.into_iter().find(|l| l.label == "SOURCE_REPO"))
+            .map(|l| l.url))
+    }
+
+    async fn get_version(
+        &self,
+        purl: &PackageUrl<'_>,
+        version: &str,
+    ) -> Result<Option<VersionResponse>, anyhow::Error> {
+        let (system, name) = match coordinates(purl) {
+            Some(coordinates) => coordinates,
+            None => return Ok(None),
+        };
+        let url = format!(
+            "{}/systems/{}/packages/{}/versions/{}",
+            self.url,
+            system,
+            urlencoding::encode(&name),
+            urlencoding::encode(version)
+        );
+        self.get(&url).await
+    }
+
+    async fn get<T: serde::de::DeserializeOwned>(
+        &self,
+        url: &str,
+    ) -> Result<Option<T>, anyhow::Error> {
+        crate::chaos::inject("depsdev").await?;
+        let response = self
+            .client
+            .get(url)
+            .headers(crate::request_id::headers())
+            .send()
+            .await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        Ok(Some(response.error_for_status()?.json().await?))
+    }
+
+    /// Ask for the package to be ingested into Guac, once per package.
+    ///
+    /// While the ingestion endpoint falls behind, and `max_queued` purls are waiting to be sent,
+    /// further purls are dropped, to be queued again by the next lookup.
+    async fn queue_ingestion(&self, purl: &PackageUrl<'_>) {
+        let ingest_url = match &self.ingest_url {
+            Some(ingest_url) => ingest_url,
+            None => return,
+        };
+        let purl = purl.to_string();
+        if !self.queued.lock().unwrap().insert(purl.clone()) {
+            return;
+        }
+        if self.webhooks.pending(ingest_url).await >= self.max_queued {
+            log::debug!("Not queuing {} for ingestion, too many are pending", purl);
+            self.queued.lock().unwrap().remove(&purl);
+            return;
+        }
+        if let Err(e) = self
+            .webhooks
+            .enqueue(ingest_url, serde_json::json!({ "purl": purl }))
+            .await
+        {
+            log::warn!("Error queuing {} for ingestion: {:?}", purl, e);
+        }
+    }
+}
+
+fn unverified(purl: String) -> PackageRef {
+    PackageRef {
+        href: format!("/api/v1/package?purl={}", &urlencoding::encode(&purl)),
+        purl,
+        trusted: None,
+        unverified: true,
+        sbom: None,
+        vulnerabilities: None,
+        published: None,
+        fetched: Some(Utc::now()),
+        relation: None,
+        dependency: None,
+        degraded: vec![],
+    }
+}
+
+/// The deps.dev system and package name of a purl.
+fn coordinates(purl: &PackageUrl<'_>) -> Option<(&'static str, String)> {
+    let name = purl.name();
+    Some(match (purl.ty(), purl.namespace()) {
+        ("maven", Some(namespace)) => ("maven", format!("{}:{}", namespace, name)),
+        ("npm", Some(namespace)) => ("npm", format!("{}/{}", namespace, name)),
+        ("npm", None) => ("npm", name.to_string()),
+        ("golang", Some(namespace)) => ("go", format!("{}/{}", namespace, name)),
+        ("cargo", None) => ("cargo", name.to_string()),
+        ("pypi", None) => ("pypi", name.to_string()),
+        ("nuget", None) => ("nuget", name.to_string()),
+        _ => return None,
+    })
+}
+
+/// The purl of a package version reported by deps.dev.
+fn to_purl(system: &str, name: &str, version: &str) -> Option<String> {
+    let (ty, name) = match system {
+        "MAVEN" => ("maven", name.replacen(':', "/", 1)),
+        "GO" => ("golang", name.to_string()),
+        // the @ of scopes must be encoded in purls
+        "NPM" => (
+            "npm",
+            match name.strip_prefix('@') {
+                Some(scoped) => format!("%40{}", scoped),
+                None => name.to_string(),
+            },
+        ),
+        "CARGO" => ("cargo", name.to_string()),
+        "PYPI" => ("pypi", name.to_string()),
+        "NUGET" => ("nuget", name.to_string()),
+        _ => return None,
+    };
+    Some(format!("pkg:{}/{}@{}", ty, name, version))
+}
+
+fn without_version(purl: &PackageUrl<'_>) -> String {
+    match purl.namespace() {
+        Some(namespace) => format!(
+            "pkg:{}/{}/{}",
+            purl.ty(),
+            namespace.replace('@', "%40"),
+            purl.name()
+        ),
+        None => format!("pkg:{}/{}", purl.ty(), purl.name()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn npm_scopes_are_encoded() {
+        assert_eq!(
+            to_purl("NPM", "@babel/core", "7.22.5").as_deref(),
+            Some("pkg:npm/%40babel/core@7.22.5")
+        );
+        assert_eq!(
+            to_purl("NPM", "lodash", "4.17.21").as_deref(),
+            Some("pkg:npm/lodash@4.17.21")
+        );
+        let purl = PackageUrl::from_str("pkg:npm/%40babel/core@7.22.5").unwrap();
+        assert_eq!(without_version(&purl), "pkg:npm/%40babel/core");
+        assert_eq!(coordinates(&purl), Some(("npm", "@babel/core".to_string())));
+    }
+
+    #[test]
+    fn the_oldest_queued_purls_are_forgotten() {
+        let mut queued = Queued::default();
+        assert!(queued.insert("pkg:npm/lodash@4.17.21".to_string()));
+        assert!(!queued.insert("pkg:npm/lodash@4.17.21".to_string()));
+        for i in 0..QUEUED_CAPACITY {
+            queued.insert(format!("pkg:npm/package-{}@1.0.0", i));
+        }
+        assert_eq!(queued.order.len(), QUEUED_CAPACITY);
+        assert!(queued.insert("pkg:npm/lodash@4.17.21".to_string()));
+    }
+}
@@ -0,0 +1,493 @@
+//! In-process stand-ins for Guac, Snyk and the security data API, answering from fixtures, so the
+//! API can run without any external services.
+
+use actix_web::{get, post, web, App, HttpRequest, HttpResponse, HttpServer};
+use packageurl::PackageUrl;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::cache::CacheBackend;
+use crate::config::Config;
+
+const BUILT_IN: &str = include_str!("../data/files/devmode-fixtures.json");
+
+/// What the mock backends know, by purl.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Fixtures {
+    pub packages: BTreeMap<String, FixturePackage>,
+    /// The details the security data API reports, by CVE id
+    pub vulnerabilities: BTreeMap<String, FixtureVulnerability>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct FixturePackage {
+    /// The ids of the vulnerabilities Guac reports, CVE or GHSA ids
+    pub vulnerabilities: Vec<String>,
+    /// The license expressions Guac reports
+    pub licenses: Vec<String>,
+    /// The issues Snyk reports
+    pub snyk: Vec<SnykIssue>,
+    /// The purls of the direct dependencies Guac reports, their versions as the version ranges
+    pub dependencies: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct FixtureVulnerability {
+    pub summary: String,
+    /// The threat severity, e.g. `Moderate`
+    pub severity: Option<String>,
+    /// The CVSS v3 base score
+    pub score: Option<String>,
+    /// When the vulnerability was made public, in RFC 3339
+    pub published: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct SnykIssue {
+    pub id: String,
+    #[serde(default)]
+    pub cves: Vec<String>,
+    #[serde(default)]
+    pub severity: Option<String>,
+}
+
+impl Fixtures {
+    /// The fixtures shipped with the server, for the packages of the built-in SBOMs and examples.
+    pub fn builtin() -> Result<Self, anyhow::Error> {
+        Ok(serde_json::from_str(BUILT_IN)?)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        Ok(serde_json::from_slice(&std::fs::read(path)?)?)
+    }
+
+    /// The packages matching a Guac `PkgSpec`, unset fields match all packages.
+    fn matching<'a>(
+        &'a self,
+        spec: &'a Value,
+    ) -> impl Iterator<Item = (PackageUrl<'a>, &'a FixturePackage)> {
+        self.packages.iter().filter_map(move |(purl, package)| {
+            let purl = PackageUrl::from_str(purl).ok()?;
+            matches(spec, &purl).then(|| (purl, package))
+        })
+    }
+
+    /// The direct dependencies of the packages matching a `PkgSpec`.
+    fn dependencies<'a>(&'a self, spec: &'a Value) -> impl Iterator<Item = PackageUrl<'a>> {
+        self.matching(spec)
+            .flat_map(|(_, package)| package.dependencies.iter())
+            .filter_map(|purl| PackageUrl::from_str(purl).ok())
+    }
+}
+
+/// Whether a package matches a Guac `PkgSpec` or `PkgNameSpec`, unset fields match all packages.
+fn matches(spec: &Value, purl: &PackageUrl<'_>) -> bool {
+    let matches = |field: &str, value: Option<&str>| {
+        spec[field]
+            .as_str()
+            .map_or(true, |s| s == value.unwrap_or_default())
+    };
+    matches("type", Some(purl.ty()))
+        && matches("namespace", purl.namespace())
+        && matches("name", Some(purl.name()))
+        && matches("version", purl.version())
+}
+
+/// A package in the trie form of Guac.
+fn guac_package(purl: &PackageUrl<'_>) -> Value {
+    let qualifiers: Vec<Value> = purl
+        .qualifiers()
+        .iter()
+        .map(|(key, value)| json!({ "key": key.to_string(), "value": value.to_string() }))
+        .collect();
+    json!({
+        "type": purl.ty(),
+        "namespaces": [{
+            "namespace": purl.namespace().unwrap_or_default(),
+            "names": [{
+                "name": purl.name(),
+                "versions": [{ "version": purl.version().unwrap_or_default(), "qualifiers": qualifiers }],
+            }],
+        }],
+    })
+}
+
+/// The name of a package in the trie form of Guac, without its versions.
+fn guac_name(purl: &PackageUrl<'_>) -> Value {
+    json!({
+        "type": purl.ty(),
+        "namespaces": [{
+            "namespace": purl.namespace().unwrap_or_default(),
+            "names": [{ "name": purl.name() }],
+        }],
+    })
+}
+
+fn guac_vulnerability(id: &str) -> Value {
+    if id.to_uppercase().starts_with("GHSA-") {
+        json!({ "vulnerability": { "__typename": "OSV", "osvIds": [{ "osvId": id }] } })
+    } else {
+        json!({ "vulnerability": { "__typename": "CVE", "cveIds": [{ "cveId": id }] } })
+    }
+}
+
+fn guac_legal(fixtures: &Fixtures, spec: &Value) -> Value {
+    fixtures
+        .matching(spec)
+        .flat_map(|(_, package)| package.licenses.iter())
+        .map(|license| json!({ "declaredLicense": license, "discoveredLicense": "" }))
+        .collect()
+}
+
+/// The name of the operation of a GraphQL document, e.g. `HasSLSA` for `query HasSLSA(...)`.
+pub(crate) fn operation(body: &Value) -> Option<&str> {
+    if let Some(name) = body["operationName"].as_str() {
+        return Some(name);
+    }
+    let rest = body["query"].as_str()?.trim_start().strip_prefix("query")?;
+    rest.trim_start()
+        .split(|c: char| !c.is_alphanumeric() && c != '_')
+        .next()
+        .filter(|name| !name.is_empty())
+}
+
+/// The answer to one of the raw queries of [`crate::guac::Guac`], `None` for other queries.
+fn guac_data(fixtures: &Fixtures, body: &Value) -> Option<Value> {
+    if body["query"].as_str()?.trim_start().starts_with('{') {
+        return Some(json!({ "__typename": "Query" }));
+    }
+    let variables = &body["variables"];
+    let empty = |name: &str| {
+        let mut data = serde_json::Map::new();
+        data.insert(name.to_string(), json!([]));
+        Some(Value::Object(data))
+    };
+    match operation(body)? {
+        "Summaries" => {
+            let mut data = serde_json::Map::new();
+            for i in 0.. {
+                let name = &variables[format!("name{}", i)];
+                if name.is_null() {
+                    break;
+                }
+                let package = &variables[format!("package{}", i)];
+                let versions: Vec<Value> = fixtures
+                    .matching(name)
+                    .map(|(purl, _)| guac_package(&purl))
+                    .collect();
+                let vulnerabilities: Vec<Value> = fixtures
+                    .matching(package)
+                    .flat_map(|(_, p)| p.vulnerabilities.iter())
+                    .map(|id| guac_vulnerability(id))
+                    .collect();
+                data.insert(format!("packages{}", i), versions.into());
+                data.insert(format!("vulnerabilities{}", i), vulnerabilities.into());
+                data.insert(format!("good{}", i), json!([]));
+                data.insert(format!("bad{}", i), json!([]));
+                data.insert(format!("legal{}", i), guac_legal(fixtures, package));
+                if !variables[format!("slsa{}", i)].is_null() {
+                    data.insert(format!("slsa{}", i), json!([]));
+                }
+            }
+            Some(Value::Object(data))
+        }
+        "CertifyLegal" => {
+            Some(json!({ "CertifyLegal": guac_legal(fixtures, &variables["subject"]["package"]) }))
+        }
+        "Packages" => Some(json!({
+            "packages": fixtures
+                .matching(&variables["name"])
+                .map(|(purl, _)| guac_package(&purl))
+                .collect::<Vec<_>>(),
+        })),
+        "CertifyVuln" => Some(json!({
+            "CertifyVuln": fixtures
+                .matching(&variables["package"])
+                .flat_map(|(_, p)| p.vulnerabilities.iter())
+                .map(|id| guac_vulnerability(id))
+                .collect::<Vec<_>>(),
+        })),
+        "VulnerablePackages" => {
+            let spec = &variables["vulnerability"];
+            let id = spec["cve"]["cveId"]
+                .as_str()
+                .or_else(|| spec["osv"]["osvId"].as_str())
+                .unwrap_or_default();
+            Some(json!({
+                "CertifyVuln": fixtures
+                    .matching(&json!({}))
+                    .filter(|(_, p)| p.vulnerabilities.iter().any(|v| v.eq_ignore_ascii_case(id)))
+                    .map(|(purl, _)| json!({ "package": guac_package(&purl) }))
+                    .collect::<Vec<_>>(),
+            }))
+        }
+        "HasSLSA" => empty("HasSLSA"),
+        "Dependencies" => Some(json!({
+            "IsDependency": fixtures
+                .dependencies(&variables["package"])
+                .map(|dependency| json!({ "dependentPackage": guac_name(&dependency) }))
+                .collect::<Vec<_>>(),
+        })),
+        "IsDependency" => Some(json!({
+            "IsDependency": fixtures
+                .dependencies(&variables["package"])
+                .map(|dependency| {
+                    json!({
+                        "dependentPackage": guac_name(&dependency),
+                        "versionRange": dependency.version().unwrap_or_default(),
+                    })
+                })
+                .collect::<Vec<_>>(),
+        })),
+        "Dependents" => {
+            let name = &variables["name"];
+            Some(json!({
+                "IsDependency": fixtures
+                    .matching(&json!({}))
+                    .filter(|(_, p)| {
+                        p.dependencies
+                            .iter()
+                            .filter_map(|d| PackageUrl::from_str(d).ok())
+                            .any(|d| matches(name, &d))
+                    })
+                    .map(|(purl, _)| json!({ "package": guac_package(&purl) }))
+                    .collect::<Vec<_>>(),
+            }))
+        }
+        "HasSourceAt" => empty("HasSourceAt"),
+        "PkgEqual" => empty("PkgEqual"),
+        _ => None,
+    }
+}
+
+/// The answer of the Guac mock to a GraphQL request.
+pub(crate) fn guac_response(fixtures: &Fixtures, body: &Value) -> Value {
+    match guac_data(fixtures, body) {
+        Some(data) => json!({ "data": data }),
+        None => {
+            let operation = operation(body).unwrap_or("unnamed").to_string();
+            log::debug!("No dev mode answer to Guac query {}", operation);
+            json!({
+                "data": null,
+                "errors": [{ "message": format!("The dev mode does not answer {} queries", operation) }],
+            })
+        }
+    }
+}
+
+/// The answer of the Snyk mock to a request of the issues of a package.
+pub(crate) fn snyk_response(fixtures: &Fixtures, purl: &str) -> Value {
+    let issues: Vec<Value> = fixtures
+        .packages
+        .get(purl)
+        .into_iter()
+        .flat_map(|p| p.snyk.iter())
+        .map(|issue| {
+            json!({
+                "id": issue.id,
+                "attributes": {
+                    "problems": issue.cves.iter().map(|cve| json!({ "id": cve })).collect::<Vec<_>>(),
+                    "effective_severity_level": issue.severity,
+                },
+            })
+        })
+        .collect();
+    json!({ "data": issues, "links": {} })
+}
+
+/// The answer of the security data mock to a request of the details of a CVE, e.g.
+/// `/cve/CVE-2023-24815.json`, `None` if there are none.
+pub(crate) fn security_data_response(fixtures: &Fixtures, path: &str) -> Option<Value> {
+    let id = path
+        .trim_start_matches('/')
+        .strip_prefix("cve/")?
+        .strip_suffix(".json")?;
+    let (id, vulnerability) = fixtures
+        .vulnerabilities
+        .iter()
+        .find(|(cve, _)| cve.eq_ignore_ascii_case(id))?;
+    Some(json!({
+        "name": id,
+        "details": [vulnerability.summary],
+        "threat_severity": vulnerability.severity,
+        "public_date": vulnerability.published,
+        "cvss3": vulnerability.score.as_ref().map(|score| json!({
+            "cvss3_base_score": score,
+            "status": "verified",
+        })),
+    }))
+}
+
+/// The purl of a request of the issues of a package, e.g. `/orgs/{org}/packages/{purl}/issues`.
+pub(crate) fn snyk_purl(path: &str) -> Option<String> {
+    let path = path.split('?').next()?;
+    let purl = path
+        .trim_start_matches('/')
+        .strip_prefix("orgs/")?
+        .split_once('/')?
+        .1
+        .strip_prefix("packages/")?
+        .strip_suffix("/issues")?;
+    Some(
+        urlencoding::decode(purl)
+            .map(|p| p.into_owned())
+            .unwrap_or_else(|_| purl.to_string()),
+    )
+}
+
+#[post("/guac/query")]
+async fn guac_query(fixtures: web::Data<Fixtures>, body: web::Json<Value>) -> HttpResponse {
+    HttpResponse::Ok().json(guac_response(&fixtures, &body))
+}
+
+#[get("/snyk/{path:.*}")]
+async fn snyk_issues(fixtures: web::Data<Fixtures>, req: HttpRequest) -> HttpResponse {
+    let path = req
+        .uri()
+        .path_and_query()
+        .map(|p| p.as_str())
+        .unwrap_or_default()
+        .trim_start_matches("/snyk");
+    match snyk_purl(path) {
+        Some(purl) => HttpResponse::Ok().json(snyk_response(&fixtures, &purl)),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+#[get("/securitydata/{path:.*}")]
+async fn security_data(fixtures: web::Data<Fixtures>, path: web::Path<String>) -> HttpResponse {
+    match security_data_response(&fixtures, &path) {
+        Some(details) => HttpResponse::Ok().json(details),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+/// Serve the mock backends on a local port, returning their base URL.
+pub async fn start(fixtures: Fixtures) -> Result<String, anyhow::Error> {
+    let fixtures = web::Data::new(fixtures);
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(fixtures.clone())
+            .app_data(web::JsonConfig::default().limit(16 * 1024 * 1024))
+            .service(guac_query)
+            .service(snyk_issues)
+            .service(security_data)
+    })
+    .workers(1)
+    .disable_signals()
+    .bind(("127.0.0.1", 0))?;
+    let url = format!("http://{}", server.addrs()[0]);
+    tokio::spawn(server.run());
+    Ok(url)
+}
+
+/// Point the configuration at the mock backends, disabling the other services which would be
+/// called: NVD, GHSA, OSV, sigstore and the delivery of webhooks.
+pub fn apply(config: &mut Config, url: &str) {
+    config.guac.url = format!("{}/guac/query", url);
+    config.guac.authorization = None;
+    config.guac.security_data_url = format!("{}/securitydata", url);
+    config.snyk.url = format!("{}/snyk", url);
+    config.snyk.org = Some("devmode".to_string());
+    config.snyk.token = Some("devmode".to_string());
+    config.cache.backend = CacheBackend::Memory;
+    config.nvd.enabled = false;
+    config.ghsa.enabled = false;
+    config.osv.enabled = false;
+    config.sigstore.enabled = false;
+    config.webhook.enabled = false;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VERTX: &str = "pkg:maven/io.vertx/vertx-web@4.3.7";
+
+    fn query(operation: &str, variables: Value) -> Value {
+        let response = guac_response(
+            &Fixtures::builtin().unwrap(),
+            &json!({ "query": format!("query {}($a: A!) {{ a }}", operation), "variables": variables }),
+        );
+        assert!(response["errors"].is_null(), "{}", response);
+        response["data"].clone()
+    }
+
+    fn spec(purl: &str) -> Value {
+        let purl = PackageUrl::from_str(purl).unwrap();
+        json!({
+            "type": purl.ty(),
+            "namespace": purl.namespace(),
+            "name": purl.name(),
+            "version": purl.version(),
+        })
+    }
+
+    #[test]
+    fn dependencies_are_answered() {
+        let dependencies = query("Dependencies", json!({ "package": spec(VERTX) }));
+        let names: Vec<&Value> = dependencies["IsDependency"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|d| &d["dependentPackage"]["namespaces"][0]["names"][0]["name"])
+            .collect();
+        assert!(names.contains(&&json!("vertx-web-common")), "{:?}", names);
+
+        let ranges = query("IsDependency", json!({ "package": spec(VERTX) }));
+        assert_eq!(ranges["IsDependency"][0]["versionRange"], "4.3.7");
+
+        let unknown = query(
+            "Dependencies",
+            json!({ "package": spec("pkg:maven/org.example/unknown@1.0") }),
+        );
+        assert_eq!(unknown["IsDependency"], json!([]));
+    }
+
+    #[test]
+    fn dependents_are_answered() {
+        let name = json!({ "type": "maven", "namespace": "io.vertx", "name": "vertx-web" });
+        let dependents = query("Dependents", json!({ "name": name }));
+        let names: Vec<&Value> = dependents["IsDependency"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|d| &d["package"]["namespaces"][0]["names"][0]["name"])
+            .collect();
+        assert!(
+            names.contains(&&json!("seedwing-java-example")),
+            "{:?}",
+            names
+        );
+        assert!(names.contains(&&json!("quarkus-vertx-http")), "{:?}", names);
+    }
+
+    #[test]
+    fn all_packages_are_listed() {
+        let packages = query("Packages", json!({ "name": {} }));
+        let fixtures = Fixtures::builtin().unwrap();
+        assert_eq!(
+            packages["packages"].as_array().unwrap().len(),
+            fixtures.packages.len()
+        );
+    }
+
+    #[test]
+    fn vulnerability_details_are_answered() {
+        let fixtures = Fixtures::builtin().unwrap();
+        let details = security_data_response(&fixtures, "/cve/cve-2023-24815.json").unwrap();
+        assert_eq!(details["threat_severity"], "Moderate");
+        assert!(details["details"][0]
+            .as_str()
+            .unwrap()
+            .contains("StaticHandler"));
+        assert!(security_data_response(&fixtures, "/cve/CVE-2000-0001.json").is_none());
+        assert!(security_data_response(&fixtures, "/other").is_none());
+    }
+}
@@ -0,0 +1,430 @@
+use actix_web::web::{self, Data, ServiceConfig};
+use actix_web::Scope;
+use chrono::{DateTime, Utc};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::analyze;
+use crate::audit::Audit;
+use crate::cache::Cache;
+use crate::cdn::Cdn;
+use crate::chaos;
+use crate::config::Config;
+use crate::coordinates;
+use crate::cost;
+use crate::cpe::{self, CpeMapper, CpeMapping};
+use crate::depsdev::DepsDev;
+use crate::events::{self, Events};
+use crate::export;
+use crate::gate::{self, Gate};
+use crate::ghsa::Ghsa;
+use crate::guac::Guac;
+use crate::image::{self, Registry};
+use crate::index::{self, Capabilities};
+use crate::ingestion::Ingestion;
+use crate::nvd::Nvd;
+use crate::osv::Osv;
+use crate::package::{self, Integrations, TrustedContent};
+use crate::policy::{PolicyEngine, PolicyResolver};
+use crate::product::{self, Products};
+use crate::provider::VulnerabilityProvider;
+use crate::publish::Publisher;
+use crate::reanalysis::Reanalysis;
+use crate::reload::Reloader;
+use crate::replication::{Follower, Journal};
+use crate::report;
+use crate::resilience::Resilience;
+use crate::sbom::SbomRegistry;
+use crate::scheduler::Scheduler;
+use crate::scorecard::Scorecard;
+use crate::search::{self, Search};
+use crate::secrets::{self, Secrets};
+use crate::signing::Signer;
+use crate::sigstore::Sigstore;
+use crate::stats::Stats;
+use crate::storage::{self, Storage};
+use crate::subgraph::Subgraphs;
+use crate::trusted::{NamingConvention, TrustedVersionResolver};
+use crate::versioning::{self, ApiVersion};
+use crate::vulnerability;
+use crate::watch::{self, Watches};
+use crate::webhook::Webhooks;
+
+/// Builds an [`Engine`] from a configuration.
+pub struct EngineBuilder {
+    config: Config,
+    background: bool,
+    cpe_mappers: Vec<Box<dyn CpeMapper>>,
+    providers: Vec<Arc<dyn VulnerabilityProvider>>,
+}
+
+impl EngineBuilder {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            background: true,
+            cpe_mappers: Vec::new(),
+            providers: Vec::new(),
+        }
+    }
+
+    /// Whether to run the background loops of re-analysis, webhooks, watches, ingestion and SBOM
+    /// reloading, enabled by default.
+    ///
+    /// Disable it when another instance sharing the same stores already runs them, to not send
+    /// notifications twice.
+    pub fn background(mut self, background: bool) -> Self {
+        self.background = background;
+        self
+    }
+
+    /// Add a mapping of CPEs to packages, consulted before the configured and built-in ones.
+    pub fn cpe_mapper(mut self, mapper: impl CpeMapper + 'static) -> Self {
+        self.cpe_mappers.push(Box::new(mapper));
+        self
+    }
+
+    /// Add a source of vulnerabilities, consulted after the configured ones.
+    pub fn vulnerability_provider(
+        mut self,
+        provider: impl VulnerabilityProvider + 'static,
+    ) -> Self {
+        self.providers.push(Arc::new(provider));
+        self
+    }
+
+    /// Set up the providers and stores, the configuration is expected to be validated already.
+    pub async fn build(self) -> anyhow::Result<Engine> {
+        let config = self.config;
+        cost::init(config.cost.clone());
+        chaos::init(config.chaos.clone());
+
+        let publisher = Arc::new(Publisher::new(config.publish.as_ref()).await?);
+        let audit = Arc::new(Audit::new(&config.audit).await?);
+        let storage = storage::open(&config.storage).await?;
+        let secrets = Arc::new(Secrets::new(&config.secrets, storage.clone())?);
+        let sboms = Arc::new(SbomRegistry::new(
+            config.sbom.indexing.clone(),
+            config.sbom.filename.clone(),
+            publisher.clone(),
+            storage.clone(),
+        ));
+        for source in config.sbom.sources.clone() {
+            let sboms = sboms.clone();
+            tokio::spawn(async move {
+                let id = source.id.clone();
+                if let Err(e) = sboms.add_source(source).await {
+                    log::warn!("Error indexing SBOM source {}: {:?}", id, e);
+                }
+            });
+        }
+        let cache = Arc::new(Cache::new(&config.cache).await?);
+        let policy = config
+            .policy
+            .as_ref()
+            .map(PolicyEngine::new)
+            .transpose()?
+            .map(Arc::new);
+        let naming = NamingConvention::new(config.api.trusted_versions.clone());
+        let resolver: Arc<dyn TrustedVersionResolver> = match &policy {
+            Some(engine) => Arc::new(PolicyResolver::new(engine.clone(), naming)),
+            None => Arc::new(naming),
+        };
+        let subgraphs = Arc::new(Subgraphs::new(
+            cache.clone(),
+            sboms.clone(),
+            Duration::from_secs(config.cache.subgraph_invalidation_secs),
+        ));
+        let guac = Arc::new(Guac::new(
+            &config.guac,
+            sboms.clone(),
+            Resilience::new("guac", config.guac.resilience.clone()),
+            resolver,
+            subgraphs.clone(),
+        )?);
+        // every instance tracks the availability of Guac on its own
+        tokio::spawn(
+            guac.clone()
+                .monitor(Duration::from_secs(config.guac.probe_interval_secs)),
+        );
+        let events = Arc::new(Events::new(publisher));
+        let signer = Signer::new(config.signing.keys.clone());
+        if !signer.is_enabled() {
+            log::warn!("No signing keys configured, webhooks will be sent unsigned");
+        }
+        let webhooks = Arc::new(
+            Webhooks::new(
+                &config.webhook,
+                signer,
+                storage.clone(),
+                config.watch.allowed_callback_hosts.clone(),
+                config.deps_dev.ingest_url.iter().cloned().collect(),
+            )
+            .await?,
+        );
+
+        let fallback = config
+            .deps_dev
+            .enabled
+            .then(|| Arc::new(DepsDev::new(&config.deps_dev, webhooks.clone())));
+        let scorecard = config.scorecard.enabled.then(|| {
+            Arc::new(Scorecard::new(
+                &config.scorecard,
+                guac.clone(),
+                fallback.clone(),
+            ))
+        });
+        let cpe = Arc::new(CpeMapping::new(&config.cpe, self.cpe_mappers));
+        let nvd = if config.nvd.enabled {
+            let nvd = Arc::new(Nvd::new(&config.nvd, cpe.clone(), storage.clone()).await?);
+            // every instance syncs its own store
+            tokio::spawn(
+                nvd.clone()
+                    .run(Duration::from_secs(config.nvd.interval_secs)),
+            );
+            Some(nvd)
+        } else {
+            None
+        };
+        let journal = config
+            .replication
+            .primary
+            .then(|| Arc::new(Journal::new(Duration::from_secs(config.cache.ttl))));
+        let trusted = TrustedContent::new(
+            guac.clone(),
+            sboms.clone(),
+            config.snyk.clone(),
+            cache.clone(),
+            &config.api,
+            Integrations {
+                deps_dev: fallback.clone(),
+                sigstore: if config.sigstore.enabled {
+                    Some(Arc::new(Sigstore::new(&config.sigstore)?))
+                } else {
+                    None
+                },
+                scorecard,
+                nvd,
+                ghsa: config
+                    .ghsa
+                    .enabled
+                    .then(|| Arc::new(Ghsa::new(&config.ghsa))),
+                osv: config.osv.enabled.then(|| Arc::new(Osv::new(&config.osv))),
+            },
+        );
+        let trusted = self
+            .providers
+            .into_iter()
+            .fold(trusted, |trusted, provider| trusted.with_provider(provider));
+        let trusted = Arc::new(match &journal {
+            Some(journal) => trusted.with_journal(journal.clone()),
+            None => trusted,
+        });
+        // a token put in place through the admin API replaces the configured one
+        if let Some(token) = secrets.get(secrets::SNYK_TOKEN).await? {
+            trusted.snyk().set_token(token);
+        }
+        if let Some(follow) = &config.replication.follow {
+            // every follower keeps its own cache and registry in sync
+            let follower = Arc::new(Follower::new(follow, trusted.clone(), sboms.clone()));
+            tokio::spawn(follower.run(Duration::from_secs(follow.interval_secs)));
+        }
+
+        let stats = Arc::new(Stats::new(guac.clone(), sboms.clone(), fallback));
+
+        // every instance serves searches from its own index
+        let search = Arc::new(Search::new(guac.clone(), sboms.clone(), trusted.clone()));
+        let mut scheduler = Scheduler::new();
+        if config.search.refresh_secs > 0 {
+            scheduler.every(
+                Duration::from_secs(config.search.refresh_secs),
+                search.clone(),
+            );
+        }
+
+        let cdn = Arc::new(Cdn::new(config.cdn.clone(), config.api.purl_parsing));
+        let ingestion = Arc::new(Ingestion::new(
+            trusted.clone(),
+            subgraphs.clone(),
+            cdn.clone(),
+        ));
+
+        let watches = Arc::new(
+            Watches::new(
+                &config.watch,
+                webhooks.clone(),
+                trusted.clone(),
+                sboms.clone(),
+                storage.clone(),
+            )
+            .await?,
+        );
+
+        // every instance serves the stored history, only background instances add to it
+        let reanalysis = Arc::new(Reanalysis::new(
+            &config.reanalysis,
+            trusted.clone(),
+            sboms.clone(),
+            events.clone(),
+            storage.clone(),
+        ));
+
+        if self.background {
+            scheduler
+                .every(
+                    Duration::from_secs(config.reanalysis.interval_secs),
+                    reanalysis.clone(),
+                )
+                .every(
+                    Duration::from_secs(config.watch.interval_secs),
+                    watches.clone(),
+                );
+            if config.webhook.enabled {
+                tokio::spawn(
+                    webhooks
+                        .clone()
+                        .run(Duration::from_secs(config.webhook.interval_secs)),
+                );
+            }
+            if let Some(config) = config.ingestion.clone() {
+                tokio::spawn(ingestion.clone().run(config));
+            }
+            if config.sbom.reload.enabled {
+                let reloader = Arc::new(Reloader::new(&config.sbom.reload, sboms.clone()));
+                tokio::spawn(reloader.run());
+            }
+        }
+        scheduler.start();
+
+        Ok(Engine {
+            sboms,
+            cache,
+            subgraphs,
+            guac,
+            events,
+            webhooks,
+            trusted,
+            reanalysis,
+            stats,
+            search,
+            watches,
+            ingestion,
+            cdn,
+            registry: Arc::new(Registry::new(config.image.clone())?),
+            cpe,
+            journal,
+            storage,
+            secrets,
+            audit,
+            products: Arc::new(Products::new(config.products.clone())),
+            gate: Arc::new(Gate::new(&config.gate, policy)),
+            sunset: config.api.sunset,
+            capabilities: Capabilities::new(&config),
+        })
+    }
+}
+
+/// The aggregation of Guac, Snyk and the other providers, without any HTTP listener.
+///
+/// Use [`Engine::trusted`] to query it directly, or mount [`Engine::scope`] into an application to
+/// serve the REST API.
+#[derive(Clone)]
+pub struct Engine {
+    pub(crate) sboms: Arc<SbomRegistry>,
+    pub(crate) cache: Arc<Cache>,
+    pub(crate) subgraphs: Arc<Subgraphs>,
+    pub(crate) guac: Arc<Guac>,
+    pub(crate) events: Arc<Events>,
+    pub(crate) webhooks: Arc<Webhooks>,
+    pub(crate) trusted: Arc<TrustedContent>,
+    pub(crate) reanalysis: Arc<Reanalysis>,
+    pub(crate) stats: Arc<Stats>,
+    pub(crate) search: Arc<Search>,
+    pub(crate) watches: Arc<Watches>,
+    pub(crate) ingestion: Arc<Ingestion>,
+    pub(crate) cdn: Arc<Cdn>,
+    pub(crate) registry: Arc<Registry>,
+    pub(crate) cpe: Arc<CpeMapping>,
+    pub(crate) journal: Option<Arc<Journal>>,
+    pub(crate) storage: Arc<dyn Storage>,
+    pub(crate) secrets: Arc<Secrets>,
+    pub(crate) audit: Arc<Audit>,
+    pub(crate) products: Arc<Products>,
+    pub(crate) gate: Arc<Gate>,
+    /// Announced for the unversioned paths
+    pub(crate) sunset: Option<DateTime<Utc>>,
+    pub(crate) capabilities: Capabilities,
+}
+
+impl Engine {
+    pub fn builder(config: Config) -> EngineBuilder {
+        EngineBuilder::new(config)
+    }
+
+    pub fn trusted(&self) -> Arc<TrustedContent> {
+        self.trusted.clone()
+    }
+
+    pub fn sboms(&self) -> Arc<SbomRegistry> {
+        self.sboms.clone()
+    }
+
+    /// The configured storage, shared by the features persisting data.
+    pub fn storage(&self) -> Arc<dyn Storage> {
+        self.storage.clone()
+    }
+
+    /// The REST API endpoints, below `path`.
+    ///
+    /// Endpoints are served below `/api/v1`, and below `/api` as deprecated aliases. Middleware
+    /// (CORS, authentication, access logs, CDN tags) and the health probes are left to the
+    /// application mounting the scope. An empty `path` matches any request, so register other
+    /// services of the application before it.
+    pub fn scope(&self, path: &str) -> Scope {
+        let sunset = self.sunset;
+        web::scope(path)
+            .app_data(Data::new(self.sboms.clone()))
+            .app_data(Data::from(self.trusted.clone()))
+            .app_data(Data::new(self.guac.clone()))
+            .app_data(Data::new(self.cache.clone()))
+            .app_data(Data::new(self.events.clone()))
+            .app_data(Data::new(self.reanalysis.clone()))
+            .app_data(Data::new(self.watches.clone()))
+            .app_data(Data::new(self.search.clone()))
+            .app_data(Data::new(self.registry.clone()))
+            .app_data(Data::new(self.cpe.clone()))
+            .app_data(Data::new(self.products.clone()))
+            .app_data(Data::new(self.gate.clone()))
+            .app_data(Data::new(self.capabilities.clone()))
+            .app_data(self.trusted.batch().json_config())
+            .service(web::scope(ApiVersion::V1.prefix()).configure(api(ApiVersion::V1)))
+            // registered after the versioned prefixes, which it would match as well
+            .service(
+                web::scope(versioning::LEGACY_PREFIX)
+                    .wrap_fn(move |req, srv| versioning::deprecated(req, srv, sunset))
+                    .configure(api(ApiVersion::LEGACY)),
+            )
+            .configure(index::configure())
+    }
+}
+
+/// The endpoints of an API version, relative to its prefix.
+fn api(version: ApiVersion) -> impl FnOnce(&mut ServiceConfig) {
+    move |config: &mut ServiceConfig| {
+        config
+            .configure(package::configure(version))
+            .configure(cpe::configure())
+            .configure(coordinates::configure())
+            .configure(analyze::configure())
+            .configure(image::configure())
+            .configure(vulnerability::configure())
+            .configure(events::configure())
+            .configure(watch::configure())
+            .configure(search::configure())
+            .configure(report::configure())
+            .configure(export::configure())
+            .configure(product::configure())
+            .configure(gate::configure())
+            .configure(index::configure_api());
+    }
+}
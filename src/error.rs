@@ -0,0 +1,32 @@
+use thiserror::Error;
+
+/// Errors of looking up trusted content, independent of how they are served.
+///
+/// The HTTP and gRPC handlers map them to status codes, see the `From` implementations of their
+/// error types.
+#[derive(Debug, Error)]
+pub enum TrustError {
+    #[error("{purl} is not a valid package URL")]
+    InvalidPackageUrl {
+        purl: String,
+        #[source]
+        source: packageurl::Error,
+    },
+    #[error("Package {purl} was not found")]
+    PackageNotFound { purl: String },
+    #[error("No provenance is known for {purl}")]
+    ProvenanceNotFound { purl: String },
+    /// A provider failed to answer, `source` is the original error
+    #[error("Error from {provider}: {source}")]
+    Provider {
+        provider: &'static str,
+        #[source]
+        source: anyhow::Error,
+    },
+}
+
+impl TrustError {
+    pub fn provider(provider: &'static str, source: anyhow::Error) -> Self {
+        Self::Provider { provider, source }
+    }
+}
@@ -0,0 +1,37 @@
+use actix_web::http::header::{ETag, EntityTag, IfNoneMatch};
+use actix_web::{HttpMessage, HttpRequest, HttpResponse, HttpResponseBuilder};
+use sha2::{Digest, Sha256};
+
+/// A strong entity tag of a response, from the data it was computed from.
+pub fn of(parts: &[&[u8]]) -> EntityTag {
+    let mut digest = Sha256::new();
+    for part in parts {
+        // length prefixed, so different splits of the same bytes differ
+        digest.update((part.len() as u64).to_be_bytes());
+        digest.update(part);
+    }
+    EntityTag::new_strong(hex::encode(&digest.finalize()[..16]))
+}
+
+/// Whether the client already has the representation with the tag, per `If-None-Match`.
+fn matches(req: &HttpRequest, etag: &EntityTag) -> bool {
+    match req.get_header::<IfNoneMatch>() {
+        Some(IfNoneMatch::Any) => true,
+        Some(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(etag)),
+        None => false,
+    }
+}
+
+/// `304 Not Modified` if the client has the representation with the tag, `None` otherwise.
+pub fn not_modified(req: &HttpRequest, etag: &EntityTag) -> Option<HttpResponse> {
+    matches(req, etag).then(|| {
+        HttpResponse::NotModified()
+            .insert_header(ETag(etag.clone()))
+            .finish()
+    })
+}
+
+/// Tag a response.
+pub fn tag(response: &mut HttpResponseBuilder, etag: &EntityTag) {
+    response.insert_header(ETag(etag.clone()));
+}
@@ -0,0 +1,108 @@
+use actix_web::{get, web, web::Bytes, web::ServiceConfig, HttpResponse};
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use utoipa::{OpenApi, ToSchema};
+
+use crate::package::VulnerabilityRef;
+use crate::publish::{PipelineEvent, Publisher};
+
+/// How many events a slow subscriber may fall behind before missing events
+const CAPACITY: usize = 1024;
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+pub(crate) fn configure() -> impl FnOnce(&mut ServiceConfig) {
+    |config: &mut ServiceConfig| {
+        config.service(events);
+    }
+}
+
+/// The documentation of the event stream.
+#[derive(OpenApi)]
+#[openapi(paths(events), components(schemas(VulnerabilityEvent)))]
+pub(crate) struct ApiDoc;
+
+/// A vulnerability newly found to affect a package known to the registry.
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct VulnerabilityEvent {
+    pub purl: String,
+    pub vulnerability: VulnerabilityRef,
+}
+
+/// Publishes new vulnerability findings to the subscribers of the event stream.
+pub struct Events {
+    sender: broadcast::Sender<VulnerabilityEvent>,
+    publisher: Arc<Publisher>,
+}
+
+impl Events {
+    pub fn new(publisher: Arc<Publisher>) -> Self {
+        Self {
+            sender: broadcast::channel(CAPACITY).0,
+            publisher,
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<VulnerabilityEvent> {
+        self.sender.subscribe()
+    }
+
+    fn publish(&self, event: VulnerabilityEvent) {
+        // fails only if there are no subscribers
+        let _ = self.sender.send(event);
+    }
+
+    /// Publish vulnerabilities newly found to affect a package, to the event stream and the
+    /// pipeline.
+    ///
+    /// VEX statements are not taken into account, the service does not read them.
+    pub async fn found(&self, purl: String, found: Vec<VulnerabilityRef>) {
+        for vuln in found.iter() {
+            self.publish(VulnerabilityEvent {
+                purl: purl.clone(),
+                vulnerability: vuln.clone(),
+            });
+        }
+        self.publisher
+            .publish(PipelineEvent::VulnerabilitiesFound {
+                purl,
+                vulnerabilities: found,
+            })
+            .await;
+    }
+}
+
+/// Stream new vulnerability findings as server-sent events.
+#[utoipa::path(
+    context_path = "/api/v1",
+    responses(
+        (status = 200, description = "`vulnerability` events, each carrying a `VulnerabilityEvent`", content_type = "text/event-stream", body = String, example = json!(
+            "event: vulnerability\ndata: {\"purl\":\"pkg:rpm/redhat/openssl@1.1.1k-7.el8_6\",\"vulnerability\":{\"cve\":\"cve-2023-0286\",\"href\":\"https://access.redhat.com/security/cve/cve-2023-0286\",\"severity\":\"high\"}}\n\n"
+        )),
+    )
+)]
+#[get("/events")]
+pub async fn events(events: web::Data<Arc<Events>>) -> HttpResponse {
+    let receiver = events.subscribe();
+    let stream = futures::stream::unfold(receiver, |mut receiver| async move {
+        let data = match tokio::time::timeout(KEEP_ALIVE, receiver.recv()).await {
+            Ok(Ok(event)) => format!(
+                "event: vulnerability\ndata: {}\n\n",
+                serde_json::to_string(&event).ok()?
+            ),
+            Ok(Err(broadcast::error::RecvError::Lagged(missed))) => {
+                log::info!("Event subscriber missed {} events", missed);
+                format!(": missed {} events\n\n", missed)
+            }
+            Ok(Err(broadcast::error::RecvError::Closed)) => return None,
+            Err(_) => ": keep-alive\n\n".to_string(),
+        };
+        Some((Ok::<_, actix_web::Error>(Bytes::from(data)), receiver))
+    });
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(stream)
+}
@@ -0,0 +1,275 @@
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::web::Bytes;
+use actix_web::{error, get, http::StatusCode, web, web::ServiceConfig, HttpResponse};
+use chrono::Utc;
+use futures::Stream;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::io::Write;
+use std::sync::Arc;
+use thiserror::Error;
+use tokio::sync::mpsc;
+use utoipa::{OpenApi, ToSchema};
+
+use crate::sbom::SbomRegistry;
+
+pub(crate) fn configure() -> impl FnOnce(&mut ServiceConfig) {
+    |config: &mut ServiceConfig| {
+        config.service(export_sboms);
+    }
+}
+
+/// The documentation of the SBOM export endpoint.
+#[derive(OpenApi)]
+#[openapi(paths(export_sboms), components(schemas(ApiError)))]
+pub(crate) struct ApiDoc;
+
+#[derive(Deserialize)]
+pub struct ExportQuery {
+    /// Only the SBOMs of this tenant
+    tenant: Option<String>,
+    /// Only the SBOM of this product and the SBOMs of its components
+    product: Option<String>,
+    /// Only the SBOMs whose purl starts with this prefix
+    prefix: Option<String>,
+}
+
+/// Download the SBOMs matching a filter as a gzipped tar archive, one JSON file per SBOM.
+///
+/// All filters are optional and combined, without any filter all SBOMs visible to the caller
+/// are exported.
+#[utoipa::path(
+    context_path = "/api/v1",
+    responses(
+        (status = 200, description = "The archive of the SBOMs", content_type = "application/gzip", body = Vec<u8>),
+        (status = NOT_FOUND, description = "No SBOM is known for the product", body = Problem, example = json!(crate::problem::example(ApiError::ProductNotFound { purl: "pkg:oci/ubi9@sha256:cb303404e576ff5528d4f08b12ad85fab8f61fa9e5dba67b37b119db24865df3".to_string() }))),
+    ),
+    params(
+        ("tenant" = Option<String>, Query, description = "Only export the SBOMs of this tenant"),
+        ("product" = Option<String>, Query, description = "Only export the SBOM of this product and those of its components"),
+        ("prefix" = Option<String>, Query, description = "Only export the SBOMs whose purl starts with this prefix, e.g. `pkg:maven/io.vertx/`"),
+    )
+)]
+#[get("/sbom/export")]
+pub async fn export_sboms(
+    sboms: web::Data<Arc<SbomRegistry>>,
+    query: web::Query<ExportQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let product = match &query.product {
+        Some(product) => {
+            let document = sboms
+                .lookup(product)
+                .ok_or_else(|| ApiError::ProductNotFound {
+                    purl: product.clone(),
+                })?;
+            let mut purls: HashSet<String> = crate::sbom::component_purls(&document)
+                .iter()
+                .map(|p| crate::purl::significant(p).unwrap_or_else(|| p.clone()))
+                .collect();
+            purls.insert(crate::purl::significant(product).unwrap_or_else(|| product.clone()));
+            Some(purls)
+        }
+        None => None,
+    };
+    // only the purls are listed up front, each SBOM is looked up while the archive is written
+    let purls: Vec<String> = sboms
+        .listed()
+        .into_iter()
+        .filter(|(_, tenant)| {
+            query
+                .tenant
+                .as_ref()
+                .map_or(true, |t| tenant.as_ref() == Some(t))
+        })
+        .map(|(purl, _)| purl)
+        .filter(|purl| query.prefix.as_ref().map_or(true, |p| purl.starts_with(p)))
+        .filter(|purl| {
+            product.as_ref().map_or(true, |purls| {
+                purls.contains(&crate::purl::significant(purl).unwrap_or_else(|| purl.clone()))
+            })
+        })
+        .collect();
+
+    let registry = sboms.get_ref().clone();
+    // SBOMs removed in the meantime are left out
+    let entries = purls.into_iter().filter_map(move |purl| {
+        let document = registry.lookup(&purl)?;
+        Some(Ok::<_, std::io::Error>((
+            registry.file_name(&purl, &document),
+            document,
+        )))
+    });
+    let stream = stream(crate::tenant::current(), entries);
+
+    let filename = format!("sboms-{}.tar.gz", Utc::now().format("%Y%m%dT%H%M%SZ"));
+    Ok(HttpResponse::Ok()
+        .content_type("application/gzip")
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(filename)],
+        })
+        .streaming(stream))
+}
+
+/// The archive of SBOMs, written on a blocking thread while it is sent, on behalf of the caller
+/// of the tenant, if any.
+///
+/// If writing fails, the stream fails after the part written so far, so the response is aborted
+/// instead of ending with a truncated archive.
+fn stream<I>(
+    tenant: Option<Option<String>>,
+    entries: I,
+) -> impl Stream<Item = Result<Bytes, actix_web::Error>>
+where
+    I: Iterator<Item = std::io::Result<(String, serde_json::Value)>> + Send + 'static,
+{
+    let (sender, mut receiver) = mpsc::channel(16);
+    tokio::task::spawn_blocking(move || {
+        let writer = ChannelWriter(sender.clone());
+        let result = match tenant {
+            Some(tenant) => crate::tenant::sync_scoped(tenant, || write_archive(writer, entries)),
+            None => write_archive(writer, entries),
+        };
+        if let Err(e) = result {
+            log::warn!("Error writing the SBOM archive: {:?}", e);
+            // the client is gone if this fails as well
+            let _ = sender.blocking_send(Err(e));
+        }
+    });
+    futures::stream::poll_fn(move |cx| {
+        receiver
+            .poll_recv(cx)
+            .map(|chunk| chunk.map(|chunk| chunk.map_err(error::ErrorInternalServerError)))
+    })
+}
+
+/// Write a gzipped tar archive of SBOMs by their file names, returning the writer.
+fn write_archive<W: Write>(
+    writer: W,
+    entries: impl Iterator<Item = std::io::Result<(String, serde_json::Value)>>,
+) -> std::io::Result<W> {
+    let encoder = flate2::write::GzEncoder::new(writer, flate2::Compression::default());
+    let mut archive = tar::Builder::new(encoder);
+    let mut names = HashSet::new();
+    let modified = Utc::now().timestamp() as u64;
+    for entry in entries {
+        let (name, document) = entry?;
+        // serialized as served, so the files match the digests of the SBOMs
+        let data = serde_json::to_vec(&document)?;
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(modified);
+        archive.append_data(&mut header, unique(&mut names, name), &data[..])?;
+    }
+    let mut writer = archive.into_inner()?.finish()?;
+    writer.flush()?;
+    Ok(writer)
+}
+
+/// Number names which would otherwise overwrite each other in the archive.
+fn unique(names: &mut HashSet<String>, name: String) -> String {
+    let mut candidate = name.clone();
+    let mut n = 1;
+    while !names.insert(candidate.clone()) {
+        n += 1;
+        candidate = match name.strip_suffix(".json") {
+            Some(stem) => format!("{}-{}.json", stem, n),
+            None => format!("{}-{}", name, n),
+        };
+    }
+    candidate
+}
+
+/// Sends the written archive to the response in chunks, failing once the client went away.
+struct ChannelWriter(mpsc::Sender<std::io::Result<Bytes>>);
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0
+            .blocking_send(Ok(Bytes::copy_from_slice(buf)))
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::BrokenPipe, "client went away"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Error, Serialize, Deserialize, ToSchema)]
+#[schema(as = ExportApiError)]
+pub enum ApiError {
+    #[error("No SBOM is known for the product {purl}")]
+    ProductNotFound { purl: String },
+}
+
+impl error::ResponseError for ApiError {
+    fn error_response(&self) -> HttpResponse {
+        crate::problem::response(self.status_code(), self, serde_json::json!({}))
+    }
+
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::ProductNotFound { purl: _ } => StatusCode::NOT_FOUND,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use std::io::Read;
+
+    fn entries(
+        names: &[&str],
+    ) -> impl Iterator<Item = std::io::Result<(String, serde_json::Value)>> {
+        names
+            .iter()
+            .map(|name| Ok((name.to_string(), serde_json::json!({ "name": name }))))
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn archives_hold_each_sbom_under_a_unique_name() {
+        let data = write_archive(
+            Vec::new(),
+            entries(&["ubi9.json", "ubi9.json", "vertx.json"]),
+        )
+        .unwrap();
+        let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(&data[..]));
+        let mut files = Vec::new();
+        for file in archive.entries().unwrap() {
+            let mut file = file.unwrap();
+            let name = file.path().unwrap().display().to_string();
+            let mut content = String::new();
+            file.read_to_string(&mut content).unwrap();
+            let document: serde_json::Value = serde_json::from_str(&content).unwrap();
+            files.push((name, document["name"].as_str().unwrap().to_string()));
+        }
+        assert_eq!(
+            files,
+            [
+                ("ubi9.json".to_string(), "ubi9.json".to_string()),
+                ("ubi9-2.json".to_string(), "ubi9.json".to_string()),
+                ("vertx.json".to_string(), "vertx.json".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn failures_fail_the_stream() {
+        let chunks: Vec<_> = stream(None, entries(&["ubi9.json"])).collect().await;
+        assert!(!chunks.is_empty());
+        assert!(chunks.iter().all(Result::is_ok));
+
+        let failing = entries(&["ubi9.json"]).chain(std::iter::once(Err(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            "storage failure",
+        ))));
+        let chunks: Vec<_> = stream(None, failing).collect().await;
+        assert!(chunks.last().unwrap().is_err());
+    }
+}
@@ -0,0 +1,141 @@
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::{error, http::header, http::StatusCode, HttpResponse};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use subtle::ConstantTimeEq;
+use thiserror::Error;
+
+use crate::cache;
+use crate::cost::API_KEY_HEADER;
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Who may bypass the caches, and how often.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FreshConfig {
+    /// API keys (`X-Api-Key`) allowed to bypass the caches
+    pub api_keys: Vec<String>,
+    /// Maximum number of requests bypassing the caches, per API key and minute
+    pub per_minute: u32,
+}
+
+impl Default for FreshConfig {
+    fn default() -> Self {
+        Self {
+            api_keys: Vec::new(),
+            per_minute: 10,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct FreshQuery {
+    #[serde(default)]
+    fresh: bool,
+}
+
+/// Grants requests for fresh results, bypassing the caches, to authenticated callers.
+pub struct FreshPolicy {
+    config: FreshConfig,
+    windows: Mutex<HashMap<String, (Instant, u32)>>,
+}
+
+impl FreshPolicy {
+    pub fn new(config: FreshConfig) -> Self {
+        Self {
+            config,
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Check if a request bypasses the caches.
+    ///
+    /// Explicit requests (`?fresh=true`) of callers which aren't allowed fail, while
+    /// `Cache-Control: no-cache` headers are ignored for them, as browsers send these on reload.
+    fn check(&self, req: &ServiceRequest) -> Result<bool, ApiError> {
+        let explicit = actix_web::web::Query::<FreshQuery>::from_query(req.query_string())
+            .map_or(false, |q| q.fresh);
+        let no_cache = req
+            .headers()
+            .get(header::CACHE_CONTROL)
+            .and_then(|v| v.to_str().ok())
+            .map_or(false, |v| v.split(',').any(|d| d.trim() == "no-cache"));
+        if !explicit && !no_cache {
+            return Ok(false);
+        }
+
+        let key = req
+            .headers()
+            .get(API_KEY_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .filter(|key| {
+                self.config
+                    .api_keys
+                    .iter()
+                    .any(|k| bool::from(k.as_bytes().ct_eq(key.as_bytes())))
+            });
+        let key = match key {
+            Some(key) => key,
+            None if explicit => return Err(ApiError::NotAllowed),
+            None => return Ok(false),
+        };
+
+        let mut windows = self.windows.lock().unwrap();
+        let (start, count) = windows
+            .entry(key.to_string())
+            .or_insert_with(|| (Instant::now(), 0));
+        if start.elapsed() >= WINDOW {
+            *start = Instant::now();
+            *count = 0;
+        }
+        if *count >= self.config.per_minute {
+            return Err(ApiError::TooManyRequests);
+        }
+        *count += 1;
+        Ok(true)
+    }
+}
+
+/// Process requests for fresh results without reading from the caches.
+pub fn middleware<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+    policy: &FreshPolicy,
+) -> impl Future<Output = Result<ServiceResponse<B>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+{
+    let call = policy.check(&req).map(|bypass| (bypass, srv.call(req)));
+    async move {
+        match call {
+            Ok((true, fut)) => cache::bypassing(fut).await,
+            Ok((false, fut)) => fut.await,
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[derive(Debug, Error, Serialize, Deserialize)]
+pub enum ApiError {
+    #[error("Fresh results require an authorized API key")]
+    NotAllowed,
+    #[error("Too many requests for fresh results, try again later")]
+    TooManyRequests,
+}
+
+impl error::ResponseError for ApiError {
+    fn error_response(&self) -> HttpResponse {
+        crate::problem::response(self.status_code(), self, serde_json::json!({}))
+    }
+
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::NotAllowed => StatusCode::FORBIDDEN,
+            ApiError::TooManyRequests => StatusCode::TOO_MANY_REQUESTS,
+        }
+    }
+}
@@ -0,0 +1,383 @@
+use actix_web::{error, http::StatusCode, post, web, web::ServiceConfig, HttpResponse};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+use thiserror::Error;
+use utoipa::{OpenApi, ToSchema};
+
+use crate::license::LicensePolicy;
+use crate::package::{Package, Severity, TrustedContent};
+use crate::policy::PolicyEngine;
+
+pub use trust_api_model::gate::*;
+
+pub(crate) fn configure() -> impl FnOnce(&mut ServiceConfig) {
+    |config: &mut ServiceConfig| {
+        config.service(check_gate);
+    }
+}
+
+/// The documentation of the gate endpoint.
+#[derive(OpenApi)]
+#[openapi(
+    paths(check_gate),
+    components(schemas(GateRequest, GateResult, GateViolation, ApiError))
+)]
+pub(crate) struct ApiDoc;
+
+/// The policies releases are checked against by `POST /api/gate`, by name.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GateConfig {
+    pub policies: BTreeMap<String, GatePolicy>,
+}
+
+/// The rules all components of a release must follow, unset rules are not checked.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GatePolicy {
+    /// The highest severity of vulnerabilities allowed
+    pub max_severity: Option<Severity>,
+    /// Whether all components must be trusted
+    pub require_trusted: bool,
+    /// Whether all components must have a SLSA provenance
+    pub require_provenance: bool,
+    /// The lowest SLSA build level of the provenance of all components
+    pub min_slsa_level: Option<u8>,
+    /// Which licenses components may use
+    pub licenses: Option<LicensePolicy>,
+    /// Whether vulnerabilities of unknown severity pass `max_severity`, they break it by default
+    pub allow_unknown_severity: bool,
+    /// Whether components may be checked without the data of unavailable providers, they are
+    /// reported as unresolved by default
+    pub allow_degraded: bool,
+}
+
+impl GatePolicy {
+    /// The rules a package breaks.
+    pub fn evaluate(&self, purl: &str, package: &Package) -> Vec<GateViolation> {
+        let mut violations = Vec::new();
+        let mut violate = |rule: &str, reason: String| {
+            violations.push(GateViolation {
+                purl: purl.to_string(),
+                rule: rule.to_string(),
+                reason,
+            })
+        };
+
+        if let Some(max) = self.max_severity {
+            for vulnerability in &package.vulnerabilities {
+                match vulnerability.severity {
+                    Some(severity) if severity > max => violate(
+                        "max-severity",
+                        format!(
+                            "{} is of severity {}, more than the allowed {}",
+                            vulnerability.cve,
+                            severity.as_str(),
+                            max.as_str()
+                        ),
+                    ),
+                    None if !self.allow_unknown_severity => violate(
+                        "max-severity",
+                        format!(
+                            "The severity of {} is unknown, at most {} is allowed",
+                            vulnerability.cve,
+                            max.as_str()
+                        ),
+                    ),
+                    _ => {}
+                }
+            }
+        }
+        if !self.allow_degraded && !package.degraded.is_empty() {
+            violate(
+                "unresolved",
+                format!(
+                    "The data of {} is missing, they are unavailable",
+                    package.degraded.join(", ")
+                ),
+            );
+        }
+        if self.require_trusted && package.trusted != Some(true) {
+            violate("trusted", "The package is not trusted".to_string());
+        }
+        if self.require_provenance && package.provenance.is_none() {
+            violate("provenance", "No provenance is known".to_string());
+        }
+        if let Some(min) = self.min_slsa_level {
+            let level = package.provenance.as_ref().and_then(|p| p.slsa_level);
+            if level.map_or(true, |level| level < min) {
+                violate(
+                    "slsa-level",
+                    match level {
+                        Some(level) => {
+                            format!(
+                                "Built at SLSA level {}, less than the required {}",
+                                level, min
+                            )
+                        }
+                        None => format!("The SLSA level is unknown, {} is required", min),
+                    },
+                );
+            }
+        }
+        if let Some(licenses) = &self.licenses {
+            for violation in licenses.evaluate(&package.licenses).violations {
+                violate("license", violation.reason);
+            }
+        }
+        violations
+    }
+}
+
+/// The configured gate policies, and the policy engine to delegate the other policies to.
+pub struct Gate {
+    policies: BTreeMap<String, GatePolicy>,
+    engine: Option<Arc<PolicyEngine>>,
+}
+
+impl Gate {
+    pub fn new(config: &GateConfig, engine: Option<Arc<PolicyEngine>>) -> Self {
+        Self {
+            policies: config.policies.clone(),
+            engine,
+        }
+    }
+
+    /// Check the components against the configured policy, returning the violations and whether
+    /// some components could not be looked up.
+    async fn check(
+        &self,
+        trusted: &TrustedContent,
+        policy: &str,
+        purls: &[String],
+    ) -> Result<(Vec<GateViolation>, bool), ApiError> {
+        let not_found = || ApiError::PolicyNotFound {
+            policy: policy.to_string(),
+        };
+        let mut violations = Vec::new();
+        let mut unresolved = false;
+        let mut unresolvable = |purl: &str, reason: String| {
+            unresolved = true;
+            violations.push(GateViolation {
+                purl: purl.to_string(),
+                rule: "unresolved".to_string(),
+                reason,
+            });
+        };
+
+        // the configured policies are checked by their rules, the others delegated to the engine
+        let engine = self
+            .engine
+            .as_ref()
+            .filter(|_| !self.policies.contains_key(policy));
+        if let Some(engine) = engine {
+            let analysis = crate::analyze::analyze(trusted, purls, false).await;
+            for item in &analysis.packages {
+                if let Some(error) = &item.error {
+                    unresolvable(&item.purl, error.clone());
+                }
+            }
+            let input = serde_json::json!({ "policy": policy, "analysis": analysis });
+            let found = engine
+                .violations(policy, &input)
+                .await
+                .map_err(|e| ApiError::Policy {
+                    message: e.to_string(),
+                })?
+                .ok_or_else(not_found)?;
+            violations.extend(found);
+            return Ok((violations, unresolved));
+        }
+
+        let policy = self.policies.get(policy).ok_or_else(not_found)?;
+        let canonical: Vec<String> = purls.iter().map(|p| trusted.canonicalize(p)).collect();
+        let results = trusted
+            .get_trusted_batch(&canonical.iter().map(String::as_str).collect::<Vec<_>>())
+            .await;
+        let mut found = Vec::new();
+        for (purl, result) in purls.iter().zip(results) {
+            match result {
+                Ok(package) => found.extend(policy.evaluate(purl, &package)),
+                Err(e) => unresolvable(purl, e.to_string()),
+            }
+        }
+        unresolved |= found.iter().any(|v| v.rule == "unresolved");
+        violations.extend(found);
+        Ok((violations, unresolved))
+    }
+}
+
+/// Check the components of a release against a named policy, for CI pipelines to block releases.
+///
+/// The result is returned with `200` whether the release passed or not, CI jobs can exit with
+/// its `exitStatus`.
+#[utoipa::path(
+    context_path = "/api/v1",
+    request_body = GateRequest,
+    responses(
+        (status = 200, description = "The result of the check", body = GateResult),
+        (status = BAD_REQUEST, description = "No components to check", body = Problem, example = json!(crate::problem::example(ApiError::NoComponents))),
+        (status = NOT_FOUND, description = "Unknown policy", body = Problem, example = json!(crate::problem::example(ApiError::PolicyNotFound { policy: "release".to_string() }))),
+        (status = BAD_GATEWAY, description = "The policy engine failed", body = Problem, example = json!(crate::problem::example(ApiError::Policy { message: "OPA did not answer in time".to_string() }))),
+    )
+)]
+#[post("/gate")]
+pub async fn check_gate(
+    gate: web::Data<Arc<Gate>>,
+    trusted: web::Data<TrustedContent>,
+    body: web::Json<GateRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let request = body.into_inner();
+    let mut purls: BTreeSet<String> = request.purls.into_iter().collect();
+    if let Some(sbom) = &request.sbom {
+        purls.extend(crate::sbom::component_purls(sbom));
+    }
+    if purls.is_empty() {
+        return Err(ApiError::NoComponents);
+    }
+    let purls: Vec<String> = purls.into_iter().collect();
+    let (violations, unresolved) = gate.check(&trusted, &request.policy, &purls).await?;
+    let broken = violations.iter().any(|v| v.rule != "unresolved");
+    Ok(HttpResponse::Ok().json(GateResult {
+        policy: request.policy,
+        passed: violations.is_empty(),
+        exit_status: match (broken, unresolved) {
+            (true, _) => 1,
+            (false, true) => 2,
+            (false, false) => 0,
+        },
+        components: purls.len(),
+        violations,
+    }))
+}
+
+#[derive(Debug, Error, Serialize, Deserialize, ToSchema)]
+#[schema(as = GateApiError)]
+pub enum ApiError {
+    #[error("The request lists no package URLs, and no SBOM with components")]
+    NoComponents,
+    #[error("No policy {policy} is configured")]
+    PolicyNotFound { policy: String },
+    #[error("Error evaluating the policy: {message}")]
+    Policy { message: String },
+}
+
+impl error::ResponseError for ApiError {
+    fn error_response(&self) -> HttpResponse {
+        crate::problem::response(self.status_code(), self, serde_json::json!({}))
+    }
+
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::NoComponents => StatusCode::BAD_REQUEST,
+            ApiError::PolicyNotFound { policy: _ } => StatusCode::NOT_FOUND,
+            ApiError::Policy { message: _ } => StatusCode::BAD_GATEWAY,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const PURL: &str = "pkg:maven/io.vertx/vertx-web@4.3.7";
+
+    fn package(package: serde_json::Value) -> Package {
+        serde_json::from_value(package).unwrap()
+    }
+
+    fn rules(violations: Vec<GateViolation>) -> Vec<String> {
+        violations.into_iter().map(|v| v.rule).collect()
+    }
+
+    fn vulnerability(cve: &str, severity: Option<&str>) -> serde_json::Value {
+        json!({"cve": cve, "href": format!("/api/v1/vulnerability/{}", cve), "severity": severity})
+    }
+
+    #[test]
+    fn unset_rules_are_not_checked() {
+        let package = package(json!({
+            "vulnerabilities": [vulnerability("cve-2023-0286", Some("critical"))]
+        }));
+        assert!(GatePolicy::default().evaluate(PURL, &package).is_empty());
+    }
+
+    #[test]
+    fn vulnerabilities_above_the_max_severity_break_it() {
+        let policy = GatePolicy {
+            max_severity: Some(Severity::Medium),
+            ..Default::default()
+        };
+        let package = package(json!({
+            "vulnerabilities": [
+                vulnerability("cve-2023-0286", Some("high")),
+                vulnerability("cve-2023-0215", Some("medium")),
+                vulnerability("cve-2022-4304", Some("low")),
+            ]
+        }));
+        let violations = policy.evaluate(PURL, &package);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].purl, PURL);
+        assert_eq!(violations[0].rule, "max-severity");
+        assert!(violations[0].reason.contains("cve-2023-0286"));
+    }
+
+    #[test]
+    fn unknown_severities_break_the_max_severity_unless_allowed() {
+        let mut policy = GatePolicy {
+            max_severity: Some(Severity::Critical),
+            ..Default::default()
+        };
+        let package = package(json!({
+            "vulnerabilities": [vulnerability("cve-2023-0286", None)]
+        }));
+        assert_eq!(rules(policy.evaluate(PURL, &package)), ["max-severity"]);
+
+        policy.allow_unknown_severity = true;
+        assert!(policy.evaluate(PURL, &package).is_empty());
+    }
+
+    #[test]
+    fn degraded_packages_are_unresolved_unless_allowed() {
+        let mut policy = GatePolicy::default();
+        let package = package(json!({"trusted": true, "degraded": ["snyk"]}));
+        let violations = policy.evaluate(PURL, &package);
+        assert_eq!(rules(violations.clone()), ["unresolved"]);
+        assert!(violations[0].reason.contains("snyk"));
+
+        policy.allow_degraded = true;
+        assert!(policy.evaluate(PURL, &package).is_empty());
+    }
+
+    #[test]
+    fn trust_and_provenance_are_required() {
+        let policy = GatePolicy {
+            require_trusted: true,
+            require_provenance: true,
+            min_slsa_level: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(
+            rules(policy.evaluate(PURL, &package(json!({"trusted": false})))),
+            ["trusted", "provenance", "slsa-level"]
+        );
+        assert_eq!(
+            rules(policy.evaluate(PURL, &package(json!({"unknown": true})))),
+            ["trusted", "provenance", "slsa-level"]
+        );
+    }
+
+    #[test]
+    fn licenses_are_checked_against_the_license_policy() {
+        let policy = GatePolicy {
+            licenses: Some(serde_json::from_value(json!({"allow": ["MIT"]})).unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(
+            rules(policy.evaluate(PURL, &package(json!({})))),
+            ["license"]
+        );
+    }
+}
@@ -0,0 +1,288 @@
+use anyhow::{anyhow, bail};
+use chrono::Utc;
+use packageurl::PackageUrl;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+
+use crate::config::ResilienceConfig;
+use crate::package::{ProviderId, Severity, VulnerabilityRef, VulnerabilitySource};
+use crate::resilience::Resilience;
+use crate::version::Scheme;
+
+const QUERY: &str = r#"query($ecosystem: SecurityAdvisoryEcosystem!, $package: String!, $after: String) {
+  securityVulnerabilities(ecosystem: $ecosystem, package: $package, first: 100, after: $after) {
+    nodes {
+      vulnerableVersionRange
+      advisory { ghsaId permalink severity withdrawnAt identifiers { type value } }
+    }
+    pageInfo { hasNextPage endCursor }
+  }
+}"#;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GhsaConfig {
+    /// Add the GitHub Security Advisories of packages to their vulnerabilities
+    pub enabled: bool,
+    pub url: String,
+    /// A GitHub token, the GraphQL API doesn't allow anonymous requests
+    pub token: Option<String>,
+    /// Retries, timeouts and the circuit breaker of calls to the GraphQL API
+    pub resilience: ResilienceConfig,
+}
+
+impl Default for GhsaConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            url: "https://api.github.com/graphql".to_string(),
+            token: None,
+            resilience: Default::default(),
+        }
+    }
+}
+
+impl GhsaConfig {
+    pub fn redacted(&self, redacted: &str) -> Self {
+        Self {
+            token: self.token.as_ref().map(|_| redacted.to_string()),
+            ..self.clone()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct GraphQlResponse {
+    data: Option<Data>,
+    #[serde(default)]
+    errors: Vec<GraphQlError>,
+}
+
+#[derive(Deserialize)]
+struct GraphQlError {
+    message: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Data {
+    security_vulnerabilities: Connection,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Connection {
+    nodes: Vec<SecurityVulnerability>,
+    page_info: PageInfo,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PageInfo {
+    has_next_page: bool,
+    end_cursor: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SecurityVulnerability {
+    vulnerable_version_range: String,
+    advisory: Advisory,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Advisory {
+    ghsa_id: String,
+    permalink: String,
+    severity: Option<String>,
+    withdrawn_at: Option<String>,
+    #[serde(default)]
+    identifiers: Vec<Identifier>,
+}
+
+#[derive(Deserialize)]
+struct Identifier {
+    #[serde(rename = "type")]
+    ty: String,
+    value: String,
+}
+
+/// The GHSA ecosystem and package name of a purl, `None` for ecosystems GitHub doesn't cover.
+fn package_of(purl: &PackageUrl<'_>) -> Option<(&'static str, String)> {
+    let namespaced = |separator: &str| match purl.namespace() {
+        Some(namespace) => format!("{}{}{}", namespace, separator, purl.name()),
+        None => purl.name().to_string(),
+    };
+    Some(match purl.ty() {
+        "maven" => ("MAVEN", namespaced(":")),
+        "npm" => ("NPM", namespaced("/")),
+        "pypi" => ("PIP", purl.name().to_string()),
+        "gem" => ("RUBYGEMS", purl.name().to_string()),
+        "nuget" => ("NUGET", purl.name().to_string()),
+        "composer" => ("COMPOSER", namespaced("/")),
+        "golang" => ("GO", namespaced("/")),
+        "cargo" => ("RUST", purl.name().to_string()),
+        "hex" => ("ERLANG", purl.name().to_string()),
+        "pub" => ("PUB", purl.name().to_string()),
+        "swift" => ("SWIFT", namespaced("/")),
+        _ => return None,
+    })
+}
+
+/// Whether a version is in a GHSA range, e.g. `>= 4.0.0, < 4.3.5`, compared by the rules of its
+/// ecosystem.
+fn in_range(scheme: Scheme, version: &str, range: &str) -> bool {
+    range.split(',').map(str::trim).all(|constraint| {
+        let (op, bound) = match constraint.split_once(' ') {
+            Some((op, bound)) => (op, bound.trim()),
+            None => return false,
+        };
+        let ordering = scheme.compare(version, bound);
+        match op {
+            "=" => ordering == Ordering::Equal,
+            "<" => ordering == Ordering::Less,
+            "<=" => ordering != Ordering::Greater,
+            ">" => ordering == Ordering::Greater,
+            ">=" => ordering != Ordering::Less,
+            _ => false,
+        }
+    })
+}
+
+/// GitHub Security Advisories, from the GraphQL API.
+pub struct Ghsa {
+    url: String,
+    token: Option<String>,
+    client: reqwest::Client,
+    resilience: Resilience,
+}
+
+impl Ghsa {
+    pub fn new(config: &GhsaConfig) -> Self {
+        Self {
+            url: config.url.clone(),
+            token: config.token.clone(),
+            client: reqwest::Client::new(),
+            resilience: Resilience::new("ghsa", config.resilience.clone()),
+        }
+    }
+
+    /// The advisories affecting a package version.
+    ///
+    /// Advisories with a CVE are reported by the CVE id, with the GHSA id in their `ids`, so they
+    /// are merged with the same vulnerability reported by other providers.
+    pub async fn get_vulnerabilities(
+        &self,
+        purl: &PackageUrl<'_>,
+        version: &str,
+    ) -> Result<Vec<VulnerabilityRef>, anyhow::Error> {
+        let (ecosystem, package) = match package_of(purl) {
+            Some(package) => package,
+            None => return Ok(Vec::new()),
+        };
+
+        let scheme = Scheme::of(purl.ty());
+        let mut vulns: Vec<VulnerabilityRef> = Vec::new();
+        let mut after: Option<String> = None;
+        loop {
+            let body = serde_json::json!({
+                "query": QUERY,
+                "variables": { "ecosystem": ecosystem, "package": package, "after": after },
+            });
+            let response: GraphQlResponse = self
+                .resilience
+                .call("security_vulnerabilities", || async {
+                    let mut request = self.client.post(&self.url).json(&body);
+                    if let Some(token) = &self.token {
+                        request = request.bearer_auth(token);
+                    }
+                    request
+                        .header(reqwest::header::USER_AGENT, "trust-api")
+                        .send()
+                        .await?
+                        .error_for_status()?
+                        .json()
+                        .await
+                })
+                .await?;
+            if let Some(error) = response.errors.first() {
+                bail!("GraphQL error: {}", error.message);
+            }
+            let connection = response
+                .data
+                .ok_or_else(|| anyhow!("GraphQL response without data"))?
+                .security_vulnerabilities;
+            let fetched = Some(Utc::now());
+
+            for node in connection.nodes {
+                let advisory = node.advisory;
+                if advisory.withdrawn_at.is_some()
+                    || !in_range(scheme, version, &node.vulnerable_version_range)
+                    || vulns
+                        .iter()
+                        .any(|v| v.ids.iter().any(|id| id.id == advisory.ghsa_id))
+                {
+                    continue;
+                }
+                let cve = advisory
+                    .identifiers
+                    .iter()
+                    .find(|i| i.ty == "CVE")
+                    .map_or_else(|| advisory.ghsa_id.clone(), |i| i.value.clone());
+                let aliases = advisory
+                    .identifiers
+                    .iter()
+                    .map(|i| i.value.clone())
+                    .filter(|id| !id.eq_ignore_ascii_case(&cve))
+                    .collect();
+                let severity = advisory.severity.as_deref().and_then(Severity::parse);
+                vulns.push(VulnerabilityRef {
+                    cve,
+                    href: advisory.permalink.clone(),
+                    ids: vec![ProviderId {
+                        provider: "ghsa".to_string(),
+                        id: advisory.ghsa_id,
+                    }],
+                    fetched,
+                    aliases,
+                    severity,
+                    sources: vec![VulnerabilitySource {
+                        provider: "ghsa".to_string(),
+                        href: advisory.permalink,
+                        severity,
+                        fetched,
+                    }],
+                });
+            }
+
+            match connection.page_info.end_cursor {
+                Some(cursor) if connection.page_info.has_next_page => after = Some(cursor),
+                _ => break,
+            }
+        }
+        Ok(vulns)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranges_are_compared_by_the_rules_of_the_ecosystem() {
+        let range = ">= 4.0.0, < 4.3.5";
+        assert!(in_range(Scheme::Semver, "4.3.5-rc.1", range));
+        assert!(!in_range(Scheme::Semver, "4.3.5", range));
+        assert!(!in_range(Scheme::Semver, "3.9.9", range));
+        // by its segments only, the pre-release would be newer than the release
+        assert!(in_range(Scheme::Maven, "4.3.5-SNAPSHOT", range));
+        assert!(!in_range(Scheme::Maven, "4.3.5.Final", range));
+        assert!(in_range(
+            Scheme::Maven,
+            "4.3.4.redhat-00007",
+            "<= 4.3.4.redhat-00007"
+        ));
+        assert!(!in_range(Scheme::Maven, "4.3.4", "invalid"));
+    }
+}
@@ -0,0 +1,358 @@
+use actix_web::ResponseError;
+use std::future::Future;
+use std::sync::Arc;
+use tonic::codegen::InterceptedService;
+use tonic::service::Interceptor;
+use tonic::{Request, Response, Status};
+
+use crate::auth::{Authenticator, Identity};
+use crate::error::TrustError;
+use crate::package::{self, ApiError, TrustedContent};
+use crate::rbac::{self, Role};
+use crate::sbom::SbomRegistry;
+use crate::subgraph::MAX_DEPTH;
+
+pub mod proto {
+    tonic::include_proto!("trust_api.v1");
+}
+
+use proto::trust_api_server::{TrustApi, TrustApiServer};
+
+/// The gRPC API, backed by the same data as the REST API.
+pub struct GrpcService {
+    trusted: Arc<TrustedContent>,
+    sboms: Arc<SbomRegistry>,
+}
+
+impl GrpcService {
+    /// The service, requiring the same ID tokens as the REST API, and the `reader` role if RBAC
+    /// is enabled.
+    pub fn new(
+        trusted: Arc<TrustedContent>,
+        sboms: Arc<SbomRegistry>,
+        authenticator: Arc<Authenticator>,
+        rbac: bool,
+    ) -> InterceptedService<TrustApiServer<Self>, Authentication> {
+        TrustApiServer::with_interceptor(
+            Self { trusted, sboms },
+            Authentication {
+                authenticator,
+                rbac,
+            },
+        )
+    }
+}
+
+/// Validates the bearer token in the `authorization` metadata of calls, if issuers are
+/// configured, passing the identity of the caller on in the request extensions.
+#[derive(Clone)]
+pub struct Authentication {
+    authenticator: Arc<Authenticator>,
+    rbac: bool,
+}
+
+impl Interceptor for Authentication {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        if !self.authenticator.is_enabled() {
+            return Ok(request);
+        }
+        let authorization = request
+            .metadata()
+            .get("authorization")
+            .and_then(|v| v.to_str().ok());
+        let identity = self
+            .authenticator
+            .authenticate_header(authorization)
+            .map_err(|e| Status::unauthenticated(e.to_string()))?;
+        if self.rbac && !Role::Reader.is_granted(&identity.roles) {
+            let missing = rbac::ApiError::MissingRole {
+                role: Role::Reader.name().to_string(),
+            };
+            return Err(Status::permission_denied(missing.to_string()));
+        }
+        request.extensions_mut().insert(identity);
+        Ok(request)
+    }
+}
+
+/// The caller the interceptor authenticated, `None` without authentication.
+fn caller<T>(request: &Request<T>) -> Option<Identity> {
+    request.extensions().get::<Identity>().cloned()
+}
+
+/// Run a call on behalf of its caller, seeing the content of its tenant, or all content without
+/// authentication.
+async fn scoped<T, F>(caller: Option<Identity>, f: F) -> Result<T, Status>
+where
+    F: Future<Output = Result<T, Status>>,
+{
+    match caller {
+        Some(identity) => crate::tenant::scoped(identity.tenant, f).await,
+        None => f.await,
+    }
+}
+
+#[tonic::async_trait]
+impl TrustApi for GrpcService {
+    async fn get_package(
+        &self,
+        request: Request<proto::GetPackageRequest>,
+    ) -> Result<Response<proto::Package>, Status> {
+        let caller = caller(&request);
+        scoped(caller, async move {
+            let request = request.into_inner();
+            let purl = self.trusted.canonicalize(&request.purl);
+            let p = self.trusted.get_trusted(&purl).await?;
+            Ok(Response::new(
+                package::with_ids(p, request.include_ids).into(),
+            ))
+        })
+        .await
+    }
+
+    async fn batch_get_packages(
+        &self,
+        request: Request<proto::BatchGetPackagesRequest>,
+    ) -> Result<Response<proto::BatchGetPackagesResponse>, Status> {
+        let caller = caller(&request);
+        scoped(caller, async move {
+            let request = request.into_inner();
+            let items = self.trusted.validate_batch(&request.purls, false)?;
+            let canonical: Vec<&str> = items.iter().map(|i| i.canonical.as_str()).collect();
+            let found = self.trusted.get_trusted_batch(&canonical).await;
+            let mut batch = Vec::new();
+            for (item, found) in items.into_iter().zip(found) {
+                let result = match item.invalid {
+                    Some(e) => Err(e),
+                    None => found.map(|p| package::with_ids(p, request.include_ids)),
+                };
+                batch.push(package::batch_item(item.purl, &item.canonical, result));
+            }
+            self.trusted.batch().limit(&mut batch);
+            let items = batch
+                .into_iter()
+                .map(|item| proto::PackageBatchItem {
+                    purl: item.purl,
+                    canonical_purl: item.canonical_purl,
+                    status: item.status.into(),
+                    result: item.result.map(Into::into),
+                    error: item.error,
+                    code: item.code,
+                    provider: item.provider,
+                })
+                .collect();
+            Ok(Response::new(proto::BatchGetPackagesResponse { items }))
+        })
+        .await
+    }
+
+    async fn get_dependencies(
+        &self,
+        request: Request<proto::GetDependenciesRequest>,
+    ) -> Result<Response<proto::PackageDependencies>, Status> {
+        let caller = caller(&request);
+        scoped(caller, async move {
+            let request = request.into_inner();
+            let purl = self.trusted.canonicalize(&request.purl);
+            let depth = request.depth.map_or(1, |depth| depth as usize);
+            if !(1..=MAX_DEPTH).contains(&depth) {
+                return Err(ApiError::InvalidDepth { depth }.into());
+            }
+            let dependencies = self.trusted.get_dependencies(&purl, depth).await?;
+            Ok(Response::new(proto::PackageDependencies {
+                packages: dependencies.0.into_iter().map(Into::into).collect(),
+            }))
+        })
+        .await
+    }
+
+    async fn get_sbom(
+        &self,
+        request: Request<proto::GetSbomRequest>,
+    ) -> Result<Response<proto::Sbom>, Status> {
+        let caller = caller(&request);
+        scoped(caller, async move {
+            let purl = self.trusted.canonicalize(&request.into_inner().purl);
+            match self.sboms.lookup(&purl) {
+                Some(document) => Ok(Response::new(proto::Sbom {
+                    purl,
+                    document: document.to_string(),
+                })),
+                None => Err(ApiError::PackageNotFound { purl }.into()),
+            }
+        })
+        .await
+    }
+}
+
+impl From<ApiError> for Status {
+    fn from(e: ApiError) -> Self {
+        let message = e.to_string();
+        match e.status_code().as_u16() {
+            400 => Status::invalid_argument(message),
+            404 => Status::not_found(message),
+            502 | 503 => Status::unavailable(message),
+            _ => Status::internal(message),
+        }
+    }
+}
+
+impl From<TrustError> for Status {
+    fn from(e: TrustError) -> Self {
+        ApiError::from(e).into()
+    }
+}
+
+impl From<package::Package> for proto::Package {
+    fn from(p: package::Package) -> Self {
+        Self {
+            purl: p.purl,
+            href: p.href,
+            sbom: p.sbom,
+            trusted: p.trusted,
+            trusted_versions: p.trusted_versions.into_iter().map(Into::into).collect(),
+            vulnerabilities: p.vulnerabilities.into_iter().map(Into::into).collect(),
+            signature_verified: p.signature_verified,
+            signer: p.signer,
+            provenance: p.provenance.map(Into::into),
+            certifications: p.certifications.into_iter().map(Into::into).collect(),
+            scorecard: p.scorecard.map(Into::into),
+            licenses: p.licenses.into_iter().map(Into::into).collect(),
+            degraded: p.degraded,
+            warnings: p
+                .warnings
+                .into_iter()
+                .map(|w| proto::ProviderWarning {
+                    provider: w.provider,
+                    reason: w.reason.as_str().to_string(),
+                })
+                .collect(),
+            sbom_fetched: p.sbom_fetched.map(|f| f.to_rfc3339()),
+            unknown: p.unknown,
+        }
+    }
+}
+
+impl From<package::Certification> for proto::Certification {
+    fn from(c: package::Certification) -> Self {
+        Self {
+            good: c.verdict == package::Verdict::Good,
+            justification: c.justification,
+            origin: c.origin,
+        }
+    }
+}
+
+impl From<package::License> for proto::License {
+    fn from(l: package::License) -> Self {
+        Self {
+            expression: l.expression,
+            source: l.source,
+        }
+    }
+}
+
+impl From<package::ScorecardSummary> for proto::Scorecard {
+    fn from(s: package::ScorecardSummary) -> Self {
+        Self {
+            repository: s.repository,
+            score: s.score,
+            failing_checks: s.failing_checks,
+        }
+    }
+}
+
+impl From<package::Provenance> for proto::Provenance {
+    fn from(p: package::Provenance) -> Self {
+        Self {
+            builder_id: p.builder_id,
+            build_type: p.build_type,
+            slsa_level: p.slsa_level.map(Into::into),
+        }
+    }
+}
+
+impl From<package::PackageRef> for proto::PackageRef {
+    fn from(p: package::PackageRef) -> Self {
+        Self {
+            purl: p.purl,
+            href: p.href,
+            trusted: p.trusted,
+            sbom: p.sbom,
+            unverified: p.unverified,
+            vulnerabilities: p.vulnerabilities.map(|v| v as u32),
+            published: p.published.map(|p| p.to_rfc3339()),
+            fetched: p.fetched.map(|f| f.to_rfc3339()),
+        }
+    }
+}
+
+impl From<package::VulnerabilityRef> for proto::VulnerabilityRef {
+    fn from(v: package::VulnerabilityRef) -> Self {
+        Self {
+            cve: v.cve,
+            href: v.href,
+            ids: v
+                .ids
+                .into_iter()
+                .map(|id| proto::ProviderId {
+                    provider: id.provider,
+                    id: id.id,
+                })
+                .collect(),
+            fetched: v.fetched.map(|f| f.to_rfc3339()),
+            aliases: v.aliases,
+            severity: v.severity.map(|s| s.as_str().to_string()),
+            sources: v
+                .sources
+                .into_iter()
+                .map(|s| proto::VulnerabilitySource {
+                    provider: s.provider,
+                    href: s.href,
+                    severity: s.severity.map(|s| s.as_str().to_string()),
+                    fetched: s.fetched.map(|f| f.to_rfc3339()),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::{AuthConfig, IssuerConfig};
+
+    fn authentication(issuers: Vec<IssuerConfig>) -> Authentication {
+        Authentication {
+            authenticator: Arc::new(Authenticator::new(&AuthConfig {
+                issuers,
+                ..Default::default()
+            })),
+            rbac: true,
+        }
+    }
+
+    #[test]
+    fn calls_require_a_token_if_issuers_are_configured() {
+        assert!(authentication(Vec::new()).call(Request::new(())).is_ok());
+
+        let mut authentication = authentication(vec![IssuerConfig {
+            issuer: "https://sso.example.com".to_string(),
+            audience: "trust".to_string(),
+            jwks_url: None,
+            roles: Vec::new(),
+            roles_claim: None,
+            tenant: None,
+            algorithms: vec![jsonwebtoken::Algorithm::RS256],
+        }]);
+        let status = authentication.call(Request::new(())).unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unauthenticated);
+
+        let mut request = Request::new(());
+        request
+            .metadata_mut()
+            .insert("authorization", "Bearer not.a.token".parse().unwrap());
+        let status = authentication.call(request).unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unauthenticated);
+    }
+}
@@ -1,112 +1,730 @@
+use crate::config::GuacConfig;
+use crate::metrics;
+use crate::package::Certification;
+use crate::package::DependencyEdge;
+use crate::package::License;
 use crate::package::Package;
 use crate::package::PackageDependencies;
 use crate::package::PackageRef;
+use crate::package::Provenance;
+use crate::package::ProviderId;
+use crate::package::Verdict;
 use crate::package::VulnerabilityRef;
+use crate::package::VulnerabilitySource;
+use crate::resilience::{Resilience, Unavailable};
 use crate::sbom::SbomRegistry;
+use crate::subgraph::{Direction, Subgraphs};
+use crate::trusted::TrustedVersionResolver;
 use crate::vulnerability::Cvss3;
 use crate::vulnerability::Vulnerability;
 use anyhow::anyhow;
 use chrono::DateTime;
 use chrono::Utc;
 use core::str::FromStr;
-use guac::client::GuacClient;
 use http::StatusCode;
 use packageurl::PackageUrl;
-use std::sync::Arc;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 
+/// How many of the shortest paths to a package are enumerated.
+pub const MAX_PATHS: usize = 10;
+
+/// How many packages a search for paths visits at most.
+pub const MAX_PATH_NODES: usize = 5000;
+
+const PACKAGE_FIELDS: &str =
+    "type namespaces { namespace names { name versions { version qualifiers { key value } } } }";
+
+const VULNERABILITY_FIELDS: &str =
+    "vulnerability { __typename ... on CVE { cveIds { cveId } } ... on OSV { osvIds { osvId } } }";
+
+const HAS_SLSA_QUERY: &str = r#"
+query HasSLSA($filter: HasSLSASpec!) {
+  HasSLSA(hasSLSASpec: $filter) {
+    slsa {
+      builtBy { uri }
+      buildType
+    }
+  }
+}
+"#;
+
+const IS_DEPENDENCY_QUERY: &str = r#"
+query IsDependency($package: PkgSpec!) {
+  IsDependency(isDependencySpec: { package: $package }) {
+    dependentPackage { type namespaces { namespace names { name } } }
+    versionRange
+  }
+}
+"#;
+
+const HAS_SOURCE_AT_QUERY: &str = r#"
+query HasSourceAt($package: PkgSpec!) {
+  HasSourceAt(hasSourceAtSpec: { package: $package }) {
+    source {
+      namespaces {
+        namespace
+        names { name }
+      }
+    }
+  }
+}
+"#;
+
+const PKG_EQUAL_QUERY: &str = r#"
+query PkgEqual($package: PkgSpec!) {
+  PkgEqual(pkgEqualSpec: { packages: [$package] }) {
+    packages {
+      type
+      namespaces {
+        namespace
+        names {
+          name
+          versions { version qualifiers { key value } }
+        }
+      }
+    }
+  }
+}
+"#;
+
+const PACKAGES_QUERY: &str = r#"
+query Packages($name: PkgSpec!) {
+  packages(pkgSpec: $name) {
+    type
+    namespaces { namespace names { name versions { version qualifiers { key value } } } }
+  }
+}
+"#;
+
+const CERTIFY_VULN_QUERY: &str = r#"
+query CertifyVuln($package: PkgSpec!) {
+  CertifyVuln(certifyVulnSpec: { package: $package }) {
+    vulnerability { __typename ... on CVE { cveIds { cveId } } ... on OSV { osvIds { osvId } } }
+  }
+}
+"#;
+
+const VULNERABLE_PACKAGES_QUERY: &str = r#"
+query VulnerablePackages($vulnerability: CveOrGhsaOrOsvSpec!) {
+  CertifyVuln(certifyVulnSpec: { vulnerability: $vulnerability }) {
+    package {
+      type
+      namespaces { namespace names { name versions { version qualifiers { key value } } } }
+    }
+  }
+}
+"#;
+
+const DEPENDENCIES_QUERY: &str = r#"
+query Dependencies($package: PkgSpec!) {
+  IsDependency(isDependencySpec: { package: $package }) {
+    dependentPackage { type namespaces { namespace names { name } } }
+  }
+}
+"#;
+
+const DEPENDENTS_QUERY: &str = r#"
+query Dependents($name: PkgNameSpec!) {
+  IsDependency(isDependencySpec: { dependentPackage: $name }) {
+    package {
+      type
+      namespaces { namespace names { name versions { version qualifiers { key value } } } }
+    }
+  }
+}
+"#;
+
+const CERTIFY_LEGAL_QUERY: &str = r#"
+query CertifyLegal($subject: PackageOrSourceSpec!) {
+  CertifyLegal(certifyLegalSpec: { subject: $subject }) { declaredLicense discoveredLicense }
+}
+"#;
+
+/// The client of the GraphQL API of Guac.
+///
+/// All queries share one HTTP client, keeping connections to Guac open between them and sending
+/// the configured authorization and the id of the request served along with each.
 #[derive(Clone)]
 pub struct Guac {
-    client: GuacClient,
+    url: String,
+    http: reqwest::Client,
     sbom: Arc<SbomRegistry>,
+    resilience: Arc<Resilience>,
+    resolver: Arc<dyn TrustedVersionResolver>,
+    subgraphs: Arc<Subgraphs>,
+    /// Whether the last probe reached Guac
+    available: Arc<AtomicBool>,
+    /// Purls recently found to be unknown to Guac, so they don't hit Guac on every lookup
+    unknown: Arc<RwLock<UnknownPurls>>,
+    /// How many packages are summarized by a single query
+    batch_size: usize,
+    /// Where the details of vulnerabilities are fetched from
+    security_data_url: String,
+    /// The client of the security data API, without the authorization of Guac
+    security_data: reqwest::Client,
+}
+
+/// What the package lookup needs to know about a package from Guac.
+#[derive(Clone, Debug, Default)]
+pub struct Summary {
+    /// The versions of the package
+    pub packages: Vec<PackageRef>,
+    pub vulnerabilities: Vec<VulnerabilityRef>,
+    pub certifications: Vec<Certification>,
+    pub licenses: Vec<License>,
+    /// Without the SLSA level, which is left to the caller
+    pub provenance: Option<Provenance>,
 }
 
 impl Guac {
-    pub fn new(url: &str, sbom: Arc<SbomRegistry>) -> Self {
-        let client = GuacClient::new(url.to_string());
-        Self { client, sbom }
+    pub fn new(
+        config: &GuacConfig,
+        sbom: Arc<SbomRegistry>,
+        resilience: Resilience,
+        resolver: Arc<dyn TrustedVersionResolver>,
+        subgraphs: Arc<Subgraphs>,
+    ) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            url: config.url.to_string(),
+            http: Self::http_client(config)?,
+            sbom,
+            resilience: Arc::new(resilience),
+            resolver,
+            subgraphs,
+            available: Arc::new(AtomicBool::new(true)),
+            unknown: Arc::new(RwLock::new(UnknownPurls::new(
+                Duration::from_secs(config.negative_ttl_secs),
+                config.negative_capacity,
+            ))),
+            batch_size: config.batch_size.max(1),
+            security_data_url: config.security_data_url.trim_end_matches('/').to_string(),
+            security_data: reqwest::Client::new(),
+        })
+    }
+
+    fn http_client(config: &GuacConfig) -> Result<reqwest::Client, anyhow::Error> {
+        let mut headers = reqwest::header::HeaderMap::new();
+        if let Some(authorization) = &config.authorization {
+            let mut value = reqwest::header::HeaderValue::from_str(authorization)
+                .map_err(|_| anyhow!("Invalid authorization header for Guac"))?;
+            value.set_sensitive(true);
+            headers.insert(reqwest::header::AUTHORIZATION, value);
+        }
+        let mut builder = reqwest::Client::builder()
+            .default_headers(headers)
+            .connect_timeout(Duration::from_millis(config.connect_timeout_ms))
+            .timeout(Duration::from_millis(config.resilience.timeout_ms))
+            .pool_max_idle_per_host(config.pool_size);
+        if config.keep_alive_secs > 0 {
+            let interval = Duration::from_secs(config.keep_alive_secs);
+            builder = builder
+                .tcp_keepalive(interval)
+                .http2_keep_alive_interval(interval)
+                .http2_keep_alive_while_idle(true);
+        }
+        Ok(builder.build()?)
+    }
+
+    /// Log a failed call, keeping for the response whether Guac could not be reached.
+    fn failed(context: &str, error: anyhow::Error) -> anyhow::Error {
+        let message = format!("{}: {:?}", context, error);
+        log::warn!("{}", message);
+        if error.is::<Unavailable>() {
+            error.context(message)
+        } else {
+            anyhow!(message)
+        }
+    }
+
+    /// Whether a purl was recently found to be unknown to Guac, fresh lookups always ask Guac.
+    fn is_unknown(&self, purl: &str) -> bool {
+        if crate::cache::bypassed() {
+            return false;
+        }
+        self.unknown.read().unwrap().contains(&unknown_key(purl))
+    }
+
+    fn remember_unknown(&self, purl: &str) {
+        self.unknown
+            .write()
+            .unwrap()
+            .insert(unknown_key(purl), Instant::now());
+    }
+
+    /// Forget that a purl is unknown, e.g. after it was ingested into Guac.
+    pub fn forget_unknown(&self, purl: &str) {
+        self.unknown.write().unwrap().remove(&unknown_key(purl));
+    }
+
+    /// Whether Guac answered the last probe, responses are degraded while it doesn't.
+    pub fn is_available(&self) -> bool {
+        self.available.load(Ordering::Relaxed)
+    }
+
+    /// Probe Guac periodically, tracking whether it is available.
+    ///
+    /// Failing calls don't affect availability, Guac is only considered unavailable if the probe
+    /// fails, and available again once a probe succeeds.
+    pub async fn monitor(self: Arc<Self>, interval: Duration) {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+            let available = match self
+                .query("probe", "{ __typename }", serde_json::json!({}))
+                .await
+            {
+                Ok(_) => true,
+                Err(e) => {
+                    log::debug!("Probing Guac failed: {:?}", e);
+                    false
+                }
+            };
+            match (self.available.swap(available, Ordering::Relaxed), available) {
+                (true, false) => log::warn!("Guac is unavailable, serving degraded responses"),
+                (false, true) => log::info!("Guac is available again"),
+                _ => {}
+            }
+        }
+    }
+
+    pub fn resolver(&self) -> &dyn TrustedVersionResolver {
+        self.resolver.as_ref()
     }
 
     pub async fn get_packages(
         &self,
         purl: PackageUrl<'_>,
     ) -> Result<Vec<PackageRef>, anyhow::Error> {
-        let pkgs = self
-            .client
-            .get_packages(&purl.to_string())
+        let name = serde_json::json!({
+            "type": purl.ty(),
+            "namespace": purl.namespace(),
+            "name": purl.name(),
+        });
+        let purl = purl.to_string();
+        if self.is_unknown(&purl) {
+            return Ok(vec![]);
+        }
+        let response = self
+            .resilience
+            .call("get_packages", || {
+                self.query(
+                    "get_packages",
+                    PACKAGES_QUERY,
+                    serde_json::json!({ "name": name }),
+                )
+            })
             .await
-            .map_err(|e| {
-                let e = format!("Error getting packages from GUAC: {:?}", e);
-                log::warn!("{}", e);
-                anyhow!(e)
-            })?;
+            .map_err(|e| Self::failed("Error getting packages from GUAC", e))?;
+        let pkgs = packages_of(response.pointer("/data/packages"));
+        if pkgs.is_empty() {
+            self.remember_unknown(&purl);
+        }
+        Ok(self.package_refs(&pkgs).await)
+    }
+
+    async fn package_refs(&self, purls: &[String]) -> Vec<PackageRef> {
+        let fetched = Some(Utc::now());
         let mut ret = Vec::new();
-        for purl in pkgs.iter() {
+        for purl in purls.iter() {
             let p = PackageRef {
                 purl: purl.clone(),
-                href: format!("/api/package?purl={}", &urlencoding::encode(&purl)),
-                trusted: Some(self.is_trusted(purl)),
+                href: format!("/api/v1/package?purl={}", &urlencoding::encode(&purl)),
+                trusted: Some(self.is_trusted(purl).await),
+                unverified: false,
                 sbom: if self.sbom.exists(&purl) {
                     Some(format!(
-                        "/api/package/sbom?purl={}",
+                        "/api/v1/package/sbom?purl={}",
                         &urlencoding::encode(&purl)
                     ))
                 } else {
                     None
                 },
+                vulnerabilities: None,
+                published: None,
+                fetched,
+                relation: None,
+                dependency: None,
+                degraded: vec![],
             };
             ret.push(p);
         }
-        Ok(ret)
+        ret
     }
 
-    fn is_trusted(&self, purl: &str) -> bool {
-        if let Ok(purl) = PackageUrl::from_str(purl) {
-            purl.version().map_or(false, |v| v.contains("redhat"))
-                || purl.namespace().map_or(false, |v| v == "redhat")
-        } else {
-            false
+    async fn is_trusted(&self, purl: &str) -> bool {
+        match PackageUrl::from_str(purl) {
+            Ok(purl) => self.resolver.is_trusted(&purl, None).await,
+            Err(_) => false,
         }
     }
 
-    pub async fn get_vulnerability(&self, cve_id: &str) -> Result<Vulnerability, anyhow::Error> {
+    /// The SLSA provenance of an artifact, by its SHA-256 digest (hex encoded).
+    ///
+    /// The level isn't part of the attestation, it is left to the caller.
+    pub async fn get_slsa(&self, digest: &str) -> Result<Option<Provenance>, anyhow::Error> {
+        let response = self
+            .resilience
+            .call("get_slsa", || {
+                self.query(
+                    "get_slsa",
+                    HAS_SLSA_QUERY,
+                    serde_json::json!({
+                        "filter": {
+                            "subject": { "algorithm": "sha256", "digest": digest },
+                        },
+                    }),
+                )
+            })
+            .await
+            .map_err(|e| Self::failed("Error getting SLSA attestations from GUAC", e))?;
+
+        Ok(provenance_of(response.pointer("/data/HasSLSA")))
+    }
+
+    /// The declared and discovered licenses of a package version.
+    pub async fn get_licenses(&self, purl: &PackageUrl<'_>) -> Result<Vec<License>, anyhow::Error> {
+        let subject = serde_json::json!({
+            "package": {
+                "type": purl.ty(),
+                "namespace": purl.namespace(),
+                "name": purl.name(),
+                "version": purl.version(),
+            },
+        });
+        let response = self
+            .resilience
+            .call("get_licenses", || {
+                self.query(
+                    "get_licenses",
+                    CERTIFY_LEGAL_QUERY,
+                    serde_json::json!({ "subject": subject }),
+                )
+            })
+            .await
+            .map_err(|e| Self::failed("Error getting licenses from GUAC", e))?;
+
+        Ok(licenses_of(response.pointer("/data/CertifyLegal")))
+    }
+
+    /// The source repository of a package version (e.g. `github.com/owner/repo`).
+    pub async fn get_source_repo(
+        &self,
+        purl: &PackageUrl<'_>,
+    ) -> Result<Option<String>, anyhow::Error> {
+        let package = serde_json::json!({
+            "type": purl.ty(),
+            "namespace": purl.namespace(),
+            "name": purl.name(),
+            "version": purl.version(),
+        });
+        let response = self
+            .resilience
+            .call("get_source_repo", || {
+                self.query(
+                    "get_source_repo",
+                    HAS_SOURCE_AT_QUERY,
+                    serde_json::json!({ "package": package }),
+                )
+            })
+            .await
+            .map_err(|e| Self::failed("Error getting source repository from GUAC", e))?;
+
+        let namespace = response.pointer("/data/HasSourceAt/0/source/namespaces/0");
+        Ok(namespace.and_then(|namespace| {
+            Some(format!(
+                "{}/{}",
+                namespace.get("namespace")?.as_str()?,
+                namespace.pointer("/names/0/name")?.as_str()?
+            ))
+        }))
+    }
+
+    /// The packages recorded as equal to a package version, e.g. its rebuilds.
+    pub async fn get_equal_packages(
+        &self,
+        purl: &PackageUrl<'_>,
+    ) -> Result<Vec<String>, anyhow::Error> {
+        let package = serde_json::json!({
+            "type": purl.ty(),
+            "namespace": purl.namespace(),
+            "name": purl.name(),
+            "version": purl.version(),
+        });
+        let response = self
+            .resilience
+            .call("get_equal_packages", || {
+                self.query(
+                    "get_equal_packages",
+                    PKG_EQUAL_QUERY,
+                    serde_json::json!({ "package": package }),
+                )
+            })
+            .await
+            .map_err(|e| Self::failed("Error getting equal packages from GUAC", e))?;
+
+        let own = purl.to_string();
+        let mut equal = Vec::new();
+        for package in response
+            .pointer("/data/PkgEqual")
+            .and_then(|e| e.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|e| e.get("packages")?.as_array())
+            .flatten()
+        {
+            for purl in purls_of(package) {
+                if purl != own && !equal.contains(&purl) {
+                    equal.push(purl);
+                }
+            }
+        }
+        Ok(equal)
+    }
+
+    /// Summarize packages, with the SHA-256 digests of their artifacts if known, in their order.
+    ///
+    /// Instead of a query for each kind of data and package, the queries of up to
+    /// `guac.batch_size` packages are sent as a single document, each under its own alias.
+    /// Packages recently found to be unknown to Guac are summarized as empty without querying.
+    pub async fn get_summaries(
+        &self,
+        packages: &[(PackageUrl<'_>, Option<String>)],
+    ) -> Vec<Result<Summary, anyhow::Error>> {
+        let mut summaries: Vec<Result<Summary, anyhow::Error>> =
+            packages.iter().map(|_| Ok(Summary::default())).collect();
+        let queried: Vec<usize> = (0..packages.len())
+            .filter(|i| !self.is_unknown(&packages[*i].0.to_string()))
+            .collect();
+        for chunk in queried.chunks(self.batch_size) {
+            let batch: Vec<_> = chunk.iter().map(|i| packages[*i].clone()).collect();
+            match self.summarize(&batch).await {
+                Ok(summarized) => {
+                    for (i, summary) in chunk.iter().zip(summarized) {
+                        summaries[*i] = Ok(summary);
+                    }
+                }
+                Err(e) => {
+                    for i in chunk {
+                        summaries[*i] = Err(copy_error(&e));
+                    }
+                }
+            }
+        }
+        summaries
+    }
+
+    async fn summarize(
+        &self,
+        packages: &[(PackageUrl<'_>, Option<String>)],
+    ) -> Result<Vec<Summary>, anyhow::Error> {
+        let (document, variables) = summaries_query(packages);
+        let response = self
+            .resilience
+            .call("get_summaries", || {
+                self.query("get_summaries", &document, variables.clone())
+            })
+            .await
+            .map_err(|e| Self::failed("Error getting package summaries from GUAC", e))?;
+
+        let fetched = Some(Utc::now());
+        let mut summaries = Vec::new();
+        for (i, (purl, _)) in packages.iter().enumerate() {
+            let (versions, mut summary) = summary_of(&response, i, fetched);
+            // only packages Guac answered for without any versions are unknown
+            if versions.as_ref().map_or(false, Vec::is_empty) {
+                self.remember_unknown(&purl.to_string());
+            }
+            summary.packages = self.package_refs(&versions.unwrap_or_default()).await;
+            summaries.push(summary);
+        }
+        Ok(summaries)
+    }
+
+    /// The ranges of versions a package version declares as dependencies, by the names of the
+    /// dependencies (see [`name_of`]).
+    async fn get_version_ranges(
+        &self,
+        purl: &str,
+    ) -> Result<HashMap<String, String>, anyhow::Error> {
+        let purl = PackageUrl::from_str(purl)?;
+        let package = serde_json::json!({
+            "type": purl.ty(),
+            "namespace": purl.namespace(),
+            "name": purl.name(),
+            "version": purl.version(),
+        });
+        let response = self
+            .resilience
+            .call("get_version_ranges", || {
+                self.query(
+                    "get_version_ranges",
+                    IS_DEPENDENCY_QUERY,
+                    serde_json::json!({ "package": package }),
+                )
+            })
+            .await
+            .map_err(|e| Self::failed("Error getting version ranges from GUAC", e))?;
+
+        let mut ranges = HashMap::new();
+        for dependency in response
+            .pointer("/data/IsDependency")
+            .and_then(|d| d.as_array())
+            .into_iter()
+            .flatten()
+        {
+            let range = match dependency.get("versionRange").and_then(|r| r.as_str()) {
+                Some(range) if !range.is_empty() => range,
+                _ => continue,
+            };
+            let ty = dependency
+                .pointer("/dependentPackage/type")
+                .and_then(|t| t.as_str())
+                .unwrap_or_default();
+            for namespace in dependency
+                .pointer("/dependentPackage/namespaces")
+                .and_then(|n| n.as_array())
+                .into_iter()
+                .flatten()
+            {
+                let ns = namespace
+                    .get("namespace")
+                    .and_then(|n| n.as_str())
+                    .unwrap_or_default();
+                for name in namespace
+                    .get("names")
+                    .and_then(|n| n.as_array())
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|n| n.get("name")?.as_str())
+                {
+                    ranges.insert(format!("{}/{}/{}", ty, ns, name), range.to_string());
+                }
+            }
+        }
+        Ok(ranges)
+    }
+
+    /// Send a GraphQL query, recording the sizes of its request and response.
+    ///
+    /// Fails if the response is not successful, or reports any `errors` next to its `data`, see
+    /// [`graphql_result`].
+    async fn query(
+        &self,
+        operation: &str,
+        query: &str,
+        variables: serde_json::Value,
+    ) -> Result<serde_json::Value, anyhow::Error> {
+        let body = serde_json::to_vec(&serde_json::json!({
+            "query": query,
+            "variables": variables,
+        }))?;
+        metrics::UPSTREAM_REQUEST_SIZE
+            .with_label_values(&["guac", operation])
+            .observe(body.len() as f64);
+        let response = self
+            .http
+            .post(&self.url)
+            .headers(crate::request_id::headers())
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        metrics::UPSTREAM_RESPONSE_SIZE
+            .with_label_values(&["guac", operation])
+            .observe(response.len() as f64);
+        graphql_result(serde_json::from_slice(&response)?)
+    }
+
+    /// The packages affected by a vulnerability, and its details from the security data, `None`
+    /// if neither knows the vulnerability.
+    pub async fn get_vulnerability(
+        &self,
+        cve_id: &str,
+    ) -> Result<Option<Vulnerability>, anyhow::Error> {
         log::info!("Lookup cve {}", cve_id);
-        let vulns = self.client.get_vulnerabilities(cve_id).await.map_err(|e| {
-            let e = format!("Error getting vulnerabilities from GUAC: {:?}", e);
-            log::warn!("{}", e);
-            anyhow!(e)
-        })?;
+        let vulnerability = if cve_id.to_lowercase().starts_with("cve-") {
+            serde_json::json!({ "cve": { "cveId": cve_id } })
+        } else {
+            serde_json::json!({ "osv": { "osvId": cve_id } })
+        };
+        let response = self
+            .resilience
+            .call("get_vulnerabilities", || {
+                self.query(
+                    "get_vulnerabilities",
+                    VULNERABLE_PACKAGES_QUERY,
+                    serde_json::json!({ "vulnerability": vulnerability }),
+                )
+            })
+            .await
+            .map_err(|e| Self::failed("Error getting vulnerabilities from GUAC", e))?;
+        let mut vulnerable = Vec::new();
+        for certified in response
+            .pointer("/data/CertifyVuln")
+            .and_then(|c| c.as_array())
+            .into_iter()
+            .flatten()
+        {
+            if let Some(package) = certified.get("package") {
+                vulnerable.extend(purls_of(package));
+            }
+        }
 
+        let fetched = Some(Utc::now());
         let mut packages = Vec::new();
-        for vuln in vulns.iter() {
-            for purl in vuln.packages.iter() {
-                let p = PackageRef {
-                    purl: purl.clone(),
-                    href: format!("/api/package?purl={}", &urlencoding::encode(&purl)),
-                    trusted: Some(self.is_trusted(&purl)),
-                    sbom: if self.sbom.exists(&purl) {
-                        Some(format!(
-                            "/api/package/sbom?purl={}",
-                            &urlencoding::encode(&purl)
-                        ))
-                    } else {
-                        None
-                    },
-                };
-                packages.push(p);
-            }
+        for purl in vulnerable.iter() {
+            let p = PackageRef {
+                purl: purl.clone(),
+                href: format!("/api/v1/package?purl={}", &urlencoding::encode(&purl)),
+                trusted: Some(self.is_trusted(&purl).await),
+                unverified: false,
+                sbom: if self.sbom.exists(&purl) {
+                    Some(format!(
+                        "/api/v1/package/sbom?purl={}",
+                        &urlencoding::encode(&purl)
+                    ))
+                } else {
+                    None
+                },
+                vulnerabilities: None,
+                published: None,
+                fetched,
+                relation: None,
+                dependency: None,
+                degraded: vec![],
+            };
+            packages.push(p);
         }
 
         // Fetch CVE details to get summary for this vulnerability.
         let hydra = format!(
-            "https://access.redhat.com/hydra/rest/securitydata/cve/{}.json",
-            cve_id.to_ascii_uppercase()
+            "{}/cve/{}.json",
+            self.security_data_url,
+            urlencoding::encode(&cve_id.to_ascii_uppercase())
         );
-        let response = reqwest::get(hydra).await;
+        let response = match crate::chaos::inject("security_data").await {
+            Ok(()) => self
+                .security_data
+                .get(hydra)
+                .headers(crate::request_id::headers())
+                .send()
+                .await
+                .map_err(anyhow::Error::from),
+            Err(e) => Err(e.into()),
+        };
         let mut summary = "Unavailable".to_string();
         let mut severity = None;
         let mut cvss3 = None;
         let mut date = None;
+        let mut unknown = false;
         if let Ok(response) = response {
+            unknown = response.status() == StatusCode::NOT_FOUND;
             if response.status() == StatusCode::OK {
                 if let Ok(data) = response.json::<serde_json::Value>().await {
                     if let Some(Some(details)) = data.get("details").map(|s| s.as_array()) {
@@ -141,7 +759,10 @@ impl Guac {
             }
         }
 
-        Ok(Vulnerability {
+        if unknown && packages.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Vulnerability {
             cve: cve_id.to_string(),
             summary,
             severity,
@@ -153,88 +774,221 @@ impl Guac {
                 cve_id.to_lowercase()
             ),
             packages,
-        })
+        }))
     }
 
     pub async fn get_vulnerabilities(
         &self,
         purl: &str,
     ) -> Result<Vec<VulnerabilityRef>, anyhow::Error> {
-        let vulns = self.client.certify_vuln(purl).await.map_err(|e| {
-            let e = format!("Error getting vulnerabilities from GUAC: {:?}", e);
-            log::warn!("{}", e);
-            anyhow!(e)
+        let package = PackageUrl::from_str(purl).map(|purl| {
+            serde_json::json!({
+                "type": purl.ty(),
+                "namespace": purl.namespace(),
+                "name": purl.name(),
+                "version": purl.version(),
+            })
         })?;
+        let response = self
+            .resilience
+            .call("certify_vuln", || {
+                self.query(
+                    "certify_vuln",
+                    CERTIFY_VULN_QUERY,
+                    serde_json::json!({ "package": package }),
+                )
+            })
+            .await
+            .map_err(|e| Self::failed("Error getting vulnerabilities from GUAC", e))?;
 
-        let mut ret = Vec::new();
-        for vuln in vulns.iter() {
-            match (&vuln.cve, &vuln.osv) {
-                (None, Some(osv)) => {
-                    let id = osv.clone();
-                    let vuln_ref = VulnerabilityRef {
-                        cve: id.clone(),
-                        href: format!(
-                            "{}/{}",
-                            "https://osv.dev/vulnerability",
-                            id.replace("ghsa", "GHSA")
-                        ), //TODO fix guac id format
-                    };
-                    //TODO fix guac repeated entries
-                    if !ret.contains(&vuln_ref) {
-                        ret.push(vuln_ref);
+        Ok(vulnerabilities_of(
+            response.pointer("/data/CertifyVuln"),
+            Some(Utc::now()),
+        ))
+    }
+
+    /// The dependencies of a package, up to `depth` levels deep.
+    pub async fn get_dependencies(
+        &self,
+        purl: &str,
+        depth: usize,
+    ) -> Result<PackageDependencies, anyhow::Error> {
+        self.walk(Direction::Dependencies, purl, depth).await
+    }
+
+    /// The dependents of a package, up to `depth` levels deep.
+    pub async fn get_dependents(
+        &self,
+        purl: &str,
+        depth: usize,
+    ) -> Result<PackageDependencies, anyhow::Error> {
+        self.walk(Direction::Dependents, purl, depth).await
+    }
+
+    /// Walk the graph breadth first, returning each package reached once.
+    async fn walk(
+        &self,
+        direction: Direction,
+        purl: &str,
+        depth: usize,
+    ) -> Result<PackageDependencies, anyhow::Error> {
+        if let Some(subgraph) = self.subgraphs.get(direction, depth, purl).await {
+            return Ok(subgraph);
+        }
+
+        let mut seen = HashSet::from([purl.to_string()]);
+        let mut found = Vec::new();
+        let mut frontier = vec![purl.to_string()];
+        for level in 0..depth {
+            let mut next = Vec::new();
+            for purl in frontier.iter() {
+                let step = match direction {
+                    Direction::Dependencies => self.direct_dependencies(purl, level == 0).await?,
+                    Direction::Dependents => self.direct_dependents(purl).await?,
+                };
+                for mut p in step.0 {
+                    // the edges of indirect dependencies start at other packages
+                    if level > 0 {
+                        p.dependency = None;
                     }
-                }
-                (Some(cve_id), None) => {
-                    let vuln_ref = VulnerabilityRef {
-                        cve: cve_id.clone(),
-                        href: format!(
-                            "https://access.redhat.com/security/cve/{}",
-                            cve_id.to_lowercase()
-                        ), //TODO fix guac id format
-                    };
-                    //TODO fix guac repeated entries
-                    if !ret.contains(&vuln_ref) {
-                        ret.push(vuln_ref);
+                    if seen.insert(p.purl.clone()) {
+                        next.push(p.purl.clone());
+                        found.push(p);
                     }
                 }
-                _ => {}
-            };
+            }
+            if next.is_empty() {
+                break;
+            }
+            frontier = next;
         }
-        Ok(ret)
+
+        let subgraph = PackageDependencies(found);
+        self.subgraphs.put(direction, depth, purl, &subgraph).await;
+        Ok(subgraph)
     }
 
-    pub async fn get_dependencies(&self, purl: &str) -> Result<PackageDependencies, anyhow::Error> {
-        let deps = self.client.get_dependencies(purl).await.map_err(|e| {
-            let e = format!("Error getting dependencies from GUAC: {:?}", e);
-            log::warn!("{}", e);
-            anyhow!(e)
+    /// The shortest paths from a package to the packages matching `is_target`, reached within
+    /// `depth` levels, at most [`MAX_PATHS`] to each of them.
+    ///
+    /// Each path starts with `from` and ends with the target. Unless `first` is set, the walk
+    /// continues beyond targets, which may be reached through other targets, otherwise it stops
+    /// with the level the first one is reached on. At most [`MAX_PATH_NODES`] packages are
+    /// visited, the paths to the targets found until then are returned.
+    pub async fn paths<F>(
+        &self,
+        direction: Direction,
+        from: &str,
+        depth: usize,
+        first: bool,
+        is_target: F,
+    ) -> Result<BTreeMap<String, Vec<Vec<String>>>, anyhow::Error>
+    where
+        F: Fn(&str) -> bool,
+    {
+        shortest_paths(from, depth, first, is_target, |purl| async move {
+            let step = match direction {
+                Direction::Dependencies => self.direct_dependencies(&purl, false).await?,
+                Direction::Dependents => self.direct_dependents(&purl).await?,
+            };
+            Ok(step.0.into_iter().map(|p| p.purl).collect())
+        })
+        .await
+    }
+
+    /// The direct dependencies of a package, with the `dependency` edges from its SBOM and the
+    /// version ranges reported by Guac if `edges` is set.
+    async fn direct_dependencies(
+        &self,
+        purl: &str,
+        edges: bool,
+    ) -> Result<PackageDependencies, anyhow::Error> {
+        let package = PackageUrl::from_str(purl).map(|purl| {
+            serde_json::json!({
+                "type": purl.ty(),
+                "namespace": purl.namespace(),
+                "name": purl.name(),
+                "version": purl.version(),
+            })
         })?;
+        let response = self
+            .resilience
+            .call("get_dependencies", || {
+                self.query(
+                    "get_dependencies",
+                    DEPENDENCIES_QUERY,
+                    serde_json::json!({ "package": package }),
+                )
+            })
+            .await
+            .map_err(|e| Self::failed("Error getting dependencies from GUAC", e))?;
+        let deps = related_of(response.pointer("/data/IsDependency"), "dependentPackage");
+
+        let (edges, ranges) = match edges {
+            true => (
+                self.sbom
+                    .lookup(purl)
+                    .map(|sbom| crate::sbom::dependency_edges(&sbom))
+                    .unwrap_or_default(),
+                // the version ranges are left out if Guac fails to report them
+                self.get_version_ranges(purl).await.unwrap_or_else(|e| {
+                    log::warn!("Error getting version ranges of {}: {:?}", purl, e);
+                    HashMap::new()
+                }),
+            ),
+            false => (HashMap::new(), HashMap::new()),
+        };
 
+        let fetched = Some(Utc::now());
         let mut ret = Vec::new();
+        // Guac reports a package once for each edge to it, e.g. once per ingested SBOM
+        let mut seen = HashSet::new();
         for purl in deps.iter() {
+            if !seen.insert(purl.as_str()) {
+                continue;
+            }
+            let mut dependency = edges.get(purl).cloned().unwrap_or_default();
+            if let Some(range) = name_of(purl).and_then(|name| ranges.get(&name)) {
+                dependency.version_range = Some(range.clone());
+            }
             let p = PackageRef {
                 purl: purl.clone(),
-                href: format!("/api/package?purl={}", &urlencoding::encode(&purl)),
-                trusted: Some(self.is_trusted(&purl)),
+                href: format!("/api/v1/package?purl={}", &urlencoding::encode(&purl)),
+                trusted: Some(self.is_trusted(&purl).await),
+                unverified: false,
                 sbom: if self.sbom.exists(&purl) {
                     Some(format!(
-                        "/api/package/sbom?purl={}",
+                        "/api/v1/package/sbom?purl={}",
                         &urlencoding::encode(&purl)
                     ))
                 } else {
                     None
                 },
+                vulnerabilities: None,
+                published: None,
+                fetched,
+                relation: None,
+                dependency: (dependency != DependencyEdge::default()).then_some(dependency),
+                degraded: vec![],
             };
-            //TODO fix guac repeated entries
-            if !ret.contains(&p) {
-                ret.push(p);
-            }
+            ret.push(p);
         }
         Ok(PackageDependencies(ret))
     }
 
     pub async fn get_all_packages(&self) -> Result<Vec<Package>, anyhow::Error> {
-        let all_packages = self.client.get_all_packages().await?;
+        let response = self
+            .resilience
+            .call("get_all_packages", || {
+                self.query(
+                    "get_all_packages",
+                    PACKAGES_QUERY,
+                    serde_json::json!({ "name": {} }),
+                )
+            })
+            .await
+            .map_err(|e| Self::failed("Error getting all packages from GUAC", e))?;
+        let all_packages = packages_of(response.pointer("/data/packages"));
 
         let mut all = Vec::new();
         for purl in all_packages.iter() {
@@ -242,16 +996,26 @@ impl Guac {
             let p = Package {
                 purl: Some(purl.to_string()),
                 href: Some(format!(
-                    "/api/package?purl={}",
+                    "/api/v1/package?purl={}",
                     &urlencoding::encode(&purl.to_string())
                 )),
-                trusted: Some(self.is_trusted(&purl)),
+                trusted: Some(self.is_trusted(&purl).await),
                 trusted_versions: vec![],
                 snyk: None,
+                signature_verified: None,
+                signer: None,
+                provenance: None,
+                certifications: vec![],
+                scorecard: None,
+                licenses: vec![],
+                degraded: vec![],
+                warnings: vec![],
+                unknown: false,
                 vulnerabilities: vulns,
+                sbom_fetched: self.sbom.indexed(&purl),
                 sbom: if self.sbom.exists(&purl) {
                     Some(format!(
-                        "/api/package/sbom?purl={}",
+                        "/api/v1/package/sbom?purl={}",
                         &urlencoding::encode(&purl)
                     ))
                 } else {
@@ -263,30 +1027,807 @@ impl Guac {
         Ok(all)
     }
 
-    pub async fn get_dependents(&self, purl: &str) -> Result<PackageDependencies, anyhow::Error> {
-        let deps = self.client.is_dependent(purl).await.map_err(|e| {
-            let e = format!("Error getting dependents from GUAC: {:?}", e);
-            log::warn!("{}", e);
-            anyhow!(e)
+    async fn direct_dependents(&self, purl: &str) -> Result<PackageDependencies, anyhow::Error> {
+        let name = PackageUrl::from_str(purl).map(|purl| {
+            serde_json::json!({
+                "type": purl.ty(),
+                "namespace": purl.namespace(),
+                "name": purl.name(),
+            })
         })?;
+        let response = self
+            .resilience
+            .call("is_dependent", || {
+                self.query(
+                    "is_dependent",
+                    DEPENDENTS_QUERY,
+                    serde_json::json!({ "name": name }),
+                )
+            })
+            .await
+            .map_err(|e| Self::failed("Error getting dependents from GUAC", e))?;
+        let deps = related_of(response.pointer("/data/IsDependency"), "package");
 
+        let fetched = Some(Utc::now());
         let mut ret = Vec::new();
         for purl in deps.iter() {
             let p = PackageRef {
                 purl: purl.clone(),
-                href: format!("/api/package?purl={}", &urlencoding::encode(&purl)),
-                trusted: Some(self.is_trusted(&purl)),
+                href: format!("/api/v1/package?purl={}", &urlencoding::encode(&purl)),
+                trusted: Some(self.is_trusted(&purl).await),
+                unverified: false,
                 sbom: if self.sbom.exists(&purl) {
                     Some(format!(
-                        "/api/package/sbom?purl={}",
+                        "/api/v1/package/sbom?purl={}",
                         &urlencoding::encode(&purl)
                     ))
                 } else {
                     None
                 },
+                vulnerabilities: None,
+                published: None,
+                fetched,
+                relation: None,
+                dependency: None,
+                degraded: vec![],
             };
             ret.push(p);
         }
         Ok(PackageDependencies(ret))
     }
 }
+
+/// The document and variables of the query summarizing packages, each package's queries under
+/// aliases with its index (e.g. `packages0`, `vulnerabilities0`).
+fn summaries_query(packages: &[(PackageUrl<'_>, Option<String>)]) -> (String, serde_json::Value) {
+    let mut parameters = Vec::new();
+    let mut fields = String::new();
+    let mut variables = serde_json::Map::new();
+    for (i, (purl, digest)) in packages.iter().enumerate() {
+        let name = serde_json::json!({
+            "type": purl.ty(),
+            "namespace": purl.namespace(),
+            "name": purl.name(),
+        });
+        let package = serde_json::json!({
+            "type": purl.ty(),
+            "namespace": purl.namespace(),
+            "name": purl.name(),
+            "version": purl.version(),
+        });
+        let subject = serde_json::json!({ "package": package });
+        parameters.push(format!(
+            "$name{i}: PkgSpec!, $package{i}: PkgSpec!, \
+             $subject{i}: PackageSourceOrArtifactSpec!, $legal{i}: PackageOrSourceSpec!",
+            i = i
+        ));
+        fields.push_str(&format!(
+            "
+  packages{i}: packages(pkgSpec: $name{i}) {{ {package} }}
+  vulnerabilities{i}: CertifyVuln(certifyVulnSpec: {{ package: $package{i} }}) {{ {vulnerability} }}
+  good{i}: CertifyGood(certifyGoodSpec: {{ subject: $subject{i} }}) {{ justification origin }}
+  bad{i}: CertifyBad(certifyBadSpec: {{ subject: $subject{i} }}) {{ justification origin }}
+  legal{i}: CertifyLegal(certifyLegalSpec: {{ subject: $legal{i} }}) {{ declaredLicense discoveredLicense }}",
+            i = i,
+            package = PACKAGE_FIELDS,
+            vulnerability = VULNERABILITY_FIELDS,
+        ));
+        variables.insert(format!("name{}", i), name);
+        variables.insert(format!("package{}", i), package);
+        variables.insert(format!("subject{}", i), subject.clone());
+        variables.insert(format!("legal{}", i), subject);
+        if let Some(digest) = digest {
+            parameters.push(format!("$slsa{}: HasSLSASpec!", i));
+            fields.push_str(&format!(
+                "
+  slsa{i}: HasSLSA(hasSLSASpec: $slsa{i}) {{ slsa {{ builtBy {{ uri }} buildType }} }}",
+                i = i
+            ));
+            variables.insert(
+                format!("slsa{}", i),
+                serde_json::json!({
+                    "subject": { "algorithm": "sha256", "digest": digest },
+                }),
+            );
+        }
+    }
+    let document = format!(
+        "query Summaries({}) {{{}\n}}",
+        parameters.join(", "),
+        fields
+    );
+    (document, serde_json::Value::Object(variables))
+}
+
+/// The summary of the package with an index from the response of [`summaries_query`], without
+/// its versions, which are returned as purls. `None` if the response has no versions of it.
+fn summary_of(
+    response: &serde_json::Value,
+    i: usize,
+    fetched: Option<DateTime<Utc>>,
+) -> (Option<Vec<String>>, Summary) {
+    let data = |alias: &str| response.pointer(&format!("/data/{}{}", alias, i));
+
+    let versions = data("packages")
+        .and_then(|p| p.as_array())
+        .map(|packages| packages.iter().flat_map(purls_of).collect::<Vec<String>>());
+    let summary = Summary {
+        packages: Vec::new(),
+        vulnerabilities: vulnerabilities_of(data("vulnerabilities"), fetched),
+        certifications: certifications_of(data("good"), data("bad")),
+        licenses: licenses_of(data("legal")),
+        provenance: provenance_of(data("slsa")),
+    };
+    (versions, summary)
+}
+
+/// The result of a GraphQL response, failing if it reports any errors.
+///
+/// Guac answers with `200` even if the query failed, e.g. part of a batch, the `data` of such a
+/// response is incomplete and must not be taken as the packages being unknown.
+fn graphql_result(response: serde_json::Value) -> Result<serde_json::Value, anyhow::Error> {
+    let errors: Vec<String> = response
+        .get("errors")
+        .and_then(|e| e.as_array())
+        .into_iter()
+        .flatten()
+        .map(|e| {
+            e.get("message")
+                .and_then(|m| m.as_str())
+                .map_or_else(|| e.to_string(), ToString::to_string)
+        })
+        .collect();
+    if !errors.is_empty() {
+        return Err(anyhow!("Guac failed the query: {}", errors.join("; ")));
+    }
+    Ok(response)
+}
+
+/// Walk the graph breadth first from `from`, with `step` giving the packages next to one, for the
+/// shortest paths to the packages matching `is_target`, see [`GuacClient::paths`].
+async fn shortest_paths<F, S, R>(
+    from: &str,
+    depth: usize,
+    first: bool,
+    is_target: F,
+    step: S,
+) -> Result<BTreeMap<String, Vec<Vec<String>>>, anyhow::Error>
+where
+    F: Fn(&str) -> bool,
+    S: Fn(String) -> R,
+    R: std::future::Future<Output = Result<Vec<String>, anyhow::Error>>,
+{
+    // the packages each package is reached from on the shortest paths to it
+    let mut parents: HashMap<String, Vec<String>> = HashMap::new();
+    let mut levels = HashMap::from([(from.to_string(), 0)]);
+    let mut targets = Vec::new();
+    let mut frontier = vec![from.to_string()];
+    'walk: for level in 1..=depth {
+        let mut next = Vec::new();
+        for purl in frontier.iter() {
+            if levels.len() >= MAX_PATH_NODES {
+                log::debug!(
+                    "Stopping the search for paths from {} after {} packages",
+                    from,
+                    levels.len()
+                );
+                break 'walk;
+            }
+            for p in step(purl.clone()).await? {
+                match levels.get(&p) {
+                    Some(l) if *l == level => {
+                        let parents = parents.entry(p).or_default();
+                        if !parents.contains(purl) {
+                            parents.push(purl.clone());
+                        }
+                    }
+                    Some(_) => {}
+                    None => {
+                        levels.insert(p.clone(), level);
+                        parents.insert(p.clone(), vec![purl.clone()]);
+                        if is_target(&p) {
+                            targets.push(p.clone());
+                        }
+                        next.push(p);
+                    }
+                }
+            }
+        }
+        // the other shortest paths to a target are all on the level it was reached on
+        if next.is_empty() || (first && !targets.is_empty()) {
+            break;
+        }
+        frontier = next;
+    }
+
+    Ok(targets
+        .into_iter()
+        .map(|target| {
+            let paths = paths_to(&parents, from, &target);
+            (target, paths)
+        })
+        .collect())
+}
+
+/// Enumerate the paths from `from` to `to`, following the parents recorded by a walk.
+fn paths_to(parents: &HashMap<String, Vec<String>>, from: &str, to: &str) -> Vec<Vec<String>> {
+    if to == from {
+        return vec![vec![from.to_string()]];
+    }
+    let mut paths = Vec::new();
+    for parent in parents.get(to).into_iter().flatten() {
+        for mut path in paths_to(parents, from, parent) {
+            path.push(to.to_string());
+            paths.push(path);
+        }
+        if paths.len() >= MAX_PATHS {
+            paths.truncate(MAX_PATHS);
+            break;
+        }
+    }
+    paths
+}
+
+/// A copy of an error for each of the packages of a failed query, keeping if Guac was unavailable.
+fn copy_error(error: &anyhow::Error) -> anyhow::Error {
+    if error.is::<Unavailable>() {
+        anyhow::Error::new(Unavailable {
+            upstream: "guac",
+            reason: error.to_string(),
+        })
+    } else {
+        anyhow!(error.to_string())
+    }
+}
+
+/// Add a vulnerability reported by Guac by its CVE or OSV id, unless it was already reported.
+///
+/// Guac lowercases ids, they are kept as reported, as ids are compared ignoring their case when
+/// merging the vulnerabilities of the providers. Links use the spelling of the linked site. The
+/// id reported as the one of Guac is the vulnerability id, which Guac is queried by, rather than
+/// its node id.
+fn add_vulnerability(
+    found: &mut Vec<VulnerabilityRef>,
+    cve: Option<&str>,
+    osv: Option<&str>,
+    fetched: Option<DateTime<Utc>>,
+) {
+    let (id, href) = match (cve, osv) {
+        (None, Some(osv)) => (
+            osv,
+            format!("https://osv.dev/vulnerability/{}", osv_spelling(osv)),
+        ),
+        (Some(cve_id), None) => (
+            cve_id,
+            format!(
+                "https://access.redhat.com/security/cve/{}",
+                cve_id.to_lowercase()
+            ),
+        ),
+        _ => return,
+    };
+    // Guac reports a vulnerability once for each package version and scanner reporting it
+    if found.iter().any(|v| v.cve.eq_ignore_ascii_case(id)) {
+        return;
+    }
+    let vuln_ref = VulnerabilityRef {
+        cve: id.to_string(),
+        href: href.clone(),
+        ids: vec![ProviderId {
+            provider: "guac".to_string(),
+            id: id.to_string(),
+        }],
+        fetched,
+        aliases: vec![],
+        severity: None,
+        sources: vec![VulnerabilitySource {
+            provider: "guac".to_string(),
+            href,
+            severity: None,
+            fetched,
+        }],
+    };
+    found.push(vuln_ref);
+}
+
+/// An OSV id as spelled by OSV, the database prefix in uppercase, e.g. `GHSA-53mj-mc38-q894` for
+/// `ghsa-53mj-mc38-q894`.
+fn osv_spelling(id: &str) -> String {
+    match id.split_once('-') {
+        Some((database, rest)) => format!("{}-{}", database.to_ascii_uppercase(), rest),
+        None => id.to_string(),
+    }
+}
+
+/// The first SLSA attestation of `HasSLSA` results.
+fn provenance_of(attestations: Option<&serde_json::Value>) -> Option<Provenance> {
+    let slsa = attestations?.pointer("/0/slsa")?;
+    Some(Provenance {
+        builder_id: slsa.pointer("/builtBy/uri")?.as_str()?.to_string(),
+        build_type: slsa.get("buildType")?.as_str()?.to_string(),
+        slsa_level: None,
+    })
+}
+
+/// The certifications of `CertifyGood` and `CertifyBad` results.
+fn certifications_of(
+    good: Option<&serde_json::Value>,
+    bad: Option<&serde_json::Value>,
+) -> Vec<Certification> {
+    let certifications = |found: Option<&serde_json::Value>, verdict: Verdict| {
+        found
+            .and_then(|c| c.as_array())
+            .cloned()
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(move |c| {
+                Some(Certification {
+                    verdict,
+                    justification: c.get("justification")?.as_str()?.to_string(),
+                    origin: c.get("origin")?.as_str()?.to_string(),
+                })
+            })
+    };
+    certifications(good, Verdict::Good)
+        .chain(certifications(bad, Verdict::Bad))
+        .collect()
+}
+
+/// The distinct licenses of `CertifyLegal` results.
+fn licenses_of(legals: Option<&serde_json::Value>) -> Vec<License> {
+    let mut licenses: Vec<License> = Vec::new();
+    for legal in legals.and_then(|l| l.as_array()).into_iter().flatten() {
+        for field in ["declaredLicense", "discoveredLicense"] {
+            let expression = match legal.get(field).and_then(|l| l.as_str()) {
+                Some(expression) if !expression.is_empty() => expression,
+                _ => continue,
+            };
+            if !licenses.iter().any(|l| l.expression == expression) {
+                licenses.push(License {
+                    expression: expression.to_string(),
+                    source: "guac".to_string(),
+                });
+            }
+        }
+    }
+    licenses
+}
+
+/// The type, namespace and name of a purl, which Guac tracks dependencies on.
+fn name_of(purl: &str) -> Option<String> {
+    let purl = PackageUrl::from_str(purl).ok()?;
+    Some(format!(
+        "{}/{}/{}",
+        purl.ty(),
+        purl.namespace().unwrap_or_default(),
+        purl.name()
+    ))
+}
+
+fn unknown_key(purl: &str) -> String {
+    crate::purl::normalize(purl).unwrap_or_else(|| purl.to_string())
+}
+
+/// Purls found to be unknown to Guac, by their normalized form, each remembered for `ttl` and
+/// the oldest forgotten first once there are more than `capacity`.
+struct UnknownPurls {
+    ttl: Duration,
+    capacity: usize,
+    /// When each purl was found to be unknown
+    found: HashMap<String, Instant>,
+    /// The purls in the order they were found, including those found again or forgotten since,
+    /// which no longer match the time in `found`
+    order: VecDeque<(String, Instant)>,
+}
+
+impl UnknownPurls {
+    fn new(ttl: Duration, capacity: usize) -> Self {
+        Self {
+            ttl,
+            capacity,
+            found: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    fn contains(&self, key: &str) -> bool {
+        self.found
+            .get(key)
+            .map_or(false, |found| found.elapsed() < self.ttl)
+    }
+
+    fn insert(&mut self, key: String, now: Instant) {
+        if self.ttl.is_zero() || self.capacity == 0 {
+            return;
+        }
+        self.found.insert(key.clone(), now);
+        self.order.push_back((key, now));
+        while let Some((key, found)) = self.order.front() {
+            if self.order.len() <= self.capacity && now.duration_since(*found) < self.ttl {
+                break;
+            }
+            if self.found.get(key) == Some(found) {
+                self.found.remove(key);
+            }
+            self.order.pop_front();
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        self.found.remove(key);
+    }
+}
+
+/// The vulnerabilities of `CertifyVuln` results, each once.
+fn vulnerabilities_of(
+    certified: Option<&serde_json::Value>,
+    fetched: Option<DateTime<Utc>>,
+) -> Vec<VulnerabilityRef> {
+    let mut vulnerabilities = Vec::new();
+    for certified in certified.and_then(|v| v.as_array()).into_iter().flatten() {
+        let ids = |list: &str, field: &str| -> Vec<String> {
+            certified
+                .pointer(&format!("/vulnerability/{}", list))
+                .and_then(|l| l.as_array())
+                .into_iter()
+                .flatten()
+                .filter_map(|id| Some(id.get(field)?.as_str()?.to_string()))
+                .collect()
+        };
+        for cve in ids("cveIds", "cveId") {
+            add_vulnerability(&mut vulnerabilities, Some(&cve), None, fetched);
+        }
+        for osv in ids("osvIds", "osvId") {
+            add_vulnerability(&mut vulnerabilities, None, Some(&osv), fetched);
+        }
+    }
+    vulnerabilities
+}
+
+/// The purls of a list of packages in the trie form of Guac.
+fn packages_of(packages: Option<&serde_json::Value>) -> Vec<String> {
+    packages
+        .and_then(|p| p.as_array())
+        .into_iter()
+        .flatten()
+        .flat_map(purls_of)
+        .collect()
+}
+
+/// The purls of the packages under `field` of each result of a query, each once.
+fn related_of(results: Option<&serde_json::Value>, field: &str) -> Vec<String> {
+    let mut purls = Vec::new();
+    for package in results
+        .and_then(|r| r.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|r| r.get(field))
+    {
+        for purl in purls_of(package) {
+            if !purls.contains(&purl) {
+                purls.push(purl);
+            }
+        }
+    }
+    purls
+}
+
+/// The purls of a package in the trie form of Guac, names without `versions`, e.g. the dependent
+/// package of an `IsDependency`, as purls without a version.
+fn purls_of(package: &serde_json::Value) -> Vec<String> {
+    let str_of = |v: &serde_json::Value, field: &str| -> Option<String> {
+        v.get(field)?.as_str().map(ToString::to_string)
+    };
+    let list = |v: &serde_json::Value, field: &str| -> Vec<serde_json::Value> {
+        v.get(field)
+            .and_then(|l| l.as_array())
+            .cloned()
+            .unwrap_or_default()
+    };
+
+    let mut purls = Vec::new();
+    let ty = match str_of(package, "type") {
+        Some(ty) => ty,
+        None => return purls,
+    };
+    for namespace in list(package, "namespaces") {
+        let ns = str_of(&namespace, "namespace").unwrap_or_default();
+        for name in list(&namespace, "names") {
+            let name_str = match str_of(&name, "name") {
+                Some(name) => name,
+                None => continue,
+            };
+            let versions = match name.get("versions") {
+                Some(_) => list(&name, "versions"),
+                None => vec![serde_json::json!({})],
+            };
+            for version in versions {
+                let mut purl = match PackageUrl::new(ty.clone(), name_str.clone()) {
+                    Ok(purl) => purl,
+                    Err(_) => continue,
+                };
+                if !ns.is_empty() {
+                    purl.with_namespace(ns.clone());
+                }
+                let version_str = str_of(&version, "version").unwrap_or_default();
+                if !version_str.is_empty() {
+                    purl.with_version(version_str);
+                }
+                for qualifier in list(&version, "qualifiers").iter() {
+                    if let (Some(key), Some(value)) =
+                        (str_of(qualifier, "key"), str_of(qualifier, "value"))
+                    {
+                        // invalid keys are skipped
+                        let _ = purl.add_qualifier(key, value);
+                    }
+                }
+                purls.push(purl.to_string());
+            }
+        }
+    }
+    purls
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn unknown_purls_are_forgotten_once_expired_or_over_capacity() {
+        let mut unknown = UnknownPurls::new(Duration::from_secs(30), 2);
+        let later = Instant::now() + Duration::from_secs(60);
+        unknown.insert("a".to_string(), Instant::now());
+        // expired by the time something else is found
+        unknown.insert("b".to_string(), later);
+        assert!(!unknown.found.contains_key("a"));
+        assert_eq!(unknown.order.len(), 1);
+
+        unknown.insert("c".to_string(), later);
+        unknown.insert("d".to_string(), later);
+        assert!(!unknown.found.contains_key("b"));
+        assert!(unknown.found.contains_key("c"));
+        assert!(unknown.found.contains_key("d"));
+        assert!(unknown.found.len() <= 2 && unknown.order.len() <= 2);
+    }
+
+    #[test]
+    fn unknown_purls_found_again_are_kept() {
+        let mut unknown = UnknownPurls::new(Duration::from_secs(30), 2);
+        unknown.insert("a".to_string(), Instant::now());
+        unknown.insert("a".to_string(), Instant::now());
+        // the first entry for `a` is evicted, but it was found again since
+        unknown.insert("b".to_string(), Instant::now());
+        assert!(unknown.contains("a"));
+        assert!(unknown.contains("b"));
+
+        unknown.remove("a");
+        assert!(!unknown.contains("a"));
+    }
+
+    fn packages() -> Vec<(PackageUrl<'static>, Option<String>)> {
+        vec![
+            (
+                PackageUrl::from_str("pkg:maven/io.vertx/vertx-web@4.3.7").unwrap(),
+                None,
+            ),
+            (
+                PackageUrl::from_str("pkg:oci/ubi9@sha256:abc").unwrap(),
+                Some("abc".to_string()),
+            ),
+        ]
+    }
+
+    #[test]
+    fn vulnerabilities_link_to_the_spelling_of_the_linked_site() {
+        let mut found = Vec::new();
+        add_vulnerability(&mut found, None, Some("ghsa-53mj-mc38-q894"), None);
+        add_vulnerability(&mut found, None, Some("GHSA-53mj-mc38-q894"), None);
+        add_vulnerability(&mut found, Some("cve-2023-24815"), None, None);
+        assert_eq!(found.len(), 2);
+        assert_eq!(
+            found[0].href,
+            "https://osv.dev/vulnerability/GHSA-53mj-mc38-q894"
+        );
+        assert_eq!(found[0].cve, "ghsa-53mj-mc38-q894");
+        assert_eq!(
+            found[1].href,
+            "https://access.redhat.com/security/cve/cve-2023-24815"
+        );
+    }
+
+    #[test]
+    fn each_package_is_queried_under_its_own_aliases() {
+        let (document, variables) = summaries_query(&packages());
+        assert!(document.starts_with("query Summaries($name0: PkgSpec!, $package0: PkgSpec!"));
+        for alias in ["packages", "vulnerabilities", "good", "bad", "legal"] {
+            assert!(document.contains(&format!("{}0:", alias)), "{}", alias);
+            assert!(document.contains(&format!("{}1:", alias)), "{}", alias);
+        }
+        // the provenance is only queried for artifacts with a digest
+        assert!(!document.contains("slsa0:"));
+        assert!(document.contains("slsa1: HasSLSA(hasSLSASpec: $slsa1)"));
+        assert!(document.contains("$slsa1: HasSLSASpec!"));
+
+        assert_eq!(
+            variables["name0"],
+            json!({"type": "maven", "namespace": "io.vertx", "name": "vertx-web"})
+        );
+        assert_eq!(variables["package0"]["version"], "4.3.7");
+        assert_eq!(variables["subject1"]["package"]["name"], "ubi9");
+        assert_eq!(variables["slsa1"]["subject"]["digest"], "abc");
+        assert!(variables.get("slsa0").is_none());
+    }
+
+    #[test]
+    fn summaries_are_parsed_by_alias() {
+        let response = json!({
+            "data": {
+                "packages0": [{
+                    "type": "maven",
+                    "namespaces": [{"namespace": "io.vertx", "names": [{"name": "vertx-web", "versions": [
+                        {"version": "4.3.7", "qualifiers": []},
+                        {"version": "4.3.4.redhat-00007", "qualifiers": []},
+                    ]}]}]
+                }],
+                "vulnerabilities0": [
+                    {"vulnerability": {"__typename": "CVE", "cveIds": [{"cveId": "cve-2023-24815"}]}},
+                    {"vulnerability": {"__typename": "OSV", "osvIds": [{"osvId": "ghsa-53mj-mc38-q894"}]}},
+                    {"vulnerability": {"__typename": "CVE", "cveIds": [{"cveId": "cve-2023-24815"}]}},
+                ],
+                "good0": [{"justification": "reviewed", "origin": "ci"}],
+                "bad0": [],
+                "legal0": [{"declaredLicense": "Apache-2.0", "discoveredLicense": "Apache-2.0"}],
+                "packages1": [],
+                "slsa1": [{"slsa": {"builtBy": {"uri": "https://github.com/actions"}, "buildType": "generic"}}],
+            }
+        });
+
+        let (versions, summary) = summary_of(&response, 0, None);
+        assert_eq!(
+            versions.unwrap(),
+            [
+                "pkg:maven/io.vertx/vertx-web@4.3.7",
+                "pkg:maven/io.vertx/vertx-web@4.3.4.redhat-00007"
+            ]
+        );
+        let cves: Vec<_> = summary
+            .vulnerabilities
+            .iter()
+            .map(|v| v.cve.as_str())
+            .collect();
+        assert_eq!(cves, ["cve-2023-24815", "ghsa-53mj-mc38-q894"]);
+        assert_eq!(summary.certifications.len(), 1);
+        assert_eq!(summary.licenses.len(), 1);
+        assert!(summary.provenance.is_none());
+
+        let (versions, summary) = summary_of(&response, 1, None);
+        assert_eq!(versions, Some(vec![]));
+        assert_eq!(summary.provenance.unwrap().build_type, "generic");
+
+        // not answered, e.g. a package past the end of the batch
+        assert_eq!(summary_of(&response, 2, None).0, None);
+    }
+
+    #[test]
+    fn responses_with_errors_fail() {
+        let response = json!({
+            "data": {"packages0": null},
+            "errors": [{"message": "timeout querying the backend"}, {"path": ["legal0"]}]
+        });
+        let error = graphql_result(response).unwrap_err().to_string();
+        assert!(error.contains("timeout querying the backend"), "{}", error);
+        assert!(error.contains("legal0"), "{}", error);
+
+        let response = json!({"data": {"packages0": []}, "errors": []});
+        assert_eq!(graphql_result(response.clone()).unwrap(), response);
+    }
+
+    #[test]
+    fn dependencies_are_package_names() {
+        let results = json!([
+            {"dependentPackage": {"type": "maven", "namespaces": [
+                {"namespace": "io.vertx", "names": [{"name": "vertx-core"}, {"name": "vertx-web-common"}]}
+            ]}},
+            {"dependentPackage": {"type": "maven", "namespaces": [
+                {"namespace": "io.vertx", "names": [{"name": "vertx-core"}]}
+            ]}},
+        ]);
+        assert_eq!(
+            related_of(Some(&results), "dependentPackage"),
+            [
+                "pkg:maven/io.vertx/vertx-core",
+                "pkg:maven/io.vertx/vertx-web-common"
+            ]
+        );
+        // names with an empty list of versions have no packages
+        let package = json!({"type": "maven", "namespaces": [
+            {"namespace": "io.vertx", "names": [{"name": "vertx-core", "versions": []}]}
+        ]});
+        assert!(purls_of(&package).is_empty());
+    }
+
+    fn graph(edges: &[(&str, &str)]) -> HashMap<String, Vec<String>> {
+        let mut graph: HashMap<String, Vec<String>> = HashMap::new();
+        for (from, to) in edges {
+            graph
+                .entry(from.to_string())
+                .or_default()
+                .push(to.to_string());
+        }
+        graph
+    }
+
+    async fn search(
+        graph: &HashMap<String, Vec<String>>,
+        depth: usize,
+        first: bool,
+        target: &str,
+    ) -> (BTreeMap<String, Vec<Vec<String>>>, usize) {
+        let steps = std::sync::atomic::AtomicUsize::new(0);
+        let paths = shortest_paths(
+            "a",
+            depth,
+            first,
+            |p| p.starts_with(target),
+            |p| {
+                steps.fetch_add(1, Ordering::Relaxed);
+                let next = graph.get(&p).cloned().unwrap_or_default();
+                async move { Ok(next) }
+            },
+        )
+        .await
+        .unwrap();
+        (paths, steps.into_inner())
+    }
+
+    #[tokio::test]
+    async fn paths_are_the_shortest_ones() {
+        let graph = graph(&[
+            ("a", "b"),
+            ("a", "c"),
+            ("b", "t"),
+            ("c", "t"),
+            ("c", "d"),
+            ("d", "t2"),
+            ("t", "e"),
+        ]);
+        let (paths, _) = search(&graph, 5, false, "t").await;
+        assert_eq!(paths["t"], [vec!["a", "b", "t"], vec!["a", "c", "t"]]);
+        assert_eq!(paths["t2"], [vec!["a", "c", "d", "t2"]]);
+        // targets beyond the depth are not reached
+        let (paths, _) = search(&graph, 2, false, "t").await;
+        assert!(!paths.contains_key("t2"));
+    }
+
+    #[tokio::test]
+    async fn searches_for_the_first_target_stop_on_its_level() {
+        let graph = graph(&[
+            ("a", "b"),
+            ("a", "c"),
+            ("b", "t"),
+            ("c", "t"),
+            ("t", "d"),
+            ("d", "e"),
+        ]);
+        let (paths, steps) = search(&graph, 10, true, "t").await;
+        assert_eq!(paths["t"].len(), 2);
+        // a, then b and c, but nothing beyond the target
+        assert_eq!(steps, 3);
+    }
+
+    #[tokio::test]
+    async fn searches_visit_a_limited_number_of_packages() {
+        let edges: Vec<(String, String)> = (0..MAX_PATH_NODES * 2)
+            .map(|i| ("a".to_string(), format!("p{}", i)))
+            .chain([("p0".to_string(), "t".to_string())])
+            .collect();
+        let edges: Vec<(&str, &str)> = edges
+            .iter()
+            .map(|(from, to)| (from.as_str(), to.as_str()))
+            .collect();
+        let (paths, steps) = search(&graph(&edges), 10, false, "t").await;
+        assert!(paths.is_empty());
+        assert_eq!(steps, 1);
+    }
+}
@@ -0,0 +1,92 @@
+use actix_web::{get, web, web::ServiceConfig, HttpResponse};
+use futures::future::join_all;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+
+use crate::config::ShutdownConfig;
+
+pub(crate) fn configure() -> impl FnOnce(&mut ServiceConfig) {
+    |config: &mut ServiceConfig| {
+        config.service(live);
+        config.service(ready);
+    }
+}
+
+/// Tracks whether the server accepts new work.
+pub struct Health {
+    ready: AtomicBool,
+    stopping: watch::Sender<bool>,
+}
+
+impl Health {
+    pub fn new() -> Self {
+        Self {
+            ready: AtomicBool::new(true),
+            stopping: watch::channel(false).0,
+        }
+    }
+
+    /// Completes once the listeners should stop accepting connections.
+    pub async fn stopping(&self) {
+        let mut stopping = self.stopping.subscribe();
+        let _ = stopping.wait_for(|stopping| *stopping).await;
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Relaxed)
+    }
+
+    fn draining(&self) {
+        self.ready.store(false, Ordering::Relaxed);
+    }
+}
+
+#[get("/health/live")]
+pub async fn live() -> HttpResponse {
+    HttpResponse::Ok().finish()
+}
+
+#[get("/health/ready")]
+pub async fn ready(health: web::Data<Arc<Health>>) -> HttpResponse {
+    if health.is_ready() {
+        HttpResponse::Ok().finish()
+    } else {
+        HttpResponse::ServiceUnavailable().finish()
+    }
+}
+
+/// Wait for SIGTERM or SIGINT, then drain the servers.
+///
+/// Readiness fails first, giving load balancers time to stop routing new requests, before the
+/// servers stop accepting connections and wait for in-flight requests to complete.
+pub async fn drain_on_signal(
+    health: Arc<Health>,
+    config: ShutdownConfig,
+    servers: Vec<actix_web::dev::ServerHandle>,
+) -> Result<(), anyhow::Error> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    tokio::select! {
+        _ = terminate.recv() => log::info!("Received SIGTERM"),
+        _ = interrupt.recv() => log::info!("Received SIGINT"),
+    }
+
+    health.draining();
+    log::info!(
+        "Draining, waiting {}s before stopping listeners",
+        config.delay_secs
+    );
+    tokio::time::sleep(Duration::from_secs(config.delay_secs)).await;
+
+    log::info!(
+        "Stopping, waiting up to {}s for in-flight requests",
+        config.timeout_secs
+    );
+    health.stopping.send_replace(true);
+    join_all(servers.iter().map(|server| server.stop(true))).await;
+    Ok(())
+}
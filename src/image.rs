@@ -0,0 +1,992 @@
+use actix_web::{error, http::StatusCode, post, web, web::ServiceConfig, HttpResponse};
+use anyhow::anyhow;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use thiserror::Error;
+use utoipa::{OpenApi, ToSchema};
+
+use crate::analyze::{self, SbomAnalysis};
+use crate::outbound::OutboundError;
+use crate::package::{self, TrustedContent};
+
+pub(crate) fn configure() -> impl FnOnce(&mut ServiceConfig) {
+    |config: &mut ServiceConfig| {
+        config.service(analyze_image);
+    }
+}
+
+/// The documentation of the image endpoint.
+#[derive(OpenApi)]
+#[openapi(
+    paths(analyze_image),
+    components(schemas(
+        ImageRequest,
+        ImageAnalysis,
+        LayerComponents,
+        ArtifactFailure,
+        ApiError
+    ))
+)]
+pub(crate) struct ApiDoc;
+
+const DOCKER_HUB: &str = "docker.io";
+const DOCKER_HUB_HOST: &str = "registry-1.docker.io";
+
+const OCI_INDEX: &str = "application/vnd.oci.image.index.v1+json";
+const OCI_MANIFEST: &str = "application/vnd.oci.image.manifest.v1+json";
+const DOCKER_LIST: &str = "application/vnd.docker.distribution.manifest.list.v2+json";
+const DOCKER_MANIFEST: &str = "application/vnd.docker.distribution.manifest.v2+json";
+
+const CYCLONEDX: &str = "application/vnd.cyclonedx+json";
+const SPDX: &str = "application/spdx+json";
+const DSSE: &str = "application/vnd.dsse.envelope.v1+json";
+
+/// The prefix of the CycloneDX properties Syft records the layers of a component in.
+const SYFT_LOCATION: &str = "syft:location:";
+
+/// Where to pull container images from, for analyzing them.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ImageConfig {
+    /// The platform picked from multi-platform images, as `os/architecture[/variant]`
+    pub platform: String,
+    /// Registries requiring credentials or plain HTTP, by host (`docker.io` for Docker Hub)
+    pub registries: HashMap<String, RegistryConfig>,
+    /// If not empty, the only registries images are pulled from besides those of `registries`.
+    /// Other registries must resolve to public addresses otherwise
+    pub allowed_registries: Vec<String>,
+    /// The largest manifest or blob pulled, in bytes
+    pub max_blob_size: u64,
+}
+
+impl Default for ImageConfig {
+    fn default() -> Self {
+        Self {
+            platform: "linux/amd64".to_string(),
+            registries: HashMap::new(),
+            allowed_registries: Vec::new(),
+            max_blob_size: 64 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RegistryConfig {
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Use plain HTTP, e.g. for a local registry
+    pub insecure: bool,
+}
+
+impl RegistryConfig {
+    pub fn redacted(&self, redacted: &str) -> Self {
+        Self {
+            password: self.password.as_ref().map(|_| redacted.to_string()),
+            ..self.clone()
+        }
+    }
+}
+
+/// An image reference, e.g. `quay.io/org/image:tag` or `image@sha256:...`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Reference {
+    registry: String,
+    repository: String,
+    /// A tag or a digest
+    reference: String,
+}
+
+impl Reference {
+    fn parse(image: &str) -> Option<Self> {
+        let (name, reference) = match image.split_once('@') {
+            Some((name, digest)) => (name, Some(digest.to_string())),
+            None => (image, None),
+        };
+        let (name, reference) = match (reference, name.rfind(':')) {
+            (Some(digest), _) => (name, digest),
+            (None, Some(i)) if !name[i..].contains('/') => (&name[..i], name[i + 1..].to_string()),
+            (None, _) => (name, "latest".to_string()),
+        };
+
+        let (registry, repository) = match name.split_once('/') {
+            Some((host, repository))
+                if host.contains('.') || host.contains(':') || host == "localhost" =>
+            {
+                (host.to_string(), repository.to_string())
+            }
+            Some(_) => (DOCKER_HUB.to_string(), name.to_string()),
+            None => (DOCKER_HUB.to_string(), format!("library/{}", name)),
+        };
+        if repository.is_empty() || reference.is_empty() {
+            return None;
+        }
+        Some(Self {
+            registry,
+            repository,
+            reference,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Descriptor {
+    media_type: Option<String>,
+    digest: String,
+    #[serde(default)]
+    size: u64,
+    artifact_type: Option<String>,
+    platform: Option<Platform>,
+}
+
+#[derive(Deserialize)]
+struct Platform {
+    os: String,
+    architecture: String,
+    variant: Option<String>,
+}
+
+impl Platform {
+    fn matches(&self, platform: &str) -> bool {
+        let mut parts = platform.split('/');
+        parts.next() == Some(self.os.as_str())
+            && parts.next() == Some(self.architecture.as_str())
+            && parts
+                .next()
+                .map_or(true, |v| self.variant.as_deref() == Some(v))
+    }
+}
+
+/// An image manifest or index, artifacts are manifests with an artifact type.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Manifest {
+    media_type: Option<String>,
+    artifact_type: Option<String>,
+    config: Option<Descriptor>,
+    #[serde(default)]
+    layers: Vec<Descriptor>,
+    #[serde(default)]
+    manifests: Vec<Descriptor>,
+}
+
+impl Manifest {
+    fn is_index(&self) -> bool {
+        matches!(
+            self.media_type.as_deref(),
+            Some(OCI_INDEX) | Some(DOCKER_LIST)
+        ) || !self.manifests.is_empty()
+    }
+}
+
+#[derive(Deserialize)]
+struct ImageConfigBlob {
+    rootfs: Option<RootFs>,
+}
+
+#[derive(Deserialize)]
+struct RootFs {
+    #[serde(default)]
+    diff_ids: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct Envelope {
+    payload: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Statement {
+    predicate_type: String,
+    predicate: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    token: Option<String>,
+    access_token: Option<String>,
+}
+
+/// Pulls manifests and blobs from OCI registries.
+pub struct Registry {
+    config: ImageConfig,
+    client: reqwest::Client,
+}
+
+/// The requests made for one image, sharing the token of its repository.
+struct Session<'a> {
+    registry: &'a Registry,
+    reference: &'a Reference,
+    token: Option<String>,
+}
+
+impl Registry {
+    pub fn new(config: ImageConfig) -> Result<Self, anyhow::Error> {
+        // registries redirect blobs to their storage, but not to the network of the service
+        Ok(Self {
+            config,
+            client: reqwest::Client::builder()
+                .redirect(crate::outbound::redirect_policy())
+                .build()?,
+        })
+    }
+
+    fn credentials(&self, registry: &str) -> Option<&RegistryConfig> {
+        self.config.registries.get(registry)
+    }
+
+    /// Whether a registry, and the token service it names, were configured by the deployment.
+    fn is_configured(&self, registry: &str) -> bool {
+        self.config.registries.contains_key(registry)
+            || self.config.allowed_registries.iter().any(|r| r == registry)
+    }
+
+    /// Check that images may be pulled from the registry of a reference: configured registries
+    /// always, others only if no registries are allowed explicitly and they are public.
+    async fn check(&self, reference: &Reference) -> Result<(), OutboundError> {
+        if self.is_configured(&reference.registry) {
+            return Ok(());
+        }
+        if !self.config.allowed_registries.is_empty() {
+            return Err(OutboundError::NotAllowed {
+                host: reference.registry.clone(),
+            });
+        }
+        crate::outbound::check(&self.base_url(reference), &[]).await
+    }
+
+    /// The body of a response, failing if it is larger than `image.max_blob_size`.
+    async fn read(&self, mut response: reqwest::Response) -> Result<Vec<u8>, anyhow::Error> {
+        let max = self.config.max_blob_size;
+        let url = response.url().to_string();
+        let too_large = || anyhow!("{} is larger than {} bytes", url, max);
+        if response
+            .content_length()
+            .map_or(false, |length| length > max)
+        {
+            return Err(too_large());
+        }
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            if (body.len() + chunk.len()) as u64 > max {
+                return Err(too_large());
+            }
+            body.extend_from_slice(&chunk);
+        }
+        Ok(body)
+    }
+
+    fn base_url(&self, reference: &Reference) -> String {
+        let insecure = self
+            .credentials(&reference.registry)
+            .map_or(false, |c| c.insecure);
+        let host = match reference.registry.as_str() {
+            DOCKER_HUB => DOCKER_HUB_HOST,
+            host => host,
+        };
+        format!(
+            "{}://{}/v2/{}",
+            if insecure { "http" } else { "https" },
+            host,
+            reference.repository
+        )
+    }
+}
+
+impl Session<'_> {
+    /// `GET` a path below the repository, `None` if it doesn't exist.
+    ///
+    /// Registries answer `401` with the token service to use, the token is requested with the
+    /// configured credentials, or anonymously.
+    async fn get(
+        &mut self,
+        path: &str,
+        accept: &[&str],
+    ) -> Result<Option<reqwest::Response>, anyhow::Error> {
+        let url = format!("{}/{}", self.registry.base_url(self.reference), path);
+        let mut retried = false;
+        loop {
+            let mut request = self.registry.client.get(&url);
+            if !accept.is_empty() {
+                request = request.header(reqwest::header::ACCEPT, accept.join(", "));
+            }
+            request = match (
+                &self.token,
+                self.registry.credentials(&self.reference.registry),
+            ) {
+                (Some(token), _) => request.bearer_auth(token),
+                (
+                    None,
+                    Some(RegistryConfig {
+                        username: Some(username),
+                        password,
+                        ..
+                    }),
+                ) => request.basic_auth(username, password.as_ref()),
+                (None, _) => request,
+            };
+            let response = request.send().await?;
+            match response.status() {
+                reqwest::StatusCode::NOT_FOUND => return Ok(None),
+                reqwest::StatusCode::UNAUTHORIZED if !retried => {
+                    let challenge = response
+                        .headers()
+                        .get(reqwest::header::WWW_AUTHENTICATE)
+                        .and_then(|h| h.to_str().ok())
+                        .ok_or_else(|| anyhow!("Registry requires authentication"))?
+                        .to_string();
+                    self.token = Some(self.authenticate(&challenge).await?);
+                    retried = true;
+                }
+                _ => return Ok(Some(response.error_for_status()?)),
+            }
+        }
+    }
+
+    async fn authenticate(&self, challenge: &str) -> Result<String, anyhow::Error> {
+        let parameters = challenge
+            .strip_prefix("Bearer ")
+            .map(parse_challenge)
+            .ok_or_else(|| anyhow!("Unsupported registry authentication: {}", challenge))?;
+        let realm = parameters
+            .get("realm")
+            .ok_or_else(|| anyhow!("Registry authentication without realm"))?;
+        // the registry must not make the service send requests to its network
+        if !self.registry.is_configured(&self.reference.registry) {
+            crate::outbound::check(realm, &[])
+                .await
+                .map_err(|e| anyhow!("Registry token service not allowed: {}", e))?;
+        }
+        let query: Vec<(&str, &str)> = parameters
+            .iter()
+            .filter(|(k, _)| k.as_str() != "realm")
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+
+        let mut request = self.registry.client.get(realm).query(&query);
+        if let Some(RegistryConfig {
+            username: Some(username),
+            password,
+            ..
+        }) = self.registry.credentials(&self.reference.registry)
+        {
+            request = request.basic_auth(username, password.as_ref());
+        }
+        let response = request.send().await?.error_for_status()?;
+        let response: TokenResponse = serde_json::from_slice(&self.registry.read(response).await?)?;
+        response
+            .token
+            .or(response.access_token)
+            .ok_or_else(|| anyhow!("Registry token response without token"))
+    }
+
+    /// A manifest and its digest, by tag or digest.
+    async fn manifest(
+        &mut self,
+        reference: &str,
+    ) -> Result<Option<(Manifest, String)>, anyhow::Error> {
+        let response = match self
+            .get(
+                &format!("manifests/{}", reference),
+                &[OCI_INDEX, OCI_MANIFEST, DOCKER_LIST, DOCKER_MANIFEST],
+            )
+            .await?
+        {
+            Some(response) => response,
+            None => return Ok(None),
+        };
+        let digest = response
+            .headers()
+            .get("Docker-Content-Digest")
+            .and_then(|h| h.to_str().ok())
+            .map(ToString::to_string);
+        let body = self.registry.read(response).await?;
+        let digest =
+            digest.unwrap_or_else(|| format!("sha256:{}", hex::encode(Sha256::digest(&body))));
+        Ok(Some((serde_json::from_slice(&body)?, digest)))
+    }
+
+    async fn blob(&mut self, digest: &str) -> Result<Vec<u8>, anyhow::Error> {
+        let response = self
+            .get(&format!("blobs/{}", digest), &[])
+            .await?
+            .ok_or_else(|| anyhow!("Blob {} not found", digest))?;
+        self.registry.read(response).await
+    }
+
+    /// The SBOMs attached to an image digest, through the OCI referrers API or the cosign tag
+    /// scheme (`sha256-<digest>.sbom` and `.att`).
+    ///
+    /// Artifacts which can't be read are added to `failures`, the others are still returned.
+    async fn sboms(
+        &mut self,
+        digest: &str,
+        failures: &mut Vec<ArtifactFailure>,
+    ) -> Vec<serde_json::Value> {
+        let mut failed = |artifact: &str, error: anyhow::Error| {
+            log::debug!("Unable to read {} of {}: {}", artifact, digest, error);
+            failures.push(ArtifactFailure {
+                artifact: artifact.to_string(),
+                reason: error.to_string(),
+            });
+        };
+        let mut artifacts = Vec::new();
+        // registries without the referrers API don't all answer 404
+        let referrers = match self
+            .get(&format!("referrers/{}", digest), &[OCI_INDEX])
+            .await
+        {
+            Ok(Some(response)) => match self.registry.read(response).await {
+                Ok(body) => serde_json::from_slice::<Manifest>(&body).ok(),
+                Err(e) => {
+                    log::debug!("Unable to list referrers of {}: {}", digest, e);
+                    None
+                }
+            },
+            Ok(None) => None,
+            Err(e) => {
+                log::debug!("Unable to list referrers of {}: {}", digest, e);
+                None
+            }
+        };
+        if let Some(index) = referrers {
+            for descriptor in index.manifests {
+                if is_sbom(descriptor.artifact_type.as_deref()) {
+                    match self.manifest(&descriptor.digest).await {
+                        Ok(Some((manifest, _))) => artifacts.push(manifest),
+                        Ok(None) => {}
+                        Err(e) => failed(&descriptor.digest, e),
+                    }
+                }
+            }
+        }
+        let tag = digest.replacen(':', "-", 1);
+        for suffix in ["sbom", "att"] {
+            let tag = format!("{}.{}", tag, suffix);
+            match self.manifest(&tag).await {
+                Ok(Some((manifest, _))) => artifacts.push(manifest),
+                Ok(None) => {}
+                Err(e) => failed(&tag, e),
+            }
+        }
+
+        let mut sboms = Vec::new();
+        for artifact in artifacts {
+            for layer in artifact.layers.iter() {
+                let media_type = layer
+                    .media_type
+                    .as_deref()
+                    .or(artifact.artifact_type.as_deref());
+                if !is_sbom(media_type) {
+                    continue;
+                }
+                let sbom = match self.blob(&layer.digest).await {
+                    Ok(blob) => extract_sbom(media_type, &blob),
+                    Err(e) => Err(e),
+                };
+                match sbom {
+                    Ok(Some(sbom)) => sboms.push(sbom),
+                    Ok(None) => {}
+                    Err(e) => failed(&layer.digest, e),
+                }
+            }
+        }
+        sboms
+    }
+}
+
+fn is_sbom(media_type: Option<&str>) -> bool {
+    matches!(media_type, Some(CYCLONEDX) | Some(SPDX) | Some(DSSE))
+}
+
+/// The SBOM of an artifact layer, unwrapping in-toto attestations in DSSE envelopes.
+fn extract_sbom(
+    media_type: Option<&str>,
+    blob: &[u8],
+) -> Result<Option<serde_json::Value>, anyhow::Error> {
+    if media_type != Some(DSSE) {
+        return Ok(Some(serde_json::from_slice(blob)?));
+    }
+    let envelope: Envelope = serde_json::from_slice(blob)?;
+    let statement: Statement = serde_json::from_slice(
+        &base64::engine::general_purpose::STANDARD.decode(envelope.payload)?,
+    )?;
+    if !statement.predicate_type.contains("cyclonedx") && !statement.predicate_type.contains("spdx")
+    {
+        return Ok(None);
+    }
+    // cosign wraps predicates it couldn't parse as JSON as a string
+    Ok(Some(match statement.predicate {
+        serde_json::Value::String(predicate) => serde_json::from_str(&predicate)?,
+        predicate => predicate,
+    }))
+}
+
+/// The `key="value"` parameters of a `WWW-Authenticate` challenge, values may contain commas.
+fn parse_challenge(parameters: &str) -> BTreeMap<String, String> {
+    let mut result = BTreeMap::new();
+    let mut rest = parameters.trim();
+    while let Some((key, value)) = rest.split_once('=') {
+        let key = key.trim().trim_start_matches(',').trim().to_string();
+        let (value, remainder) = match value.strip_prefix('"') {
+            Some(quoted) => quoted.split_once('"').unwrap_or((quoted, "")),
+            None => value.split_once(',').unwrap_or((value, "")),
+        };
+        result.insert(key, value.to_string());
+        rest = remainder;
+    }
+    result
+}
+
+/// The layers (by diff id) of the components of a CycloneDX SBOM, as recorded by Syft.
+fn component_layers(sbom: &serde_json::Value) -> HashMap<String, Vec<String>> {
+    fn walk(components: &serde_json::Value, layers: &mut HashMap<String, Vec<String>>) {
+        for component in components.as_array().into_iter().flatten() {
+            if let Some(purl) = component.get("purl").and_then(|p| p.as_str()) {
+                let diff_ids = component
+                    .get("properties")
+                    .and_then(|p| p.as_array())
+                    .into_iter()
+                    .flatten()
+                    .filter(|p| {
+                        p.get("name").and_then(|n| n.as_str()).map_or(false, |n| {
+                            n.starts_with(SYFT_LOCATION) && n.ends_with(":layerID")
+                        })
+                    })
+                    .filter_map(|p| p.get("value")?.as_str().map(ToString::to_string));
+                let entry = layers.entry(purl.to_string()).or_default();
+                for diff_id in diff_ids {
+                    if !entry.contains(&diff_id) {
+                        entry.push(diff_id);
+                    }
+                }
+            }
+            if let Some(components) = component.get("components") {
+                walk(components, layers);
+            }
+        }
+    }
+
+    let mut layers = HashMap::new();
+    if let Some(components) = sbom.get("components") {
+        walk(components, &mut layers);
+    }
+    layers
+}
+
+/// A layer of an image, and the components the SBOM places in it.
+#[derive(Clone, Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct LayerComponents {
+    pub digest: String,
+    /// The digest of the uncompressed layer, which SBOMs refer to
+    pub diff_id: Option<String>,
+    pub size: u64,
+    pub components: Vec<String>,
+}
+
+/// An artifact of an image which could not be read, e.g. an SBOM layer, its components are
+/// missing from the analysis.
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct ArtifactFailure {
+    /// The digest or tag of the artifact
+    pub artifact: String,
+    pub reason: String,
+}
+
+/// The result of analyzing the components of an image, from its attached SBOMs.
+#[derive(Clone, Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ImageAnalysis {
+    pub reference: String,
+    /// The digest of the analyzed image manifest
+    pub digest: String,
+    /// The layers of the image, components are only placed in layers if the SBOM records them
+    pub layers: Vec<LayerComponents>,
+    /// The artifacts which could not be read
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub failures: Vec<ArtifactFailure>,
+    #[serde(flatten)]
+    pub analysis: SbomAnalysis,
+}
+
+#[derive(Clone, Debug, Deserialize, ToSchema)]
+#[schema(example = json!({"reference": "registry.access.redhat.com/ubi9/ubi:9.1.0"}))]
+pub struct ImageRequest {
+    /// The image reference, e.g. `quay.io/org/image:tag`
+    pub reference: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ImageQuery {
+    /// Include the identifiers native to the providers reporting a fact
+    #[serde(default)]
+    include_ids: bool,
+}
+
+/// Analyze the components of a container image, from the SBOMs attached to it.
+#[utoipa::path(
+    context_path = "/api/v1",
+    request_body = ImageRequest,
+    responses(
+        (status = 200, description = "The analysis of the components of the image", body = ImageAnalysis, example = json!({
+            "reference": "registry.access.redhat.com/ubi9/ubi:9.1.0",
+            "digest": "sha256:23879d61ecb319c10e60741d04b5f2f121e62333662df2439f338da5f457a4e7",
+            "layers": [{
+                "digest": "sha256:2a625e4afab51b49edb0e5f4ff37d8afbb20ec644ed1e68641358a6305557de3",
+                "diffId": "sha256:e3f15f2d9f7c3d6a0d5ee0ba4e5e8b1a3bfb256ab8e44d1f1ad2e6f1c2a0e6a4",
+                "size": 79345012,
+                "components": ["pkg:rpm/redhat/openssl@3.0.1-47.el9_1?arch=x86_64&epoch=1"]
+            }],
+            "components": 1,
+            "vulnerable": 0,
+            "vulnerabilities": [],
+            "packages": [{
+                "purl": "pkg:rpm/redhat/openssl@3.0.1-47.el9_1?arch=x86_64&epoch=1",
+                "status": 200,
+                "result": {"purl": "pkg:rpm/redhat/openssl@3.0.1-47.el9_1?arch=x86_64&epoch=1", "trusted": true, "vulnerabilities": []}
+            }]
+        })),
+        (status = NOT_FOUND, description = "Unknown image or platform, or no SBOM attached to the image", body = Problem, example = json!(crate::problem::example(ApiError::SbomNotFound { reference: "quay.io/example/app:1.0".to_string() }))),
+        (status = BAD_REQUEST, description = "Invalid image reference", body = Problem, example = json!(crate::problem::example(ApiError::InvalidReference { reference: "quay.io/example/App:latest".to_string() }))),
+        (status = FORBIDDEN, description = "The registry is not allowed, or reaches an internal address", body = Problem, example = json!(crate::problem::example(ApiError::RegistryNotAllowed { registry: "10.0.0.1:5000".to_string(), reason: "10.0.0.1 resolves to the non-public address 10.0.0.1".to_string() }))),
+        (status = BAD_GATEWAY, description = "The registry failed", body = Problem, example = json!(crate::problem::example(ApiError::Upstream(package::ApiError::UpstreamError { upstream: "registry".to_string(), status: 500, correlation_id: "5b0e1f3c-8a8e-4a77-9a3d-7f6b2c1d9e40".to_string() })))),
+        (status = SERVICE_UNAVAILABLE, description = "The registry could not be reached", body = Problem, example = json!(crate::problem::example(ApiError::Upstream(package::ApiError::UpstreamUnavailable { upstream: "registry".to_string(), correlation_id: "5b0e1f3c-8a8e-4a77-9a3d-7f6b2c1d9e40".to_string() })))),
+    ),
+    params(
+        ("include_ids" = Option<bool>, Query, description = "Include provider identifiers of vulnerabilities"),
+    )
+)]
+#[post("/image")]
+pub async fn analyze_image(
+    registry: web::Data<Arc<Registry>>,
+    trusted: web::Data<TrustedContent>,
+    query: web::Query<ImageQuery>,
+    body: web::Json<ImageRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let image = body.into_inner().reference;
+    let reference = Reference::parse(&image).ok_or_else(|| ApiError::InvalidReference {
+        reference: image.clone(),
+    })?;
+    let upstream = |e| ApiError::Upstream(package::ApiError::upstream("registry", e));
+    registry
+        .check(&reference)
+        .await
+        .map_err(|e| ApiError::RegistryNotAllowed {
+            registry: reference.registry.clone(),
+            reason: e.to_string(),
+        })?;
+    let mut session = Session {
+        registry: &registry,
+        reference: &reference,
+        token: None,
+    };
+
+    let (manifest, digest) = session
+        .manifest(&reference.reference)
+        .await
+        .map_err(upstream)?
+        .ok_or_else(|| ApiError::ImageNotFound {
+            reference: image.clone(),
+        })?;
+    // SBOMs may be attached to the index of a multi-platform image, or to the platform image
+    let mut subjects = vec![digest.clone()];
+    let (manifest, digest) = if manifest.is_index() {
+        let descriptor = manifest
+            .manifests
+            .into_iter()
+            .find(|m| {
+                m.platform
+                    .as_ref()
+                    .map_or(false, |p| p.matches(&registry.config.platform))
+            })
+            .ok_or_else(|| ApiError::PlatformNotFound {
+                reference: image.clone(),
+                platform: registry.config.platform.clone(),
+            })?;
+        let platform = session
+            .manifest(&descriptor.digest)
+            .await
+            .map_err(upstream)?
+            .ok_or_else(|| ApiError::ImageNotFound {
+                reference: image.clone(),
+            })?;
+        subjects.push(platform.1.clone());
+        platform
+    } else {
+        (manifest, digest)
+    };
+
+    let mut sboms = Vec::new();
+    let mut failures = Vec::new();
+    for subject in subjects.iter() {
+        sboms.extend(session.sboms(subject, &mut failures).await);
+    }
+    if sboms.is_empty() {
+        if let Some(failure) = failures.first() {
+            return Err(upstream(anyhow!(
+                "Unable to read {}: {}",
+                failure.artifact,
+                failure.reason
+            )));
+        }
+        return Err(ApiError::SbomNotFound { reference: image });
+    }
+
+    let mut purls: Vec<String> = sboms
+        .iter()
+        .flat_map(crate::sbom::component_purls)
+        .collect();
+    let mut seen = std::collections::HashSet::new();
+    purls.retain(|p| seen.insert(p.clone()));
+
+    let mut diff_ids = Vec::new();
+    if let Some(config) = &manifest.config {
+        // the layers are listed without their components if the config is missing
+        let blob = session
+            .blob(&config.digest)
+            .await
+            .and_then(|blob| Ok(serde_json::from_slice::<ImageConfigBlob>(&blob)?));
+        match blob {
+            Ok(blob) => diff_ids = blob.rootfs.map(|r| r.diff_ids).unwrap_or_default(),
+            Err(e) => failures.push(ArtifactFailure {
+                artifact: config.digest.clone(),
+                reason: e.to_string(),
+            }),
+        }
+    }
+    let mut placed: HashMap<String, Vec<String>> = HashMap::new();
+    for sbom in sboms.iter() {
+        for (purl, layers) in component_layers(sbom) {
+            for diff_id in layers {
+                placed.entry(diff_id).or_default().push(purl.clone());
+            }
+        }
+    }
+    let layers = manifest
+        .layers
+        .into_iter()
+        .enumerate()
+        .map(|(i, layer)| {
+            let diff_id = diff_ids.get(i).cloned();
+            let mut components = diff_id
+                .as_ref()
+                .and_then(|d| placed.remove(d))
+                .unwrap_or_default();
+            components.sort();
+            components.dedup();
+            LayerComponents {
+                digest: layer.digest,
+                diff_id,
+                size: layer.size,
+                components,
+            }
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(ImageAnalysis {
+        reference: image,
+        digest,
+        layers,
+        failures,
+        analysis: analyze::analyze(&trusted, &purls, query.include_ids).await,
+    }))
+}
+
+#[derive(Debug, Error, Serialize, Deserialize, ToSchema)]
+#[schema(as = ImageApiError)]
+pub enum ApiError {
+    #[error("{reference} is not a valid image reference")]
+    InvalidReference { reference: String },
+    #[error("Image {reference} was not found")]
+    ImageNotFound { reference: String },
+    #[error("Image {reference} has no manifest for platform {platform}")]
+    PlatformNotFound { reference: String, platform: String },
+    #[error("No SBOM is attached to image {reference}")]
+    SbomNotFound { reference: String },
+    #[error("Images are not pulled from {registry}: {reason}")]
+    RegistryNotAllowed { registry: String, reason: String },
+    #[error(transparent)]
+    Upstream(#[schema(inline)] package::ApiError),
+}
+
+impl error::ResponseError for ApiError {
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            ApiError::Upstream(e) => e.error_response(),
+            _ => crate::problem::response(self.status_code(), self, serde_json::json!({})),
+        }
+    }
+
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::InvalidReference { reference: _ } => StatusCode::BAD_REQUEST,
+            ApiError::ImageNotFound { reference: _ } => StatusCode::NOT_FOUND,
+            ApiError::PlatformNotFound { .. } => StatusCode::NOT_FOUND,
+            ApiError::SbomNotFound { reference: _ } => StatusCode::NOT_FOUND,
+            ApiError::RegistryNotAllowed { .. } => StatusCode::FORBIDDEN,
+            ApiError::Upstream(e) => e.status_code(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn reference(registry: &str, repository: &str, reference: &str) -> Option<Reference> {
+        Some(Reference {
+            registry: registry.to_string(),
+            repository: repository.to_string(),
+            reference: reference.to_string(),
+        })
+    }
+
+    #[test]
+    fn references_are_parsed() {
+        assert_eq!(
+            Reference::parse("ubi9"),
+            reference(DOCKER_HUB, "library/ubi9", "latest")
+        );
+        assert_eq!(
+            Reference::parse("org/app:1.0"),
+            reference(DOCKER_HUB, "org/app", "1.0")
+        );
+        assert_eq!(
+            Reference::parse("quay.io/org/app@sha256:abc"),
+            reference("quay.io", "org/app", "sha256:abc")
+        );
+        assert_eq!(
+            Reference::parse("localhost:5000/app"),
+            reference("localhost:5000", "app", "latest")
+        );
+        assert_eq!(Reference::parse("quay.io/"), None);
+    }
+
+    #[test]
+    fn challenges_are_parsed() {
+        let parameters = parse_challenge(
+            r#"realm="https://auth.example.com/token",service="registry",scope="repository:org/app:pull,push""#,
+        );
+        assert_eq!(parameters["realm"], "https://auth.example.com/token");
+        assert_eq!(parameters["service"], "registry");
+        assert_eq!(parameters["scope"], "repository:org/app:pull,push");
+    }
+
+    #[test]
+    fn platforms_match_with_optional_variants() {
+        let platform = Platform {
+            os: "linux".to_string(),
+            architecture: "arm64".to_string(),
+            variant: Some("v8".to_string()),
+        };
+        assert!(platform.matches("linux/arm64"));
+        assert!(platform.matches("linux/arm64/v8"));
+        assert!(!platform.matches("linux/arm64/v7"));
+        assert!(!platform.matches("linux/amd64"));
+    }
+
+    #[test]
+    fn sboms_are_unwrapped_from_attestations() {
+        let sbom = json!({"bomFormat": "CycloneDX", "components": []});
+        let envelope = |predicate_type: &str, predicate: serde_json::Value| {
+            let statement = json!({"predicateType": predicate_type, "predicate": predicate});
+            serde_json::to_vec(&json!({
+                "payload": base64::engine::general_purpose::STANDARD.encode(statement.to_string()),
+            }))
+            .unwrap()
+        };
+        assert_eq!(
+            extract_sbom(
+                Some(DSSE),
+                &envelope("https://cyclonedx.org/bom", sbom.clone())
+            )
+            .unwrap(),
+            Some(sbom.clone())
+        );
+        assert_eq!(
+            extract_sbom(
+                Some(DSSE),
+                &envelope("https://cyclonedx.org/bom", sbom.to_string().into())
+            )
+            .unwrap(),
+            Some(sbom.clone())
+        );
+        assert_eq!(
+            extract_sbom(
+                Some(DSSE),
+                &envelope("https://slsa.dev/provenance/v1", json!({}))
+            )
+            .unwrap(),
+            None
+        );
+        assert_eq!(
+            extract_sbom(Some(CYCLONEDX), sbom.to_string().as_bytes()).unwrap(),
+            Some(sbom)
+        );
+    }
+
+    #[test]
+    fn components_are_placed_in_their_layers() {
+        let sbom = json!({"components": [{
+            "purl": "pkg:rpm/redhat/openssl@3.0.1",
+            "properties": [
+                {"name": "syft:location:0:layerID", "value": "sha256:1"},
+                {"name": "syft:location:0:path", "value": "/var/lib/rpm"},
+                {"name": "syft:location:1:layerID", "value": "sha256:1"},
+            ],
+            "components": [{
+                "purl": "pkg:rpm/redhat/openssl-libs@3.0.1",
+                "properties": [{"name": "syft:location:0:layerID", "value": "sha256:2"}],
+            }],
+        }]});
+        let layers = component_layers(&sbom);
+        assert_eq!(layers["pkg:rpm/redhat/openssl@3.0.1"], ["sha256:1"]);
+        assert_eq!(layers["pkg:rpm/redhat/openssl-libs@3.0.1"], ["sha256:2"]);
+    }
+
+    #[tokio::test]
+    async fn only_allowed_or_public_registries_are_pulled_from() {
+        let registry = |config: serde_json::Value| {
+            Registry::new(serde_json::from_value(config).unwrap()).unwrap()
+        };
+        let local = Reference::parse("localhost:5000/app").unwrap();
+        let internal = Reference::parse("10.0.0.1:5000/app").unwrap();
+        let public = Reference::parse("93.184.216.34/app").unwrap();
+
+        let open = registry(json!({}));
+        assert!(open.check(&public).await.is_ok());
+        assert!(open.check(&local).await.is_err());
+        assert!(open.check(&internal).await.is_err());
+
+        let configured = registry(json!({"registries": {"localhost:5000": {"insecure": true}}}));
+        assert!(configured.check(&local).await.is_ok());
+
+        let allowed = registry(json!({"allowed_registries": ["10.0.0.1:5000"]}));
+        assert!(allowed.check(&internal).await.is_ok());
+        assert!(matches!(
+            allowed.check(&public).await,
+            Err(OutboundError::NotAllowed { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn blobs_are_limited_in_size() {
+        let registry = Registry::new(ImageConfig {
+            max_blob_size: 4,
+            ..Default::default()
+        })
+        .unwrap();
+        let response = |body: &'static str| {
+            reqwest::Response::from(http::Response::builder().body(body).unwrap())
+        };
+        assert_eq!(registry.read(response("1234")).await.unwrap(), b"1234");
+        assert!(registry.read(response("12345")).await.is_err());
+    }
+}
@@ -1,42 +1,201 @@
-use actix_web::{error, get, http::StatusCode, web::ServiceConfig, HttpRequest, HttpResponse};
+use actix_web::dev::ConnectionInfo;
+use actix_web::{error, get, http::StatusCode, web, web::ServiceConfig, HttpRequest, HttpResponse};
 use http::uri::Builder;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use thiserror::Error;
+use utoipa::{OpenApi, ToSchema};
 
+use crate::config::{Config, DocsConfig};
+use crate::search::MAX_LIMIT;
+use crate::subgraph::MAX_DEPTH;
+
+/// The index of the listener, outside of the API prefixes.
 pub(crate) fn configure() -> impl FnOnce(&mut ServiceConfig) {
     |config: &mut ServiceConfig| {
         config.service(index);
     }
 }
 
+/// The capability document, at the root of an API prefix.
+pub(crate) fn configure_api() -> impl FnOnce(&mut ServiceConfig) {
+    |config: &mut ServiceConfig| {
+        config.service(capabilities);
+    }
+}
+
+/// The documentation of the index and the capability document.
+#[derive(OpenApi)]
+#[openapi(paths(index, capabilities), components(schemas(Capabilities, Limits)))]
+pub(crate) struct ApiDoc;
+
+/// Subresources linked from the capability document, by name.
+const LINKS: &[(&str, &str)] = &[
+    ("package", "/api/v1/package"),
+    ("dependencies", "/api/v1/package/dependencies"),
+    ("dependents", "/api/v1/package/dependents"),
+    ("path", "/api/v1/package/path"),
+    ("counterpart", "/api/v1/package/counterpart"),
+    ("versions", "/api/v1/package/versions"),
+    ("licenses", "/api/v1/package/licenses"),
+    ("licenseCheck", "/api/v1/package/license-check"),
+    ("provenance", "/api/v1/package/provenance"),
+    ("byCpe", "/api/v1/package/by-cpe"),
+    ("byCoordinates", "/api/v1/package/by-coordinates"),
+    ("sbom", "/api/v1/package/sbom"),
+    ("history", "/api/v1/package/history"),
+    ("analyze", "/api/v1/sbom/analyze"),
+    ("annotate", "/api/v1/sbom/annotate"),
+    ("image", "/api/v1/image"),
+    ("trusted", "/api/v1/trusted"),
+    ("search", "/api/v1/search"),
+    ("vulnerability", "/api/v1/vulnerability"),
+    ("affected", "/api/v1/report/affected"),
+    ("events", "/api/v1/events"),
+    ("watch", "/api/v1/watch"),
+    ("openapi", "/openapi.json"),
+];
+
+#[derive(Clone, Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Limits {
+    /// Maximum `depth` of dependency queries
+    pub max_depth: usize,
+    /// Maximum `limit` of search results
+    pub max_search_limit: usize,
+    /// Maximum number of purls of batch requests
+    pub max_batch_size: usize,
+    /// Maximum size of JSON request bodies, in bytes
+    pub max_body_bytes: usize,
+}
+
+/// What a deployment offers, so clients can adapt to its configuration.
+#[derive(Clone, Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Capabilities {
+    /// Data providers, and whether they are enabled
+    pub providers: BTreeMap<&'static str, bool>,
+    /// Optional features, and whether they are enabled
+    pub features: BTreeMap<&'static str, bool>,
+    /// Package types deps.dev is consulted for, if enabled; Guac accepts any package type
+    pub fallback_ecosystems: Vec<&'static str>,
+    pub limits: Limits,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub links: BTreeMap<&'static str, String>,
+}
+
+impl Capabilities {
+    pub fn new(config: &Config) -> Self {
+        let enabled = |id: &str| config.api.is_provider_enabled(id);
+        let providers = BTreeMap::from([
+            ("guac", true),
+            ("snyk", config.snyk.token.is_some() && enabled("snyk")),
+            ("depsDev", config.deps_dev.enabled),
+            ("sigstore", config.sigstore.enabled),
+            ("scorecard", config.scorecard.enabled),
+            ("nvd", config.nvd.enabled && enabled("nvd")),
+            ("ghsa", config.ghsa.enabled && enabled("ghsa")),
+            ("osv", config.osv.enabled && enabled("osv")),
+        ]);
+        let features = BTreeMap::from([
+            ("events", true),
+            ("watch", true),
+            ("grpc", config.grpc.enabled),
+            ("publish", config.publish.is_some()),
+            ("ingestion", config.ingestion.is_some()),
+            ("adoptionStats", config.deps_dev.enabled),
+            ("freshRequests", !config.api.fresh.api_keys.is_empty()),
+            ("replicationPrimary", config.replication.primary),
+            ("follower", config.replication.follow.is_some()),
+        ]);
+        Self {
+            providers,
+            features,
+            fallback_ecosystems: if config.deps_dev.enabled {
+                crate::depsdev::ECOSYSTEMS.to_vec()
+            } else {
+                Vec::new()
+            },
+            limits: Limits {
+                max_depth: MAX_DEPTH,
+                max_search_limit: MAX_LIMIT,
+                max_batch_size: config.api.batch.max_items,
+                max_body_bytes: config.api.batch.max_body_bytes,
+            },
+            links: BTreeMap::new(),
+        }
+    }
+}
+
+fn absolute(conn: &ConnectionInfo, path: &str) -> Option<String> {
+    Builder::new()
+        .authority(conn.host())
+        .scheme(conn.scheme())
+        .path_and_query(path)
+        .build()
+        .ok()
+        .map(|uri| uri.to_string())
+}
+
 #[utoipa::path(
     responses(
-        (status = 200, description = "API", body = Vec<String>),
+        (status = 200, description = "API", body = Vec<String>, example = json!([
+            "http://localhost:8080/api/v1",
+            "http://localhost:8080/api/v1/package",
+            "http://localhost:8080/api/v1/vulnerability",
+            "http://localhost:8080/api/v1/events",
+            "http://localhost:8080/swagger-ui/",
+            "http://localhost:8080/openapi.json"
+        ])),
     )
 )]
 #[get("/")]
-pub async fn index(req: HttpRequest) -> Result<HttpResponse, ApiError> {
+pub async fn index(
+    req: HttpRequest,
+    docs: Option<web::Data<DocsConfig>>,
+) -> Result<HttpResponse, ApiError> {
     let mut apis = Vec::new();
     let conn = req.connection_info();
 
-    for api in &[
-        "/api/package",
-        "/api/vulnerability",
-        "/swagger-ui/",
-        "/openapi.json",
+    // the documentation is served below the base path, by the standalone server only
+    let docs = docs.map(|docs| docs.get_ref().clone()).unwrap_or_default();
+    let (ui, document) = (docs.ui_path(), docs.document_path());
+    for api in [
+        "/api/v1",
+        "/api/v1/package",
+        "/api/v1/vulnerability",
+        "/api/v1/events",
+        &ui,
+        &document,
     ] {
-        if let Ok(uri) = Builder::new()
-            .authority(conn.host())
-            .scheme(conn.scheme())
-            .path_and_query(*api)
-            .build()
-        {
-            apis.push(uri.to_string());
+        if let Some(uri) = absolute(&conn, api) {
+            apis.push(uri);
         }
     }
     Ok(HttpResponse::Ok().json(apis))
 }
 
+/// Describe the providers, features and limits of this deployment.
+#[utoipa::path(
+    context_path = "/api/v1",
+    responses(
+        (status = 200, description = "The capabilities of the deployment", body = Capabilities, example = json!(Capabilities::new(&Config::default()))),
+    )
+)]
+#[get("")]
+pub async fn capabilities(
+    req: HttpRequest,
+    capabilities: web::Data<Capabilities>,
+) -> Result<HttpResponse, ApiError> {
+    let conn = req.connection_info();
+    let mut capabilities = capabilities.get_ref().clone();
+    capabilities.links = LINKS
+        .iter()
+        .filter_map(|(name, path)| Some((*name, absolute(&conn, path)?)))
+        .collect();
+    Ok(HttpResponse::Ok().json(capabilities))
+}
+
 #[derive(Debug, Error, Serialize, Deserialize)]
 pub enum ApiError {
     #[error("No query argument was specified")]
@@ -47,10 +206,7 @@ pub enum ApiError {
 
 impl error::ResponseError for ApiError {
     fn error_response(&self) -> HttpResponse {
-        HttpResponse::build(self.status_code()).json(serde_json::json!({
-            "status": self.status_code().as_u16(),
-            "error": self.to_string(),
-        }))
+        crate::problem::response(self.status_code(), self, serde_json::json!({}))
     }
 
     fn status_code(&self) -> StatusCode {
@@ -0,0 +1,95 @@
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::cdn::Cdn;
+use crate::package::TrustedContent;
+use crate::subgraph::Subgraphs;
+
+/// Where to receive ingestion events from, in addition to the admin endpoint.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum IngestionConfig {
+    /// Requires the `nats` feature
+    Nats { url: String, subject: String },
+}
+
+impl IngestionConfig {
+    /// Whether support for the configured listener was built in.
+    pub fn is_supported(&self) -> bool {
+        match self {
+            Self::Nats { .. } => cfg!(feature = "nats"),
+        }
+    }
+}
+
+/// Documents about these packages were ingested into Guac.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct IngestionEvent {
+    pub purls: Vec<String>,
+}
+
+/// Invalidates and refreshes cached data of packages when new documents about them are ingested.
+pub struct Ingestion {
+    trusted: Arc<TrustedContent>,
+    subgraphs: Arc<Subgraphs>,
+    cdn: Arc<Cdn>,
+}
+
+impl Ingestion {
+    pub fn new(trusted: Arc<TrustedContent>, subgraphs: Arc<Subgraphs>, cdn: Arc<Cdn>) -> Self {
+        Self {
+            trusted,
+            subgraphs,
+            cdn,
+        }
+    }
+
+    /// Drop the cached data of the ingested packages, and look them up again to refresh the cache.
+    ///
+    /// Any subgraph may contain the packages, so all cached subgraphs are dropped. Responses about
+    /// the packages are purged from the CDN.
+    pub async fn handle(&self, event: IngestionEvent) {
+        self.subgraphs.invalidate().await;
+        self.cdn.purge_purls(&event.purls).await;
+        for purl in event.purls {
+            // responses to strict queries are cached by the purl with all its qualifiers
+            let strict = self.trusted.canonicalize_with(&purl, true);
+            let purl = self.trusted.canonicalize(&purl);
+            if strict != purl {
+                self.trusted.invalidate(&strict).await;
+            }
+            self.trusted.invalidate(&purl).await;
+            if let Err(e) = self.trusted.get_trusted(&purl).await {
+                log::debug!("Unable to refresh {} after ingestion: {}", purl, e);
+            }
+        }
+    }
+
+    /// Listen for ingestion events, failures are logged and don't stop the server.
+    pub async fn run(self: Arc<Self>, config: IngestionConfig) {
+        if let Err(e) = self.listen(config).await {
+            log::warn!("Error listening for ingestion events: {:?}", e);
+        }
+    }
+
+    #[cfg(feature = "nats")]
+    async fn listen(&self, config: IngestionConfig) -> Result<(), anyhow::Error> {
+        use futures::StreamExt;
+
+        let IngestionConfig::Nats { url, subject } = config;
+        let client = async_nats::connect(url.as_str()).await?;
+        let mut subscriber = client.subscribe(subject).await?;
+        while let Some(message) = subscriber.next().await {
+            match serde_json::from_slice(&message.payload) {
+                Ok(event) => self.handle(event).await,
+                Err(e) => log::warn!("Ignoring invalid ingestion event: {}", e),
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(not(feature = "nats"))]
+    async fn listen(&self, config: IngestionConfig) -> Result<(), anyhow::Error> {
+        anyhow::bail!("Listening on {:?} is not supported by this build", config)
+    }
+}
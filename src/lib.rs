@@ -0,0 +1,79 @@
+//! The trusted content API, as a library.
+//!
+//! Build an [`engine::Engine`] to use the aggregation of Guac, Snyk and the other providers
+//! in-process, and mount [`engine::Engine::scope`] into an existing actix application to serve the
+//! REST API. [`server::Server`] is the complete standalone server.
+
+mod access_log;
+mod admin;
+mod analyze;
+mod audit;
+mod auth;
+mod cache;
+mod cdn;
+#[cfg(feature = "cel")]
+mod cel;
+mod chaos;
+pub mod config;
+mod convert;
+mod coordinates;
+mod cost;
+pub mod cpe;
+mod depsdev;
+pub mod devmode;
+pub mod engine;
+pub mod error;
+mod etag;
+mod events;
+mod export;
+mod fresh;
+mod gate;
+mod ghsa;
+mod grpc;
+mod guac;
+mod health;
+mod image;
+mod index;
+mod ingestion;
+mod license;
+pub mod logging;
+mod metrics;
+mod nvd;
+pub mod openapi;
+mod osv;
+mod outbound;
+pub mod package;
+mod policy;
+mod problem;
+mod product;
+pub mod provider;
+mod publish;
+mod purl;
+mod rbac;
+mod reanalysis;
+pub mod recording;
+mod reload;
+mod replication;
+mod report;
+mod request_id;
+mod resilience;
+pub mod sbom;
+mod scheduler;
+mod scorecard;
+mod search;
+pub mod secrets;
+pub mod server;
+mod signing;
+mod sigstore;
+mod snyk;
+mod stats;
+pub mod storage;
+mod subgraph;
+mod tenant;
+mod tls;
+mod trusted;
+mod version;
+mod versioning;
+mod vulnerability;
+mod watch;
+mod webhook;
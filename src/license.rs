@@ -0,0 +1,195 @@
+use serde::{Deserialize, Serialize};
+use spdx::{Expression, LicenseItem, LicenseReq, Licensee, ParseMode};
+
+use crate::package::{License, LicenseCheck, LicenseViolation};
+
+/// Which licenses packages may use, by SPDX license identifier.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LicensePolicy {
+    /// If not empty, only these licenses are allowed
+    pub allow: Vec<String>,
+    /// These licenses are never allowed
+    pub deny: Vec<String>,
+    /// Whether packages without any license information pass
+    pub allow_unknown: bool,
+}
+
+impl LicensePolicy {
+    fn is_allowed(&self, allow: &[Licensee], deny: &[Licensee], req: &LicenseReq) -> bool {
+        !deny.iter().any(|d| d.satisfies(req))
+            && (self.allow.is_empty() || allow.iter().any(|a| a.satisfies(req)))
+    }
+
+    /// Check the licenses of a package, an expression passes if it can be satisfied by allowed licenses.
+    pub fn evaluate(&self, licenses: &[License]) -> LicenseCheck {
+        let allow = licensees(&self.allow);
+        let deny = licensees(&self.deny);
+        let mut violations = Vec::new();
+        if licenses.is_empty() && !self.allow_unknown {
+            violations.push(LicenseViolation {
+                expression: None,
+                reason: "No license information is available".to_string(),
+            });
+        }
+
+        for license in licenses {
+            let reason = match Expression::parse_mode(&license.expression, ParseMode::LAX) {
+                Ok(expression) => {
+                    if expression.evaluate(|req| self.is_allowed(&allow, &deny, req)) {
+                        continue;
+                    }
+                    let denied: Vec<String> = expression
+                        .requirements()
+                        .filter(|r| !self.is_allowed(&allow, &deny, &r.req))
+                        // as written in the expression, e.g. `GPL-3.0-only` rather than `GPL-3.0`
+                        .map(|r| {
+                            license.expression[r.span.start as usize..r.span.end as usize]
+                                .to_string()
+                        })
+                        .collect();
+                    format!("Not allowed: {}", denied.join(", "))
+                }
+                Err(e) => format!("Not a valid SPDX expression: {}", e),
+            };
+            violations.push(LicenseViolation {
+                expression: Some(license.expression.clone()),
+                reason,
+            });
+        }
+
+        LicenseCheck {
+            allowed: violations.is_empty(),
+            licenses: licenses.to_vec(),
+            violations,
+        }
+    }
+}
+
+/// The licensees of the entries of a policy, entries which are not a license are left out.
+///
+/// Entries are parsed leniently, like the expressions of packages, so lowercase ids and the
+/// `-only` suffix of GNU licenses are accepted. A licensee satisfies the licenses it is
+/// normalized to, e.g. `GPL-3.0-only` satisfies `GPL-3.0` and `GPL-3.0-only`.
+fn licensees(entries: &[String]) -> Vec<Licensee> {
+    entries
+        .iter()
+        .filter_map(|entry| {
+            // the `-only` of GNU licenses is for license holders, licensees go without it
+            let entry = match entry.len().checked_sub("-only".len()) {
+                Some(at)
+                    if entry.is_char_boundary(at) && entry[at..].eq_ignore_ascii_case("-only") =>
+                {
+                    &entry[..at]
+                }
+                _ => entry.as_str(),
+            };
+            Licensee::parse(entry).ok().or_else(|| {
+                let expression = Expression::parse_mode(entry, ParseMode::LAX).ok()?;
+                let mut requirements = expression.requirements();
+                let req = requirements.next()?.req.clone();
+                if requirements.next().is_some() {
+                    return None;
+                }
+                let license = match req.license {
+                    LicenseItem::Spdx { id, .. } => LicenseItem::Spdx {
+                        id,
+                        or_later: false,
+                    },
+                    other => other,
+                };
+                Some(Licensee::new(license, req.exception))
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn licenses(expressions: &[&str]) -> Vec<License> {
+        expressions
+            .iter()
+            .map(|expression| License {
+                expression: expression.to_string(),
+                source: "sbom".to_string(),
+            })
+            .collect()
+    }
+
+    fn policy(allow: &[&str], deny: &[&str]) -> LicensePolicy {
+        LicensePolicy {
+            allow: allow.iter().map(ToString::to_string).collect(),
+            deny: deny.iter().map(ToString::to_string).collect(),
+            allow_unknown: false,
+        }
+    }
+
+    fn reasons(check: &LicenseCheck) -> Vec<(Option<&str>, &str)> {
+        check
+            .violations
+            .iter()
+            .map(|v| (v.expression.as_deref(), v.reason.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn packages_without_licenses_pass_only_if_unknown_licenses_are_allowed() {
+        let mut policy = policy(&[], &[]);
+        let check = policy.evaluate(&[]);
+        assert!(!check.allowed);
+        assert_eq!(
+            reasons(&check),
+            [(None, "No license information is available")]
+        );
+
+        policy.allow_unknown = true;
+        assert!(policy.evaluate(&[]).allowed);
+    }
+
+    #[test]
+    fn expressions_pass_if_allowed_licenses_satisfy_them() {
+        let policy = policy(&[], &["gpl-3.0-only"]);
+        let check = policy.evaluate(&licenses(&["MIT OR GPL-3.0-only", "Apache-2.0"]));
+        assert!(check.allowed, "{:?}", check.violations);
+        assert_eq!(check.licenses.len(), 2);
+
+        let check = policy.evaluate(&licenses(&["MIT AND GPL-3.0-only"]));
+        assert!(!check.allowed);
+        assert_eq!(
+            reasons(&check),
+            [(Some("MIT AND GPL-3.0-only"), "Not allowed: GPL-3.0-only")]
+        );
+    }
+
+    #[test]
+    fn only_listed_licenses_are_allowed_if_any_are() {
+        let policy = policy(&["Apache-2.0", "mit"], &["MIT"]);
+        assert!(policy.evaluate(&licenses(&["Apache-2.0"])).allowed);
+        // denying wins over allowing
+        let check = policy.evaluate(&licenses(&["MIT", "BSD-3-Clause"]));
+        assert_eq!(
+            reasons(&check),
+            [
+                (Some("MIT"), "Not allowed: MIT"),
+                (Some("BSD-3-Clause"), "Not allowed: BSD-3-Clause")
+            ]
+        );
+    }
+
+    #[test]
+    fn license_refs_and_invalid_expressions_are_reported() {
+        let policy = policy(&[], &["LicenseRef-Proprietary"]);
+        let check = policy.evaluate(&licenses(&["LicenseRef-Proprietary", "MIT AND ("]));
+        assert!(!check.allowed);
+        assert_eq!(
+            check.violations[0].reason,
+            "Not allowed: LicenseRef-Proprietary"
+        );
+        assert_eq!(check.violations[1].expression.as_deref(), Some("MIT AND ("));
+        assert!(check.violations[1]
+            .reason
+            .starts_with("Not a valid SPDX expression"));
+    }
+}
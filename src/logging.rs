@@ -0,0 +1,38 @@
+use serde::{Deserialize, Serialize};
+use tracing_subscriber::EnvFilter;
+
+/// How log events are written to stderr.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// A line of text per event
+    Human,
+    /// A JSON object per event, with its fields and those of the request it happened in, for log
+    /// aggregation
+    Json,
+}
+
+impl Default for LogFormat {
+    fn default() -> Self {
+        Self::Human
+    }
+}
+
+/// Install the subscriber of the process, which also receives the records of the `log` crate.
+///
+/// Events are filtered by `RUST_LOG`, only errors are logged if it is unset.
+pub fn init(format: LogFormat) -> Result<(), anyhow::Error> {
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .with_writer(std::io::stderr);
+    match format {
+        LogFormat::Human => builder.try_init(),
+        LogFormat::Json => builder
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(false)
+            .try_init(),
+    }
+    .map_err(|e| anyhow::anyhow!("Unable to install the logger: {}", e))
+}
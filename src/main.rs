@@ -1,17 +1,29 @@
 use clap::Parser;
+use std::path::PathBuf;
 use std::process::{ExitCode, Termination};
-
-mod guac;
-mod index;
-mod package;
-mod sbom;
-mod server;
-mod snyk;
-mod vulnerability;
+use trust_api::{config, devmode, logging, secrets, server, storage};
 
 #[derive(clap::Subcommand, Debug)]
 pub enum Command {
     Run(Run),
+    /// Manage the keys encrypting stored secrets
+    #[command(subcommand)]
+    Secrets(SecretsCommand),
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum SecretsCommand {
+    /// Print a new key, to add at the top of the key file
+    GenerateKey {
+        /// The id of the key, e.g. the date it was created
+        id: String,
+    },
+    /// Encrypt the stored secrets with the first key of the key file
+    Rotate {
+        /// Configuration file (YAML or TOML)
+        #[arg(short = 'c', long = "config")]
+        config: Option<PathBuf>,
+    },
 }
 
 #[derive(clap::Parser, Debug)]
@@ -39,15 +51,40 @@ impl Cli {
 
     async fn run_command(self) -> anyhow::Result<ExitCode> {
         match self.command {
-            Command::Run(Run {
-                bind,
-                port,
-                guac_url,
-                snyk,
-            }) => {
-                let s = server::Server::new(bind, port, guac_url, snyk);
+            Command::Run(run) => {
+                let mut config = run.config()?;
+                logging::init(config.logging.format)?;
+                if run.devmode {
+                    let fixtures = match &run.fixtures {
+                        Some(path) => devmode::Fixtures::load(path)?,
+                        None => devmode::Fixtures::builtin()?,
+                    };
+                    let url = devmode::start(fixtures).await?;
+                    log::warn!(
+                        "Dev mode: serving Guac, Snyk and security data from fixtures on {}",
+                        url
+                    );
+                    devmode::apply(&mut config, &url);
+                }
+                if run.dump_config {
+                    println!("{}", serde_yaml::to_string(&config.redacted())?);
+                    return Ok(ExitCode::SUCCESS);
+                }
+                let s = server::Server::new(config);
                 s.run().await?;
             }
+            Command::Secrets(SecretsCommand::GenerateKey { id }) => {
+                println!("{}", secrets::Keyring::generate(&id));
+            }
+            Command::Secrets(SecretsCommand::Rotate { config }) => {
+                let config = config::Config::load(config.as_deref())?;
+                config.validate()?;
+                let storage = storage::open(&config.storage).await?;
+                let rotated = secrets::Secrets::new(&config.secrets, storage)?
+                    .rotate()
+                    .await?;
+                println!("Encrypted {} secrets with the current key", rotated);
+            }
         }
         Ok(ExitCode::SUCCESS)
     }
@@ -56,35 +93,67 @@ impl Cli {
 #[derive(clap::Args, Debug)]
 #[command(about = "Run the api server", args_conflicts_with_subcommands = true)]
 pub struct Run {
-    #[command(flatten)]
-    pub(crate) snyk: Snyk,
+    /// Configuration file (YAML or TOML)
+    #[arg(short = 'c', long = "config")]
+    pub(crate) config: Option<PathBuf>,
 
-    #[arg(short, long, default_value = "0.0.0.0")]
-    pub(crate) bind: String,
+    /// Print the effective configuration and exit
+    #[arg(long = "dump-config")]
+    pub(crate) dump_config: bool,
 
-    #[arg(short = 'p', long = "port", default_value_t = 8080)]
-    pub(crate) port: u16,
+    #[arg(short, long)]
+    pub(crate) bind: Option<String>,
 
-    #[arg(
-        short = 'g',
-        long = "guac",
-        default_value = "http://localhost:8080/query"
-    )]
-    pub(crate) guac_url: String,
-}
+    #[arg(short = 'p', long = "port")]
+    pub(crate) port: Option<u16>,
+
+    #[arg(short = 'g', long = "guac")]
+    pub(crate) guac_url: Option<String>,
 
-#[derive(clap::Args, Debug, Clone)]
-#[group(required = false)]
-pub struct Snyk {
     #[arg(long = "snyk-org")]
-    pub(crate) org: Option<String>,
+    pub(crate) snyk_org: Option<String>,
 
     #[arg(long = "snyk-token")]
-    pub(crate) token: Option<String>,
+    pub(crate) snyk_token: Option<String>,
+
+    /// Serve Guac and Snyk from in-process mocks seeded with fixtures, for development and tests
+    #[arg(long = "devmode")]
+    pub(crate) devmode: bool,
+
+    /// The fixtures of the dev mode, instead of the built-in ones
+    #[arg(long = "fixtures", requires = "devmode")]
+    pub(crate) fixtures: Option<PathBuf>,
+}
+
+impl Run {
+    /// Load the configuration, applying the command line arguments on top.
+    fn config(&self) -> anyhow::Result<config::Config> {
+        let mut config = config::Config::load(self.config.as_deref())?;
+        if let Some(bind) = &self.bind {
+            config.bind = bind.clone();
+        }
+        if let Some(port) = self.port {
+            config.port = port;
+        }
+        if let Some(guac_url) = &self.guac_url {
+            config.guac.url = guac_url.clone();
+        }
+        if let Some(org) = &self.snyk_org {
+            config.snyk.org = Some(org.clone());
+        }
+        if let Some(token) = &self.snyk_token {
+            config.snyk.token = Some(token.clone());
+        }
+        if self.devmode {
+            // the admin listener is bound to localhost by default
+            config.admin.allow_unauthenticated = true;
+        }
+        config.validate()?;
+        Ok(config)
+    }
 }
 
 #[tokio::main]
 async fn main() -> impl Termination {
-    env_logger::init();
     Cli::parse().run().await
 }
@@ -0,0 +1,103 @@
+use once_cell::sync::Lazy;
+use prometheus::core::Collector;
+use prometheus::{
+    exponential_buckets, register_histogram_vec, register_int_counter_vec, register_int_gauge_vec,
+    Encoder, HistogramVec, IntCounterVec, IntGaugeVec, TextEncoder,
+};
+use std::collections::BTreeMap;
+
+/// State of the circuit breaker of an upstream service (0 = closed, 1 = half-open, 2 = open).
+pub static CIRCUIT_BREAKER_STATE: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "upstream_circuit_breaker_state",
+        "State of the circuit breaker of an upstream service (0 = closed, 1 = half-open, 2 = open)",
+        &["upstream"]
+    )
+    .unwrap()
+});
+
+pub static UPSTREAM_CALLS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "upstream_calls_total",
+        "Number of calls to an upstream service, counting retried calls once",
+        &["upstream", "operation"]
+    )
+    .unwrap()
+});
+
+pub static UPSTREAM_RETRIES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "upstream_retries_total",
+        "Number of retried calls to an upstream service",
+        &["upstream", "operation"]
+    )
+    .unwrap()
+});
+
+pub static UPSTREAM_FAILURES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "upstream_failures_total",
+        "Number of failed calls to an upstream service, after all retries",
+        &["upstream", "operation"]
+    )
+    .unwrap()
+});
+
+/// Duration of calls to an upstream service, by operation, so the queries dominating response
+/// times stand out.
+pub static UPSTREAM_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "upstream_call_duration_seconds",
+        "Duration of calls to an upstream service, including retries",
+        &["upstream", "operation"],
+        vec![0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0]
+    )
+    .unwrap()
+});
+
+/// Size of the requests sent to an upstream service: the body of the GraphQL queries to Guac,
+/// the URL of the requests to Snyk, which have no body.
+pub static UPSTREAM_REQUEST_SIZE: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "upstream_request_size_bytes",
+        "Size of the bodies of requests to an upstream service",
+        &["upstream", "operation"],
+        exponential_buckets(64.0, 4.0, 10).unwrap()
+    )
+    .unwrap()
+});
+
+/// Size of the bodies received from an upstream service, as for [`UPSTREAM_REQUEST_SIZE`].
+pub static UPSTREAM_RESPONSE_SIZE: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "upstream_response_size_bytes",
+        "Size of the bodies of responses of an upstream service",
+        &["upstream", "operation"],
+        exponential_buckets(64.0, 4.0, 10).unwrap()
+    )
+    .unwrap()
+});
+
+/// The value of a counter by its `upstream` label, summed over its other labels.
+pub fn by_upstream(counter: &IntCounterVec) -> BTreeMap<String, u64> {
+    let mut totals = BTreeMap::new();
+    for family in counter.collect() {
+        for metric in family.get_metric() {
+            let upstream = metric
+                .get_label()
+                .iter()
+                .find(|label| label.get_name() == "upstream")
+                .map(|label| label.get_value().to_string())
+                .unwrap_or_default();
+            *totals.entry(upstream).or_insert(0) += metric.get_counter().get_value() as u64;
+        }
+    }
+    totals
+}
+
+/// Render all registered metrics in the Prometheus text format.
+pub fn render() -> Result<String, anyhow::Error> {
+    let mut buffer = Vec::new();
+    TextEncoder::new().encode(&prometheus::gather(), &mut buffer)?;
+    Ok(String::from_utf8(buffer)?)
+}
@@ -0,0 +1,554 @@
+use chrono::{DateTime, Duration as Age, SecondsFormat, Utc};
+use core::str::FromStr;
+use packageurl::PackageUrl;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+use crate::config::ResilienceConfig;
+use crate::cpe::{Cpe, CpeMapping};
+use crate::package::{ProviderId, VulnerabilityRef, VulnerabilitySource};
+use crate::resilience::Resilience;
+use crate::storage::Storage;
+use crate::version::Scheme;
+
+/// The largest page of the NVD API.
+const PAGE_SIZE: usize = 2000;
+/// The longest range of modification dates the NVD API accepts, older stores are synced fully.
+const MAX_RANGE_DAYS: i64 = 120;
+/// How often a rate limited or failing request is retried.
+const MAX_RETRIES: u32 = 5;
+/// The storage key of the state of the sync.
+const STATE_KEY: &str = "nvd/state.json";
+/// The storage keys of the CVEs of a year start with this.
+const CVES_PREFIX: &str = "nvd/cves-";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NvdConfig {
+    /// Merge the vulnerabilities of NVD into package results
+    pub enabled: bool,
+    pub url: String,
+    /// Raises the rate limit of the API from 5 to 50 requests in 30 seconds
+    pub api_key: Option<String>,
+    /// How often the store is synced, in seconds
+    pub interval_secs: u64,
+}
+
+impl Default for NvdConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            url: "https://services.nvd.nist.gov/rest/json/cves/2.0".to_string(),
+            api_key: None,
+            interval_secs: 7200,
+        }
+    }
+}
+
+impl NvdConfig {
+    pub fn redacted(&self, redacted: &str) -> Self {
+        Self {
+            api_key: self.api_key.as_ref().map(|_| redacted.to_string()),
+            ..self.clone()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CvesResponse {
+    total_results: usize,
+    #[serde(default)]
+    vulnerabilities: Vec<CveItem>,
+}
+
+#[derive(Deserialize)]
+struct CveItem {
+    cve: Cve,
+}
+
+#[derive(Deserialize)]
+struct Cve {
+    id: String,
+    #[serde(default)]
+    configurations: Vec<Configuration>,
+}
+
+#[derive(Deserialize)]
+struct Configuration {
+    #[serde(default)]
+    nodes: Vec<Node>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Node {
+    #[serde(default)]
+    negate: bool,
+    #[serde(default)]
+    cpe_match: Vec<CpeMatch>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CpeMatch {
+    vulnerable: bool,
+    criteria: String,
+    version_start_including: Option<String>,
+    version_start_excluding: Option<String>,
+    version_end_including: Option<String>,
+    version_end_excluding: Option<String>,
+}
+
+/// The products a CVE affects, by their CPE and a range of versions.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Affected {
+    criteria: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    start_including: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    start_excluding: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    end_including: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    end_excluding: Option<String>,
+}
+
+impl Affected {
+    /// Whether a version is affected, `exact` is the version of the CPE itself. Bounds are
+    /// compared by the rules of the ecosystem of the package.
+    fn matches(&self, scheme: Scheme, version: &str, exact: Option<&str>) -> bool {
+        let bounded = self.start_including.is_some()
+            || self.start_excluding.is_some()
+            || self.end_including.is_some()
+            || self.end_excluding.is_some();
+        if !bounded {
+            return exact.map_or(true, |exact| {
+                scheme.compare(exact, version) == Ordering::Equal
+            });
+        }
+        let cmp = |bound: &Option<String>| bound.as_ref().map(|b| scheme.compare(version, b));
+        cmp(&self.start_including).map_or(true, |o| o != Ordering::Less)
+            && cmp(&self.start_excluding).map_or(true, |o| o == Ordering::Greater)
+            && cmp(&self.end_including).map_or(true, |o| o != Ordering::Greater)
+            && cmp(&self.end_excluding).map_or(true, |o| o == Ordering::Less)
+    }
+}
+
+/// The synced CVEs, persisted to the storage by the year of their id.
+#[derive(Default)]
+struct NvdStore {
+    last_sync: Option<DateTime<Utc>>,
+    cves: BTreeMap<String, Vec<Affected>>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SyncState {
+    last_sync: Option<DateTime<Utc>>,
+}
+
+/// The year of a CVE id, e.g. `2023` of `CVE-2023-24815`.
+fn year(cve: &str) -> &str {
+    cve.split('-').nth(1).unwrap_or("unknown")
+}
+
+impl NvdStore {
+    async fn load(storage: &dyn Storage) -> Result<Self, anyhow::Error> {
+        let mut store = Self::default();
+        for key in storage.list(CVES_PREFIX).await? {
+            if let Some(data) = storage.get(&key).await? {
+                let cves: BTreeMap<String, Vec<Affected>> = serde_json::from_slice(&data)?;
+                store.cves.extend(cves);
+            }
+        }
+        if let Some(data) = storage.get(STATE_KEY).await? {
+            store.last_sync = serde_json::from_slice::<SyncState>(&data)?.last_sync;
+        }
+        Ok(store)
+    }
+
+    /// Write the CVEs of the `years` which changed, and the state of the sync.
+    async fn save(
+        &self,
+        storage: &dyn Storage,
+        years: &BTreeSet<String>,
+    ) -> Result<(), anyhow::Error> {
+        for y in years {
+            let cves: BTreeMap<&String, &Vec<Affected>> =
+                self.cves.iter().filter(|(cve, _)| year(cve) == y).collect();
+            let key = format!("{}{}.json", CVES_PREFIX, y);
+            storage.put(&key, serde_json::to_vec(&cves)?).await?;
+        }
+        let state = SyncState {
+            last_sync: self.last_sync,
+        };
+        storage.put(STATE_KEY, serde_json::to_vec(&state)?).await
+    }
+}
+
+/// An affected range of a CVE, indexed by the package it maps to.
+struct IndexEntry {
+    cve: String,
+    exact: Option<String>,
+    affected: Affected,
+}
+
+/// The purl of a package without its version, qualifiers and subpath, normalized.
+fn base_purl(purl: &PackageUrl<'_>) -> String {
+    let base = match purl.namespace() {
+        Some(namespace) => format!("pkg:{}/{}/{}", purl.ty(), namespace, purl.name()),
+        None => format!("pkg:{}/{}", purl.ty(), purl.name()),
+    };
+    crate::purl::normalize(&base).unwrap_or(base)
+}
+
+/// Syncs the CVEs of the NVD into the storage, and matches them to packages through the CPEs
+/// of the products they affect.
+pub struct Nvd {
+    url: String,
+    api_key: Option<String>,
+    storage: Arc<dyn Storage>,
+    client: reqwest::Client,
+    /// Fails the sync fast while the NVD API keeps failing, the pages retry on their own
+    resilience: Resilience,
+    cpe: Arc<CpeMapping>,
+    store: Mutex<NvdStore>,
+    index: RwLock<HashMap<String, Vec<IndexEntry>>>,
+    /// When the indexed CVEs were synced
+    synced: RwLock<Option<DateTime<Utc>>>,
+}
+
+impl Nvd {
+    pub async fn new(
+        config: &NvdConfig,
+        cpe: Arc<CpeMapping>,
+        storage: Arc<dyn Storage>,
+    ) -> Result<Self, anyhow::Error> {
+        let store = NvdStore::load(storage.as_ref()).await?;
+        let nvd = Self {
+            url: config.url.clone(),
+            api_key: config.api_key.clone(),
+            storage,
+            client: reqwest::Client::new(),
+            resilience: Resilience::new("nvd", ResilienceConfig::default()),
+            cpe,
+            index: RwLock::new(HashMap::new()),
+            synced: RwLock::new(None),
+            store: Mutex::new(NvdStore::default()),
+        };
+        nvd.reindex(&store);
+        *nvd.store.lock().await = store;
+        Ok(nvd)
+    }
+
+    /// The vulnerabilities of a package version known to the NVD.
+    pub fn vulnerabilities(&self, purl: &PackageUrl<'_>, version: &str) -> Vec<VulnerabilityRef> {
+        let index = self.index.read().unwrap();
+        let fetched = *self.synced.read().unwrap();
+        let scheme = Scheme::of(purl.ty());
+        let mut vulns: Vec<VulnerabilityRef> = Vec::new();
+        for entry in index.get(&base_purl(purl)).into_iter().flatten() {
+            if !entry
+                .affected
+                .matches(scheme, version, entry.exact.as_deref())
+                || vulns.iter().any(|v| v.cve == entry.cve)
+            {
+                continue;
+            }
+            let href = format!("https://nvd.nist.gov/vuln/detail/{}", entry.cve);
+            vulns.push(VulnerabilityRef {
+                cve: entry.cve.clone(),
+                href: href.clone(),
+                ids: vec![ProviderId {
+                    provider: "nvd".to_string(),
+                    id: entry.cve.clone(),
+                }],
+                fetched,
+                aliases: vec![],
+                severity: None,
+                sources: vec![VulnerabilitySource {
+                    provider: "nvd".to_string(),
+                    href,
+                    severity: None,
+                    fetched,
+                }],
+            });
+        }
+        vulns
+    }
+
+    /// Map the affected products of all CVEs to packages.
+    fn reindex(&self, store: &NvdStore) {
+        let mut index: HashMap<String, Vec<IndexEntry>> = HashMap::new();
+        for (cve, affected) in store.cves.iter() {
+            for affected in affected {
+                let mut product = match Cpe::parse(&affected.criteria) {
+                    // products without versions don't match any version of a package
+                    Some(product) if !product.version_not_applicable => product,
+                    _ => continue,
+                };
+                let exact = product.version.take();
+                for purl in self.cpe.map(&product) {
+                    let key = match PackageUrl::from_str(&purl) {
+                        Ok(purl) => base_purl(&purl),
+                        Err(_) => continue,
+                    };
+                    index.entry(key).or_default().push(IndexEntry {
+                        cve: cve.clone(),
+                        exact: exact.clone(),
+                        affected: affected.clone(),
+                    });
+                }
+            }
+        }
+        log::debug!("Indexed NVD data of {} packages", index.len());
+        *self.index.write().unwrap() = index;
+        *self.synced.write().unwrap() = store.last_sync;
+    }
+
+    /// Fetch a page of CVEs, backing off while rate limited or the API fails.
+    async fn page(
+        &self,
+        range: Option<(DateTime<Utc>, DateTime<Utc>)>,
+        start: usize,
+    ) -> Result<CvesResponse, anyhow::Error> {
+        self.resilience
+            .guard("cves", self.page_attempts(range, start))
+            .await
+    }
+
+    async fn page_attempts(
+        &self,
+        range: Option<(DateTime<Utc>, DateTime<Utc>)>,
+        start: usize,
+    ) -> Result<CvesResponse, anyhow::Error> {
+        let mut query = vec![
+            ("resultsPerPage", PAGE_SIZE.to_string()),
+            ("startIndex", start.to_string()),
+        ];
+        if let Some((from, to)) = range {
+            query.push((
+                "lastModStartDate",
+                from.to_rfc3339_opts(SecondsFormat::Millis, true),
+            ));
+            query.push((
+                "lastModEndDate",
+                to.to_rfc3339_opts(SecondsFormat::Millis, true),
+            ));
+        }
+
+        let mut backoff = Duration::from_secs(30);
+        let mut attempt = 0;
+        loop {
+            let mut request = self.client.get(&self.url).query(&query);
+            if let Some(key) = &self.api_key {
+                request = request.header("apiKey", key);
+            }
+            crate::chaos::inject("nvd").await?;
+            let result = match request.send().await {
+                Ok(response) => response.error_for_status(),
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(response) => return Ok(response.json().await?),
+                Err(e) if attempt < MAX_RETRIES => {
+                    log::info!("NVD request failed, retrying in {:?}: {}", backoff, e);
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Fetch the CVEs modified since the last sync, or all of them if the store is empty or too
+    /// old, returning the number of CVEs updated.
+    async fn sync(&self) -> Result<usize, anyhow::Error> {
+        let now = Utc::now();
+        let mut store = self.store.lock().await;
+        let range = store
+            .last_sync
+            .filter(|last| now - *last < Age::days(MAX_RANGE_DAYS))
+            .map(|last| (last, now));
+        // stay below the rate limit of the API, which is lower without a key
+        let pause = if self.api_key.is_some() {
+            Duration::from_millis(600)
+        } else {
+            Duration::from_secs(6)
+        };
+
+        let mut updated = 0;
+        let mut years = BTreeSet::new();
+        let mut start = 0;
+        loop {
+            let page = self.page(range, start).await?;
+            for item in page.vulnerabilities.iter() {
+                let affected: Vec<Affected> = item
+                    .cve
+                    .configurations
+                    .iter()
+                    .flat_map(|c| c.nodes.iter())
+                    .filter(|n| !n.negate)
+                    .flat_map(|n| n.cpe_match.iter())
+                    .filter(|m| m.vulnerable)
+                    .map(|m| Affected {
+                        criteria: m.criteria.clone(),
+                        start_including: m.version_start_including.clone(),
+                        start_excluding: m.version_start_excluding.clone(),
+                        end_including: m.version_end_including.clone(),
+                        end_excluding: m.version_end_excluding.clone(),
+                    })
+                    .collect();
+                if affected.is_empty() {
+                    store.cves.remove(&item.cve.id);
+                } else {
+                    store.cves.insert(item.cve.id.clone(), affected);
+                }
+                years.insert(year(&item.cve.id).to_string());
+                updated += 1;
+            }
+            start += page.vulnerabilities.len();
+            if page.vulnerabilities.is_empty() || start >= page.total_results {
+                break;
+            }
+            tokio::time::sleep(pause).await;
+        }
+
+        store.last_sync = Some(now);
+        store.save(self.storage.as_ref(), &years).await?;
+        self.reindex(&store);
+        Ok(updated)
+    }
+
+    /// Run the background sync loop.
+    pub async fn run(self: Arc<Self>, interval: Duration) {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+            match self.sync().await {
+                Ok(updated) => log::info!("Synced {} CVEs from NVD", updated),
+                Err(e) => log::warn!("Error syncing NVD: {:?}", e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpe::CpeConfig;
+    use crate::storage::FileStorage;
+
+    fn affected(
+        criteria: &str,
+        start_including: Option<&str>,
+        end_excluding: Option<&str>,
+    ) -> Affected {
+        Affected {
+            criteria: criteria.to_string(),
+            start_including: start_including.map(ToString::to_string),
+            start_excluding: None,
+            end_including: None,
+            end_excluding: end_excluding.map(ToString::to_string),
+        }
+    }
+
+    fn store() -> NvdStore {
+        let mut store = NvdStore::default();
+        for (cve, affected) in [
+            (
+                "CVE-2023-0001",
+                affected(
+                    "cpe:2.3:a:eclipse:vert.x:*:*:*:*:*:*:*:*",
+                    Some("4.0.0"),
+                    Some("4.3.5"),
+                ),
+            ),
+            // an unrelated product of the same name
+            (
+                "CVE-2023-0002",
+                affected("cpe:2.3:a:example:lodash:*:*:*:*:*:node.js:*:*", None, None),
+            ),
+            (
+                "CVE-2022-0003",
+                affected("cpe:2.3:a:lodash:lodash:-:*:*:*:*:node.js:*:*", None, None),
+            ),
+            (
+                "CVE-2022-0004",
+                affected(
+                    "cpe:2.3:a:lodash:lodash:4.17.20:*:*:*:*:node.js:*:*",
+                    None,
+                    None,
+                ),
+            ),
+        ] {
+            store.cves.insert(cve.to_string(), vec![affected]);
+        }
+        store
+    }
+
+    async fn nvd(storage: Arc<dyn Storage>) -> Nvd {
+        let cpe = Arc::new(CpeMapping::new(&CpeConfig::default(), vec![]));
+        Nvd::new(&NvdConfig::default(), cpe, storage).await.unwrap()
+    }
+
+    fn cves(nvd: &Nvd, purl: &str) -> Vec<String> {
+        let purl = PackageUrl::from_str(purl).unwrap();
+        let version = purl.version().unwrap().to_string();
+        nvd.vulnerabilities(&purl, &version)
+            .into_iter()
+            .map(|v| v.cve)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn products_are_matched_by_vendor_product_and_version() {
+        let dir = std::env::temp_dir().join(format!("trust-api-nvd-{}", uuid::Uuid::new_v4()));
+        let nvd = nvd(Arc::new(FileStorage::new(dir))).await;
+        nvd.reindex(&store());
+
+        assert_eq!(
+            cves(&nvd, "pkg:maven/io.vertx/vertx-web@4.3.4.redhat-00007"),
+            vec!["CVE-2023-0001"]
+        );
+        assert!(cves(&nvd, "pkg:maven/io.vertx/vertx-web@4.3.5").is_empty());
+        assert!(cves(&nvd, "pkg:maven/io.vertx/vertx-web@3.9.16").is_empty());
+        assert_eq!(cves(&nvd, "pkg:npm/lodash@4.17.20"), vec!["CVE-2022-0004"]);
+        assert!(cves(&nvd, "pkg:npm/lodash@4.17.21").is_empty());
+    }
+
+    #[tokio::test]
+    async fn cves_are_stored_by_year() {
+        let dir = std::env::temp_dir().join(format!("trust-api-nvd-{}", uuid::Uuid::new_v4()));
+        let storage: Arc<dyn Storage> = Arc::new(FileStorage::new(dir.clone()));
+        let mut store = store();
+        store.last_sync = Some(Utc::now());
+
+        let years = BTreeSet::from(["2023".to_string()]);
+        store.save(storage.as_ref(), &years).await.unwrap();
+        assert_eq!(
+            storage.list(CVES_PREFIX).await.unwrap(),
+            vec!["nvd/cves-2023.json"]
+        );
+        let loaded = NvdStore::load(storage.as_ref()).await.unwrap();
+        assert_eq!(loaded.last_sync, store.last_sync);
+        assert_eq!(
+            loaded.cves.keys().collect::<Vec<_>>(),
+            vec!["CVE-2023-0001", "CVE-2023-0002"]
+        );
+
+        let years = BTreeSet::from(["2022".to_string(), "2023".to_string()]);
+        store.save(storage.as_ref(), &years).await.unwrap();
+        let loaded = NvdStore::load(storage.as_ref()).await.unwrap();
+        assert_eq!(loaded.cves.len(), 4);
+        assert_eq!(nvd(storage).await.index.read().unwrap().len(), 3);
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
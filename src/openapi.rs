@@ -0,0 +1,368 @@
+use serde_json::Value;
+use std::collections::BTreeSet;
+use utoipa::openapi::schema::{AllOfBuilder, ObjectBuilder, Ref, Schema, SchemaType};
+use utoipa::openapi::security::{
+    ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityRequirement, SecurityScheme,
+};
+use utoipa::openapi::{ComponentsBuilder, OpenApi as Document, RefOr, Server};
+use utoipa::OpenApi;
+use utoipa_swagger_ui::Config as SwaggerConfig;
+
+use crate::config::Config;
+use crate::{
+    analyze, coordinates, cpe, events, export, gate, image, index, package, problem, product,
+    report, search, vulnerability, watch,
+};
+
+/// The document information and tags, the paths are contributed by the endpoint modules.
+#[derive(OpenApi)]
+#[openapi(
+    components(schemas(problem::Problem)),
+    tags(
+        (name = "package", description = "Package query endpoints."),
+        (name = "vulnerability", description = "Vulnerability query endpoints")
+    )
+)]
+struct ApiDoc;
+
+/// The OpenAPI document of the public API.
+///
+/// Each endpoint module documents the routes it registers in its own `ApiDoc`, next to its
+/// `configure`, so routes are added to the document along with their handlers.
+pub fn document(config: &Config) -> Document {
+    let mut doc = ApiDoc::openapi();
+    for module in [
+        index::ApiDoc::openapi(),
+        package::ApiDoc::openapi(),
+        cpe::ApiDoc::openapi(),
+        coordinates::ApiDoc::openapi(),
+        analyze::ApiDoc::openapi(),
+        image::ApiDoc::openapi(),
+        vulnerability::ApiDoc::openapi(),
+        events::ApiDoc::openapi(),
+        watch::ApiDoc::openapi(),
+        search::ApiDoc::openapi(),
+        report::ApiDoc::openapi(),
+        export::ApiDoc::openapi(),
+        product::ApiDoc::openapi(),
+        gate::ApiDoc::openapi(),
+    ] {
+        doc.merge(with_error_schema(module));
+    }
+
+    let components = doc
+        .components
+        .get_or_insert_with(|| ComponentsBuilder::new().build());
+    components.add_security_scheme(
+        "bearer",
+        SecurityScheme::Http(
+            HttpBuilder::new()
+                .scheme(HttpAuthScheme::Bearer)
+                .bearer_format("JWT")
+                .description(Some("An ID token of one of the issuers in `auth.issuers`"))
+                .build(),
+        ),
+    );
+    components.add_security_scheme(
+        "apiKey",
+        SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::with_description(
+            "X-Api-Key",
+            "Required for bypassing the caches with `?fresh=true`, one of `api.fresh.api_keys`",
+        ))),
+    );
+    // try-it-out sends requests to the versioned paths through the reverse proxy, if any
+    let base_path = config.api.docs.base_path();
+    doc.servers = Some(vec![Server::new(match base_path {
+        "" => "/",
+        base_path => base_path,
+    })]);
+    // the API is only protected if identity providers are configured
+    if !config.auth.issuers.is_empty() {
+        doc.security = Some(vec![SecurityRequirement::new(
+            "bearer",
+            Vec::<String>::new(),
+        )]);
+    }
+    doc
+}
+
+/// Describe the error responses of the endpoints of a module by its error type.
+///
+/// The schema of the error type (e.g. `PackageApiError`) is replaced by a `Problem` with the
+/// codes of its variants, which the error responses of the module then reference instead of the
+/// plain `Problem`.
+fn with_error_schema(mut module: Document) -> Document {
+    let schemas = match module.components.as_mut() {
+        Some(components) => &mut components.schemas,
+        None => return module,
+    };
+    let (name, schema) = match schemas
+        .iter_mut()
+        .find(|(name, _)| name.ends_with("ApiError"))
+    {
+        Some((name, schema)) => (name.clone(), schema),
+        None => return module,
+    };
+    let mut codes = BTreeSet::new();
+    error_codes(
+        &serde_json::to_value(&*schema).unwrap_or_default(),
+        &mut codes,
+    );
+    let code = ObjectBuilder::new().property(
+        "code",
+        ObjectBuilder::new()
+            .schema_type(SchemaType::String)
+            .enum_values(Some(codes)),
+    );
+    *schema = RefOr::T(Schema::AllOf(
+        AllOfBuilder::new()
+            .description(Some(
+                "A problem, with the codes of the errors of the endpoints",
+            ))
+            .item(Ref::from_schema_name("Problem"))
+            .item(code)
+            .build(),
+    ));
+
+    for content in module
+        .paths
+        .paths
+        .values_mut()
+        .flat_map(|item| item.operations.values_mut())
+        .flat_map(|operation| operation.responses.responses.values_mut())
+        .filter_map(|response| match response {
+            RefOr::T(response) => Some(response),
+            RefOr::Ref(_) => None,
+        })
+        .flat_map(|response| response.content.values_mut())
+    {
+        if matches!(&content.schema, RefOr::Ref(r) if r.ref_location == PROBLEM_REF) {
+            content.schema = RefOr::Ref(Ref::from_schema_name(&name));
+        }
+    }
+    module
+}
+
+const PROBLEM_REF: &str = "#/components/schemas/Problem";
+
+/// The codes of the variants of an error type, by its schema.
+///
+/// Variants wrapping another error type (e.g. `Upstream(package::ApiError)`) answer with the codes
+/// of the wrapped type.
+fn error_codes(schema: &Value, codes: &mut BTreeSet<String>) {
+    if let Some(variants) = schema.get("oneOf").and_then(|v| v.as_array()) {
+        for variant in variants {
+            error_codes(variant, codes);
+        }
+    } else if let Some(units) = schema.get("enum").and_then(|e| e.as_array()) {
+        codes.extend(
+            units
+                .iter()
+                .filter_map(|u| u.as_str())
+                .map(ToString::to_string),
+        );
+    } else if let Some(reference) = schema.get("$ref").and_then(|r| r.as_str()) {
+        // utoipa references the wrapped type rather than inlining it, by its path
+        if reference.ends_with("package.ApiError") {
+            let (_, package) = <package::ApiError as utoipa::ToSchema>::schema();
+            error_codes(&serde_json::to_value(package).unwrap_or_default(), codes);
+        }
+    } else if let Some(properties) = schema.get("properties").and_then(|p| p.as_object()) {
+        for (variant, fields) in properties {
+            match ["oneOf", "enum", "$ref"]
+                .iter()
+                .any(|key| fields.get(key).is_some())
+            {
+                true => error_codes(fields, codes),
+                false => {
+                    codes.insert(variant.clone());
+                }
+            }
+        }
+    }
+}
+
+/// The configuration of the Swagger UI, loading the document through the reverse proxy, and
+/// keeping the tokens entered with "Authorize" across reloads only if configured.
+pub fn swagger_config(config: &Config) -> SwaggerConfig<'static> {
+    SwaggerConfig::new([config.api.docs.document_path()])
+        .try_it_out_enabled(true)
+        .persist_authorization(config.api.docs.persist_authorization)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// The sources of the modules whose routes `Engine::scope` registers.
+    const MODULES: &[&str] = &[
+        include_str!("index.rs"),
+        include_str!("package.rs"),
+        include_str!("cpe.rs"),
+        include_str!("coordinates.rs"),
+        include_str!("analyze.rs"),
+        include_str!("image.rs"),
+        include_str!("vulnerability.rs"),
+        include_str!("events.rs"),
+        include_str!("watch.rs"),
+        include_str!("search.rs"),
+        include_str!("report.rs"),
+        include_str!("export.rs"),
+        include_str!("product.rs"),
+        include_str!("gate.rs"),
+    ];
+
+    /// A route without the regular expressions of its parameters, `{id:\d+}` becomes `{id}`.
+    fn without_patterns(route: &str) -> String {
+        let mut path = String::new();
+        let mut depth = 0;
+        let mut pattern = false;
+        for c in route.chars() {
+            match c {
+                '{' => {
+                    depth += 1;
+                    if depth == 1 {
+                        path.push(c);
+                    }
+                }
+                '}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        pattern = false;
+                        path.push(c);
+                    }
+                }
+                ':' if depth == 1 => pattern = true,
+                _ if pattern || depth > 1 => {}
+                _ => path.push(c),
+            }
+        }
+        path
+    }
+
+    /// The method and full path of each handler registered with `config.service`, from the
+    /// route macro of the handler and the `context_path` of its documentation.
+    fn registered() -> BTreeSet<(String, String)> {
+        let mut routes = BTreeSet::new();
+        for source in MODULES {
+            let mut context = "";
+            let mut lines = source.lines().map(str::trim);
+            while let Some(line) = lines.next() {
+                if let Some(path) = line.strip_prefix("context_path = \"") {
+                    context = path.trim_end_matches(['"', ',']);
+                    continue;
+                }
+                for method in ["get", "post", "put", "delete", "patch"] {
+                    let route = match line
+                        .strip_prefix(&format!("#[{}(\"", method))
+                        .and_then(|r| r.strip_suffix("\")]"))
+                    {
+                        Some(route) => route,
+                        None => continue,
+                    };
+                    let handler = lines
+                        .by_ref()
+                        .find_map(|l| l.split_once("fn ").map(|(_, f)| f))
+                        .and_then(|f| f.split(['(', '<']).next())
+                        .unwrap_or_default();
+                    if source.contains(&format!("config.service({});", handler)) {
+                        routes.insert((
+                            method.to_string(),
+                            without_patterns(&format!("{}{}", context, route)),
+                        ));
+                    }
+                    context = "";
+                }
+            }
+        }
+        routes
+    }
+
+    #[test]
+    fn error_responses_reference_the_error_types() {
+        let document = serde_json::to_value(document(&Config::default())).unwrap();
+        let schemas = document["components"]["schemas"].as_object().unwrap();
+        let errors: Vec<&String> = schemas
+            .keys()
+            .filter(|name| name.ends_with("ApiError"))
+            .collect();
+        assert!(errors.len() > 10, "{:?}", errors);
+
+        let mut referenced = BTreeSet::new();
+        for (path, item) in document["paths"].as_object().unwrap() {
+            for (status, response) in item
+                .as_object()
+                .unwrap()
+                .values()
+                .filter_map(|operation| operation["responses"].as_object())
+                .flatten()
+            {
+                let reference = response["content"]["application/json"]["schema"]["$ref"]
+                    .as_str()
+                    .unwrap_or_default();
+                assert_ne!(reference, PROBLEM_REF, "{} {}", path, status);
+                if let Some(name) = reference.strip_prefix("#/components/schemas/") {
+                    referenced.insert(name.to_string());
+                }
+            }
+        }
+        for error in errors {
+            assert!(referenced.contains(error), "{} is not referenced", error);
+        }
+
+        let codes = &schemas["PackageApiError"]["allOf"][1]["properties"]["code"]["enum"];
+        assert!(codes
+            .as_array()
+            .unwrap()
+            .contains(&json!("PackageNotFound")));
+        let codes = &schemas["ImageApiError"]["allOf"][1]["properties"]["code"]["enum"];
+        for code in ["SbomNotFound", "UpstreamUnavailable"] {
+            assert!(codes.as_array().unwrap().contains(&json!(code)), "{}", code);
+        }
+        assert!(!codes.as_array().unwrap().contains(&json!("Upstream")));
+    }
+
+    #[test]
+    fn patterns_are_removed_from_routes() {
+        assert_eq!(
+            without_patterns("/sbom/{sha256:[0-9a-fA-F]{64}}"),
+            "/sbom/{sha256}"
+        );
+        assert_eq!(
+            without_patterns("/product/{id}/summary"),
+            "/product/{id}/summary"
+        );
+    }
+
+    #[test]
+    fn every_registered_route_is_documented() {
+        let document = serde_json::to_value(document(&Config::default())).unwrap();
+        let documented: BTreeSet<(String, String)> = document["paths"]
+            .as_object()
+            .unwrap()
+            .iter()
+            .flat_map(|(path, item)| {
+                item.as_object()
+                    .into_iter()
+                    .flatten()
+                    .map(move |(method, _)| (method.clone(), without_patterns(path)))
+            })
+            .filter(|(method, _)| {
+                ["get", "post", "put", "delete", "patch"].contains(&method.as_str())
+            })
+            .collect();
+        let registered = registered();
+        assert!(registered.len() > 30, "{:?}", registered);
+        assert_eq!(
+            registered.difference(&documented).collect::<Vec<_>>(),
+            Vec::<&(String, String)>::new(),
+            "registered, but not documented"
+        );
+        assert_eq!(
+            documented.difference(&registered).collect::<Vec<_>>(),
+            Vec::<&(String, String)>::new(),
+            "documented, but not registered"
+        );
+    }
+}
@@ -0,0 +1,209 @@
+use chrono::Utc;
+use packageurl::PackageUrl;
+use serde::{Deserialize, Serialize};
+
+use crate::config::ResilienceConfig;
+use crate::package::{ProviderId, Severity, VulnerabilityRef, VulnerabilitySource};
+use crate::resilience::Resilience;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OsvConfig {
+    /// Add the vulnerabilities known to OSV of packages to their vulnerabilities
+    pub enabled: bool,
+    pub url: String,
+    /// Retries, timeouts and the circuit breaker of calls to the OSV API
+    pub resilience: ResilienceConfig,
+}
+
+impl Default for OsvConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            url: "https://api.osv.dev".to_string(),
+            resilience: Default::default(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct QueryResponse {
+    #[serde(default)]
+    vulns: Vec<Vulnerability>,
+    next_page_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Vulnerability {
+    id: String,
+    #[serde(default)]
+    aliases: Vec<String>,
+    withdrawn: Option<String>,
+    /// The severity of GitHub advisories, e.g. `MODERATE`
+    database_specific: Option<DatabaseSpecific>,
+}
+
+#[derive(Deserialize)]
+struct DatabaseSpecific {
+    severity: Option<String>,
+}
+
+/// The purl of a package, without its version and qualifiers, as OSV expects it next to a
+/// version.
+fn package_purl(purl: &PackageUrl<'_>) -> String {
+    match purl.namespace() {
+        Some(namespace) => format!(
+            "pkg:{}/{}/{}",
+            purl.ty(),
+            namespace.replace('@', "%40"),
+            purl.name()
+        ),
+        None => format!("pkg:{}/{}", purl.ty(), purl.name()),
+    }
+}
+
+/// An OSV record, reported by its CVE where it has one, so it is merged with the same
+/// vulnerability reported by other providers.
+fn vulnerability_ref(vuln: Vulnerability) -> VulnerabilityRef {
+    let cve = std::iter::once(&vuln.id)
+        .chain(vuln.aliases.iter())
+        .find(|id| id.starts_with("CVE-"))
+        .unwrap_or(&vuln.id)
+        .clone();
+    let aliases = std::iter::once(vuln.id.clone())
+        .chain(vuln.aliases)
+        .filter(|id| !id.eq_ignore_ascii_case(&cve))
+        .collect();
+    let href = format!("https://osv.dev/vulnerability/{}", vuln.id);
+    let severity = vuln
+        .database_specific
+        .and_then(|d| d.severity)
+        .as_deref()
+        .and_then(Severity::parse);
+    let fetched = Some(Utc::now());
+    VulnerabilityRef {
+        cve,
+        href: href.clone(),
+        ids: vec![ProviderId {
+            provider: "osv".to_string(),
+            id: vuln.id,
+        }],
+        fetched,
+        aliases,
+        severity,
+        sources: vec![VulnerabilitySource {
+            provider: "osv".to_string(),
+            href,
+            severity,
+            fetched,
+        }],
+    }
+}
+
+/// The vulnerabilities of packages known to [OSV](https://osv.dev), from its query API.
+pub struct Osv {
+    url: String,
+    client: reqwest::Client,
+    resilience: Resilience,
+}
+
+impl Osv {
+    pub fn new(config: &OsvConfig) -> Self {
+        Self {
+            url: config.url.trim_end_matches('/').to_string(),
+            client: reqwest::Client::new(),
+            resilience: Resilience::new("osv", config.resilience.clone()),
+        }
+    }
+
+    /// The vulnerabilities affecting a package version, withdrawn ones left out.
+    pub async fn get_vulnerabilities(
+        &self,
+        purl: &PackageUrl<'_>,
+        version: &str,
+    ) -> Result<Vec<VulnerabilityRef>, anyhow::Error> {
+        let package = package_purl(purl);
+        let url = format!("{}/v1/query", self.url);
+        let mut vulns: Vec<VulnerabilityRef> = Vec::new();
+        let mut page_token: Option<String> = None;
+        loop {
+            let body = serde_json::json!({
+                "package": { "purl": package },
+                "version": version,
+                "page_token": page_token,
+            });
+            let response: QueryResponse = self
+                .resilience
+                .call("query", || async {
+                    self.client
+                        .post(&url)
+                        .json(&body)
+                        .send()
+                        .await?
+                        .error_for_status()?
+                        .json()
+                        .await
+                })
+                .await?;
+
+            for vuln in response.vulns {
+                if vuln.withdrawn.is_some()
+                    || vulns
+                        .iter()
+                        .any(|v| v.ids.iter().any(|id| id.id == vuln.id))
+                {
+                    continue;
+                }
+                vulns.push(vulnerability_ref(vuln));
+            }
+
+            match response.next_page_token {
+                Some(token) if !token.is_empty() => page_token = Some(token),
+                _ => break,
+            }
+        }
+        Ok(vulns)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn packages_are_queried_without_version_and_qualifiers() {
+        let purl = PackageUrl::from_str("pkg:npm/%40babel/core@7.22.5?arch=x86_64").unwrap();
+        assert_eq!(package_purl(&purl), "pkg:npm/%40babel/core");
+        let purl = PackageUrl::from_str("pkg:pypi/django@4.2.1").unwrap();
+        assert_eq!(package_purl(&purl), "pkg:pypi/django");
+    }
+
+    #[test]
+    fn records_are_reported_by_their_cve() {
+        let vuln: Vulnerability = serde_json::from_value(serde_json::json!({
+            "id": "GHSA-qppj-fm5r-hxr3",
+            "aliases": ["CVE-2023-44487"],
+            "database_specific": { "severity": "MODERATE" },
+        }))
+        .unwrap();
+        let vuln = vulnerability_ref(vuln);
+        assert_eq!(vuln.cve, "CVE-2023-44487");
+        assert_eq!(vuln.aliases, ["GHSA-qppj-fm5r-hxr3"]);
+        assert_eq!(vuln.ids[0].id, "GHSA-qppj-fm5r-hxr3");
+        assert_eq!(vuln.severity, Some(Severity::Medium));
+        assert_eq!(
+            vuln.href,
+            "https://osv.dev/vulnerability/GHSA-qppj-fm5r-hxr3"
+        );
+
+        let vuln: Vulnerability = serde_json::from_value(serde_json::json!({
+            "id": "PYSEC-2023-100",
+        }))
+        .unwrap();
+        let vuln = vulnerability_ref(vuln);
+        assert_eq!(vuln.cve, "PYSEC-2023-100");
+        assert!(vuln.aliases.is_empty());
+        assert_eq!(vuln.severity, None);
+    }
+}
@@ -0,0 +1,208 @@
+//! Checks of the URLs callers make the service send requests to, e.g. watch callbacks, so they
+//! can't be used to reach the internal network of the deployment.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum OutboundError {
+    #[error("{url} is not an HTTP(S) URL")]
+    Invalid { url: String },
+    #[error("{host} could not be resolved: {message}")]
+    Unresolved { host: String, message: String },
+    #[error("{host} resolves to the non-public address {address}")]
+    NonPublic { host: String, address: IpAddr },
+    #[error("{host} is not an allowed host")]
+    NotAllowed { host: String },
+}
+
+/// Check that a URL may be requested on behalf of a caller.
+///
+/// If `allowed_hosts` is not empty, the host of the URL must be one of them, an entry starting
+/// with `.` allowing all subdomains. Otherwise, all addresses the host resolves to must be public,
+/// neither loopback, link-local nor private.
+pub async fn check(url: &str, allowed_hosts: &[String]) -> Result<(), OutboundError> {
+    let invalid = || OutboundError::Invalid {
+        url: url.to_string(),
+    };
+    let uri = url.parse::<http::Uri>().map_err(|_| invalid())?;
+    let port = match uri.scheme_str() {
+        Some("http") => 80,
+        Some("https") => 443,
+        _ => return Err(invalid()),
+    };
+    let host = uri
+        .host()
+        .map(|h| h.trim_start_matches('[').trim_end_matches(']'))
+        .filter(|h| !h.is_empty())
+        .ok_or_else(invalid)?;
+
+    if !allowed_hosts.is_empty() {
+        return match is_allowed(host, allowed_hosts) {
+            true => Ok(()),
+            false => Err(OutboundError::NotAllowed {
+                host: host.to_string(),
+            }),
+        };
+    }
+
+    let addresses: Vec<IpAddr> = match host.parse::<IpAddr>() {
+        Ok(address) => vec![address],
+        Err(_) => tokio::net::lookup_host((host, uri.port_u16().unwrap_or(port)))
+            .await
+            .map_err(|e| OutboundError::Unresolved {
+                host: host.to_string(),
+                message: e.to_string(),
+            })?
+            .map(|a| a.ip())
+            .collect(),
+    };
+    match addresses.into_iter().find(|a| !is_public(a)) {
+        Some(address) => Err(OutboundError::NonPublic {
+            host: host.to_string(),
+            address,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Whether a host is obviously internal without resolving it, an internal IP address or
+/// `localhost`, for checks which can't wait for the resolver.
+pub fn is_internal_literal(host: &str) -> bool {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    match host.parse::<IpAddr>() {
+        Ok(address) => !is_public(&address),
+        Err(_) => {
+            let host = host.to_ascii_lowercase();
+            host == "localhost" || host.ends_with(".localhost")
+        }
+    }
+}
+
+/// A redirect policy following at most 10 redirects, none of them to an obviously internal host.
+pub fn redirect_policy() -> reqwest::redirect::Policy {
+    reqwest::redirect::Policy::custom(|attempt| {
+        if attempt.previous().len() >= 10 {
+            attempt.error("too many redirects")
+        } else if attempt.url().host_str().map_or(true, is_internal_literal) {
+            attempt.error("redirect to an internal address")
+        } else {
+            attempt.follow()
+        }
+    })
+}
+
+fn is_allowed(host: &str, allowed_hosts: &[String]) -> bool {
+    let host = host.to_ascii_lowercase();
+    allowed_hosts.iter().any(|allowed| {
+        let allowed = allowed.to_ascii_lowercase();
+        match allowed.strip_prefix('.') {
+            Some(domain) => host == domain || host.ends_with(&allowed),
+            None => host == allowed,
+        }
+    })
+}
+
+/// Whether an address is reachable on the internet, rather than on the host or its networks.
+fn is_public(address: &IpAddr) -> bool {
+    match address {
+        IpAddr::V4(v4) => is_public_v4(v4),
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => is_public_v4(&v4),
+            None => is_public_v6(v6),
+        },
+    }
+}
+
+fn is_public_v4(address: &Ipv4Addr) -> bool {
+    let [a, b, ..] = address.octets();
+    !(address.is_loopback()
+        || address.is_private()
+        || address.is_link_local()
+        || address.is_unspecified()
+        || address.is_broadcast()
+        || address.is_multicast()
+        // "this network", and the shared address space of carrier-grade NAT
+        || a == 0
+        || (a == 100 && (64..128).contains(&b)))
+}
+
+fn is_public_v6(address: &Ipv6Addr) -> bool {
+    let first = address.segments()[0];
+    !(address.is_loopback()
+        || address.is_unspecified()
+        || address.is_multicast()
+        // unique local and link-local addresses
+        || (first & 0xfe00) == 0xfc00
+        || (first & 0xffc0) == 0xfe80)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn internal_addresses_are_not_public() {
+        for address in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public(&address.parse().unwrap()), "{}", address);
+        }
+        for address in ["93.184.216.34", "2606:2800:220:1::1"] {
+            assert!(is_public(&address.parse().unwrap()), "{}", address);
+        }
+    }
+
+    #[tokio::test]
+    async fn urls_must_reach_public_addresses() {
+        assert!(check("https://93.184.216.34/hook", &[]).await.is_ok());
+        for url in [
+            "http://127.0.0.1:8081/api/admin/sboms",
+            "http://[::1]/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://localhost/hook",
+        ] {
+            assert!(
+                matches!(check(url, &[]).await, Err(OutboundError::NonPublic { .. })),
+                "{}",
+                url
+            );
+        }
+        assert!(matches!(
+            check("ftp://example.com/hook", &[]).await,
+            Err(OutboundError::Invalid { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn allowed_hosts_are_the_only_ones_allowed() {
+        let allowed = vec!["ci.internal".to_string(), ".example.com".to_string()];
+        for url in [
+            "http://ci.internal/hook",
+            "https://example.com/hook",
+            "https://hooks.Example.com/trust",
+        ] {
+            assert!(check(url, &allowed).await.is_ok(), "{}", url);
+        }
+        for url in ["https://93.184.216.34/hook", "https://badexample.com/hook"] {
+            assert!(
+                matches!(
+                    check(url, &allowed).await,
+                    Err(OutboundError::NotAllowed { .. })
+                ),
+                "{}",
+                url
+            );
+        }
+    }
+}
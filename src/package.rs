@@ -1,292 +1,1759 @@
-use crate::guac::Guac;
+use crate::cache::Cache;
+use crate::config::{ApiConfig, Snyk};
+use crate::convert::{SbomFormat, LOSSY_FIELDS_HEADER};
+use crate::depsdev::DepsDev;
+use crate::error::TrustError;
+use crate::ghsa::Ghsa;
+use crate::guac::{Guac, Summary};
+use crate::license::LicensePolicy;
+use crate::nvd::Nvd;
+use crate::osv::Osv;
+use crate::provider::{ProviderTimeouts, VulnerabilityProvider, VulnerabilityQuery};
+use crate::purl::PurlParsing;
+use crate::reanalysis::{AnalysisSnapshot, PackageHistory, Reanalysis, VulnerabilityPeriod};
+use crate::replication::{Journal, Scope};
+use crate::resilience::Unavailable;
 use crate::sbom::SbomRegistry;
-use crate::Snyk;
+use crate::scorecard::Scorecard;
+use crate::sigstore::Sigstore;
+use crate::snyk::SnykClient;
+use crate::subgraph::{Direction, MAX_DEPTH};
+use crate::versioning::ApiVersion;
+use actix_web::error::JsonPayloadError;
 use actix_web::http::header::{DispositionParam, DispositionType};
 use actix_web::{
-    error, get,
+    error::{self, ResponseError},
+    get,
     http::{header::ContentDisposition, StatusCode},
     post, web,
     web::Json,
     web::ServiceConfig,
-    HttpResponse,
+    HttpRequest, HttpResponse,
 };
+use base64::Engine;
 use core::str::FromStr;
+use futures::StreamExt;
 use packageurl::PackageUrl;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::sync::Arc;
 use thiserror::Error;
+use utoipa::{OpenApi, ToSchema};
 
 pub use trust_api_model::pkg::*;
 
-pub(crate) fn configure() -> impl FnOnce(&mut ServiceConfig) {
-    |config: &mut ServiceConfig| {
-        config.service(get_package);
-        config.service(query_package);
-        config.service(query_package_dependencies);
-        config.service(query_package_dependents);
-        config.service(get_trusted);
-        config.service(query_package_versions);
-        config.service(query_sbom);
+/// The package endpoints of an API version, relative to its prefix.
+pub(crate) fn configure(version: ApiVersion) -> impl FnOnce(&mut ServiceConfig) {
+    move |config: &mut ServiceConfig| match version {
+        ApiVersion::V1 => {
+            config.service(get_package);
+            config.service(query_package);
+            config.service(query_package_dependencies);
+            config.service(query_package_dependents);
+            config.service(get_trusted);
+            config.service(query_package_versions);
+            config.service(query_sbom);
+            config.service(get_sbom_by_digest);
+            config.service(get_provenance);
+            config.service(get_licenses);
+            config.service(check_licenses);
+            config.service(get_history);
+            config.service(get_paths);
+            config.service(get_counterparts);
+        }
     }
 }
 
+/// The documentation of the package endpoints.
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        get_package,
+        get_trusted,
+        query_package,
+        query_package_dependencies,
+        query_package_dependents,
+        query_package_versions,
+        query_sbom,
+        get_sbom_by_digest,
+        get_provenance,
+        get_licenses,
+        check_licenses,
+        get_history,
+        get_paths,
+        get_counterparts,
+    ),
+    components(schemas(
+        Package,
+        PackageList,
+        PackageBatchItem,
+        DependenciesBatchItem,
+        VersionsBatchItem,
+        LicenseCheckBatchItem,
+        PackageDependencies,
+        PackageDependents,
+        DependencyPaths,
+        Counterparts,
+        Counterpart,
+        Confidence,
+        CounterpartEvidence,
+        PackageRef,
+        VersionRelation,
+        DependencyEdge,
+        DependencyScope,
+        SnykData,
+        VulnerabilityRef,
+        VulnerabilitySource,
+        Severity,
+        ProviderId,
+        ProviderWarning,
+        WarningReason,
+        Provenance,
+        Certification,
+        Verdict,
+        ScorecardSummary,
+        License,
+        LicenseCheck,
+        LicenseViolation,
+        PackageHistory,
+        AnalysisSnapshot,
+        VulnerabilityPeriod,
+        ApiError,
+    ))
+)]
+pub(crate) struct ApiDoc;
+
 #[derive(serde::Deserialize)]
 pub struct PackageQuery {
     purl: Option<String>,
+    #[serde(default)]
+    include_ids: bool,
+    /// Match all qualifiers, not only the significant ones
+    #[serde(default)]
+    strict: bool,
+}
+
+#[derive(serde::Deserialize)]
+pub struct IdsQuery {
+    /// Include the identifiers native to the providers reporting a fact
+    #[serde(default)]
+    include_ids: bool,
+    /// Match all qualifiers, not only the significant ones
+    #[serde(default)]
+    strict: bool,
+}
+
+#[derive(serde::Deserialize)]
+pub struct StrictQuery {
+    /// Match all qualifiers, not only the significant ones
+    #[serde(default)]
+    strict: bool,
+}
+
+#[derive(serde::Deserialize)]
+pub struct DepthQuery {
+    /// How many levels of the graph to walk
+    #[serde(default = "default_depth")]
+    depth: usize,
+    /// Match all qualifiers, not only the significant ones
+    #[serde(default)]
+    strict: bool,
+    /// Only packages of this type (ecosystem), e.g. `maven`
+    #[serde(default, rename = "type")]
+    ty: Option<String>,
+    /// Only packages whose namespace starts with this prefix
+    #[serde(default)]
+    namespace: Option<String>,
+    /// Only packages with vulnerabilities
+    #[serde(default)]
+    vulnerable_only: bool,
+}
+
+/// How many dependencies are checked for vulnerabilities at once.
+const FILTER_CHUNK: usize = 100;
+
+/// How many packages of a batch have their versions, or versions their vulnerabilities and
+/// release date, looked up at the same time.
+const VERSION_CONCURRENCY: usize = 8;
+
+/// The packages with vulnerabilities, by the results of looking them up in the same order, and
+/// those which couldn't be checked as a provider failed.
+fn retain_vulnerable(
+    packages: Vec<PackageRef>,
+    found: Vec<Result<Package, TrustError>>,
+) -> Vec<PackageRef> {
+    packages
+        .into_iter()
+        .zip(found)
+        .filter_map(|(mut p, found)| match found {
+            Ok(package) => (!package.vulnerabilities.is_empty()).then_some(p),
+            Err(TrustError::Provider { provider, source }) => {
+                log::debug!(
+                    "Unable to check the vulnerabilities of {}: {}",
+                    p.purl,
+                    source
+                );
+                p.degraded.push(provider.to_string());
+                Some(p)
+            }
+            Err(e) => {
+                log::debug!("Unable to check the vulnerabilities of {}: {}", p.purl, e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Mark packages served without the data from Guac, as it is unavailable.
+fn degraded(packages: Vec<PackageRef>) -> Vec<PackageRef> {
+    packages
+        .into_iter()
+        .map(|mut p| {
+            if !p.degraded.iter().any(|d| d == "guac") {
+                p.degraded.push("guac".to_string());
+            }
+            p
+        })
+        .collect()
+}
+
+/// Which of the packages found walking the graph are returned.
+#[derive(Clone, Debug, Default)]
+pub struct DependencyFilter {
+    pub ty: Option<String>,
+    pub namespace: Option<String>,
+    pub vulnerable_only: bool,
+}
+
+impl DependencyFilter {
+    /// Whether the purl of a package matches, the vulnerabilities are checked separately.
+    fn matches(&self, purl: &str) -> bool {
+        let purl = match PackageUrl::from_str(purl) {
+            Ok(purl) => purl,
+            Err(_) => return self.ty.is_none() && self.namespace.is_none(),
+        };
+        self.ty.as_deref().map_or(true, |ty| purl.ty() == ty)
+            && self.namespace.as_deref().map_or(true, |prefix| {
+                purl.namespace().unwrap_or_default().starts_with(prefix)
+            })
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct PathQuery {
+    from: Option<String>,
+    to: Option<String>,
+    /// How many levels of the graph to walk
+    #[serde(default = "max_depth")]
+    depth: usize,
+    /// Match all qualifiers, not only the significant ones
+    #[serde(default)]
+    strict: bool,
+}
+
+#[derive(serde::Deserialize)]
+pub struct VersionsQuery {
+    /// Match all qualifiers, not only the significant ones
+    #[serde(default)]
+    strict: bool,
+    /// Only include trusted rebuilds
+    #[serde(default)]
+    trusted_only: bool,
+    /// Only include versions of the same major version
+    #[serde(default)]
+    same_major: bool,
+}
+
+fn default_depth() -> usize {
+    1
+}
+
+fn max_depth() -> usize {
+    MAX_DEPTH
+}
+
+impl DepthQuery {
+    fn depth(&self) -> Result<usize, ApiError> {
+        if (1..=MAX_DEPTH).contains(&self.depth) {
+            Ok(self.depth)
+        } else {
+            Err(ApiError::InvalidDepth { depth: self.depth })
+        }
+    }
+
+    fn filter(&self) -> DependencyFilter {
+        DependencyFilter {
+            ty: self.ty.clone(),
+            namespace: self.namespace.clone(),
+            vulnerable_only: self.vulnerable_only,
+        }
+    }
+}
+
+/// Remove provider identifiers, unless they were requested.
+pub(crate) fn with_ids(mut package: Package, include_ids: bool) -> Package {
+    if !include_ids {
+        for vuln in package.vulnerabilities.iter_mut() {
+            vuln.ids.clear();
+        }
+    }
+    package
+}
+
+/// All identifiers of a vulnerability, lowercased.
+fn names_of(vuln: &VulnerabilityRef) -> Vec<String> {
+    std::iter::once(&vuln.cve)
+        .chain(vuln.aliases.iter())
+        .chain(vuln.ids.iter().map(|id| &id.id))
+        .map(|name| name.to_lowercase())
+        .collect()
+}
+
+fn is_cve(id: &str) -> bool {
+    id.to_lowercase().starts_with("cve-")
+}
+
+/// The severity most sources agree on, the higher one on a tie.
+fn consensus(sources: &[VulnerabilitySource]) -> Option<Severity> {
+    let mut votes: Vec<(Severity, usize)> = Vec::new();
+    for severity in sources.iter().filter_map(|s| s.severity) {
+        match votes.iter_mut().find(|(s, _)| *s == severity) {
+            Some((_, count)) => *count += 1,
+            None => votes.push((severity, 1)),
+        }
+    }
+    votes
+        .into_iter()
+        .max_by_key(|(severity, count)| (*count, *severity))
+        .map(|(severity, _)| severity)
+}
+
+/// Add a vulnerability, unless it is already listed by its CVE or one of its aliases (e.g. a GHSA
+/// or Snyk id), in which case the reports are merged.
+///
+/// A merged vulnerability is listed by its CVE if any provider knows it, with the other
+/// identifiers as aliases, the details of every provider as its sources, and the consensus of
+/// their severities. It counts as confirmed by the latest provider reporting it.
+pub(crate) fn merge_vulnerability(vulns: &mut Vec<VulnerabilityRef>, mut vuln: VulnerabilityRef) {
+    let names = names_of(&vuln);
+    let existing = match vulns
+        .iter_mut()
+        .find(|v| names_of(v).iter().any(|n| names.contains(n)))
+    {
+        Some(existing) => existing,
+        None => {
+            vuln.severity = consensus(&vuln.sources).or(vuln.severity);
+            vulns.push(vuln);
+            return;
+        }
+    };
+
+    if !is_cve(&existing.cve) && is_cve(&vuln.cve) {
+        std::mem::swap(&mut existing.cve, &mut vuln.cve);
+        std::mem::swap(&mut existing.href, &mut vuln.href);
+    }
+    for alias in std::iter::once(vuln.cve).chain(vuln.aliases) {
+        if !alias.eq_ignore_ascii_case(&existing.cve)
+            && !existing
+                .aliases
+                .iter()
+                .any(|a| a.eq_ignore_ascii_case(&alias))
+        {
+            existing.aliases.push(alias);
+        }
+    }
+    for id in vuln.ids {
+        if !existing.ids.contains(&id) {
+            existing.ids.push(id);
+        }
+    }
+    existing.sources.extend(vuln.sources);
+    existing.severity = consensus(&existing.sources).or(existing.severity.or(vuln.severity));
+    existing.fetched = existing.fetched.max(vuln.fetched);
+}
+
+/// The evaluation of a package depends on the SBOMs visible to the caller, so it is cached for
+/// each tenant.
+fn cache_key(purl: &str) -> String {
+    scoped_key("package", purl, crate::tenant::current())
+}
+
+/// A cached evaluation of a package, and the generation identifying it in the ETags of
+/// responses, replaced whenever the package is evaluated again.
+#[derive(Serialize, Deserialize)]
+struct CachedPackage {
+    generation: String,
+    package: Package,
+}
+
+impl CachedPackage {
+    fn new(package: Package) -> Self {
+        Self {
+            generation: uuid::Uuid::new_v4().to_string(),
+            package,
+        }
+    }
+}
+
+/// The cache key of an entry of some kind which depends on the SBOMs visible to `tenant`.
+pub(crate) fn scoped_key(kind: &str, key: &str, tenant: Option<Option<String>>) -> String {
+    match tenant {
+        None => format!("{}:{}", kind, key),
+        Some(None) => format!("{}:global:{}", kind, key),
+        Some(Some(tenant)) => format!("{}:tenant:{}:{}", kind, tenant, key),
+    }
+}
+
+/// Optional integrations providing data beyond Guac and Snyk.
+pub struct Integrations {
+    /// Fallback for packages unknown to Guac
+    pub deps_dev: Option<Arc<DepsDev>>,
+    pub sigstore: Option<Arc<Sigstore>>,
+    pub scorecard: Option<Arc<Scorecard>>,
+    /// Additional vulnerabilities, matched by CPE
+    pub nvd: Option<Arc<Nvd>>,
+    pub ghsa: Option<Arc<Ghsa>>,
+    pub osv: Option<Arc<Osv>>,
 }
 
 pub struct TrustedContent {
     sbom: Arc<SbomRegistry>,
     client: Arc<Guac>,
-    snyk: Snyk,
+    snyk: Arc<SnykClient>,
+    cache: Arc<Cache>,
+    unknown_purl: UnknownPurl,
+    purl_parsing: PurlParsing,
+    fallback: Option<Arc<DepsDev>>,
+    sigstore: Option<Arc<Sigstore>>,
+    scorecard: Option<Arc<Scorecard>>,
+    /// Consulted in order, their reports merged
+    providers: Vec<Arc<dyn VulnerabilityProvider>>,
+    provider_timeouts: ProviderTimeouts,
+    journal: Option<Arc<Journal>>,
+    batch: BatchConfig,
+    slsa_levels: HashMap<String, u8>,
+    license_policy: LicensePolicy,
 }
 
 impl TrustedContent {
-    pub fn new(client: Arc<Guac>, sbom: Arc<SbomRegistry>, snyk: Snyk) -> Self {
-        Self { client, snyk, sbom }
+    pub fn new(
+        client: Arc<Guac>,
+        sbom: Arc<SbomRegistry>,
+        snyk: Snyk,
+        cache: Arc<Cache>,
+        api: &ApiConfig,
+        integrations: Integrations,
+    ) -> Self {
+        let snyk = Arc::new(SnykClient::new(&snyk));
+        let mut providers: Vec<Arc<dyn VulnerabilityProvider>> = vec![client.clone()];
+        if snyk.is_configured() {
+            providers.push(snyk.clone());
+        }
+        if let Some(nvd) = integrations.nvd {
+            providers.push(nvd);
+        }
+        if let Some(ghsa) = integrations.ghsa {
+            providers.push(ghsa);
+        }
+        if let Some(osv) = integrations.osv {
+            providers.push(osv);
+        }
+        providers.retain(|p| api.is_provider_enabled(p.id()));
+
+        Self {
+            client,
+            snyk,
+            sbom,
+            cache,
+            unknown_purl: api.unknown_purl,
+            purl_parsing: api.purl_parsing,
+            fallback: integrations.deps_dev,
+            sigstore: integrations.sigstore,
+            scorecard: integrations.scorecard,
+            providers,
+            provider_timeouts: api.provider_timeouts.clone(),
+            journal: None,
+            batch: api.batch.clone(),
+            slsa_levels: api.slsa_levels.clone(),
+            license_policy: api.license_policy.clone(),
+        }
     }
 
-    pub async fn get_versions(&self, purl_str: &str) -> Result<Vec<PackageRef>, ApiError> {
-        if let Ok(purl) = PackageUrl::from_str(purl_str) {
-            let trusted_versions: Vec<PackageRef> = self
-                .client
-                .get_packages(purl.clone())
-                .await
-                .map_err(|_| ApiError::InternalError)?;
+    /// The Snyk client, shared with the admin API to rotate its token.
+    pub(crate) fn snyk(&self) -> Arc<SnykClient> {
+        self.snyk.clone()
+    }
+
+    /// Consult another vulnerability provider, after the configured ones.
+    pub fn with_provider(mut self, provider: Arc<dyn VulnerabilityProvider>) -> Self {
+        self.providers.push(provider);
+        self
+    }
+
+    /// Record the cached evaluations for followers.
+    pub fn with_journal(mut self, journal: Arc<Journal>) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+
+    pub(crate) fn batch(&self) -> &BatchConfig {
+        &self.batch
+    }
+
+    /// The cache of evaluations, shared with the rollups of their results.
+    pub(crate) fn cache(&self) -> &Cache {
+        &self.cache
+    }
 
-            Ok(trusted_versions)
+    /// The form of a purl sent by a client to process, according to the parsing mode.
+    pub(crate) fn canonicalize(&self, purl_str: &str) -> String {
+        self.canonicalize_with(purl_str, false)
+    }
+
+    /// The form of a purl sent by a client to process, keeping all its qualifiers if `strict`.
+    pub(crate) fn canonicalize_with(&self, purl_str: &str, strict: bool) -> String {
+        self.purl_parsing.canonicalize(purl_str, strict)
+    }
+
+    /// Check the size of a batch request and parse all its purls, before looking up any of them.
+    pub(crate) fn validate_batch<'a>(
+        &self,
+        purls: &'a [String],
+        strict: bool,
+    ) -> Result<Vec<BatchPurl<'a>>, ApiError> {
+        if purls.is_empty() {
+            return Err(ApiError::EmptyRequest);
+        }
+        if purls.len() > self.batch.max_items {
+            return Err(ApiError::BatchTooLarge {
+                count: purls.len(),
+                limit: self.batch.max_items,
+            });
+        }
+        Ok(purls
+            .iter()
+            .map(|purl| {
+                let canonical = self.canonicalize_with(purl, strict);
+                let invalid = self.parse_purl(&canonical).err();
+                BatchPurl {
+                    purl,
+                    canonical,
+                    invalid,
+                }
+            })
+            .collect())
+    }
+
+    fn parse_purl<'a>(&self, purl_str: &'a str) -> Result<PackageUrl<'a>, TrustError> {
+        PackageUrl::from_str(purl_str).map_err(|source| TrustError::InvalidPackageUrl {
+            purl: purl_str.to_string(),
+            source,
+        })
+    }
+
+    /// Check if a purl is known, based on Guac and the SBOM registry.
+    async fn is_known(&self, purl: &PackageUrl<'_>) -> Result<bool, TrustError> {
+        if self.sbom.exists(&purl.to_string()) {
+            return Ok(true);
+        }
+        Ok(!self
+            .client
+            .get_packages(purl.clone())
+            .await
+            .map_err(|e| TrustError::provider("guac", e))?
+            .is_empty())
+    }
+
+    /// Fail with [`TrustError::PackageNotFound`] for unknown purls, unless configured otherwise.
+    async fn ensure_known(&self, purl: &PackageUrl<'_>) -> Result<(), TrustError> {
+        if self.unknown_purl == UnknownPurl::Empty || self.is_known(purl).await? {
+            Ok(())
         } else {
-            Err(ApiError::InvalidPackageUrl {
-                purl: purl_str.to_string(),
+            Err(TrustError::PackageNotFound {
+                purl: purl.to_string(),
             })
         }
     }
 
-    async fn get_trusted(&self, purl_str: &str) -> Result<Package, ApiError> {
-        if let Ok(purl) = PackageUrl::from_str(purl_str) {
-            // get vulnerabilities from Guac
-            let mut vulns = self
-                .client
-                .get_vulnerabilities(purl_str)
-                .await
-                .map_err(|_| ApiError::InternalError)?;
+    /// Fails right away while Guac is unavailable, rather than waiting for a call to it to fail.
+    fn guac_unavailable() -> TrustError {
+        TrustError::provider(
+            "guac",
+            Unavailable {
+                upstream: "guac",
+                reason: "the last probe failed".to_string(),
+            }
+            .into(),
+        )
+    }
 
-            // get vulnerabilities from Snyk
-            let mut snyk_vulns = crate::snyk::get_vulnerabilities(self.snyk.clone(), purl_str)
+    /// The versions of a package while Guac is unavailable: those with an SBOM in the registry,
+    /// and those known to deps.dev.
+    async fn degraded_versions(
+        &self,
+        purl: &PackageUrl<'_>,
+    ) -> Result<Vec<PackageRef>, TrustError> {
+        let same_package = |other: &PackageUrl<'_>| {
+            other.ty() == purl.ty()
+                && other.namespace() == purl.namespace()
+                && other.name() == purl.name()
+        };
+        let mut versions = Vec::new();
+        for candidate in self.sbom.purls() {
+            let other = match PackageUrl::from_str(&candidate) {
+                Ok(other) if same_package(&other) => other,
+                _ => continue,
+            };
+            versions.push(PackageRef {
+                href: format!("/api/v1/package?purl={}", urlencoding::encode(&candidate)),
+                trusted: Some(self.client.resolver().is_trusted(&other, None).await),
+                unverified: false,
+                sbom: Some(format!(
+                    "/api/v1/package/sbom?purl={}",
+                    urlencoding::encode(&candidate)
+                )),
+                vulnerabilities: None,
+                published: None,
+                fetched: None,
+                relation: None,
+                dependency: None,
+                degraded: vec![],
+                purl: candidate.clone(),
+            });
+        }
+        if let Some(fallback) = &self.fallback {
+            let known = fallback
+                .get_versions(purl)
                 .await
-                .map_err(|_| ApiError::InternalError)?;
-            vulns.append(&mut snyk_vulns);
+                .map_err(|e| TrustError::provider("deps.dev", e))?;
+            for version in known.into_iter().flatten() {
+                if !versions.iter().any(|v| v.purl == version.purl) {
+                    versions.push(version);
+                }
+            }
+        }
+        if versions.is_empty() {
+            return Err(Self::guac_unavailable());
+        }
+        Ok(degraded(versions))
+    }
 
-            //get related packages from Guac
-            let trusted_versions: Vec<PackageRef> = self
-                .client
-                .get_packages(purl.clone())
+    /// The direct dependencies of a package while Guac is unavailable, from its SBOM in the
+    /// registry, or from deps.dev.
+    async fn degraded_dependencies(
+        &self,
+        purl: &PackageUrl<'_>,
+        purl_str: &str,
+    ) -> Result<PackageDependencies, TrustError> {
+        if let Some(sbom) = self.sbom.lookup(purl_str) {
+            let mut dependencies = Vec::new();
+            for (dependency, edge) in crate::sbom::dependency_edges(&sbom) {
+                let trusted = match PackageUrl::from_str(&dependency) {
+                    Ok(p) => self.client.resolver().is_trusted(&p, None).await,
+                    Err(_) => false,
+                };
+                dependencies.push(PackageRef {
+                    href: format!("/api/v1/package?purl={}", urlencoding::encode(&dependency)),
+                    trusted: Some(trusted),
+                    unverified: false,
+                    sbom: self.sbom.exists(&dependency).then(|| {
+                        format!(
+                            "/api/v1/package/sbom?purl={}",
+                            urlencoding::encode(&dependency)
+                        )
+                    }),
+                    vulnerabilities: None,
+                    published: None,
+                    fetched: None,
+                    relation: None,
+                    dependency: (edge != DependencyEdge::default()).then_some(edge),
+                    degraded: vec![],
+                    purl: dependency,
+                });
+            }
+            dependencies.sort_by(|a, b| a.purl.cmp(&b.purl));
+            return Ok(PackageDependencies(degraded(dependencies)));
+        }
+        if let Some(fallback) = &self.fallback {
+            if let Some(dependencies) = fallback
+                .get_dependencies(purl)
                 .await
-                .map_err(|_| ApiError::InternalError)?;
+                .map_err(|e| TrustError::provider("deps.dev", e))?
+            {
+                return Ok(PackageDependencies(degraded(dependencies.0)));
+            }
+        }
+        Err(Self::guac_unavailable())
+    }
 
-            let p = Package {
-                purl: Some(purl.to_string()),
-                href: Some(format!(
-                    "/api/package?purl={}",
-                    &urlencoding::encode(&purl.to_string())
-                )),
-                trusted: Some(self.is_trusted(purl.clone())),
-                trusted_versions,
-                snyk: None,
-                vulnerabilities: vulns,
-                sbom: if self.sbom.exists(&purl.to_string()) {
-                    Some(format!(
-                        "/api/package/sbom?purl={}",
-                        &urlencoding::encode(&purl.to_string())
-                    ))
-                } else {
-                    None
-                },
+    pub async fn get_versions(&self, purl_str: &str) -> Result<Vec<PackageRef>, TrustError> {
+        let purl = self.parse_purl(purl_str)?;
+        if !self.client.is_available() {
+            return self.degraded_versions(&purl).await;
+        }
+        let trusted_versions: Vec<PackageRef> = self
+            .client
+            .get_packages(purl.clone())
+            .await
+            .map_err(|e| TrustError::provider("guac", e))?;
+
+        // the versions are the same lookup as the known check, avoid making it twice
+        if trusted_versions.is_empty() && !self.sbom.exists(purl_str) {
+            if let Some(fallback) = &self.fallback {
+                if let Some(versions) = fallback
+                    .get_versions(&purl)
+                    .await
+                    .map_err(|e| TrustError::provider("deps.dev", e))?
+                {
+                    return Ok(versions);
+                }
+            }
+        }
+        if trusted_versions.is_empty()
+            && !self.sbom.exists(purl_str)
+            && self.unknown_purl == UnknownPurl::NotFound
+        {
+            return Err(TrustError::PackageNotFound {
+                purl: purl_str.to_string(),
+            });
+        }
+        Ok(self
+            .client
+            .resolver()
+            .trusted_versions(&purl, trusted_versions))
+    }
+
+    /// The trusted packages a community package maps to, with how confident each mapping is.
+    ///
+    /// Candidates are the trusted packages Guac records as equal to the package, and its trusted
+    /// versions. A candidate is kept if related to the package by at least one of: being equal,
+    /// being a rebuild of its version, or being built from the same source repository.
+    pub async fn get_counterparts(&self, purl_str: &str) -> Result<Counterparts, TrustError> {
+        let purl = self.parse_purl(purl_str)?;
+        self.ensure_known(&purl).await?;
+        let guac = |e| TrustError::provider("guac", e);
+        let resolver = self.client.resolver();
+
+        let mut candidates: Vec<(String, Vec<CounterpartEvidence>)> = Vec::new();
+        for equal in self.client.get_equal_packages(&purl).await.map_err(guac)? {
+            let trusted = match PackageUrl::from_str(&equal) {
+                Ok(p) => resolver.is_trusted(&p, None).await,
+                Err(_) => false,
             };
-            Ok(p)
-        } else {
-            Err(ApiError::InvalidPackageUrl {
+            if trusted {
+                candidates.push((equal, vec![CounterpartEvidence::Equal]));
+            }
+        }
+        let versions = self.client.get_packages(purl.clone()).await.map_err(guac)?;
+        for version in resolver.trusted_versions(&purl, versions) {
+            if version.purl != purl_str && !candidates.iter().any(|(c, _)| *c == version.purl) {
+                candidates.push((version.purl, Vec::new()));
+            }
+        }
+
+        let source = self.client.get_source_repo(&purl).await.map_err(guac)?;
+        let mut counterparts = Vec::new();
+        for (candidate, mut evidence) in candidates {
+            let candidate_purl = match PackageUrl::from_str(&candidate) {
+                Ok(p) => p,
+                Err(_) => continue,
+            };
+            if purl.version().is_some()
+                && resolver.upstream_version(&candidate_purl).as_deref() == purl.version()
+            {
+                evidence.push(CounterpartEvidence::Version);
+            }
+            if source.is_some()
+                && self
+                    .client
+                    .get_source_repo(&candidate_purl)
+                    .await
+                    .map_err(guac)?
+                    == source
+            {
+                evidence.push(CounterpartEvidence::Source);
+            }
+            let confidence = match evidence.as_slice() {
+                [] => continue,
+                [CounterpartEvidence::Source] => Confidence::Low,
+                [CounterpartEvidence::Version] => Confidence::Medium,
+                _ => Confidence::High,
+            };
+            counterparts.push(Counterpart {
+                href: format!("/api/v1/package?purl={}", urlencoding::encode(&candidate)),
+                purl: candidate,
+                confidence,
+                evidence,
+            });
+        }
+        counterparts.sort_by(|a, b| a.confidence.cmp(&b.confidence).then(a.purl.cmp(&b.purl)));
+
+        Ok(Counterparts {
+            purl: purl_str.to_string(),
+            counterparts,
+        })
+    }
+
+    /// Add the number of vulnerabilities and the release date to each version, as far as known.
+    ///
+    /// Release dates are looked up from deps.dev, if it is enabled. [`VERSION_CONCURRENCY`]
+    /// versions are described at a time.
+    pub async fn describe_versions(&self, versions: Vec<PackageRef>) -> Vec<PackageRef> {
+        futures::stream::iter(versions)
+            .map(|mut version| async move {
+                let found = self.get_trusted(&self.canonicalize(&version.purl)).await;
+                version.vulnerabilities = match found {
+                    Ok(package) => Some(package.vulnerabilities.len()),
+                    Err(e) => {
+                        log::debug!("Unable to count vulnerabilities of {}: {}", version.purl, e);
+                        None
+                    }
+                };
+                if let (Some(fallback), Ok(purl)) =
+                    (&self.fallback, PackageUrl::from_str(&version.purl))
+                {
+                    if let Some(v) = purl.version() {
+                        match fallback.get_published(&purl, v).await {
+                            Ok(published) => version.published = published,
+                            Err(e) => {
+                                log::debug!(
+                                    "Unable to find when {} was published: {:?}",
+                                    version.purl,
+                                    e
+                                )
+                            }
+                        }
+                    }
+                }
+                version
+            })
+            .buffered(VERSION_CONCURRENCY)
+            .collect()
+            .await
+    }
+
+    /// The dependencies up to `depth` levels deep, deps.dev only provides direct dependencies.
+    ///
+    /// While Guac is unavailable, only the direct dependencies are served, see
+    /// [`Self::degraded_dependencies`].
+    pub async fn get_dependencies(
+        &self,
+        purl_str: &str,
+        depth: usize,
+    ) -> Result<PackageDependencies, TrustError> {
+        let purl = self.parse_purl(purl_str)?;
+        if !self.client.is_available() {
+            return self.degraded_dependencies(&purl, purl_str).await;
+        }
+        if let Some(fallback) = &self.fallback {
+            if !self.is_known(&purl).await? {
+                if let Some(dependencies) = fallback
+                    .get_dependencies(&purl)
+                    .await
+                    .map_err(|e| TrustError::provider("deps.dev", e))?
+                {
+                    return Ok(dependencies);
+                }
+            }
+        }
+        self.ensure_known(&purl).await?;
+        self.client
+            .get_dependencies(purl_str, depth)
+            .await
+            .map_err(|e| TrustError::provider("guac", e))
+    }
+
+    /// Keep the packages matching a filter, looking up the vulnerabilities of the remaining
+    /// packages at once, [`FILTER_CHUNK`] at a time, if only vulnerable ones are kept.
+    ///
+    /// Packages which can't be checked as a provider failed are kept, with the provider in
+    /// `degraded`.
+    pub async fn filter_dependencies(
+        &self,
+        dependencies: PackageDependencies,
+        filter: &DependencyFilter,
+    ) -> PackageDependencies {
+        let matching: Vec<PackageRef> = dependencies
+            .0
+            .into_iter()
+            .filter(|p| filter.matches(&p.purl))
+            .collect();
+        if !filter.vulnerable_only {
+            return PackageDependencies(matching);
+        }
+        let mut vulnerable = Vec::new();
+        for chunk in matching.chunks(FILTER_CHUNK) {
+            let canonical: Vec<String> = chunk.iter().map(|p| self.canonicalize(&p.purl)).collect();
+            let canonical: Vec<&str> = canonical.iter().map(String::as_str).collect();
+            let found = self.get_trusted_batch(&canonical).await;
+            vulnerable.extend(retain_vulnerable(chunk.to_vec(), found));
+        }
+        PackageDependencies(vulnerable)
+    }
+
+    /// The shortest dependency chains from a package to one of its dependencies, within `depth`
+    /// levels.
+    pub async fn get_paths(
+        &self,
+        from: &str,
+        to: &str,
+        depth: usize,
+    ) -> Result<DependencyPaths, TrustError> {
+        let purl = self.parse_purl(from)?;
+        self.parse_purl(to)?;
+        self.ensure_known(&purl).await?;
+        let mut paths = self
+            .client
+            .paths(Direction::Dependencies, from, depth, true, |p| p == to)
+            .await
+            .map_err(|e| TrustError::provider("guac", e))?;
+        Ok(DependencyPaths {
+            from: from.to_string(),
+            to: to.to_string(),
+            paths: paths.remove(to).unwrap_or_default(),
+        })
+    }
+
+    /// The dependents up to `depth` levels deep, only Guac knows them.
+    pub async fn get_dependents(
+        &self,
+        purl_str: &str,
+        depth: usize,
+    ) -> Result<PackageDependencies, TrustError> {
+        let purl = self.parse_purl(purl_str)?;
+        if !self.client.is_available() {
+            return Err(Self::guac_unavailable());
+        }
+        self.ensure_known(&purl).await?;
+        self.client
+            .get_dependents(purl_str, depth)
+            .await
+            .map_err(|e| TrustError::provider("guac", e))
+    }
+
+    pub async fn get_trusted(&self, purl_str: &str) -> Result<Package, TrustError> {
+        self.get_trusted_batch(&[purl_str])
+            .await
+            .pop()
+            .unwrap_or_else(|| {
+                Err(TrustError::provider(
+                    "guac",
+                    anyhow::anyhow!("No result for {}", purl_str),
+                ))
+            })
+    }
+
+    /// Look up packages, with a single query to Guac for all those which aren't cached.
+    pub async fn get_trusted_batch(&self, purls: &[&str]) -> Vec<Result<Package, TrustError>> {
+        let mut results: Vec<Option<Result<Package, TrustError>>> =
+            purls.iter().map(|_| None).collect();
+        let mut missing = Vec::new();
+        for (i, purl_str) in purls.iter().enumerate() {
+            if let Some(cached) = self.cache.get::<CachedPackage>(&cache_key(purl_str)).await {
+                results[i] = Some(Ok(cached.package));
+                continue;
+            }
+            match self.parse_purl(purl_str) {
+                Ok(purl) => missing.push((i, purl)),
+                Err(e) => results[i] = Some(Err(e)),
+            }
+        }
+
+        let summaries = self
+            .summaries(&missing.iter().map(|(_, p)| p.clone()).collect::<Vec<_>>())
+            .await;
+        for ((i, purl), summary) in missing.into_iter().zip(summaries) {
+            results[i] = Some(self.evaluate(purls[i], purl, summary).await);
+        }
+        results.into_iter().flatten().collect()
+    }
+
+    /// What Guac knows about packages, failing for all of them while Guac is unavailable.
+    async fn summaries(&self, purls: &[PackageUrl<'_>]) -> Vec<Result<Summary, anyhow::Error>> {
+        if purls.is_empty() {
+            return vec![];
+        }
+        if !self.client.is_available() {
+            return purls
+                .iter()
+                .map(|_| {
+                    Err(Unavailable {
+                        upstream: "guac",
+                        reason: "the last probe failed".to_string(),
+                    }
+                    .into())
+                })
+                .collect();
+        }
+        let packages: Vec<_> = purls
+            .iter()
+            .map(|purl| (purl.clone(), self.digest_of(purl)))
+            .collect();
+        self.client.get_summaries(&packages).await
+    }
+
+    async fn evaluate(
+        &self,
+        purl_str: &str,
+        purl: PackageUrl<'_>,
+        summary: Result<Summary, anyhow::Error>,
+    ) -> Result<Package, TrustError> {
+        let p = self.lookup_trusted(purl_str, purl, summary).await?;
+        // degraded responses are not cached, to serve the complete data once providers recover,
+        // and unknown packages only briefly by Guac, to find them soon after they are ingested
+        if p.degraded.is_empty() && !p.unknown {
+            self.cache
+                .put(&cache_key(purl_str), &CachedPackage::new(p.clone()))
+                .await;
+            if let Some(journal) = &self.journal {
+                journal.record(crate::tenant::current().into(), purl_str, &p);
+            }
+        }
+        Ok(p)
+    }
+
+    /// Cache the evaluation of a package replicated from a primary, for the callers it was made
+    /// for.
+    pub(crate) async fn replicate(&self, scope: &Scope, purl_str: &str, package: &Package) {
+        let key = scoped_key("package", purl_str, scope.clone().into());
+        self.cache
+            .put(&key, &CachedPackage::new(package.clone()))
+            .await;
+        if let Some(journal) = &self.journal {
+            journal.record(scope.clone(), purl_str, package);
+        }
+    }
+
+    /// The cached evaluation of a package and its generation, without evaluating it.
+    pub(crate) async fn cached(&self, purl_str: &str) -> Option<(Package, String)> {
+        self.cache
+            .get::<CachedPackage>(&cache_key(purl_str))
+            .await
+            .map(|cached| (cached.package, cached.generation))
+    }
+
+    /// Drop the cached data of a package.
+    pub(crate) async fn invalidate(&self, purl_str: &str) {
+        let tenants = self.sbom.tenants().into_iter().map(|t| Some(Some(t)));
+        for tenant in [None, Some(None)].into_iter().chain(tenants) {
+            self.cache
+                .remove(&scoped_key("package", purl_str, tenant))
+                .await;
+        }
+        self.client.forget_unknown(purl_str);
+    }
+
+    async fn lookup_trusted(
+        &self,
+        purl_str: &str,
+        purl: PackageUrl<'_>,
+        summary: Result<Summary, anyhow::Error>,
+    ) -> Result<Package, TrustError> {
+        let sbom = self.sbom.exists(&purl.to_string());
+        let mut warnings: Vec<ProviderWarning> = Vec::new();
+
+        // packages with an SBOM are still served without the data from Guac if it is unavailable
+        let summary = match summary {
+            Ok(summary) => summary,
+            Err(e) if sbom => {
+                log::warn!("Serving {} without data from Guac: {:?}", purl, e);
+                warnings.push(ProviderWarning {
+                    provider: "guac".to_string(),
+                    reason: WarningReason::Failed,
+                });
+                Summary::default()
+            }
+            Err(e) => return Err(TrustError::provider("guac", e)),
+        };
+        let guac_available = warnings.is_empty();
+        let Summary {
+            packages: trusted_versions,
+            vulnerabilities: guac_vulnerabilities,
+            certifications,
+            licenses: guac_licenses,
+            provenance,
+        } = summary;
+
+        if trusted_versions.is_empty() && !sbom && self.unknown_purl == UnknownPurl::NotFound {
+            return Err(TrustError::PackageNotFound {
                 purl: purl_str.to_string(),
+            });
+        }
+
+        // merge the vulnerabilities of all providers, matching trusted rebuilds by their upstream
+        // version
+        let version = self
+            .client
+            .resolver()
+            .upstream_version(&purl)
+            .or_else(|| purl.version().map(ToString::to_string));
+        let query = VulnerabilityQuery {
+            purl: &purl,
+            purl_str,
+            version: version.as_deref(),
+        };
+        let mut vulns = Vec::new();
+        // Guac reported its vulnerabilities along with the rest of its data
+        if self.providers.iter().any(|p| p.id() == "guac") {
+            for vuln in guac_vulnerabilities {
+                merge_vulnerability(&mut vulns, vuln);
+            }
+        }
+        let providers: Vec<_> = self.providers.iter().filter(|p| p.id() != "guac").collect();
+        let reports = futures::future::join_all(providers.iter().map(|provider| {
+            let timeout = self.provider_timeouts.timeout(provider.id());
+            tokio::time::timeout(timeout, provider.vulnerabilities(&query))
+        }))
+        .await;
+        for (provider, report) in providers.into_iter().zip(reports) {
+            let reason = match report {
+                Ok(Ok(reported)) => {
+                    for vuln in reported {
+                        merge_vulnerability(&mut vulns, vuln);
+                    }
+                    continue;
+                }
+                Ok(Err(e)) => {
+                    log::warn!(
+                        "Serving {} without vulnerabilities from {}: {:?}",
+                        purl,
+                        provider.id(),
+                        e
+                    );
+                    WarningReason::Failed
+                }
+                Err(_) => {
+                    log::warn!(
+                        "Serving {} without vulnerabilities from {}, timed out",
+                        purl,
+                        provider.id()
+                    );
+                    WarningReason::TimedOut
+                }
+            };
+            warnings.push(ProviderWarning {
+                provider: provider.id().to_string(),
+                reason,
+            });
+        }
+
+        let signature = self.verify_signature(&purl).await;
+        let mut licenses = self.sbom_licenses(&purl);
+        licenses.extend(guac_licenses);
+        let scorecard = match &self.scorecard {
+            Some(scorecard) => scorecard.lookup(&purl).await.unwrap_or_else(|e| {
+                log::warn!("Error looking up the scorecard of {}: {:?}", purl, e);
+                None
+            }),
+            None => None,
+        };
+        // only served if unknown purls are configured to be served empty
+        let unknown = guac_available && trusted_versions.is_empty() && !sbom;
+        let provenance = provenance.map(|p| self.with_slsa_level(p));
+
+        let mut p = Package {
+            purl: Some(purl.to_string()),
+            href: Some(format!(
+                "/api/v1/package?purl={}",
+                &urlencoding::encode(&purl.to_string())
+            )),
+            trusted: None,
+            trusted_versions,
+            unknown,
+            snyk: None,
+            signature_verified: signature.as_ref().and_then(|s| s.verified),
+            signer: signature.and_then(|s| s.signer),
+            provenance,
+            certifications,
+            scorecard,
+            licenses,
+            degraded: warnings.iter().map(|w| w.provider.clone()).collect(),
+            warnings,
+            vulnerabilities: vulns,
+            sbom_fetched: self.sbom.indexed(&purl.to_string()),
+            sbom: if sbom {
+                Some(format!(
+                    "/api/v1/package/sbom?purl={}",
+                    &urlencoding::encode(&purl.to_string())
+                ))
+            } else {
+                None
+            },
+        };
+        // the trust decision is made with the rest of the analysis, a package certified as bad is
+        // never trusted, a good certification doesn't make it trusted
+        let analysis = serde_json::to_value(&p).ok();
+        let trusted = self
+            .client
+            .resolver()
+            .is_trusted(&purl, analysis.as_ref())
+            .await
+            && !p.certifications.iter().any(|c| c.verdict == Verdict::Bad);
+        p.trusted = (!unknown).then_some(trusted);
+        Ok(p)
+    }
+
+    /// Look up the signature of the artifact, by the digest from an OCI purl or its SBOM.
+    ///
+    /// `None` if signatures aren't checked or the digest is unknown, failures are only logged as
+    /// the signature is just a signal of trust.
+    async fn verify_signature(
+        &self,
+        purl: &PackageUrl<'_>,
+    ) -> Option<crate::sigstore::Verification> {
+        let sigstore = self.sigstore.as_ref()?;
+        let digest = self.digest_of(purl)?;
+        match sigstore.verify(&digest).await {
+            Ok(verification) => Some(verification),
+            Err(e) => {
+                log::warn!("Error looking up signatures of {}: {:?}", purl, e);
+                None
+            }
+        }
+    }
+
+    /// The SHA-256 digest of the artifact, from an OCI purl or its SBOM.
+    fn digest_of(&self, purl: &PackageUrl<'_>) -> Option<String> {
+        purl.version()
+            .and_then(|v| v.strip_prefix("sha256:"))
+            .map(ToString::to_string)
+            .or_else(|| {
+                self.sbom
+                    .lookup(&purl.to_string())
+                    .and_then(|sbom| crate::sbom::digest_of(&sbom))
             })
+    }
+
+    /// The provenance of the artifact from Guac, with the SLSA level of its builder.
+    async fn lookup_provenance(
+        &self,
+        purl: &PackageUrl<'_>,
+    ) -> Result<Option<Provenance>, TrustError> {
+        let digest = match self.digest_of(purl) {
+            Some(digest) => digest,
+            None => return Ok(None),
+        };
+        let provenance = self
+            .client
+            .get_slsa(&digest)
+            .await
+            .map_err(|e| TrustError::provider("guac", e))?;
+        Ok(provenance.map(|p| self.with_slsa_level(p)))
+    }
+
+    /// Add the SLSA level of its builder to a provenance from Guac.
+    fn with_slsa_level(&self, provenance: Provenance) -> Provenance {
+        Provenance {
+            slsa_level: self.slsa_levels.get(&provenance.builder_id).copied(),
+            ..provenance
         }
     }
 
-    // temp fn to decide if the package is trusted based on its version or namespace
-    fn is_trusted(&self, purl: PackageUrl<'_>) -> bool {
-        purl.version().map_or(false, |v| v.contains("redhat"))
-            || purl.namespace().map_or(false, |v| v == "redhat")
+    /// The licenses from the SBOM of the package.
+    fn sbom_licenses(&self, purl: &PackageUrl<'_>) -> Vec<License> {
+        self.sbom
+            .lookup(&purl.to_string())
+            .map(|sbom| crate::sbom::licenses_of(&sbom))
+            .unwrap_or_default()
+            .into_iter()
+            .map(|expression| License {
+                expression,
+                source: "sbom".to_string(),
+            })
+            .collect()
     }
 
-    async fn get_all_trusted(&self) -> Result<Vec<Package>, ApiError> {
+    /// The licenses from the SBOM of the package, and from Guac.
+    async fn lookup_licenses(&self, purl: &PackageUrl<'_>) -> Result<Vec<License>, TrustError> {
+        let mut licenses = self.sbom_licenses(purl);
+        licenses.extend(
+            self.client
+                .get_licenses(purl)
+                .await
+                .map_err(|e| TrustError::provider("guac", e))?,
+        );
+        Ok(licenses)
+    }
+
+    pub async fn get_licenses(&self, purl_str: &str) -> Result<Vec<License>, TrustError> {
+        let purl = self.parse_purl(purl_str)?;
+        self.ensure_known(&purl).await?;
+        self.lookup_licenses(&purl).await
+    }
+
+    pub async fn check_licenses(&self, purl_str: &str) -> Result<LicenseCheck, TrustError> {
+        let licenses = self.get_licenses(purl_str).await?;
+        Ok(self.license_policy.evaluate(&licenses))
+    }
+
+    pub async fn get_provenance(&self, purl_str: &str) -> Result<Provenance, TrustError> {
+        let purl = self.parse_purl(purl_str)?;
+        self.lookup_provenance(&purl)
+            .await?
+            .ok_or_else(|| TrustError::ProvenanceNotFound {
+                purl: purl_str.to_string(),
+            })
+    }
+
+    async fn get_all_trusted(&self) -> Result<Vec<Package>, TrustError> {
         let trusted_versions: Vec<Package> = self
             .client
             .get_all_packages()
             .await
-            .map_err(|_| ApiError::InternalError)?;
-        Ok(trusted_versions)
+            .map_err(|e| TrustError::provider("guac", e))?;
+        // the packages of other tenants' SBOMs are not theirs to know about
+        Ok(trusted_versions
+            .into_iter()
+            .filter(|p| {
+                !p.purl
+                    .as_deref()
+                    .map_or(false, |purl| self.sbom.is_hidden(purl))
+            })
+            .collect())
+    }
+}
+
+/// How to respond to purls which are neither known to Guac nor to the SBOM registry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum UnknownPurl {
+    /// Respond with 404, or a 404 item status for batch requests
+    NotFound,
+    /// Respond as for a known package without any data
+    Empty,
+}
+
+impl Default for UnknownPurl {
+    fn default() -> Self {
+        Self::NotFound
+    }
+}
+
+/// A purl of a batch request, as requested and in its canonical form.
+pub(crate) struct BatchPurl<'a> {
+    pub purl: &'a str,
+    pub canonical: String,
+    /// Why the purl is invalid, reported without looking it up
+    pub invalid: Option<TrustError>,
+}
+
+/// Bounds of batch requests and of the errors reported by batch responses.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BatchConfig {
+    /// Error messages of items are truncated to this many characters
+    pub max_error_length: usize,
+    /// Only this many failed items carry an error message, the others only their code
+    pub max_error_messages: usize,
+    /// Larger JSON request bodies are rejected with `413`
+    pub max_body_bytes: usize,
+    /// Batch requests with more purls are rejected
+    pub max_items: usize,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            max_error_length: 200,
+            max_error_messages: 100,
+            max_body_bytes: 2 * 1024 * 1024,
+            max_items: 1000,
+        }
+    }
+}
+
+impl BatchConfig {
+    /// Limit JSON request bodies, rejecting larger ones with [`ApiError::PayloadTooLarge`].
+    pub(crate) fn json_config(&self) -> web::JsonConfig {
+        let limit = self.max_body_bytes;
+        web::JsonConfig::default()
+            .limit(limit)
+            .error_handler(move |err, _| match err {
+                JsonPayloadError::Overflow { .. }
+                | JsonPayloadError::OverflowKnownLength { .. } => {
+                    ApiError::PayloadTooLarge { limit }.into()
+                }
+                err => err.into(),
+            })
+    }
+
+    /// Cap the error messages of batch items, so batches of failures stay small.
+    pub(crate) fn limit<T>(&self, items: &mut [BatchItem<T>]) {
+        let mut messages = 0;
+        for item in items.iter_mut() {
+            let error = match item.error.take() {
+                Some(error) => error,
+                None => continue,
+            };
+            if messages >= self.max_error_messages {
+                continue;
+            }
+            messages += 1;
+            item.error = Some(if error.chars().count() > self.max_error_length {
+                let mut truncated: String = error.chars().take(self.max_error_length).collect();
+                truncated.push('…');
+                truncated
+            } else {
+                error
+            });
+        }
+    }
+}
+
+/// Turn the result for a single purl into an item of a batch response.
+pub(crate) fn batch_item<T, E: Into<ApiError>>(
+    purl: &str,
+    canonical: &str,
+    result: Result<T, E>,
+) -> BatchItem<T> {
+    let canonical_purl = (purl != canonical).then(|| canonical.to_string());
+    match result {
+        Ok(result) => BatchItem {
+            purl: purl.to_string(),
+            canonical_purl,
+            status: StatusCode::OK.as_u16(),
+            result: Some(result),
+            error: None,
+            code: None,
+            provider: None,
+        },
+        Err(e) => {
+            let e = e.into();
+            BatchItem {
+                purl: purl.to_string(),
+                canonical_purl,
+                status: e.status_code().as_u16(),
+                result: None,
+                error: Some(e.to_string()),
+                code: Some(crate::problem::code(&e)),
+                provider: match &e {
+                    ApiError::UpstreamError { upstream, .. }
+                    | ApiError::UpstreamUnavailable { upstream, .. } => Some(upstream.clone()),
+                    _ => None,
+                },
+            }
+        }
     }
 }
 
 #[utoipa::path(
+    context_path = "/api/v1",
     responses(
         (status = 200, description = "Package found", body = Package),
-        (status = NOT_FOUND, description = "Package not found", body = Package, example = json!({
-                "error": "Package pkg:rpm/redhat/openssl@1.1.1k-7.el8_9 was not found",
-                "status": 404
-        })),
-        (status = BAD_REQUEST, description = "Invalid package URL"),
-        (status = BAD_REQUEST, description = "Missing query argument")
+        (status = NOT_MODIFIED, description = "The package did not change since it was fetched with the ETag in `If-None-Match`"),
+        (status = NOT_FOUND, description = "Package not found", body = Problem, example = json!(crate::problem::example(ApiError::PackageNotFound { purl: "pkg:maven/org.example/unknown@1.0".to_string() }))),
+        (status = BAD_REQUEST, description = "Invalid package URL, or missing query argument", body = Problem, example = json!(crate::problem::example(ApiError::InvalidPackageUrl { purl: "maven/io.vertx/vertx-web@4.3.7".to_string() }))),
     ),
     params(
         ("purl" = String, Query, description = "Package URL to query"),
+        ("strict" = Option<bool>, Query, description = "Match all qualifiers, not only the significant ones"),
+        ("include_ids" = Option<bool>, Query, description = "Include provider identifiers of vulnerabilities"),
     )
 )]
-#[get("/api/package")]
+#[get("/package")]
 pub async fn get_package(
+    req: HttpRequest,
     data: web::Data<TrustedContent>,
     query: web::Query<PackageQuery>,
 ) -> Result<HttpResponse, ApiError> {
     if let Some(purl) = &query.purl {
-        let p = data.get_trusted(purl).await?;
-        Ok(HttpResponse::Ok().json(p))
+        let purl = data.canonicalize_with(purl, query.strict);
+        let tag = |generation: &str| {
+            crate::etag::of(&[
+                purl.as_bytes(),
+                generation.as_bytes(),
+                &[query.include_ids as u8],
+            ])
+        };
+        // a cached evaluation is identified by its generation, so clients which have it are
+        // answered without evaluating or serializing the package again
+        let (p, etag) = match data.cached(&purl).await {
+            Some((p, generation)) => {
+                let etag = tag(&generation);
+                if let Some(response) = crate::etag::not_modified(&req, &etag) {
+                    return Ok(response);
+                }
+                (p, Some(etag))
+            }
+            None => {
+                let p = data.get_trusted(&purl).await?;
+                let etag = data.cached(&purl).await.map(|(_, g)| tag(&g));
+                (p, etag)
+            }
+        };
+        let p = with_ids(p, query.include_ids);
+        let body = serde_json::to_vec(&p).map_err(|_| ApiError::InternalError)?;
+        // degraded and unknown packages are not cached, their data is what identifies them
+        let etag = etag.unwrap_or_else(|| crate::etag::of(&[purl.as_bytes(), &body]));
+        if let Some(response) = crate::etag::not_modified(&req, &etag) {
+            return Ok(response);
+        }
+        let mut response = HttpResponse::Ok();
+        crate::etag::tag(&mut response, &etag);
+        Ok(response.content_type(mime::APPLICATION_JSON).body(body))
     } else {
         Err(ApiError::MissingQueryArgument)
     }
 }
 
 #[utoipa::path(
+    context_path = "/api/v1",
     responses(
         (status = 200, description = "Get the entire inventory", body = Vec<Package>),
+        (status = BAD_GATEWAY, description = "Guac failed", body = Problem, example = json!(crate::problem::example(ApiError::UpstreamError { upstream: "guac".to_string(), status: 502, correlation_id: "5b0e1f3c-8a8e-4a77-9a3d-7f6b2c1d9e40".to_string() }))),
+        (status = SERVICE_UNAVAILABLE, description = "Guac could not be reached", body = Problem, example = json!(crate::problem::example(ApiError::UpstreamUnavailable { upstream: "guac".to_string(), correlation_id: "5b0e1f3c-8a8e-4a77-9a3d-7f6b2c1d9e40".to_string() }))),
+    ),
+    params(
+        ("include_ids" = Option<bool>, Query, description = "Include provider identifiers of vulnerabilities"),
     )
 )]
-#[get("/api/trusted")]
-pub async fn get_trusted(data: web::Data<TrustedContent>) -> Result<HttpResponse, ApiError> {
-    Ok(HttpResponse::Ok().json(data.get_all_trusted().await?))
+#[get("/trusted")]
+pub async fn get_trusted(
+    data: web::Data<TrustedContent>,
+    query: web::Query<IdsQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let packages: Vec<Package> = data
+        .get_all_trusted()
+        .await?
+        .into_iter()
+        .map(|p| with_ids(p, query.include_ids))
+        .collect();
+    Ok(HttpResponse::Ok().json(packages))
 }
 
 #[utoipa::path(
-    request_body = PackageList,
+    context_path = "/api/v1",
+    request_body(content = PackageList, description = "At most `api.batch.max_items` package URLs, 1000 by default"),
     responses(
-        (status = 200, description = "Package found", body = Vec<Option<Package>>),
-        (status = NOT_FOUND, description = "Package not found", body = Package, example = json!({
-            "error": "Package pkg:rpm/redhat/openssl@1.1.1k-7.el8_9 was not found",
-            "status": 404
-    })),
-        (status = BAD_REQUEST, description = "Invalid package URLs"),
+        (status = 200, description = "Result for each requested package", body = Vec<PackageBatchItem>, example = json!([
+            {
+                "purl": "pkg:rpm/redhat/openssl@1.1.1k-7.el8_6",
+                "status": 200,
+                "result": {
+                    "purl": "pkg:rpm/redhat/openssl@1.1.1k-7.el8_6",
+                    "href": "/api/v1/package?purl=pkg%3Arpm%2Fredhat%2Fopenssl%401.1.1k-7.el8_6",
+                    "trusted": true,
+                    "vulnerabilities": [{"cve": "cve-2023-0286", "href": "https://access.redhat.com/security/cve/cve-2023-0286", "severity": "high"}]
+                }
+            },
+            {
+                "purl": "pkg:maven/org.example/unknown@1.0",
+                "status": 404,
+                "error": "Package pkg:maven/org.example/unknown@1.0 was not found",
+                "code": "PackageNotFound"
+            }
+        ])),
+        (status = BAD_REQUEST, description = "No or too many package URLs", body = Problem, example = json!(crate::problem::example(ApiError::EmptyRequest))),
+        (status = PAYLOAD_TOO_LARGE, description = "The request body exceeds `api.batch.max_body_bytes`", body = Problem, example = json!(crate::problem::example(ApiError::PayloadTooLarge { limit: 2 * 1024 * 1024 }))),
     ),
+    params(
+        ("include_ids" = Option<bool>, Query, description = "Include provider identifiers of vulnerabilities"),
+        ("strict" = Option<bool>, Query, description = "Match all qualifiers, not only the significant ones"),
+    )
 )]
-#[post("/api/package")]
+#[post("/package")]
 pub async fn query_package(
     data: web::Data<TrustedContent>,
+    query: web::Query<IdsQuery>,
     body: Json<PackageList>,
 ) -> Result<HttpResponse, ApiError> {
-    let mut packages: Vec<Option<Package>> = Vec::new();
-    for purl in body.list().iter() {
-        if let Ok(p) = data.get_trusted(purl).await {
-            packages.push(Some(p));
-        }
-    }
-
-    if packages.is_empty() {
-        Err(ApiError::PackageNotFound {
-            purl: body
-                .list()
-                .first()
-                .ok_or(ApiError::MissingQueryArgument)?
-                .to_string(),
-        })
-    } else {
-        Ok(HttpResponse::Ok().json(packages))
+    let items = data.validate_batch(body.list(), query.strict)?;
+    let canonical: Vec<&str> = items.iter().map(|i| i.canonical.as_str()).collect();
+    let found = data.get_trusted_batch(&canonical).await;
+    let mut packages = Vec::new();
+    for (item, found) in items.into_iter().zip(found) {
+        let result = match item.invalid {
+            Some(e) => Err(e),
+            None => found.map(|p| with_ids(p, query.include_ids)),
+        };
+        packages.push(batch_item(item.purl, &item.canonical, result));
     }
+    data.batch().limit(&mut packages);
+    Ok(HttpResponse::Ok().json(packages))
 }
 
 #[utoipa::path(
-    request_body = PackageList,
+    context_path = "/api/v1",
+    request_body(content = PackageList, description = "At most `api.batch.max_items` package URLs, 1000 by default"),
     responses(
-        (status = 200, description = "Package found", body = Vec<PackageDependencies>),
-        (status = BAD_REQUEST, description = "Invalid package URL"),
+        (status = 200, description = "Dependencies of each requested package", body = Vec<DependenciesBatchItem>, example = json!([
+            {
+                "purl": "pkg:maven/io.vertx/vertx-web@4.3.7",
+                "status": 200,
+                "result": [{"purl": "pkg:maven/io.vertx/vertx-web-common@4.3.7", "href": "/api/v1/package?purl=pkg%3Amaven%2Fio.vertx%2Fvertx-web-common%404.3.7"}]
+            }
+        ])),
+        (status = BAD_REQUEST, description = "No or too many package URLs, or invalid depth", body = Problem, example = json!(crate::problem::example(ApiError::InvalidDepth { depth: 0 }))),
+        (status = PAYLOAD_TOO_LARGE, description = "The request body exceeds `api.batch.max_body_bytes`", body = Problem, example = json!(crate::problem::example(ApiError::PayloadTooLarge { limit: 2 * 1024 * 1024 }))),
     ),
+    params(
+        ("depth" = Option<usize>, Query, description = "How many levels of dependencies to include, defaults to 1"),
+        ("type" = Option<String>, Query, description = "Only include dependencies of this package type, e.g. `maven`"),
+        ("namespace" = Option<String>, Query, description = "Only include dependencies whose namespace starts with this prefix"),
+        ("vulnerable_only" = Option<bool>, Query, description = "Only include dependencies with vulnerabilities"),
+        ("strict" = Option<bool>, Query, description = "Match all qualifiers, not only the significant ones"),
+    )
 )]
-#[post("/api/package/dependencies")]
+#[post("/package/dependencies")]
 pub async fn query_package_dependencies(
-    data: web::Data<Arc<Guac>>,
+    data: web::Data<TrustedContent>,
+    query: web::Query<DepthQuery>,
     body: Json<PackageList>,
 ) -> Result<HttpResponse, ApiError> {
-    let mut dependencies: Vec<PackageDependencies> = Vec::new();
-    for purl in body.list().iter() {
-        if PackageUrl::from_str(purl).is_ok() {
-            let lst = data
-                .get_dependencies(purl)
-                .await
-                .map_err(|_| ApiError::InternalError)?;
-            dependencies.push(lst);
-        } else {
-            return Err(ApiError::InvalidPackageUrl {
-                purl: purl.to_string(),
-            });
-        }
+    let depth = query.depth()?;
+    let filter = query.filter();
+    let purls = data.validate_batch(body.list(), query.strict)?;
+
+    let mut dependencies = Vec::new();
+    for item in purls {
+        let result = match item.invalid {
+            Some(e) => Err(e),
+            None => match data.get_dependencies(&item.canonical, depth).await {
+                Ok(found) => Ok(data.filter_dependencies(found, &filter).await),
+                Err(e) => Err(e),
+            },
+        };
+        dependencies.push(batch_item(item.purl, &item.canonical, result));
     }
+    data.batch().limit(&mut dependencies);
     Ok(HttpResponse::Ok().json(dependencies))
 }
 
 #[utoipa::path(
-    request_body = PackageList,
+    context_path = "/api/v1",
+    request_body(content = PackageList, description = "At most `api.batch.max_items` package URLs, 1000 by default"),
     responses(
-        (status = 200, description = "Package found", body = Vec<PackageDependents>),
-        (status = BAD_REQUEST, description = "Invalid package URL"),
+        (status = 200, description = "Dependents of each requested package", body = Vec<DependenciesBatchItem>, example = json!([
+            {
+                "purl": "pkg:maven/io.vertx/vertx-web@4.3.7",
+                "status": 200,
+                "result": [{"purl": "pkg:maven/io.quarkus/quarkus-vertx-http@2.16.2.Final", "href": "/api/v1/package?purl=pkg%3Amaven%2Fio.quarkus%2Fquarkus-vertx-http%402.16.2.Final"}]
+            }
+        ])),
+        (status = BAD_REQUEST, description = "No or too many package URLs, or invalid depth", body = Problem, example = json!(crate::problem::example(ApiError::InvalidDepth { depth: 0 }))),
+        (status = PAYLOAD_TOO_LARGE, description = "The request body exceeds `api.batch.max_body_bytes`", body = Problem, example = json!(crate::problem::example(ApiError::PayloadTooLarge { limit: 2 * 1024 * 1024 }))),
     ),
+    params(
+        ("depth" = Option<usize>, Query, description = "How many levels of dependents to include, defaults to 1"),
+        ("type" = Option<String>, Query, description = "Only include dependents of this package type, e.g. `maven`"),
+        ("namespace" = Option<String>, Query, description = "Only include dependents whose namespace starts with this prefix"),
+        ("vulnerable_only" = Option<bool>, Query, description = "Only include dependents with vulnerabilities"),
+        ("strict" = Option<bool>, Query, description = "Match all qualifiers, not only the significant ones"),
+    )
 )]
-#[post("/api/package/dependents")]
+#[post("/package/dependents")]
 pub async fn query_package_dependents(
-    data: web::Data<Arc<Guac>>,
+    data: web::Data<TrustedContent>,
+    query: web::Query<DepthQuery>,
     body: Json<PackageList>,
 ) -> Result<HttpResponse, ApiError> {
-    let mut dependencies: Vec<PackageDependencies> = Vec::new();
-    for purl in body.list().iter() {
-        if PackageUrl::from_str(purl).is_ok() {
-            let lst = data
-                .get_dependents(purl)
-                .await
-                .map_err(|_| ApiError::InternalError)?;
-            dependencies.push(lst);
-        } else {
-            return Err(ApiError::InvalidPackageUrl {
-                purl: purl.to_string(),
-            });
-        }
+    let depth = query.depth()?;
+    let filter = query.filter();
+    let purls = data.validate_batch(body.list(), query.strict)?;
+
+    let mut dependents = Vec::new();
+    for item in purls {
+        let result = match item.invalid {
+            Some(e) => Err(e),
+            None => match data.get_dependents(&item.canonical, depth).await {
+                Ok(found) => Ok(data.filter_dependencies(found, &filter).await),
+                Err(e) => Err(e),
+            },
+        };
+        dependents.push(batch_item(item.purl, &item.canonical, result));
     }
-    Ok(HttpResponse::Ok().json(dependencies))
+    data.batch().limit(&mut dependents);
+    Ok(HttpResponse::Ok().json(dependents))
 }
 
 #[utoipa::path(
-    request_body = PackageList,
+    context_path = "/api/v1",
+    request_body(content = PackageList, description = "At most `api.batch.max_items` package URLs, 1000 by default"),
     responses(
-        (status = 200, description = "Package found", body = Vec<PackageRef>, example = json!(vec![
-            (PackageRef {
-                purl: "pkg:maven/io.vertx/vertx-web@4.3.4.redhat-00007".to_string(),
-                href: format!("/api/package?purl={}", &urlencoding::encode("pkg:maven/io.vertx/vertx-web@4.3.4.redhat-00007")),
-                trusted: Some(true),
-                sbom: None,
-                })]
+        (status = 200, description = "Versions of each requested package", body = Vec<VersionsBatchItem>, example = json!(vec![
+            BatchItem {
+                purl: "pkg:maven/io.vertx/vertx-web@4.3.4".to_string(),
+                canonical_purl: None,
+                status: 200,
+                result: Some(vec![PackageRef {
+                    purl: "pkg:maven/io.vertx/vertx-web@4.3.4.redhat-00007".to_string(),
+                    href: format!("/api/v1/package?purl={}", &urlencoding::encode("pkg:maven/io.vertx/vertx-web@4.3.4.redhat-00007")),
+                    trusted: Some(true),
+                    unverified: false,
+                    sbom: None,
+                    vulnerabilities: Some(0),
+                    published: None,
+                    fetched: None,
+                    relation: None,
+                    dependency: None,
+                    degraded: vec![],
+                }]),
+                error: None,
+                code: None,
+                provider: None,
+            }]
         )),
-        (status = BAD_REQUEST, description = "Invalid package URL"),
+        (status = BAD_REQUEST, description = "No or too many package URLs", body = Problem, example = json!(crate::problem::example(ApiError::EmptyRequest))),
+        (status = PAYLOAD_TOO_LARGE, description = "The request body exceeds `api.batch.max_body_bytes`", body = Problem, example = json!(crate::problem::example(ApiError::PayloadTooLarge { limit: 2 * 1024 * 1024 }))),
     ),
+    params(
+        ("trusted_only" = Option<bool>, Query, description = "Only include trusted rebuilds"),
+        ("same_major" = Option<bool>, Query, description = "Only include versions of the same major version"),
+        ("strict" = Option<bool>, Query, description = "Match all qualifiers, not only the significant ones"),
+    )
 )]
-#[post("/api/package/versions")]
+#[post("/package/versions")]
 pub async fn query_package_versions(
     data: web::Data<TrustedContent>,
+    query: web::Query<VersionsQuery>,
     body: Json<PackageList>,
 ) -> Result<HttpResponse, ApiError> {
+    let items = data.validate_batch(body.list(), query.strict)?;
+    let data = &data;
+    let query = &query;
+    let found: Vec<(&str, String, Result<Vec<PackageRef>, TrustError>)> =
+        futures::stream::iter(items)
+            .map(|item| async move {
+                let found = match item.invalid {
+                    Some(e) => Err(e),
+                    None => data.get_versions(&item.canonical).await,
+                };
+                let found = found.map(|found| {
+                    let found = found
+                        .into_iter()
+                        .filter(|v| !query.trusted_only || v.trusted == Some(true))
+                        .collect();
+                    // validated by the batch
+                    match PackageUrl::from_str(&item.canonical) {
+                        Ok(purl) => crate::version::arrange(
+                            purl.ty(),
+                            purl.version(),
+                            found,
+                            query.same_major,
+                        ),
+                        Err(_) => found,
+                    }
+                });
+                (item.purl, item.canonical, found)
+            })
+            .buffered(VERSION_CONCURRENCY)
+            .collect()
+            .await;
+
+    // describe the versions of all packages together, then hand them back to their package
+    let all = found
+        .iter()
+        .filter_map(|(_, _, found)| found.as_ref().ok())
+        .flatten()
+        .cloned()
+        .collect::<Vec<_>>();
+    let mut described = data.describe_versions(all).await.into_iter();
     let mut versions = Vec::new();
-    for purl_str in body.list().iter() {
-        if PackageUrl::from_str(purl_str).is_ok() {
-            versions = data.get_versions(purl_str).await?;
-        } else {
-            return Err(ApiError::InvalidPackageUrl {
-                purl: purl_str.to_string(),
-            });
-        }
+    for (purl, canonical, found) in found {
+        let result = found.map(|found| described.by_ref().take(found.len()).collect::<Vec<_>>());
+        versions.push(batch_item(purl, &canonical, result));
     }
+    data.batch().limit(&mut versions);
     Ok(HttpResponse::Ok().json(versions))
 }
 
@@ -295,30 +1762,102 @@ pub struct SBOMQuery {
     purl: Option<String>,
     #[serde(default)]
     download: bool,
+    /// Match all qualifiers, not only the significant ones
+    #[serde(default)]
+    strict: bool,
+    /// Convert the SBOM to this format, if it is in another one
+    format: Option<SbomFormat>,
 }
 
 #[utoipa::path(
-    request_body = PackageList,
+    context_path = "/api/v1",
     responses(
-        (status = 200, description = "SBOM found", body = serde_json::Value),
-        (status = BAD_REQUEST, description = "Invalid package URL"),
+        (status = 200, description = "SBOM found", body = serde_json::Value, headers(
+            ("Digest" = String, description = "The SHA-256 digest of the SBOM, e.g. `sha-256=O08OTx0PosT9HI+dvxp8ToC8Wouh...`"),
+            ("Content-Location" = String, description = "Where the SBOM is served by its digest, e.g. `/api/v1/sbom/3b4f0e4f1d0fa2c4...`, unless it was converted"),
+            ("X-Trust-Lossy-Fields" = String, description = "The fields lost converting the SBOM, e.g. `components[].scope, vulnerabilities`"),
+        ), example = json!({
+            "bomFormat": "CycloneDX",
+            "specVersion": "1.4",
+            "version": 1,
+            "metadata": {
+                "component": {"type": "library", "name": "vertx-web", "version": "4.3.7", "purl": "pkg:maven/io.vertx/vertx-web@4.3.7"}
+            },
+            "components": [
+                {"type": "library", "name": "vertx-web-common", "version": "4.3.7", "purl": "pkg:maven/io.vertx/vertx-web-common@4.3.7"}
+            ]
+        })),
+        (status = NOT_MODIFIED, description = "The SBOM did not change since it was fetched with the ETag in `If-None-Match`"),
+        (status = NOT_FOUND, description = "No SBOM is known for the package", body = Problem, example = json!(crate::problem::example(ApiError::PackageNotFound { purl: "pkg:maven/org.example/unknown@1.0".to_string() }))),
+        (status = BAD_REQUEST, description = "Missing query argument", body = Problem, example = json!(crate::problem::example(ApiError::MissingQueryArgument))),
+        (status = UNPROCESSABLE_ENTITY, description = "The SBOM can't be converted to the format", body = Problem, example = json!(crate::problem::example(ApiError::NotConvertible { purl: "pkg:maven/io.vertx/vertx-web@4.3.7".to_string() }))),
     ),
+    params(
+        ("purl" = String, Query, description = "Package URL to query"),
+        ("strict" = Option<bool>, Query, description = "Match all qualifiers, not only the significant ones"),
+        ("download" = Option<bool>, Query, description = "Serve the SBOM as an attachment"),
+        ("format" = Option<String>, Query, description = "Convert the SBOM to `cyclonedx` or `spdx`, lost fields are listed in `X-Trust-Lossy-Fields`"),
+    )
 )]
-#[get("/api/package/sbom")]
+#[get("/package/sbom")]
 pub async fn query_sbom(
+    req: HttpRequest,
     data: web::Data<Arc<SbomRegistry>>,
     query: web::Query<SBOMQuery>,
 ) -> Result<HttpResponse, ApiError> {
     if let Some(purl) = &query.purl {
-        if let Some(value) = data.lookup(purl) {
+        let purl = &match query.strict {
+            true => purl.clone(),
+            false => crate::purl::significant(purl).unwrap_or_else(|| purl.clone()),
+        };
+        // an SBOM only changes when it is indexed again, checked before serializing it
+        let format = query.format.map_or("", |f| f.name());
+        let etag = data.indexed(purl).map(|indexed| {
+            crate::etag::of(&[
+                purl.as_bytes(),
+                indexed.to_rfc3339().as_bytes(),
+                format.as_bytes(),
+            ])
+        });
+        if let Some(response) = etag
+            .as_ref()
+            .and_then(|etag| crate::etag::not_modified(&req, etag))
+        {
+            return Ok(response);
+        }
+        if let Some((mut value, mut sha256)) = data.lookup_with_digest(purl) {
             let mut response = HttpResponse::Ok();
+            if let Some(etag) = &etag {
+                crate::etag::tag(&mut response, etag);
+            }
+            let mut converted = false;
+            match query.format {
+                Some(format) if SbomFormat::of(&value) != Some(format) => {
+                    let conversion =
+                        crate::convert::convert_async(std::mem::take(&mut value), format)
+                            .await
+                            .ok_or_else(|| ApiError::NotConvertible {
+                                purl: purl.to_string(),
+                            })?;
+                    if !conversion.lossy.is_empty() {
+                        let lossy: Vec<String> = conversion.lossy.into_iter().collect();
+                        response.insert_header((LOSSY_FIELDS_HEADER, lossy.join(", ")));
+                    }
+                    value = conversion.document;
+                    sha256 = crate::sbom::sha256_of(&value);
+                    converted = true;
+                }
+                _ => {}
+            }
+            digested(&mut response, &sha256);
+            // converted documents are not stored, so they can't be fetched by their digest
+            if !converted {
+                located(&mut response, &sha256);
+            }
             if query.download {
                 response.append_header(ContentDisposition {
                     disposition: DispositionType::Attachment,
-                    parameters: vec![
-                        // TODO: I guess we can do better, but for now it's ok
-                        DispositionParam::Filename("sbom.json".to_string()),
-                    ],
+                    parameters: vec![DispositionParam::Filename(data.file_name(purl, &value))],
                 });
             }
             Ok(response.json(value))
@@ -332,7 +1871,270 @@ pub async fn query_sbom(
     }
 }
 
-#[derive(Debug, Error, Serialize, Deserialize)]
+/// Fetch an SBOM by the SHA-256 digest of its document, as served by `/package/sbom`.
+#[utoipa::path(
+    context_path = "/api/v1",
+    responses(
+        (status = 200, description = "SBOM found", body = serde_json::Value),
+        (status = NOT_MODIFIED, description = "The client already has the SBOM, per `If-None-Match`"),
+        (status = NOT_FOUND, description = "No SBOM has the digest", body = Problem, example = json!(crate::problem::example(ApiError::SbomNotFound { sha256: "3b4f0e4f1d0fa2c4fd1c8f9dbf1a7c4e8b0c5a8b9e1b6d7c2e3f4a5b6c7d8e9f".to_string() }))),
+    ),
+    params(
+        ("sha256" = String, Path, description = "The hex encoded SHA-256 digest of the SBOM"),
+    )
+)]
+#[get("/sbom/{sha256:[0-9a-fA-F]{64}}")]
+pub async fn get_sbom_by_digest(
+    req: HttpRequest,
+    data: web::Data<Arc<SbomRegistry>>,
+    sha256: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let sha256 = sha256.into_inner().to_lowercase();
+    let value = data
+        .lookup_digest(&sha256)
+        .ok_or_else(|| ApiError::SbomNotFound {
+            sha256: sha256.clone(),
+        })?;
+    // the content of a digest never changes
+    let etag = crate::etag::of(&[sha256.as_bytes()]);
+    if let Some(response) = crate::etag::not_modified(&req, &etag) {
+        return Ok(response);
+    }
+    let mut response = HttpResponse::Ok();
+    crate::etag::tag(&mut response, &etag);
+    digested(&mut response, &sha256);
+    located(&mut response, &sha256);
+    Ok(response.json(value))
+}
+
+/// Add the digest of an SBOM to its response, as `Digest` header (RFC 3230).
+fn digested(response: &mut actix_web::HttpResponseBuilder, sha256: &str) {
+    if let Ok(digest) = hex::decode(sha256) {
+        response.insert_header((
+            "Digest",
+            format!(
+                "sha-256={}",
+                base64::engine::general_purpose::STANDARD.encode(digest)
+            ),
+        ));
+    }
+}
+
+/// Add the location of an SBOM by its digest to its response.
+fn located(response: &mut actix_web::HttpResponseBuilder, sha256: &str) {
+    response.insert_header((
+        actix_web::http::header::CONTENT_LOCATION,
+        format!("/api/v1/sbom/{}", sha256),
+    ));
+}
+
+#[utoipa::path(
+    context_path = "/api/v1",
+    responses(
+        (status = 200, description = "Provenance found", body = Provenance),
+        (status = NOT_FOUND, description = "No provenance is known for the package", body = Problem, example = json!(crate::problem::example(ApiError::ProvenanceNotFound { purl: "pkg:maven/io.vertx/vertx-web@4.3.7".to_string() }))),
+        (status = BAD_REQUEST, description = "Invalid package URL", body = Problem, example = json!(crate::problem::example(ApiError::InvalidPackageUrl { purl: "maven/io.vertx/vertx-web@4.3.7".to_string() }))),
+    ),
+    params(
+        ("purl" = String, Query, description = "Package URL to query"),
+        ("strict" = Option<bool>, Query, description = "Match all qualifiers, not only the significant ones"),
+    )
+)]
+#[get("/package/provenance")]
+pub async fn get_provenance(
+    data: web::Data<TrustedContent>,
+    query: web::Query<PackageQuery>,
+) -> Result<HttpResponse, ApiError> {
+    if let Some(purl) = &query.purl {
+        let provenance = data
+            .get_provenance(&data.canonicalize_with(purl, query.strict))
+            .await?;
+        Ok(HttpResponse::Ok().json(provenance))
+    } else {
+        Err(ApiError::MissingQueryArgument)
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/v1",
+    responses(
+        (status = 200, description = "Licenses of the package", body = Vec<License>),
+        (status = NOT_FOUND, description = "Unknown package", body = Problem, example = json!(crate::problem::example(ApiError::PackageNotFound { purl: "pkg:maven/org.example/unknown@1.0".to_string() }))),
+        (status = BAD_REQUEST, description = "Invalid package URL", body = Problem, example = json!(crate::problem::example(ApiError::InvalidPackageUrl { purl: "maven/io.vertx/vertx-web@4.3.7".to_string() }))),
+    ),
+    params(
+        ("purl" = String, Query, description = "Package URL to query"),
+        ("strict" = Option<bool>, Query, description = "Match all qualifiers, not only the significant ones"),
+    )
+)]
+#[get("/package/licenses")]
+pub async fn get_licenses(
+    data: web::Data<TrustedContent>,
+    query: web::Query<PackageQuery>,
+) -> Result<HttpResponse, ApiError> {
+    if let Some(purl) = &query.purl {
+        let licenses = data
+            .get_licenses(&data.canonicalize_with(purl, query.strict))
+            .await?;
+        Ok(HttpResponse::Ok().json(licenses))
+    } else {
+        Err(ApiError::MissingQueryArgument)
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/v1",
+    request_body(content = PackageList, description = "At most `api.batch.max_items` package URLs, 1000 by default"),
+    responses(
+        (status = 200, description = "License check of each requested package", body = Vec<LicenseCheckBatchItem>, example = json!([
+            {
+                "purl": "pkg:maven/io.vertx/vertx-web@4.3.7",
+                "status": 200,
+                "result": {
+                    "allowed": true,
+                    "licenses": [{"expression": "EPL-2.0 OR Apache-2.0", "source": "guac"}]
+                }
+            },
+            {
+                "purl": "pkg:maven/org.example/gpl-library@1.0",
+                "status": 200,
+                "result": {
+                    "allowed": false,
+                    "licenses": [{"expression": "GPL-3.0-only", "source": "guac"}],
+                    "violations": [{"expression": "GPL-3.0-only", "reason": "Not allowed: GPL-3.0-only"}]
+                }
+            }
+        ])),
+        (status = BAD_REQUEST, description = "No or too many package URLs", body = Problem, example = json!(crate::problem::example(ApiError::EmptyRequest))),
+        (status = PAYLOAD_TOO_LARGE, description = "The request body exceeds `api.batch.max_body_bytes`", body = Problem, example = json!(crate::problem::example(ApiError::PayloadTooLarge { limit: 2 * 1024 * 1024 }))),
+    ),
+    params(
+        ("strict" = Option<bool>, Query, description = "Match all qualifiers, not only the significant ones"),
+    )
+)]
+#[post("/package/license-check")]
+pub async fn check_licenses(
+    data: web::Data<TrustedContent>,
+    query: web::Query<StrictQuery>,
+    body: Json<PackageList>,
+) -> Result<HttpResponse, ApiError> {
+    let mut checks = Vec::new();
+    for item in data.validate_batch(body.list(), query.strict)? {
+        let result = match item.invalid {
+            Some(e) => Err(e),
+            None => data.check_licenses(&item.canonical).await,
+        };
+        checks.push(batch_item(item.purl, &item.canonical, result));
+    }
+    data.batch().limit(&mut checks);
+    Ok(HttpResponse::Ok().json(checks))
+}
+
+#[utoipa::path(
+    context_path = "/api/v1",
+    responses(
+        (status = 200, description = "The analyses of the package, oldest first", body = PackageHistory),
+        (status = NOT_FOUND, description = "The package was never analyzed", body = Problem, example = json!(crate::problem::example(ApiError::HistoryNotFound { purl: "pkg:maven/io.vertx/vertx-web@4.3.7".to_string() }))),
+    ),
+    params(
+        ("purl" = String, Query, description = "Package URL to query"),
+        ("strict" = Option<bool>, Query, description = "Match all qualifiers, not only the significant ones"),
+    )
+)]
+#[get("/package/history")]
+pub async fn get_history(
+    data: web::Data<TrustedContent>,
+    reanalysis: web::Data<Arc<Reanalysis>>,
+    sboms: web::Data<Arc<SbomRegistry>>,
+    query: web::Query<PackageQuery>,
+) -> Result<HttpResponse, ApiError> {
+    if let Some(purl) = &query.purl {
+        let purl = data.canonicalize_with(purl, query.strict);
+        // analyses of the packages of other tenants' SBOMs are not theirs to know about
+        if sboms.is_hidden(&purl) {
+            return Err(ApiError::HistoryNotFound { purl });
+        }
+        match reanalysis.history(&purl).await {
+            Ok(Some(history)) => Ok(HttpResponse::Ok().json(history)),
+            Ok(None) => Err(ApiError::HistoryNotFound { purl }),
+            Err(e) => {
+                log::warn!("Error reading the history of {}: {:?}", purl, e);
+                Err(ApiError::InternalError)
+            }
+        }
+    } else {
+        Err(ApiError::MissingQueryArgument)
+    }
+}
+
+/// Explain why a package depends on another one, with the dependency chains leading to it.
+#[utoipa::path(
+    context_path = "/api/v1",
+    responses(
+        (status = 200, description = "The shortest dependency chains, at most 10", body = DependencyPaths),
+        (status = NOT_FOUND, description = "Unknown package", body = Problem, example = json!(crate::problem::example(ApiError::PackageNotFound { purl: "pkg:maven/org.example/unknown@1.0".to_string() }))),
+        (status = BAD_REQUEST, description = "Invalid package URL or depth", body = Problem, example = json!(crate::problem::example(ApiError::InvalidDepth { depth: 0 }))),
+    ),
+    params(
+        ("from" = String, Query, description = "Package URL of the root, e.g. a product"),
+        ("to" = String, Query, description = "Package URL of the dependency"),
+        ("depth" = Option<usize>, Query, description = "How many levels of dependencies to walk, defaults to 10"),
+        ("strict" = Option<bool>, Query, description = "Match all qualifiers, not only the significant ones"),
+    )
+)]
+#[get("/package/path")]
+pub async fn get_paths(
+    data: web::Data<TrustedContent>,
+    query: web::Query<PathQuery>,
+) -> Result<HttpResponse, ApiError> {
+    match (&query.from, &query.to) {
+        (Some(from), Some(to)) => {
+            if !(1..=MAX_DEPTH).contains(&query.depth) {
+                return Err(ApiError::InvalidDepth { depth: query.depth });
+            }
+            let paths = data
+                .get_paths(
+                    &data.canonicalize_with(from, query.strict),
+                    &data.canonicalize_with(to, query.strict),
+                    query.depth,
+                )
+                .await?;
+            Ok(HttpResponse::Ok().json(paths))
+        }
+        _ => Err(ApiError::MissingQueryArgument),
+    }
+}
+
+/// Map a community package to its trusted counterparts, e.g. the rebuilds of its version.
+#[utoipa::path(
+    context_path = "/api/v1",
+    responses(
+        (status = 200, description = "The trusted counterparts, most confident first", body = Counterparts),
+        (status = NOT_FOUND, description = "Unknown package", body = Problem, example = json!(crate::problem::example(ApiError::PackageNotFound { purl: "pkg:maven/org.example/unknown@1.0".to_string() }))),
+        (status = BAD_REQUEST, description = "Invalid package URL", body = Problem, example = json!(crate::problem::example(ApiError::InvalidPackageUrl { purl: "maven/io.vertx/vertx-web@4.3.7".to_string() }))),
+    ),
+    params(
+        ("purl" = String, Query, description = "Package URL of the community package"),
+        ("strict" = Option<bool>, Query, description = "Match all qualifiers, not only the significant ones"),
+    )
+)]
+#[get("/package/counterpart")]
+pub async fn get_counterparts(
+    data: web::Data<TrustedContent>,
+    query: web::Query<PackageQuery>,
+) -> Result<HttpResponse, ApiError> {
+    if let Some(purl) = &query.purl {
+        let counterparts = data
+            .get_counterparts(&data.canonicalize_with(purl, query.strict))
+            .await?;
+        Ok(HttpResponse::Ok().json(counterparts))
+    } else {
+        Err(ApiError::MissingQueryArgument)
+    }
+}
+
+#[derive(Debug, Error, Serialize, Deserialize, ToSchema)]
+#[schema(as = PackageApiError)]
 pub enum ApiError {
     #[error("No query argument was specified")]
     MissingQueryArgument,
@@ -340,16 +2142,101 @@ pub enum ApiError {
     PackageNotFound { purl: String },
     #[error("{purl} is not a valid package URL")]
     InvalidPackageUrl { purl: String },
+    #[error("The depth must be between 1 and {MAX_DEPTH}, not {depth}")]
+    InvalidDepth { depth: usize },
+    #[error("No provenance is known for {purl}")]
+    ProvenanceNotFound { purl: String },
+    #[error("{purl} was never analyzed")]
+    HistoryNotFound { purl: String },
+    #[error("No SBOM has the digest {sha256}")]
+    SbomNotFound { sha256: String },
+    #[error("The SBOM of {purl} is neither CycloneDX nor SPDX, it can't be converted")]
+    NotConvertible { purl: String },
     #[error("Error processing error internally")]
     InternalError,
+    #[error("The request body exceeds the limit of {limit} bytes")]
+    PayloadTooLarge { limit: usize },
+    #[error("The request lists no package URLs")]
+    EmptyRequest,
+    #[error("The request lists {count} package URLs, more than the limit of {limit}")]
+    BatchTooLarge { count: usize, limit: usize },
+    #[error("{upstream} failed, the error is logged with correlation id {correlation_id}")]
+    UpstreamError {
+        upstream: String,
+        status: u16,
+        correlation_id: String,
+    },
+    #[error(
+        "{upstream} could not be reached, the error is logged with correlation id {correlation_id}"
+    )]
+    UpstreamUnavailable {
+        upstream: String,
+        correlation_id: String,
+    },
+}
+
+impl ApiError {
+    /// Wrap an error from an upstream service, logging the original error with a correlation id.
+    ///
+    /// The error itself is not returned to the caller, it may reveal e.g. internal URLs of the
+    /// upstream service.
+    pub fn upstream(upstream: &str, error: anyhow::Error) -> Self {
+        let correlation_id =
+            crate::request_id::current().unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+        log::warn!("[{}] Error from {}: {:?}", correlation_id, upstream, error);
+        if crate::resilience::is_unavailable(&error) {
+            return ApiError::UpstreamUnavailable {
+                upstream: upstream.to_string(),
+                correlation_id,
+            };
+        }
+        let status = error
+            .downcast_ref::<reqwest::Error>()
+            .and_then(|e| e.status())
+            .map_or(StatusCode::BAD_GATEWAY.as_u16(), |s| s.as_u16());
+        ApiError::UpstreamError {
+            upstream: upstream.to_string(),
+            status,
+            correlation_id,
+        }
+    }
+}
+
+impl From<TrustError> for ApiError {
+    fn from(e: TrustError) -> Self {
+        match e {
+            TrustError::InvalidPackageUrl { purl, .. } => ApiError::InvalidPackageUrl { purl },
+            TrustError::PackageNotFound { purl } => ApiError::PackageNotFound { purl },
+            TrustError::ProvenanceNotFound { purl } => ApiError::ProvenanceNotFound { purl },
+            TrustError::Provider { provider, source } => ApiError::upstream(provider, source),
+        }
+    }
 }
 
 impl error::ResponseError for ApiError {
     fn error_response(&self) -> HttpResponse {
-        HttpResponse::build(self.status_code()).json(serde_json::json!({
-            "status": self.status_code().as_u16(),
-            "error": self.to_string(),
-        }))
+        let extensions = match self {
+            ApiError::UpstreamError {
+                upstream,
+                status,
+                correlation_id,
+                ..
+            } => serde_json::json!({
+                "upstream": upstream,
+                "upstreamStatus": status,
+                "correlationId": correlation_id,
+            }),
+            ApiError::UpstreamUnavailable {
+                upstream,
+                correlation_id,
+                ..
+            } => serde_json::json!({
+                "upstream": upstream,
+                "correlationId": correlation_id,
+            }),
+            _ => serde_json::json!({}),
+        };
+        crate::problem::response(self.status_code(), self, extensions)
     }
 
     fn status_code(&self) -> StatusCode {
@@ -357,7 +2244,91 @@ impl error::ResponseError for ApiError {
             ApiError::MissingQueryArgument => StatusCode::BAD_REQUEST,
             ApiError::PackageNotFound { purl: _ } => StatusCode::NOT_FOUND,
             ApiError::InvalidPackageUrl { purl: _ } => StatusCode::BAD_REQUEST,
+            ApiError::InvalidDepth { depth: _ } => StatusCode::BAD_REQUEST,
+            ApiError::ProvenanceNotFound { purl: _ } => StatusCode::NOT_FOUND,
+            ApiError::HistoryNotFound { purl: _ } => StatusCode::NOT_FOUND,
+            ApiError::SbomNotFound { sha256: _ } => StatusCode::NOT_FOUND,
+            ApiError::NotConvertible { purl: _ } => StatusCode::UNPROCESSABLE_ENTITY,
             ApiError::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::PayloadTooLarge { limit: _ } => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::EmptyRequest => StatusCode::BAD_REQUEST,
+            ApiError::BatchTooLarge { .. } => StatusCode::BAD_REQUEST,
+            ApiError::UpstreamError { .. } => StatusCode::BAD_GATEWAY,
+            ApiError::UpstreamUnavailable { .. } => StatusCode::SERVICE_UNAVAILABLE,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn package_ref(purl: &str) -> PackageRef {
+        PackageRef {
+            purl: purl.to_string(),
+            href: String::new(),
+            trusted: None,
+            unverified: false,
+            sbom: None,
+            vulnerabilities: None,
+            published: None,
+            fetched: None,
+            relation: None,
+            dependency: None,
+            degraded: vec![],
+        }
+    }
+
+    fn package(cves: &[&str]) -> Package {
+        serde_json::from_value(serde_json::json!({
+            "vulnerabilities": cves
+                .iter()
+                .map(|cve| serde_json::json!({"cve": cve, "href": ""}))
+                .collect::<Vec<_>>(),
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn packages_failing_to_be_checked_are_kept_as_degraded() {
+        let packages = ["a", "b", "c", "d"].map(package_ref).to_vec();
+        let found = vec![
+            Ok(package(&["CVE-2023-24815"])),
+            Ok(package(&[])),
+            Err(TrustError::provider("guac", anyhow::anyhow!("unavailable"))),
+            Err(TrustError::PackageNotFound {
+                purl: "d".to_string(),
+            }),
+        ];
+        let kept = retain_vulnerable(packages, found);
+        let kept: Vec<_> = kept
+            .iter()
+            .map(|p| (p.purl.as_str(), &p.degraded))
+            .collect();
+        assert_eq!(kept, [("a", &vec![]), ("c", &vec!["guac".to_string()])]);
+    }
+
+    #[test]
+    fn batch_items_report_repaired_purls_in_camel_case() {
+        let item =
+            batch_item::<(), ApiError>(" pkg:npm/lodash@4.17.21", "pkg:npm/lodash@4.17.21", Ok(()));
+        let json = serde_json::to_value(item).unwrap();
+        assert_eq!(json["canonicalPurl"], "pkg:npm/lodash@4.17.21");
+        assert!(json.get("canonical_purl").is_none());
+    }
+
+    #[test]
+    fn upstream_errors_are_not_returned_to_callers() {
+        let error = ApiError::upstream(
+            "guac",
+            anyhow::anyhow!("error sending request for url (http://10.0.0.1:8080/query)"),
+        );
+        assert!(matches!(error, ApiError::UpstreamError { status: 502, .. }));
+        assert!(!error.to_string().contains("10.0.0.1"));
+
+        let body = crate::problem::example(error);
+        assert_eq!(body["upstream"], "guac");
+        assert!(body["correlationId"].is_string());
+        assert!(!body.to_string().contains("10.0.0.1"));
+    }
+}
@@ -0,0 +1,527 @@
+use futures::future::BoxFuture;
+use packageurl::PackageUrl;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+
+use crate::gate::GateViolation;
+use crate::package::PackageRef;
+use crate::trusted::{NamingConvention, TrustedVersionResolver};
+
+/// Delegate trust and gate decisions to existing OPA policies, or to CEL expressions.
+///
+/// For Rego, the trust decision of a package is the rule `data.<package>.trusted`, the violations
+/// of a gate policy the rule `data.<package>.gate.<policy>.violations`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "engine", rename_all = "lowercase")]
+pub enum PolicyConfig {
+    /// Evaluate Rego policies in process, requires the `rego` feature
+    Rego {
+        /// The `.rego` files to load
+        policies: Vec<PathBuf>,
+        #[serde(default = "default_package")]
+        package: String,
+    },
+    /// Query an OPA server, e.g. a sidecar, through its REST API
+    Opa {
+        /// The base URL of the server, e.g. `http://localhost:8181`
+        url: String,
+        #[serde(default = "default_package")]
+        package: String,
+        #[serde(default = "default_timeout_secs")]
+        timeout_secs: u64,
+    },
+    /// Evaluate CEL expressions per package, requires the `cel` feature
+    Cel {
+        /// Trusts the packages it is true for, with their `purl` and `vulnerabilities`, the naming
+        /// conventions decide if unset
+        #[serde(default)]
+        trusted: Option<String>,
+        /// Gate policies by name, the packages it is false for break them
+        #[serde(default)]
+        gate: BTreeMap<String, String>,
+    },
+}
+
+fn default_package() -> String {
+    "trust".to_string()
+}
+
+fn default_timeout_secs() -> u64 {
+    2
+}
+
+impl PolicyConfig {
+    /// Whether support for the configured engine was built in.
+    pub fn is_supported(&self) -> bool {
+        match self {
+            Self::Rego { .. } => cfg!(feature = "rego"),
+            Self::Opa { .. } => true,
+            Self::Cel { .. } => cfg!(feature = "cel"),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum PolicyError {
+    #[error("Error evaluating {rule}: {message}")]
+    Evaluation { rule: String, message: String },
+    #[error("Unexpected result of {rule}: {message}")]
+    Result { rule: String, message: String },
+    #[error("OPA did not answer in time")]
+    Timeout,
+}
+
+/// Evaluates the configured Rego policies, in process or by OPA, or CEL expressions.
+pub enum PolicyEngine {
+    #[cfg(feature = "rego")]
+    Rego {
+        engines: Arc<Engines>,
+        package: String,
+    },
+    Opa(Opa),
+    #[cfg(feature = "cel")]
+    Cel(crate::cel::Cel),
+}
+
+impl PolicyEngine {
+    pub fn new(config: &PolicyConfig) -> Result<Self, anyhow::Error> {
+        match config {
+            #[cfg(feature = "rego")]
+            PolicyConfig::Rego { policies, package } => {
+                let mut engine = regorus::Engine::new();
+                for policy in policies {
+                    engine.add_policy_from_file(policy)?;
+                }
+                Ok(Self::Rego {
+                    engines: Arc::new(Engines::new(engine)),
+                    package: package.clone(),
+                })
+            }
+            PolicyConfig::Opa {
+                url,
+                package,
+                timeout_secs,
+            } => Ok(Self::Opa(Opa::new(
+                url,
+                package,
+                Duration::from_secs(*timeout_secs),
+            )?)),
+            #[cfg(feature = "cel")]
+            PolicyConfig::Cel { trusted, gate } => {
+                Ok(Self::Cel(crate::cel::Cel::new(trusted.as_ref(), gate)?))
+            }
+            #[allow(unreachable_patterns)]
+            config => anyhow::bail!("Policy engine {:?} is not supported by this build", config),
+        }
+    }
+
+    fn package(&self) -> &str {
+        match self {
+            #[cfg(feature = "rego")]
+            Self::Rego { package, .. } => package,
+            Self::Opa(opa) => &opa.package,
+            #[cfg(feature = "cel")]
+            Self::Cel(_) => "",
+        }
+    }
+
+    async fn eval(&self, rule: &str, input: &Value) -> Result<Option<Value>, PolicyError> {
+        match self {
+            #[cfg(feature = "rego")]
+            Self::Rego { engines, .. } => engines.eval(rule, input).await,
+            Self::Opa(opa) => opa.query(rule, input).await,
+            #[cfg(feature = "cel")]
+            Self::Cel(_) => Ok(None),
+        }
+    }
+
+    /// Whether the policies trust a package, `None` if they don't decide.
+    ///
+    /// The input document has the parts of its purl (see [`purl_input`]), and the `analysis` of
+    /// the package if it is looked up on its own.
+    pub async fn trusted(
+        &self,
+        purl: &PackageUrl<'_>,
+        analysis: Option<&Value>,
+    ) -> Result<Option<bool>, PolicyError> {
+        let mut input = purl_input(purl);
+        #[cfg(feature = "cel")]
+        if let Self::Cel(cel) = self {
+            return cel.trusted(&input, analysis);
+        }
+        if let Some(analysis) = analysis {
+            input["analysis"] = analysis.clone();
+        }
+        let rule = format!("{}.trusted", self.package());
+        match self.eval(&rule, &input).await? {
+            None => Ok(Some(false)),
+            Some(Value::Bool(trusted)) => Ok(Some(trusted)),
+            Some(other) => Err(PolicyError::Result {
+                rule,
+                message: format!("Expected a boolean, got {}", other),
+            }),
+        }
+    }
+
+    /// The violations of a gate policy, `None` if the policies don't define it.
+    ///
+    /// `input` is the analysis of the components of the release.
+    pub async fn violations(
+        &self,
+        policy: &str,
+        input: &Value,
+    ) -> Result<Option<Vec<GateViolation>>, PolicyError> {
+        #[cfg(feature = "cel")]
+        if let Self::Cel(cel) = self {
+            return cel.violations(policy, &input["analysis"]);
+        }
+        if policy.is_empty()
+            || !policy
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            return Ok(None);
+        }
+        let rule = format!("{}.gate.{}.violations", self.package(), policy);
+        match self.eval(&rule, input).await? {
+            None => Ok(None),
+            Some(violations) => {
+                serde_json::from_value(violations)
+                    .map(Some)
+                    .map_err(|e| PolicyError::Result {
+                        rule,
+                        message: e.to_string(),
+                    })
+            }
+        }
+    }
+}
+
+/// Rego engines with the policies loaded, evaluating on the blocking threads of the runtime.
+///
+/// An engine evaluates one rule at a time, idle ones are kept for the next evaluations, and more
+/// are cloned from the first while all are busy.
+#[cfg(feature = "rego")]
+pub struct Engines {
+    loaded: regorus::Engine,
+    idle: std::sync::Mutex<Vec<regorus::Engine>>,
+}
+
+#[cfg(feature = "rego")]
+impl Engines {
+    fn new(loaded: regorus::Engine) -> Self {
+        Self {
+            loaded,
+            idle: std::sync::Mutex::new(Vec::new()),
+        }
+    }
+
+    async fn eval(
+        self: &Arc<Self>,
+        rule: &str,
+        input: &Value,
+    ) -> Result<Option<Value>, PolicyError> {
+        let engines = self.clone();
+        let (evaluated, input) = (rule.to_string(), input.clone());
+        tokio::task::spawn_blocking(move || {
+            let idle = engines.idle.lock().unwrap().pop();
+            let mut engine = idle.unwrap_or_else(|| engines.loaded.clone());
+            let result = eval(&mut engine, &evaluated, &input);
+            engines.idle.lock().unwrap().push(engine);
+            result
+        })
+        .await
+        .map_err(|e| PolicyError::Evaluation {
+            rule: rule.to_string(),
+            message: e.to_string(),
+        })?
+    }
+}
+
+#[cfg(feature = "rego")]
+fn eval(
+    engine: &mut regorus::Engine,
+    rule: &str,
+    input: &Value,
+) -> Result<Option<Value>, PolicyError> {
+    let failed = |e: anyhow::Error| PolicyError::Evaluation {
+        rule: rule.to_string(),
+        message: e.to_string(),
+    };
+    engine.set_input(regorus::Value::from_json_str(&input.to_string()).map_err(failed)?);
+    match engine.eval_rule(format!("data.{}", rule)).map_err(failed)? {
+        regorus::Value::Undefined => Ok(None),
+        value => {
+            let json = value.to_json_str().map_err(failed)?;
+            serde_json::from_str(&json)
+                .map(Some)
+                .map_err(|e| PolicyError::Result {
+                    rule: rule.to_string(),
+                    message: e.to_string(),
+                })
+        }
+    }
+}
+
+/// A client of the data API of OPA.
+pub struct Opa {
+    url: String,
+    package: String,
+    client: reqwest::Client,
+}
+
+#[derive(Deserialize)]
+struct DataResponse {
+    #[serde(default)]
+    result: Option<Value>,
+}
+
+impl Opa {
+    pub fn new(url: &str, package: &str, timeout: Duration) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            url: url.to_string(),
+            package: package.to_string(),
+            client: reqwest::Client::builder().timeout(timeout).build()?,
+        })
+    }
+
+    async fn query(&self, rule: &str, input: &Value) -> Result<Option<Value>, PolicyError> {
+        let failed = |e: reqwest::Error| {
+            if e.is_timeout() {
+                PolicyError::Timeout
+            } else {
+                PolicyError::Evaluation {
+                    rule: rule.to_string(),
+                    message: e.to_string(),
+                }
+            }
+        };
+        let response: DataResponse = self
+            .client
+            .post(format!(
+                "{}/v1/data/{}",
+                self.url.trim_end_matches('/'),
+                rule.replace('.', "/")
+            ))
+            .json(&json!({ "input": input }))
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(failed)?
+            .json()
+            .await
+            .map_err(failed)?;
+        Ok(response.result)
+    }
+}
+
+/// The input document of the trust decision of a package.
+pub(crate) fn purl_input(purl: &PackageUrl<'_>) -> Value {
+    let qualifiers: BTreeMap<String, String> = purl
+        .qualifiers()
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+    json!({
+        "purl": purl.to_string(),
+        "type": purl.ty(),
+        "namespace": purl.namespace(),
+        "name": purl.name(),
+        "version": purl.version(),
+        "qualifiers": qualifiers,
+    })
+}
+
+/// Trusts the packages the policies trust, versions of community releases are still found by the
+/// naming conventions, as are trusted packages if the policies don't decide.
+pub struct PolicyResolver {
+    engine: Arc<PolicyEngine>,
+    naming: NamingConvention,
+}
+
+impl PolicyResolver {
+    pub fn new(engine: Arc<PolicyEngine>, naming: NamingConvention) -> Self {
+        Self { engine, naming }
+    }
+}
+
+impl TrustedVersionResolver for PolicyResolver {
+    fn is_trusted<'a>(
+        &'a self,
+        purl: &'a PackageUrl<'a>,
+        analysis: Option<&'a Value>,
+    ) -> BoxFuture<'a, bool> {
+        Box::pin(async move {
+            match self.engine.trusted(purl, analysis).await {
+                Ok(Some(trusted)) => trusted,
+                Ok(None) => self.naming.matches(purl),
+                Err(e) => {
+                    log::warn!("Error deciding whether {} is trusted: {}", purl, e);
+                    false
+                }
+            }
+        })
+    }
+
+    fn trusted_versions(
+        &self,
+        purl: &PackageUrl<'_>,
+        candidates: Vec<PackageRef>,
+    ) -> Vec<PackageRef> {
+        self.naming.trusted_versions(purl, candidates)
+    }
+
+    fn upstream_version(&self, purl: &PackageUrl<'_>) -> Option<String> {
+        self.naming.upstream_version(purl)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+    use std::str::FromStr;
+
+    /// An OPA server trusting the packages of the `redhat` namespace whose analysis lists no
+    /// vulnerabilities, with a `release` gate policy reporting every component.
+    async fn opa() -> String {
+        async fn data(request: HttpRequest, body: web::Json<Value>) -> HttpResponse {
+            let input = &body["input"];
+            match request.path() {
+                "/v1/data/trust/trusted" => HttpResponse::Ok().json(json!({
+                    "result": input["namespace"] == "redhat"
+                        && input["analysis"]["vulnerabilities"] == json!([])
+                })),
+                "/v1/data/trust/gate/release/violations" => HttpResponse::Ok().json(json!({
+                    "result": input["analysis"]["packages"]
+                        .as_array()
+                        .into_iter()
+                        .flatten()
+                        .map(|item| json!({"purl": item["purl"], "rule": "release", "reason": "Not yet"}))
+                        .collect::<Vec<_>>()
+                })),
+                "/v1/data/trust/gate/broken/violations" => HttpResponse::InternalServerError().finish(),
+                _ => HttpResponse::Ok().json(json!({})),
+            }
+        }
+        let server = HttpServer::new(|| App::new().default_service(web::post().to(data)))
+            .workers(1)
+            .disable_signals()
+            .bind(("127.0.0.1", 0))
+            .unwrap();
+        let url = format!("http://{}", server.addrs()[0]);
+        tokio::spawn(server.run());
+        url
+    }
+
+    fn engine(url: &str, package: &str) -> Arc<PolicyEngine> {
+        Arc::new(PolicyEngine::Opa(
+            Opa::new(url, package, Duration::from_secs(2)).unwrap(),
+        ))
+    }
+
+    fn purl(purl: &str) -> PackageUrl<'_> {
+        PackageUrl::from_str(purl).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn trust_is_decided_with_the_analysis() {
+        let engine = engine(&opa().await, "trust");
+        let redhat = purl("pkg:maven/redhat/vertx-web@4.3.4");
+        let clean = json!({"vulnerabilities": []});
+        let vulnerable = json!({"vulnerabilities": [{"cve": "cve-2023-0286"}]});
+
+        assert_eq!(
+            engine.trusted(&redhat, Some(&clean)).await.unwrap(),
+            Some(true)
+        );
+        assert_eq!(
+            engine.trusted(&redhat, Some(&vulnerable)).await.unwrap(),
+            Some(false)
+        );
+        assert_eq!(engine.trusted(&redhat, None).await.unwrap(), Some(false));
+        let community = purl("pkg:maven/io.vertx/vertx-web@4.3.7");
+        assert_eq!(
+            engine.trusted(&community, Some(&clean)).await.unwrap(),
+            Some(false)
+        );
+    }
+
+    #[actix_web::test]
+    async fn undefined_rules_do_not_trust_nor_define_gates() {
+        let engine = engine(&opa().await, "other");
+        let redhat = purl("pkg:maven/redhat/vertx-web@4.3.4");
+        assert_eq!(engine.trusted(&redhat, None).await.unwrap(), Some(false));
+        assert!(engine
+            .violations("release", &json!({}))
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[actix_web::test]
+    async fn gate_violations_are_queried() {
+        let engine = engine(&opa().await, "trust");
+        let input = json!({
+            "policy": "release",
+            "analysis": {"packages": [{"purl": "pkg:maven/io.vertx/vertx-web@4.3.7"}]}
+        });
+        let violations = engine.violations("release", &input).await.unwrap().unwrap();
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].purl, "pkg:maven/io.vertx/vertx-web@4.3.7");
+
+        // not a rule name, not queried
+        assert!(engine
+            .violations("../release", &input)
+            .await
+            .unwrap()
+            .is_none());
+        assert!(matches!(
+            engine.violations("broken", &input).await,
+            Err(PolicyError::Evaluation { .. })
+        ));
+    }
+
+    #[actix_web::test]
+    async fn packages_are_not_trusted_if_the_policies_fail() {
+        // nothing listens on the port of a server which was stopped
+        let url = {
+            let listener = std::net::TcpListener::bind(("127.0.0.1", 0)).unwrap();
+            format!("http://{}", listener.local_addr().unwrap())
+        };
+        let naming = NamingConvention::new(Default::default());
+        let resolver = PolicyResolver::new(engine(&url, "trust"), naming);
+        let redhat = purl("pkg:maven/redhat/vertx-web@4.3.4.redhat-00007");
+        assert!(!resolver.is_trusted(&redhat, None).await);
+    }
+
+    #[cfg(feature = "rego")]
+    #[tokio::test]
+    async fn rego_policies_are_evaluated_concurrently() {
+        let mut loaded = regorus::Engine::new();
+        loaded
+            .add_policy(
+                "trust.rego".to_string(),
+                "package trust\n\ntrusted { input.namespace == \"redhat\" }\n".to_string(),
+            )
+            .unwrap();
+        let engine = Arc::new(PolicyEngine::Rego {
+            engines: Arc::new(Engines::new(loaded)),
+            package: "trust".to_string(),
+        });
+        let redhat = purl("pkg:maven/redhat/vertx-web@4.3.4");
+        let community = purl("pkg:maven/io.vertx/vertx-web@4.3.7");
+        let decisions = futures::future::join_all(
+            (0..8).map(|i| engine.trusted(if i % 2 == 0 { &redhat } else { &community }, None)),
+        )
+        .await;
+        for (i, decision) in decisions.into_iter().enumerate() {
+            assert_eq!(decision.unwrap(), Some(i % 2 == 0));
+        }
+    }
+}
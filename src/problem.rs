@@ -0,0 +1,61 @@
+use actix_web::body::MessageBody;
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use serde::Serialize;
+use std::fmt::Display;
+
+pub use trust_api_model::problem::Problem;
+
+pub const CONTENT_TYPE: &str = "application/problem+json";
+
+/// The `code` of an error, the name of its variant.
+pub fn code<E: Serialize>(error: &E) -> String {
+    match serde_json::to_value(error) {
+        Ok(serde_json::Value::String(code)) => code,
+        Ok(serde_json::Value::Object(variant)) => {
+            variant.keys().next().cloned().unwrap_or_default()
+        }
+        _ => String::new(),
+    }
+}
+
+/// Build an RFC 7807 problem details response.
+///
+/// The `extensions` are added to the members of the problem.
+pub fn response<E: Serialize + Display>(
+    status: StatusCode,
+    error: &E,
+    extensions: serde_json::Value,
+) -> HttpResponse {
+    let detail = error.to_string();
+    let problem = Problem {
+        ty: "about:blank".to_string(),
+        title: status.canonical_reason().unwrap_or("Unknown").to_string(),
+        status: status.as_u16(),
+        error: detail.clone(),
+        detail,
+        code: code(error),
+        request_id: crate::request_id::current(),
+        upstream: None,
+        upstream_status: None,
+        correlation_id: None,
+    };
+    let mut body = serde_json::to_value(problem).unwrap_or_default();
+    if let (Some(body), serde_json::Value::Object(extensions)) = (body.as_object_mut(), extensions)
+    {
+        body.extend(extensions);
+    }
+
+    HttpResponse::build(status)
+        .content_type(CONTENT_TYPE)
+        .body(body.to_string())
+}
+
+/// The body of the response to an error, as example of the OpenAPI document.
+pub fn example<E: ResponseError>(error: E) -> serde_json::Value {
+    let body = error
+        .error_response()
+        .into_body()
+        .try_into_bytes()
+        .unwrap_or_default();
+    serde_json::from_slice(&body).unwrap_or_default()
+}
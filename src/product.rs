@@ -0,0 +1,463 @@
+use actix_web::{error, get, http::StatusCode, web, web::ServiceConfig, HttpResponse};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+use thiserror::Error;
+use utoipa::{OpenApi, ToSchema};
+
+use crate::error::TrustError;
+use crate::package::{scoped_key, PackageRef, Severity, TrustedContent};
+use crate::sbom::SbomRegistry;
+
+pub use trust_api_model::product::*;
+
+pub(crate) fn configure() -> impl FnOnce(&mut ServiceConfig) {
+    |config: &mut ServiceConfig| {
+        config.service(list_products);
+        config.service(get_product);
+        config.service(get_product_packages);
+        config.service(get_product_vulnerabilities);
+        config.service(get_product_summary);
+        config.service(get_sbom_summary);
+    }
+}
+
+/// The documentation of the product endpoints.
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        list_products,
+        get_product,
+        get_product_packages,
+        get_product_vulnerabilities,
+        get_product_summary,
+        get_sbom_summary
+    ),
+    components(schemas(Product, ProductSummary, SeverityCounts, RiskyComponent, ApiError))
+)]
+pub(crate) struct ApiDoc;
+
+/// The configured products.
+pub struct Products {
+    products: Vec<Product>,
+}
+
+impl Products {
+    pub fn new(products: Vec<Product>) -> Self {
+        Self { products }
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Product> {
+        self.products.iter().find(|p| p.id == id)
+    }
+
+    /// The products, optionally only the versions of a product name, ignoring case.
+    pub fn list(&self, name: Option<&str>) -> Vec<Product> {
+        self.products
+            .iter()
+            .filter(|p| name.map_or(true, |n| p.name.eq_ignore_ascii_case(n)))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Whether a product is visible to the running task, it is not if all its SBOMs are of other
+/// tenants.
+fn is_visible(sboms: &SbomRegistry, product: &Product) -> bool {
+    product.sboms.is_empty() || !product.sboms.iter().all(|sbom| sboms.is_hidden(sbom))
+}
+
+/// The purls of the SBOMs of a product and of their components, as far as visible to the running
+/// task.
+pub(crate) fn components(sboms: &SbomRegistry, product: &Product) -> Vec<String> {
+    let mut purls = BTreeSet::new();
+    for sbom in &product.sboms {
+        if let Some(document) = sboms.lookup(sbom) {
+            purls.insert(sbom.clone());
+            purls.extend(crate::sbom::component_purls(&document));
+        }
+    }
+    purls.into_iter().collect()
+}
+
+/// Check that the components of a product or SBOM are at most `api.batch.max_items`, as many as
+/// a batch request may look up.
+pub(crate) fn check_size(trusted: &TrustedContent, purls: &[String]) -> Result<(), ApiError> {
+    let limit = trusted.batch().max_items;
+    match purls.len() > limit {
+        true => Err(ApiError::TooManyComponents {
+            count: purls.len(),
+            limit,
+        }),
+        false => Ok(()),
+    }
+}
+
+/// How many of the riskiest components a summary lists.
+const RISKIEST: usize = 10;
+
+/// Summarize the components of a product or SBOM, looking them all up at once.
+///
+/// Summaries are cached by their components and the tenant of the caller, unless a provider
+/// failed.
+pub(crate) async fn summarize(
+    trusted: &TrustedContent,
+    purls: &[String],
+) -> Result<ProductSummary, ApiError> {
+    check_size(trusted, purls)?;
+    let digest = hex::encode(Sha256::digest(purls.join("\n")));
+    let key = scoped_key("summary", &digest, crate::tenant::current());
+    if let Some(summary) = trusted.cache().get(&key).await {
+        return Ok(summary);
+    }
+    let (summary, complete) = rollup(trusted, purls).await;
+    if complete {
+        trusted.cache().put(&key, &summary).await;
+    }
+    Ok(summary)
+}
+
+/// The summary of components, and whether all providers answered.
+async fn rollup(trusted: &TrustedContent, purls: &[String]) -> (ProductSummary, bool) {
+    let canonical: Vec<String> = purls.iter().map(|p| trusted.canonicalize(p)).collect();
+    let results = trusted
+        .get_trusted_batch(&canonical.iter().map(String::as_str).collect::<Vec<_>>())
+        .await;
+
+    let mut summary = ProductSummary {
+        components: purls.len(),
+        trusted: 0,
+        vulnerable: 0,
+        unresolved: 0,
+        severities: SeverityCounts::default(),
+        riskiest: Vec::new(),
+        with_trusted_alternatives: 0,
+        licenses: BTreeMap::new(),
+    };
+    let mut complete = true;
+    let mut severities: BTreeMap<String, Option<Severity>> = BTreeMap::new();
+    for (purl, result) in purls.iter().zip(results) {
+        let package = match result {
+            Ok(package) => package,
+            Err(e) => {
+                complete &= !matches!(e, TrustError::Provider { .. });
+                summary.unresolved += 1;
+                continue;
+            }
+        };
+        complete &= package.degraded.is_empty();
+        if package.trusted == Some(true) {
+            summary.trusted += 1;
+        } else if !package.trusted_versions.is_empty() {
+            summary.with_trusted_alternatives += 1;
+        }
+        let expressions: BTreeSet<&str> = package
+            .licenses
+            .iter()
+            .map(|l| l.expression.as_str())
+            .collect();
+        for expression in expressions {
+            *summary.licenses.entry(expression.to_string()).or_default() += 1;
+        }
+        if package.vulnerabilities.is_empty() {
+            continue;
+        }
+        summary.vulnerable += 1;
+        for vulnerability in &package.vulnerabilities {
+            let severity = severities.entry(vulnerability.cve.clone()).or_default();
+            *severity = (*severity).max(vulnerability.severity);
+        }
+        summary.riskiest.push(RiskyComponent {
+            purl: purl.clone(),
+            vulnerabilities: package.vulnerabilities.len(),
+            severity: package
+                .vulnerabilities
+                .iter()
+                .filter_map(|v| v.severity)
+                .max(),
+        });
+    }
+    for severity in severities.into_values() {
+        let count = match severity {
+            Some(Severity::Critical) => &mut summary.severities.critical,
+            Some(Severity::High) => &mut summary.severities.high,
+            Some(Severity::Medium) => &mut summary.severities.medium,
+            Some(Severity::Low) => &mut summary.severities.low,
+            None => &mut summary.severities.unknown,
+        };
+        *count += 1;
+    }
+    summary.riskiest.sort_by(|a, b| {
+        b.severity
+            .cmp(&a.severity)
+            .then(b.vulnerabilities.cmp(&a.vulnerabilities))
+            .then(a.purl.cmp(&b.purl))
+    });
+    summary.riskiest.truncate(RISKIEST);
+    (summary, complete)
+}
+
+/// The product of an id, as far as visible to the running task.
+fn find<'a>(
+    products: &'a Products,
+    sboms: &SbomRegistry,
+    id: &str,
+) -> Result<&'a Product, ApiError> {
+    products
+        .get(id)
+        .filter(|product| is_visible(sboms, product))
+        .ok_or_else(|| ApiError::ProductNotFound { id: id.to_string() })
+}
+
+#[derive(Deserialize)]
+pub struct ProductQuery {
+    /// Only the versions of the product of this name
+    name: Option<String>,
+}
+
+/// List the products and their versions.
+#[utoipa::path(
+    context_path = "/api/v1",
+    responses(
+        (status = 200, description = "The products", body = Vec<Product>),
+    ),
+    params(
+        ("name" = Option<String>, Query, description = "Only list the versions of the product of this name"),
+    )
+)]
+#[get("/product")]
+pub async fn list_products(
+    products: web::Data<Arc<Products>>,
+    sboms: web::Data<Arc<SbomRegistry>>,
+    query: web::Query<ProductQuery>,
+) -> HttpResponse {
+    let mut list = products.list(query.name.as_deref());
+    list.retain(|product| is_visible(&sboms, product));
+    HttpResponse::Ok().json(list)
+}
+
+/// Fetch a product.
+#[utoipa::path(
+    context_path = "/api/v1",
+    responses(
+        (status = 200, description = "The product", body = Product),
+        (status = NOT_FOUND, description = "Unknown product", body = Problem, example = json!(crate::problem::example(ApiError::ProductNotFound { id: "rhel-9.2".to_string() }))),
+    ),
+    params(
+        ("id" = String, Path, description = "The id of the product"),
+    )
+)]
+#[get("/product/{id}")]
+pub async fn get_product(
+    products: web::Data<Arc<Products>>,
+    sboms: web::Data<Arc<SbomRegistry>>,
+    id: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok().json(find(&products, &sboms, &id)?))
+}
+
+/// List the packages of a product: its SBOMs and their components.
+#[utoipa::path(
+    context_path = "/api/v1",
+    responses(
+        (status = 200, description = "The packages of the product", body = Vec<PackageRef>),
+        (status = NOT_FOUND, description = "Unknown product", body = Problem, example = json!(crate::problem::example(ApiError::ProductNotFound { id: "rhel-9.2".to_string() }))),
+    ),
+    params(
+        ("id" = String, Path, description = "The id of the product"),
+    )
+)]
+#[get("/product/{id}/packages")]
+pub async fn get_product_packages(
+    products: web::Data<Arc<Products>>,
+    sboms: web::Data<Arc<SbomRegistry>>,
+    id: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let product = find(&products, &sboms, &id)?;
+    let packages: Vec<PackageRef> = components(&sboms, product)
+        .into_iter()
+        .map(|purl| PackageRef {
+            href: format!("/api/v1/package?purl={}", &urlencoding::encode(&purl)),
+            trusted: None,
+            unverified: false,
+            sbom: sboms
+                .exists(&purl)
+                .then(|| format!("/api/v1/package/sbom?purl={}", &urlencoding::encode(&purl))),
+            vulnerabilities: None,
+            published: None,
+            fetched: None,
+            relation: None,
+            dependency: None,
+            degraded: vec![],
+            purl,
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(packages))
+}
+
+/// List the vulnerabilities affecting the packages of a product, with the packages they affect.
+#[utoipa::path(
+    context_path = "/api/v1",
+    responses(
+        (status = 200, description = "The vulnerabilities of the product", body = Vec<AffectingVulnerability>),
+        (status = NOT_FOUND, description = "Unknown product", body = Problem, example = json!(crate::problem::example(ApiError::ProductNotFound { id: "rhel-9.2".to_string() }))),
+        (status = UNPROCESSABLE_ENTITY, description = "There are more components than `api.batch.max_items`", body = Problem, example = json!(crate::problem::example(ApiError::TooManyComponents { count: 1200, limit: 1000 }))),
+    ),
+    params(
+        ("id" = String, Path, description = "The id of the product"),
+    )
+)]
+#[get("/product/{id}/vulnerabilities")]
+pub async fn get_product_vulnerabilities(
+    products: web::Data<Arc<Products>>,
+    sboms: web::Data<Arc<SbomRegistry>>,
+    trusted: web::Data<TrustedContent>,
+    id: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let product = find(&products, &sboms, &id)?;
+    let purls = components(&sboms, product);
+    check_size(&trusted, &purls)?;
+    let analysis = crate::analyze::analyze(&trusted, &purls, false).await;
+    Ok(HttpResponse::Ok().json(analysis.vulnerabilities))
+}
+
+/// Summarize the vulnerabilities, trust and licenses of the packages of a product.
+#[utoipa::path(
+    context_path = "/api/v1",
+    responses(
+        (status = 200, description = "The summary of the product", body = ProductSummary),
+        (status = NOT_FOUND, description = "Unknown product", body = Problem, example = json!(crate::problem::example(ApiError::ProductNotFound { id: "rhel-9.2".to_string() }))),
+        (status = UNPROCESSABLE_ENTITY, description = "There are more components than `api.batch.max_items`", body = Problem, example = json!(crate::problem::example(ApiError::TooManyComponents { count: 1200, limit: 1000 }))),
+    ),
+    params(
+        ("id" = String, Path, description = "The id of the product"),
+    )
+)]
+#[get("/product/{id}/summary")]
+pub async fn get_product_summary(
+    products: web::Data<Arc<Products>>,
+    sboms: web::Data<Arc<SbomRegistry>>,
+    trusted: web::Data<TrustedContent>,
+    id: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let product = find(&products, &sboms, &id)?;
+    let purls = components(&sboms, product);
+    Ok(HttpResponse::Ok().json(summarize(&trusted, &purls).await?))
+}
+
+#[derive(Deserialize)]
+pub struct SummaryQuery {
+    purl: String,
+}
+
+/// Summarize the vulnerabilities, trust and licenses of the components of an SBOM.
+#[utoipa::path(
+    context_path = "/api/v1",
+    responses(
+        (status = 200, description = "The summary of the SBOM", body = ProductSummary),
+        (status = NOT_FOUND, description = "No SBOM is known for the package", body = Problem, example = json!(crate::problem::example(ApiError::SbomNotFound { purl: "pkg:maven/org.example/unknown@1.0".to_string() }))),
+        (status = UNPROCESSABLE_ENTITY, description = "There are more components than `api.batch.max_items`", body = Problem, example = json!(crate::problem::example(ApiError::TooManyComponents { count: 1200, limit: 1000 }))),
+    ),
+    params(
+        ("purl" = String, Query, description = "The purl of the package the SBOM describes"),
+    )
+)]
+#[get("/sbom/summary")]
+pub async fn get_sbom_summary(
+    sboms: web::Data<Arc<SbomRegistry>>,
+    trusted: web::Data<TrustedContent>,
+    query: web::Query<SummaryQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let document = sboms
+        .lookup(&query.purl)
+        .ok_or_else(|| ApiError::SbomNotFound {
+            purl: query.purl.clone(),
+        })?;
+    let mut purls: BTreeSet<String> = crate::sbom::component_purls(&document)
+        .into_iter()
+        .collect();
+    purls.insert(query.purl.clone());
+    let purls: Vec<String> = purls.into_iter().collect();
+    Ok(HttpResponse::Ok().json(summarize(&trusted, &purls).await?))
+}
+
+#[derive(Debug, Error, Serialize, Deserialize, ToSchema)]
+#[schema(as = ProductApiError)]
+pub enum ApiError {
+    #[error("Product {id} was not found")]
+    ProductNotFound { id: String },
+    #[error("No SBOM is known for {purl}")]
+    SbomNotFound { purl: String },
+    #[error("{count} components are more than the {limit} looked up at once")]
+    TooManyComponents { count: usize, limit: usize },
+}
+
+impl error::ResponseError for ApiError {
+    fn error_response(&self) -> HttpResponse {
+        crate::problem::response(self.status_code(), self, serde_json::json!({}))
+    }
+
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::ProductNotFound { id: _ } => StatusCode::NOT_FOUND,
+            ApiError::SbomNotFound { purl: _ } => StatusCode::NOT_FOUND,
+            ApiError::TooManyComponents { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SBOM: &str = "pkg:oci/app@sha256:abc";
+
+    async fn registry() -> SbomRegistry {
+        let sboms = SbomRegistry::for_tests().await;
+        sboms.replicate(
+            vec![(
+                SBOM.to_string(),
+                Some("a".to_string()),
+                serde_json::json!({}),
+            )],
+            &[],
+        );
+        sboms
+    }
+
+    fn products() -> Products {
+        let product = |id: &str, sboms: &[&str]| Product {
+            id: id.to_string(),
+            name: id.to_string(),
+            version: "1.0".to_string(),
+            sboms: sboms.iter().map(ToString::to_string).collect(),
+        };
+        Products::new(vec![
+            product("tenant-only", &[SBOM]),
+            product("unindexed", &["pkg:oci/other@sha256:def"]),
+        ])
+    }
+
+    #[tokio::test]
+    async fn products_of_other_tenants_are_not_found() {
+        let sboms = registry().await;
+        let products = products();
+        crate::tenant::scoped(Some("b".to_string()), async {
+            assert!(matches!(
+                find(&products, &sboms, "tenant-only"),
+                Err(ApiError::ProductNotFound { .. })
+            ));
+            assert!(find(&products, &sboms, "unindexed").is_ok());
+        })
+        .await;
+        crate::tenant::scoped(Some("a".to_string()), async {
+            assert!(find(&products, &sboms, "tenant-only").is_ok());
+            assert_eq!(
+                components(&sboms, products.get("tenant-only").unwrap()),
+                [SBOM]
+            );
+        })
+        .await;
+    }
+}
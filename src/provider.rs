@@ -0,0 +1,156 @@
+use futures::future::BoxFuture;
+use packageurl::PackageUrl;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::ghsa::Ghsa;
+use crate::guac::Guac;
+use crate::nvd::Nvd;
+use crate::osv::Osv;
+use crate::package::VulnerabilityRef;
+use crate::resilience::Unavailable;
+use crate::snyk::SnykClient;
+
+/// The ids of the built-in vulnerability providers, which can be disabled by configuration.
+pub const BUILT_IN: &[&str] = &["guac", "snyk", "nvd", "ghsa", "osv"];
+
+/// How long vulnerability providers are waited for, before serving a package without their data.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProviderTimeouts {
+    /// Timeout of providers without their own, in milliseconds
+    pub default_ms: u64,
+    /// Timeouts by provider id, in milliseconds
+    pub providers: HashMap<String, u64>,
+}
+
+impl Default for ProviderTimeouts {
+    fn default() -> Self {
+        Self {
+            default_ms: 15000,
+            providers: HashMap::new(),
+        }
+    }
+}
+
+impl ProviderTimeouts {
+    pub fn timeout(&self, id: &str) -> Duration {
+        Duration::from_millis(*self.providers.get(id).unwrap_or(&self.default_ms))
+    }
+}
+
+/// The package to look up vulnerabilities of.
+pub struct VulnerabilityQuery<'a> {
+    pub purl: &'a PackageUrl<'a>,
+    /// The purl as requested
+    pub purl_str: &'a str,
+    /// The upstream version of trusted rebuilds, otherwise the version of the package
+    pub version: Option<&'a str>,
+}
+
+/// A source of vulnerabilities affecting packages.
+///
+/// The reports of all providers are merged, so providers report a vulnerability by its CVE id
+/// where they know one. Deployments can register their own providers, see
+/// [`crate::engine::EngineBuilder::vulnerability_provider`].
+pub trait VulnerabilityProvider: Send + Sync {
+    /// Identifies the provider in the `degraded` providers of packages, e.g. `snyk`.
+    fn id(&self) -> &str;
+
+    /// The vulnerabilities affecting a package.
+    ///
+    /// If this fails, the package is served without the vulnerabilities of this provider.
+    fn vulnerabilities<'a>(
+        &'a self,
+        query: &'a VulnerabilityQuery<'a>,
+    ) -> BoxFuture<'a, Result<Vec<VulnerabilityRef>, anyhow::Error>>;
+}
+
+impl VulnerabilityProvider for Guac {
+    fn id(&self) -> &str {
+        "guac"
+    }
+
+    fn vulnerabilities<'a>(
+        &'a self,
+        query: &'a VulnerabilityQuery<'a>,
+    ) -> BoxFuture<'a, Result<Vec<VulnerabilityRef>, anyhow::Error>> {
+        Box::pin(async move {
+            if !self.is_available() {
+                return Err(Unavailable {
+                    upstream: "guac",
+                    reason: "the last probe failed".to_string(),
+                }
+                .into());
+            }
+            self.get_vulnerabilities(query.purl_str).await
+        })
+    }
+}
+
+impl VulnerabilityProvider for SnykClient {
+    fn id(&self) -> &str {
+        "snyk"
+    }
+
+    fn vulnerabilities<'a>(
+        &'a self,
+        query: &'a VulnerabilityQuery<'a>,
+    ) -> BoxFuture<'a, Result<Vec<VulnerabilityRef>, anyhow::Error>> {
+        Box::pin(self.get_vulnerabilities(query.purl_str))
+    }
+}
+
+impl VulnerabilityProvider for Nvd {
+    fn id(&self) -> &str {
+        "nvd"
+    }
+
+    fn vulnerabilities<'a>(
+        &'a self,
+        query: &'a VulnerabilityQuery<'a>,
+    ) -> BoxFuture<'a, Result<Vec<VulnerabilityRef>, anyhow::Error>> {
+        let vulns = match query.version {
+            Some(version) => Nvd::vulnerabilities(self, query.purl, version),
+            None => Vec::new(),
+        };
+        Box::pin(futures::future::ready(Ok(vulns)))
+    }
+}
+
+impl VulnerabilityProvider for Ghsa {
+    fn id(&self) -> &str {
+        "ghsa"
+    }
+
+    fn vulnerabilities<'a>(
+        &'a self,
+        query: &'a VulnerabilityQuery<'a>,
+    ) -> BoxFuture<'a, Result<Vec<VulnerabilityRef>, anyhow::Error>> {
+        Box::pin(async move {
+            match query.version {
+                Some(version) => self.get_vulnerabilities(query.purl, version).await,
+                None => Ok(Vec::new()),
+            }
+        })
+    }
+}
+
+impl VulnerabilityProvider for Osv {
+    fn id(&self) -> &str {
+        "osv"
+    }
+
+    fn vulnerabilities<'a>(
+        &'a self,
+        query: &'a VulnerabilityQuery<'a>,
+    ) -> BoxFuture<'a, Result<Vec<VulnerabilityRef>, anyhow::Error>> {
+        Box::pin(async move {
+            match query.version {
+                Some(version) => self.get_vulnerabilities(query.purl, version).await,
+                None => Ok(Vec::new()),
+            }
+        })
+    }
+}
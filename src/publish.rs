@@ -0,0 +1,120 @@
+use serde::{Deserialize, Serialize};
+
+use crate::package::VulnerabilityRef;
+
+/// Where to publish events for downstream pipelines.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum PublishConfig {
+    /// Requires the `kafka` feature
+    Kafka { brokers: String, topic: String },
+    /// Requires the `nats` feature
+    Nats { url: String, subject: String },
+}
+
+impl PublishConfig {
+    /// Whether support for the configured publisher was built in.
+    pub fn is_supported(&self) -> bool {
+        match self {
+            Self::Kafka { .. } => cfg!(feature = "kafka"),
+            Self::Nats { .. } => cfg!(feature = "nats"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum PipelineEvent {
+    /// An SBOM was added to the registry
+    SbomIndexed { purl: String, source: String },
+    /// Vulnerabilities were newly found to affect a package
+    VulnerabilitiesFound {
+        purl: String,
+        vulnerabilities: Vec<VulnerabilityRef>,
+    },
+}
+
+impl PipelineEvent {
+    fn key(&self) -> &str {
+        match self {
+            Self::SbomIndexed { purl, .. } => purl,
+            Self::VulnerabilitiesFound { purl, .. } => purl,
+        }
+    }
+}
+
+/// Publishes events to Kafka or NATS, if configured.
+pub enum Publisher {
+    Disabled,
+    #[cfg(feature = "kafka")]
+    Kafka {
+        producer: rdkafka::producer::FutureProducer,
+        topic: String,
+    },
+    #[cfg(feature = "nats")]
+    Nats {
+        client: async_nats::Client,
+        subject: String,
+    },
+}
+
+impl Publisher {
+    pub async fn new(config: Option<&PublishConfig>) -> Result<Self, anyhow::Error> {
+        match config {
+            None => Ok(Self::Disabled),
+            #[cfg(feature = "kafka")]
+            Some(PublishConfig::Kafka { brokers, topic }) => Ok(Self::Kafka {
+                producer: rdkafka::ClientConfig::new()
+                    .set("bootstrap.servers", brokers)
+                    .create()?,
+                topic: topic.clone(),
+            }),
+            #[cfg(feature = "nats")]
+            Some(PublishConfig::Nats { url, subject }) => Ok(Self::Nats {
+                client: async_nats::connect(url.as_str()).await?,
+                subject: subject.clone(),
+            }),
+            #[allow(unreachable_patterns)]
+            Some(config) => {
+                anyhow::bail!("Publishing to {:?} is not supported by this build", config)
+            }
+        }
+    }
+
+    /// Publish an event, failures are logged but don't fail the operation causing the event.
+    pub async fn publish(&self, event: PipelineEvent) {
+        if let Err(e) = self.try_publish(&event).await {
+            log::warn!("Error publishing event for {}: {:?}", event.key(), e);
+        }
+    }
+
+    async fn try_publish(&self, event: &PipelineEvent) -> Result<(), anyhow::Error> {
+        match self {
+            Self::Disabled => {
+                // only the publishing backends of the enabled features use the event
+                let _ = event;
+                Ok(())
+            }
+            #[cfg(feature = "kafka")]
+            Self::Kafka { producer, topic } => {
+                let payload = serde_json::to_vec(event)?;
+                producer
+                    .send(
+                        rdkafka::producer::FutureRecord::to(topic)
+                            .key(event.key())
+                            .payload(&payload),
+                        std::time::Duration::from_secs(5),
+                    )
+                    .await
+                    .map_err(|(e, _)| e)?;
+                Ok(())
+            }
+            #[cfg(feature = "nats")]
+            Self::Nats { client, subject } => {
+                let payload = serde_json::to_vec(event)?;
+                client.publish(subject.clone(), payload.into()).await?;
+                Ok(())
+            }
+        }
+    }
+}
@@ -0,0 +1,406 @@
+use core::str::FromStr;
+use packageurl::PackageUrl;
+use serde::{Deserialize, Serialize};
+
+/// How package URLs sent by clients are parsed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PurlParsing {
+    /// Reject package URLs which can't be parsed as sent
+    Strict,
+    /// Repair common issues before parsing, reporting the repaired form
+    Lenient,
+}
+
+impl Default for PurlParsing {
+    fn default() -> Self {
+        Self::Strict
+    }
+}
+
+impl PurlParsing {
+    /// The form of a package URL to process, repairing it in lenient mode and normalizing it if it
+    /// can be parsed.
+    ///
+    /// Unless `strict`, qualifiers which aren't significant for matching are dropped, see
+    /// [`significant`].
+    pub fn canonicalize(&self, purl: &str, strict: bool) -> String {
+        let purl = match self {
+            Self::Strict => purl.to_string(),
+            Self::Lenient => repair(purl),
+        };
+        let canonical = if strict {
+            normalize(&purl)
+        } else {
+            significant(&purl)
+        };
+        canonical.unwrap_or(purl)
+    }
+}
+
+/// The canonical form of a package URL, as defined by the purl specification, `None` if it can't
+/// be parsed.
+///
+/// Names are lowercased for ecosystems which are case insensitive, qualifiers are sorted by key,
+/// empty and default qualifiers dropped, and all components consistently percent-encoded, so that
+/// different spellings of the same package map to the same string.
+pub fn normalize(purl: &str) -> Option<String> {
+    render(purl, false)
+}
+
+/// The canonical form of a package URL with only the qualifiers significant for matching, `None`
+/// if it can't be parsed.
+///
+/// Lookups of all providers and the SBOM registry use this form unless a query is `strict`, so a
+/// package is found regardless of where it was downloaded from, while different artifacts of a
+/// version (e.g. the `arch` of an RPM or the `classifier` of a Maven jar) stay distinct.
+pub fn significant(purl: &str) -> Option<String> {
+    render(purl, true)
+}
+
+/// Whether a qualifier distinguishes different artifacts of the same package version.
+///
+/// Ecosystems not listed here only ignore qualifiers about the location or checksum of a package.
+pub fn is_significant_qualifier(ty: &str, key: &str) -> bool {
+    match ty {
+        "maven" => matches!(key, "classifier" | "type"),
+        "rpm" => matches!(key, "arch" | "epoch" | "distro"),
+        "deb" | "apk" | "alpm" => matches!(key, "arch" | "distro"),
+        "oci" => matches!(key, "arch" | "os" | "tag" | "repository_url"),
+        "pypi" => key == "file_name",
+        "conda" => matches!(key, "build" | "channel" | "subdir" | "type"),
+        "npm" | "cargo" | "golang" | "gem" | "nuget" | "composer" => false,
+        _ => !matches!(
+            key,
+            "repository_url" | "download_url" | "vcs_url" | "checksum"
+        ),
+    }
+}
+
+fn render(purl: &str, significant_only: bool) -> Option<String> {
+    let parsed = PackageUrl::from_str(purl).ok()?;
+    let ty = parsed.ty().to_lowercase();
+
+    let mut result = format!("pkg:{}/", ty);
+    if let Some(namespace) = parsed.namespace() {
+        for segment in namespace.split('/').filter(|s| !s.is_empty()) {
+            let segment = match ty.as_str() {
+                "alpm" | "apk" | "bitbucket" | "composer" | "deb" | "github" | "hex" | "npm"
+                | "pypi" => segment.to_lowercase(),
+                _ => segment.to_string(),
+            };
+            result.push_str(&escape(&segment, ""));
+            result.push('/');
+        }
+    }
+    let name = match ty.as_str() {
+        "alpm" | "apk" | "bitbucket" | "composer" | "deb" | "github" | "hex" | "npm" => {
+            parsed.name().to_lowercase()
+        }
+        "pypi" => parsed.name().to_lowercase().replace('_', "-"),
+        _ => parsed.name().to_string(),
+    };
+    result.push_str(&escape(&name, ""));
+
+    if let Some(version) = parsed.version() {
+        result.push('@');
+        result.push_str(&escape(version, "+"));
+    }
+
+    let mut qualifiers: Vec<(String, &str)> = parsed
+        .qualifiers()
+        .iter()
+        .map(|(key, value)| (key.to_lowercase(), value.as_ref()))
+        .filter(|(key, value)| !value.is_empty() && !is_default_qualifier(&ty, key, value))
+        .filter(|(key, _)| !significant_only || is_significant_qualifier(&ty, key))
+        .collect();
+    qualifiers.sort();
+    for (i, (key, value)) in qualifiers.iter().enumerate() {
+        result.push(if i == 0 { '?' } else { '&' });
+        result.push_str(key);
+        result.push('=');
+        result.push_str(&escape(value, ":/+"));
+    }
+
+    if let Some(subpath) = parsed.subpath() {
+        let segments: Vec<String> = subpath
+            .split('/')
+            .filter(|s| !s.is_empty() && *s != "." && *s != "..")
+            .map(|s| escape(s, ""))
+            .collect();
+        if !segments.is_empty() {
+            result.push('#');
+            result.push_str(&segments.join("/"));
+        }
+    }
+
+    Some(result)
+}
+
+/// Qualifiers which are implied when missing, e.g. Maven packages are jars unless stated otherwise.
+fn is_default_qualifier(ty: &str, key: &str, value: &str) -> bool {
+    matches!((ty, key, value), ("maven", "type", "jar"))
+}
+
+/// Percent-encode a decoded component, keeping unreserved characters and those in `keep`.
+fn escape(value: &str, keep: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for c in value.chars() {
+        if c.is_ascii_alphanumeric() || "-._~".contains(c) || keep.contains(c) {
+            encoded.push(c);
+        } else {
+            let mut buf = [0; 4];
+            for b in c.encode_utf8(&mut buf).bytes() {
+                encoded.push_str(&format!("%{:02X}", b));
+            }
+        }
+    }
+    encoded
+}
+
+/// Repair common issues of package URLs: surrounding whitespace, a missing or `pkg://` scheme,
+/// uppercase types and qualifier keys, and unencoded characters in qualifier values.
+fn repair(purl: &str) -> String {
+    let purl = purl.trim();
+    let rest = ["pkg://", "pkg:"]
+        .iter()
+        .find_map(|scheme| {
+            purl.get(..scheme.len())
+                .filter(|p| p.eq_ignore_ascii_case(scheme))
+                .map(|_| &purl[scheme.len()..])
+        })
+        .unwrap_or(purl);
+
+    let (ty, rest) = match rest.split_once('/') {
+        Some(split) => split,
+        None => return format!("pkg:{}", rest),
+    };
+    let (path, qualifiers) = match rest.split_once('?') {
+        Some((path, qualifiers)) => (path, Some(qualifiers)),
+        None => (rest, None),
+    };
+
+    let mut result = format!("pkg:{}/{}", ty.to_lowercase(), path);
+    if let Some(qualifiers) = qualifiers {
+        let (qualifiers, subpath) = match qualifiers.split_once('#') {
+            Some((qualifiers, subpath)) => (qualifiers, Some(subpath)),
+            None => (qualifiers, None),
+        };
+        let qualifiers: Vec<String> = qualifiers
+            .split('&')
+            .filter(|q| !q.is_empty())
+            .map(|q| match q.split_once('=') {
+                Some((key, value)) => format!("{}={}", key.to_lowercase(), encode(value)),
+                None => q.to_lowercase(),
+            })
+            .collect();
+        result.push('?');
+        result.push_str(&qualifiers.join("&"));
+        if let Some(subpath) = subpath {
+            result.push('#');
+            result.push_str(subpath);
+        }
+    }
+    result
+}
+
+/// Percent-encode the characters not allowed in qualifier values, keeping existing escapes.
+fn encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for c in value.chars() {
+        if c.is_ascii_alphanumeric() || "-._~:/%+".contains(c) {
+            encoded.push(c);
+        } else {
+            let mut buf = [0; 4];
+            for b in c.encode_utf8(&mut buf).bytes() {
+                encoded.push_str(&format!("%{:02X}", b));
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spellings_of_a_package_normalize_to_the_same_purl() {
+        for (purl, expected) in [
+            // Maven names are case sensitive, jars are the default type
+            (
+                "pkg:maven/IO.Vertx/Vertx-Web@4.3.7?type=jar",
+                "pkg:maven/IO.Vertx/Vertx-Web@4.3.7",
+            ),
+            (
+                "pkg:maven/io.vertx/vertx-web@4.3.7?repository_url=https://repo.example.com&classifier=sources",
+                "pkg:maven/io.vertx/vertx-web@4.3.7?classifier=sources&repository_url=https://repo.example.com",
+            ),
+            // npm names are lowercase, the @ of scopes is encoded
+            ("pkg:npm/Lodash@4.17.21", "pkg:npm/lodash@4.17.21"),
+            ("pkg:npm/@Angular/Core@12.0.0", "pkg:npm/%40angular/core@12.0.0"),
+            ("pkg:npm/%40angular/core@12.0.0", "pkg:npm/%40angular/core@12.0.0"),
+            // PyPI names are lowercase, with dashes rather than underscores
+            ("pkg:pypi/Django_Rest@3.0", "pkg:pypi/django-rest@3.0"),
+            // RPM names are case sensitive, the namespaces of RPM and Go packages are lowercase
+            (
+                "pkg:rpm/RedHat/openssl@1.1.1k-7.el8_6?epoch=1&arch=x86_64",
+                "pkg:rpm/redhat/openssl@1.1.1k-7.el8_6?arch=x86_64&epoch=1",
+            ),
+            (
+                "pkg:deb/Debian/curl@7.50.3-1?distro=jessie&arch=i386",
+                "pkg:deb/debian/curl@7.50.3-1?arch=i386&distro=jessie",
+            ),
+            (
+                "pkg:golang/github.com/Gorilla/context@v1.1.1",
+                "pkg:golang/github.com/gorilla/context@v1.1.1",
+            ),
+            (
+                "pkg:github/Package-URL/Purl-Spec@244fd47e07d1004",
+                "pkg:github/package-url/purl-spec@244fd47e07d1004",
+            ),
+            ("pkg:cargo/Serde@1.0.0+build.1", "pkg:cargo/Serde@1.0.0+build.1"),
+            // the : of digests is encoded
+            (
+                "pkg:oci/debian@sha256:244fd47e07d10?tag=latest&arch=amd64",
+                "pkg:oci/debian@sha256%3A244fd47e07d10?arch=amd64&tag=latest",
+            ),
+            (
+                "pkg:golang/google.golang.org/genproto@abcdedf#/googleapis/./api/",
+                "pkg:golang/google.golang.org/genproto@abcdedf#googleapis/api",
+            ),
+        ] {
+            assert_eq!(normalize(purl).as_deref(), Some(expected), "{}", purl);
+        }
+    }
+
+    #[test]
+    fn only_significant_qualifiers_are_kept_for_matching() {
+        for (purl, expected) in [
+            (
+                "pkg:maven/io.vertx/vertx-web@4.3.7?repository_url=https://repo.example.com&classifier=sources",
+                "pkg:maven/io.vertx/vertx-web@4.3.7?classifier=sources",
+            ),
+            (
+                "pkg:rpm/redhat/openssl@1.1.1k-7.el8_6?arch=x86_64&repository_id=rhel-8",
+                "pkg:rpm/redhat/openssl@1.1.1k-7.el8_6?arch=x86_64",
+            ),
+            (
+                "pkg:deb/debian/curl@7.50.3-1?arch=i386&distro=jessie&repository_url=https://deb.debian.org",
+                "pkg:deb/debian/curl@7.50.3-1?arch=i386&distro=jessie",
+            ),
+            (
+                "pkg:oci/debian@sha256:244fd47e07d10?repository_url=docker.io/library/debian&arch=amd64",
+                "pkg:oci/debian@sha256%3A244fd47e07d10?arch=amd64&repository_url=docker.io/library/debian",
+            ),
+            (
+                "pkg:pypi/django@1.11.1?file_name=Django-1.11.1.tar.gz&repository_url=https://pypi.org",
+                "pkg:pypi/django@1.11.1?file_name=Django-1.11.1.tar.gz",
+            ),
+            ("pkg:npm/lodash@4.17.21?vcs_url=git://example.com", "pkg:npm/lodash@4.17.21"),
+            ("pkg:golang/github.com/gorilla/context@v1.1.1?goos=linux", "pkg:golang/github.com/gorilla/context@v1.1.1"),
+            (
+                "pkg:generic/openssl@1.1.0g?download_url=https://openssl.org/openssl-1.1.0g.tar.gz&checksum=sha256:de4d50",
+                "pkg:generic/openssl@1.1.0g",
+            ),
+            ("pkg:generic/openssl@1.1.0g?flavor=fips", "pkg:generic/openssl@1.1.0g?flavor=fips"),
+        ] {
+            assert_eq!(significant(purl).as_deref(), Some(expected), "{}", purl);
+        }
+    }
+
+    #[test]
+    fn strict_parsing_processes_purls_as_sent() {
+        let parsing = PurlParsing::Strict;
+        for purl in [" pkg:npm/lodash@4.17.21", "npm/lodash@4.17.21"] {
+            assert_eq!(parsing.canonicalize(purl, false), purl);
+        }
+        assert_eq!(
+            parsing.canonicalize("pkg:npm/Lodash@4.17.21", false),
+            "pkg:npm/lodash@4.17.21"
+        );
+    }
+
+    #[test]
+    fn lenient_parsing_repairs_purls() {
+        let parsing = PurlParsing::Lenient;
+        for purl in [
+            " pkg:npm/lodash@4.17.21\n",
+            "PKG:npm/lodash@4.17.21",
+            "pkg://NPM/lodash@4.17.21",
+            "npm/lodash@4.17.21",
+        ] {
+            assert_eq!(
+                parsing.canonicalize(purl, false),
+                "pkg:npm/lodash@4.17.21",
+                "{:?}",
+                purl
+            );
+        }
+        assert_eq!(
+            parsing.canonicalize(
+                "pkg:rpm/redhat/openssl@1.1.1k?ARCH=x86_64&repository_url=https://example.com/a b",
+                true
+            ),
+            "pkg:rpm/redhat/openssl@1.1.1k?arch=x86_64&repository_url=https://example.com/a%20b"
+        );
+        // purls which still can't be parsed are processed in the repaired form
+        assert_eq!(parsing.canonicalize(" openssl ", false), "pkg:openssl");
+    }
+
+    #[test]
+    fn strict_queries_keep_all_qualifiers() {
+        let purl = "pkg:maven/io.vertx/vertx-web@4.3.7?repository_url=https://repo.example.com";
+        for parsing in [PurlParsing::Strict, PurlParsing::Lenient] {
+            assert_eq!(parsing.canonicalize(purl, true), purl);
+            assert_eq!(
+                parsing.canonicalize(purl, false),
+                "pkg:maven/io.vertx/vertx-web@4.3.7"
+            );
+        }
+    }
+
+    #[test]
+    fn repairing_keeps_the_path_and_encodes_qualifier_values() {
+        assert_eq!(
+            repair("Pkg:RPM/redhat/OpenSSL@1.1.1k?Arch=x86_64&&label=a b%20c#sub/path"),
+            "pkg:rpm/redhat/OpenSSL@1.1.1k?arch=x86_64&label=a%20b%20c#sub/path"
+        );
+        assert_eq!(repair("pkg:npm/lodash"), "pkg:npm/lodash");
+    }
+
+    #[test]
+    fn repairing_fixes_schemes_and_qualifier_keys() {
+        for (purl, expected) in [
+            (
+                "\tpkg://Maven/io.vertx/vertx-web@4.3.7 ",
+                "pkg:maven/io.vertx/vertx-web@4.3.7",
+            ),
+            ("PKG:npm/lodash", "pkg:npm/lodash"),
+            ("npm/lodash", "pkg:npm/lodash"),
+            // without a path, only the scheme is repaired
+            ("openssl", "pkg:openssl"),
+            // qualifiers without a value are kept, escapes are not encoded twice
+            (
+                "pkg:generic/openssl?FIPS&label=a%20b",
+                "pkg:generic/openssl?fips&label=a%20b",
+            ),
+            (
+                "pkg:generic/openssl?label=é",
+                "pkg:generic/openssl?label=%C3%A9",
+            ),
+            // an empty query is kept, dropping it is up to normalizing
+            ("pkg:generic/openssl?", "pkg:generic/openssl?"),
+        ] {
+            assert_eq!(repair(purl), expected, "{:?}", purl);
+        }
+    }
+
+    #[test]
+    fn invalid_purls_have_no_canonical_form() {
+        for purl in ["", "openssl", "pkg:maven", "https://example.com/openssl"] {
+            assert_eq!(normalize(purl), None, "{}", purl);
+            assert_eq!(significant(purl), None, "{}", purl);
+        }
+    }
+}
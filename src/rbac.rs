@@ -0,0 +1,286 @@
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::{error, http::StatusCode, HttpMessage, HttpResponse};
+use futures::future::{ready, Either};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use thiserror::Error;
+
+use crate::auth::Identity;
+use crate::versioning;
+
+/// Requiring roles of authenticated callers, by endpoint.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RbacConfig {
+    /// Authenticated callers may use all endpoints of the public API if disabled
+    pub enabled: bool,
+    /// Roles granted by the claims of ID tokens, in addition to those of the issuers
+    pub rules: Vec<RoleRule>,
+}
+
+/// Grants roles to the users whose ID token has a claim of a value.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RoleRule {
+    /// Only applies to tokens of this issuer, to tokens of all issuers if unset
+    #[serde(default)]
+    pub issuer: Option<String>,
+    pub claim: String,
+    /// The value of the claim, or one of its values if it is a list
+    pub value: String,
+    pub roles: Vec<String>,
+}
+
+impl RoleRule {
+    pub fn matches(&self, issuer: &str, claims: &serde_json::Value) -> bool {
+        if self.issuer.as_deref().map_or(false, |i| i != issuer) {
+            return false;
+        }
+        match claims.get(&self.claim) {
+            Some(serde_json::Value::String(value)) => value == &self.value,
+            Some(serde_json::Value::Array(values)) => values
+                .iter()
+                .any(|v| v.as_str() == Some(self.value.as_str())),
+            Some(serde_json::Value::Bool(value)) => value.to_string() == self.value,
+            _ => false,
+        }
+    }
+}
+
+/// The roles required by endpoints, each role grants the permissions of the lower ones.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    /// Querying packages, vulnerabilities and SBOMs, and managing watches
+    Reader,
+    /// Publishing SBOMs, no endpoint of the public API requires it yet
+    SbomPublisher,
+    /// Purging caches and reindexing SBOMs, on the admin listener
+    Admin,
+}
+
+impl Role {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Reader => "reader",
+            Self::SbomPublisher => "sbom-publisher",
+            Self::Admin => "admin",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "reader" => Some(Self::Reader),
+            "sbom-publisher" => Some(Self::SbomPublisher),
+            "admin" => Some(Self::Admin),
+            _ => None,
+        }
+    }
+
+    /// Whether any of the roles of a caller grants this role.
+    pub fn is_granted(&self, roles: &[String]) -> bool {
+        roles
+            .iter()
+            .filter_map(|r| Self::from_name(r))
+            .any(|r| r >= *self)
+    }
+}
+
+/// The role required for a request to the public API, `None` for requests outside of it.
+pub fn required(path: &str) -> Option<Role> {
+    let path = versioning::unversioned(path);
+    let path = path.strip_prefix(versioning::LEGACY_PREFIX)?;
+    if !path.is_empty() && !path.starts_with('/') {
+        return None;
+    }
+    // analyzing and annotating SBOMs stores nothing, SBOM sources are managed on the admin
+    // listener
+    Some(Role::Reader)
+}
+
+/// Reject requests of authenticated callers without the role of the endpoint, if enabled.
+///
+/// Must run inside the authentication, requests without an identity are passed on.
+pub fn middleware<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+    enabled: bool,
+) -> impl Future<Output = Result<ServiceResponse<B>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+{
+    if enabled {
+        let denied = match (required(req.path()), req.extensions().get::<Identity>()) {
+            (Some(role), Some(identity)) if !role.is_granted(&identity.roles) => Some(role),
+            _ => None,
+        };
+        if let Some(role) = denied {
+            return Either::Right(ready(Err(ApiError::MissingRole {
+                role: role.name().to_string(),
+            }
+            .into())));
+        }
+    }
+    Either::Left(srv.call(req))
+}
+
+#[derive(Debug, Error, Serialize, Deserialize)]
+pub enum ApiError {
+    #[error("The role {role} is required")]
+    MissingRole { role: String },
+}
+
+impl error::ResponseError for ApiError {
+    fn error_response(&self) -> HttpResponse {
+        crate::problem::response(self.status_code(), self, serde_json::json!({}))
+    }
+
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::MissingRole { role: _ } => StatusCode::FORBIDDEN,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test as actix_test, web, App};
+
+    fn roles(roles: &[&str]) -> Vec<String> {
+        roles.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn api_endpoints_require_reader() {
+        for path in [
+            "/api/v1/package",
+            "/api/v1/trusted",
+            "/api/v1/package/dependencies",
+            "/api/v1/package/dependents",
+            "/api/v1/package/versions",
+            "/api/v1/package/sbom",
+            "/api/v1/package/provenance",
+            "/api/v1/package/licenses",
+            "/api/v1/package/license-check",
+            "/api/v1/package/history",
+            "/api/v1/package/path",
+            "/api/v1/package/counterpart",
+            "/api/v1/package/by-coordinates",
+            "/api/v1/package/by-cpe",
+            "/api/v1/vulnerability",
+            "/api/v1/search",
+            "/api/v1/events",
+            "/api/v1/sbom/analyze",
+            "/api/v1/sbom/annotate",
+            "/api/v1/sbom/export",
+            "/api/v1/product",
+            "/api/v1/product/rhel-9.2/vulnerabilities",
+            "/api/v1/product/rhel-9.2/summary",
+            "/api/v1/sbom/summary",
+            "/api/v1/sbom/3b4f0e4f1d0fa2c4fd1c8f9dbf1a7c4e8b0c5a8b9e1b6d7c2e3f4a5b6c7d8e9f",
+            "/api/v1/report/affected",
+            "/api/v1/gate",
+            "/api/v1/image",
+            "/api/v1/watch",
+            "/api/v1/watch/1",
+            "/api/v1",
+            "/api/package",
+            "/api/sbom/analyze",
+            "/api/gate",
+        ] {
+            assert_eq!(required(path), Some(Role::Reader), "{}", path);
+        }
+    }
+
+    #[test]
+    fn other_paths_require_no_role() {
+        for path in [
+            "/",
+            "/health/live",
+            "/openapi.json",
+            "/swagger-ui/",
+            "/apidocs",
+        ] {
+            assert_eq!(required(path), None, "{}", path);
+        }
+    }
+
+    async fn call(roles: Option<&[&str]>, enabled: bool) -> Result<StatusCode, StatusCode> {
+        let identity = roles.map(|roles| Identity {
+            issuer: "https://sso.example.com".to_string(),
+            subject: "user".to_string(),
+            roles: roles.iter().map(ToString::to_string).collect(),
+            tenant: None,
+        });
+        let app = actix_test::init_service(
+            App::new()
+                .wrap_fn(move |req, srv| middleware(req, srv, enabled))
+                .wrap_fn(move |req, srv| {
+                    if let Some(identity) = identity.clone() {
+                        req.extensions_mut().insert(identity);
+                    }
+                    srv.call(req)
+                })
+                .route(
+                    "/api/v1/package",
+                    web::get().to(|| async { HttpResponse::Ok().finish() }),
+                ),
+        )
+        .await;
+        match app
+            .call(
+                actix_test::TestRequest::get()
+                    .uri("/api/v1/package")
+                    .to_request(),
+            )
+            .await
+        {
+            Ok(response) => Ok(response.status()),
+            Err(e) => Err(e.as_response_error().status_code()),
+        }
+    }
+
+    #[actix_web::test]
+    async fn callers_without_the_role_are_forbidden() {
+        assert_eq!(call(Some(&["reader"]), true).await, Ok(StatusCode::OK));
+        assert_eq!(call(Some(&["admin"]), true).await, Ok(StatusCode::OK));
+        assert_eq!(
+            call(Some(&["other"]), true).await,
+            Err(StatusCode::FORBIDDEN)
+        );
+        assert_eq!(call(Some(&[]), true).await, Err(StatusCode::FORBIDDEN));
+        assert_eq!(call(Some(&["other"]), false).await, Ok(StatusCode::OK));
+        // left to the authentication
+        assert_eq!(call(None, true).await, Ok(StatusCode::OK));
+    }
+
+    #[test]
+    fn roles_grant_lower_roles() {
+        assert!(Role::Reader.is_granted(&roles(&["reader"])));
+        assert!(Role::Reader.is_granted(&roles(&["sbom-publisher"])));
+        assert!(Role::SbomPublisher.is_granted(&roles(&["admin"])));
+        assert!(!Role::SbomPublisher.is_granted(&roles(&["reader"])));
+        assert!(!Role::Admin.is_granted(&roles(&["sbom-publisher", "other"])));
+        assert!(!Role::Reader.is_granted(&[]));
+    }
+
+    #[test]
+    fn rules_match_claims() {
+        let rule = RoleRule {
+            issuer: Some("https://sso.example.com".to_string()),
+            claim: "groups".to_string(),
+            value: "publishers".to_string(),
+            roles: roles(&["sbom-publisher"]),
+        };
+        let claims = serde_json::json!({"groups": ["users", "publishers"]});
+        assert!(rule.matches("https://sso.example.com", &claims));
+        assert!(!rule.matches("https://idp.example.com", &claims));
+        assert!(!rule.matches(
+            "https://sso.example.com",
+            &serde_json::json!({"groups": ["users"]})
+        ));
+        assert!(rule.matches(
+            "https://sso.example.com",
+            &serde_json::json!({"groups": "publishers"})
+        ));
+    }
+}
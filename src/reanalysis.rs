@@ -0,0 +1,306 @@
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeSet, HashSet};
+use std::sync::Arc;
+
+use crate::cache;
+use crate::events::Events;
+use crate::package::{TrustedContent, VulnerabilityRef};
+use crate::sbom::SbomRegistry;
+use crate::scheduler::Job;
+use crate::storage::Storage;
+
+pub use trust_api_model::history::*;
+
+/// The storage prefix of the latest analysis of each package.
+const PREFIX: &str = "analysis/";
+/// The storage prefix of the analyses of each package over time.
+const HISTORY_PREFIX: &str = "history/";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReanalysisConfig {
+    /// How often the packages of the SBOM registry are analyzed again, in seconds
+    pub interval_secs: u64,
+    /// How many packages are analyzed at the same time
+    pub concurrency: usize,
+    /// How many changes of each package are kept in its history, the oldest are dropped
+    pub history_limit: usize,
+}
+
+impl Default for ReanalysisConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: 300,
+            concurrency: 4,
+            history_limit: 1000,
+        }
+    }
+}
+
+/// The result of analyzing a package, as stored.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Analysis {
+    pub purl: String,
+    pub analyzed: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trusted: Option<bool>,
+    pub vulnerabilities: Vec<VulnerabilityRef>,
+}
+
+/// The analyses of a package, as stored.
+#[derive(Serialize, Deserialize)]
+struct History {
+    purl: String,
+    analyses: Vec<AnalysisSnapshot>,
+}
+
+impl History {
+    /// Add the snapshot of an analysis, unless the package is still in the state of the latest
+    /// one, dropping the oldest beyond `limit`. Returns whether it was added.
+    fn push(&mut self, snapshot: AnalysisSnapshot, limit: usize) -> bool {
+        if let Some(latest) = self.analyses.last() {
+            if same_state(latest, &snapshot) {
+                return false;
+            }
+        }
+        self.analyses.push(snapshot);
+        let excess = self.analyses.len().saturating_sub(limit);
+        self.analyses.drain(..excess);
+        true
+    }
+}
+
+/// Whether two snapshots found the same trust and vulnerabilities, in any order.
+fn same_state(a: &AnalysisSnapshot, b: &AnalysisSnapshot) -> bool {
+    let cves =
+        |s: &AnalysisSnapshot| -> BTreeSet<String> { s.vulnerabilities.iter().cloned().collect() };
+    a.trusted == b.trusted && cves(a) == cves(b)
+}
+
+/// Periodically analyzes all packages of the SBOM registry again, bypassing the cache.
+///
+/// Fresh results are cached, so lookups of known packages are served from the cache, and stored,
+/// so vulnerabilities found since the previous analysis are published as events, also across
+/// restarts.
+pub struct Reanalysis {
+    trusted: Arc<TrustedContent>,
+    sboms: Arc<SbomRegistry>,
+    events: Arc<Events>,
+    storage: Arc<dyn Storage>,
+    concurrency: usize,
+    history_limit: usize,
+}
+
+fn key(prefix: &str, purl: &str) -> String {
+    format!(
+        "{}{}.json",
+        prefix,
+        hex::encode(Sha256::digest(purl.as_bytes()))
+    )
+}
+
+fn snapshot(analysis: &Analysis) -> AnalysisSnapshot {
+    AnalysisSnapshot {
+        analyzed: analysis.analyzed,
+        trusted: analysis.trusted,
+        vulnerabilities: analysis
+            .vulnerabilities
+            .iter()
+            .map(|v| v.cve.clone())
+            .collect(),
+    }
+}
+
+impl Reanalysis {
+    pub fn new(
+        config: &ReanalysisConfig,
+        trusted: Arc<TrustedContent>,
+        sboms: Arc<SbomRegistry>,
+        events: Arc<Events>,
+        storage: Arc<dyn Storage>,
+    ) -> Self {
+        Self {
+            trusted,
+            sboms,
+            events,
+            storage,
+            concurrency: config.concurrency.max(1),
+            history_limit: config.history_limit.max(1),
+        }
+    }
+
+    /// The latest stored analysis of a package.
+    pub async fn latest(&self, purl: &str) -> Result<Option<Analysis>, anyhow::Error> {
+        match self.storage.get(&key(PREFIX, purl)).await? {
+            Some(data) => Ok(Some(serde_json::from_slice(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// The stored analyses of a package, `None` if it was never analyzed.
+    pub async fn history(&self, purl: &str) -> Result<Option<PackageHistory>, anyhow::Error> {
+        match self.storage.get(&key(HISTORY_PREFIX, purl)).await? {
+            Some(data) => {
+                let history: History = serde_json::from_slice(&data)?;
+                Ok(Some(PackageHistory::new(history.purl, history.analyses)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Add an analysis to the history of its package, the history is only written if the
+    /// package changed since its previous analysis.
+    async fn record(&self, analysis: &Analysis) -> Result<(), anyhow::Error> {
+        let key = key(HISTORY_PREFIX, &analysis.purl);
+        let mut history = match self.storage.get(&key).await? {
+            Some(data) => serde_json::from_slice(&data)?,
+            None => History {
+                purl: analysis.purl.clone(),
+                analyses: Vec::new(),
+            },
+        };
+        if history.push(snapshot(analysis), self.history_limit) {
+            self.storage
+                .put(&key, serde_json::to_vec(&history)?)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Analyze a package again, publishing the vulnerabilities not found by its previous
+    /// analysis.
+    ///
+    /// The first analysis of a package only records its current state, without publishing events.
+    async fn analyze(&self, purl: String) -> Result<(), anyhow::Error> {
+        let package = cache::bypassing(self.trusted.get_trusted(&purl)).await?;
+        if !package.degraded.is_empty() {
+            // keep the previous analysis, to not report vulnerabilities of recovering providers
+            log::debug!(
+                "Skipping degraded analysis of {}, missing {:?}",
+                purl,
+                package.degraded
+            );
+            return Ok(());
+        }
+
+        let previous = self.latest(&purl).await?;
+        let analysis = Analysis {
+            purl: purl.clone(),
+            analyzed: Utc::now(),
+            trusted: package.trusted,
+            vulnerabilities: package.vulnerabilities,
+        };
+        let changed = match &previous {
+            Some(previous) => !same_state(&snapshot(previous), &snapshot(&analysis)),
+            None => true,
+        };
+        if changed {
+            self.storage
+                .put(&key(PREFIX, &purl), serde_json::to_vec(&analysis)?)
+                .await?;
+        }
+        self.record(&analysis).await?;
+
+        if let Some(previous) = previous {
+            let seen: HashSet<&str> = previous
+                .vulnerabilities
+                .iter()
+                .map(|v| v.cve.as_str())
+                .collect();
+            let found: Vec<VulnerabilityRef> = analysis
+                .vulnerabilities
+                .into_iter()
+                .filter(|v| !seen.contains(v.cve.as_str()))
+                .collect();
+            if !found.is_empty() {
+                self.events.found(purl, found).await;
+            }
+        }
+        Ok(())
+    }
+
+    /// Analyze all packages of the SBOM registry once, returning how many were analyzed.
+    pub async fn run_once(&self) -> usize {
+        futures::stream::iter(self.sboms.purls())
+            .map(|purl| async move {
+                match self.analyze(purl.clone()).await {
+                    Ok(()) => 1,
+                    Err(e) => {
+                        log::debug!("Unable to analyze {}: {:?}", purl, e);
+                        0
+                    }
+                }
+            })
+            .buffer_unordered(self.concurrency)
+            .fold(0, |analyzed, n| async move { analyzed + n })
+            .await
+    }
+}
+
+impl Job for Reanalysis {
+    fn name(&self) -> &'static str {
+        "reanalysis"
+    }
+
+    fn run(&self) -> BoxFuture<'_, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let analyzed = self.run_once().await;
+            log::info!("Analyzed {} known packages again", analyzed);
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(day: u32, trusted: bool, vulnerabilities: &[&str]) -> AnalysisSnapshot {
+        AnalysisSnapshot {
+            analyzed: Utc.with_ymd_and_hms(2023, 3, day, 8, 0, 0).unwrap(),
+            trusted: Some(trusted),
+            vulnerabilities: vulnerabilities.iter().map(|v| v.to_string()).collect(),
+        }
+    }
+
+    fn history() -> History {
+        History {
+            purl: "pkg:rpm/redhat/openssl@1.1.1k-7.el8_6".to_string(),
+            analyses: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn unchanged_analyses_are_not_added() {
+        let mut history = history();
+        assert!(history.push(at(1, true, &["cve-1", "cve-2"]), 10));
+        assert!(!history.push(at(2, true, &["cve-2", "cve-1"]), 10));
+        assert!(history.push(at(3, true, &["cve-2"]), 10));
+        assert!(history.push(at(4, false, &["cve-2"]), 10));
+        assert_eq!(
+            history.analyses,
+            vec![
+                at(1, true, &["cve-1", "cve-2"]),
+                at(3, true, &["cve-2"]),
+                at(4, false, &["cve-2"])
+            ]
+        );
+    }
+
+    #[test]
+    fn the_oldest_analyses_are_dropped() {
+        let mut history = history();
+        for day in 1..=5 {
+            assert!(history.push(at(day, day % 2 == 0, &[]), 3));
+        }
+        assert_eq!(
+            history.analyses,
+            vec![at(3, false, &[]), at(4, true, &[]), at(5, false, &[])]
+        );
+    }
+}
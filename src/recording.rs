@@ -0,0 +1,436 @@
+//! Recorded exchanges with Guac, Snyk and the security data API, replayed in tests instead of
+//! calling the services.
+//!
+//! Recording proxies the calls to the real services and writes the exchanges to a fixture file,
+//! replaying answers them from that file only. A call without a recorded exchange is answered with
+//! an error and fails the test once the harness is dropped, so tests can't pass against answers
+//! nobody recorded. Tests looking up more packages than are worth recording use the mocks of the
+//! dev mode instead, explicitly with [`Mode::Mock`].
+
+use actix_web::{http::StatusCode, web, App, HttpRequest, HttpResponse, HttpServer};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::config::Config;
+use crate::devmode::{self, Fixtures};
+
+/// Set to the URL of a Guac instance to record exchanges instead of replaying them.
+pub const RECORD_GUAC_ENV: &str = "TRUST_API_RECORD_GUAC";
+/// The REST API of Snyk to record from, `https://api.snyk.io/rest` by default.
+pub const RECORD_SNYK_ENV: &str = "TRUST_API_RECORD_SNYK";
+/// The security data API to record from, the one of Red Hat by default.
+pub const RECORD_SECURITY_DATA_ENV: &str = "TRUST_API_RECORD_SECURITY_DATA";
+
+/// The services exchanges are recorded with, by the path they are served below.
+const SERVICES: &[(&str, &str)] = &[
+    ("guac", "/guac/query"),
+    ("snyk", "/snyk"),
+    ("securitydata", "/securitydata"),
+];
+
+/// A request to Guac, Snyk or the security data API and the response to it.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Exchange {
+    /// `guac`, `snyk` or `securitydata`
+    pub service: String,
+    pub method: String,
+    /// Below the URL of the service, the Snyk organization replaced by `{org}`
+    pub path: String,
+    /// The JSON body of the request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request: Option<Value>,
+    pub status: u16,
+    pub response: Value,
+}
+
+impl Exchange {
+    /// What identifies the request: Guac queries by their operation and variables, so rewording
+    /// a query doesn't require recording it again, other requests by their whole body.
+    fn request_key(&self) -> Option<Value> {
+        let request = self.request.as_ref()?;
+        match (self.service.as_str(), devmode::operation(request)) {
+            ("guac", Some(operation)) => Some(json!({
+                "operation": operation,
+                "variables": request["variables"],
+            })),
+            _ => Some(request.clone()),
+        }
+    }
+
+    fn is_same_request(&self, other: &Exchange) -> bool {
+        (&self.service, &self.method, &self.path) == (&other.service, &other.method, &other.path)
+            && self.request_key() == other.request_key()
+    }
+
+    /// The request, as reported when it wasn't recorded.
+    fn describe(&self) -> String {
+        match self.request_key() {
+            Some(key) => format!("{} {} {} {}", self.service, self.method, self.path, key),
+            None => format!("{} {} {}", self.service, self.method, self.path),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Recordings {
+    pub exchanges: Vec<Exchange>,
+}
+
+impl Recordings {
+    /// Load recorded exchanges, none if the file doesn't exist yet.
+    pub fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        match std::fs::read(path) {
+            Ok(data) => Ok(serde_json::from_slice(&data)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), anyhow::Error> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+
+    fn find(&self, request: &Exchange) -> Option<&Exchange> {
+        self.exchanges.iter().find(|e| e.is_same_request(request))
+    }
+
+    /// Record an exchange, replacing an earlier one of the same request.
+    fn record(&mut self, exchange: Exchange) {
+        self.exchanges.retain(|e| !e.is_same_request(&exchange));
+        self.exchanges.push(exchange);
+    }
+}
+
+/// Whether exchanges are recorded from the real services, replayed, or answered by mocks.
+pub enum Mode {
+    /// Answer calls with the recorded exchanges only
+    Replay,
+    /// Answer calls with the mocks of the dev mode, nothing is recorded or replayed
+    Mock { fixtures: Fixtures },
+    Record {
+        guac: String,
+        snyk: String,
+        security_data: String,
+    },
+}
+
+impl Mode {
+    /// Record if [`RECORD_GUAC_ENV`] is set, replay otherwise.
+    pub fn from_env() -> Self {
+        match std::env::var(RECORD_GUAC_ENV) {
+            Ok(guac) => Self::Record {
+                guac,
+                snyk: std::env::var(RECORD_SNYK_ENV)
+                    .unwrap_or_else(|_| "https://api.snyk.io/rest".to_string()),
+                security_data: std::env::var(RECORD_SECURITY_DATA_ENV).unwrap_or_else(|_| {
+                    "https://access.redhat.com/hydra/rest/securitydata".to_string()
+                }),
+            },
+            Err(_) => Self::Replay,
+        }
+    }
+
+    /// Answer calls with the mocks of the dev mode and its built-in fixtures.
+    pub fn mock() -> Result<Self, anyhow::Error> {
+        Ok(Self::Mock {
+            fixtures: Fixtures::builtin()?,
+        })
+    }
+}
+
+struct State {
+    mode: Mode,
+    file: PathBuf,
+    recordings: Mutex<Recordings>,
+    /// The calls replaying found no recorded exchange for
+    misses: Mutex<Vec<String>>,
+    client: reqwest::Client,
+}
+
+/// The recording or replaying server, on a local port.
+///
+/// Dropping it fails the test if any call was not replayed, keep it alive until the test is done.
+pub struct Harness {
+    pub url: String,
+    state: web::Data<State>,
+}
+
+impl Harness {
+    /// Serve the exchanges of a fixture file.
+    pub async fn start(mode: Mode, file: &Path) -> Result<Self, anyhow::Error> {
+        let state = web::Data::new(State {
+            mode,
+            file: file.to_path_buf(),
+            recordings: Mutex::new(Recordings::load(file)?),
+            misses: Mutex::new(Vec::new()),
+            client: reqwest::Client::new(),
+        });
+        let served = state.clone();
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(served.clone())
+                .app_data(web::PayloadConfig::new(16 * 1024 * 1024))
+                .default_service(web::to(exchange))
+        })
+        .workers(1)
+        .disable_signals()
+        .bind(("127.0.0.1", 0))?;
+        let url = format!("http://{}", server.addrs()[0]);
+        tokio::spawn(server.run());
+        Ok(Self { url, state })
+    }
+
+    /// Point the configuration at the harness. When recording, Snyk is called with the
+    /// organization and token of the configuration, or of `TRUST_API_SNYK__ORG` and
+    /// `TRUST_API_SNYK__TOKEN`.
+    pub fn apply(&self, config: &mut Config) {
+        let snyk = (config.snyk.org.clone(), config.snyk.token.clone());
+        devmode::apply(config, &self.url);
+        if matches!(self.state.mode, Mode::Record { .. }) {
+            config.snyk.org = snyk.0.or_else(|| std::env::var("TRUST_API_SNYK__ORG").ok());
+            config.snyk.token = snyk
+                .1
+                .or_else(|| std::env::var("TRUST_API_SNYK__TOKEN").ok());
+        }
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        let misses = self.state.misses.lock().unwrap();
+        if !misses.is_empty() && !std::thread::panicking() {
+            panic!(
+                "No recorded exchanges to replay for:\n{}\nRecord them with {} set",
+                misses.join("\n"),
+                RECORD_GUAC_ENV
+            );
+        }
+    }
+}
+
+async fn exchange(state: web::Data<State>, req: HttpRequest, body: web::Bytes) -> HttpResponse {
+    let full = req
+        .uri()
+        .path_and_query()
+        .map(|p| p.as_str())
+        .unwrap_or_default();
+    let (service, path) = match SERVICES
+        .iter()
+        .find_map(|(service, prefix)| Some((*service, full.strip_prefix(prefix)?)))
+    {
+        Some(found) => found,
+        None => return HttpResponse::NotFound().finish(),
+    };
+    let mut exchange = Exchange {
+        service: service.to_string(),
+        method: req.method().to_string(),
+        path: without_org(service, path),
+        request: serde_json::from_slice(&body).ok(),
+        status: 0,
+        response: Value::Null,
+    };
+
+    let (guac, snyk, security_data) = match &state.mode {
+        Mode::Replay => {
+            if let Some(recorded) = state.recordings.lock().unwrap().find(&exchange) {
+                return respond(recorded.status, &recorded.response);
+            }
+            let missed = exchange.describe();
+            log::warn!("No recorded exchange to replay for {}", missed);
+            state.misses.lock().unwrap().push(missed.clone());
+            return match service {
+                // Guac reports failed queries along with the data
+                "guac" => respond(
+                    200,
+                    &json!({
+                        "data": null,
+                        "errors": [{ "message": format!("No recorded exchange for {}", missed) }],
+                    }),
+                ),
+                _ => HttpResponse::NotFound().finish(),
+            };
+        }
+        Mode::Mock { fixtures } => {
+            return match service {
+                "guac" => {
+                    let request = exchange.request.unwrap_or_default();
+                    respond(200, &devmode::guac_response(fixtures, &request))
+                }
+                "snyk" => match devmode::snyk_purl(path) {
+                    Some(purl) => respond(200, &devmode::snyk_response(fixtures, &purl)),
+                    None => HttpResponse::NotFound().finish(),
+                },
+                _ => match devmode::security_data_response(fixtures, path) {
+                    Some(details) => respond(200, &details),
+                    None => HttpResponse::NotFound().finish(),
+                },
+            };
+        }
+        Mode::Record {
+            guac,
+            snyk,
+            security_data,
+        } => (guac, snyk, security_data),
+    };
+
+    let url = match service {
+        "guac" => format!("{}{}", guac, path),
+        "snyk" => format!("{}{}", snyk.trim_end_matches('/'), path),
+        _ => format!("{}{}", security_data.trim_end_matches('/'), path),
+    };
+    let method = match reqwest::Method::from_bytes(req.method().as_str().as_bytes()) {
+        Ok(method) => method,
+        Err(_) => return HttpResponse::MethodNotAllowed().finish(),
+    };
+    let mut upstream = state.client.request(method, url);
+    if let Some(authorization) = req.headers().get("authorization") {
+        upstream = upstream.header("authorization", authorization.as_bytes());
+    }
+    if !body.is_empty() {
+        upstream = upstream
+            .header("content-type", "application/json")
+            .body(body.to_vec());
+    }
+    let response = match upstream.send().await {
+        Ok(response) => response,
+        Err(e) => {
+            log::warn!("Error recording {} {}: {}", exchange.method, full, e);
+            return HttpResponse::BadGateway().finish();
+        }
+    };
+    exchange.status = response.status().as_u16();
+    exchange.response = response.json().await.unwrap_or_default();
+
+    let answer = respond(exchange.status, &exchange.response);
+    let mut recordings = state.recordings.lock().unwrap();
+    recordings.record(exchange);
+    if let Err(e) = recordings.save(&state.file) {
+        log::warn!("Error saving recordings to {}: {}", state.file.display(), e);
+    }
+    answer
+}
+
+/// The path of a call, with the Snyk organization replaced, so recordings don't depend on it.
+fn without_org(service: &str, path: &str) -> String {
+    match (service, path.strip_prefix("/orgs/")) {
+        ("snyk", Some(rest)) => match rest.split_once('/') {
+            Some((_, rest)) => format!("/orgs/{{org}}/{}", rest),
+            None => path.to_string(),
+        },
+        _ => path.to_string(),
+    }
+}
+
+fn respond(status: u16, body: &Value) -> HttpResponse {
+    HttpResponse::build(StatusCode::from_u16(status).unwrap_or(StatusCode::BAD_GATEWAY)).json(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exchange(path: &str, request: Option<Value>) -> Exchange {
+        Exchange {
+            service: "guac".to_string(),
+            method: "POST".to_string(),
+            path: path.to_string(),
+            request,
+            status: 200,
+            response: json!({ "data": {} }),
+        }
+    }
+
+    #[test]
+    fn snyk_paths_are_recorded_without_the_organization() {
+        assert_eq!(
+            without_org(
+                "snyk",
+                "/orgs/63884128/packages/pkg%3Amaven%2Fio.vertx%2Fvertx-web%404.3.7/issues?version=2023-02-15"
+            ),
+            "/orgs/{org}/packages/pkg%3Amaven%2Fio.vertx%2Fvertx-web%404.3.7/issues?version=2023-02-15"
+        );
+        assert_eq!(without_org("guac", "/orgs/1/x"), "/orgs/1/x");
+    }
+
+    #[test]
+    fn exchanges_match_by_request() {
+        let mut recordings = Recordings::default();
+        recordings.record(exchange("", Some(json!({ "query": "{ a }" }))));
+        recordings.record(exchange("", Some(json!({ "query": "{ b }" }))));
+        // recording a request again replaces it
+        recordings.record(exchange("", Some(json!({ "query": "{ a }" }))));
+        assert_eq!(recordings.exchanges.len(), 2);
+
+        assert!(recordings
+            .find(&exchange("", Some(json!({ "query": "{ b }" }))))
+            .is_some());
+        assert!(recordings
+            .find(&exchange("", Some(json!({ "query": "{ c }" }))))
+            .is_none());
+        assert!(recordings.find(&exchange("/other", None)).is_none());
+    }
+
+    #[test]
+    fn guac_queries_match_by_operation_and_variables() {
+        let query = |document: &str, version: &str| {
+            json!({
+                "query": document,
+                "variables": { "package": { "type": "maven", "version": version } },
+            })
+        };
+        let mut recordings = Recordings::default();
+        recordings.record(exchange(
+            "",
+            Some(query("query CertifyVuln($package: PkgSpec!) { a }", "1")),
+        ));
+
+        let reworded = query("\nquery CertifyVuln($package: PkgSpec!) {\n  a\n}", "1");
+        assert!(recordings.find(&exchange("", Some(reworded))).is_some());
+        let other_version = query("query CertifyVuln($package: PkgSpec!) { a }", "2");
+        assert!(recordings
+            .find(&exchange("", Some(other_version)))
+            .is_none());
+        let other_operation = query("query HasSLSA($package: PkgSpec!) { a }", "1");
+        assert!(recordings
+            .find(&exchange("", Some(other_operation)))
+            .is_none());
+    }
+
+    #[actix_web::test]
+    async fn calls_without_a_recorded_exchange_fail() {
+        let file = std::env::temp_dir().join(format!("trust-api-{}.json", uuid::Uuid::new_v4()));
+        let harness = Harness::start(Mode::Replay, &file).await.unwrap();
+        let client = reqwest::Client::new();
+
+        let response: Value = client
+            .post(format!("{}/guac/query", harness.url))
+            .json(&json!({ "query": "query Packages($name: PkgSpec!) { a }", "variables": {} }))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert!(response["errors"][0]["message"]
+            .as_str()
+            .unwrap()
+            .contains("Packages"));
+        let response = client
+            .get(format!("{}/snyk/orgs/1/packages/a/issues", harness.url))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+        assert_eq!(harness.state.misses.lock().unwrap().len(), 2);
+        let dropped = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| drop(harness)));
+        assert!(dropped.is_err());
+    }
+}
@@ -0,0 +1,162 @@
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+use crate::sbom::{SbomRegistry, SbomSourceKind};
+
+/// How often the watched directories are synced with the registered sources.
+const SYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Reloading SBOMs of directory sources when their files change.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReloadConfig {
+    pub enabled: bool,
+    /// How long to wait for more changes before reloading, in milliseconds
+    pub debounce_ms: u64,
+}
+
+impl Default for ReloadConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            debounce_ms: 500,
+        }
+    }
+}
+
+/// Watches the directories of the registered directory sources, and refreshes the SBOMs of
+/// files added, changed or removed while running.
+pub struct Reloader {
+    sboms: Arc<SbomRegistry>,
+    debounce: Duration,
+}
+
+impl Reloader {
+    pub fn new(config: &ReloadConfig, sboms: Arc<SbomRegistry>) -> Self {
+        Self {
+            sboms,
+            debounce: Duration::from_millis(config.debounce_ms),
+        }
+    }
+
+    /// Run the watch loop, failing to watch is logged and doesn't stop the server.
+    pub async fn run(self: Arc<Self>) {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut watcher = match notify::recommended_watcher(move |event| {
+            // the receiver only goes away with the loop
+            let _ = tx.send(event);
+        }) {
+            Ok(watcher) => watcher,
+            Err(e) => {
+                log::warn!("Unable to watch SBOM directories: {:?}", e);
+                return;
+            }
+        };
+
+        let mut watched = BTreeMap::new();
+        let mut changed = BTreeSet::new();
+        let mut rescan = false;
+        let mut interval = tokio::time::interval(SYNC_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    self.sync(&mut watcher, &mut watched);
+                }
+                event = rx.recv() => match event {
+                    Some(Ok(event)) => collect(event, &mut changed, &mut rescan),
+                    Some(Err(e)) => log::warn!("Error watching SBOM directories: {:?}", e),
+                    None => return,
+                },
+            }
+            if changed.is_empty() && !rescan {
+                continue;
+            }
+
+            // editors and copies touch files several times, wait until they are done
+            while let Ok(Some(event)) = tokio::time::timeout(self.debounce, rx.recv()).await {
+                match event {
+                    Ok(event) => collect(event, &mut changed, &mut rescan),
+                    Err(e) => log::warn!("Error watching SBOM directories: {:?}", e),
+                }
+            }
+            if std::mem::take(&mut rescan) {
+                // events were dropped, the changed files are unknown
+                changed.clear();
+                for id in watched.values() {
+                    log::info!("Lost track of changes to SBOM source {}, reindexing it", id);
+                    self.sboms.reindex(Some(id)).await;
+                }
+            } else {
+                self.refresh(&watched, std::mem::take(&mut changed)).await;
+            }
+        }
+    }
+
+    /// Watch the directories of sources added since the last sync, and stop watching the ones
+    /// of removed sources.
+    fn sync(&self, watcher: &mut RecommendedWatcher, watched: &mut BTreeMap<PathBuf, String>) {
+        let sources: BTreeMap<PathBuf, String> = self
+            .sboms
+            .sources()
+            .into_iter()
+            .filter_map(|source| match source.kind {
+                SbomSourceKind::Directory { path } => Some((path, source.id)),
+                SbomSourceKind::Storage { .. } => None,
+            })
+            .collect();
+
+        watched.retain(|path, id| {
+            if sources.get(path) == Some(id) {
+                return true;
+            }
+            if let Err(e) = watcher.unwatch(path) {
+                log::debug!("Error unwatching {}: {:?}", path.display(), e);
+            }
+            false
+        });
+        for (path, id) in sources {
+            if watched.contains_key(&path) {
+                continue;
+            }
+            match watcher.watch(&path, RecursiveMode::NonRecursive) {
+                Ok(()) => {
+                    log::info!("Watching {} for SBOMs of source {}", path.display(), id);
+                    watched.insert(path, id);
+                }
+                // retried on the next sync, the directory may not exist yet
+                Err(e) => log::debug!("Unable to watch {}: {:?}", path.display(), e),
+            }
+        }
+    }
+
+    /// Refresh the changed files of each source.
+    async fn refresh(&self, watched: &BTreeMap<PathBuf, String>, changed: BTreeSet<PathBuf>) {
+        let mut files: BTreeMap<&str, Vec<PathBuf>> = BTreeMap::new();
+        for path in changed {
+            let id = path.parent().and_then(|parent| watched.get(parent));
+            if let Some(id) = id {
+                files.entry(id.as_str()).or_default().push(path);
+            }
+        }
+        for (id, paths) in files {
+            self.sboms.refresh_files(id, &paths).await;
+        }
+    }
+}
+
+/// Collect the files of an event, or whether to scan all directories again if events were dropped.
+fn collect(event: Event, changed: &mut BTreeSet<PathBuf>, rescan: &mut bool) {
+    if event.need_rescan() {
+        *rescan = true;
+        return;
+    }
+    if event.kind.is_access() {
+        return;
+    }
+    changed.extend(event.paths);
+}
@@ -0,0 +1,349 @@
+use chrono::{DateTime, Duration as Age, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use crate::package::{Package, TrustedContent};
+use crate::sbom::SbomRegistry;
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReplicationConfig {
+    /// Record package evaluations for followers, served as deltas by the admin listener
+    pub primary: bool,
+    /// Sync from a primary, to serve read traffic without access to the providers
+    pub follow: Option<FollowConfig>,
+}
+
+impl ReplicationConfig {
+    pub fn redacted(&self, redacted: &str) -> Self {
+        Self {
+            follow: self.follow.as_ref().map(|follow| FollowConfig {
+                token: follow.token.as_ref().map(|_| redacted.to_string()),
+                ..follow.clone()
+            }),
+            ..self.clone()
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FollowConfig {
+    /// The admin listener of the primary, e.g. `http://primary:8081`
+    pub url: String,
+    /// The admin token of the primary
+    #[serde(default)]
+    pub token: Option<String>,
+    /// How often deltas are fetched, in seconds
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u64,
+    /// Only replicate what the callers of this tenant see, the evaluations made for them and the
+    /// SBOMs of the tenant and the global namespace. All of them are replicated by default
+    #[serde(default)]
+    pub tenant: Option<String>,
+}
+
+fn default_interval_secs() -> u64 {
+    30
+}
+
+/// Whom a package was evaluated for, by the tenant of the task evaluating it, see
+/// [`crate::tenant::current`]. Evaluations depend on the SBOMs the task sees.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Scope {
+    /// Outside of a tenant scope, seeing all SBOMs
+    #[default]
+    Unscoped,
+    /// For callers of no tenant, seeing the SBOMs of the global namespace
+    Global,
+    /// For callers of a tenant, seeing its SBOMs and those of the global namespace
+    Tenant(String),
+}
+
+impl Scope {
+    fn is_unscoped(&self) -> bool {
+        *self == Scope::Unscoped
+    }
+
+    /// Whether the callers of a tenant may see an evaluation of this scope, all evaluations are
+    /// visible without a tenant.
+    fn is_visible_to(&self, tenant: Option<&str>) -> bool {
+        match (tenant, self) {
+            (None, _) | (Some(_), Scope::Global) => true,
+            (Some(tenant), Scope::Tenant(scope)) => tenant == scope,
+            (Some(_), Scope::Unscoped) => false,
+        }
+    }
+}
+
+impl From<Option<Option<String>>> for Scope {
+    fn from(tenant: Option<Option<String>>) -> Self {
+        match tenant {
+            None => Scope::Unscoped,
+            Some(None) => Scope::Global,
+            Some(Some(tenant)) => Scope::Tenant(tenant),
+        }
+    }
+}
+
+impl From<Scope> for Option<Option<String>> {
+    fn from(scope: Scope) -> Self {
+        match scope {
+            Scope::Unscoped => None,
+            Scope::Global => Some(None),
+            Scope::Tenant(tenant) => Some(Some(tenant)),
+        }
+    }
+}
+
+/// A package evaluation, as cached by the primary.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ReplicatedPackage {
+    pub purl: String,
+    #[serde(default, skip_serializing_if = "Scope::is_unscoped")]
+    pub scope: Scope,
+    pub package: Package,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ReplicatedSbom {
+    pub purl: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    pub document: serde_json::Value,
+}
+
+/// The changes of a primary since a previous delta.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Delta {
+    /// Pass as `since` to fetch the next delta
+    pub until: DateTime<Utc>,
+    pub packages: Vec<ReplicatedPackage>,
+    /// SBOMs indexed since the previous delta
+    pub sboms: Vec<ReplicatedSbom>,
+    /// The purls of all SBOMs of the primary, replicated SBOMs missing from it were removed
+    pub sbom_purls: Vec<String>,
+}
+
+/// The package evaluations of a primary, kept as long as they are cached.
+///
+/// Evaluations are appended in the order they are recorded, so the expired ones are dropped
+/// from the front.
+pub struct Journal {
+    ttl: Age,
+    entries: RwLock<VecDeque<Recorded>>,
+}
+
+struct Recorded {
+    recorded: DateTime<Utc>,
+    scope: Scope,
+    purl: String,
+    package: Package,
+}
+
+impl Journal {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl: Age::from_std(ttl).unwrap_or_else(|_| Age::days(1)),
+            entries: RwLock::new(VecDeque::new()),
+        }
+    }
+
+    /// Record the evaluation of a package, made for the callers of `scope`.
+    pub fn record(&self, scope: Scope, purl: &str, package: &Package) {
+        let now = Utc::now();
+        let cutoff = now - self.ttl;
+        let mut entries = self.entries.write().unwrap();
+        while entries.front().map_or(false, |e| e.recorded < cutoff) {
+            entries.pop_front();
+        }
+        entries.push_back(Recorded {
+            recorded: now,
+            scope,
+            purl: purl.to_string(),
+            package: package.clone(),
+        });
+    }
+
+    /// The evaluations and SBOMs changed since a previous delta, or all of them, as far as the
+    /// callers of `tenant` see them, if given.
+    ///
+    /// Only the latest evaluation of a package is included. Deltas overlap at their boundaries,
+    /// applying an entry twice is harmless.
+    pub fn delta(
+        &self,
+        since: Option<DateTime<Utc>>,
+        sboms: &SbomRegistry,
+        tenant: Option<&str>,
+    ) -> Delta {
+        let until = Utc::now();
+        let cutoff = until - self.ttl;
+        let mut packages: Vec<ReplicatedPackage> = {
+            let entries = self.entries.read().unwrap();
+            let mut seen = HashSet::new();
+            entries
+                .iter()
+                .rev()
+                .take_while(|e| {
+                    since.map_or(true, |since| e.recorded >= since) && e.recorded >= cutoff
+                })
+                .filter(|e| e.scope.is_visible_to(tenant))
+                .filter(|e| seen.insert((&e.scope, &e.purl)))
+                .map(|e| ReplicatedPackage {
+                    purl: e.purl.clone(),
+                    scope: e.scope.clone(),
+                    package: e.package.clone(),
+                })
+                .collect()
+        };
+        packages.reverse();
+
+        let visible = |sbom_tenant: Option<&str>| match (tenant, sbom_tenant) {
+            (None, _) | (_, None) => true,
+            (Some(tenant), Some(sbom_tenant)) => tenant == sbom_tenant,
+        };
+        let sbom_purls = match tenant {
+            None => sboms.purls(),
+            Some(tenant) => crate::tenant::sync_scoped(Some(tenant.to_string()), || sboms.purls()),
+        };
+        Delta {
+            until,
+            packages,
+            sboms: sboms
+                .indexed_since(since)
+                .into_iter()
+                .filter(|(_, sbom_tenant, _)| visible(sbom_tenant.as_deref()))
+                .map(|(purl, tenant, document)| ReplicatedSbom {
+                    purl,
+                    tenant,
+                    document,
+                })
+                .collect(),
+            sbom_purls,
+        }
+    }
+}
+
+/// Syncs the package evaluations and SBOMs of a primary into the local cache and SBOM registry.
+pub struct Follower {
+    url: String,
+    token: Option<String>,
+    tenant: Option<String>,
+    client: reqwest::Client,
+    trusted: Arc<TrustedContent>,
+    sboms: Arc<SbomRegistry>,
+}
+
+impl Follower {
+    pub fn new(
+        config: &FollowConfig,
+        trusted: Arc<TrustedContent>,
+        sboms: Arc<SbomRegistry>,
+    ) -> Self {
+        Self {
+            url: config.url.trim_end_matches('/').to_string(),
+            token: config.token.clone(),
+            tenant: config.tenant.clone(),
+            client: reqwest::Client::new(),
+            trusted,
+            sboms,
+        }
+    }
+
+    async fn sync(&self, since: Option<DateTime<Utc>>) -> Result<Delta, anyhow::Error> {
+        let mut request = self
+            .client
+            .get(format!("{}/api/admin/replication/delta", self.url));
+        if let Some(since) = since {
+            request = request.query(&[("since", since.to_rfc3339())]);
+        }
+        if let Some(tenant) = &self.tenant {
+            request = request.query(&[("tenant", tenant)]);
+        }
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+        let delta: Delta = request.send().await?.error_for_status()?.json().await?;
+
+        for replicated in delta.packages.iter() {
+            self.trusted
+                .replicate(&replicated.scope, &replicated.purl, &replicated.package)
+                .await;
+        }
+        self.sboms.replicate(
+            delta
+                .sboms
+                .iter()
+                .map(|s| (s.purl.clone(), s.tenant.clone(), s.document.clone()))
+                .collect(),
+            &delta.sbom_purls,
+        );
+        Ok(delta)
+    }
+
+    /// Run the background sync loop, starting with a full sync.
+    pub async fn run(self: Arc<Self>, interval: Duration) {
+        let mut since = None;
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+            match self.sync(since).await {
+                Ok(delta) => {
+                    log::debug!(
+                        "Replicated {} packages and {} SBOMs from {}",
+                        delta.packages.len(),
+                        delta.sboms.len(),
+                        self.url
+                    );
+                    since = Some(delta.until);
+                }
+                Err(e) => log::warn!("Error syncing from primary {}: {:?}", self.url, e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn package(purl: &str) -> Package {
+        serde_json::from_value(serde_json::json!({ "purl": purl, "vulnerabilities": [] })).unwrap()
+    }
+
+    #[test]
+    fn evaluations_are_visible_to_the_callers_they_were_made_for() {
+        let tenant = Scope::Tenant("acme".to_string());
+        assert!(tenant.is_visible_to(Some("acme")));
+        assert!(!tenant.is_visible_to(Some("other")));
+        assert!(Scope::Global.is_visible_to(Some("acme")));
+        assert!(!Scope::Unscoped.is_visible_to(Some("acme")));
+        for scope in [tenant, Scope::Global, Scope::Unscoped] {
+            assert!(scope.is_visible_to(None));
+            let tenant: Option<Option<String>> = scope.clone().into();
+            assert_eq!(Scope::from(tenant), scope);
+        }
+    }
+
+    #[test]
+    fn expired_evaluations_are_dropped() {
+        let journal = Journal::new(Duration::from_millis(50));
+        journal.record(
+            Scope::Unscoped,
+            "pkg:npm/a@1.0.0",
+            &package("pkg:npm/a@1.0.0"),
+        );
+        std::thread::sleep(Duration::from_millis(100));
+        journal.record(
+            Scope::Global,
+            "pkg:npm/b@1.0.0",
+            &package("pkg:npm/b@1.0.0"),
+        );
+        let entries = journal.entries.read().unwrap();
+        let purls: Vec<&str> = entries.iter().map(|e| e.purl.as_str()).collect();
+        assert_eq!(purls, ["pkg:npm/b@1.0.0"]);
+    }
+}
@@ -0,0 +1,164 @@
+use actix_web::{error, http::StatusCode, post, web, web::ServiceConfig, HttpResponse};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+use thiserror::Error;
+use utoipa::{OpenApi, ToSchema};
+
+use crate::guac::Guac;
+use crate::package;
+use crate::sbom::SbomRegistry;
+use crate::subgraph::{Direction, MAX_DEPTH};
+
+pub use trust_api_model::report::*;
+
+pub(crate) fn configure() -> impl FnOnce(&mut ServiceConfig) {
+    |config: &mut ServiceConfig| {
+        config.service(report_affected);
+    }
+}
+
+/// The documentation of the report endpoints.
+#[derive(OpenApi)]
+#[openapi(
+    paths(report_affected),
+    components(schemas(
+        AffectedRequest,
+        AffectedReport,
+        AffectedProduct,
+        AffectedComponent,
+        ApiError
+    ))
+)]
+pub(crate) struct ApiDoc;
+
+/// The dependency paths from each product to its affected components.
+type Paths = BTreeMap<String, BTreeMap<String, Vec<Vec<String>>>>;
+
+/// List the products affected by a vulnerability, with the paths to their affected components.
+///
+/// Products are the packages with an SBOM. They are found walking the dependents of the affected
+/// packages in Guac, and listing the components of all SBOMs, which also finds products whose
+/// SBOMs were not ingested into Guac.
+#[utoipa::path(
+    context_path = "/api/v1",
+    request_body = AffectedRequest,
+    responses(
+        (status = 200, description = "The affected products", body = AffectedReport),
+        (status = BAD_REQUEST, description = "No vulnerability id", body = Problem, example = json!(crate::problem::example(ApiError::MissingVulnerability))),
+        (status = BAD_GATEWAY, description = "Guac failed", body = Problem, example = json!(crate::problem::example(ApiError::Upstream(package::ApiError::UpstreamError { upstream: "guac".to_string(), status: 502, correlation_id: "5b0e1f3c-8a8e-4a77-9a3d-7f6b2c1d9e40".to_string() })))),
+        (status = SERVICE_UNAVAILABLE, description = "Guac could not be reached", body = Problem, example = json!(crate::problem::example(ApiError::Upstream(package::ApiError::UpstreamUnavailable { upstream: "guac".to_string(), correlation_id: "5b0e1f3c-8a8e-4a77-9a3d-7f6b2c1d9e40".to_string() })))),
+    )
+)]
+#[post("/report/affected")]
+pub async fn report_affected(
+    guac: web::Data<Arc<Guac>>,
+    sboms: web::Data<Arc<SbomRegistry>>,
+    body: web::Json<AffectedRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let cve = body.cve.trim();
+    if cve.is_empty() {
+        return Err(ApiError::MissingVulnerability);
+    }
+    let upstream = |e| ApiError::Upstream(package::ApiError::upstream("guac", e));
+
+    let packages = guac
+        .get_vulnerability(cve)
+        .await
+        .map_err(upstream)?
+        .map(|v| v.packages)
+        .unwrap_or_default();
+    let components: HashSet<String> = packages
+        .iter()
+        .map(|p| crate::purl::significant(&p.purl).unwrap_or_else(|| p.purl.clone()))
+        .collect();
+
+    let mut paths = Paths::new();
+    for component in &components {
+        if sboms.exists(component) {
+            add(&mut paths, component, component, vec![component.clone()]);
+        }
+        let products = guac
+            .paths(Direction::Dependents, component, MAX_DEPTH, false, |p| {
+                sboms.exists(p)
+            })
+            .await
+            .map_err(upstream)?;
+        for (product, found) in products {
+            for mut path in found {
+                // walked from the component up to the product
+                path.reverse();
+                add(&mut paths, &product, component, path);
+            }
+        }
+    }
+    for (product, listed) in sboms.containing(&components) {
+        for component in listed {
+            let known = paths
+                .get(&product)
+                .map_or(false, |c| c.contains_key(&component));
+            if !known {
+                add(
+                    &mut paths,
+                    &product,
+                    &component,
+                    vec![product.clone(), component.clone()],
+                );
+            }
+        }
+    }
+
+    let mut components: Vec<String> = components.into_iter().collect();
+    components.sort();
+    Ok(HttpResponse::Ok().json(AffectedReport {
+        cve: cve.to_string(),
+        components,
+        products: paths
+            .into_iter()
+            .map(|(purl, components)| AffectedProduct {
+                sbom: format!("/api/v1/package/sbom?purl={}", urlencoding::encode(&purl)),
+                purl,
+                components: components
+                    .into_iter()
+                    .map(|(purl, paths)| AffectedComponent { purl, paths })
+                    .collect(),
+            })
+            .collect(),
+    }))
+}
+
+fn add(paths: &mut Paths, product: &str, component: &str, path: Vec<String>) {
+    let found = paths
+        .entry(product.to_string())
+        .or_default()
+        .entry(component.to_string())
+        .or_default();
+    if !found.contains(&path) {
+        found.push(path);
+    }
+}
+
+#[derive(Debug, Error, Serialize, Deserialize, ToSchema)]
+#[schema(as = ReportApiError)]
+pub enum ApiError {
+    #[error("No vulnerability id was specified")]
+    MissingVulnerability,
+    #[error(transparent)]
+    Upstream(#[schema(inline)] package::ApiError),
+}
+
+impl error::ResponseError for ApiError {
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            ApiError::Upstream(e) => e.error_response(),
+            _ => crate::problem::response(self.status_code(), self, serde_json::json!({})),
+        }
+    }
+
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::MissingVulnerability => StatusCode::BAD_REQUEST,
+            ApiError::Upstream(e) => e.status_code(),
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use std::future::Future;
+use tracing::Instrument;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+const MAX_LENGTH: usize = 128;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// The request id of the request currently being processed, if any.
+pub fn current() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// Headers to add to outbound calls, propagating the current request id.
+pub fn headers() -> reqwest::header::HeaderMap {
+    let mut headers = reqwest::header::HeaderMap::new();
+    if let Some(value) = current().and_then(|id| reqwest::header::HeaderValue::from_str(&id).ok()) {
+        headers.insert(REQUEST_ID_HEADER, value);
+    }
+    headers
+}
+
+/// Accept the `X-Request-Id` of the incoming request, or generate one.
+///
+/// The id is made available to the handlers and outbound calls, attached to the tracing span
+/// of the request, and echoed in the response.
+pub fn middleware<S, B>(
+    mut req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+{
+    let id = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.is_empty() && v.len() <= MAX_LENGTH)
+        .map(ToString::to_string)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+
+    let name = HeaderName::from_static(REQUEST_ID_HEADER);
+    let value = HeaderValue::from_str(&id).ok();
+    if let Some(value) = &value {
+        // make it available to the access log
+        req.headers_mut().insert(name.clone(), value.clone());
+    }
+
+    let span = tracing::info_span!("request", request_id = %id, method = %req.method(), path = %req.path());
+    let fut = srv.call(req);
+
+    REQUEST_ID.scope(
+        id,
+        async move {
+            let mut res = fut.await?;
+            if let Some(value) = value {
+                res.headers_mut().insert(name, value);
+            }
+            Ok(res)
+        }
+        .instrument(span),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn outbound_calls_carry_the_current_request_id() {
+        assert!(headers().is_empty());
+        let headers = REQUEST_ID
+            .scope("abc".to_string(), async { headers() })
+            .await;
+        assert_eq!(headers[REQUEST_ID_HEADER], "abc");
+    }
+}
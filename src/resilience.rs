@@ -0,0 +1,331 @@
+use anyhow::anyhow;
+use std::any::Any;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::ResilienceConfig;
+use crate::metrics;
+
+const CLOSED: i64 = 0;
+const HALF_OPEN: i64 = 1;
+const OPEN: i64 = 2;
+
+/// The upstream service could not be reached, as opposed to failing the call.
+///
+/// Returned if the circuit breaker is open, or the last attempt failed to connect or timed out.
+#[derive(Debug, thiserror::Error)]
+#[error("{reason}")]
+pub struct Unavailable {
+    pub upstream: &'static str,
+    pub reason: String,
+}
+
+/// Whether an error means the upstream service could not be reached.
+pub fn is_unavailable(error: &anyhow::Error) -> bool {
+    error.downcast_ref::<Unavailable>().is_some()
+        || error
+            .downcast_ref::<reqwest::Error>()
+            .map_or(false, is_connection_error)
+}
+
+fn is_connection_error(error: &reqwest::Error) -> bool {
+    error.is_connect() || error.is_timeout()
+}
+
+/// A failed attempt, and whether it failed to reach the upstream service.
+struct Failure {
+    message: String,
+    unreachable: bool,
+}
+
+impl Failure {
+    fn new<E: std::fmt::Debug + 'static>(error: E) -> Self {
+        let any: &dyn Any = &error;
+        let unreachable = any
+            .downcast_ref::<reqwest::Error>()
+            .or_else(|| {
+                any.downcast_ref::<anyhow::Error>()
+                    .and_then(|e| e.downcast_ref::<reqwest::Error>())
+            })
+            .map_or(false, is_connection_error);
+        Self {
+            message: format!("{:?}", error),
+            unreachable,
+        }
+    }
+}
+
+impl From<String> for Failure {
+    fn from(message: String) -> Self {
+        Self {
+            message,
+            unreachable: false,
+        }
+    }
+}
+
+struct Breaker {
+    failures: u32,
+    open_until: Option<Instant>,
+    /// Whether a call is under way probing if the upstream service recovered, while half-open
+    probing: bool,
+}
+
+/// A call let through the circuit breaker.
+struct Permit<'a> {
+    resilience: &'a Resilience,
+    /// Whether the call probes if the upstream service recovered
+    probe: bool,
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        // the probe is given up if the call is dropped before it finished, e.g. cancelled
+        if self.probe {
+            self.resilience.breaker.lock().unwrap().probing = false;
+        }
+    }
+}
+
+/// Retries, timeouts and a circuit breaker for calls to an upstream service.
+pub struct Resilience {
+    upstream: &'static str,
+    config: ResilienceConfig,
+    breaker: Mutex<Breaker>,
+}
+
+impl Resilience {
+    pub fn new(upstream: &'static str, config: ResilienceConfig) -> Self {
+        metrics::CIRCUIT_BREAKER_STATE
+            .with_label_values(&[upstream])
+            .set(CLOSED);
+        Self {
+            upstream,
+            config,
+            breaker: Mutex::new(Breaker {
+                failures: 0,
+                open_until: None,
+                probing: false,
+            }),
+        }
+    }
+
+    /// Call an operation, retrying with exponential backoff on failures and timeouts.
+    ///
+    /// While the circuit breaker is open, calls fail immediately without reaching the upstream
+    /// service. Once it is half-open, a single call probes if the upstream service recovered, the
+    /// others keep failing until it succeeded.
+    pub async fn call<T, E, F, Fut>(&self, operation: &str, f: F) -> Result<T, anyhow::Error>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+        E: std::fmt::Debug + 'static,
+    {
+        let permit = self.acquire(operation)?;
+
+        metrics::UPSTREAM_CALLS
+            .with_label_values(&[self.upstream, operation])
+            .inc();
+        let start = Instant::now();
+        let timeout = Duration::from_millis(self.config.timeout_ms);
+        let mut backoff = Duration::from_millis(self.config.backoff_ms);
+        let mut attempt = 0;
+        loop {
+            let error = match tokio::time::timeout(timeout, self.attempt(operation, &f)).await {
+                Ok(Ok(value)) => {
+                    self.record_success();
+                    self.record_duration(operation, start);
+                    return Ok(value);
+                }
+                Ok(Err(e)) => e,
+                Err(_) => Failure {
+                    message: format!("timed out after {:?}", timeout),
+                    unreachable: true,
+                },
+            };
+
+            attempt += 1;
+            if attempt > self.config.retries {
+                self.record_failure(&permit);
+                self.record_duration(operation, start);
+                metrics::UPSTREAM_FAILURES
+                    .with_label_values(&[self.upstream, operation])
+                    .inc();
+                let reason = format!(
+                    "{} failed on {} after {} attempts: {}",
+                    operation, self.upstream, attempt, error.message
+                );
+                return Err(if error.unreachable {
+                    Unavailable {
+                        upstream: self.upstream,
+                        reason,
+                    }
+                    .into()
+                } else {
+                    anyhow!(reason)
+                });
+            }
+
+            log::debug!(
+                "{} failed on {} ({}), retrying in {:?}",
+                operation,
+                self.upstream,
+                error.message,
+                backoff
+            );
+            metrics::UPSTREAM_RETRIES
+                .with_label_values(&[self.upstream, operation])
+                .inc();
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+        }
+    }
+
+    async fn attempt<T, E, F, Fut>(&self, operation: &str, f: &F) -> Result<T, Failure>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+        E: std::fmt::Debug + 'static,
+    {
+        crate::chaos::inject(self.upstream)
+            .await
+            .map_err(|e| Failure::from(e.to_string()))?;
+        crate::cost::record(self.upstream, operation);
+        f().await.map_err(Failure::new)
+    }
+
+    /// Guard a call which retries and times out on its own by the circuit breaker, failing fast
+    /// while it is open and counting the failure of the call.
+    ///
+    /// The caller records the cost of its requests and injects faults into them, as only it knows
+    /// how many it sent.
+    pub async fn guard<T, Fut>(&self, operation: &str, call: Fut) -> Result<T, anyhow::Error>
+    where
+        Fut: Future<Output = Result<T, anyhow::Error>>,
+    {
+        let permit = self.acquire(operation)?;
+        metrics::UPSTREAM_CALLS
+            .with_label_values(&[self.upstream, operation])
+            .inc();
+        let start = Instant::now();
+        let result = call.await;
+        self.record_duration(operation, start);
+        match &result {
+            Ok(_) => self.record_success(),
+            Err(_) => {
+                self.record_failure(&permit);
+                metrics::UPSTREAM_FAILURES
+                    .with_label_values(&[self.upstream, operation])
+                    .inc();
+            }
+        }
+        result
+    }
+
+    /// Let a call through the circuit breaker, unless it is open, or half-open and already
+    /// probing.
+    fn acquire(&self, operation: &str) -> Result<Permit<'_>, Unavailable> {
+        let mut breaker = self.breaker.lock().unwrap();
+        let probe = match breaker.open_until {
+            None => false,
+            Some(until) if Instant::now() >= until && !breaker.probing => {
+                // let a single call through to probe if the upstream service has recovered
+                breaker.probing = true;
+                self.set_state(HALF_OPEN);
+                true
+            }
+            Some(_) => {
+                return Err(Unavailable {
+                    upstream: self.upstream,
+                    reason: format!("the circuit breaker is open, not calling {}", operation),
+                })
+            }
+        };
+        Ok(Permit {
+            resilience: self,
+            probe,
+        })
+    }
+
+    fn record_success(&self) {
+        let mut breaker = self.breaker.lock().unwrap();
+        breaker.failures = 0;
+        breaker.open_until = None;
+        breaker.probing = false;
+        self.set_state(CLOSED);
+    }
+
+    /// Count a failed call, opening the circuit breaker once there were too many in a row, or
+    /// again if the call probed it.
+    fn record_failure(&self, permit: &Permit<'_>) {
+        let mut breaker = self.breaker.lock().unwrap();
+        breaker.failures += 1;
+        if permit.probe {
+            breaker.probing = false;
+        }
+        if breaker.failures >= self.config.breaker_threshold || permit.probe {
+            breaker.open_until =
+                Some(Instant::now() + Duration::from_secs(self.config.breaker_reset_secs));
+            log::warn!(
+                "Opening circuit breaker for {} after {} failures",
+                self.upstream,
+                breaker.failures
+            );
+            self.set_state(OPEN);
+        }
+    }
+
+    fn record_duration(&self, operation: &str, start: Instant) {
+        metrics::UPSTREAM_DURATION
+            .with_label_values(&[self.upstream, operation])
+            .observe(start.elapsed().as_secs_f64());
+    }
+
+    fn set_state(&self, state: i64) {
+        metrics::CIRCUIT_BREAKER_STATE
+            .with_label_values(&[self.upstream])
+            .set(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn a_single_call_probes_a_half_open_breaker() {
+        let resilience = Resilience::new(
+            "test",
+            ResilienceConfig {
+                retries: 0,
+                breaker_threshold: 1,
+                breaker_reset_secs: 0,
+                ..Default::default()
+            },
+        );
+        let failed = resilience
+            .call("fail", || async { Err::<(), _>("failed") })
+            .await;
+        assert!(failed.is_err() && !is_unavailable(&failed.unwrap_err()));
+
+        let probe = resilience.call("probe", || async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            Ok::<_, String>(())
+        });
+        let other = async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            resilience
+                .call("other", || async { Ok::<_, String>(()) })
+                .await
+        };
+        let (probe, other) = tokio::join!(probe, other);
+        assert!(probe.is_ok());
+        assert!(is_unavailable(&other.unwrap_err()));
+
+        assert!(resilience
+            .call("closed", || async { Ok::<_, String>(()) })
+            .await
+            .is_ok());
+    }
+}
@@ -1,4 +1,19 @@
-use std::collections::HashMap;
+use anyhow::bail;
+use chrono::{DateTime, Utc};
+use packageurl::PackageUrl;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::config::IndexingLimits;
+use crate::package::{DependencyEdge, DependencyScope};
+use crate::publish::{PipelineEvent, Publisher};
+use crate::storage::Storage;
 
 const REGISTRY: &[(&'static str, &'static str)] = &[(
     "pkg:maven/io.seedwing/seedwing-java-example@1.0.0-SNAPSHOT?type=jar",
@@ -11,27 +26,1211 @@ const REGISTRY: &[(&'static str, &'static str)] = &[(
 )
 ];
 
-#[derive(Clone)]
+/// The source of SBOMs replicated from a primary.
+const REPLICA: &str = "primary";
+
+/// Reported as the source of the SBOMs shipped with the service.
+const BUILTIN: &str = "builtin";
+
+/// A location SBOMs are loaded from.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SbomSource {
+    pub id: String,
+    /// The tenant the SBOMs belong to, they are in the global namespace visible to all otherwise
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    #[serde(flatten)]
+    pub kind: SbomSourceKind,
+}
+
+/// Where the SBOMs of a source are read from.
+///
+/// OCI repositories are not supported as sources yet, as there is no OCI registry client to pull
+/// the SBOMs attached to images. Until then, such SBOMs can be copied into a storage prefix, e.g.
+/// with `oras pull`, and served from there.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SbomSourceKind {
+    /// All `.json` files of a directory
+    Directory { path: PathBuf },
+    /// All `.json` objects of the configured storage below a prefix, e.g. `sboms/`
+    Storage { prefix: String },
+}
+
+struct Entry {
+    /// The normalized purl of the SBOM, with all its qualifiers
+    purl: String,
+    /// The id of the source the SBOM was loaded from, `None` for built-in SBOMs
+    source: Option<String>,
+    /// The file or storage key the SBOM was read from
+    origin: Option<String>,
+    /// The tenant the SBOM belongs to, `None` for the global namespace
+    tenant: Option<String>,
+    /// The SHA-256 digest of the document, as it is served
+    sha256: String,
+    document: serde_json::Value,
+    indexed: DateTime<Utc>,
+}
+
+impl Entry {
+    fn is_visible(&self) -> bool {
+        crate::tenant::can_see(self.tenant.as_deref())
+    }
+}
+
+/// An SBOM with its purl and tenant.
+pub type Indexed = (String, Option<String>, serde_json::Value);
+
+/// The SBOMs of each key, at most one of each source and tenant. Of those of the same tenant, the
+/// most recently indexed one is in effect, the others are kept so they take effect again once it
+/// is removed.
+type Entries = HashMap<String, Vec<Entry>>;
+
 pub struct SbomRegistry {
-    data: HashMap<String, serde_json::Value>,
+    data: RwLock<Entries>,
+    sources: RwLock<Vec<SbomSource>>,
+    limits: IndexingLimits,
+    /// The pattern of the names of downloaded SBOMs
+    filename: String,
+    publisher: Arc<Publisher>,
+    storage: Arc<dyn Storage>,
+    /// Increased whenever SBOMs are added or removed
+    generation: AtomicU64,
 }
 
 impl SbomRegistry {
-    pub fn new() -> Self {
-        let mut data = HashMap::new();
+    pub fn new(
+        limits: IndexingLimits,
+        filename: String,
+        publisher: Arc<Publisher>,
+        storage: Arc<dyn Storage>,
+    ) -> Self {
+        let mut data = Entries::new();
 
         for entry in REGISTRY {
-            data.insert(entry.0.to_string(), serde_json::from_str(entry.1).unwrap());
+            let document = serde_json::from_str(entry.1).unwrap();
+            insert(
+                &mut data,
+                key(entry.0),
+                Entry {
+                    purl: full(entry.0),
+                    source: None,
+                    origin: None,
+                    tenant: None,
+                    sha256: sha256_of(&document),
+                    document,
+                    indexed: Utc::now(),
+                },
+            );
+        }
+
+        Self {
+            data: RwLock::new(data),
+            sources: RwLock::new(Vec::new()),
+            limits,
+            filename,
+            publisher,
+            storage,
+            generation: AtomicU64::new(0),
         }
+    }
+
+    /// A registry of the built-in SBOMs, storing SBOMs in a temporary directory of its own.
+    #[cfg(test)]
+    pub(crate) async fn for_tests() -> Self {
+        let dir = std::env::temp_dir().join(format!("trust-api-sboms-{}", uuid::Uuid::new_v4()));
+        Self::new(
+            IndexingLimits::default(),
+            "{name}.json".to_string(),
+            Arc::new(Publisher::new(None).await.unwrap()),
+            Arc::new(crate::storage::FileStorage::new(dir)),
+        )
+    }
 
-        Self { data }
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Relaxed)
     }
 
     pub fn exists(&self, purl: &str) -> bool {
-        self.data.contains_key(purl)
+        find(&self.data.read().unwrap(), purl).is_some()
     }
 
     pub fn lookup(&self, purl: &str) -> Option<serde_json::Value> {
-        self.data.get(purl).cloned()
+        find(&self.data.read().unwrap(), purl).map(|e| e.document.clone())
+    }
+
+    /// The SBOM of a package with its SHA-256 digest, hex encoded.
+    pub fn lookup_with_digest(&self, purl: &str) -> Option<(serde_json::Value, String)> {
+        find(&self.data.read().unwrap(), purl).map(|e| (e.document.clone(), e.sha256.clone()))
+    }
+
+    /// The SBOM with a SHA-256 digest, if visible to the running task.
+    pub fn lookup_digest(&self, sha256: &str) -> Option<serde_json::Value> {
+        self.data
+            .read()
+            .unwrap()
+            .values()
+            .flat_map(|entries| effective(entries))
+            .find(|e| e.is_visible() && e.sha256.eq_ignore_ascii_case(sha256))
+            .map(|e| e.document.clone())
+    }
+
+    /// The name of the downloaded SBOM of a package, per the configured pattern.
+    pub fn file_name(&self, purl: &str, document: &serde_json::Value) -> String {
+        file_name(&self.filename, purl, document)
+    }
+
+    /// When the SBOM of a package was indexed.
+    pub fn indexed(&self, purl: &str) -> Option<DateTime<Utc>> {
+        find(&self.data.read().unwrap(), purl).map(|e| e.indexed)
+    }
+
+    /// The purls of all packages with an SBOM.
+    pub fn purls(&self) -> Vec<String> {
+        self.data
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, entries)| entries.iter().any(Entry::is_visible))
+            .map(|(purl, _)| purl.clone())
+            .collect()
+    }
+
+    /// Whether a package only has SBOMs of other tenants than the one of the running task.
+    pub fn is_hidden(&self, purl: &str) -> bool {
+        self.data
+            .read()
+            .unwrap()
+            .get(&key(purl))
+            .map_or(false, |entries| !entries.iter().any(Entry::is_visible))
+    }
+
+    /// The tenants of the SBOM sources and of the SBOMs replicated from a primary.
+    pub fn tenants(&self) -> BTreeSet<String> {
+        let mut tenants: BTreeSet<String> = self
+            .sources()
+            .into_iter()
+            .filter_map(|s| s.tenant)
+            .collect();
+        tenants.extend(
+            self.data
+                .read()
+                .unwrap()
+                .values()
+                .flatten()
+                .filter_map(|e| e.tenant.clone()),
+        );
+        tenants
+    }
+
+    /// The SBOMs listing any of the components, by their purl, with the components they list.
+    pub fn containing(&self, components: &HashSet<String>) -> BTreeMap<String, Vec<String>> {
+        self.data
+            .read()
+            .unwrap()
+            .iter()
+            .filter_map(|(purl, entries)| Some((purl, visible(entries)?)))
+            .filter_map(|(purl, entry)| {
+                let listed: Vec<String> = component_purls(&entry.document)
+                    .into_iter()
+                    .map(|c| key(&c))
+                    .filter(|c| components.contains(c))
+                    .collect();
+                (!listed.is_empty()).then(|| (purl.clone(), listed))
+            })
+            .collect()
+    }
+
+    pub fn sources(&self) -> Vec<SbomSource> {
+        self.sources.read().unwrap().clone()
+    }
+
+    /// Register a new source and index its SBOMs, returning the number of SBOMs found.
+    ///
+    /// Only the SBOMs of the new source are loaded, existing entries are kept.
+    pub async fn add_source(&self, source: SbomSource) -> Result<usize, anyhow::Error> {
+        {
+            let mut sources = self.sources.write().unwrap();
+            if sources.iter().any(|s| s.id == source.id) {
+                bail!("SBOM source {} already exists", source.id);
+            }
+            sources.push(source.clone());
+        }
+
+        let documents = match self.load(&source.kind).await {
+            Ok(documents) => documents,
+            Err(e) => {
+                self.sources.write().unwrap().retain(|s| s.id != source.id);
+                return Err(e);
+            }
+        };
+        let purls = self.replace(&source.id, documents);
+        log::info!("Indexed {} SBOMs from source {}", purls.len(), source.id);
+        self.published(&source.id, &purls).await;
+        Ok(purls.len())
+    }
+
+    /// Load the SBOMs of sources again, all of them or the one with an id, returning the number
+    /// of SBOMs found.
+    ///
+    /// The SBOMs of a source are replaced at once when it was read, so lookups never see a
+    /// partially indexed source. Sources failing to load keep their previous SBOMs.
+    pub async fn reindex(&self, id: Option<&str>) -> usize {
+        let sources: Vec<SbomSource> = self
+            .sources()
+            .into_iter()
+            .filter(|s| id.map_or(true, |id| s.id == id))
+            .collect();
+
+        let mut indexed = 0;
+        for source in sources {
+            let documents = match self.load(&source.kind).await {
+                Ok(documents) => documents,
+                Err(e) => {
+                    log::warn!("Error reindexing SBOM source {}: {:?}", source.id, e);
+                    continue;
+                }
+            };
+            if !self.sources.read().unwrap().contains(&source) {
+                // removed while it was loaded
+                continue;
+            }
+            let purls = self.replace(&source.id, documents);
+            log::info!("Reindexed {} SBOMs from source {}", purls.len(), source.id);
+            self.published(&source.id, &purls).await;
+            indexed += purls.len();
+        }
+        indexed
+    }
+
+    async fn load(&self, kind: &SbomSourceKind) -> Result<Vec<Loaded>, anyhow::Error> {
+        match kind {
+            SbomSourceKind::Directory { path } => {
+                load_directory(path, Throttle::new(self.limits.clone())).await
+            }
+            SbomSourceKind::Storage { prefix } => {
+                load_storage(
+                    self.storage.as_ref(),
+                    prefix,
+                    Throttle::new(self.limits.clone()),
+                )
+                .await
+            }
+        }
+    }
+
+    /// Replace the SBOMs of a source, returning the keys of the new ones.
+    fn replace(&self, source: &str, documents: Vec<Loaded>) -> Vec<String> {
+        self.update(source, |_| true, documents)
+    }
+
+    /// Replace the SBOMs of a source read from the origins matching a predicate, returning the
+    /// keys of the new ones.
+    fn update<F>(&self, source: &str, replaced: F, documents: Vec<Loaded>) -> Vec<String>
+    where
+        F: Fn(Option<&str>) -> bool,
+    {
+        let purls: Vec<String> = documents.iter().map(|d| key(&d.purl)).collect();
+        let tenant = self
+            .sources()
+            .into_iter()
+            .find(|s| s.id == source)
+            .and_then(|s| s.tenant);
+        {
+            let indexed = Utc::now();
+            let mut data = self.data.write().unwrap();
+            retain(&mut data, |e| {
+                e.source.as_deref() != Some(source) || !replaced(e.origin.as_deref())
+            });
+            for loaded in documents {
+                insert(
+                    &mut data,
+                    key(&loaded.purl),
+                    Entry {
+                        purl: full(&loaded.purl),
+                        source: Some(source.to_string()),
+                        origin: Some(loaded.origin),
+                        tenant: tenant.clone(),
+                        sha256: sha256_of(&loaded.document),
+                        document: loaded.document,
+                        indexed,
+                    },
+                );
+            }
+        }
+        self.generation.fetch_add(1, Ordering::Relaxed);
+        purls
+    }
+
+    /// Read files of a directory source again, after they were added, changed or removed,
+    /// returning the number of SBOMs found.
+    ///
+    /// The SBOMs of all files are replaced at once. Files which can't be read keep their SBOMs.
+    pub async fn refresh_files(&self, source: &str, paths: &[PathBuf]) -> usize {
+        let mut origins = Vec::new();
+        let mut documents = Vec::new();
+        for path in paths {
+            let origin = path.display().to_string();
+            if path.extension().and_then(|e| e.to_str()) == Some("json") {
+                match tokio::fs::read(path).await {
+                    Ok(data) => match parse(&origin, data).await {
+                        Ok(Some(loaded)) => documents.push(loaded),
+                        Ok(None) => {}
+                        Err(e) => {
+                            log::warn!("Error parsing {}: {:?}", origin, e);
+                            continue;
+                        }
+                    },
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(e) => {
+                        log::warn!("Error reading {}: {:?}", origin, e);
+                        continue;
+                    }
+                }
+            }
+            origins.push(origin);
+        }
+        if origins.is_empty() {
+            return 0;
+        }
+
+        let purls = self.update(
+            source,
+            |origin| origin.map_or(false, |o| origins.iter().any(|c| c == o)),
+            documents,
+        );
+        log::info!(
+            "Refreshed {} SBOMs from {} changed files of source {}",
+            purls.len(),
+            origins.len(),
+            source
+        );
+        self.published(source, &purls).await;
+        purls.len()
+    }
+
+    async fn published(&self, source: &str, purls: &[String]) {
+        for purl in purls {
+            self.publisher
+                .publish(PipelineEvent::SbomIndexed {
+                    purl: purl.clone(),
+                    source: source.to_string(),
+                })
+                .await;
+        }
+    }
+
+    /// The number of SBOMs by the id of the source they were loaded from, `builtin` for the
+    /// SBOMs shipped with the service.
+    pub fn counts(&self) -> BTreeMap<String, usize> {
+        let mut counts = BTreeMap::new();
+        for entry in self.data.read().unwrap().values().flatten() {
+            let source = entry.source.as_deref().unwrap_or(BUILTIN);
+            *counts.entry(source.to_string()).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    /// The SBOMs indexed since a point in time, or all of them, with their tenant.
+    pub fn indexed_since(&self, since: Option<DateTime<Utc>>) -> Vec<Indexed> {
+        self.data
+            .read()
+            .unwrap()
+            .values()
+            .flat_map(|entries| effective(entries))
+            .filter(|e| since.map_or(true, |since| e.indexed >= since))
+            .map(|e| (e.purl.clone(), e.tenant.clone(), e.document.clone()))
+            .collect()
+    }
+
+    /// The purls and tenants of the SBOMs [`Self::documents`] returns, without their documents.
+    pub fn listed(&self) -> Vec<(String, Option<String>)> {
+        let mut listed: Vec<(String, Option<String>)> = self
+            .data
+            .read()
+            .unwrap()
+            .values()
+            .filter_map(|entries| visible(entries))
+            .map(|e| (e.purl.clone(), e.tenant.clone()))
+            .collect();
+        listed.sort();
+        listed
+    }
+
+    /// The SBOMs visible to the running task, at most one of each package, ordered by purl.
+    pub fn documents(&self) -> Vec<Indexed> {
+        let mut documents: Vec<Indexed> = self
+            .data
+            .read()
+            .unwrap()
+            .values()
+            .filter_map(|entries| visible(entries))
+            .map(|e| (e.purl.clone(), e.tenant.clone(), e.document.clone()))
+            .collect();
+        documents.sort_by(|a, b| a.0.cmp(&b.0));
+        documents
+    }
+
+    /// Add the SBOMs replicated from a primary, and remove those the primary no longer has.
+    pub fn replicate(&self, documents: Vec<Indexed>, purls: &[String]) {
+        let indexed = Utc::now();
+        let mut data = self.data.write().unwrap();
+        data.retain(|purl, entries| {
+            entries.retain(|e| e.source.as_deref() != Some(REPLICA) || purls.contains(purl));
+            !entries.is_empty()
+        });
+        for (purl, tenant, document) in documents {
+            insert(
+                &mut data,
+                key(&purl),
+                Entry {
+                    purl: full(&purl),
+                    source: Some(REPLICA.to_string()),
+                    origin: None,
+                    tenant,
+                    sha256: sha256_of(&document),
+                    document,
+                    indexed,
+                },
+            );
+        }
+        self.generation.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Remove a source and all SBOMs loaded from it.
+    pub fn remove_source(&self, id: &str) -> bool {
+        let mut sources = self.sources.write().unwrap();
+        let len = sources.len();
+        sources.retain(|s| s.id != id);
+        if sources.len() == len {
+            return false;
+        }
+
+        retain(&mut self.data.write().unwrap(), |e| {
+            e.source.as_deref() != Some(id)
+        });
+        self.generation.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+}
+
+/// The registry is keyed by normalized purls with their significant qualifiers, so lookups don't
+/// depend on how a purl is spelled or where the package was downloaded from.
+fn key(purl: &str) -> String {
+    crate::purl::significant(purl).unwrap_or_else(|| purl.to_string())
+}
+
+fn full(purl: &str) -> String {
+    crate::purl::normalize(purl).unwrap_or_else(|| purl.to_string())
+}
+
+/// The visible entry of a purl, purls with insignificant qualifiers (from `strict` queries) only
+/// match SBOMs with exactly the same qualifiers.
+fn find<'a>(data: &'a Entries, purl: &str) -> Option<&'a Entry> {
+    let entry = visible(data.get(&key(purl))?)?;
+    let purl = full(purl);
+    (purl == key(&purl) || entry.purl == purl).then_some(entry)
+}
+
+/// The entry visible to the running task, the one of its tenant takes precedence over the global
+/// one.
+fn visible(entries: &[Entry]) -> Option<&Entry> {
+    effective(entries)
+        .filter(|e| e.is_visible())
+        .max_by_key(|e| e.tenant.is_some())
+}
+
+/// The entries in effect, the most recently indexed one of each tenant, the later added one if
+/// they were indexed at the same time.
+fn effective(entries: &[Entry]) -> impl Iterator<Item = &Entry> {
+    entries
+        .iter()
+        .enumerate()
+        .filter(move |(i, entry)| {
+            !entries.iter().enumerate().any(|(j, other)| {
+                other.tenant == entry.tenant && (other.indexed, j) > (entry.indexed, *i)
+            })
+        })
+        .map(|(_, entry)| entry)
+}
+
+/// Add an entry, replacing the one of the same source and tenant.
+fn insert(data: &mut Entries, key: String, entry: Entry) {
+    let entries = data.entry(key).or_default();
+    entries.retain(|e| e.tenant != entry.tenant || e.source != entry.source);
+    entries.push(entry);
+}
+
+fn retain<F: Fn(&Entry) -> bool>(data: &mut Entries, f: F) {
+    data.retain(|_, entries| {
+        entries.retain(&f);
+        !entries.is_empty()
+    });
+}
+
+/// The name of a downloaded SBOM, from the parts of its purl and its format.
+///
+/// Placeholders without a value are left out together with a `-` in front of them, and all
+/// characters other than letters, digits, `.`, `-` and `_` are replaced by `_`.
+pub fn file_name(pattern: &str, purl: &str, document: &serde_json::Value) -> String {
+    let (ty, namespace, name, version) = match PackageUrl::from_str(purl) {
+        Ok(p) => (
+            Some(p.ty().to_string()),
+            p.namespace().map(ToString::to_string),
+            p.name().to_string(),
+            p.version().map(ToString::to_string),
+        ),
+        Err(_) => (None, None, purl.to_string(), None),
+    };
+    let format = match document.get("spdxVersion") {
+        Some(_) => "spdx",
+        None => "cdx",
+    };
+    let values = [
+        ("type", ty),
+        ("namespace", namespace),
+        ("name", Some(name)),
+        ("version", version),
+        ("format", Some(format.to_string())),
+    ];
+    let mut name = pattern.to_string();
+    for (placeholder, value) in values {
+        let placeholder = format!("{{{}}}", placeholder);
+        match value.filter(|v| !v.is_empty()) {
+            Some(value) => name = name.replace(&placeholder, &value),
+            None => {
+                name = name
+                    .replace(&format!("-{}", placeholder), "")
+                    .replace(&placeholder, "")
+            }
+        }
+    }
+    name.chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' | '_' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+/// The SHA-256 digest of a document serialized the way it is served, hex encoded.
+pub fn sha256_of(document: &serde_json::Value) -> String {
+    hex::encode(Sha256::digest(
+        serde_json::to_vec(document).unwrap_or_default(),
+    ))
+}
+
+/// Find the purl of the component an SBOM describes, supporting CycloneDX and SPDX.
+pub fn purl_of(document: &serde_json::Value) -> Option<String> {
+    if let Some(purl) = document
+        .pointer("/metadata/component/purl")
+        .and_then(|p| p.as_str())
+    {
+        return Some(purl.to_string());
+    }
+
+    described_package(document)?
+        .get("externalRefs")?
+        .as_array()?
+        .iter()
+        .find(|r| r.get("referenceType").and_then(|t| t.as_str()) == Some("purl"))?
+        .get("referenceLocator")?
+        .as_str()
+        .map(ToString::to_string)
+}
+
+/// Find the SHA-256 digest of the component an SBOM describes, supporting CycloneDX and SPDX.
+pub fn digest_of(document: &serde_json::Value) -> Option<String> {
+    let find = |list: &serde_json::Value, alg: &str, algorithm: &str, value: &str| {
+        list.as_array()?
+            .iter()
+            .find(|h| h.get(alg).and_then(|a| a.as_str()) == Some(algorithm))?
+            .get(value)?
+            .as_str()
+            .map(ToString::to_string)
+    };
+
+    if let Some(hashes) = document.pointer("/metadata/component/hashes") {
+        return find(hashes, "alg", "SHA-256", "content");
+    }
+    find(
+        described_package(document)?.get("checksums")?,
+        "algorithm",
+        "SHA256",
+        "checksumValue",
+    )
+}
+
+/// The purls of all components listed in an SBOM, supporting CycloneDX and SPDX.
+pub fn component_purls(document: &serde_json::Value) -> Vec<String> {
+    fn cyclonedx(components: &serde_json::Value, purls: &mut Vec<String>) {
+        for component in components.as_array().into_iter().flatten() {
+            if let Some(purl) = component.get("purl").and_then(|p| p.as_str()) {
+                purls.push(purl.to_string());
+            }
+            if let Some(components) = component.get("components") {
+                cyclonedx(components, purls);
+            }
+        }
+    }
+
+    let mut purls = Vec::new();
+    if let Some(components) = document.get("components") {
+        cyclonedx(components, &mut purls);
+    }
+    for package in document
+        .get("packages")
+        .and_then(|p| p.as_array())
+        .into_iter()
+        .flatten()
+    {
+        purls.extend(
+            package
+                .get("externalRefs")
+                .and_then(|r| r.as_array())
+                .into_iter()
+                .flatten()
+                .filter(|r| r.get("referenceType").and_then(|t| t.as_str()) == Some("purl"))
+                .filter_map(|r| r.get("referenceLocator")?.as_str().map(ToString::to_string)),
+        );
+    }
+
+    let mut seen = std::collections::HashSet::new();
+    purls.retain(|p| seen.insert(p.clone()));
+    purls
+}
+
+/// The license expressions of the component an SBOM describes, supporting CycloneDX and SPDX.
+pub fn licenses_of(document: &serde_json::Value) -> Vec<String> {
+    if let Some(licenses) = document.pointer("/metadata/component/licenses") {
+        return licenses
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|l| {
+                l.get("expression")
+                    .or_else(|| l.pointer("/license/id"))
+                    .or_else(|| l.pointer("/license/name"))?
+                    .as_str()
+                    .map(ToString::to_string)
+            })
+            .collect();
+    }
+
+    let package = match described_package(document) {
+        Some(package) => package,
+        None => return Vec::new(),
+    };
+    let mut licenses = Vec::new();
+    for field in ["licenseConcluded", "licenseDeclared"] {
+        if let Some(license) = package.get(field).and_then(|l| l.as_str()) {
+            if license != "NOASSERTION"
+                && license != "NONE"
+                && !licenses.iter().any(|l| l == license)
+            {
+                licenses.push(license.to_string());
+            }
+        }
+    }
+    licenses
+}
+
+/// How the component an SBOM describes depends on its direct dependencies, by their purls,
+/// supporting CycloneDX and SPDX.
+pub fn dependency_edges(document: &serde_json::Value) -> HashMap<String, DependencyEdge> {
+    let mut edges = HashMap::new();
+
+    if let Some(described) = document.pointer("/metadata/component") {
+        fn cyclonedx<'a>(
+            list: &'a serde_json::Value,
+            components: &mut HashMap<&'a str, (&'a str, Option<&'a str>)>,
+        ) {
+            for component in list.as_array().into_iter().flatten() {
+                let purl = component.get("purl").and_then(|p| p.as_str());
+                let reference = component.get("bom-ref").and_then(|r| r.as_str()).or(purl);
+                if let (Some(reference), Some(purl)) = (reference, purl) {
+                    let scope = component.get("scope").and_then(|s| s.as_str());
+                    components.insert(reference, (purl, scope));
+                }
+                if let Some(nested) = component.get("components") {
+                    cyclonedx(nested, components);
+                }
+            }
+        }
+
+        // the purls and scopes of the components, by their reference
+        let mut components = HashMap::new();
+        if let Some(list) = document.get("components") {
+            cyclonedx(list, &mut components);
+        }
+        let scope = |scope: Option<&str>| match scope {
+            Some("required") => Some(DependencyScope::Runtime),
+            Some("optional") => Some(DependencyScope::Optional),
+            Some("excluded") => Some(DependencyScope::Excluded),
+            _ => None,
+        };
+
+        let own = described
+            .get("bom-ref")
+            .or_else(|| described.get("purl"))
+            .and_then(|r| r.as_str());
+        let depends_on = document
+            .get("dependencies")
+            .and_then(|d| d.as_array())
+            .into_iter()
+            .flatten()
+            .find(|d| d.get("ref").and_then(|r| r.as_str()) == own)
+            .and_then(|d| d.get("dependsOn"))
+            .and_then(|d| d.as_array());
+        match depends_on {
+            Some(depends_on) => {
+                for reference in depends_on.iter().filter_map(|r| r.as_str()) {
+                    if let Some((purl, declared)) = components.get(reference) {
+                        edges.insert(
+                            purl.to_string(),
+                            DependencyEdge {
+                                scope: scope(*declared),
+                                version_range: None,
+                                relationship: Some("dependsOn".to_string()),
+                            },
+                        );
+                    }
+                }
+            }
+            // without a dependency graph, all components are taken as direct dependencies
+            None => {
+                for (purl, declared) in components.values() {
+                    edges.insert(
+                        purl.to_string(),
+                        DependencyEdge {
+                            scope: scope(*declared),
+                            version_range: None,
+                            relationship: None,
+                        },
+                    );
+                }
+            }
+        }
+        return edges;
+    }
+
+    let described = match described_package(document)
+        .and_then(|p| p.get("SPDXID"))
+        .and_then(|id| id.as_str())
+    {
+        Some(described) => described,
+        None => return edges,
+    };
+    let purls: HashMap<&str, &str> = document
+        .get("packages")
+        .and_then(|p| p.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|package| {
+            let id = package.get("SPDXID")?.as_str()?;
+            let purl = package
+                .get("externalRefs")?
+                .as_array()?
+                .iter()
+                .find(|r| r.get("referenceType").and_then(|t| t.as_str()) == Some("purl"))?
+                .get("referenceLocator")?
+                .as_str()?;
+            Some((id, purl))
+        })
+        .collect();
+    for relationship in document
+        .get("relationships")
+        .and_then(|r| r.as_array())
+        .into_iter()
+        .flatten()
+    {
+        let field = |name: &str| relationship.get(name).and_then(|v| v.as_str());
+        let (element, kind, related) = match (
+            field("spdxElementId"),
+            field("relationshipType"),
+            field("relatedSpdxElement"),
+        ) {
+            (Some(element), Some(kind), Some(related)) => (element, kind, related),
+            _ => continue,
+        };
+        // `A DEPENDS_ON B` and `A CONTAINS B` point to the dependency, `B ..._OF A` from it
+        let dependency = match kind {
+            "DEPENDS_ON" | "CONTAINS" if element == described => related,
+            kind if kind.ends_with("DEPENDENCY_OF") && related == described => element,
+            _ => continue,
+        };
+        let scope = match kind {
+            "RUNTIME_DEPENDENCY_OF" => Some(DependencyScope::Runtime),
+            "DEV_DEPENDENCY_OF" => Some(DependencyScope::Development),
+            "OPTIONAL_DEPENDENCY_OF" => Some(DependencyScope::Optional),
+            "BUILD_DEPENDENCY_OF" => Some(DependencyScope::Build),
+            "TEST_DEPENDENCY_OF" => Some(DependencyScope::Test),
+            "PROVIDED_DEPENDENCY_OF" => Some(DependencyScope::Provided),
+            _ => None,
+        };
+        let purl = match purls.get(dependency) {
+            Some(purl) => purl.to_string(),
+            None => continue,
+        };
+        // a relationship with a scope wins over a plain `DEPENDS_ON` of the same package
+        if scope.is_some() || !edges.contains_key(&purl) {
+            edges.insert(
+                purl,
+                DependencyEdge {
+                    scope,
+                    version_range: None,
+                    relationship: Some(kind.to_string()),
+                },
+            );
+        }
+    }
+    edges
+}
+
+/// When an SBOM was created, supporting CycloneDX and SPDX.
+pub fn created_of(document: &serde_json::Value) -> Option<DateTime<Utc>> {
+    document
+        .pointer("/metadata/timestamp")
+        .or_else(|| document.pointer("/creationInfo/created"))?
+        .as_str()?
+        .parse()
+        .ok()
+}
+
+/// The package an SPDX document describes.
+pub fn described_package(document: &serde_json::Value) -> Option<&serde_json::Value> {
+    let described = document.pointer("/documentDescribes/0")?.as_str()?;
+    document
+        .get("packages")?
+        .as_array()?
+        .iter()
+        .find(|p| p.get("SPDXID").and_then(|id| id.as_str()) == Some(described))
+}
+
+/// Limits the rate of reading SBOMs, so indexing doesn't degrade the latency of requests.
+struct Throttle {
+    limits: IndexingLimits,
+    start: Instant,
+    files: u64,
+    bytes: u64,
+}
+
+impl Throttle {
+    fn new(limits: IndexingLimits) -> Self {
+        Self {
+            limits,
+            start: Instant::now(),
+            files: 0,
+            bytes: 0,
+        }
+    }
+
+    /// Account for a file which was read, waiting until the rate is within the limits.
+    async fn consume(&mut self, bytes: u64) {
+        self.files += 1;
+        self.bytes += bytes;
+
+        let due = |amount: u64, rate: u64| match rate {
+            0 => Duration::ZERO,
+            rate => Duration::from_secs_f64(amount as f64 / rate as f64),
+        };
+        let due = due(self.files, self.limits.files_per_sec)
+            .max(due(self.bytes, self.limits.bytes_per_sec));
+        let wait = due.saturating_sub(self.start.elapsed());
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// An SBOM read from a source.
+struct Loaded {
+    /// The file or storage key it was read from
+    origin: String,
+    purl: String,
+    document: serde_json::Value,
+}
+
+async fn load_directory(path: &Path, mut throttle: Throttle) -> Result<Vec<Loaded>, anyhow::Error> {
+    let mut documents = Vec::new();
+    let mut entries = tokio::fs::read_dir(path).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("json") {
+            continue;
+        }
+
+        let data = tokio::fs::read(&path).await?;
+        throttle.consume(data.len() as u64).await;
+        if let Some(document) = parse(&path.display().to_string(), data).await? {
+            documents.push(document);
+        }
+    }
+    Ok(documents)
+}
+
+async fn load_storage(
+    storage: &dyn Storage,
+    prefix: &str,
+    mut throttle: Throttle,
+) -> Result<Vec<Loaded>, anyhow::Error> {
+    let mut documents = Vec::new();
+    for key in storage.list(prefix).await? {
+        if !key.ends_with(".json") {
+            continue;
+        }
+
+        // removed since it was listed
+        let data = match storage.get(&key).await? {
+            Some(data) => data,
+            None => continue,
+        };
+        throttle.consume(data.len() as u64).await;
+        if let Some(document) = parse(&key, data).await? {
+            documents.push(document);
+        }
+    }
+    Ok(documents)
+}
+
+/// Parse a document read from `name`, `None` if it is not an SBOM with a purl.
+async fn parse(name: &str, data: Vec<u8>) -> Result<Option<Loaded>, anyhow::Error> {
+    // parsing huge documents is CPU bound, keep it off the threads serving requests
+    let parsed =
+        tokio::task::spawn_blocking(move || serde_json::from_slice::<serde_json::Value>(&data))
+            .await?;
+    let document: serde_json::Value = match parsed {
+        Ok(document) => document,
+        Err(e) => {
+            log::warn!("Skipping {}, not valid JSON: {}", name, e);
+            return Ok(None);
+        }
+    };
+    match purl_of(&document) {
+        Some(purl) => Ok(Some(Loaded {
+            origin: name.to_string(),
+            purl,
+            document,
+        })),
+        None => {
+            log::debug!("Skipping {}, not an SBOM with a purl", name);
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn scopes(edges: &HashMap<String, DependencyEdge>) -> BTreeMap<&str, Option<DependencyScope>> {
+        edges
+            .iter()
+            .map(|(purl, edge)| (purl.as_str(), edge.scope))
+            .collect()
+    }
+
+    #[test]
+    fn file_names_fill_the_placeholders_of_the_pattern() {
+        let pattern = "{name}-{version}.{format}.json";
+        assert_eq!(
+            file_name(
+                pattern,
+                "pkg:maven/io.vertx/vertx-web@4.3.4.redhat-00007",
+                &json!({})
+            ),
+            "vertx-web-4.3.4.redhat-00007.cdx.json"
+        );
+        assert_eq!(
+            file_name(
+                "{type}-{namespace}-{name}-{version}.{format}.json",
+                "pkg:maven/io.vertx/vertx-web@4.3.7",
+                &json!({"spdxVersion": "SPDX-2.3"})
+            ),
+            "maven-io.vertx-vertx-web-4.3.7.spdx.json"
+        );
+    }
+
+    #[test]
+    fn file_names_leave_out_missing_parts() {
+        assert_eq!(
+            file_name(
+                "{name}-{version}.{format}.json",
+                "pkg:npm/lodash",
+                &json!({})
+            ),
+            "lodash.cdx.json"
+        );
+        assert_eq!(
+            file_name(
+                "{type}-{namespace}-{name}-{version}.{format}.json",
+                "pkg:pypi/django@4.2.1",
+                &json!({})
+            ),
+            "pypi-django-4.2.1.cdx.json"
+        );
+    }
+
+    #[test]
+    fn file_names_are_safe_to_write() {
+        let pattern = "{namespace}-{name}-{version}.{format}.json";
+        assert_eq!(
+            file_name(pattern, "pkg:npm/%40babel/core@7.22.5", &json!({})),
+            "_babel-core-7.22.5.cdx.json"
+        );
+        assert_eq!(
+            file_name(
+                pattern,
+                "pkg:golang/github.com/gorilla/mux@v1.8.0",
+                &json!({})
+            ),
+            "github.com_gorilla-mux-v1.8.0.cdx.json"
+        );
+        // not a purl, the whole of it is the name
+        assert_eq!(
+            file_name(
+                "{name}-{version}.{format}.json",
+                "../../etc/passwd",
+                &json!({})
+            ),
+            ".._.._etc_passwd.cdx.json"
+        );
+    }
+
+    #[test]
+    fn cyclonedx_edges_follow_the_dependency_graph() {
+        let document = json!({
+            "metadata": {"component": {"bom-ref": "app", "purl": "pkg:maven/io.example/app@1.0"}},
+            "components": [
+                {"bom-ref": "web", "purl": "pkg:maven/io.vertx/vertx-web@4.3.7", "scope": "required"},
+                {"bom-ref": "junit", "purl": "pkg:maven/junit/junit@4.13", "scope": "excluded"},
+                {"purl": "pkg:maven/io.vertx/vertx-core@4.3.7", "components": [
+                    {"bom-ref": "netty", "purl": "pkg:maven/io.netty/netty-codec@4.1.86", "scope": "optional"},
+                ]},
+                {"bom-ref": "other", "purl": "pkg:maven/io.example/other@1.0"},
+            ],
+            "dependencies": [
+                {"ref": "app", "dependsOn": ["web", "junit", "netty", "pkg:maven/io.vertx/vertx-core@4.3.7"]},
+                {"ref": "web", "dependsOn": ["other"]},
+            ],
+        });
+        let edges = dependency_edges(&document);
+        assert_eq!(
+            scopes(&edges),
+            BTreeMap::from([
+                (
+                    "pkg:maven/io.netty/netty-codec@4.1.86",
+                    Some(DependencyScope::Optional)
+                ),
+                ("pkg:maven/io.vertx/vertx-core@4.3.7", None),
+                (
+                    "pkg:maven/io.vertx/vertx-web@4.3.7",
+                    Some(DependencyScope::Runtime)
+                ),
+                (
+                    "pkg:maven/junit/junit@4.13",
+                    Some(DependencyScope::Excluded)
+                ),
+            ])
+        );
+        assert_eq!(
+            edges["pkg:maven/io.vertx/vertx-web@4.3.7"]
+                .relationship
+                .as_deref(),
+            Some("dependsOn")
+        );
+    }
+
+    #[test]
+    fn cyclonedx_components_are_direct_dependencies_without_a_graph() {
+        let document = json!({
+            "metadata": {"component": {"purl": "pkg:maven/io.example/app@1.0"}},
+            "components": [
+                {"purl": "pkg:maven/io.vertx/vertx-web@4.3.7", "scope": "required"},
+                {"purl": "pkg:maven/junit/junit@4.13"},
+            ],
+        });
+        let edges = dependency_edges(&document);
+        assert_eq!(
+            scopes(&edges),
+            BTreeMap::from([
+                (
+                    "pkg:maven/io.vertx/vertx-web@4.3.7",
+                    Some(DependencyScope::Runtime)
+                ),
+                ("pkg:maven/junit/junit@4.13", None),
+            ])
+        );
+        assert!(edges.values().all(|e| e.relationship.is_none()));
+    }
+
+    #[test]
+    fn spdx_edges_are_the_relationships_of_the_described_package() {
+        let package = |id: &str, purl: &str| {
+            json!({"SPDXID": id, "externalRefs": [
+                {"referenceType": "purl", "referenceLocator": purl},
+            ]})
+        };
+        let relationship = |element: &str, kind: &str, related: &str| json!({"spdxElementId": element, "relationshipType": kind, "relatedSpdxElement": related});
+        let document = json!({
+            "documentDescribes": ["SPDXRef-app"],
+            "packages": [
+                package("SPDXRef-app", "pkg:npm/app@1.0.0"),
+                package("SPDXRef-jest", "pkg:npm/jest@29.5.0"),
+                package("SPDXRef-lodash", "pkg:npm/lodash@4.17.21"),
+                package("SPDXRef-left-pad", "pkg:npm/left-pad@1.3.0"),
+            ],
+            "relationships": [
+                relationship("SPDXRef-app", "DEPENDS_ON", "SPDXRef-jest"),
+                relationship("SPDXRef-jest", "DEV_DEPENDENCY_OF", "SPDXRef-app"),
+                relationship("SPDXRef-app", "CONTAINS", "SPDXRef-lodash"),
+                // not a dependency of the described package
+                relationship("SPDXRef-lodash", "DEPENDS_ON", "SPDXRef-left-pad"),
+            ],
+        });
+        let edges = dependency_edges(&document);
+        assert_eq!(
+            scopes(&edges),
+            BTreeMap::from([
+                ("pkg:npm/jest@29.5.0", Some(DependencyScope::Development)),
+                ("pkg:npm/lodash@4.17.21", None),
+            ])
+        );
+        assert_eq!(
+            edges["pkg:npm/jest@29.5.0"].relationship.as_deref(),
+            Some("DEV_DEPENDENCY_OF")
+        );
+    }
+
+    #[tokio::test]
+    async fn overridden_sboms_take_effect_again_once_the_override_is_removed() {
+        let (purl, builtin) = REGISTRY[0];
+        let dir = std::env::temp_dir().join(format!("trust-api-sboms-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let document = json!({"metadata": {"component": {"purl": purl}}, "components": []});
+        std::fs::write(dir.join("override.json"), document.to_string()).unwrap();
+
+        let sboms = SbomRegistry::new(
+            IndexingLimits::default(),
+            "{name}.json".to_string(),
+            Arc::new(Publisher::new(None).await.unwrap()),
+            Arc::new(crate::storage::FileStorage::new(dir.clone())),
+        );
+        let builtin: serde_json::Value = serde_json::from_str(builtin).unwrap();
+        assert_eq!(sboms.lookup(purl), Some(builtin.clone()));
+
+        let source = SbomSource {
+            id: "override".to_string(),
+            tenant: None,
+            kind: SbomSourceKind::Directory { path: dir.clone() },
+        };
+        assert_eq!(sboms.add_source(source).await.unwrap(), 1);
+        assert_eq!(sboms.lookup(purl), Some(document));
+        assert_eq!(sboms.indexed_since(None).len(), REGISTRY.len());
+
+        assert!(sboms.remove_source("override"));
+        assert_eq!(sboms.lookup(purl), Some(builtin));
+        std::fs::remove_dir_all(dir).ok();
     }
 }
@@ -0,0 +1,97 @@
+//! The periodic background jobs of an instance, e.g. analyzing the known packages again,
+//! evaluating watches and rebuilding the search index.
+
+use futures::future::BoxFuture;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::time::MissedTickBehavior;
+
+/// A job run periodically by the [`Scheduler`].
+pub trait Job: Send + Sync + 'static {
+    /// The name of the job, in the log.
+    fn name(&self) -> &'static str;
+
+    /// Run the job once. A failed run is logged, the job runs again at its next time.
+    fn run(&self) -> BoxFuture<'_, Result<(), anyhow::Error>>;
+}
+
+/// Runs each of its jobs at its interval, the first time right when started.
+///
+/// A run of a job which takes longer than the interval delays its next run, rather than runs
+/// piling up.
+#[derive(Default)]
+pub struct Scheduler {
+    jobs: Vec<(Arc<dyn Job>, Duration)>,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run a job every `interval`.
+    pub fn every(&mut self, interval: Duration, job: Arc<dyn Job>) -> &mut Self {
+        self.jobs.push((job, interval));
+        self
+    }
+
+    /// Start running the jobs in the background.
+    pub fn start(self) {
+        for (job, interval) in self.jobs {
+            tokio::spawn(run(job, interval));
+        }
+    }
+}
+
+async fn run(job: Arc<dyn Job>, interval: Duration) {
+    let mut ticks = tokio::time::interval(interval);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        ticks.tick().await;
+        let started = Instant::now();
+        match job.run().await {
+            Ok(()) => log::debug!("Ran {} in {:?}", job.name(), started.elapsed()),
+            Err(e) => log::warn!("Error running {}: {:?}", job.name(), e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct Counter(AtomicUsize);
+
+    impl Job for Counter {
+        fn name(&self) -> &'static str {
+            "counter"
+        }
+
+        fn run(&self) -> BoxFuture<'_, Result<(), anyhow::Error>> {
+            Box::pin(async move {
+                // failing runs don't stop the job
+                match self.0.fetch_add(1, Ordering::SeqCst) {
+                    0 => Err(anyhow::anyhow!("first run fails")),
+                    _ => Ok(()),
+                }
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn jobs_run_again_after_failing() {
+        let counter = Arc::new(Counter(AtomicUsize::new(0)));
+        let mut scheduler = Scheduler::new();
+        scheduler.every(Duration::from_millis(10), counter.clone());
+        scheduler.start();
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while counter.0.load(Ordering::SeqCst) < 3 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+}
@@ -0,0 +1,124 @@
+use packageurl::PackageUrl;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::depsdev::DepsDev;
+use crate::guac::Guac;
+use crate::package::ScorecardSummary;
+
+/// Where to fetch OpenSSF Scorecard results from.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScorecardConfig {
+    pub enabled: bool,
+    pub url: String,
+    /// Checks scoring below this (out of 10) are reported as failing
+    pub threshold: i32,
+}
+
+impl Default for ScorecardConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            url: "https://api.securityscorecards.dev".to_string(),
+            threshold: 5,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Check {
+    name: String,
+    /// `-1` if the check was inconclusive
+    score: i32,
+}
+
+#[derive(Deserialize)]
+struct ScorecardResult {
+    score: f64,
+    #[serde(default)]
+    checks: Vec<Check>,
+}
+
+/// Looks up the OpenSSF Scorecard of the source repository of a package.
+///
+/// The repository is taken from Guac (`hasSourceAt`), or the ecosystem metadata known to deps.dev.
+pub struct Scorecard {
+    url: String,
+    threshold: i32,
+    client: reqwest::Client,
+    guac: Arc<Guac>,
+    deps_dev: Option<Arc<DepsDev>>,
+}
+
+impl Scorecard {
+    pub fn new(config: &ScorecardConfig, guac: Arc<Guac>, deps_dev: Option<Arc<DepsDev>>) -> Self {
+        Self {
+            url: config.url.trim_end_matches('/').to_string(),
+            threshold: config.threshold,
+            client: reqwest::Client::new(),
+            guac,
+            deps_dev,
+        }
+    }
+
+    /// The scorecard of a package, `None` if its repository or the scorecard of it is unknown.
+    pub async fn lookup(
+        &self,
+        purl: &PackageUrl<'_>,
+    ) -> Result<Option<ScorecardSummary>, anyhow::Error> {
+        let repository = match self.guac.get_source_repo(purl).await? {
+            Some(repository) => Some(repository),
+            None => match &self.deps_dev {
+                Some(deps_dev) => deps_dev.get_source_repo(purl).await?,
+                None => None,
+            },
+        };
+        let repository = match repository.as_deref().and_then(normalize) {
+            Some(repository) => repository,
+            None => return Ok(None),
+        };
+
+        crate::chaos::inject("scorecard").await?;
+        let response = self
+            .client
+            .get(format!("{}/projects/{}", self.url, repository))
+            .headers(crate::request_id::headers())
+            .send()
+            .await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let result: ScorecardResult = response.error_for_status()?.json().await?;
+
+        Ok(Some(ScorecardSummary {
+            repository,
+            score: result.score,
+            failing_checks: result
+                .checks
+                .into_iter()
+                .filter(|c| c.score >= 0 && c.score < self.threshold)
+                .map(|c| c.name)
+                .collect(),
+        }))
+    }
+}
+
+/// The repository in the form used by Scorecard (e.g. `github.com/owner/repo`).
+fn normalize(url: &str) -> Option<String> {
+    let url = url.trim_start_matches("git+");
+    let url = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let url = url.trim_start_matches("git@").replacen(':', "/", 1);
+    let url = url.trim_end_matches('/').trim_end_matches(".git");
+    let mut parts = url.splitn(4, '/');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(host), Some(owner), Some(repo))
+            if matches!(host, "github.com" | "gitlab.com")
+                && !owner.is_empty()
+                && !repo.is_empty() =>
+        {
+            Some(format!("{}/{}/{}", host, owner, repo))
+        }
+        _ => None,
+    }
+}
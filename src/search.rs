@@ -0,0 +1,467 @@
+use actix_web::{error, get, http::StatusCode, web, web::ServiceConfig, HttpResponse};
+use core::str::FromStr;
+use futures::future::BoxFuture;
+use packageurl::PackageUrl;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+use thiserror::Error;
+use utoipa::{OpenApi, ToSchema};
+
+use crate::guac::Guac;
+use crate::package::TrustedContent;
+use crate::sbom::SbomRegistry;
+use crate::scheduler::Job;
+
+pub(crate) fn configure() -> impl FnOnce(&mut ServiceConfig) {
+    |config: &mut ServiceConfig| {
+        config.service(search);
+    }
+}
+
+/// The documentation of the search endpoint.
+#[derive(OpenApi)]
+#[openapi(paths(search), components(schemas(SearchHit, SearchResults, ApiError)))]
+pub(crate) struct ApiDoc;
+
+/// The largest page of search results.
+pub const MAX_LIMIT: usize = 100;
+
+/// How often the search index is rebuilt from Guac and the SBOM registry.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SearchConfig {
+    /// In seconds, `0` disables it, leaving the index empty
+    pub refresh_secs: u64,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        Self { refresh_secs: 600 }
+    }
+}
+
+/// A package found by a search.
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct SearchHit {
+    pub purl: String,
+    pub href: String,
+    #[serde(rename = "type")]
+    pub ty: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trusted: Option<bool>,
+    /// `None` if the vulnerabilities could not be looked up
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vulnerable: Option<bool>,
+    /// Where the package is known from, `guac` and `sbom`
+    pub sources: Vec<&'static str>,
+}
+
+impl SearchHit {
+    fn new(purl: &PackageUrl<'_>) -> Self {
+        let purl_str = purl.to_string();
+        let purl_str = crate::purl::normalize(&purl_str).unwrap_or(purl_str);
+        Self {
+            href: format!("/api/v1/package?purl={}", &urlencoding::encode(&purl_str)),
+            purl: purl_str,
+            ty: purl.ty().to_string(),
+            namespace: purl.namespace().map(ToString::to_string),
+            name: purl.name().to_string(),
+            version: purl.version().map(ToString::to_string),
+            trusted: None,
+            vulnerable: None,
+            sources: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct SearchResults {
+    /// The number of packages matching the query
+    pub total: usize,
+    pub offset: usize,
+    pub limit: usize,
+    pub results: Vec<SearchHit>,
+}
+
+/// A parsed query: free text matched against package names, and `field:value` filters.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Query {
+    text: Vec<String>,
+    ty: Option<String>,
+    namespace: Option<String>,
+    vulnerable: Option<bool>,
+    trusted: Option<bool>,
+}
+
+impl Query {
+    fn parse(q: &str) -> Result<Self, ApiError> {
+        let invalid = |term: &str| ApiError::InvalidQuery {
+            term: term.to_string(),
+        };
+        let flag = |term: &str, value: &str| value.parse::<bool>().map_err(|_| invalid(term));
+
+        let mut query = Query::default();
+        for term in q.split_whitespace() {
+            match term.split_once(':') {
+                Some(("type", value)) => query.ty = Some(value.to_lowercase()),
+                Some(("namespace", value)) => query.namespace = Some(value.to_lowercase()),
+                Some(("vulnerable", value)) => query.vulnerable = Some(flag(term, value)?),
+                Some(("trusted", value)) => query.trusted = Some(flag(term, value)?),
+                Some(_) => return Err(invalid(term)),
+                None => query.text.push(term.to_lowercase()),
+            }
+        }
+        Ok(query)
+    }
+
+    fn matches(&self, hit: &SearchHit) -> bool {
+        let name = hit.name.to_lowercase();
+        self.text.iter().all(|t| name.contains(t.as_str()))
+            && self.ty.as_ref().map_or(true, |ty| &hit.ty == ty)
+            && self.namespace.as_ref().map_or(true, |namespace| {
+                hit.namespace.as_ref().map(|n| n.to_lowercase()).as_ref() == Some(namespace)
+            })
+            && self
+                .vulnerable
+                .map_or(true, |vulnerable| hit.vulnerable == Some(vulnerable))
+            && self
+                .trusted
+                .map_or(true, |trusted| hit.trusted == Some(trusted))
+    }
+}
+
+/// The order of search results, by `purl`, `name` or `type`, descending with a `-` prefix.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Sort {
+    Purl,
+    Name,
+    Type,
+}
+
+fn parse_sort(sort: &str) -> Result<(Sort, bool), ApiError> {
+    let (field, descending) = match sort.strip_prefix('-') {
+        Some(field) => (field, true),
+        None => (sort, false),
+    };
+    let field = match field {
+        "purl" => Sort::Purl,
+        "name" => Sort::Name,
+        "type" => Sort::Type,
+        _ => {
+            return Err(ApiError::InvalidSort {
+                sort: sort.to_string(),
+            })
+        }
+    };
+    Ok((field, descending))
+}
+
+fn compare(sort: Sort, a: &SearchHit, b: &SearchHit) -> Ordering {
+    match sort {
+        Sort::Purl => Ordering::Equal,
+        Sort::Name => a.name.cmp(&b.name),
+        Sort::Type => a.ty.cmp(&b.ty),
+    }
+    .then_with(|| a.purl.cmp(&b.purl))
+}
+
+/// How many packages only known from SBOMs are looked up at once, rebuilding the index.
+const LOOKUP_CHUNK: usize = 100;
+
+/// An index of the packages known to Guac and the SBOM registry, rebuilt periodically.
+pub struct Search {
+    guac: Arc<Guac>,
+    sboms: Arc<SbomRegistry>,
+    trusted: Arc<TrustedContent>,
+    index: RwLock<Vec<SearchHit>>,
+}
+
+impl Search {
+    pub fn new(guac: Arc<Guac>, sboms: Arc<SbomRegistry>, trusted: Arc<TrustedContent>) -> Self {
+        Self {
+            guac,
+            sboms,
+            trusted,
+            index: RwLock::new(Vec::new()),
+        }
+    }
+
+    /// Rebuild the index, the previous index is kept if Guac can't be queried.
+    ///
+    /// The vulnerabilities of packages only known from SBOMs are looked up, mostly from the cache,
+    /// [`LOOKUP_CHUNK`] packages at once.
+    async fn rebuild(&self) -> Result<usize, anyhow::Error> {
+        let mut hits: BTreeMap<String, SearchHit> = BTreeMap::new();
+        for package in self.guac.get_all_packages().await? {
+            let purl = match package.purl.as_deref().map(PackageUrl::from_str) {
+                Some(Ok(purl)) => purl,
+                _ => continue,
+            };
+            let mut hit = SearchHit::new(&purl);
+            hit.trusted = package.trusted;
+            hit.vulnerable = Some(!package.vulnerabilities.is_empty());
+            hit.sources.push("guac");
+            hits.insert(hit.purl.clone(), hit);
+        }
+
+        let mut sbom_only = Vec::new();
+        for purl_str in self.sboms.purls() {
+            match hits.get_mut(&purl_str) {
+                Some(hit) => hit.sources.push("sbom"),
+                None => {
+                    if PackageUrl::from_str(&purl_str).is_ok() {
+                        sbom_only.push(purl_str);
+                    }
+                }
+            }
+        }
+        for chunk in sbom_only.chunks(LOOKUP_CHUNK) {
+            let purls: Vec<&str> = chunk.iter().map(String::as_str).collect();
+            let found = self.trusted.get_trusted_batch(&purls).await;
+            for (purl_str, found) in purls.into_iter().zip(found) {
+                // parsed above
+                let purl = match PackageUrl::from_str(purl_str) {
+                    Ok(purl) => purl,
+                    Err(_) => continue,
+                };
+                let mut hit = SearchHit::new(&purl);
+                match found {
+                    Ok(package) => {
+                        hit.trusted = package.trusted;
+                        hit.vulnerable = Some(!package.vulnerabilities.is_empty());
+                    }
+                    Err(e) => log::debug!("Unable to index {}: {}", purl_str, e),
+                }
+                hit.sources.push("sbom");
+                hits.insert(hit.purl.clone(), hit);
+            }
+        }
+
+        let len = hits.len();
+        *self.index.write().unwrap() = hits.into_values().collect();
+        Ok(len)
+    }
+
+    /// Search the index, the packages whose SBOMs are all of other tenants than the one of the
+    /// running task are left out.
+    fn search(
+        &self,
+        query: &Query,
+        sort: Sort,
+        descending: bool,
+        offset: usize,
+        limit: usize,
+    ) -> SearchResults {
+        let mut hits = matching(&self.index.read().unwrap(), query, &self.sboms);
+        hits.sort_by(|a, b| {
+            let ordering = compare(sort, a, b);
+            if descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        });
+        SearchResults {
+            total: hits.len(),
+            offset,
+            limit,
+            results: hits.into_iter().skip(offset).take(limit).collect(),
+        }
+    }
+}
+
+impl Job for Search {
+    fn name(&self) -> &'static str {
+        "search index rebuild"
+    }
+
+    fn run(&self) -> BoxFuture<'_, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let len = self.rebuild().await?;
+            log::debug!("Indexed {} packages for search", len);
+            Ok(())
+        })
+    }
+}
+
+/// The hits matching a query, which are visible to the running task.
+fn matching(index: &[SearchHit], query: &Query, sboms: &SbomRegistry) -> Vec<SearchHit> {
+    index
+        .iter()
+        .filter(|hit| query.matches(hit) && !sboms.is_hidden(&hit.purl))
+        .cloned()
+        .collect()
+}
+
+#[derive(serde::Deserialize)]
+pub struct SearchQuery {
+    #[serde(default)]
+    q: String,
+    #[serde(default)]
+    offset: usize,
+    #[serde(default = "default_limit")]
+    limit: usize,
+    #[serde(default = "default_sort")]
+    sort: String,
+}
+
+fn default_limit() -> usize {
+    20
+}
+
+fn default_sort() -> String {
+    "purl".to_string()
+}
+
+/// Search packages by name, narrowed by `type:`, `namespace:`, `vulnerable:` and `trusted:` filters.
+#[utoipa::path(
+    context_path = "/api/v1",
+    responses(
+        (status = 200, description = "A page of the matching packages", body = SearchResults, example = json!({
+            "total": 1,
+            "offset": 0,
+            "limit": 20,
+            "results": [{
+                "purl": "pkg:maven/io.vertx/vertx-web@4.3.7",
+                "href": "/api/v1/package?purl=pkg%3Amaven%2Fio.vertx%2Fvertx-web%404.3.7",
+                "type": "maven",
+                "namespace": "io.vertx",
+                "name": "vertx-web",
+                "version": "4.3.7",
+                "trusted": false,
+                "vulnerable": true,
+                "sources": ["guac"]
+            }]
+        })),
+        (status = BAD_REQUEST, description = "Invalid query, sort or limit", body = Problem, example = json!(crate::problem::example(ApiError::InvalidQuery { term: "license:mit".to_string() }))),
+    ),
+    params(
+        ("q" = Option<String>, Query, description = "Free text and `type:`, `namespace:`, `vulnerable:` and `trusted:` filters"),
+        ("offset" = Option<usize>, Query, description = "The number of matching packages to skip"),
+        ("limit" = Option<usize>, Query, description = "The size of the page, 20 by default and at most 100"),
+        ("sort" = Option<String>, Query, description = "Sort by `purl` (default), `name` or `type`, descending when prefixed with `-`"),
+    )
+)]
+#[get("/search")]
+pub async fn search(
+    search: web::Data<Arc<Search>>,
+    query: web::Query<SearchQuery>,
+) -> Result<HttpResponse, ApiError> {
+    if !(1..=MAX_LIMIT).contains(&query.limit) {
+        return Err(ApiError::InvalidLimit { limit: query.limit });
+    }
+    let (sort, descending) = parse_sort(&query.sort)?;
+    let parsed = Query::parse(&query.q)?;
+    Ok(
+        HttpResponse::Ok().json(search.search(
+            &parsed,
+            sort,
+            descending,
+            query.offset,
+            query.limit,
+        )),
+    )
+}
+
+#[derive(Debug, Error, Serialize, Deserialize, ToSchema)]
+#[schema(as = SearchApiError)]
+pub enum ApiError {
+    #[error("Unsupported search term {term}, filters are type, namespace, vulnerable and trusted")]
+    InvalidQuery { term: String },
+    #[error("Unsupported sort {sort}, sort by purl, name or type")]
+    InvalidSort { sort: String },
+    #[error("The limit must be between 1 and {MAX_LIMIT}, not {limit}")]
+    InvalidLimit { limit: usize },
+}
+
+impl error::ResponseError for ApiError {
+    fn error_response(&self) -> HttpResponse {
+        crate::problem::response(self.status_code(), self, serde_json::json!({}))
+    }
+
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::InvalidQuery { term: _ } => StatusCode::BAD_REQUEST,
+            ApiError::InvalidSort { sort: _ } => StatusCode::BAD_REQUEST,
+            ApiError::InvalidLimit { limit: _ } => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PURL: &str = "pkg:maven/org.example/tenant-only@1.0";
+
+    async fn registry() -> SbomRegistry {
+        let sboms = SbomRegistry::for_tests().await;
+        sboms.replicate(
+            vec![(
+                PURL.to_string(),
+                Some("a".to_string()),
+                serde_json::json!({}),
+            )],
+            &[],
+        );
+        sboms
+    }
+
+    fn index() -> Vec<SearchHit> {
+        [PURL, "pkg:maven/org.example/global@1.0"]
+            .into_iter()
+            .map(|purl| SearchHit::new(&PackageUrl::from_str(purl).unwrap()))
+            .collect()
+    }
+
+    async fn found(sboms: &SbomRegistry, tenant: Option<Option<&str>>) -> Vec<String> {
+        let matched = || {
+            matching(&index(), &Query::default(), sboms)
+                .into_iter()
+                .map(|hit| hit.name)
+                .collect::<Vec<_>>()
+        };
+        match tenant {
+            Some(tenant) => {
+                crate::tenant::scoped(tenant.map(ToString::to_string), async { matched() }).await
+            }
+            None => matched(),
+        }
+    }
+
+    #[tokio::test]
+    async fn packages_of_other_tenants_are_not_found() {
+        let sboms = registry().await;
+        assert_eq!(
+            found(&sboms, Some(Some("a"))).await,
+            ["tenant-only", "global"]
+        );
+        assert_eq!(found(&sboms, Some(Some("b"))).await, ["global"]);
+        assert_eq!(found(&sboms, Some(None)).await, ["global"]);
+        // background tasks see all packages
+        assert_eq!(found(&sboms, None).await, ["tenant-only", "global"]);
+    }
+
+    #[tokio::test]
+    async fn sboms_of_other_tenants_are_hidden() {
+        let sboms = registry().await;
+        crate::tenant::scoped(Some("b".to_string()), async {
+            assert!(sboms.is_hidden(PURL));
+            assert!(sboms.lookup(PURL).is_none());
+            assert!(!sboms.purls().iter().any(|p| p.contains("tenant-only")));
+        })
+        .await;
+        crate::tenant::scoped(Some("a".to_string()), async {
+            assert!(!sboms.is_hidden(PURL));
+            assert!(sboms.lookup(PURL).is_some());
+        })
+        .await;
+    }
+}
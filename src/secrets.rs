@@ -0,0 +1,231 @@
+//! Secrets persisted by the service, e.g. a Snyk token put in place through the admin API,
+//! encrypted at rest with the keys of a key file.
+//!
+//! The key file holds one key per line, `<id>:<base64 encoded 32 bytes>`, the first one
+//! encrypting new secrets. To rotate, add a new key at the top, run `trust-api secrets rotate`
+//! to encrypt the stored secrets again, then remove the old key.
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use anyhow::{anyhow, bail};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine as _;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::storage::Storage;
+
+/// The storage keys of secrets start with this.
+const PREFIX: &str = "secrets/";
+
+/// The storage key of the Snyk token.
+pub const SNYK_TOKEN: &str = "snyk/token";
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SecretsConfig {
+    /// The keys encrypting stored secrets, secrets are not stored without
+    pub key_file: Option<PathBuf>,
+}
+
+/// The keys of a key file, the first one encrypting.
+pub struct Keyring {
+    keys: Vec<(String, Aes256Gcm)>,
+}
+
+impl Keyring {
+    pub fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Error reading key file {}: {}", path.display(), e))?;
+        Self::parse(&content)
+    }
+
+    fn parse(content: &str) -> Result<Self, anyhow::Error> {
+        let mut keys = Vec::new();
+        for line in content
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        {
+            let (id, key) = line
+                .split_once(':')
+                .filter(|(id, _)| !id.is_empty())
+                .ok_or_else(|| anyhow!("Keys must be in the format <id>:<base64 key>"))?;
+            let key = BASE64.decode(key.trim())?;
+            if key.len() != 32 {
+                bail!("Key {} must be 32 bytes long, not {}", id, key.len());
+            }
+            let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
+            keys.push((id.to_string(), cipher));
+        }
+        if keys.is_empty() {
+            bail!("The key file contains no keys");
+        }
+        Ok(Self { keys })
+    }
+
+    /// A line for the key file, with a new random key.
+    pub fn generate(id: &str) -> String {
+        let key: [u8; 32] = rand::random();
+        format!("{}:{}", id, BASE64.encode(key))
+    }
+
+    fn seal(&self, plaintext: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+        let (id, cipher) = &self.keys[0];
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let data = cipher
+            .encrypt(&nonce, plaintext)
+            .map_err(|_| anyhow!("Error encrypting a secret"))?;
+        Ok(serde_json::to_vec(&Sealed {
+            key: id.clone(),
+            nonce: BASE64.encode(nonce),
+            data: BASE64.encode(data),
+        })?)
+    }
+
+    /// Decrypt a sealed secret, and whether it was sealed with an older key.
+    fn open(&self, sealed: &[u8]) -> Result<(Vec<u8>, bool), anyhow::Error> {
+        let sealed: Sealed = serde_json::from_slice(sealed)?;
+        let (index, (_, cipher)) = self
+            .keys
+            .iter()
+            .enumerate()
+            .find(|(_, (id, _))| *id == sealed.key)
+            .ok_or_else(|| anyhow!("The key {} is not in the key file", sealed.key))?;
+        let nonce = BASE64.decode(sealed.nonce)?;
+        if nonce.len() != 12 {
+            bail!("Invalid nonce of a secret");
+        }
+        let data = cipher
+            .decrypt(
+                Nonce::from_slice(&nonce),
+                BASE64.decode(sealed.data)?.as_slice(),
+            )
+            .map_err(|_| anyhow!("Error decrypting a secret with key {}", sealed.key))?;
+        Ok((data, index > 0))
+    }
+}
+
+/// A secret as stored, encrypted with the key `key`.
+#[derive(Serialize, Deserialize)]
+struct Sealed {
+    key: String,
+    nonce: String,
+    data: String,
+}
+
+/// Secrets, encrypted in the configured storage.
+pub struct Secrets {
+    storage: Arc<dyn Storage>,
+    keys: Option<Keyring>,
+}
+
+impl Secrets {
+    pub fn new(config: &SecretsConfig, storage: Arc<dyn Storage>) -> Result<Self, anyhow::Error> {
+        let keys = config.key_file.as_deref().map(Keyring::load).transpose()?;
+        Ok(Self { storage, keys })
+    }
+
+    /// Whether secrets can be stored, they are only kept in memory otherwise.
+    pub fn is_enabled(&self) -> bool {
+        self.keys.is_some()
+    }
+
+    /// A stored secret, `None` if none is stored or no key file is configured.
+    pub async fn get(&self, name: &str) -> Result<Option<String>, anyhow::Error> {
+        let keys = match &self.keys {
+            Some(keys) => keys,
+            None => return Ok(None),
+        };
+        match self.storage.get(&format!("{}{}", PREFIX, name)).await? {
+            Some(sealed) => Ok(Some(String::from_utf8(keys.open(&sealed)?.0)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn put(&self, name: &str, value: &str) -> Result<(), anyhow::Error> {
+        let keys = self
+            .keys
+            .as_ref()
+            .ok_or_else(|| anyhow!("Storing secrets requires secrets.key_file"))?;
+        self.storage
+            .put(&format!("{}{}", PREFIX, name), keys.seal(value.as_bytes())?)
+            .await
+    }
+
+    /// Encrypt the secrets sealed with older keys with the first key, returning how many were.
+    pub async fn rotate(&self) -> Result<usize, anyhow::Error> {
+        let keys = self
+            .keys
+            .as_ref()
+            .ok_or_else(|| anyhow!("Rotating secrets requires secrets.key_file"))?;
+        let mut rotated = 0;
+        for key in self.storage.list(PREFIX).await? {
+            let sealed = match self.storage.get(&key).await? {
+                Some(sealed) => sealed,
+                None => continue,
+            };
+            let (plaintext, outdated) = keys.open(&sealed)?;
+            if outdated {
+                self.storage.put(&key, keys.seal(&plaintext)?).await?;
+                rotated += 1;
+            }
+        }
+        Ok(rotated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::FileStorage;
+
+    fn keyring(lines: &[String]) -> Keyring {
+        Keyring::parse(&lines.join("\n")).unwrap()
+    }
+
+    #[test]
+    fn secrets_are_opened_with_the_key_they_were_sealed_with() {
+        let old = Keyring::generate("old");
+        let sealed = keyring(&[old.clone()]).seal(b"token").unwrap();
+        assert!(!String::from_utf8_lossy(&sealed).contains("token"));
+
+        let rotated = keyring(&[Keyring::generate("new"), old]);
+        assert_eq!(rotated.open(&sealed).unwrap(), (b"token".to_vec(), true));
+        assert!(keyring(&[Keyring::generate("other")])
+            .open(&sealed)
+            .is_err());
+    }
+
+    #[test]
+    fn invalid_keys_are_rejected() {
+        assert!(Keyring::parse("").is_err());
+        assert!(Keyring::parse("key").is_err());
+        assert!(Keyring::parse(&format!("key:{}", BASE64.encode([0u8; 16]))).is_err());
+    }
+
+    #[tokio::test]
+    async fn rotation_seals_secrets_with_the_first_key() {
+        let dir = std::env::temp_dir().join(format!("trust-api-secrets-{}", uuid::Uuid::new_v4()));
+        let storage: Arc<dyn Storage> = Arc::new(FileStorage::new(dir.clone()));
+        let old = Keyring::generate("old");
+        let secrets = Secrets {
+            storage: storage.clone(),
+            keys: Some(keyring(&[old.clone()])),
+        };
+        secrets.put(SNYK_TOKEN, "token").await.unwrap();
+
+        let secrets = Secrets {
+            storage,
+            keys: Some(keyring(&[Keyring::generate("new"), old])),
+        };
+        assert_eq!(secrets.rotate().await.unwrap(), 1);
+        assert_eq!(secrets.rotate().await.unwrap(), 0);
+        assert_eq!(
+            secrets.get(SNYK_TOKEN).await.unwrap().as_deref(),
+            Some("token")
+        );
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
@@ -1,86 +1,281 @@
 use actix_cors::Cors;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceFactory, ServiceRequest, ServiceResponse};
+use actix_web::middleware::{Compress, Condition};
 use actix_web::web::Data;
-use actix_web::{middleware::Logger, App, HttpServer};
+use actix_web::{App, HttpServer};
 use std::sync::Arc;
-use utoipa::OpenApi;
+use std::time::Duration;
+use utoipa::openapi::OpenApi as Document;
 use utoipa_swagger_ui::SwaggerUi;
 
-use crate::guac;
-use crate::index;
-use crate::package;
-use crate::sbom::SbomRegistry;
-use crate::vulnerability;
-use crate::Snyk;
+use crate::access_log::{self, LogSampling};
+use crate::admin;
+use crate::audit;
+use crate::auth::{self, Authenticator};
+use crate::cdn;
+use crate::config::{Config, CorsConfig};
+use crate::cost;
+use crate::engine::Engine;
+use crate::fresh::{self, FreshPolicy};
+use crate::grpc::GrpcService;
+use crate::health::{self, Health};
+use crate::openapi;
+use crate::rbac;
+use crate::request_id;
+use crate::stats;
+use crate::tls;
 
-pub struct Server {
-    bind: String,
-    port: u16,
-    guac_url: String,
-    snyk: Snyk,
+fn cors(config: &CorsConfig) -> Cors {
+    let any = |values: &Vec<String>| values.iter().any(|v| v == "*");
+    let mut cors = Cors::default().max_age(config.max_age);
+
+    if any(&config.allowed_origins) {
+        cors = cors.send_wildcard().allow_any_origin();
+    } else {
+        for origin in &config.allowed_origins {
+            cors = cors.allowed_origin(origin);
+        }
+    }
+
+    cors = if any(&config.allowed_methods) {
+        cors.allow_any_method()
+    } else {
+        cors.allowed_methods(config.allowed_methods.iter().map(String::as_str))
+    };
+
+    if any(&config.allowed_headers) {
+        cors.allow_any_header()
+    } else {
+        cors.allowed_headers(config.allowed_headers.iter().map(String::as_str))
+    }
 }
 
-#[derive(OpenApi)]
-#[openapi(
-        paths(
-            package::get_package,
-            package::query_package,
-            package::query_package_dependencies,
-            package::query_package_dependents,
-            package::query_package_versions,
-            vulnerability::query_vulnerability,
-        ),
-        components(
-            schemas(package::Package, package::PackageList, package::PackageDependencies, package::PackageDependents, package::PackageRef, package::SnykData, package::VulnerabilityRef, vulnerability::Vulnerability)
-        ),
-        tags(
-            (name = "package", description = "Package query endpoints."),
-            (name = "vulnerability", description = "Vulnerability query endpoints")
-        ),
-    )]
-pub struct ApiDoc;
+/// The applications of the public and the admin listener with all their middleware, serving the
+/// endpoints of one engine.
+///
+/// Each worker of a listener builds its application from a clone.
+#[derive(Clone)]
+pub struct Apps {
+    config: Arc<Config>,
+    engine: Engine,
+    openapi: Document,
+    health: Arc<Health>,
+    sampling: Arc<LogSampling>,
+    authenticator: Arc<Authenticator>,
+    fresh: Arc<FreshPolicy>,
+}
 
-impl Server {
-    pub fn new(bind: String, port: u16, guac_url: String, snyk: Snyk) -> Self {
+impl Apps {
+    pub fn new(config: &Config, engine: Engine) -> Self {
         Self {
-            bind,
-            port,
-            guac_url,
-            snyk,
+            config: Arc::new(config.clone()),
+            engine,
+            openapi: openapi::document(config),
+            health: Arc::new(Health::new()),
+            sampling: Arc::new(LogSampling::new(config.logging.sampling.clone())),
+            authenticator: Arc::new(Authenticator::new(&config.auth)),
+            fresh: Arc::new(FreshPolicy::new(config.api.fresh.clone())),
         }
     }
 
+    /// The health of the service, served by the public application.
+    pub fn health(&self) -> Arc<Health> {
+        self.health.clone()
+    }
+
+    /// Validates the ID tokens of requests, once [`Authenticator::run`] fetched the keys.
+    pub fn authenticator(&self) -> Arc<Authenticator> {
+        self.authenticator.clone()
+    }
+
+    /// The REST API, the health probes and the OpenAPI document.
+    pub fn public(
+        &self,
+    ) -> App<
+        impl ServiceFactory<
+            ServiceRequest,
+            Config = (),
+            Response = ServiceResponse<impl MessageBody>,
+            Error = actix_web::Error,
+            InitError = (),
+        >,
+    > {
+        let config = &self.config;
+        let rbac = config.auth.rbac.enabled;
+        let sampling = self.sampling.clone();
+        let fresh = self.fresh.clone();
+        let authenticator = self.authenticator.clone();
+        let cdn = self.engine.cdn.clone();
+        let audit = self.engine.audit.clone();
+
+        App::new()
+            .wrap(Condition::new(
+                config.api.compression.enabled,
+                Compress::default(),
+            ))
+            .wrap_fn(move |req, srv| cdn::middleware(req, srv, cdn.clone()))
+            .wrap_fn(move |req, srv| rbac::middleware(req, srv, rbac))
+            .wrap_fn(move |req, srv| audit::middleware(req, srv, audit.clone()))
+            .wrap_fn(move |req, srv| auth::middleware(req, srv, &authenticator))
+            .wrap_fn(move |req, srv| fresh::middleware(req, srv, &fresh))
+            .wrap_fn(|req, srv| cost::middleware(req, srv))
+            // outside of authentication, so rejected and preflight requests are logged and carry
+            // the CORS headers
+            .wrap(cors(&config.cors))
+            .wrap_fn(move |req, srv| access_log::middleware(req, srv, sampling.clone()))
+            .wrap_fn(|req, srv| request_id::middleware(req, srv))
+            .app_data(Data::new(self.health.clone()))
+            .app_data(Data::new(config.api.docs.clone()))
+            .configure(health::configure())
+            .service(
+                SwaggerUi::new(format!("{}{{_:.*}}", config.api.docs.ui_path()))
+                    .url(config.api.docs.document_path(), self.openapi.clone())
+                    .config(openapi::swagger_config(config)),
+            )
+            .service(self.engine.scope(""))
+    }
+
+    /// The admin API, requiring the admin token, or an ID token with the admin role if RBAC is
+    /// enabled.
+    pub fn admin(
+        &self,
+    ) -> App<
+        impl ServiceFactory<
+            ServiceRequest,
+            Config = (),
+            Response = ServiceResponse<impl MessageBody>,
+            Error = actix_web::Error,
+            InitError = (),
+        >,
+    > {
+        let token = self.config.admin.token.clone();
+        let authenticator = self.admin_authenticator();
+        let sampling = self.sampling.clone();
+        let engine = &self.engine;
+        App::new()
+            .wrap_fn(move |req, srv| {
+                admin::middleware(req, srv, token.as_deref(), authenticator.as_deref())
+            })
+            .wrap_fn(move |req, srv| access_log::middleware(req, srv, sampling.clone()))
+            .app_data(Data::new(engine.cache.clone()))
+            .app_data(Data::new(engine.webhooks.clone()))
+            .app_data(Data::new(engine.sboms.clone()))
+            .app_data(Data::new(self.sampling.clone()))
+            .app_data(Data::new(engine.subgraphs.clone()))
+            .app_data(Data::new(engine.ingestion.clone()))
+            .app_data(Data::new(engine.cdn.clone()))
+            .app_data(Data::new(engine.journal.clone()))
+            .app_data(Data::new(engine.trusted.snyk()))
+            .app_data(Data::new(engine.secrets.clone()))
+            .app_data(Data::new(engine.audit.clone()))
+            .app_data(Data::new(engine.stats.clone()))
+            .configure(admin::configure())
+            .configure(stats::configure())
+    }
+
+    /// ID tokens are only accepted by role if RBAC is enabled.
+    fn admin_authenticator(&self) -> Option<Arc<Authenticator>> {
+        (self.authenticator.is_enabled() && self.config.auth.rbac.enabled)
+            .then(|| self.authenticator.clone())
+    }
+}
+
+pub struct Server {
+    config: Config,
+}
+
+impl Server {
+    pub fn new(config: Config) -> Self {
+        Self { config }
+    }
+
     pub async fn run(self) -> anyhow::Result<()> {
-        let openapi = ApiDoc::openapi();
-
-        let sboms = Arc::new(SbomRegistry::new());
-        let guac = Arc::new(guac::Guac::new(&self.guac_url, sboms.clone()));
-
-        HttpServer::new(move || {
-            let cors = Cors::default()
-                .send_wildcard()
-                .allow_any_origin()
-                .allow_any_method()
-                .allow_any_header()
-                .max_age(3600);
-
-            App::new()
-                .wrap(Logger::default())
-                .wrap(cors)
-                .app_data(Data::new(sboms.clone()))
-                .app_data(Data::new(package::TrustedContent::new(
-                    guac.clone(),
-                    sboms.clone(),
-                    self.snyk.clone(),
-                )))
-                .app_data(Data::new(guac.clone()))
-                .configure(package::configure())
-                .configure(vulnerability::configure())
-                .configure(index::configure())
-                .service(SwaggerUi::new("/swagger-ui/{_:.*}").url("/openapi.json", openapi.clone()))
-        })
-        .bind((self.bind, self.port))?
-        .run()
-        .await?;
+        let config = self.config;
+
+        let engine = Engine::builder(config.clone()).build().await?;
+        let apps = Apps::new(&config, engine.clone());
+
+        let health = apps.health();
+        let shutdown_timeout = config.shutdown.timeout_secs;
+
+        let authenticator = apps.authenticator();
+        if authenticator.is_enabled() {
+            tokio::spawn(authenticator.run(Duration::from_secs(config.auth.refresh_secs)));
+        }
+
+        if config.admin.token.is_none() && apps.admin_authenticator().is_none() {
+            if !config.admin.allow_unauthenticated {
+                anyhow::bail!(
+                    "The admin listener requires admin.token or RBAC, set admin.allow_unauthenticated to serve it without authentication"
+                );
+            }
+            log::warn!(
+                "No admin token configured, the admin listener does not require authentication"
+            );
+        }
+        let admin_apps = apps.clone();
+        let admin = HttpServer::new(move || admin_apps.admin())
+            .disable_signals()
+            .shutdown_timeout(shutdown_timeout)
+            .bind((config.admin.bind, config.admin.port))?
+            .run();
+
+        let grpc = {
+            let service = GrpcService::new(
+                engine.trusted(),
+                engine.sboms(),
+                apps.authenticator(),
+                config.auth.rbac.enabled,
+            );
+            let grpc = config.grpc.clone();
+            let health = health.clone();
+            async move {
+                if !grpc.enabled {
+                    return Ok(());
+                }
+                let addr = tokio::net::lookup_host((grpc.bind.as_str(), grpc.port))
+                    .await?
+                    .next()
+                    .ok_or_else(|| {
+                        std::io::Error::new(
+                            std::io::ErrorKind::AddrNotAvailable,
+                            format!("Unable to resolve {}", grpc.bind),
+                        )
+                    })?;
+                log::info!("Serving gRPC on {}", addr);
+                tonic::transport::Server::builder()
+                    .add_service(service)
+                    .serve_with_shutdown(addr, health.stopping())
+                    .await
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))
+            }
+        };
+
+        let shutdown = config.shutdown.clone();
+        let public = HttpServer::new(move || apps.public())
+            .disable_signals()
+            .shutdown_timeout(shutdown_timeout);
+
+        let public = match &config.tls {
+            Some(tls) => {
+                let (server_config, reloader) = tls::server_config(tls)?;
+                tls::reload_on_change(reloader)?;
+                log::info!("Serving HTTPS on {}:{}", config.bind, config.port);
+                public.bind_rustls((config.bind, config.port), server_config)?
+            }
+            None => public.bind((config.bind, config.port))?,
+        }
+        .run();
+
+        let servers = vec![public.handle(), admin.handle()];
+        tokio::spawn(async move {
+            if let Err(e) = health::drain_on_signal(health, shutdown, servers).await {
+                log::warn!("Error installing shutdown signal handlers: {:?}", e);
+            }
+        });
+
+        futures::try_join!(public, admin, grpc)?;
         Ok(())
     }
 }
@@ -0,0 +1,111 @@
+use anyhow::anyhow;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::str::FromStr;
+
+pub const SIGNATURE_HEADER: &str = "X-Trust-Signature";
+pub const TIMESTAMP_HEADER: &str = "X-Trust-Signature-Timestamp";
+
+/// A named HMAC key, configured as `<id>:<secret>`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct SigningKey {
+    pub id: String,
+    secret: String,
+}
+
+impl SigningKey {
+    pub fn redacted(&self, replacement: &str) -> Self {
+        Self {
+            id: self.id.clone(),
+            secret: replacement.to_string(),
+        }
+    }
+}
+
+impl TryFrom<String> for SigningKey {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<SigningKey> for String {
+    fn from(key: SigningKey) -> Self {
+        format!("{}:{}", key.id, key.secret)
+    }
+}
+
+impl FromStr for SigningKey {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some((id, secret)) if !id.is_empty() && !secret.is_empty() => Ok(Self {
+                id: id.to_string(),
+                secret: secret.to_string(),
+            }),
+            _ => Err(anyhow!("Signing keys must be in the format <id>:<secret>")),
+        }
+    }
+}
+
+/// Signs outgoing payloads (webhooks, exports) with HMAC-SHA256.
+///
+/// The payload is signed with every configured key, which allows rotating keys: add the new key,
+/// let receivers pick it up, then remove the old one.
+#[derive(Clone, Debug, Default)]
+pub struct Signer {
+    keys: Vec<SigningKey>,
+}
+
+/// The headers to attach to a signed payload.
+pub struct Signature {
+    pub timestamp: String,
+    pub signature: String,
+}
+
+impl Signer {
+    pub fn new(keys: Vec<SigningKey>) -> Self {
+        Self { keys }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.keys.is_empty()
+    }
+
+    /// Sign a payload, returning `None` if no signing keys are configured.
+    ///
+    /// The signed message is `<timestamp>.<payload>`, the signature header value is a comma
+    /// separated list of `<key id>=<hex encoded hmac>` entries.
+    pub fn sign(&self, payload: &[u8]) -> Option<Signature> {
+        if !self.is_enabled() {
+            return None;
+        }
+
+        let timestamp = chrono::Utc::now().timestamp().to_string();
+        let signature = self
+            .keys
+            .iter()
+            .filter_map(|key| {
+                let mut mac = Hmac::<Sha256>::new_from_slice(key.secret.as_bytes()).ok()?;
+                mac.update(timestamp.as_bytes());
+                mac.update(b".");
+                mac.update(payload);
+                Some(format!(
+                    "{}={}",
+                    key.id,
+                    hex::encode(mac.finalize().into_bytes())
+                ))
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+
+        Some(Signature {
+            timestamp,
+            signature,
+        })
+    }
+}
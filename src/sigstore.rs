@@ -0,0 +1,287 @@
+use anyhow::{anyhow, bail};
+use base64::Engine;
+use p256::ecdsa::signature::hazmat::PrehashVerifier;
+use p256::ecdsa::{Signature, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use x509_parser::certificate::X509Certificate;
+use x509_parser::extensions::GeneralName;
+use x509_parser::parse_x509_certificate;
+use x509_parser::pem::Pem;
+use x509_parser::time::ASN1Time;
+
+/// Where to look up signatures of artifacts.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SigstoreConfig {
+    pub enabled: bool,
+    pub rekor_url: String,
+    /// PEM encoded Fulcio root and intermediate certificates, signatures are not verified without
+    pub fulcio_roots: Option<PathBuf>,
+}
+
+impl Default for SigstoreConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            rekor_url: "https://rekor.sigstore.dev".to_string(),
+            fulcio_roots: None,
+        }
+    }
+}
+
+/// The result of checking the signatures of an artifact in the Rekor transparency log.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Verification {
+    /// `false` if no entry verifies, `None` if the entry could not be verified, e.g. without
+    /// Fulcio roots
+    pub verified: Option<bool>,
+    /// The identity of the signer, from the Fulcio certificate, only if verified
+    pub signer: Option<String>,
+}
+
+impl Verification {
+    const UNVERIFIED: Self = Self {
+        verified: Some(false),
+        signer: None,
+    };
+    const UNKNOWN: Self = Self {
+        verified: None,
+        signer: None,
+    };
+}
+
+#[derive(Deserialize)]
+struct LogEntry {
+    body: String,
+    #[serde(rename = "integratedTime")]
+    integrated_time: i64,
+}
+
+/// Checks Rekor for signatures of artifacts, by their digest.
+///
+/// Entries are verified locally: the signature of a `hashedrekord` entry must match the digest
+/// of the artifact, and its certificate must chain up to the configured Fulcio roots and be valid
+/// when the entry was logged. Other kinds of entries are reported as unknown.
+pub struct Sigstore {
+    url: String,
+    client: reqwest::Client,
+    /// The Fulcio root and intermediate certificates, DER encoded
+    roots: Vec<Vec<u8>>,
+}
+
+impl Sigstore {
+    pub fn new(config: &SigstoreConfig) -> Result<Self, anyhow::Error> {
+        let roots = match &config.fulcio_roots {
+            Some(path) => {
+                let pem = std::fs::read(path)
+                    .map_err(|e| anyhow!("Error reading Fulcio roots {}: {}", path.display(), e))?;
+                let roots = Pem::iter_from_buffer(&pem)
+                    .map(|pem| pem.map(|pem| pem.contents))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if roots.is_empty() {
+                    bail!("No certificates in {}", path.display());
+                }
+                roots
+            }
+            None => {
+                log::warn!("No Fulcio roots configured, signatures are reported as unverified");
+                Vec::new()
+            }
+        };
+        Ok(Self {
+            url: config.rekor_url.trim_end_matches('/').to_string(),
+            client: reqwest::Client::new(),
+            roots,
+        })
+    }
+
+    /// Look up and verify the signatures of an artifact, by its SHA-256 digest (hex encoded).
+    pub async fn verify(&self, digest: &str) -> Result<Verification, anyhow::Error> {
+        crate::chaos::inject("rekor").await?;
+        let uuids: Vec<String> = self
+            .client
+            .post(format!("{}/api/v1/index/retrieve", self.url))
+            .headers(crate::request_id::headers())
+            .json(&serde_json::json!({ "hash": format!("sha256:{}", digest) }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        let uuid = match uuids.first() {
+            Some(uuid) => uuid,
+            None => return Ok(Verification::UNVERIFIED),
+        };
+        if self.roots.is_empty() {
+            return Ok(Verification::UNKNOWN);
+        }
+
+        let entries: HashMap<String, LogEntry> = self
+            .client
+            .get(format!("{}/api/v1/log/entries/{}", self.url, uuid))
+            .headers(crate::request_id::headers())
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let entry = entries
+            .into_values()
+            .next()
+            .ok_or_else(|| anyhow!("Rekor entry {} not found", uuid))?;
+
+        let body: serde_json::Value = serde_json::from_slice(
+            &base64::engine::general_purpose::STANDARD.decode(&entry.body)?,
+        )?;
+        Ok(self.verify_entry(&body, entry.integrated_time, digest))
+    }
+
+    fn verify_entry(
+        &self,
+        body: &serde_json::Value,
+        integrated: i64,
+        digest: &str,
+    ) -> Verification {
+        if body["kind"].as_str() != Some("hashedrekord") {
+            return Verification::UNKNOWN;
+        }
+        match verify_hashedrekord(body, integrated, digest, &self.roots) {
+            Ok(signer) => Verification {
+                verified: Some(true),
+                signer,
+            },
+            Err(e) => {
+                log::info!("Signature of {} does not verify: {}", digest, e);
+                Verification::UNVERIFIED
+            }
+        }
+    }
+}
+
+/// Verify a `hashedrekord` entry, returning the identity of its certificate.
+fn verify_hashedrekord(
+    body: &serde_json::Value,
+    integrated: i64,
+    digest: &str,
+    roots: &[Vec<u8>],
+) -> Result<Option<String>, anyhow::Error> {
+    let base64 = base64::engine::general_purpose::STANDARD;
+    let hash = &body["spec"]["data"]["hash"];
+    if hash["algorithm"].as_str() != Some("sha256")
+        || !hash["value"]
+            .as_str()
+            .map_or(false, |v| v.eq_ignore_ascii_case(digest))
+    {
+        bail!("the entry is not about the digest");
+    }
+
+    let pem = base64.decode(
+        body.pointer("/spec/signature/publicKey/content")
+            .and_then(|c| c.as_str())
+            .ok_or_else(|| anyhow!("no certificate"))?,
+    )?;
+    let (_, pem) = x509_parser::pem::parse_x509_pem(&pem)?;
+    let certificate = pem.parse_x509()?;
+
+    let time = ASN1Time::from_timestamp(integrated)?;
+    if !certificate.validity().is_valid_at(time) {
+        bail!("the certificate was not valid when the entry was logged");
+    }
+    verify_chain(&certificate, roots)?;
+
+    let signature = base64.decode(
+        body.pointer("/spec/signature/content")
+            .and_then(|c| c.as_str())
+            .ok_or_else(|| anyhow!("no signature"))?,
+    )?;
+    let key = VerifyingKey::from_sec1_bytes(&certificate.public_key().subject_public_key.data)?;
+    key.verify_prehash(&hex::decode(digest)?, &Signature::from_der(&signature)?)?;
+
+    Ok(signer_of(&certificate))
+}
+
+/// Check that a certificate is issued by the roots, through their intermediates.
+fn verify_chain(certificate: &X509Certificate<'_>, roots: &[Vec<u8>]) -> Result<(), anyhow::Error> {
+    let roots = roots
+        .iter()
+        .map(|der| parse_x509_certificate(der).map(|(_, c)| c))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut current = certificate;
+    // bounded by the number of roots, a chain doesn't use a certificate twice
+    for _ in 0..=roots.len() {
+        let issuer = roots
+            .iter()
+            .find(|root| {
+                root.subject() == current.issuer()
+                    && current.verify_signature(Some(root.public_key())).is_ok()
+            })
+            .ok_or_else(|| anyhow!("the certificate is not issued by the Fulcio roots"))?;
+        if issuer.subject() == issuer.issuer() {
+            return Ok(());
+        }
+        current = issuer;
+    }
+    bail!("the certificate chain is too long")
+}
+
+/// The identity from the subject alternative names of a Fulcio certificate.
+fn signer_of(certificate: &X509Certificate<'_>) -> Option<String> {
+    let names = certificate.subject_alternative_name().ok()??;
+    names
+        .value
+        .general_names
+        .iter()
+        .find_map(|name| match name {
+            GeneralName::RFC822Name(name) | GeneralName::URI(name) => Some(name.to_string()),
+            _ => None,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sigstore() -> Sigstore {
+        Sigstore {
+            url: String::new(),
+            client: reqwest::Client::new(),
+            roots: vec![Vec::new()],
+        }
+    }
+
+    #[test]
+    fn entries_about_other_digests_do_not_verify() {
+        let body = serde_json::json!({
+            "kind": "hashedrekord",
+            "spec": { "data": { "hash": { "algorithm": "sha256", "value": "aa" } } },
+        });
+        assert_eq!(
+            sigstore().verify_entry(&body, 0, "bb"),
+            Verification::UNVERIFIED
+        );
+    }
+
+    #[test]
+    fn entries_without_certificates_do_not_verify() {
+        let body = serde_json::json!({
+            "kind": "hashedrekord",
+            "spec": { "data": { "hash": { "algorithm": "sha256", "value": "aa" } } },
+        });
+        assert_eq!(
+            sigstore().verify_entry(&body, 0, "AA"),
+            Verification::UNVERIFIED
+        );
+    }
+
+    #[test]
+    fn other_entries_are_unknown() {
+        let body = serde_json::json!({ "kind": "intoto" });
+        assert_eq!(
+            sigstore().verify_entry(&body, 0, "aa"),
+            Verification::UNKNOWN
+        );
+    }
+}
@@ -1,54 +1,233 @@
-use snyk::apis::configuration::{self, ApiKey};
-
-use crate::{
-    package::{ApiError, VulnerabilityRef},
-    Snyk,
-};
-
-pub async fn get_vulnerabilities(
-    snyk: Snyk,
-    purl: &str,
-) -> Result<Vec<VulnerabilityRef>, anyhow::Error> {
-    let mut ret = Vec::new();
-    if snyk.org.is_some() && snyk.token.is_some() {
-        let key = ApiKey {
-            prefix: Some("token".to_string()),
-            key: snyk
+use anyhow::{anyhow, bail};
+use chrono::Utc;
+use reqwest::header::{HeaderValue, AUTHORIZATION, RETRY_AFTER};
+use reqwest::StatusCode;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use crate::config::{ResilienceConfig, Snyk};
+use crate::metrics;
+use crate::package::{ProviderId, Severity, VulnerabilityRef, VulnerabilitySource};
+use crate::resilience::Resilience;
+
+const API_VERSION: &str = "2023-02-15";
+
+/// Waits longer than this are not worth holding a request for, the call fails instead.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(30);
+
+/// The CVE and GHSA ids of a Snyk issue, and its severity.
+fn details(issue: &serde_json::Value) -> (Vec<String>, Option<Severity>) {
+    let attributes = &issue["attributes"];
+    let aliases = attributes["problems"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|p| p["id"].as_str())
+        .filter(|id| {
+            let id = id.to_uppercase();
+            id.starts_with("CVE-") || id.starts_with("GHSA-")
+        })
+        .map(ToString::to_string)
+        .collect();
+    let severity = attributes["effective_severity_level"]
+        .as_str()
+        .and_then(Severity::parse);
+    (aliases, severity)
+}
+
+/// How long a rate limited response asks to wait, in seconds or until a date.
+fn retry_after(value: &HeaderValue) -> Option<Duration> {
+    let value = value.to_str().ok()?;
+    if let Ok(secs) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    (date.with_timezone(&Utc) - Utc::now()).to_std().ok()
+}
+
+/// The issues API of Snyk.
+///
+/// One client is shared by all requests, reusing its connections. The token can be replaced at
+/// runtime, e.g. when it is rotated.
+pub struct SnykClient {
+    url: String,
+    org: Option<String>,
+    token: RwLock<Option<String>>,
+    retries: u32,
+    backoff: Duration,
+    client: reqwest::Client,
+    resilience: Resilience,
+}
+
+impl SnykClient {
+    pub fn new(config: &Snyk) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .build()
+            .unwrap_or_default();
+        Self {
+            url: config.url.trim_end_matches('/').to_string(),
+            org: config.org.clone(),
+            token: RwLock::new(config.token.clone()),
+            retries: config.retries,
+            backoff: Duration::from_millis(config.backoff_ms),
+            client,
+            resilience: Resilience::new(
+                "snyk",
+                ResilienceConfig {
+                    breaker_threshold: config.breaker_threshold,
+                    breaker_reset_secs: config.breaker_reset_secs,
+                    ..Default::default()
+                },
+            ),
+        }
+    }
+
+    /// Whether an organization is configured, the token may be put in place later.
+    pub fn is_configured(&self) -> bool {
+        self.org.is_some()
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.org.is_some() && self.token.read().unwrap().is_some()
+    }
+
+    /// Replace the token used for subsequent calls.
+    pub fn set_token(&self, token: String) {
+        *self.token.write().unwrap() = Some(token);
+    }
+
+    /// Fetch a page of issues, retrying on rate limits, timeouts and server errors.
+    ///
+    /// The retries are Snyk's own, honoring `Retry-After`, the circuit breaker only fails fast
+    /// once they were exhausted too often.
+    async fn fetch(&self, url: &str) -> Result<serde_json::Value, anyhow::Error> {
+        self.resilience
+            .guard("get_vulnerabilities", self.fetch_attempts(url))
+            .await
+    }
+
+    async fn fetch_attempts(&self, url: &str) -> Result<serde_json::Value, anyhow::Error> {
+        let mut backoff = self.backoff;
+        let mut attempt = 0;
+        loop {
+            let token = self
                 .token
-                .as_ref()
-                .ok_or(ApiError::InternalError)?
-                .to_string(),
-        };
+                .read()
+                .unwrap()
+                .clone()
+                .ok_or_else(|| anyhow!("No Snyk token configured"))?;
+            crate::chaos::inject("snyk").await?;
+            crate::cost::record("snyk", "fetch_issues_per_purl");
+            metrics::UPSTREAM_REQUEST_SIZE
+                .with_label_values(&["snyk", "get_vulnerabilities"])
+                .observe(url.len() as f64);
+            let result = self
+                .client
+                .get(url)
+                .headers(crate::request_id::headers())
+                .header(AUTHORIZATION, format!("token {}", token))
+                .send()
+                .await;
 
-        let config = configuration::Configuration {
-            api_key: Some(key),
-            ..Default::default()
-        };
-        let issues = snyk::apis::issues_api::fetch_issues_per_purl(
-            &config,
-            "2023-02-15",
-            purl,
-            snyk.org.as_ref().ok_or(ApiError::InternalError)?,
-            None,
-            None,
-        )
-        .await;
-
-        if let Ok(issue) = issues {
-            if let Some(data) = issue.data {
-                for d in data {
-                    if let Some(id) = d.id {
-                        let vuln_ref = VulnerabilityRef {
-                            cve: id.clone(),
-                            href: format!("{}/{}", "https://security.snyk.io/vuln", id),
-                        };
-                        if !ret.contains(&vuln_ref) {
-                            ret.push(vuln_ref);
-                        }
+            attempt += 1;
+            let wait = match result {
+                Ok(response) if response.status().is_success() => {
+                    let body = response.bytes().await?;
+                    metrics::UPSTREAM_RESPONSE_SIZE
+                        .with_label_values(&["snyk", "get_vulnerabilities"])
+                        .observe(body.len() as f64);
+                    return Ok(serde_json::from_slice(&body)?);
+                }
+                Ok(response) if response.status() == StatusCode::TOO_MANY_REQUESTS => {
+                    let wait = response
+                        .headers()
+                        .get(RETRY_AFTER)
+                        .and_then(retry_after)
+                        .unwrap_or(backoff);
+                    if wait > MAX_RETRY_AFTER {
+                        bail!("Rate limited by Snyk for {:?}", wait);
                     }
+                    wait
                 }
+                Ok(response) if response.status().is_server_error() => backoff,
+                Ok(response) => bail!("Snyk responded with {}", response.status()),
+                Err(e) if e.is_timeout() || e.is_connect() => backoff,
+                Err(e) => return Err(e.into()),
             };
+            if attempt > self.retries {
+                bail!(
+                    "Fetching issues from Snyk failed after {} attempts",
+                    attempt
+                );
+            }
+
+            log::debug!("Retrying Snyk issues in {:?}", wait);
+            tokio::time::sleep(wait).await;
+            backoff *= 2;
+        }
+    }
+
+    /// The issues Snyk reports for a package, none if Snyk is not configured.
+    pub async fn get_vulnerabilities(
+        &self,
+        purl: &str,
+    ) -> Result<Vec<VulnerabilityRef>, anyhow::Error> {
+        let mut ret = Vec::new();
+        let org = match &self.org {
+            Some(org) if self.is_enabled() => org,
+            _ => return Ok(ret),
+        };
+
+        let mut next = Some(format!(
+            "{}/orgs/{}/packages/{}/issues?version={}",
+            self.url,
+            org,
+            urlencoding::encode(purl),
+            API_VERSION
+        ));
+        while let Some(url) = next.take() {
+            let page = self.fetch(&url).await?;
+            let fetched = Some(Utc::now());
+
+            for d in page["data"].as_array().into_iter().flatten() {
+                if let Some(id) = d["id"].as_str() {
+                    let href = format!("{}/{}", "https://security.snyk.io/vuln", id);
+                    let (aliases, severity) = details(d);
+                    let vuln_ref = VulnerabilityRef {
+                        cve: id.to_string(),
+                        href: href.clone(),
+                        ids: vec![ProviderId {
+                            provider: "snyk".to_string(),
+                            id: id.to_string(),
+                        }],
+                        fetched,
+                        aliases,
+                        severity,
+                        sources: vec![VulnerabilitySource {
+                            provider: "snyk".to_string(),
+                            href,
+                            severity,
+                            fetched,
+                        }],
+                    };
+                    if !ret.contains(&vuln_ref) {
+                        ret.push(vuln_ref);
+                    }
+                }
+            }
+
+            // links are relative to the host, with or without the `/rest` path
+            next = page["links"]["next"].as_str().map(|link| {
+                if link.starts_with("http") {
+                    link.to_string()
+                } else if link.starts_with("/rest/") {
+                    format!("{}{}", self.url.trim_end_matches("/rest"), link)
+                } else {
+                    format!("{}{}", self.url, link)
+                }
+            });
         }
+        Ok(ret)
     }
-    Ok(ret)
 }
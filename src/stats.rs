@@ -0,0 +1,186 @@
+use actix_web::{error, get, http::StatusCode, web, web::ServiceConfig, HttpResponse};
+use chrono::{DateTime, Utc};
+use core::str::FromStr;
+use packageurl::PackageUrl;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+
+use crate::depsdev::DepsDev;
+use crate::guac::Guac;
+use crate::sbom::SbomRegistry;
+
+/// The statistics endpoints, served by the admin listener, as they scan all SBOMs.
+pub(crate) fn configure() -> impl FnOnce(&mut ServiceConfig) {
+    |config: &mut ServiceConfig| {
+        config.service(adoption);
+    }
+}
+
+/// The lag between community releases and their trusted builds, in hours.
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LagSummary {
+    pub builds: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mean_lag_hours: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub median_lag_hours: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_lag_hours: Option<f64>,
+}
+
+impl LagSummary {
+    fn of(mut lags: Vec<f64>) -> Self {
+        if lags.is_empty() {
+            return Self::default();
+        }
+        lags.sort_by(|a, b| a.total_cmp(b));
+        let len = lags.len();
+        let median = if len % 2 == 0 {
+            (lags[len / 2 - 1] + lags[len / 2]) / 2.0
+        } else {
+            lags[len / 2]
+        };
+        Self {
+            builds: len,
+            mean_lag_hours: Some(lags.iter().sum::<f64>() / len as f64),
+            median_lag_hours: Some(median),
+            max_lag_hours: lags.last().copied(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PackageAdoption {
+    /// The package, without a version
+    pub package: String,
+    #[serde(flatten)]
+    pub lag: LagSummary,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct AdoptionStats {
+    #[serde(flatten)]
+    pub overall: LagSummary,
+    pub packages: Vec<PackageAdoption>,
+}
+
+/// Computes how long it takes for trusted builds of community releases to appear.
+///
+/// A trusted build appears when its SBOM was created, community releases are dated by deps.dev.
+pub struct Stats {
+    guac: Arc<Guac>,
+    sboms: Arc<SbomRegistry>,
+    deps_dev: Option<Arc<DepsDev>>,
+    /// Publication dates of community releases, which don't change
+    published: Mutex<HashMap<String, Option<DateTime<Utc>>>>,
+}
+
+impl Stats {
+    pub fn new(guac: Arc<Guac>, sboms: Arc<SbomRegistry>, deps_dev: Option<Arc<DepsDev>>) -> Self {
+        Self {
+            guac,
+            sboms,
+            deps_dev,
+            published: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn adoption(&self) -> Result<AdoptionStats, ApiError> {
+        let deps_dev = self.deps_dev.as_ref().ok_or(ApiError::DepsDevDisabled)?;
+
+        let mut lags: BTreeMap<String, Vec<f64>> = BTreeMap::new();
+        for purl_str in self.sboms.purls() {
+            let purl = match PackageUrl::from_str(&purl_str) {
+                Ok(purl) => purl,
+                Err(_) => continue,
+            };
+            if !self.guac.resolver().is_trusted(&purl, None).await {
+                continue;
+            }
+            let (version, created) = match (
+                self.guac.resolver().upstream_version(&purl),
+                self.sboms
+                    .lookup(&purl_str)
+                    .and_then(|sbom| crate::sbom::created_of(&sbom)),
+            ) {
+                (Some(version), Some(created)) => (version, created),
+                _ => continue,
+            };
+            let published = match self.published(deps_dev, &purl, &version).await {
+                Some(published) => published,
+                None => continue,
+            };
+
+            let lag = (created - published).num_minutes().max(0) as f64 / 60.0;
+            lags.entry(package_of(&purl)).or_default().push(lag);
+        }
+
+        Ok(AdoptionStats {
+            overall: LagSummary::of(lags.values().flatten().copied().collect()),
+            packages: lags
+                .into_iter()
+                .map(|(package, lags)| PackageAdoption {
+                    package,
+                    lag: LagSummary::of(lags),
+                })
+                .collect(),
+        })
+    }
+
+    /// When a community release was published, failures are not remembered.
+    async fn published(
+        &self,
+        deps_dev: &DepsDev,
+        purl: &PackageUrl<'_>,
+        version: &str,
+    ) -> Option<DateTime<Utc>> {
+        let key = format!("{}@{}", package_of(purl), version);
+        if let Some(published) = self.published.lock().unwrap().get(&key) {
+            return *published;
+        }
+        match deps_dev.get_published(purl, version).await {
+            Ok(published) => {
+                self.published.lock().unwrap().insert(key, published);
+                published
+            }
+            Err(e) => {
+                log::debug!("Unable to find when {} was published: {:?}", key, e);
+                None
+            }
+        }
+    }
+}
+
+fn package_of(purl: &PackageUrl<'_>) -> String {
+    match purl.namespace() {
+        Some(namespace) => format!("pkg:{}/{}/{}", purl.ty(), namespace, purl.name()),
+        None => format!("pkg:{}/{}", purl.ty(), purl.name()),
+    }
+}
+
+/// The time between community releases and their trusted builds appearing, by package.
+#[get("/api/admin/stats/adoption")]
+pub async fn adoption(stats: web::Data<Arc<Stats>>) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok().json(stats.adoption().await?))
+}
+
+#[derive(Debug, Error, Serialize, Deserialize)]
+pub enum ApiError {
+    #[error("Adoption stats require the deps.dev integration to be enabled")]
+    DepsDevDisabled,
+}
+
+impl error::ResponseError for ApiError {
+    fn error_response(&self) -> HttpResponse {
+        crate::problem::response(self.status_code(), self, serde_json::json!({}))
+    }
+
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::DepsDevDisabled => StatusCode::NOT_IMPLEMENTED,
+        }
+    }
+}
@@ -0,0 +1,461 @@
+use anyhow::bail;
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+/// Where data persisted by the service is stored.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum StorageConfig {
+    /// Files below a directory, keys being their relative paths
+    Filesystem { path: PathBuf },
+    /// Objects of an S3 bucket, requires the `s3` feature
+    S3 {
+        bucket: String,
+        region: String,
+        /// S3 compatible services other than AWS, e.g. `http://minio:9000`
+        #[serde(default)]
+        endpoint: Option<String>,
+    },
+    /// Rows of a Postgres table, created if missing, requires the `postgres` feature
+    Postgres {
+        /// TLS is used if the server supports it, `sslmode=require` in the URL requires it
+        url: String,
+        #[serde(default = "default_table")]
+        table: String,
+        /// The CA certificates (PEM) verifying the server, the system ones if not set
+        #[serde(default)]
+        ca_file: Option<PathBuf>,
+    },
+}
+
+fn default_table() -> String {
+    "trust_api_storage".to_string()
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self::Filesystem {
+            path: PathBuf::from("data"),
+        }
+    }
+}
+
+impl StorageConfig {
+    /// Whether support for the configured storage was built in.
+    pub fn is_supported(&self) -> bool {
+        match self {
+            Self::Filesystem { .. } => true,
+            Self::S3 { .. } => cfg!(feature = "s3"),
+            Self::Postgres { .. } => cfg!(feature = "postgres"),
+        }
+    }
+
+    pub fn redacted(&self, redacted: &str) -> Self {
+        match self {
+            Self::Postgres { table, ca_file, .. } => Self::Postgres {
+                url: redacted.to_string(),
+                table: table.clone(),
+                ca_file: ca_file.clone(),
+            },
+            config => config.clone(),
+        }
+    }
+}
+
+/// Persistence of documents by key, shared by the features storing data.
+///
+/// Keys are `/` separated paths, e.g. `sboms/ubi9.json`.
+pub trait Storage: Send + Sync {
+    /// The data stored under a key, `None` if there is none.
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Vec<u8>>, anyhow::Error>>;
+
+    /// Store data under a key, replacing the data stored before.
+    fn put<'a>(&'a self, key: &'a str, data: Vec<u8>) -> BoxFuture<'a, Result<(), anyhow::Error>>;
+
+    /// Remove the data stored under a key, if any.
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<(), anyhow::Error>>;
+
+    /// The keys starting with a prefix.
+    fn list<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<Vec<String>, anyhow::Error>>;
+}
+
+/// Open the configured storage.
+pub async fn open(config: &StorageConfig) -> Result<Arc<dyn Storage>, anyhow::Error> {
+    match config {
+        StorageConfig::Filesystem { path } => Ok(Arc::new(FileStorage::new(path.clone()))),
+        #[cfg(feature = "s3")]
+        StorageConfig::S3 {
+            bucket,
+            region,
+            endpoint,
+        } => Ok(Arc::new(S3Storage::new(
+            bucket,
+            region,
+            endpoint.as_deref(),
+        )?)),
+        #[cfg(feature = "postgres")]
+        StorageConfig::Postgres {
+            url,
+            table,
+            ca_file,
+        } => Ok(Arc::new(
+            PostgresStorage::new(url, table, ca_file.as_deref()).await?,
+        )),
+        #[allow(unreachable_patterns)]
+        config => bail!("Storage {:?} is not supported by this build", config),
+    }
+}
+
+/// Stores data as files below a root directory.
+pub struct FileStorage {
+    root: PathBuf,
+}
+
+impl FileStorage {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    /// The file of a key, keys escaping the root are rejected.
+    fn path(&self, key: &str) -> Result<PathBuf, anyhow::Error> {
+        let relative = Path::new(key);
+        if key.is_empty()
+            || !relative
+                .components()
+                .all(|c| matches!(c, Component::Normal(_)))
+        {
+            bail!("Invalid storage key {}", key);
+        }
+        Ok(self.root.join(relative))
+    }
+
+    async fn list_files(&self, prefix: &str) -> Result<Vec<String>, anyhow::Error> {
+        // only walk the directory the prefix points into
+        let dir = match prefix.rfind('/') {
+            Some(end) => self.path(&prefix[..end])?,
+            None => self.root.clone(),
+        };
+        let mut keys = Vec::new();
+        let mut pending = vec![dir];
+        while let Some(dir) = pending.pop() {
+            let mut entries = match tokio::fs::read_dir(&dir).await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+                if entry.file_type().await?.is_dir() {
+                    pending.push(path);
+                    continue;
+                }
+                if path.extension().and_then(|e| e.to_str()) == Some("tmp") {
+                    continue;
+                }
+                let key = path
+                    .strip_prefix(&self.root)?
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                if key.starts_with(prefix) {
+                    keys.push(key);
+                }
+            }
+        }
+        keys.sort();
+        Ok(keys)
+    }
+}
+
+impl Storage for FileStorage {
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Vec<u8>>, anyhow::Error>> {
+        Box::pin(async move {
+            match tokio::fs::read(self.path(key)?).await {
+                Ok(data) => Ok(Some(data)),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e.into()),
+            }
+        })
+    }
+
+    fn put<'a>(&'a self, key: &'a str, data: Vec<u8>) -> BoxFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let path = self.path(key)?;
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            // a file of its own, concurrent writes of a key don't mix, the last one wins
+            let tmp = path.with_extension(format!("{}.tmp", uuid::Uuid::new_v4()));
+            tokio::fs::write(&tmp, data).await?;
+            tokio::fs::rename(&tmp, &path).await?;
+            Ok(())
+        })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            match tokio::fs::remove_file(self.path(key)?).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+                _ => Ok(()),
+            }
+        })
+    }
+
+    fn list<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<Vec<String>, anyhow::Error>> {
+        Box::pin(self.list_files(prefix))
+    }
+}
+
+/// Stores data as objects of an S3 bucket, with the credentials of the environment.
+#[cfg(feature = "s3")]
+pub struct S3Storage {
+    bucket: s3::Bucket,
+}
+
+#[cfg(feature = "s3")]
+impl S3Storage {
+    pub fn new(bucket: &str, region: &str, endpoint: Option<&str>) -> Result<Self, anyhow::Error> {
+        let credentials = s3::creds::Credentials::default()?;
+        let bucket = match endpoint {
+            Some(endpoint) => s3::Bucket::new(
+                bucket,
+                s3::Region::Custom {
+                    region: region.to_string(),
+                    endpoint: endpoint.to_string(),
+                },
+                credentials,
+            )?
+            .with_path_style(),
+            None => s3::Bucket::new(bucket, region.parse()?, credentials)?,
+        };
+        Ok(Self { bucket })
+    }
+}
+
+#[cfg(feature = "s3")]
+impl Storage for S3Storage {
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Vec<u8>>, anyhow::Error>> {
+        Box::pin(async move {
+            let response = self.bucket.get_object(key).await?;
+            match response.status_code() {
+                200 => Ok(Some(response.bytes().to_vec())),
+                404 => Ok(None),
+                status => bail!("Error reading {} from S3: {}", key, status),
+            }
+        })
+    }
+
+    fn put<'a>(&'a self, key: &'a str, data: Vec<u8>) -> BoxFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let response = self.bucket.put_object(key, &data).await?;
+            match response.status_code() {
+                200..=299 => Ok(()),
+                status => bail!("Error writing {} to S3: {}", key, status),
+            }
+        })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let response = self.bucket.delete_object(key).await?;
+            match response.status_code() {
+                200..=299 | 404 => Ok(()),
+                status => bail!("Error deleting {} from S3: {}", key, status),
+            }
+        })
+    }
+
+    fn list<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<Vec<String>, anyhow::Error>> {
+        Box::pin(async move {
+            let pages = self.bucket.list(prefix.to_string(), None).await?;
+            Ok(pages
+                .into_iter()
+                .flat_map(|page| page.contents)
+                .map(|object| object.key)
+                .collect())
+        })
+    }
+}
+
+/// Stores data as rows of a Postgres table.
+///
+/// The connection is opened again by the next call after it was closed, e.g. by a restart of
+/// the database.
+#[cfg(feature = "postgres")]
+pub struct PostgresStorage {
+    url: String,
+    tls: tokio_postgres_rustls::MakeRustlsConnect,
+    client: tokio::sync::Mutex<Arc<tokio_postgres::Client>>,
+    table: String,
+}
+
+#[cfg(feature = "postgres")]
+impl PostgresStorage {
+    pub async fn new(
+        url: &str,
+        table: &str,
+        ca_file: Option<&Path>,
+    ) -> Result<Self, anyhow::Error> {
+        if !table.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            bail!("Invalid Postgres table name {}", table);
+        }
+        let tls = Self::tls(ca_file)?;
+        let client = Self::connect(url, &tls).await?;
+        client
+            .batch_execute(&format!(
+                "CREATE TABLE IF NOT EXISTS {} (key TEXT PRIMARY KEY, data BYTEA NOT NULL, modified TIMESTAMPTZ NOT NULL DEFAULT now())",
+                table
+            ))
+            .await?;
+        Ok(Self {
+            url: url.to_string(),
+            tls,
+            client: tokio::sync::Mutex::new(Arc::new(client)),
+            table: table.to_string(),
+        })
+    }
+
+    /// Verify the server with the certificates of `ca_file`, or the system ones.
+    fn tls(
+        ca_file: Option<&Path>,
+    ) -> Result<tokio_postgres_rustls::MakeRustlsConnect, anyhow::Error> {
+        let mut roots = rustls::RootCertStore::empty();
+        let certs = match ca_file {
+            Some(path) => crate::tls::load_certs(path)?,
+            None => rustls_native_certs::load_native_certs()?
+                .into_iter()
+                .map(|cert| rustls::Certificate(cert.0))
+                .collect(),
+        };
+        for cert in certs {
+            roots.add(&cert)?;
+        }
+        let config = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        Ok(tokio_postgres_rustls::MakeRustlsConnect::new(config))
+    }
+
+    async fn connect(
+        url: &str,
+        tls: &tokio_postgres_rustls::MakeRustlsConnect,
+    ) -> Result<tokio_postgres::Client, anyhow::Error> {
+        let (client, connection) = tokio_postgres::connect(url, tls.clone()).await?;
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                log::warn!("Postgres storage connection failed: {:?}", e);
+            }
+        });
+        Ok(client)
+    }
+
+    /// The open connection, connecting again if it was closed.
+    async fn client(&self) -> Result<Arc<tokio_postgres::Client>, anyhow::Error> {
+        let mut client = self.client.lock().await;
+        if client.is_closed() {
+            log::info!("Connecting the Postgres storage again");
+            *client = Arc::new(Self::connect(&self.url, &self.tls).await?);
+        }
+        Ok(client.clone())
+    }
+}
+
+#[cfg(feature = "postgres")]
+impl Storage for PostgresStorage {
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Vec<u8>>, anyhow::Error>> {
+        Box::pin(async move {
+            let row = self
+                .client()
+                .await?
+                .query_opt(
+                    &format!("SELECT data FROM {} WHERE key = $1", self.table),
+                    &[&key],
+                )
+                .await?;
+            Ok(row.map(|row| row.get(0)))
+        })
+    }
+
+    fn put<'a>(&'a self, key: &'a str, data: Vec<u8>) -> BoxFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            self.client()
+                .await?
+                .execute(
+                    &format!(
+                        "INSERT INTO {} (key, data) VALUES ($1, $2) ON CONFLICT (key) DO UPDATE SET data = EXCLUDED.data, modified = now()",
+                        self.table
+                    ),
+                    &[&key, &data],
+                )
+                .await?;
+            Ok(())
+        })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            self.client()
+                .await?
+                .execute(
+                    &format!("DELETE FROM {} WHERE key = $1", self.table),
+                    &[&key],
+                )
+                .await?;
+            Ok(())
+        })
+    }
+
+    fn list<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<Vec<String>, anyhow::Error>> {
+        Box::pin(async move {
+            let rows = self
+                .client()
+                .await?
+                .query(
+                    &format!(
+                        "SELECT key FROM {} WHERE left(key, length($1)) = $1 ORDER BY key",
+                        self.table
+                    ),
+                    &[&prefix],
+                )
+                .await?;
+            Ok(rows.into_iter().map(|row| row.get(0)).collect())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn files_are_replaced_as_a_whole() {
+        let dir = std::env::temp_dir().join(format!("trust-api-storage-{}", uuid::Uuid::new_v4()));
+        let storage = Arc::new(FileStorage::new(dir.clone()));
+        let writes = (0..16).map(|i| {
+            let storage = storage.clone();
+            tokio::spawn(async move {
+                storage
+                    .put("history/package.json", vec![b'0' + i as u8; 4096])
+                    .await
+            })
+        });
+        for write in futures::future::join_all(writes).await {
+            write.unwrap().unwrap();
+        }
+
+        let data = storage.get("history/package.json").await.unwrap().unwrap();
+        assert_eq!(data.len(), 4096);
+        assert!(data.iter().all(|b| *b == data[0]));
+        // no temporary files are left or listed
+        assert_eq!(
+            storage.list("history/").await.unwrap(),
+            vec!["history/package.json".to_string()]
+        );
+        assert_eq!(std::fs::read_dir(dir.join("history")).unwrap().count(), 1);
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
@@ -0,0 +1,188 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::cache::Cache;
+use crate::package::PackageDependencies;
+use crate::sbom::SbomRegistry;
+
+/// How deep dependency graphs may be walked.
+pub const MAX_DEPTH: usize = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Dependencies,
+    Dependents,
+}
+
+/// The cache key of the current generation of subgraphs.
+const GENERATION_KEY: &str = "subgraph:generation";
+
+/// Caches dependency subgraphs walked in Guac, keyed by a digest of (purl, depth, direction).
+///
+/// Keys include a generation, which is replaced when the graph changes, so stale entries are
+/// no longer read and expire. The generation is kept in the cache as well, so all instances
+/// sharing it see invalidations, and a new one is chosen whenever it expired. Changes of the
+/// SBOM registry are taken into account as well, as the SBOM links of the packages in a
+/// subgraph depend on it.
+pub struct Subgraphs {
+    cache: Arc<Cache>,
+    sboms: Arc<SbomRegistry>,
+    /// How often the generation is replaced at most, invalidations in between are coalesced
+    min_interval: Duration,
+    /// The generation last read, for lookups bypassing the cache
+    known: Mutex<Option<String>>,
+    invalidation: Mutex<Invalidation>,
+}
+
+#[derive(Default)]
+struct Invalidation {
+    /// When the generation was last replaced by this instance
+    last: Option<Instant>,
+    /// Whether an invalidation waits for `min_interval` to pass
+    pending: bool,
+}
+
+impl Subgraphs {
+    pub fn new(cache: Arc<Cache>, sboms: Arc<SbomRegistry>, min_interval: Duration) -> Self {
+        Self {
+            cache,
+            sboms,
+            min_interval,
+            known: Default::default(),
+            invalidation: Default::default(),
+        }
+    }
+
+    /// The current generation, `None` if it is unknown while bypassing the cache.
+    async fn generation(&self) -> Option<String> {
+        if self.take_pending() {
+            return Some(self.replace_generation().await);
+        }
+        if let Some(generation) = self.cache.get::<String>(GENERATION_KEY).await {
+            *self.known.lock().unwrap() = Some(generation.clone());
+            return Some(generation);
+        }
+        if crate::cache::bypassed() {
+            return self.known.lock().unwrap().clone();
+        }
+        Some(self.replace_generation().await)
+    }
+
+    async fn replace_generation(&self) -> String {
+        let generation = uuid::Uuid::new_v4().to_string();
+        self.cache.put(GENERATION_KEY, &generation).await;
+        *self.known.lock().unwrap() = Some(generation.clone());
+        generation
+    }
+
+    /// Whether an invalidation was coalesced and may be applied now.
+    fn take_pending(&self) -> bool {
+        let mut invalidation = self.invalidation.lock().unwrap();
+        let due = invalidation.pending
+            && invalidation
+                .last
+                .map_or(true, |last| last.elapsed() >= self.min_interval);
+        if due {
+            invalidation.pending = false;
+            invalidation.last = Some(Instant::now());
+        }
+        due
+    }
+
+    async fn key(&self, direction: Direction, depth: usize, purl: &str) -> Option<String> {
+        let digest = Sha256::digest(format!("{:?}:{}:{}", direction, depth, purl));
+        // the SBOM links of the packages depend on the SBOMs visible to the caller
+        Some(crate::package::scoped_key(
+            &format!(
+                "subgraph:{}.{}",
+                self.generation().await?,
+                self.sboms.generation()
+            ),
+            &hex::encode(digest),
+            crate::tenant::current(),
+        ))
+    }
+
+    pub async fn get(
+        &self,
+        direction: Direction,
+        depth: usize,
+        purl: &str,
+    ) -> Option<PackageDependencies> {
+        self.cache
+            .get(&self.key(direction, depth, purl).await?)
+            .await
+    }
+
+    pub async fn put(
+        &self,
+        direction: Direction,
+        depth: usize,
+        purl: &str,
+        subgraph: &PackageDependencies,
+    ) {
+        if let Some(key) = self.key(direction, depth, purl).await {
+            self.cache.put(&key, subgraph).await
+        }
+    }
+
+    /// Drop all cached subgraphs, e.g. after new documents were ingested into Guac.
+    ///
+    /// Invalidations within `min_interval` of the last one are applied once it passed, with the
+    /// next lookup.
+    pub async fn invalidate(&self) {
+        {
+            let mut invalidation = self.invalidation.lock().unwrap();
+            invalidation.pending = true;
+        }
+        if self.take_pending() {
+            self.replace_generation().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CacheConfig;
+
+    async fn subgraphs(min_interval: Duration) -> Subgraphs {
+        let cache = Arc::new(Cache::new(&CacheConfig::default()).await.unwrap());
+        let sboms = Arc::new(SbomRegistry::for_tests().await);
+        Subgraphs::new(cache, sboms, min_interval)
+    }
+
+    #[tokio::test]
+    async fn invalidations_are_seen_by_all_instances_sharing_the_cache() {
+        let first = subgraphs(Duration::ZERO).await;
+        let second = Subgraphs::new(first.cache.clone(), first.sboms.clone(), Duration::ZERO);
+        let subgraph = PackageDependencies(vec![]);
+        first
+            .put(Direction::Dependencies, 1, "pkg:maven/a/b@1", &subgraph)
+            .await;
+        assert!(second
+            .get(Direction::Dependencies, 1, "pkg:maven/a/b@1")
+            .await
+            .is_some());
+
+        second.invalidate().await;
+        assert!(first
+            .get(Direction::Dependencies, 1, "pkg:maven/a/b@1")
+            .await
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn invalidations_within_the_interval_are_coalesced() {
+        let subgraphs = subgraphs(Duration::from_secs(60)).await;
+        subgraphs.invalidate().await;
+        let generation = subgraphs.generation().await;
+        subgraphs.invalidate().await;
+        subgraphs.invalidate().await;
+        assert_eq!(subgraphs.generation().await, generation);
+        assert!(subgraphs.invalidation.lock().unwrap().pending);
+    }
+}
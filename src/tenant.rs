@@ -0,0 +1,33 @@
+use std::future::Future;
+
+tokio::task_local! {
+    static TENANT: Option<String>;
+}
+
+/// Run a future on behalf of an authenticated caller, which sees the content of its tenant and
+/// the global namespace, or only the global namespace if it belongs to no tenant.
+///
+/// Outside of such a scope (background tasks, deployments without authentication) all content is
+/// visible.
+pub async fn scoped<F: Future>(tenant: Option<String>, f: F) -> F::Output {
+    TENANT.scope(tenant, f).await
+}
+
+/// Run a function on behalf of a caller, as [`scoped`] does for futures.
+pub fn sync_scoped<R>(tenant: Option<String>, f: impl FnOnce() -> R) -> R {
+    TENANT.sync_scope(tenant, f)
+}
+
+/// The tenant of the running task, `None` if it isn't scoped, `Some(None)` for callers of no
+/// tenant.
+pub fn current() -> Option<Option<String>> {
+    TENANT.try_with(Clone::clone).ok()
+}
+
+/// Whether the running task may see content of a tenant, `None` being the global namespace.
+pub fn can_see(tenant: Option<&str>) -> bool {
+    match (current(), tenant) {
+        (None, _) | (_, None) => true,
+        (Some(current), Some(tenant)) => current.as_deref() == Some(tenant),
+    }
+}
@@ -0,0 +1,248 @@
+use anyhow::{anyhow, Context};
+use notify::{Event, RecursiveMode, Watcher};
+use rustls::server::{
+    AllowAnyAuthenticatedClient, ClientCertVerified, ClientCertVerifier, ClientHello,
+    ResolvesServerCert,
+};
+use rustls::sign::CertifiedKey;
+use rustls::{Certificate, DistinguishedNames, PrivateKey, RootCertStore, ServerConfig};
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio::sync::mpsc;
+
+use crate::config::TlsConfig;
+
+/// How long to wait for more changes to the files before reloading, certificate and key are
+/// usually replaced one after the other.
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Serves the configured certificate, which can be reloaded from disk at runtime.
+pub struct ReloadingResolver {
+    cert: PathBuf,
+    key: PathBuf,
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl ReloadingResolver {
+    fn new(cert: PathBuf, key: PathBuf) -> Result<Self, anyhow::Error> {
+        let current = RwLock::new(Arc::new(load_certified_key(&cert, &key)?));
+        Ok(Self { cert, key, current })
+    }
+
+    /// Reload certificate and key, keeping the current ones if loading fails.
+    pub fn reload(&self) -> Result<(), anyhow::Error> {
+        let key = load_certified_key(&self.cert, &self.key)?;
+        *self.current.write().unwrap() = Arc::new(key);
+        Ok(())
+    }
+}
+
+impl ResolvesServerCert for ReloadingResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap().clone())
+    }
+}
+
+/// Verifies client certificates against the configured client CA, which can be reloaded from
+/// disk at runtime.
+pub struct ReloadingVerifier {
+    client_ca: PathBuf,
+    current: RwLock<Arc<dyn ClientCertVerifier>>,
+}
+
+impl ReloadingVerifier {
+    fn new(client_ca: PathBuf) -> Result<Self, anyhow::Error> {
+        let current = RwLock::new(load_client_verifier(&client_ca)?);
+        Ok(Self { client_ca, current })
+    }
+
+    /// Reload the client CA, keeping the current one if loading fails.
+    pub fn reload(&self) -> Result<(), anyhow::Error> {
+        let verifier = load_client_verifier(&self.client_ca)?;
+        *self.current.write().unwrap() = verifier;
+        Ok(())
+    }
+
+    fn current(&self) -> Arc<dyn ClientCertVerifier> {
+        self.current.read().unwrap().clone()
+    }
+}
+
+impl ClientCertVerifier for ReloadingVerifier {
+    fn offer_client_auth(&self) -> bool {
+        self.current().offer_client_auth()
+    }
+
+    fn client_auth_mandatory(&self) -> Option<bool> {
+        self.current().client_auth_mandatory()
+    }
+
+    fn client_auth_root_subjects(&self) -> Option<DistinguishedNames> {
+        self.current().client_auth_root_subjects()
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        now: SystemTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        self.current()
+            .verify_client_cert(end_entity, intermediates, now)
+    }
+}
+
+/// The files of the TLS configuration, reloaded together.
+pub struct TlsReloader {
+    resolver: Arc<ReloadingResolver>,
+    verifier: Option<Arc<ReloadingVerifier>>,
+}
+
+impl TlsReloader {
+    /// Reload certificate, key and client CA, keeping the current ones of the files failing to
+    /// load.
+    pub fn reload(&self) -> Result<(), anyhow::Error> {
+        let reloaded = self.resolver.reload();
+        if let Some(verifier) = &self.verifier {
+            verifier.reload()?;
+        }
+        reloaded
+    }
+
+    /// The directories of the files, watched rather than the files themselves, as these are
+    /// often replaced, e.g. by swapping a symlink in a Kubernetes secret volume.
+    fn directories(&self) -> BTreeSet<PathBuf> {
+        let resolver = &self.resolver;
+        [&resolver.cert, &resolver.key]
+            .into_iter()
+            .chain(self.verifier.iter().map(|v| &v.client_ca))
+            .map(|path| match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+                _ => PathBuf::from("."),
+            })
+            .collect()
+    }
+}
+
+/// Create the server side TLS configuration, requiring client certificates if a client CA is configured.
+pub fn server_config(
+    config: &TlsConfig,
+) -> Result<(ServerConfig, Arc<TlsReloader>), anyhow::Error> {
+    let resolver = Arc::new(ReloadingResolver::new(
+        config.cert.clone(),
+        config.key.clone(),
+    )?);
+    let verifier = config
+        .client_ca
+        .clone()
+        .map(ReloadingVerifier::new)
+        .transpose()?
+        .map(Arc::new);
+
+    let builder = ServerConfig::builder().with_safe_defaults();
+    let server = match &verifier {
+        Some(verifier) => builder
+            .with_client_cert_verifier(verifier.clone())
+            .with_cert_resolver(resolver.clone()),
+        None => builder
+            .with_no_client_auth()
+            .with_cert_resolver(resolver.clone()),
+    };
+
+    Ok((server, Arc::new(TlsReloader { resolver, verifier })))
+}
+
+/// Reload the certificates whenever the process receives a SIGHUP, or their files change.
+///
+/// Failing to watch the files is logged, the certificates are still reloaded on SIGHUP then.
+pub fn reload_on_change(reloader: Arc<TlsReloader>) -> Result<(), anyhow::Error> {
+    let mut signal = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?;
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let watcher = notify::recommended_watcher(move |event: notify::Result<Event>| match event {
+        // the receiver only goes away with the loop
+        Ok(event) if !event.kind.is_access() => {
+            let _ = tx.send(());
+        }
+        Ok(_) => {}
+        Err(e) => log::warn!("Error watching TLS certificates: {:?}", e),
+    })
+    .and_then(|mut watcher| {
+        for directory in reloader.directories() {
+            watcher.watch(&directory, RecursiveMode::NonRecursive)?;
+        }
+        Ok(watcher)
+    });
+    let watcher = match watcher {
+        Ok(watcher) => Some(watcher),
+        Err(e) => {
+            log::warn!("Unable to watch TLS certificates: {:?}", e);
+            None
+        }
+    };
+
+    tokio::spawn(async move {
+        // watching stops once the watcher is dropped
+        let _watcher = watcher;
+        loop {
+            tokio::select! {
+                Some(()) = signal.recv() => {}
+                Some(()) = rx.recv() => {
+                    while let Ok(Some(())) = tokio::time::timeout(DEBOUNCE, rx.recv()).await {}
+                }
+                else => return,
+            }
+            match reloader.reload() {
+                Ok(()) => log::info!("Reloaded TLS certificates"),
+                Err(e) => log::warn!("Error reloading TLS certificates: {:?}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+pub(crate) fn load_certs(path: &Path) -> Result<Vec<Certificate>, anyhow::Error> {
+    let mut reader = BufReader::new(
+        File::open(path).with_context(|| format!("Unable to open {}", path.display()))?,
+    );
+    let certs = rustls_pemfile::certs(&mut reader)?;
+    if certs.is_empty() {
+        return Err(anyhow!("No certificates found in {}", path.display()));
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn load_key(path: &Path) -> Result<PrivateKey, anyhow::Error> {
+    let mut reader = BufReader::new(
+        File::open(path).with_context(|| format!("Unable to open {}", path.display()))?,
+    );
+    for item in rustls_pemfile::read_all(&mut reader)? {
+        match item {
+            rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::ECKey(key) => return Ok(PrivateKey(key)),
+            _ => {}
+        }
+    }
+    Err(anyhow!("No private key found in {}", path.display()))
+}
+
+fn load_client_verifier(client_ca: &Path) -> Result<Arc<dyn ClientCertVerifier>, anyhow::Error> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(client_ca)? {
+        roots
+            .add(&cert)
+            .map_err(|e| anyhow!("Invalid client CA certificate: {:?}", e))?;
+    }
+    Ok(AllowAnyAuthenticatedClient::new(roots))
+}
+
+fn load_certified_key(cert: &Path, key: &Path) -> Result<CertifiedKey, anyhow::Error> {
+    let certs = load_certs(cert)?;
+    let key = rustls::sign::any_supported_type(&load_key(key)?)
+        .map_err(|_| anyhow!("Unsupported private key type in {}", key.display()))?;
+    Ok(CertifiedKey::new(certs, key))
+}
@@ -0,0 +1,95 @@
+use futures::future::BoxFuture;
+use packageurl::PackageUrl;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::package::PackageRef;
+
+/// Decides which packages are trusted rebuilds, and maps community packages to them.
+///
+/// Deployments with rebuild naming conventions which can't be expressed by
+/// [`TrustedVersionsConfig`] can provide their own implementation.
+pub trait TrustedVersionResolver: Send + Sync {
+    /// Whether a package is a trusted rebuild, `analysis` being what is known about it if it is
+    /// looked up on its own.
+    fn is_trusted<'a>(
+        &'a self,
+        purl: &'a PackageUrl<'a>,
+        analysis: Option<&'a Value>,
+    ) -> BoxFuture<'a, bool>;
+
+    /// Select the trusted rebuilds of a community package, from the versions of it known to Guac.
+    fn trusted_versions(
+        &self,
+        _purl: &PackageUrl<'_>,
+        candidates: Vec<PackageRef>,
+    ) -> Vec<PackageRef> {
+        candidates
+    }
+
+    /// The version of the community release a trusted rebuild is based on, if known.
+    fn upstream_version(&self, _purl: &PackageUrl<'_>) -> Option<String> {
+        None
+    }
+}
+
+/// Naming conventions of trusted rebuilds.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TrustedVersionsConfig {
+    /// Versions containing one of these are trusted (e.g. `1.2.3.redhat-00001`)
+    pub version_markers: Vec<String>,
+    /// Packages in one of these namespaces are trusted
+    pub namespaces: Vec<String>,
+}
+
+impl Default for TrustedVersionsConfig {
+    fn default() -> Self {
+        Self {
+            version_markers: vec!["redhat".to_string()],
+            namespaces: vec!["redhat".to_string()],
+        }
+    }
+}
+
+/// Resolves trusted rebuilds by markers in their versions or their namespaces.
+pub struct NamingConvention {
+    config: TrustedVersionsConfig,
+}
+
+impl NamingConvention {
+    pub fn new(config: TrustedVersionsConfig) -> Self {
+        Self { config }
+    }
+
+    /// Whether the version or namespace of a package marks it as a trusted rebuild.
+    pub fn matches(&self, purl: &PackageUrl<'_>) -> bool {
+        purl.version().map_or(false, |v| {
+            self.config.version_markers.iter().any(|m| v.contains(m))
+        }) || purl
+            .namespace()
+            .map_or(false, |ns| self.config.namespaces.iter().any(|n| n == ns))
+    }
+}
+
+impl TrustedVersionResolver for NamingConvention {
+    fn is_trusted<'a>(
+        &'a self,
+        purl: &'a PackageUrl<'a>,
+        _analysis: Option<&'a Value>,
+    ) -> BoxFuture<'a, bool> {
+        Box::pin(futures::future::ready(self.matches(purl)))
+    }
+
+    fn upstream_version(&self, purl: &PackageUrl<'_>) -> Option<String> {
+        let version = purl.version()?;
+        let start = self
+            .config
+            .version_markers
+            .iter()
+            .filter_map(|m| version.find(m.as_str()))
+            .min()?;
+        let upstream = version[..start].trim_end_matches(|c| matches!(c, '.' | '-' | '_' | '+'));
+        (!upstream.is_empty()).then(|| upstream.to_string())
+    }
+}
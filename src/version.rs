@@ -0,0 +1,539 @@
+use core::str::FromStr;
+use packageurl::PackageUrl;
+use std::cmp::Ordering;
+
+use crate::package::{PackageRef, VersionRelation};
+
+/// Compare versions by their segments, numeric segments numerically, e.g. `2.9.10` < `2.10.0`.
+pub fn compare(a: &str, b: &str) -> Ordering {
+    let segments = |v: &str| -> Vec<String> {
+        v.split(|c: char| !c.is_ascii_alphanumeric())
+            .filter(|s| !s.is_empty())
+            .map(|s| s.to_lowercase())
+            .collect()
+    };
+    let (a, b) = (segments(a), segments(b));
+    for (a, b) in a.iter().zip(b.iter()) {
+        let ordering = match (a.parse::<u64>(), b.parse::<u64>()) {
+            (Ok(a), Ok(b)) => a.cmp(&b),
+            _ => a.cmp(b),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    a.len().cmp(&b.len())
+}
+
+/// How the versions of an ecosystem are ordered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scheme {
+    /// Semantic versioning, pre-releases before their release, build metadata ignored
+    Semver,
+    /// Maven's `ComparableVersion`, `1.0-alpha` < `1.0-rc1` < `1.0-SNAPSHOT` < `1.0` < `1.0-sp1`
+    Maven,
+    /// RPM's `epoch:version-release`, `~` sorting before anything
+    Rpm,
+    /// [`compare`], for ecosystems without dedicated rules
+    Generic,
+}
+
+impl Scheme {
+    /// The scheme of a purl type.
+    pub fn of(ty: &str) -> Self {
+        match ty.to_lowercase().as_str() {
+            "npm" | "cargo" | "golang" | "gem" | "nuget" | "composer" | "hex" | "pub" => {
+                Self::Semver
+            }
+            "maven" => Self::Maven,
+            "rpm" => Self::Rpm,
+            _ => Self::Generic,
+        }
+    }
+
+    pub fn compare(self, a: &str, b: &str) -> Ordering {
+        match self {
+            Self::Semver => semver::compare(a, b),
+            Self::Maven => maven::compare(a, b),
+            Self::Rpm => rpm::compare(a, b),
+            Self::Generic => compare(a, b),
+        }
+    }
+
+    /// The major version, e.g. `4` of `4.3.7`, including the epoch of RPM versions.
+    pub fn major(self, version: &str) -> String {
+        let version = version.trim_start_matches(['v', 'V']);
+        let (epoch, version) = match self {
+            Self::Rpm => rpm::split_epoch(version),
+            _ => ("", version),
+        };
+        let major = version
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .next()
+            .unwrap_or_default()
+            .to_lowercase();
+        match epoch.trim_start_matches('0') {
+            "" => major,
+            epoch => format!("{}:{}", epoch, major),
+        }
+    }
+}
+
+/// Sort the versions of a package, newest first, marking whether each one is an upgrade or a
+/// downgrade of `current`.
+///
+/// With `same_major`, only versions of the major version of `current` are kept. Versions
+/// without a version in their purl are kept, and listed last.
+pub fn arrange(
+    ty: &str,
+    current: Option<&str>,
+    versions: Vec<PackageRef>,
+    same_major: bool,
+) -> Vec<PackageRef> {
+    let scheme = Scheme::of(ty);
+    let mut versions: Vec<(Option<String>, PackageRef)> = versions
+        .into_iter()
+        .map(|v| {
+            let version = PackageUrl::from_str(&v.purl)
+                .ok()
+                .and_then(|p| p.version().map(ToString::to_string));
+            (version, v)
+        })
+        .collect();
+
+    if let Some(current) = current {
+        let major = scheme.major(current);
+        if same_major {
+            versions.retain(|(version, _)| {
+                version
+                    .as_deref()
+                    .map_or(true, |v| scheme.major(v) == major)
+            });
+        }
+        for (version, package) in versions.iter_mut() {
+            package.relation = version
+                .as_deref()
+                .map(|v| match scheme.compare(v, current) {
+                    Ordering::Greater => VersionRelation::Upgrade,
+                    Ordering::Less => VersionRelation::Downgrade,
+                    Ordering::Equal => VersionRelation::Same,
+                });
+        }
+    }
+
+    versions.sort_by(|(a, _), (b, _)| match (a, b) {
+        (Some(a), Some(b)) => scheme.compare(b, a),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    });
+    versions.into_iter().map(|(_, package)| package).collect()
+}
+
+mod semver {
+    use std::cmp::Ordering;
+
+    /// Compare the dot separated identifiers of pre-releases, numeric ones lower than others.
+    fn compare_pre(a: &str, b: &str) -> Ordering {
+        let (mut a, mut b) = (a.split('.'), b.split('.'));
+        loop {
+            match (a.next(), b.next()) {
+                (Some(x), Some(y)) => {
+                    let ordering = match (x.parse::<u64>(), y.parse::<u64>()) {
+                        (Ok(x), Ok(y)) => x.cmp(&y),
+                        (Ok(_), Err(_)) => Ordering::Less,
+                        (Err(_), Ok(_)) => Ordering::Greater,
+                        (Err(_), Err(_)) => x.cmp(y),
+                    };
+                    if ordering != Ordering::Equal {
+                        return ordering;
+                    }
+                }
+                (Some(_), None) => return Ordering::Greater,
+                (None, Some(_)) => return Ordering::Less,
+                (None, None) => return Ordering::Equal,
+            }
+        }
+    }
+
+    /// The release numbers and the pre-release of a version, without build metadata.
+    fn parse(version: &str) -> (Vec<u64>, Option<&str>) {
+        let version = version.trim_start_matches(['v', 'V', '=']);
+        let version = version.split('+').next().unwrap_or_default();
+        let (release, pre) = match version.split_once('-') {
+            Some((release, pre)) => (release, Some(pre)),
+            None => (version, None),
+        };
+        let numbers = release
+            .split('.')
+            .map(|n| n.parse::<u64>().unwrap_or(0))
+            .collect();
+        (numbers, pre)
+    }
+
+    pub fn compare(a: &str, b: &str) -> Ordering {
+        let ((mut a_numbers, a_pre), (mut b_numbers, b_pre)) = (parse(a), parse(b));
+        // `1.2` is `1.2.0`
+        let len = a_numbers.len().max(b_numbers.len()).max(3);
+        a_numbers.resize(len, 0);
+        b_numbers.resize(len, 0);
+        a_numbers
+            .cmp(&b_numbers)
+            .then_with(|| match (a_pre, b_pre) {
+                (Some(a), Some(b)) => compare_pre(a, b),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            })
+    }
+}
+
+mod maven {
+    use std::cmp::Ordering;
+
+    #[derive(Debug, PartialEq, Eq)]
+    enum Item {
+        Number(u64),
+        Qualifier(String),
+    }
+
+    /// The rank of well known qualifiers, unknown ones come after them, in lexical order.
+    fn rank(qualifier: &str) -> Option<usize> {
+        match qualifier {
+            "alpha" | "a" => Some(0),
+            "beta" | "b" => Some(1),
+            "milestone" | "m" => Some(2),
+            "rc" | "cr" => Some(3),
+            "snapshot" => Some(4),
+            "" | "ga" | "final" | "release" => Some(5),
+            "sp" => Some(6),
+            _ => None,
+        }
+    }
+
+    fn compare_qualifiers(a: &str, b: &str) -> Ordering {
+        match (rank(a), rank(b)) {
+            (Some(a), Some(b)) => a.cmp(&b),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => a.cmp(b),
+        }
+    }
+
+    /// Split a version into numbers and qualifiers, at separators and digit/letter transitions.
+    fn items(version: &str) -> Vec<Item> {
+        let mut items = Vec::new();
+        let mut current = String::new();
+        let push = |current: &mut String, items: &mut Vec<Item>| {
+            if current.is_empty() {
+                return;
+            }
+            let item = match current.parse::<u64>() {
+                Ok(n) => Item::Number(n),
+                Err(_) => Item::Qualifier(current.to_lowercase()),
+            };
+            items.push(item);
+            current.clear();
+        };
+        for c in version.chars() {
+            if c == '.' || c == '-' || c == '_' {
+                push(&mut current, &mut items);
+            } else {
+                let transition = current
+                    .chars()
+                    .last()
+                    .map_or(false, |last| last.is_ascii_digit() != c.is_ascii_digit());
+                if transition {
+                    push(&mut current, &mut items);
+                }
+                current.push(c);
+            }
+        }
+        push(&mut current, &mut items);
+
+        // trailing zeros and release qualifiers don't change the version, `1.0.0` is `1`
+        while matches!(items.last(), Some(Item::Number(0)))
+            || matches!(items.last(), Some(Item::Qualifier(q)) if rank(q) == Some(5))
+        {
+            items.pop();
+        }
+        items
+    }
+
+    fn compare_items(a: Option<&Item>, b: Option<&Item>) -> Ordering {
+        match (a, b) {
+            (Some(Item::Number(a)), Some(Item::Number(b))) => a.cmp(b),
+            (Some(Item::Number(_)), Some(Item::Qualifier(_))) => Ordering::Greater,
+            (Some(Item::Qualifier(_)), Some(Item::Number(_))) => Ordering::Less,
+            (Some(Item::Qualifier(a)), Some(Item::Qualifier(b))) => compare_qualifiers(a, b),
+            // a missing item is a `0`, or the release of qualifiers
+            (Some(Item::Number(a)), None) => a.cmp(&0),
+            (None, Some(Item::Number(b))) => 0.cmp(b),
+            (Some(Item::Qualifier(a)), None) => compare_qualifiers(a, ""),
+            (None, Some(Item::Qualifier(b))) => compare_qualifiers("", b),
+            (None, None) => Ordering::Equal,
+        }
+    }
+
+    pub fn compare(a: &str, b: &str) -> Ordering {
+        let (a, b) = (items(a), items(b));
+        for i in 0..a.len().max(b.len()) {
+            let ordering = compare_items(a.get(i), b.get(i));
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+        Ordering::Equal
+    }
+}
+
+mod rpm {
+    use std::cmp::Ordering;
+
+    /// The epoch and the rest of an `epoch:version-release`, the epoch empty if there is none.
+    pub fn split_epoch(evr: &str) -> (&str, &str) {
+        match evr.split_once(':') {
+            Some((epoch, rest)) if epoch.chars().all(|c| c.is_ascii_digit()) => (epoch, rest),
+            _ => ("", evr),
+        }
+    }
+
+    /// `rpmvercmp`, comparing alternating runs of digits and letters.
+    fn vercmp(a: &str, b: &str) -> Ordering {
+        let (mut a, mut b) = (a, b);
+        loop {
+            a = a.trim_start_matches(|c: char| !c.is_ascii_alphanumeric() && c != '~' && c != '^');
+            b = b.trim_start_matches(|c: char| !c.is_ascii_alphanumeric() && c != '~' && c != '^');
+
+            // a tilde sorts before anything, even the end of the version
+            match (a.starts_with('~'), b.starts_with('~')) {
+                (true, true) => {
+                    a = &a[1..];
+                    b = &b[1..];
+                    continue;
+                }
+                (true, false) => return Ordering::Less,
+                (false, true) => return Ordering::Greater,
+                _ => {}
+            }
+            // a caret sorts after the end of the version, but before anything else
+            match (a.starts_with('^'), b.starts_with('^')) {
+                (true, true) => {
+                    a = &a[1..];
+                    b = &b[1..];
+                    continue;
+                }
+                (true, false) if b.is_empty() => return Ordering::Greater,
+                (true, false) => return Ordering::Less,
+                (false, true) if a.is_empty() => return Ordering::Less,
+                (false, true) => return Ordering::Greater,
+                _ => {}
+            }
+            if a.is_empty() || b.is_empty() {
+                return a.len().cmp(&b.len());
+            }
+
+            let numeric = a.starts_with(|c: char| c.is_ascii_digit());
+            let run = |s: &str| -> usize {
+                s.find(|c: char| c.is_ascii_digit() != numeric || !c.is_ascii_alphanumeric())
+                    .unwrap_or(s.len())
+            };
+            let (a_run, a_rest) = a.split_at(run(a));
+            let (b_run, b_rest) = b.split_at(run(b));
+            if b_run.is_empty() {
+                // a number is newer than letters
+                return if numeric {
+                    Ordering::Greater
+                } else {
+                    Ordering::Less
+                };
+            }
+
+            let ordering = if numeric {
+                let (a_run, b_run) = (a_run.trim_start_matches('0'), b_run.trim_start_matches('0'));
+                a_run.len().cmp(&b_run.len()).then_with(|| a_run.cmp(b_run))
+            } else {
+                a_run.cmp(b_run)
+            };
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+            a = a_rest;
+            b = b_rest;
+        }
+    }
+
+    pub fn compare(a: &str, b: &str) -> Ordering {
+        let ((a_epoch, a), (b_epoch, b)) = (split_epoch(a), split_epoch(b));
+        let epoch = |e: &str| e.parse::<u64>().unwrap_or(0);
+        let (a_version, a_release) = a.rsplit_once('-').unwrap_or((a, ""));
+        let (b_version, b_release) = b.rsplit_once('-').unwrap_or((b, ""));
+        epoch(a_epoch)
+            .cmp(&epoch(b_epoch))
+            .then_with(|| vercmp(a_version, b_version))
+            .then_with(|| vercmp(a_release, b_release))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_ordered(scheme: Scheme, versions: &[&str]) {
+        for pair in versions.windows(2) {
+            assert_eq!(
+                scheme.compare(pair[0], pair[1]),
+                Ordering::Less,
+                "{:?}: {} < {}",
+                scheme,
+                pair[0],
+                pair[1]
+            );
+            assert_eq!(scheme.compare(pair[1], pair[0]), Ordering::Greater);
+        }
+    }
+
+    #[test]
+    fn schemes_of_purl_types() {
+        assert_eq!(Scheme::of("npm"), Scheme::Semver);
+        assert_eq!(Scheme::of("Cargo"), Scheme::Semver);
+        assert_eq!(Scheme::of("maven"), Scheme::Maven);
+        assert_eq!(Scheme::of("rpm"), Scheme::Rpm);
+        assert_eq!(Scheme::of("pypi"), Scheme::Generic);
+    }
+
+    #[test]
+    fn generic_versions_compare_numeric_segments_numerically() {
+        assert_ordered(Scheme::Generic, &["2.9.10", "2.10.0", "2.10.0.1", "10.0"]);
+        assert_eq!(compare("1.0-A", "1.0.a"), Ordering::Equal);
+    }
+
+    #[test]
+    fn semver_pre_releases_come_before_their_release() {
+        assert_ordered(
+            Scheme::Semver,
+            &[
+                "1.0.0-alpha",
+                "1.0.0-alpha.1",
+                "1.0.0-alpha.beta",
+                "1.0.0-beta.2",
+                "1.0.0-beta.11",
+                "1.0.0-rc.1",
+                "1.0.0",
+                "1.2",
+                "v1.10.0",
+            ],
+        );
+        assert_eq!(Scheme::Semver.compare("1.2", "1.2.0"), Ordering::Equal);
+        assert_eq!(
+            Scheme::Semver.compare("1.0.0+build.1", "1.0.0+build.2"),
+            Ordering::Equal
+        );
+    }
+
+    #[test]
+    fn maven_qualifiers_are_ranked() {
+        assert_ordered(
+            Scheme::Maven,
+            &[
+                "1.0-alpha1",
+                "1.0-beta",
+                "1.0-M2",
+                "1.0-rc1",
+                "1.0-SNAPSHOT",
+                "1.0",
+                "1.0-sp1",
+                "1.0-redhat-00001",
+                "1.0.1",
+                "1.1",
+            ],
+        );
+        assert_eq!(Scheme::Maven.compare("1.0.0", "1"), Ordering::Equal);
+        assert_eq!(Scheme::Maven.compare("1.0.Final", "1.0"), Ordering::Equal);
+        assert_ordered(
+            Scheme::Maven,
+            &["4.3.4", "4.3.4.redhat-00007", "4.3.4.redhat-00010", "4.3.7"],
+        );
+    }
+
+    #[test]
+    fn rpm_versions_follow_rpmvercmp() {
+        assert_ordered(
+            Scheme::Rpm,
+            &[
+                "1.0~rc1-1",
+                "1.0-1",
+                "1.0^git1-1",
+                "1.0a-1",
+                "1.0.1-1",
+                "1.0.1-2.el8",
+                "1.0.1-10.el8",
+                "1:0.9-1",
+            ],
+        );
+        assert_eq!(Scheme::Rpm.compare("0:1.0-1", "1.0-1"), Ordering::Equal);
+        assert_eq!(Scheme::Rpm.compare("1.01-1", "1.1-1"), Ordering::Equal);
+    }
+
+    #[test]
+    fn major_versions_include_the_epoch() {
+        assert_eq!(Scheme::Semver.major("v4.3.7"), "4");
+        assert_eq!(Scheme::Maven.major("4.3.4.redhat-00007"), "4");
+        assert_eq!(Scheme::Rpm.major("1:1.1.1k-7.el8_6"), "1:1");
+        assert_eq!(Scheme::Rpm.major("0:1.1.1k-7.el8_6"), "1");
+    }
+
+    fn package(purl: &str) -> PackageRef {
+        PackageRef {
+            purl: purl.to_string(),
+            href: String::new(),
+            trusted: None,
+            unverified: false,
+            sbom: None,
+            vulnerabilities: None,
+            published: None,
+            fetched: None,
+            relation: None,
+            dependency: None,
+            degraded: vec![],
+        }
+    }
+
+    #[test]
+    fn versions_are_arranged_newest_first() {
+        let versions = vec![
+            package("pkg:maven/io.vertx/vertx-web@3.9.0"),
+            package("pkg:maven/io.vertx/vertx-web"),
+            package("pkg:maven/io.vertx/vertx-web@4.3.7"),
+            package("pkg:maven/io.vertx/vertx-web@4.3.4"),
+            package("pkg:maven/io.vertx/vertx-web@4.3.4.redhat-00007"),
+        ];
+        let arranged = arrange("maven", Some("4.3.4.redhat-00007"), versions.clone(), false);
+        let purls: Vec<&str> = arranged.iter().map(|p| p.purl.as_str()).collect();
+        assert_eq!(
+            purls,
+            vec![
+                "pkg:maven/io.vertx/vertx-web@4.3.7",
+                "pkg:maven/io.vertx/vertx-web@4.3.4.redhat-00007",
+                "pkg:maven/io.vertx/vertx-web@4.3.4",
+                "pkg:maven/io.vertx/vertx-web@3.9.0",
+                "pkg:maven/io.vertx/vertx-web",
+            ]
+        );
+        let relations: Vec<Option<VersionRelation>> = arranged.iter().map(|p| p.relation).collect();
+        assert_eq!(
+            relations,
+            vec![
+                Some(VersionRelation::Upgrade),
+                Some(VersionRelation::Same),
+                Some(VersionRelation::Downgrade),
+                Some(VersionRelation::Downgrade),
+                None,
+            ]
+        );
+
+        let same_major = arrange("maven", Some("4.3.4.redhat-00007"), versions, true);
+        assert_eq!(same_major.len(), 4);
+        assert!(same_major.iter().all(|p| !p.purl.ends_with("@3.9.0")));
+    }
+}
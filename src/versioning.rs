@@ -0,0 +1,74 @@
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use chrono::{DateTime, Utc};
+use std::future::Future;
+
+/// The prefix of the unversioned paths, deprecated aliases of the current version.
+pub const LEGACY_PREFIX: &str = "/api";
+
+/// A version of the REST API, served below its own prefix.
+///
+/// Handlers of a new version are registered next to those of the previous versions, each module
+/// choosing the handlers of a version in its `configure`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ApiVersion {
+    V1,
+}
+
+impl ApiVersion {
+    /// The version served by the unversioned paths.
+    pub const LEGACY: ApiVersion = ApiVersion::V1;
+
+    pub fn prefix(&self) -> &'static str {
+        match self {
+            Self::V1 => "/api/v1",
+        }
+    }
+}
+
+/// The path of a request, without the version prefix, e.g. `/api/package` for `/api/v1/package`.
+pub fn unversioned(path: &str) -> String {
+    match path.strip_prefix(ApiVersion::V1.prefix()) {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+            format!("{}{}", LEGACY_PREFIX, rest)
+        }
+        _ => path.to_string(),
+    }
+}
+
+/// Mark the responses of the unversioned paths as deprecated, linking to their successor.
+pub fn deprecated<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+    sunset: Option<DateTime<Utc>>,
+) -> impl Future<Output = Result<ServiceResponse<B>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+{
+    let successor = req.path().strip_prefix(LEGACY_PREFIX).map(|rest| {
+        format!(
+            "<{}{}>; rel=\"successor-version\"",
+            ApiVersion::LEGACY.prefix(),
+            rest
+        )
+    });
+    let fut = srv.call(req);
+    async move {
+        let mut res = fut.await?;
+        let headers = res.headers_mut();
+        headers.insert(
+            HeaderName::from_static("deprecation"),
+            HeaderValue::from_static("true"),
+        );
+        if let Some(sunset) = sunset {
+            let date = sunset.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+            if let Ok(value) = HeaderValue::from_str(&date) {
+                headers.insert(HeaderName::from_static("sunset"), value);
+            }
+        }
+        if let Some(value) = successor.and_then(|s| HeaderValue::from_str(&s).ok()) {
+            headers.append(actix_web::http::header::LINK, value);
+        }
+        Ok(res)
+    }
+}
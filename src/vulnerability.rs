@@ -1,8 +1,12 @@
+use crate::cache::Cache;
 use crate::guac::Guac;
+use crate::package::{self, scoped_key};
+use crate::sbom::SbomRegistry;
 use actix_web::{error, get, http::StatusCode, web, web::ServiceConfig, HttpResponse};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use thiserror::Error;
+use utoipa::{OpenApi, ToSchema};
 
 pub use trust_api_model::vuln::*;
 
@@ -12,59 +16,121 @@ pub(crate) fn configure() -> impl FnOnce(&mut ServiceConfig) {
     }
 }
 
+/// The documentation of the vulnerability endpoint.
+#[derive(OpenApi)]
+#[openapi(
+    paths(query_vulnerability),
+    components(schemas(Vulnerability, Cvss3, ApiError))
+)]
+pub(crate) struct ApiDoc;
+
 #[derive(serde::Deserialize)]
 pub struct VulnerabilityQuery {
     cve: Option<String>,
 }
 
 #[utoipa::path(
+    context_path = "/api/v1",
     responses(
         (status = 200, description = "Vulnerability found", body = Vulnerability),
-        (status = NOT_FOUND, description = "Vulnerability was not found"),
-        (status = BAD_REQUEST, description = "Missing query argument")
+        (status = NOT_FOUND, description = "Vulnerability was not found", body = Problem, example = json!(crate::problem::example(ApiError::NotFound { cve: "cve-2099-0001".to_string() }))),
+        (status = BAD_REQUEST, description = "Missing query argument", body = Problem, example = json!(crate::problem::example(ApiError::MissingQueryArgument))),
+        (status = BAD_REQUEST, description = "Not a CVE id", body = Problem, example = json!(crate::problem::example(ApiError::InvalidCve { cve: "cve-2099".to_string() }))),
+        (status = BAD_GATEWAY, description = "Guac failed", body = Problem, example = json!(crate::problem::example(ApiError::Upstream(package::ApiError::UpstreamError { upstream: "guac".to_string(), status: 502, correlation_id: "5b0e1f3c-8a8e-4a77-9a3d-7f6b2c1d9e40".to_string() })))),
+        (status = SERVICE_UNAVAILABLE, description = "Guac could not be reached", body = Problem, example = json!(crate::problem::example(ApiError::Upstream(package::ApiError::UpstreamUnavailable { upstream: "guac".to_string(), correlation_id: "5b0e1f3c-8a8e-4a77-9a3d-7f6b2c1d9e40".to_string() }))))
     ),
     params(
         ("cve" = String, Query, description = "CVE id to query"),
     )
 )]
-#[get("/api/vulnerability")]
+#[get("/vulnerability")]
 pub async fn query_vulnerability(
     query: web::Query<VulnerabilityQuery>,
     guac: web::Data<Arc<Guac>>,
+    cache: web::Data<Arc<Cache>>,
+    sboms: web::Data<Arc<SbomRegistry>>,
 ) -> Result<HttpResponse, ApiError> {
     if let Some(cve) = &query.cve {
-        Ok(
-            HttpResponse::Ok().json(guac.get_vulnerability(cve).await.map_err(|_| {
-                ApiError::NotFound {
-                    cve: cve.to_string(),
-                }
-            })?),
-        )
+        if !is_cve_id(cve) {
+            return Err(ApiError::InvalidCve {
+                cve: cve.to_string(),
+            });
+        }
+        // the affected packages and their SBOM links depend on the SBOMs visible to the caller
+        let key = scoped_key("vulnerability", cve, crate::tenant::current());
+        if let Some(v) = cache.get::<Vulnerability>(&key).await {
+            return Ok(HttpResponse::Ok().json(v));
+        }
+
+        let mut v = guac
+            .get_vulnerability(cve)
+            .await
+            .map_err(|e| ApiError::Upstream(package::ApiError::upstream("guac", e)))?
+            .ok_or_else(|| ApiError::NotFound {
+                cve: cve.to_string(),
+            })?;
+        v.packages.retain(|p| !sboms.is_hidden(&p.purl));
+        cache.put(&key, &v).await;
+        Ok(HttpResponse::Ok().json(v))
     } else {
         Err(ApiError::MissingQueryArgument)
     }
 }
 
-#[derive(Debug, Error, Serialize, Deserialize)]
+/// Whether an id is a CVE id, like `CVE-2023-24815`, ignoring case.
+fn is_cve_id(id: &str) -> bool {
+    let digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    match id.splitn(3, '-').collect::<Vec<_>>()[..] {
+        [prefix, year, number] => {
+            prefix.eq_ignore_ascii_case("cve") && year.len() == 4 && digits(year) && digits(number)
+        }
+        _ => false,
+    }
+}
+
+#[derive(Debug, Error, Serialize, Deserialize, ToSchema)]
+#[schema(as = VulnerabilityApiError)]
 pub enum ApiError {
     #[error("No query argument was specified")]
     MissingQueryArgument,
+    #[error("{cve} is not a CVE id")]
+    InvalidCve { cve: String },
     #[error("CVE {cve} was not found")]
     NotFound { cve: String },
+    #[error(transparent)]
+    Upstream(#[schema(inline)] package::ApiError),
 }
 
 impl error::ResponseError for ApiError {
     fn error_response(&self) -> HttpResponse {
-        HttpResponse::build(self.status_code()).json(serde_json::json!({
-            "status": self.status_code().as_u16(),
-            "error": self.to_string(),
-        }))
+        match self {
+            ApiError::Upstream(e) => e.error_response(),
+            _ => crate::problem::response(self.status_code(), self, serde_json::json!({})),
+        }
     }
 
     fn status_code(&self) -> StatusCode {
         match self {
             ApiError::MissingQueryArgument => StatusCode::BAD_REQUEST,
+            ApiError::InvalidCve { cve: _ } => StatusCode::BAD_REQUEST,
             ApiError::NotFound { cve: _ } => StatusCode::NOT_FOUND,
+            ApiError::Upstream(e) => e.status_code(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_cve_ids_are_accepted() {
+        assert!(is_cve_id("CVE-2023-24815"));
+        assert!(is_cve_id("cve-2023-1234567"));
+        assert!(!is_cve_id("CVE-2023"));
+        assert!(!is_cve_id("CVE-23-1234"));
+        assert!(!is_cve_id("GHSA-qppj-fm5r-hxr3"));
+        assert!(!is_cve_id("CVE-2023-1234/../../admin"));
+        assert!(!is_cve_id("CVE-2023-1234?x=1"));
+    }
+}
@@ -0,0 +1,394 @@
+use actix_web::{
+    delete, error, get, http::StatusCode, post, web, web::ServiceConfig, HttpResponse,
+};
+use chrono::{DateTime, Utc};
+use core::str::FromStr;
+use futures::future::BoxFuture;
+use packageurl::PackageUrl;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+use thiserror::Error;
+use tokio::sync::Mutex;
+use utoipa::{OpenApi, ToSchema};
+
+use crate::config::WatchConfig;
+use crate::outbound::OutboundError;
+use crate::package::TrustedContent;
+use crate::sbom::SbomRegistry;
+use crate::scheduler::Job;
+use crate::storage::Storage;
+use crate::webhook::Webhooks;
+
+pub(crate) fn configure() -> impl FnOnce(&mut ServiceConfig) {
+    |config: &mut ServiceConfig| {
+        config.service(create_watch);
+        config.service(list_watches);
+        config.service(delete_watch);
+    }
+}
+
+/// The documentation of the watch endpoints.
+#[derive(OpenApi)]
+#[openapi(
+    paths(create_watch, list_watches, delete_watch),
+    components(schemas(WatchRequest, Watch, ApiError))
+)]
+pub(crate) struct ApiDoc;
+
+/// A purl, or a purl prefix ending with `*`, and the URL to notify when its findings change.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[schema(example = json!({
+    "purl": "pkg:maven/io.vertx/*",
+    "callback": "https://ci.example.com/hooks/trust"
+}))]
+pub struct WatchRequest {
+    pub purl: String,
+    pub callback: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[schema(example = json!({
+    "id": 1,
+    "purl": "pkg:maven/io.vertx/*",
+    "callback": "https://ci.example.com/hooks/trust",
+    "created": "2023-03-01T08:00:00Z"
+}))]
+pub struct Watch {
+    pub id: u64,
+    pub purl: String,
+    pub callback: String,
+    pub created: DateTime<Utc>,
+    /// The tenant of the caller who created the watch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+}
+
+impl Watch {
+    fn matches(&self, purl: &str) -> bool {
+        match self.purl.strip_suffix('*') {
+            Some(prefix) => purl.starts_with(prefix),
+            None => purl == self.purl,
+        }
+    }
+
+    /// Whether the running task may list and delete the watch, callers only manage the watches of
+    /// their own tenant.
+    fn is_visible(&self) -> bool {
+        match crate::tenant::current() {
+            None => true,
+            Some(tenant) => tenant == self.tenant,
+        }
+    }
+}
+
+/// The payload sent when the findings of a watched purl change.
+#[derive(Clone, Debug, Serialize)]
+struct Notification<'a> {
+    watch: u64,
+    purl: &'a str,
+    added: Vec<&'a String>,
+    removed: Vec<&'a String>,
+    vulnerabilities: &'a BTreeSet<String>,
+}
+
+/// A watch and the vulnerabilities last found for each matching purl.
+#[derive(Clone, Serialize, Deserialize)]
+struct Entry {
+    #[serde(flatten)]
+    watch: Watch,
+    #[serde(default)]
+    findings: BTreeMap<String, BTreeSet<String>>,
+}
+
+/// The storage key of the watches.
+const KEY: &str = "watches.json";
+
+/// Watches, persisted to the storage alongside their last findings.
+struct WatchStore {
+    storage: Arc<dyn Storage>,
+    entries: Vec<Entry>,
+}
+
+impl WatchStore {
+    async fn load(storage: Arc<dyn Storage>) -> Result<Self, anyhow::Error> {
+        let entries = match storage.get(KEY).await? {
+            Some(data) => serde_json::from_slice(&data)?,
+            None => Vec::new(),
+        };
+        Ok(Self { storage, entries })
+    }
+
+    async fn save(&self) -> Result<(), anyhow::Error> {
+        self.storage
+            .put(KEY, serde_json::to_vec(&self.entries)?)
+            .await
+    }
+
+    fn next_id(&self) -> u64 {
+        self.entries.iter().map(|e| e.watch.id).max().unwrap_or(0) + 1
+    }
+}
+
+/// Re-evaluates the vulnerabilities of watched purls, notifying the callbacks of changes
+/// through signed webhooks.
+pub struct Watches {
+    store: Mutex<WatchStore>,
+    allowed_callback_hosts: Vec<String>,
+    webhooks: Arc<Webhooks>,
+    trusted: Arc<TrustedContent>,
+    sboms: Arc<SbomRegistry>,
+}
+
+impl Watches {
+    pub async fn new(
+        config: &WatchConfig,
+        webhooks: Arc<Webhooks>,
+        trusted: Arc<TrustedContent>,
+        sboms: Arc<SbomRegistry>,
+        storage: Arc<dyn Storage>,
+    ) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            store: Mutex::new(WatchStore::load(storage).await?),
+            allowed_callback_hosts: config.allowed_callback_hosts.clone(),
+            webhooks,
+            trusted,
+            sboms,
+        })
+    }
+
+    async fn add(&self, request: WatchRequest) -> Result<Watch, anyhow::Error> {
+        let mut store = self.store.lock().await;
+        let watch = Watch {
+            id: store.next_id(),
+            purl: request.purl,
+            callback: request.callback,
+            created: Utc::now(),
+            tenant: crate::tenant::current().flatten(),
+        };
+        store.entries.push(Entry {
+            watch: watch.clone(),
+            findings: BTreeMap::new(),
+        });
+        store.save().await?;
+        Ok(watch)
+    }
+
+    async fn list(&self) -> Vec<Watch> {
+        let store = self.store.lock().await;
+        store
+            .entries
+            .iter()
+            .filter(|e| e.watch.is_visible())
+            .map(|e| e.watch.clone())
+            .collect()
+    }
+
+    async fn remove(&self, id: u64) -> Result<bool, anyhow::Error> {
+        let mut store = self.store.lock().await;
+        let len = store.entries.len();
+        store
+            .entries
+            .retain(|e| e.watch.id != id || !e.watch.is_visible());
+        if store.entries.len() == len {
+            return Ok(false);
+        }
+        store.save().await?;
+        Ok(true)
+    }
+
+    /// The purls a watch applies to, prefixes are resolved against the SBOM registry.
+    fn purls(&self, watch: &Watch) -> Vec<String> {
+        if watch.purl.ends_with('*') {
+            self.sboms
+                .purls()
+                .into_iter()
+                .filter(|p| watch.matches(p))
+                .collect()
+        } else {
+            vec![watch.purl.clone()]
+        }
+    }
+
+    /// Evaluate all watches once.
+    ///
+    /// The first evaluation of a purl only records its findings, later ones notify changes. Each
+    /// watch is evaluated on behalf of its tenant, seeing only the SBOMs of it.
+    async fn evaluate(&self) -> Result<(), anyhow::Error> {
+        let entries = self.store.lock().await.entries.clone();
+        for entry in entries {
+            let tenant = entry.watch.tenant.clone();
+            crate::tenant::scoped(tenant, self.evaluate_watch(entry)).await?;
+        }
+        Ok(())
+    }
+
+    async fn evaluate_watch(&self, entry: Entry) -> Result<(), anyhow::Error> {
+        let Entry {
+            watch,
+            findings: previous,
+        } = entry;
+        let mut findings = BTreeMap::new();
+        for purl in self.purls(&watch) {
+            let vulnerabilities: BTreeSet<String> = match self.trusted.get_trusted(&purl).await {
+                Ok(package) => package.vulnerabilities.into_iter().map(|v| v.cve).collect(),
+                Err(e) => {
+                    log::debug!("Unable to evaluate {} for watch {}: {}", purl, watch.id, e);
+                    // keep the previous findings, to not report them as changed
+                    match previous.get(&purl) {
+                        Some(previous) => previous.clone(),
+                        None => continue,
+                    }
+                }
+            };
+
+            if let Some(previous) = previous.get(&purl) {
+                if previous != &vulnerabilities {
+                    // the host may resolve to other addresses by now
+                    if let Err(e) =
+                        crate::outbound::check(&watch.callback, &self.allowed_callback_hosts).await
+                    {
+                        log::warn!("Not notifying watch {}: {}", watch.id, e);
+                        findings.insert(purl, previous.clone());
+                        continue;
+                    }
+                    let notification = Notification {
+                        watch: watch.id,
+                        purl: &purl,
+                        added: vulnerabilities.difference(previous).collect(),
+                        removed: previous.difference(&vulnerabilities).collect(),
+                        vulnerabilities: &vulnerabilities,
+                    };
+                    self.webhooks
+                        .enqueue(&watch.callback, serde_json::to_value(&notification)?)
+                        .await?;
+                }
+            }
+            findings.insert(purl, vulnerabilities);
+        }
+
+        let mut store = self.store.lock().await;
+        if let Some(e) = store.entries.iter_mut().find(|e| e.watch.id == watch.id) {
+            e.findings = findings;
+        }
+        store.save().await?;
+        Ok(())
+    }
+}
+
+impl Job for Watches {
+    fn name(&self) -> &'static str {
+        "watch evaluation"
+    }
+
+    fn run(&self) -> BoxFuture<'_, Result<(), anyhow::Error>> {
+        Box::pin(self.evaluate())
+    }
+}
+
+/// Notify a callback URL whenever the vulnerabilities of a purl, or purls matching a prefix, change.
+#[utoipa::path(
+    context_path = "/api/v1",
+    request_body = WatchRequest,
+    responses(
+        (status = CREATED, description = "Watch created", body = Watch),
+        (status = BAD_REQUEST, description = "Invalid package URL or callback URL", body = Problem, example = json!(crate::problem::example(ApiError::InvalidCallback { url: "ftp://example.com/hook".to_string() }))),
+        (status = FORBIDDEN, description = "The callback URL reaches an internal address or a host which is not allowed", body = Problem, example = json!(crate::problem::example(ApiError::CallbackNotAllowed { url: "http://169.254.169.254/latest".to_string(), reason: "169.254.169.254 resolves to the non-public address 169.254.169.254".to_string() }))),
+    )
+)]
+#[post("/watch")]
+pub async fn create_watch(
+    watches: web::Data<Arc<Watches>>,
+    body: web::Json<WatchRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let request = body.into_inner();
+
+    if !request.purl.ends_with('*') && PackageUrl::from_str(&request.purl).is_err() {
+        return Err(ApiError::InvalidPackageUrl { purl: request.purl });
+    }
+    match crate::outbound::check(&request.callback, &watches.allowed_callback_hosts).await {
+        Ok(()) => {}
+        Err(OutboundError::Invalid { .. }) => {
+            return Err(ApiError::InvalidCallback {
+                url: request.callback,
+            })
+        }
+        Err(e) => {
+            return Err(ApiError::CallbackNotAllowed {
+                url: request.callback,
+                reason: e.to_string(),
+            })
+        }
+    }
+
+    let watch = watches
+        .add(request)
+        .await
+        .map_err(|_| ApiError::InternalError)?;
+    Ok(HttpResponse::Created().json(watch))
+}
+
+#[utoipa::path(
+    context_path = "/api/v1",
+    responses(
+        (status = 200, description = "All watches", body = Vec<Watch>),
+    )
+)]
+#[get("/watch")]
+pub async fn list_watches(watches: web::Data<Arc<Watches>>) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok().json(watches.list().await))
+}
+
+#[utoipa::path(
+    context_path = "/api/v1",
+    responses(
+        (status = NO_CONTENT, description = "Watch deleted"),
+        (status = NOT_FOUND, description = "Watch not found", body = Problem, example = json!(crate::problem::example(ApiError::WatchNotFound { id: 42 }))),
+    ),
+    params(
+        ("id" = u64, Path, description = "The id of the watch"),
+    )
+)]
+#[delete("/watch/{id}")]
+pub async fn delete_watch(
+    watches: web::Data<Arc<Watches>>,
+    id: web::Path<u64>,
+) -> Result<HttpResponse, ApiError> {
+    let id = id.into_inner();
+    match watches.remove(id).await {
+        Ok(true) => Ok(HttpResponse::NoContent().finish()),
+        Ok(false) => Err(ApiError::WatchNotFound { id }),
+        Err(_) => Err(ApiError::InternalError),
+    }
+}
+
+#[derive(Debug, Error, Serialize, Deserialize, ToSchema)]
+#[schema(as = WatchApiError)]
+pub enum ApiError {
+    #[error("{purl} is not a valid package URL or prefix")]
+    InvalidPackageUrl { purl: String },
+    #[error("{url} is not a valid callback URL")]
+    InvalidCallback { url: String },
+    #[error("{url} is not an allowed callback URL: {reason}")]
+    CallbackNotAllowed { url: String, reason: String },
+    #[error("Watch {id} was not found")]
+    WatchNotFound { id: u64 },
+    #[error("Error processing error internally")]
+    InternalError,
+}
+
+impl error::ResponseError for ApiError {
+    fn error_response(&self) -> HttpResponse {
+        crate::problem::response(self.status_code(), self, serde_json::json!({}))
+    }
+
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::InvalidPackageUrl { purl: _ } => StatusCode::BAD_REQUEST,
+            ApiError::InvalidCallback { url: _ } => StatusCode::BAD_REQUEST,
+            ApiError::CallbackNotAllowed { .. } => StatusCode::FORBIDDEN,
+            ApiError::WatchNotFound { id: _ } => StatusCode::NOT_FOUND,
+            ApiError::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
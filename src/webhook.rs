@@ -0,0 +1,429 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+use crate::config::WebhookConfig;
+use crate::signing::{self, Signer};
+use crate::storage::Storage;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryStatus {
+    Pending,
+    Delivered,
+    Failed,
+}
+
+/// A single webhook notification, and the state of delivering it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Delivery {
+    pub id: u64,
+    pub url: String,
+    pub payload: serde_json::Value,
+    pub status: DeliveryStatus,
+    pub attempts: u32,
+    pub created: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_attempt: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+/// The storage keys of deliveries start with this, each delivery is stored on its own.
+const PREFIX: &str = "webhook/deliveries/";
+
+/// Where all deliveries were stored at once before, moved to their own keys when loading.
+const LEGACY_KEY: &str = "webhook/deliveries.json";
+
+fn key(id: u64) -> String {
+    format!("{}{}.json", PREFIX, id)
+}
+
+/// Deliveries, persisted to the storage so that pending and failed deliveries survive restarts.
+///
+/// Each delivery is written on its own when it changes, finished deliveries are removed once
+/// they are older than the retention period, or the oldest ones beyond the maximum number.
+struct DeliveryStore {
+    storage: Arc<dyn Storage>,
+    /// Ordered by their ID, the oldest first
+    deliveries: Vec<Delivery>,
+    next_id: u64,
+    retention: Option<chrono::Duration>,
+    max_deliveries: usize,
+}
+
+impl DeliveryStore {
+    async fn load(
+        config: &WebhookConfig,
+        storage: Arc<dyn Storage>,
+    ) -> Result<Self, anyhow::Error> {
+        let mut deliveries: Vec<Delivery> = Vec::new();
+        for key in storage.list(PREFIX).await? {
+            if let Some(data) = storage.get(&key).await? {
+                deliveries.push(serde_json::from_slice(&data)?);
+            }
+        }
+        if let Some(data) = storage.get(LEGACY_KEY).await? {
+            let legacy: Vec<Delivery> = serde_json::from_slice(&data)?;
+            for delivery in legacy {
+                if !deliveries.iter().any(|d| d.id == delivery.id) {
+                    storage
+                        .put(&key(delivery.id), serde_json::to_vec(&delivery)?)
+                        .await?;
+                    deliveries.push(delivery);
+                }
+            }
+            storage.delete(LEGACY_KEY).await?;
+        }
+        deliveries.sort_by_key(|d| d.id);
+        let next_id = deliveries.last().map_or(0, |d| d.id) + 1;
+        Ok(Self {
+            storage,
+            deliveries,
+            next_id,
+            retention: (config.retention_secs > 0)
+                .then(|| chrono::Duration::seconds(config.retention_secs as i64)),
+            max_deliveries: config.max_deliveries,
+        })
+    }
+
+    async fn save(&self, delivery: &Delivery) -> Result<(), anyhow::Error> {
+        self.storage
+            .put(&key(delivery.id), serde_json::to_vec(delivery)?)
+            .await
+    }
+
+    async fn add(&mut self, delivery: Delivery) -> Result<(), anyhow::Error> {
+        self.save(&delivery).await?;
+        self.next_id = self.next_id.max(delivery.id + 1);
+        self.deliveries.push(delivery);
+        Ok(())
+    }
+
+    /// Remove the finished deliveries past the retention period or the maximum number, the
+    /// oldest first, returning how many were. Pending deliveries are kept.
+    async fn prune(&mut self, now: DateTime<Utc>) -> Result<usize, anyhow::Error> {
+        let mut excess = self.deliveries.len().saturating_sub(self.max_deliveries);
+        let expired = |d: &Delivery| self.retention.map_or(false, |r| d.created < now - r);
+        let mut pruned = Vec::new();
+        for delivery in &self.deliveries {
+            if delivery.status != DeliveryStatus::Pending && (excess > 0 || expired(delivery)) {
+                excess = excess.saturating_sub(1);
+                pruned.push(delivery.id);
+            }
+        }
+        for id in &pruned {
+            self.storage.delete(&key(*id)).await?;
+        }
+        self.deliveries.retain(|d| !pruned.contains(&d.id));
+        Ok(pruned.len())
+    }
+}
+
+/// Queue-backed delivery of webhook notifications.
+///
+/// Notifications are stored before they are sent, and retried by a background task until they
+/// are delivered or the maximum number of attempts is reached. Failed deliveries can be replayed.
+pub struct Webhooks {
+    store: Mutex<DeliveryStore>,
+    client: reqwest::Client,
+    signer: Signer,
+    max_attempts: u32,
+    /// The hosts deliveries may be sent to, see [`crate::outbound::check`]
+    allowed_hosts: Vec<String>,
+    /// The URLs of the configuration, e.g. of the ingestion of deps.dev packages, which may be
+    /// internal
+    configured_urls: Vec<String>,
+}
+
+impl Webhooks {
+    pub async fn new(
+        config: &WebhookConfig,
+        signer: Signer,
+        storage: Arc<dyn Storage>,
+        allowed_hosts: Vec<String>,
+        configured_urls: Vec<String>,
+    ) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            store: Mutex::new(DeliveryStore::load(config, storage).await?),
+            // callbacks may redirect, but not to the network of the service
+            client: reqwest::Client::builder()
+                .redirect(crate::outbound::redirect_policy())
+                .build()?,
+            signer,
+            max_attempts: config.max_attempts,
+            allowed_hosts,
+            configured_urls,
+        })
+    }
+
+    /// Queue a notification for delivery.
+    pub async fn enqueue(
+        &self,
+        url: &str,
+        payload: serde_json::Value,
+    ) -> Result<Delivery, anyhow::Error> {
+        let mut store = self.store.lock().await;
+        let delivery = Delivery {
+            id: store.next_id,
+            url: url.to_string(),
+            payload,
+            status: DeliveryStatus::Pending,
+            attempts: 0,
+            created: Utc::now(),
+            last_attempt: None,
+            last_error: None,
+        };
+        store.add(delivery.clone()).await?;
+        store.prune(Utc::now()).await?;
+        Ok(delivery)
+    }
+
+    /// How many deliveries to a URL are still pending.
+    pub async fn pending(&self, url: &str) -> usize {
+        let store = self.store.lock().await;
+        store
+            .deliveries
+            .iter()
+            .filter(|d| d.status == DeliveryStatus::Pending && d.url == url)
+            .count()
+    }
+
+    pub async fn deliveries(&self, status: Option<DeliveryStatus>) -> Vec<Delivery> {
+        let store = self.store.lock().await;
+        store
+            .deliveries
+            .iter()
+            .filter(|d| status.map_or(true, |s| d.status == s))
+            .cloned()
+            .collect()
+    }
+
+    /// Re-queue all failed deliveries created in the given time range, returning their number.
+    pub async fn replay(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<usize, anyhow::Error> {
+        let mut store = self.store.lock().await;
+        let mut replayed = 0;
+        for index in 0..store.deliveries.len() {
+            let delivery = &mut store.deliveries[index];
+            if delivery.status == DeliveryStatus::Failed
+                && delivery.created >= from
+                && delivery.created <= to
+            {
+                delivery.status = DeliveryStatus::Pending;
+                delivery.attempts = 0;
+                store.save(&store.deliveries[index]).await?;
+                replayed += 1;
+            }
+        }
+        Ok(replayed)
+    }
+
+    /// Attempt to send all pending deliveries once.
+    async fn deliver_pending(&self) -> Result<(), anyhow::Error> {
+        let pending = self.deliveries(Some(DeliveryStatus::Pending)).await;
+        for delivery in pending {
+            let body = serde_json::to_vec(&delivery.payload)?;
+            let mut request = self
+                .client
+                .post(&delivery.url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json");
+            if let Some(signature) = self.signer.sign(&body) {
+                request = request
+                    .header(signing::TIMESTAMP_HEADER, signature.timestamp)
+                    .header(signing::SIGNATURE_HEADER, signature.signature);
+            }
+            // checked on every attempt, the host may resolve to other addresses by now
+            let checked = match self.configured_urls.contains(&delivery.url) {
+                true => Ok(()),
+                false => crate::outbound::check(&delivery.url, &self.allowed_hosts).await,
+            };
+            let result = match checked {
+                Ok(()) => request
+                    .body(body)
+                    .send()
+                    .await
+                    .and_then(|r| r.error_for_status())
+                    .map_err(anyhow::Error::from),
+                Err(e) => Err(e.into()),
+            };
+
+            let mut store = self.store.lock().await;
+            let index = match store.deliveries.iter().position(|d| d.id == delivery.id) {
+                Some(index) => index,
+                None => continue,
+            };
+            {
+                let d = &mut store.deliveries[index];
+                d.attempts += 1;
+                d.last_attempt = Some(Utc::now());
+                match result {
+                    Ok(_) => {
+                        d.status = DeliveryStatus::Delivered;
+                        d.last_error = None;
+                    }
+                    Err(e) => {
+                        log::warn!("Error delivering webhook {} to {}: {}", d.id, d.url, e);
+                        d.last_error = Some(e.to_string());
+                        if d.attempts >= self.max_attempts {
+                            d.status = DeliveryStatus::Failed;
+                        }
+                    }
+                }
+            }
+            store.save(&store.deliveries[index]).await?;
+        }
+        self.store.lock().await.prune(Utc::now()).await?;
+        Ok(())
+    }
+
+    /// Run the background delivery loop.
+    pub async fn run(self: Arc<Self>, interval: Duration) {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.deliver_pending().await {
+                log::warn!("Error processing webhook deliveries: {:?}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::FileStorage;
+    use serde_json::json;
+
+    fn storage() -> (std::path::PathBuf, Arc<dyn Storage>) {
+        let dir = std::env::temp_dir().join(format!("trust-api-webhook-{}", uuid::Uuid::new_v4()));
+        (dir.clone(), Arc::new(FileStorage::new(dir)))
+    }
+
+    fn delivery(id: u64, status: DeliveryStatus, created: DateTime<Utc>) -> Delivery {
+        Delivery {
+            id,
+            url: "https://example.com/hook".to_string(),
+            payload: json!({}),
+            status,
+            attempts: 0,
+            created,
+            last_attempt: None,
+            last_error: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn finished_deliveries_are_removed_after_the_retention_period() {
+        let (dir, storage) = storage();
+        let config = WebhookConfig {
+            retention_secs: 60,
+            max_deliveries: 10,
+            ..Default::default()
+        };
+        let mut store = DeliveryStore::load(&config, storage.clone()).await.unwrap();
+        let old = Utc::now() - chrono::Duration::minutes(5);
+        store
+            .add(delivery(1, DeliveryStatus::Delivered, old))
+            .await
+            .unwrap();
+        store
+            .add(delivery(2, DeliveryStatus::Pending, old))
+            .await
+            .unwrap();
+        store
+            .add(delivery(3, DeliveryStatus::Failed, Utc::now()))
+            .await
+            .unwrap();
+
+        assert_eq!(store.prune(Utc::now()).await.unwrap(), 1);
+        assert_eq!(storage.list(PREFIX).await.unwrap(), vec![key(2), key(3)]);
+
+        let store = DeliveryStore::load(&config, storage).await.unwrap();
+        assert_eq!(store.deliveries.len(), 2);
+        assert_eq!(store.next_id, 4);
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn the_oldest_finished_deliveries_are_removed_beyond_the_maximum() {
+        let (dir, storage) = storage();
+        let config = WebhookConfig {
+            retention_secs: 0,
+            max_deliveries: 2,
+            ..Default::default()
+        };
+        let mut store = DeliveryStore::load(&config, storage).await.unwrap();
+        let old = Utc::now() - chrono::Duration::days(365);
+        for (id, status) in [
+            (1, DeliveryStatus::Pending),
+            (2, DeliveryStatus::Delivered),
+            (3, DeliveryStatus::Failed),
+            (4, DeliveryStatus::Delivered),
+        ] {
+            store.add(delivery(id, status, old)).await.unwrap();
+        }
+
+        assert_eq!(store.prune(Utc::now()).await.unwrap(), 2);
+        let ids: Vec<_> = store.deliveries.iter().map(|d| d.id).collect();
+        assert_eq!(ids, vec![1, 4]);
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn deliveries_stored_at_once_are_moved_to_their_own_keys() {
+        let (dir, storage) = storage();
+        let legacy = vec![
+            delivery(1, DeliveryStatus::Failed, Utc::now()),
+            delivery(2, DeliveryStatus::Pending, Utc::now()),
+        ];
+        storage
+            .put(LEGACY_KEY, serde_json::to_vec(&legacy).unwrap())
+            .await
+            .unwrap();
+
+        let store = DeliveryStore::load(&Default::default(), storage.clone())
+            .await
+            .unwrap();
+        assert_eq!(store.deliveries, legacy);
+        assert!(storage.get(LEGACY_KEY).await.unwrap().is_none());
+        assert_eq!(storage.list(PREFIX).await.unwrap(), vec![key(1), key(2)]);
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn deliveries_to_internal_addresses_are_not_sent() {
+        let (dir, storage) = storage();
+        let config = WebhookConfig {
+            max_attempts: 1,
+            ..Default::default()
+        };
+        let webhooks = Webhooks::new(
+            &config,
+            Signer::new(Vec::new()),
+            storage,
+            Vec::new(),
+            Vec::new(),
+        )
+        .await
+        .unwrap();
+        webhooks
+            .enqueue("http://127.0.0.1:9/hook", json!({}))
+            .await
+            .unwrap();
+
+        webhooks.deliver_pending().await.unwrap();
+        let failed = webhooks.deliveries(Some(DeliveryStatus::Failed)).await;
+        assert_eq!(failed.len(), 1);
+        assert_eq!(
+            failed[0].last_error.as_deref(),
+            Some("127.0.0.1 resolves to the non-public address 127.0.0.1")
+        );
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
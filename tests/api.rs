@@ -0,0 +1,1210 @@
+//! End-to-end tests of the REST API, through all the middleware of the listeners, with Guac,
+//! Snyk and the security data API served by the recording harness.
+//!
+//! The tests of lookups replay the exchanges of `tests/fixtures/recordings.json`, failing on
+//! calls without a recorded exchange. Set `TRUST_API_RECORD_GUAC` to record the exchanges from
+//! real services instead. The other tests are answered by the mocks of the dev mode.
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{Service, ServiceResponse};
+use actix_web::http::{header, StatusCode};
+use actix_web::test::{self, TestRequest};
+use actix_web::{web, App, HttpResponse, HttpServer};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine as _;
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use std::time::Duration;
+use tracing_subscriber::EnvFilter;
+use trust_api::config::Config;
+use trust_api::engine::Engine;
+use trust_api::recording::{Harness, Mode};
+use trust_api::server::Apps;
+use trust_api::storage::StorageConfig;
+
+const VERTX: &str = "pkg:maven/io.vertx/vertx-web@4.3.7";
+const VERTX_REDHAT: &str = "pkg:maven/io.vertx/vertx-web@4.3.4.redhat-00007";
+const UNKNOWN: &str = "pkg:maven/org.example/unknown@1.0";
+const JAVA_EXAMPLE: &str = "pkg:maven/io.seedwing/seedwing-java-example@1.0.0-SNAPSHOT?type=jar";
+const PROBLEM_JSON: &str = "application/problem+json";
+const ADMIN_TOKEN: &str = "admin-token";
+const ISSUER: &str = "https://sso.example.com";
+/// Signs the ID tokens of [`ISSUER`]
+const SECRET: &[u8] = b"a secret only the tests know!!";
+
+/// Replay the recorded exchanges, or record them with `TRUST_API_RECORD_GUAC` set.
+fn recorded() -> Mode {
+    Mode::from_env()
+}
+
+fn mocked() -> Mode {
+    Mode::mock().unwrap()
+}
+
+/// The applications of the listeners with the built-in SBOMs, a `release` gate policy, an
+/// `example-1.0` product and the admin token [`ADMIN_TOKEN`], their data in a directory of their
+/// own.
+///
+/// Keep the harness until the test is done, dropping it fails the test if calls were not
+/// replayed.
+async fn apps(mode: Mode) -> (Apps, Harness) {
+    apps_with(mode, |_| {}).await
+}
+
+/// Like [`apps`], the configuration changed by `configure` before the engine is built.
+async fn apps_with<F: FnOnce(&mut Config)>(mode: Mode, configure: F) -> (Apps, Harness) {
+    let _ = tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .with_test_writer()
+        .try_init();
+    let recordings = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/recordings.json");
+    let harness = Harness::start(mode, &recordings).await.unwrap();
+
+    let dir = std::env::temp_dir().join(format!("trust-api-test-{}", uuid::Uuid::new_v4()));
+    let mut config = Config::default();
+    harness.apply(&mut config);
+    // calls the mocks don't answer fail at once, without opening the circuit for the others
+    config.guac.resilience.retries = 0;
+    config.guac.resilience.breaker_threshold = u32::MAX;
+    // the index would be built from Guac while the tests run
+    config.search.refresh_secs = 0;
+    config.admin.token = Some(ADMIN_TOKEN.to_string());
+    config.storage = StorageConfig::Filesystem {
+        path: dir.join("data"),
+    };
+    config.gate = serde_json::from_value(json!({
+        "policies": { "release": { "max_severity": "low" } }
+    }))
+    .unwrap();
+    config.products = serde_json::from_value(json!([{
+        "id": "example-1.0",
+        "name": "example",
+        "version": "1.0",
+        "sboms": [JAVA_EXAMPLE],
+    }]))
+    .unwrap();
+    configure(&mut config);
+
+    let engine = Engine::builder(config.clone())
+        .background(false)
+        .build()
+        .await
+        .unwrap();
+    (Apps::new(&config, engine), harness)
+}
+
+/// Call an application, the errors of its middleware answered with the responses the server
+/// sends for them.
+async fn call<S, R, B>(app: &S, req: R) -> ServiceResponse
+where
+    S: Service<R, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody + 'static,
+{
+    match app.call(req).await {
+        Ok(response) => response.map_into_boxed_body(),
+        Err(e) => {
+            ServiceResponse::new(TestRequest::default().to_http_request(), e.error_response())
+        }
+    }
+}
+
+fn get(uri: &str) -> TestRequest {
+    TestRequest::get().uri(uri)
+}
+
+fn post(uri: &str, body: Value) -> TestRequest {
+    TestRequest::post().uri(uri).set_json(body)
+}
+
+fn bearer(req: TestRequest, token: &str) -> TestRequest {
+    req.insert_header((header::AUTHORIZATION, format!("Bearer {}", token)))
+}
+
+/// A request to the admin listener, with the admin token.
+fn admin(req: TestRequest) -> TestRequest {
+    bearer(req, ADMIN_TOKEN)
+}
+
+/// Serve the signing key of [`ISSUER`] on a local port, returning the URL of the key set.
+async fn jwks() -> String {
+    let keys = json!({ "keys": [{
+        "kty": "oct",
+        "kid": "test",
+        "alg": "HS256",
+        "k": URL_SAFE_NO_PAD.encode(SECRET),
+    }] });
+    let server = HttpServer::new(move || {
+        let keys = keys.clone();
+        App::new().route(
+            "/jwks",
+            web::get().to(move || {
+                let keys = keys.clone();
+                async move { HttpResponse::Ok().json(keys) }
+            }),
+        )
+    })
+    .workers(1)
+    .disable_signals()
+    .bind(("127.0.0.1", 0))
+    .unwrap();
+    let url = format!("http://{}/jwks", server.addrs()[0]);
+    tokio::spawn(server.run());
+    url
+}
+
+/// An ID token of `issuer`, granting the roles of its `groups` claim.
+fn id_token(issuer: &str, groups: &[&str]) -> String {
+    let header = Header {
+        kid: Some("test".to_string()),
+        ..Header::default()
+    };
+    signed(header, issuer, groups)
+}
+
+/// An ID token of `issuer` with the header `header`, signed with the key of [`ISSUER`].
+fn signed(header: Header, issuer: &str, groups: &[&str]) -> String {
+    let exp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        + 3600;
+    let claims = json!({
+        "iss": issuer,
+        "aud": "trust",
+        "sub": "user",
+        "exp": exp,
+        "groups": groups,
+    });
+    jsonwebtoken::encode(&header, &claims, &EncodingKey::from_secret(SECRET)).unwrap()
+}
+
+fn encode(purl: &str) -> String {
+    urlencoding::encode(purl).into_owned()
+}
+
+/// The JSON body of a response, after checking its status, `null` if it isn't JSON.
+async fn expect<B: MessageBody>(response: ServiceResponse<B>, status: StatusCode) -> Value {
+    let actual = response.status();
+    let body = test::read_body(response).await;
+    assert_eq!(actual, status, "{}", String::from_utf8_lossy(&body));
+    serde_json::from_slice(&body).unwrap_or(Value::Null)
+}
+
+/// The problem of an error response, after checking its status.
+async fn problem<B: MessageBody>(response: ServiceResponse<B>, status: StatusCode) -> Value {
+    let content_type = response.headers().get(header::CONTENT_TYPE).cloned();
+    let problem = expect(response, status).await;
+    assert_eq!(
+        content_type.as_ref().and_then(|c| c.to_str().ok()),
+        Some(PROBLEM_JSON)
+    );
+    problem
+}
+
+fn cves(vulnerabilities: &Value) -> Vec<String> {
+    vulnerabilities
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|v| v["cve"].as_str().or_else(|| v["id"].as_str()))
+        .map(str::to_lowercase)
+        .collect()
+}
+
+#[actix_web::test]
+async fn index() {
+    let (apps, _harness) = apps(mocked()).await;
+    let app = test::init_service(apps.public()).await;
+
+    let apis = expect(call(&app, get("/").to_request()).await, StatusCode::OK).await;
+    assert!(apis.as_array().map_or(false, |a| !a.is_empty()));
+
+    let capabilities = expect(
+        call(&app, get("/api/v1").to_request()).await,
+        StatusCode::OK,
+    )
+    .await;
+    assert!(capabilities["providers"].is_object());
+    assert!(capabilities["limits"].is_object());
+}
+
+#[actix_web::test]
+async fn package() {
+    let (apps, _harness) = apps(recorded()).await;
+    let app = test::init_service(apps.public()).await;
+
+    let uri = format!("/api/v1/package?purl={}", encode(VERTX));
+    let response = call(&app, get(&uri).to_request()).await;
+    let etag = response.headers().get(header::ETAG).cloned();
+    let package = expect(response, StatusCode::OK).await;
+    assert_eq!(package["purl"], VERTX);
+    assert_eq!(package["trusted"], false);
+    assert!(cves(&package["vulnerabilities"]).contains(&"cve-2023-24815".to_string()));
+
+    // the sizes of the calls to all upstreams are recorded
+    let admin_app = test::init_service(apps.admin()).await;
+    let response = call(&admin_app, admin(get("/metrics")).to_request()).await;
+    let metrics = String::from_utf8(test::read_body(response).await.to_vec()).unwrap();
+    for (upstream, operation) in [("guac", "get_packages"), ("snyk", "get_vulnerabilities")] {
+        for metric in [
+            "upstream_request_size_bytes",
+            "upstream_response_size_bytes",
+        ] {
+            let count = format!(
+                "{}_count{{operation=\"{}\",upstream=\"{}\"}}",
+                metric, operation, upstream
+            );
+            assert!(metrics.contains(&count), "{}", count);
+        }
+    }
+
+    // the same package is not sent again
+    let etag = etag.expect("an ETag");
+    let request = get(&uri).insert_header((header::IF_NONE_MATCH, etag));
+    let response = call(&app, request.to_request()).await;
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+    let uri = format!("/api/v1/package?purl={}", encode(UNKNOWN));
+    let response = call(&app, get(&uri).to_request()).await;
+    let found = problem(response, StatusCode::NOT_FOUND).await;
+    assert_eq!(found["code"], "PackageNotFound");
+
+    let response = call(&app, get("/api/v1/package").to_request()).await;
+    let missing = problem(response, StatusCode::BAD_REQUEST).await;
+    assert_eq!(missing["code"], "MissingQueryArgument");
+
+    let uri = "/api/v1/package?purl=maven%2Fio.vertx%2Fvertx-web%404.3.7";
+    let response = call(&app, get(uri).to_request()).await;
+    problem(response, StatusCode::BAD_REQUEST).await;
+}
+
+#[actix_web::test]
+async fn package_batch() {
+    let (apps, _harness) = apps(recorded()).await;
+    let app = test::init_service(apps.public()).await;
+
+    let request = post("/api/v1/package", json!([VERTX, UNKNOWN, "not a purl"]));
+    let items = expect(call(&app, request.to_request()).await, StatusCode::OK).await;
+    let statuses: Vec<&Value> = items
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|i| &i["status"])
+        .collect();
+    assert_eq!(statuses, [&json!(200), &json!(404), &json!(400)]);
+
+    let response = call(&app, post("/api/v1/package", json!([])).to_request()).await;
+    let empty = problem(response, StatusCode::BAD_REQUEST).await;
+    assert_eq!(empty["code"], "EmptyRequest");
+}
+
+#[actix_web::test]
+async fn package_graph_arguments() {
+    let (apps, _harness) = apps(mocked()).await;
+    let app = test::init_service(apps.public()).await;
+
+    for path in ["dependencies", "dependents", "versions"] {
+        let uri = format!("/api/v1/package/{}", path);
+        let response = call(&app, post(&uri, json!([])).to_request()).await;
+        problem(response, StatusCode::BAD_REQUEST).await;
+
+        // invalid purls fail on their own, without asking Guac
+        let response = call(&app, post(&uri, json!(["not a purl"])).to_request()).await;
+        let items = expect(response, StatusCode::OK).await;
+        assert_eq!(items[0]["status"], 400);
+    }
+    for path in ["dependencies", "dependents"] {
+        let uri = format!("/api/v1/package/{}?depth=0", path);
+        let response = call(&app, post(&uri, json!([VERTX])).to_request()).await;
+        let depth = problem(response, StatusCode::BAD_REQUEST).await;
+        assert_eq!(depth["code"], "InvalidDepth");
+    }
+
+    let response = call(&app, get("/api/v1/package/path").to_request()).await;
+    problem(response, StatusCode::BAD_REQUEST).await;
+    let uri = format!(
+        "/api/v1/package/path?from={}&to={}&depth=0",
+        encode(JAVA_EXAMPLE),
+        encode(VERTX)
+    );
+    let response = call(&app, get(&uri).to_request()).await;
+    problem(response, StatusCode::BAD_REQUEST).await;
+
+    let response = call(&app, get("/api/v1/package/counterpart").to_request()).await;
+    problem(response, StatusCode::BAD_REQUEST).await;
+}
+
+#[actix_web::test]
+async fn package_details() {
+    let (apps, _harness) = apps(mocked()).await;
+    let app = test::init_service(apps.public()).await;
+
+    // known by its SBOM
+    let uri = format!("/api/v1/package/licenses?purl={}", encode(JAVA_EXAMPLE));
+    let licenses = expect(call(&app, get(&uri).to_request()).await, StatusCode::OK).await;
+    assert!(licenses.is_array());
+
+    let request = post("/api/v1/package/license-check", json!([JAVA_EXAMPLE]));
+    let checks = expect(call(&app, request.to_request()).await, StatusCode::OK).await;
+    assert_eq!(checks[0]["status"], 200);
+
+    let uri = format!("/api/v1/package/provenance?purl={}", encode(JAVA_EXAMPLE));
+    let response = call(&app, get(&uri).to_request()).await;
+    let provenance = problem(response, StatusCode::NOT_FOUND).await;
+    assert_eq!(provenance["code"], "ProvenanceNotFound");
+
+    let uri = format!("/api/v1/package/history?purl={}", encode(VERTX));
+    let response = call(&app, get(&uri).to_request()).await;
+    let history = problem(response, StatusCode::NOT_FOUND).await;
+    assert_eq!(history["code"], "HistoryNotFound");
+
+    for path in ["licenses", "provenance", "history"] {
+        let uri = format!("/api/v1/package/{}", path);
+        let response = call(&app, get(&uri).to_request()).await;
+        problem(response, StatusCode::BAD_REQUEST).await;
+    }
+}
+
+#[actix_web::test]
+async fn package_lookups() {
+    let (apps, _harness) = apps(mocked()).await;
+    let app = test::init_service(apps.public()).await;
+
+    let uri = "/api/v1/package/by-coordinates?ecosystem=maven&coordinates=io.vertx:vertx-web:4.3.7";
+    let package = expect(call(&app, get(uri).to_request()).await, StatusCode::OK).await;
+    assert_eq!(package["purl"], VERTX);
+
+    let uri = "/api/v1/package/by-coordinates?ecosystem=cobol&coordinates=a:b:1";
+    let response = call(&app, get(uri).to_request()).await;
+    let unsupported = problem(response, StatusCode::BAD_REQUEST).await;
+    assert_eq!(unsupported["code"], "UnsupportedEcosystem");
+    let response = call(&app, get("/api/v1/package/by-coordinates").to_request()).await;
+    problem(response, StatusCode::BAD_REQUEST).await;
+
+    let response = call(&app, get("/api/v1/package/by-cpe").to_request()).await;
+    problem(response, StatusCode::BAD_REQUEST).await;
+    let response = call(&app, get("/api/v1/package/by-cpe?cpe=ubi9").to_request()).await;
+    let invalid = problem(response, StatusCode::BAD_REQUEST).await;
+    assert_eq!(invalid["code"], "InvalidCpe");
+}
+
+#[actix_web::test]
+async fn sbom() {
+    let (apps, _harness) = apps(mocked()).await;
+    let app = test::init_service(apps.public()).await;
+
+    let uri = format!("/api/v1/package/sbom?purl={}", encode(JAVA_EXAMPLE));
+    let response = call(&app, get(&uri).to_request()).await;
+    let location = response
+        .headers()
+        .get(header::CONTENT_LOCATION)
+        .and_then(|l| l.to_str().ok())
+        .map(ToString::to_string)
+        .expect("the location of the SBOM by its digest");
+    let document = expect(response, StatusCode::OK).await;
+    assert_eq!(document["bomFormat"], "CycloneDX");
+
+    let by_digest = expect(
+        call(&app, get(&location).to_request()).await,
+        StatusCode::OK,
+    )
+    .await;
+    assert_eq!(by_digest, document);
+
+    let uri = format!("/api/v1/sbom/{}", "0".repeat(64));
+    let response = call(&app, get(&uri).to_request()).await;
+    let digest = problem(response, StatusCode::NOT_FOUND).await;
+    assert_eq!(digest["code"], "SbomNotFound");
+
+    let uri = format!("/api/v1/package/sbom?purl={}", encode(UNKNOWN));
+    let response = call(&app, get(&uri).to_request()).await;
+    problem(response, StatusCode::NOT_FOUND).await;
+    let response = call(&app, get("/api/v1/package/sbom").to_request()).await;
+    problem(response, StatusCode::BAD_REQUEST).await;
+
+    let response = call(&app, get("/api/v1/sbom/export").to_request()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers().get(header::CONTENT_TYPE).unwrap(),
+        "application/gzip"
+    );
+    let uri = format!("/api/v1/sbom/export?product={}", encode(UNKNOWN));
+    let response = call(&app, get(&uri).to_request()).await;
+    problem(response, StatusCode::NOT_FOUND).await;
+}
+
+#[actix_web::test]
+async fn sbom_analysis() {
+    let (apps, _harness) = apps(mocked()).await;
+    let app = test::init_service(apps.public()).await;
+    let sbom = json!({
+        "bomFormat": "CycloneDX",
+        "specVersion": "1.4",
+        "version": 1,
+        "components": [
+            {"type": "library", "name": "vertx-web", "version": "4.3.7", "purl": VERTX},
+            {"type": "library", "name": "vertx-web", "version": "4.3.4.redhat-00007", "purl": VERTX_REDHAT},
+        ],
+    });
+
+    let analysis = expect(
+        call(
+            &app,
+            post("/api/v1/sbom/analyze", sbom.clone()).to_request(),
+        )
+        .await,
+        StatusCode::OK,
+    )
+    .await;
+    assert_eq!(analysis["components"], 2);
+    assert_eq!(analysis["vulnerable"], 1);
+    assert_eq!(cves(&analysis["vulnerabilities"]), ["cve-2023-24815"]);
+
+    let annotated = expect(
+        call(&app, post("/api/v1/sbom/annotate", sbom).to_request()).await,
+        StatusCode::OK,
+    )
+    .await;
+    assert_eq!(cves(&annotated["vulnerabilities"]), ["cve-2023-24815"]);
+
+    let response = call(&app, post("/api/v1/sbom/analyze", json!({})).to_request()).await;
+    let empty = problem(response, StatusCode::BAD_REQUEST).await;
+    assert_eq!(empty["code"], "NoComponents");
+    let spdx = json!({ "spdxVersion": "SPDX-2.2", "packages": [] });
+    let response = call(&app, post("/api/v1/sbom/annotate", spdx).to_request()).await;
+    let format = problem(response, StatusCode::BAD_REQUEST).await;
+    assert_eq!(format["code"], "NotCycloneDx");
+
+    let uri = format!("/api/v1/sbom/summary?purl={}", encode(JAVA_EXAMPLE));
+    let summary = expect(call(&app, get(&uri).to_request()).await, StatusCode::OK).await;
+    assert!(summary["components"].as_u64().unwrap() > 1);
+    let uri = format!("/api/v1/sbom/summary?purl={}", encode(UNKNOWN));
+    let response = call(&app, get(&uri).to_request()).await;
+    problem(response, StatusCode::NOT_FOUND).await;
+}
+
+#[actix_web::test]
+async fn products() {
+    let (apps, _harness) = apps(mocked()).await;
+    let app = test::init_service(apps.public()).await;
+
+    let products = expect(
+        call(&app, get("/api/v1/product").to_request()).await,
+        StatusCode::OK,
+    )
+    .await;
+    assert_eq!(products.as_array().map(Vec::len), Some(1));
+
+    let product = expect(
+        call(&app, get("/api/v1/product/example-1.0").to_request()).await,
+        StatusCode::OK,
+    )
+    .await;
+    assert_eq!(product["sboms"], json!([JAVA_EXAMPLE]));
+
+    let packages = expect(
+        call(
+            &app,
+            get("/api/v1/product/example-1.0/packages").to_request(),
+        )
+        .await,
+        StatusCode::OK,
+    )
+    .await;
+    assert!(packages
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|p| p["purl"] == JAVA_EXAMPLE));
+
+    let uri = "/api/v1/product/example-1.0/vulnerabilities";
+    let vulnerabilities = expect(call(&app, get(uri).to_request()).await, StatusCode::OK).await;
+    assert!(vulnerabilities.is_array());
+
+    let uri = "/api/v1/product/example-1.0/summary";
+    let summary = expect(call(&app, get(uri).to_request()).await, StatusCode::OK).await;
+    assert_eq!(summary["components"], packages.as_array().unwrap().len());
+
+    for uri in [
+        "/api/v1/product/unknown",
+        "/api/v1/product/unknown/packages",
+        "/api/v1/product/unknown/vulnerabilities",
+        "/api/v1/product/unknown/summary",
+    ] {
+        let response = call(&app, get(uri).to_request()).await;
+        let unknown = problem(response, StatusCode::NOT_FOUND).await;
+        assert_eq!(unknown["code"], "ProductNotFound");
+    }
+}
+
+#[actix_web::test]
+async fn product_limits() {
+    let (apps, _harness) = apps_with(mocked(), |config| config.api.batch.max_items = 10).await;
+    let app = test::init_service(apps.public()).await;
+
+    for uri in [
+        "/api/v1/product/example-1.0/vulnerabilities",
+        "/api/v1/product/example-1.0/summary",
+    ] {
+        let response = call(&app, get(uri).to_request()).await;
+        let rejected = problem(response, StatusCode::UNPROCESSABLE_ENTITY).await;
+        assert_eq!(rejected["code"], "TooManyComponents", "{}", uri);
+    }
+    // listing the packages doesn't look them up
+    let response = call(
+        &app,
+        get("/api/v1/product/example-1.0/packages").to_request(),
+    )
+    .await;
+    expect(response, StatusCode::OK).await;
+}
+
+#[actix_web::test]
+async fn gate() {
+    let (apps, _harness) = apps(mocked()).await;
+    let app = test::init_service(apps.public()).await;
+
+    let request = json!({ "policy": "release", "purls": [VERTX, VERTX_REDHAT] });
+    let result = expect(
+        call(&app, post("/api/v1/gate", request).to_request()).await,
+        StatusCode::OK,
+    )
+    .await;
+    assert_eq!(result["passed"], false);
+    assert_eq!(result["exitStatus"], 1);
+    assert_eq!(result["components"], 2);
+    assert_eq!(result["violations"][0]["purl"], VERTX);
+    assert_eq!(result["violations"][0]["rule"], "max-severity");
+
+    let request = json!({ "policy": "release", "purls": [VERTX_REDHAT] });
+    let result = expect(
+        call(&app, post("/api/v1/gate", request).to_request()).await,
+        StatusCode::OK,
+    )
+    .await;
+    assert_eq!(result["passed"], true);
+    assert_eq!(result["exitStatus"], 0);
+
+    let request = json!({ "policy": "release", "purls": [UNKNOWN] });
+    let result = expect(
+        call(&app, post("/api/v1/gate", request).to_request()).await,
+        StatusCode::OK,
+    )
+    .await;
+    assert_eq!(result["exitStatus"], 2);
+
+    let request = json!({ "policy": "unknown", "purls": [VERTX] });
+    let response = call(&app, post("/api/v1/gate", request).to_request()).await;
+    let policy = problem(response, StatusCode::NOT_FOUND).await;
+    assert_eq!(policy["code"], "PolicyNotFound");
+
+    let request = json!({ "policy": "release" });
+    let response = call(&app, post("/api/v1/gate", request).to_request()).await;
+    let empty = problem(response, StatusCode::BAD_REQUEST).await;
+    assert_eq!(empty["code"], "NoComponents");
+}
+
+#[actix_web::test]
+async fn watches() {
+    // callbacks to the allowed hosts are not resolved
+    let (apps, _harness) = apps_with(mocked(), |config| {
+        config.watch.allowed_callback_hosts = vec!["example.com".to_string()];
+    })
+    .await;
+    let app = test::init_service(apps.public()).await;
+
+    let request = json!({ "purl": "pkg:maven/io.vertx/*", "callback": "https://example.com/hook" });
+    let watch = expect(
+        call(&app, post("/api/v1/watch", request).to_request()).await,
+        StatusCode::CREATED,
+    )
+    .await;
+    let id = watch["id"].as_u64().expect("the id of the watch");
+
+    let watches = expect(
+        call(&app, get("/api/v1/watch").to_request()).await,
+        StatusCode::OK,
+    )
+    .await;
+    assert_eq!(watches, json!([watch]));
+
+    let uri = format!("/api/v1/watch/{}", id);
+    let response = call(&app, TestRequest::delete().uri(&uri).to_request()).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = call(&app, TestRequest::delete().uri(&uri).to_request()).await;
+    let deleted = problem(response, StatusCode::NOT_FOUND).await;
+    assert_eq!(deleted["code"], "WatchNotFound");
+
+    let request = json!({ "purl": "maven/io.vertx", "callback": "https://example.com/hook" });
+    let response = call(&app, post("/api/v1/watch", request).to_request()).await;
+    problem(response, StatusCode::BAD_REQUEST).await;
+    let request = json!({ "purl": VERTX, "callback": "file:///etc/passwd" });
+    let response = call(&app, post("/api/v1/watch", request).to_request()).await;
+    let callback = problem(response, StatusCode::BAD_REQUEST).await;
+    assert_eq!(callback["code"], "InvalidCallback");
+}
+
+#[actix_web::test]
+async fn other_endpoints() {
+    let (apps, _harness) = apps(mocked()).await;
+    let app = test::init_service(apps.public()).await;
+
+    let results = expect(
+        call(&app, get("/api/v1/search?q=vertx").to_request()).await,
+        StatusCode::OK,
+    )
+    .await;
+    assert!(results["results"].is_array());
+    assert_eq!(results["limit"], 20);
+    for uri in ["/api/v1/search?limit=0", "/api/v1/search?sort=size"] {
+        let response = call(&app, get(uri).to_request()).await;
+        problem(response, StatusCode::BAD_REQUEST).await;
+    }
+
+    let response = call(&app, get("/api/v1/events").to_request()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers().get(header::CONTENT_TYPE).unwrap(),
+        "text/event-stream"
+    );
+
+    let response = call(&app, get("/api/v1/vulnerability").to_request()).await;
+    problem(response, StatusCode::BAD_REQUEST).await;
+
+    let request = post("/api/v1/report/affected", json!({ "cve": " " }));
+    let response = call(&app, request.to_request()).await;
+    let missing = problem(response, StatusCode::BAD_REQUEST).await;
+    assert_eq!(missing["code"], "MissingVulnerability");
+
+    let request = post("/api/v1/image", json!({ "reference": "quay.io/" }));
+    let response = call(&app, request.to_request()).await;
+    let reference = problem(response, StatusCode::BAD_REQUEST).await;
+    assert_eq!(reference["code"], "InvalidReference");
+}
+
+#[actix_web::test]
+async fn legacy_paths() {
+    let (apps, _harness) = apps(recorded()).await;
+    let app = test::init_service(apps.public()).await;
+
+    let uri = format!("/api/package?purl={}", encode(VERTX));
+    let response = call(&app, get(&uri).to_request()).await;
+    assert!(response.headers().contains_key("deprecation"));
+    let package = expect(response, StatusCode::OK).await;
+    assert_eq!(package["purl"], VERTX);
+
+    let response = call(&app, get("/api/package").to_request()).await;
+    problem(response, StatusCode::BAD_REQUEST).await;
+}
+
+#[actix_web::test]
+async fn trusted() {
+    let (apps, _harness) = apps(recorded()).await;
+    let app = test::init_service(apps.public()).await;
+
+    let packages = expect(
+        call(&app, get("/api/v1/trusted").to_request()).await,
+        StatusCode::OK,
+    )
+    .await;
+    assert!(packages.is_array());
+}
+
+#[actix_web::test]
+async fn package_graph() {
+    let (apps, _harness) = apps(recorded()).await;
+    let app = test::init_service(apps.public()).await;
+
+    for path in ["dependencies", "dependents", "versions"] {
+        let uri = format!("/api/v1/package/{}", path);
+        let response = call(&app, post(&uri, json!([VERTX])).to_request()).await;
+        let items = expect(response, StatusCode::OK).await;
+        assert_eq!(items[0]["status"], 200, "{}", items);
+    }
+
+    let uri = format!("/api/v1/package/counterpart?purl={}", encode(VERTX));
+    expect(call(&app, get(&uri).to_request()).await, StatusCode::OK).await;
+
+    let uri = format!(
+        "/api/v1/package/path?from={}&to={}",
+        encode(JAVA_EXAMPLE),
+        encode(VERTX)
+    );
+    expect(call(&app, get(&uri).to_request()).await, StatusCode::OK).await;
+}
+
+#[actix_web::test]
+async fn vulnerabilities() {
+    let (apps, _harness) = apps(recorded()).await;
+    let app = test::init_service(apps.public()).await;
+
+    let uri = "/api/v1/vulnerability?cve=CVE-2023-24815";
+    let vulnerability = expect(call(&app, get(uri).to_request()).await, StatusCode::OK).await;
+    assert!(vulnerability["packages"].is_array());
+
+    let request = post(
+        "/api/v1/report/affected",
+        json!({ "cve": "CVE-2023-24815" }),
+    );
+    let report = expect(call(&app, request.to_request()).await, StatusCode::OK).await;
+    assert!(report["products"].is_array());
+}
+
+#[actix_web::test]
+async fn health_and_openapi() {
+    let (apps, _harness) = apps(mocked()).await;
+    let app = test::init_service(apps.public()).await;
+
+    for uri in ["/health/live", "/health/ready"] {
+        let response = call(&app, get(uri).to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK, "{}", uri);
+    }
+    let document = expect(
+        call(&app, get("/openapi.json").to_request()).await,
+        StatusCode::OK,
+    )
+    .await;
+    assert!(document["paths"]["/api/v1/package"].is_object());
+}
+
+#[actix_web::test]
+async fn docs_below_base_path() {
+    let (apps, _harness) = apps_with(mocked(), |config| {
+        config.api.docs.base_path = Some("/trust/".to_string());
+    })
+    .await;
+    let app = test::init_service(apps.public()).await;
+
+    let document = expect(
+        call(&app, get("/trust/openapi.json").to_request()).await,
+        StatusCode::OK,
+    )
+    .await;
+    assert_eq!(document["servers"][0]["url"], "/trust");
+    let response = call(&app, get("/openapi.json").to_request()).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let index = expect(call(&app, get("/").to_request()).await, StatusCode::OK).await;
+    let links: Vec<&str> = index
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(|l| l.as_str())
+        .collect();
+    assert!(
+        links.iter().any(|l| l.ends_with("/trust/swagger-ui/")),
+        "{:?}",
+        links
+    );
+    assert!(
+        links.iter().any(|l| l.ends_with("/trust/openapi.json")),
+        "{:?}",
+        links
+    );
+}
+
+#[actix_web::test]
+async fn upstream_failures() {
+    let (apps, _harness) = apps_with(mocked(), |config| {
+        // nothing listens on the discard port
+        config.guac.url = "http://127.0.0.1:9/query".to_string();
+    })
+    .await;
+    let app = test::init_service(apps.public()).await;
+
+    for uri in [
+        "/api/v1/trusted",
+        "/api/v1/vulnerability?cve=CVE-2023-24815",
+    ] {
+        let response = call(&app, get(uri).to_request()).await;
+        let status = response.status();
+        assert!(
+            [StatusCode::BAD_GATEWAY, StatusCode::SERVICE_UNAVAILABLE].contains(&status),
+            "{}: {}",
+            uri,
+            status
+        );
+        let failed = problem(response, status).await;
+        assert!(failed["code"].as_str().unwrap().starts_with("Upstream"));
+    }
+}
+
+#[actix_web::test]
+async fn admin_endpoints() {
+    let (apps, _harness) = apps(mocked()).await;
+    let app = test::init_service(apps.admin()).await;
+
+    let response = call(&app, get("/api/admin/stats").to_request()).await;
+    let missing = problem(response, StatusCode::UNAUTHORIZED).await;
+    assert_eq!(missing["code"], "Unauthorized");
+    let request = bearer(get("/api/admin/stats"), "wrong");
+    let response = call(&app, request.to_request()).await;
+    let wrong = problem(response, StatusCode::FORBIDDEN).await;
+    assert_eq!(wrong["code"], "Forbidden");
+
+    let stats = expect(
+        call(&app, admin(get("/api/admin/stats")).to_request()).await,
+        StatusCode::OK,
+    )
+    .await;
+    assert!(stats["sboms"]["total"].as_u64().unwrap() > 0);
+
+    // without the deps.dev integration
+    let request = admin(get("/api/admin/stats/adoption"));
+    let response = call(&app, request.to_request()).await;
+    problem(response, StatusCode::NOT_IMPLEMENTED).await;
+    let response = call(&app, get("/api/admin/stats/adoption").to_request()).await;
+    problem(response, StatusCode::UNAUTHORIZED).await;
+
+    for (uri, body) in [
+        ("/api/admin/cache/purge", json!({})),
+        ("/api/admin/subgraphs/invalidate", json!({})),
+        ("/api/admin/cdn/purge", json!({ "purls": [VERTX] })),
+        ("/api/admin/cdn/purge", json!({ "all": true })),
+    ] {
+        let response = call(&app, admin(post(uri, body)).to_request()).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT, "{}", uri);
+    }
+    let request = admin(post("/api/admin/ingestion", json!({ "purls": [VERTX] })));
+    let response = call(&app, request.to_request()).await;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+    let response = call(&app, admin(get("/metrics")).to_request()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let deliveries = expect(
+        call(
+            &app,
+            admin(get("/api/admin/webhooks/deliveries")).to_request(),
+        )
+        .await,
+        StatusCode::OK,
+    )
+    .await;
+    assert!(deliveries.is_array());
+    let request = admin(post(
+        "/api/admin/webhooks/replay",
+        json!({ "from": "2023-01-01T00:00:00Z", "to": "2023-01-02T00:00:00Z" }),
+    ));
+    let replayed = expect(call(&app, request.to_request()).await, StatusCode::OK).await;
+    assert_eq!(replayed["replayed"], 0);
+
+    // SBOM sources
+    let dir = std::env::temp_dir().join(format!("trust-api-sboms-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let source = json!({ "id": "test", "type": "directory", "path": dir });
+    let request = admin(post("/api/admin/sbom/sources", source.clone()));
+    let response = call(&app, request.to_request()).await;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    // the source is added in the background
+    let mut sources = Value::Null;
+    for _ in 0..50 {
+        sources = expect(
+            call(&app, admin(get("/api/admin/sbom/sources")).to_request()).await,
+            StatusCode::OK,
+        )
+        .await;
+        if sources
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|s| s["id"] == "test")
+        {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(sources
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|s| s["id"] == "test"));
+    let request = admin(post("/api/admin/sbom/sources", source));
+    let response = call(&app, request.to_request()).await;
+    let exists = problem(response, StatusCode::CONFLICT).await;
+    assert_eq!(exists["code"], "SourceExists");
+
+    let request = TestRequest::post().uri("/api/admin/sbom/reindex?source=test");
+    let response = call(&app, admin(request).to_request()).await;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    let request = TestRequest::post().uri("/api/admin/sbom/reindex?source=unknown");
+    let response = call(&app, admin(request).to_request()).await;
+    let unknown = problem(response, StatusCode::NOT_FOUND).await;
+    assert_eq!(unknown["code"], "SourceNotFound");
+
+    let request = TestRequest::delete().uri("/api/admin/sbom/sources/test");
+    let response = call(&app, admin(request).to_request()).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let request = TestRequest::delete().uri("/api/admin/sbom/sources/test");
+    let response = call(&app, admin(request).to_request()).await;
+    let removed = problem(response, StatusCode::NOT_FOUND).await;
+    assert_eq!(removed["code"], "SourceNotFound");
+    std::fs::remove_dir_all(dir).ok();
+
+    // log sampling
+    let rules = json!([{ "path": "/api/v1/package", "rate": 0.5 }]);
+    let request = admin(TestRequest::put().uri("/api/admin/logging/sampling")).set_json(&rules);
+    let response = call(&app, request.to_request()).await;
+    assert_eq!(expect(response, StatusCode::OK).await, rules);
+    let sampling = expect(
+        call(&app, admin(get("/api/admin/logging/sampling")).to_request()).await,
+        StatusCode::OK,
+    )
+    .await;
+    assert_eq!(sampling, rules);
+    let request = admin(TestRequest::put().uri("/api/admin/logging/sampling"))
+        .set_json(json!([{ "path": "/api/v1/package", "rate": 2.0 }]));
+    let response = call(&app, request.to_request()).await;
+    let rate = problem(response, StatusCode::BAD_REQUEST).await;
+    assert_eq!(rate["code"], "InvalidSamplingRate");
+
+    // costs
+    let response = call(&app, admin(get("/api/admin/costs")).to_request()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let request = TestRequest::delete().uri("/api/admin/costs");
+    let response = call(&app, admin(request).to_request()).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    // without replication
+    let response = call(
+        &app,
+        admin(get("/api/admin/replication/delta")).to_request(),
+    )
+    .await;
+    let primary = problem(response, StatusCode::NOT_FOUND).await;
+    assert_eq!(primary["code"], "NotPrimary");
+
+    let request = admin(TestRequest::put().uri("/api/admin/snyk/token"));
+    let response = call(&app, request.set_json(json!({ "token": "" })).to_request()).await;
+    let empty = problem(response, StatusCode::BAD_REQUEST).await;
+    assert_eq!(empty["code"], "EmptyToken");
+    let request = admin(TestRequest::put().uri("/api/admin/snyk/token"));
+    let response = call(
+        &app,
+        request.set_json(json!({ "token": "rotated" })).to_request(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let entries = expect(
+        call(&app, admin(get("/api/admin/audit")).to_request()).await,
+        StatusCode::OK,
+    )
+    .await;
+    assert_eq!(entries, json!([]));
+}
+
+#[actix_web::test]
+async fn authentication() {
+    let jwks_url = jwks().await;
+    let (apps, _harness) = apps_with(mocked(), |config| {
+        config.admin.token = None;
+        config.auth = serde_json::from_value(json!({
+            "issuers": [{
+                "issuer": ISSUER,
+                "audience": "trust",
+                "jwks_url": jwks_url,
+                "roles_claim": "groups",
+                "algorithms": ["HS256"],
+            }],
+            "rbac": { "enabled": true },
+        }))
+        .unwrap();
+    })
+    .await;
+    tokio::spawn(apps.authenticator().run(Duration::from_secs(300)));
+    let app = test::init_service(apps.public()).await;
+    let admin_app = test::init_service(apps.admin()).await;
+
+    // the signing keys are fetched in the background
+    let reader = id_token(ISSUER, &["reader"]);
+    let mut status = StatusCode::UNAUTHORIZED;
+    for _ in 0..50 {
+        let request = bearer(get("/api/v1"), &reader);
+        status = call(&app, request.to_request()).await.status();
+        if status == StatusCode::OK {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(status, StatusCode::OK);
+
+    let response = call(&app, get("/api/v1").to_request()).await;
+    let missing = problem(response, StatusCode::UNAUTHORIZED).await;
+    assert_eq!(missing["code"], "Unauthorized");
+    for (token, code) in [
+        ("not.a.token".to_string(), "InvalidToken"),
+        (format!("{}x", reader), "InvalidToken"),
+        (
+            id_token("https://other.example.com", &["reader"]),
+            "UnknownIssuer",
+        ),
+        // an algorithm the issuer doesn't use, and a key it doesn't have
+        (
+            signed(
+                Header {
+                    kid: Some("test".to_string()),
+                    ..Header::new(Algorithm::HS384)
+                },
+                ISSUER,
+                &["reader"],
+            ),
+            "InvalidToken",
+        ),
+        (
+            signed(
+                Header {
+                    kid: Some("other".to_string()),
+                    ..Header::default()
+                },
+                ISSUER,
+                &["reader"],
+            ),
+            "InvalidToken",
+        ),
+    ] {
+        let response = call(&app, bearer(get("/api/v1"), &token).to_request()).await;
+        let invalid = problem(response, StatusCode::UNAUTHORIZED).await;
+        assert_eq!(invalid["code"], code);
+    }
+
+    let request = bearer(get("/api/v1"), &id_token(ISSUER, &[]));
+    let response = call(&app, request.to_request()).await;
+    let role = problem(response, StatusCode::FORBIDDEN).await;
+    assert_eq!(role["code"], "MissingRole");
+
+    // outside of the API
+    let response = call(&app, get("/health/live").to_request()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let request = bearer(get("/api/admin/stats"), &id_token(ISSUER, &["admin"]));
+    let response = call(&admin_app, request.to_request()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let request = bearer(get("/api/admin/stats"), &reader);
+    let response = call(&admin_app, request.to_request()).await;
+    problem(response, StatusCode::FORBIDDEN).await;
+}
+
+#[actix_web::test]
+async fn audit_log() {
+    let (apps, _harness) = apps_with(mocked(), |config| config.audit.enabled = true).await;
+    let app = test::init_service(apps.public()).await;
+    let admin_app = test::init_service(apps.admin()).await;
+
+    let uri = format!("/api/v1/package/sbom?purl={}", encode(JAVA_EXAMPLE));
+    let response = call(&app, get(&uri).to_request()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = call(&app, get("/api/v1/package/sbom").to_request()).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    // not part of the API
+    call(&app, get("/health/live").to_request()).await;
+
+    let entries = expect(
+        call(&admin_app, admin(get("/api/admin/audit")).to_request()).await,
+        StatusCode::OK,
+    )
+    .await;
+    let entries = entries.as_array().unwrap();
+    assert_eq!(entries.len(), 2, "{:?}", entries);
+    // newest first
+    assert_eq!(entries[0]["status"], 400);
+    assert_eq!(entries[1]["path"], "/api/v1/package/sbom");
+    assert_eq!(entries[1]["status"], 200);
+    assert_eq!(entries[1]["caller"], "anonymous");
+    assert_eq!(entries[1]["purls"], json!([JAVA_EXAMPLE]));
+
+    let uri = format!("/api/admin/audit?purl={}", encode(JAVA_EXAMPLE));
+    let entries = expect(
+        call(&admin_app, admin(get(&uri)).to_request()).await,
+        StatusCode::OK,
+    )
+    .await;
+    assert_eq!(entries.as_array().map(Vec::len), Some(1));
+    let uri = format!("/api/admin/audit?purl={}", encode(VERTX));
+    let entries = expect(
+        call(&admin_app, admin(get(&uri)).to_request()).await,
+        StatusCode::OK,
+    )
+    .await;
+    assert_eq!(entries, json!([]));
+
+    // bodies are only digested
+    let body = json!([VERTX]);
+    call(&app, post("/api/v1/package", body.clone()).to_request()).await;
+    let entries = expect(
+        call(
+            &admin_app,
+            admin(get("/api/admin/audit?limit=1")).to_request(),
+        )
+        .await,
+        StatusCode::OK,
+    )
+    .await;
+    let digest = hex::encode(Sha256::digest(serde_json::to_vec(&body).unwrap()));
+    assert_eq!(entries[0]["body_digest"], digest);
+    assert!(entries[0].get("purls").is_none());
+}
+
+#[actix_web::test]
+async fn cdn_headers() {
+    let (apps, _harness) = apps_with(mocked(), |config| config.cdn.enabled = true).await;
+    let app = test::init_service(apps.public()).await;
+
+    let uri = format!("/api/v1/package/sbom?purl={}", encode(JAVA_EXAMPLE));
+    let response = call(&app, get(&uri).to_request()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().contains_key("surrogate-key"));
+    assert_eq!(
+        response.headers().get(header::CACHE_CONTROL).unwrap(),
+        "public, max-age=300"
+    );
+
+    // responses to callers with credentials are their own
+    let request = get(&uri).insert_header(("x-api-key", "key"));
+    let response = call(&app, request.to_request()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(!response.headers().contains_key("surrogate-key"));
+    assert_eq!(
+        response.headers().get(header::CACHE_CONTROL).unwrap(),
+        "private, max-age=300"
+    );
+    assert!(response.headers().contains_key(header::VARY));
+
+    // errors are not cached
+    let response = call(&app, get("/api/v1/package/sbom").to_request()).await;
+    assert!(!response.headers().contains_key("surrogate-key"));
+}
+
+#[actix_web::test]
+async fn dev_mode() {
+    let (apps, _harness) = apps(mocked()).await;
+    let app = test::init_service(apps.public()).await;
+
+    for path in ["dependencies", "dependents"] {
+        let uri = format!("/api/v1/package/{}", path);
+        let response = call(&app, post(&uri, json!([VERTX])).to_request()).await;
+        let items = expect(response, StatusCode::OK).await;
+        assert_eq!(items[0]["status"], 200, "{}", items);
+    }
+
+    let packages = expect(
+        call(&app, get("/api/v1/trusted").to_request()).await,
+        StatusCode::OK,
+    )
+    .await;
+    assert!(packages.as_array().map_or(false, |p| !p.is_empty()));
+
+    let uri = "/api/v1/vulnerability?cve=CVE-2023-24815";
+    let vulnerability = expect(call(&app, get(uri).to_request()).await, StatusCode::OK).await;
+    assert_eq!(vulnerability["severity"], "Moderate");
+    assert_eq!(vulnerability["packages"][0]["purl"], VERTX);
+
+    let request = post(
+        "/api/v1/report/affected",
+        json!({ "cve": "CVE-2023-24815" }),
+    );
+    let report = expect(call(&app, request.to_request()).await, StatusCode::OK).await;
+    assert_eq!(report["components"], json!([VERTX]));
+}
@@ -0,0 +1,226 @@
+//! A client of the trusted content API.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), trust_api_client::Error> {
+//! let client = trust_api_client::Client::new("http://localhost:8080");
+//! let package = client
+//!     .get_package("pkg:maven/io.vertx/vertx-web@4.3.7")
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use reqwest::{RequestBuilder, StatusCode};
+use serde::de::DeserializeOwned;
+use thiserror::Error;
+use trust_api_model::prelude::*;
+
+pub use trust_api_model as model;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Error calling the trusted content API: {0}")]
+    Request(#[from] reqwest::Error),
+    /// The problem the API responded with, its `code` tells the kind of error
+    #[error("{}", .0.detail)]
+    Problem(Problem),
+    #[error("The trusted content API responded with {status}: {body}")]
+    Response { status: StatusCode, body: String },
+}
+
+impl Error {
+    /// The status the API responded with, if it responded.
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            Self::Request(e) => e.status(),
+            Self::Problem(problem) => StatusCode::from_u16(problem.status).ok(),
+            Self::Response { status, .. } => Some(*status),
+        }
+    }
+}
+
+/// Calls the REST API of an instance, below `/api/v1`.
+///
+/// Clones share the connections of the underlying HTTP client.
+#[derive(Clone, Debug)]
+pub struct Client {
+    url: String,
+    token: Option<String>,
+    client: reqwest::Client,
+}
+
+impl Client {
+    /// A client of the instance at a base URL, e.g. `http://localhost:8080`.
+    pub fn new(url: impl Into<String>) -> Self {
+        Self::with_client(url, reqwest::Client::new())
+    }
+
+    /// A client using a configured HTTP client, e.g. with timeouts or proxies.
+    pub fn with_client(url: impl Into<String>, client: reqwest::Client) -> Self {
+        Self {
+            url: url.into().trim_end_matches('/').to_string(),
+            token: None,
+            client,
+        }
+    }
+
+    /// Send a bearer token, for instances requiring authentication.
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/api/v1{}", self.url, path)
+    }
+
+    fn purl_query(path: &str, purl: &str) -> String {
+        format!("{}?purl={}", path, urlencoding::encode(purl))
+    }
+
+    async fn send<T: DeserializeOwned>(&self, request: RequestBuilder) -> Result<T, Error> {
+        let request = match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        };
+        let response = request.send().await?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response.json().await?);
+        }
+
+        let body = response.text().await?;
+        match serde_json::from_str::<Problem>(&body) {
+            Ok(problem) => Err(Error::Problem(problem)),
+            Err(_) => Err(Error::Response { status, body }),
+        }
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T, Error> {
+        self.send(self.client.get(self.url(path))).await
+    }
+
+    async fn post<T: DeserializeOwned, B: serde::Serialize + ?Sized>(
+        &self,
+        path: &str,
+        body: &B,
+    ) -> Result<T, Error> {
+        self.send(self.client.post(self.url(path)).json(body)).await
+    }
+
+    /// The capability document of the instance, its enabled providers, features and `limits`.
+    pub async fn get_capabilities(&self) -> Result<serde_json::Value, Error> {
+        self.get("").await
+    }
+
+    /// A package, with its trusted versions and vulnerabilities.
+    pub async fn get_package(&self, purl: &str) -> Result<Package, Error> {
+        self.get(&Self::purl_query("/package", purl)).await
+    }
+
+    /// Packages, with an item for each purl, in request order.
+    pub async fn query_packages(&self, purls: &[String]) -> Result<Vec<PackageBatchItem>, Error> {
+        self.post("/package", purls).await
+    }
+
+    /// The dependencies of packages, reachable within `depth` levels.
+    pub async fn query_dependencies(
+        &self,
+        purls: &[String],
+        depth: usize,
+    ) -> Result<Vec<DependenciesBatchItem>, Error> {
+        self.post(&format!("/package/dependencies?depth={}", depth), purls)
+            .await
+    }
+
+    /// The packages depending on packages, reachable within `depth` levels.
+    pub async fn query_dependents(
+        &self,
+        purls: &[String],
+        depth: usize,
+    ) -> Result<Vec<DependenciesBatchItem>, Error> {
+        self.post(&format!("/package/dependents?depth={}", depth), purls)
+            .await
+    }
+
+    /// The shortest dependency chains from a package to one of its dependencies.
+    pub async fn get_paths(&self, from: &str, to: &str) -> Result<DependencyPaths, Error> {
+        self.get(&format!(
+            "/package/path?from={}&to={}",
+            urlencoding::encode(from),
+            urlencoding::encode(to)
+        ))
+        .await
+    }
+
+    /// The trusted counterparts of a community package, most confident first.
+    pub async fn get_counterparts(&self, purl: &str) -> Result<Counterparts, Error> {
+        self.get(&Self::purl_query("/package/counterpart", purl))
+            .await
+    }
+
+    /// The known versions of packages, only trusted rebuilds if `trusted_only` is set.
+    pub async fn query_versions(
+        &self,
+        purls: &[String],
+        trusted_only: bool,
+    ) -> Result<Vec<VersionsBatchItem>, Error> {
+        self.post(
+            &format!("/package/versions?trusted_only={}", trusted_only),
+            purls,
+        )
+        .await
+    }
+
+    /// The inventory of trusted packages.
+    pub async fn get_trusted(&self) -> Result<Vec<Package>, Error> {
+        self.get("/trusted").await
+    }
+
+    /// The SBOM of a package, as it was published.
+    pub async fn get_sbom(&self, purl: &str) -> Result<serde_json::Value, Error> {
+        self.get(&Self::purl_query("/package/sbom", purl)).await
+    }
+
+    /// Analyze all components of a CycloneDX or SPDX SBOM.
+    pub async fn upload_sbom(&self, sbom: &serde_json::Value) -> Result<SbomAnalysis, Error> {
+        self.post("/sbom/analyze", sbom).await
+    }
+
+    pub async fn get_provenance(&self, purl: &str) -> Result<Provenance, Error> {
+        self.get(&Self::purl_query("/package/provenance", purl))
+            .await
+    }
+
+    pub async fn get_licenses(&self, purl: &str) -> Result<Vec<License>, Error> {
+        self.get(&Self::purl_query("/package/licenses", purl)).await
+    }
+
+    /// Check packages against the license policy of the instance.
+    pub async fn check_licenses(
+        &self,
+        purls: &[String],
+    ) -> Result<Vec<LicenseCheckBatchItem>, Error> {
+        self.post("/package/license-check", purls).await
+    }
+
+    /// The analyses of a package over time, and when its vulnerabilities appeared and were
+    /// remediated.
+    pub async fn get_history(&self, purl: &str) -> Result<PackageHistory, Error> {
+        self.get(&Self::purl_query("/package/history", purl)).await
+    }
+
+    /// The products affected by a vulnerability, with the dependency paths to the affected
+    /// components.
+    pub async fn report_affected(&self, cve: &str) -> Result<AffectedReport, Error> {
+        let request = AffectedRequest {
+            cve: cve.to_string(),
+        };
+        self.post("/report/affected", &request).await
+    }
+
+    pub async fn get_vulnerability(&self, cve: &str) -> Result<Vulnerability, Error> {
+        self.get(&format!("/vulnerability?cve={}", urlencoding::encode(cve)))
+            .await
+    }
+}
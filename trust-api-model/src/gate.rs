@@ -0,0 +1,53 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// The components of a release to check against a gate policy, as an SBOM or a list of purls.
+#[derive(Clone, Debug, PartialEq, ToSchema, Serialize, Deserialize)]
+#[schema(example = json!({
+    "policy": "release",
+    "purls": ["pkg:rpm/redhat/openssl@1.1.1k-7.el8_6", "pkg:maven/io.vertx/vertx-web@4.3.7"]
+}))]
+pub struct GateRequest {
+    /// The name of the configured policy
+    pub policy: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub purls: Vec<String>,
+    /// A CycloneDX or SPDX SBOM, in JSON, all its components with a purl are checked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sbom: Option<serde_json::Value>,
+}
+
+/// A rule of a gate policy a component breaks.
+#[derive(Clone, Debug, PartialEq, Eq, ToSchema, Serialize, Deserialize)]
+pub struct GateViolation {
+    pub purl: String,
+    /// The rule, e.g. `max-severity`, `trusted`, `provenance`, `slsa-level`, `license` or
+    /// `unresolved`
+    pub rule: String,
+    pub reason: String,
+}
+
+/// Whether a release passes a gate policy.
+#[derive(Clone, Debug, PartialEq, Eq, ToSchema, Serialize, Deserialize)]
+#[schema(example = json!({
+    "policy": "release",
+    "passed": false,
+    "exitStatus": 1,
+    "components": 2,
+    "violations": [{
+        "purl": "pkg:rpm/redhat/openssl@1.1.1k-7.el8_6",
+        "rule": "max-severity",
+        "reason": "cve-2023-0286 is of severity high, more than the allowed medium"
+    }]
+}))]
+#[serde(rename_all = "camelCase")]
+pub struct GateResult {
+    pub policy: String,
+    pub passed: bool,
+    /// For CI jobs to exit with: `0` if passed, `1` for violations, `2` if components could not be
+    /// checked
+    pub exit_status: i32,
+    /// The number of components checked
+    pub components: usize,
+    pub violations: Vec<GateViolation>,
+}
@@ -0,0 +1,78 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// The state of a package found by one of its analyses.
+#[derive(Clone, Debug, PartialEq, ToSchema, Serialize, Deserialize)]
+pub struct AnalysisSnapshot {
+    pub analyzed: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trusted: Option<bool>,
+    /// The CVEs affecting the package
+    #[serde(default)]
+    pub vulnerabilities: Vec<String>,
+}
+
+/// A period in which a vulnerability affected a package.
+#[derive(Clone, Debug, PartialEq, ToSchema, Serialize, Deserialize)]
+pub struct VulnerabilityPeriod {
+    pub cve: String,
+    /// The first analysis finding the vulnerability
+    #[serde(rename = "firstSeen")]
+    pub first_seen: DateTime<Utc>,
+    /// The first analysis no longer finding it, `None` while it still affects the package
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remediated: Option<DateTime<Utc>>,
+}
+
+/// The analyses of a package over time.
+#[derive(Clone, Debug, PartialEq, ToSchema, Serialize, Deserialize)]
+#[schema(example = json!({
+    "purl": "pkg:rpm/redhat/openssl@1.1.1k-7.el8_6",
+    "analyses": [
+        {"analyzed": "2023-02-01T08:00:00Z", "trusted": true, "vulnerabilities": []},
+        {"analyzed": "2023-02-08T08:00:00Z", "trusted": true, "vulnerabilities": ["cve-2023-0286"]},
+        {"analyzed": "2023-03-01T08:00:00Z", "trusted": true, "vulnerabilities": []}
+    ],
+    "vulnerabilities": [
+        {"cve": "cve-2023-0286", "firstSeen": "2023-02-08T08:00:00Z", "remediated": "2023-03-01T08:00:00Z"}
+    ]
+}))]
+pub struct PackageHistory {
+    pub purl: String,
+    /// Oldest first
+    pub analyses: Vec<AnalysisSnapshot>,
+    /// When each vulnerability appeared and was remediated, derived from the analyses
+    pub vulnerabilities: Vec<VulnerabilityPeriod>,
+}
+
+impl PackageHistory {
+    /// The history of a package from its analyses, oldest first.
+    pub fn new(purl: String, analyses: Vec<AnalysisSnapshot>) -> Self {
+        let mut vulnerabilities: Vec<VulnerabilityPeriod> = Vec::new();
+        for analysis in &analyses {
+            for period in vulnerabilities.iter_mut() {
+                if period.remediated.is_none() && !analysis.vulnerabilities.contains(&period.cve) {
+                    period.remediated = Some(analysis.analyzed);
+                }
+            }
+            for cve in &analysis.vulnerabilities {
+                let open = vulnerabilities
+                    .iter()
+                    .any(|p| &p.cve == cve && p.remediated.is_none());
+                if !open {
+                    vulnerabilities.push(VulnerabilityPeriod {
+                        cve: cve.clone(),
+                        first_seen: analysis.analyzed,
+                        remediated: None,
+                    });
+                }
+            }
+        }
+        Self {
+            purl,
+            analyses,
+            vulnerabilities,
+        }
+    }
+}
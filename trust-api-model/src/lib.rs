@@ -1,7 +1,19 @@
+pub mod gate;
+pub mod history;
 pub mod pkg;
+pub mod problem;
+pub mod product;
+pub mod report;
+pub mod sbom;
 pub mod vuln;
 
 pub mod prelude {
+    pub use crate::gate::*;
+    pub use crate::history::*;
     pub use crate::pkg::*;
+    pub use crate::problem::*;
+    pub use crate::product::*;
+    pub use crate::report::*;
+    pub use crate::sbom::*;
     pub use crate::vuln::*;
 }
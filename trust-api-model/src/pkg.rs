@@ -1,23 +1,46 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::ops::Deref;
 use utoipa::ToSchema;
 
-#[derive(Clone, Debug, PartialEq, Eq, ToSchema, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, ToSchema, Serialize, Deserialize)]
 #[schema(example = json!(Package {
-    purl: Some("pkg:rpm/redhat/openssl@1.1.1k-7.el8_6".to_string()), href: Some(format!("/api/package?purl={}", &urlencoding::encode("pkg:rpm/redhat/openssl@1.1.1k-7.el8_6"))),
-    sbom: Some(format!("/api/package/sbom?purl={}", &urlencoding::encode("pkg:rpm/redhat/openssl@1.1.1k-7.el8_6"))),
+    purl: Some("pkg:rpm/redhat/openssl@1.1.1k-7.el8_6".to_string()), href: Some(format!("/api/v1/package?purl={}", &urlencoding::encode("pkg:rpm/redhat/openssl@1.1.1k-7.el8_6"))),
+    sbom: Some(format!("/api/v1/package/sbom?purl={}", &urlencoding::encode("pkg:rpm/redhat/openssl@1.1.1k-7.el8_6"))),
+    sbom_fetched: None,
     trusted: Some(true),
     trusted_versions: vec![PackageRef {
         purl: "pkg:rpm/redhat/openssl@1.1.1k-7.el8_6".to_string(),
-        href: format!("/api/package?purl={}", &urlencoding::encode("pkg:rpm/redhat/openssl@1.1.1k-7.el8_6")),
+        href: format!("/api/v1/package?purl={}", &urlencoding::encode("pkg:rpm/redhat/openssl@1.1.1k-7.el8_6")),
         trusted: Some(true),
-        sbom: Some(format!("/api/package/sbom?purl={}", &urlencoding::encode("pkg:rpm/redhat/openssl@1.1.1k-7.el8_6"))),
+        unverified: false,
+        sbom: Some(format!("/api/v1/package/sbom?purl={}", &urlencoding::encode("pkg:rpm/redhat/openssl@1.1.1k-7.el8_6"))),
+        vulnerabilities: None,
+        published: None,
+        fetched: None,
+        relation: None,
+        dependency: None,
+        degraded: vec![],
     }],
     vulnerabilities: vec![VulnerabilityRef {
         cve: "cve-2023-0286".into(),
-        href: "https://access.redhat.com/security/cve/cve-2023-0286".into()
+        href: "https://access.redhat.com/security/cve/cve-2023-0286".into(),
+        ids: vec![],
+        fetched: None,
+        aliases: vec![],
+        severity: Some(Severity::High),
+        sources: vec![],
     }],
     snyk: None,
+    signature_verified: None,
+    signer: None,
+    provenance: None,
+    certifications: vec![],
+    scorecard: None,
+    licenses: vec![],
+    degraded: vec![],
+    warnings: vec![],
+    unknown: false,
 }))]
 pub struct Package {
     #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -26,41 +49,346 @@ pub struct Package {
     pub href: Option<String>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub sbom: Option<String>,
+    /// When the SBOM was indexed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "sbomFetched")]
+    pub sbom_fetched: Option<DateTime<Utc>>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub trusted: Option<bool>,
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     #[serde(rename = "trustedVersions")]
     pub trusted_versions: Vec<PackageRef>,
-    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[serde(default)]
     pub vulnerabilities: Vec<VulnerabilityRef>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub snyk: Option<SnykData>,
+    /// Whether a signature of the artifact verified, if it could be checked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "signatureVerified")]
+    pub signature_verified: Option<bool>,
+    /// The identity which signed the artifact
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signer: Option<String>,
+    /// How the artifact was built, from its SLSA attestation
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<Provenance>,
+    /// Statements certifying the package as good or bad, a bad one makes it untrusted
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub certifications: Vec<Certification>,
+    /// The OpenSSF Scorecard of the source repository
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scorecard: Option<ScorecardSummary>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub licenses: Vec<License>,
+    /// Providers which were unavailable, their data is missing from the response
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub degraded: Vec<String>,
+    /// Why the providers in `degraded` are missing, the data of healthy providers is still included
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<ProviderWarning>,
+    /// Neither Guac nor the SBOM registry know the package, `trusted` is unknown then instead of
+    /// `false`. Only reported if `api.unknown_purl` is `empty`, unknown packages are not found
+    /// otherwise
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub unknown: bool,
+}
+
+/// A provider whose data is missing from a response.
+#[derive(Clone, Debug, PartialEq, Eq, ToSchema, Serialize, Deserialize)]
+pub struct ProviderWarning {
+    pub provider: String,
+    pub reason: WarningReason,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ToSchema, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum WarningReason {
+    /// The provider was unavailable or responded with an error
+    Failed,
+    /// The provider didn't respond within its timeout
+    TimedOut,
+}
+
+impl WarningReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Failed => "failed",
+            Self::TimedOut => "timedOut",
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, ToSchema, Serialize, Deserialize)]
 #[schema(example = json!(VulnerabilityRef {
 cve: "cve-2023-0286".into(),
-href: "https://access.redhat.com/security/cve/cve-2023-0286".into()
+href: "https://access.redhat.com/security/cve/cve-2023-0286".into(),
+ids: vec![],
+fetched: None,
+aliases: vec![],
+severity: Some(Severity::High),
+sources: vec![VulnerabilitySource {
+    provider: "guac".into(),
+    href: "https://access.redhat.com/security/cve/cve-2023-0286".into(),
+    severity: None,
+    fetched: None,
+}, VulnerabilitySource {
+    provider: "ghsa".into(),
+    href: "https://github.com/advisories/GHSA-x4qr-2fvf-3mr5".into(),
+    severity: Some(Severity::High),
+    fetched: None,
+}],
 }))]
 pub struct VulnerabilityRef {
     pub cve: String,
     pub href: String,
+    /// Identifiers of the vulnerability in the systems it was reported by, only included on request
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ids: Vec<ProviderId>,
+    /// When the vulnerability was last fetched from or confirmed by its provider
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fetched: Option<DateTime<Utc>>,
+    /// Other identifiers of the vulnerability, e.g. its GHSA or Snyk id
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+    /// The consensus of the severities reported by its sources
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub severity: Option<Severity>,
+    /// What each provider reported about the vulnerability
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sources: Vec<VulnerabilitySource>,
+}
+
+/// A vulnerability as reported by one provider.
+#[derive(Clone, Debug, PartialEq, Eq, ToSchema, Serialize, Deserialize)]
+pub struct VulnerabilitySource {
+    pub provider: String,
+    pub href: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub severity: Option<Severity>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fetched: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, ToSchema, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+impl Severity {
+    /// Parse the severity levels of the providers, e.g. `MODERATE` of GHSA or `important` of Red
+    /// Hat.
+    pub fn parse(severity: &str) -> Option<Self> {
+        match severity.to_lowercase().as_str() {
+            "low" => Some(Self::Low),
+            "medium" | "moderate" => Some(Self::Medium),
+            "high" | "important" => Some(Self::High),
+            "critical" => Some(Self::Critical),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Low => "low",
+            Self::Medium => "medium",
+            Self::High => "high",
+            Self::Critical => "critical",
+        }
+    }
+}
+
+/// An identifier native to a data provider (e.g. a Snyk issue id).
+#[derive(Clone, Debug, PartialEq, Eq, ToSchema, Serialize, Deserialize)]
+#[schema(example = json!(ProviderId {
+provider: "snyk".into(),
+id: "SNYK-JAVA-IOVERTX-3318108".into(),
+}))]
+pub struct ProviderId {
+    pub provider: String,
+    pub id: String,
+}
+
+/// A license of a package, as an SPDX license expression.
+#[derive(Clone, Debug, PartialEq, Eq, ToSchema, Serialize, Deserialize)]
+#[schema(example = json!(License {
+expression: "Apache-2.0 OR EPL-2.0".into(),
+source: "sbom".into(),
+}))]
+pub struct License {
+    pub expression: String,
+    /// Where the license was found, `sbom` or `guac`
+    pub source: String,
+}
+
+/// The result of checking the licenses of a package against the license policy.
+#[derive(Clone, Debug, PartialEq, Eq, ToSchema, Serialize, Deserialize)]
+#[schema(example = json!(LicenseCheck {
+allowed: false,
+licenses: vec![License {
+    expression: "GPL-3.0-only".into(),
+    source: "sbom".into(),
+}],
+violations: vec![LicenseViolation {
+    expression: Some("GPL-3.0-only".into()),
+    reason: "Not allowed: GPL-3.0-only".into(),
+}],
+}))]
+pub struct LicenseCheck {
+    pub allowed: bool,
+    pub licenses: Vec<License>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub violations: Vec<LicenseViolation>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, ToSchema, Serialize, Deserialize)]
+pub struct LicenseViolation {
+    /// The offending expression, `None` if no license is known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expression: Option<String>,
+    pub reason: String,
+}
+
+/// The OpenSSF Scorecard result of a source repository.
+#[derive(Clone, Debug, PartialEq, ToSchema, Serialize, Deserialize)]
+#[schema(example = json!(ScorecardSummary {
+repository: "github.com/eclipse-vertx/vert.x".into(),
+score: 6.4,
+failing_checks: vec!["Fuzzing".into(), "Signed-Releases".into()],
+}))]
+pub struct ScorecardSummary {
+    pub repository: String,
+    /// The aggregate score, out of 10
+    pub score: f64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[serde(rename = "failingChecks")]
+    pub failing_checks: Vec<String>,
+}
+
+/// A statement certifying a package as good or bad, recorded in Guac.
+#[derive(Clone, Debug, PartialEq, Eq, ToSchema, Serialize, Deserialize)]
+#[schema(example = json!(Certification {
+verdict: Verdict::Bad,
+justification: "Malicious package".into(),
+origin: "https://example.com/advisories".into(),
+}))]
+pub struct Certification {
+    pub verdict: Verdict,
+    pub justification: String,
+    pub origin: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ToSchema, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Verdict {
+    Good,
+    Bad,
+}
+
+/// The build of an artifact, as attested by SLSA provenance.
+#[derive(Clone, Debug, PartialEq, Eq, ToSchema, Serialize, Deserialize)]
+#[schema(example = json!(Provenance {
+builder_id: "https://github.com/slsa-framework/slsa-github-generator/.github/workflows/generator_generic_slsa3.yml@refs/tags/v1.5.0".into(),
+build_type: "https://github.com/slsa-framework/slsa-github-generator/generic@v1".into(),
+slsa_level: Some(3),
+}))]
+pub struct Provenance {
+    #[serde(rename = "builderId")]
+    pub builder_id: String,
+    #[serde(rename = "buildType")]
+    pub build_type: String,
+    /// The SLSA build level, if the builder is known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "slsaLevel")]
+    pub slsa_level: Option<u8>,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, ToSchema, Serialize, Deserialize)]
 #[schema(example = json!(PackageRef {
     purl: "pkg:rpm/redhat/openssl@1.1.1k-7.el8_6".to_string(),
-    href: format!("/api/package?purl={}", &urlencoding::encode("pkg:rpm/redhat/openssl@1.1.1k-7.el8_6")),
+    href: format!("/api/v1/package?purl={}", &urlencoding::encode("pkg:rpm/redhat/openssl@1.1.1k-7.el8_6")),
     trusted: Some(true),
-    sbom: None
+    unverified: false,
+    sbom: None,
+    vulnerabilities: None,
+    published: None,
+    fetched: None,
+    relation: None,
+    dependency: None,
+    degraded: vec![],
 }))]
 pub struct PackageRef {
     pub purl: String,
     pub href: String,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub trusted: Option<bool>,
+    /// Reported by a fallback source (e.g. deps.dev) instead of Guac, and not verified
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub unverified: bool,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub sbom: Option<String>,
+    /// The number of vulnerabilities of the version, only reported by version queries
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vulnerabilities: Option<usize>,
+    /// When the version was released, if known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub published: Option<DateTime<Utc>>,
+    /// When the version was last fetched from or confirmed by its source
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fetched: Option<DateTime<Utc>>,
+    /// How the version relates to the queried one, only reported by version queries
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub relation: Option<VersionRelation>,
+    /// How the queried package depends on this one, only reported for direct dependencies
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dependency: Option<DependencyEdge>,
+    /// Providers which were unavailable checking the package for a filter, it is kept as it may
+    /// match
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub degraded: Vec<String>,
+}
+
+/// How a package depends on another one, as far as Guac, deps.dev or its SBOM tell.
+#[derive(Clone, Debug, Default, PartialEq, Eq, ToSchema, Serialize, Deserialize)]
+pub struct DependencyEdge {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<DependencyScope>,
+    /// The range of versions declared as dependency, e.g. `^4.3.0`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "versionRange")]
+    pub version_range: Option<String>,
+    /// The kind of relationship in the SBOM, e.g. `DEV_DEPENDENCY_OF` in SPDX or `dependsOn` in
+    /// CycloneDX
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub relationship: Option<String>,
+}
+
+/// When a dependency is needed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ToSchema, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DependencyScope {
+    Runtime,
+    /// Only needed for developing the package
+    Development,
+    Optional,
+    Build,
+    Test,
+    /// Provided by the environment the package runs in
+    Provided,
+    /// Not part of the package, CycloneDX components `excluded` from it
+    Excluded,
+}
+
+/// How a version relates to another version of the same package, in the order of its ecosystem.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ToSchema, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VersionRelation {
+    Upgrade,
+    Downgrade,
+    Same,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, ToSchema, Serialize, Deserialize)]
@@ -70,13 +398,92 @@ pub struct SnykData;
 #[schema(example = json!(vec![
     PackageRef {
         purl: "pkg:maven/io.vertx/vertx-web-common@4.3.7".to_string(),
-        href: format!("/api/package?purl={}", &urlencoding::encode("pkg:maven/io.vertx/vertx-web-common@4.3.7")),
+        href: format!("/api/v1/package?purl={}", &urlencoding::encode("pkg:maven/io.vertx/vertx-web-common@4.3.7")),
         trusted: None,
+        unverified: false,
         sbom: None,
+        vulnerabilities: None,
+        published: None,
+        fetched: None,
+        relation: None,
+        dependency: Some(DependencyEdge {
+            scope: Some(DependencyScope::Runtime),
+            version_range: Some("4.3.7".to_string()),
+            relationship: Some("dependsOn".to_string()),
+        }),
+        degraded: vec![],
     }
 ]))]
 pub struct PackageDependencies(pub Vec<PackageRef>);
 
+/// The dependency chains through which a package depends on another one.
+#[derive(Clone, Debug, PartialEq, ToSchema, Serialize, Deserialize)]
+#[schema(example = json!({
+    "from": "pkg:maven/io.quarkus/quarkus-vertx-http@2.16.2.Final",
+    "to": "pkg:maven/io.netty/netty-codec-http@4.1.86.Final",
+    "paths": [[
+        "pkg:maven/io.quarkus/quarkus-vertx-http@2.16.2.Final",
+        "pkg:maven/io.vertx/vertx-web@4.3.7",
+        "pkg:maven/io.vertx/vertx-core@4.3.7",
+        "pkg:maven/io.netty/netty-codec-http@4.1.86.Final"
+    ]]
+}))]
+pub struct DependencyPaths {
+    pub from: String,
+    pub to: String,
+    /// The shortest chains, each starting with `from` and ending with `to`, empty if `to` is not
+    /// a dependency of `from`
+    pub paths: Vec<Vec<String>>,
+}
+
+/// The trusted counterparts of a community package.
+#[derive(Clone, Debug, PartialEq, Eq, ToSchema, Serialize, Deserialize)]
+#[schema(example = json!({
+    "purl": "pkg:maven/io.vertx/vertx-web@4.3.4",
+    "counterparts": [{
+        "purl": "pkg:maven/io.vertx/vertx-web@4.3.4.redhat-00007",
+        "href": "/api/v1/package?purl=pkg%3Amaven%2Fio.vertx%2Fvertx-web%404.3.4.redhat-00007",
+        "confidence": "high",
+        "evidence": ["equal", "version"]
+    }]
+}))]
+pub struct Counterparts {
+    pub purl: String,
+    /// The most confident mappings first
+    pub counterparts: Vec<Counterpart>,
+}
+
+/// A trusted package some community package maps to.
+#[derive(Clone, Debug, PartialEq, Eq, ToSchema, Serialize, Deserialize)]
+pub struct Counterpart {
+    pub purl: String,
+    pub href: String,
+    pub confidence: Confidence,
+    /// Why the package is considered a counterpart
+    pub evidence: Vec<CounterpartEvidence>,
+}
+
+/// How confident a mapping to a trusted counterpart is, most confident first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, ToSchema, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Confidence {
+    High,
+    Medium,
+    Low,
+}
+
+/// The relations a mapping to a trusted counterpart is based on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, ToSchema, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CounterpartEvidence {
+    /// Guac records both packages as equal (`pkgEqual`)
+    Equal,
+    /// The trusted package is a rebuild of the community version
+    Version,
+    /// Both packages are built from the same source repository (`hasSourceAt`)
+    Source,
+}
+
 impl Deref for PackageDependencies {
     type Target = [PackageRef];
 
@@ -89,9 +496,16 @@ impl Deref for PackageDependencies {
 #[schema(example = json!(vec![
     PackageRef {
         purl: "pkg:maven/io.quarkus/quarkus-vertx-http@2.16.2.Final".to_string(),
-        href: format!("/api/package?purl={}", &urlencoding::encode("pkg:maven/io.quarkus/quarkus-vertx-http@2.16.2.Final")),
+        href: format!("/api/v1/package?purl={}", &urlencoding::encode("pkg:maven/io.quarkus/quarkus-vertx-http@2.16.2.Final")),
         trusted: None,
+        unverified: false,
         sbom: None,
+        vulnerabilities: None,
+        published: None,
+        fetched: None,
+        relation: None,
+        dependency: None,
+        degraded: vec![],
     }
 ]))]
 pub struct PackageDependents(pub Vec<PackageRef>);
@@ -113,3 +527,33 @@ impl PackageList {
         &self.0
     }
 }
+
+/// The result for a single purl of a batch request.
+///
+/// The `status` is the HTTP status code the item would have if it was requested on its own.
+#[derive(Clone, Debug, PartialEq, Eq, ToSchema, Serialize, Deserialize)]
+#[aliases(
+    PackageBatchItem = BatchItem<Package>,
+    DependenciesBatchItem = BatchItem<PackageDependencies>,
+    VersionsBatchItem = BatchItem<Vec<PackageRef>>,
+    LicenseCheckBatchItem = BatchItem<LicenseCheck>
+)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchItem<T> {
+    pub purl: String,
+    /// The form of the purl which was processed, if it had to be repaired
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub canonical_purl: Option<String>,
+    pub status: u16,
+    // `Option::default` keeps serde from requiring `T: Default`
+    #[serde(default = "Option::default", skip_serializing_if = "Option::is_none")]
+    pub result: Option<T>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The kind of error, as the `code` of the problem the item would fail with on its own
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    /// The provider which failed to answer, for upstream errors
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+}
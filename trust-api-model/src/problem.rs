@@ -0,0 +1,38 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// An RFC 7807 problem, the body of all error responses.
+#[derive(Clone, Debug, PartialEq, Eq, ToSchema, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[schema(example = json!({
+    "type": "about:blank",
+    "title": "Not Found",
+    "status": 404,
+    "detail": "Package pkg:rpm/redhat/openssl@1.1.1k-7.el8_9 was not found",
+    "error": "Package pkg:rpm/redhat/openssl@1.1.1k-7.el8_9 was not found",
+    "code": "PackageNotFound"
+}))]
+pub struct Problem {
+    #[serde(rename = "type")]
+    pub ty: String,
+    pub title: String,
+    pub status: u16,
+    pub detail: String,
+    /// The same as `detail`, for clients of the previous error format
+    #[serde(default)]
+    pub error: String,
+    /// The kind of error, the name of the variant of the endpoint's error type
+    #[serde(default)]
+    pub code: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    /// The failing service, for errors of an upstream service
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upstream: Option<String>,
+    /// The status the upstream service responded with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upstream_status: Option<u16>,
+    /// Identifies the upstream error in the logs of the service
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
+}
@@ -0,0 +1,74 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use utoipa::ToSchema;
+
+use super::pkg::Severity;
+
+/// A named version of a product, grouping the SBOMs of its deliverables.
+#[derive(Clone, Debug, PartialEq, ToSchema, Serialize, Deserialize)]
+#[schema(example = json!({
+    "id": "ubi9-9.2",
+    "name": "Universal Base Image 9",
+    "version": "9.2",
+    "sboms": ["pkg:oci/ubi9@sha256:d03c30dddefc59229303f49a94105d537ac324c86df9177ec5be37d30d44672d?arch=x86_64&repository_url=registry.redhat.io/ubi9"]
+}))]
+pub struct Product {
+    /// The id of the product in the API, e.g. `rhel-9.2`
+    pub id: String,
+    pub name: String,
+    pub version: String,
+    /// The purls of the SBOMs of the product
+    pub sboms: Vec<String>,
+}
+
+/// The number of distinct vulnerabilities of each severity.
+#[derive(Clone, Debug, Default, PartialEq, ToSchema, Serialize, Deserialize)]
+pub struct SeverityCounts {
+    pub critical: usize,
+    pub high: usize,
+    pub medium: usize,
+    pub low: usize,
+    /// Vulnerabilities without a known severity
+    pub unknown: usize,
+}
+
+/// A component affected by vulnerabilities.
+#[derive(Clone, Debug, PartialEq, ToSchema, Serialize, Deserialize)]
+pub struct RiskyComponent {
+    pub purl: String,
+    pub vulnerabilities: usize,
+    /// The highest severity of its vulnerabilities
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub severity: Option<Severity>,
+}
+
+/// The rollup of the components of a product or SBOM, to render a dashboard with one call.
+#[derive(Clone, Debug, PartialEq, ToSchema, Serialize, Deserialize)]
+#[schema(example = json!({
+    "components": 3,
+    "trusted": 2,
+    "vulnerable": 1,
+    "unresolved": 0,
+    "severities": {"critical": 0, "high": 1, "medium": 0, "low": 0, "unknown": 0},
+    "riskiest": [{"purl": "pkg:rpm/redhat/openssl@1.1.1k-7.el8_6", "vulnerabilities": 1, "severity": "high"}],
+    "withTrustedAlternatives": 1,
+    "licenses": {"Apache-2.0": 2, "OpenSSL": 1}
+}))]
+pub struct ProductSummary {
+    /// The number of components with a purl, including the SBOMs themselves
+    pub components: usize,
+    /// The number of trusted components
+    pub trusted: usize,
+    /// The number of components affected by vulnerabilities
+    pub vulnerable: usize,
+    /// The number of components which could not be looked up
+    pub unresolved: usize,
+    pub severities: SeverityCounts,
+    /// The components with the most severe vulnerabilities, most at risk first
+    pub riskiest: Vec<RiskyComponent>,
+    /// The number of untrusted components with trusted versions to switch to
+    #[serde(rename = "withTrustedAlternatives")]
+    pub with_trusted_alternatives: usize,
+    /// The number of components of each license expression
+    pub licenses: BTreeMap<String, usize>,
+}
@@ -0,0 +1,49 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[derive(Clone, Debug, PartialEq, ToSchema, Serialize, Deserialize)]
+#[schema(example = json!({"cve": "CVE-2023-0286"}))]
+pub struct AffectedRequest {
+    /// The id of the vulnerability, e.g. `CVE-2023-0286`
+    pub cve: String,
+}
+
+/// A component of a product affected by a vulnerability.
+#[derive(Clone, Debug, PartialEq, ToSchema, Serialize, Deserialize)]
+pub struct AffectedComponent {
+    pub purl: String,
+    /// The dependency paths from the product to the component, each starting with the product
+    pub paths: Vec<Vec<String>>,
+}
+
+/// A product with an SBOM containing components affected by a vulnerability.
+#[derive(Clone, Debug, PartialEq, ToSchema, Serialize, Deserialize)]
+pub struct AffectedProduct {
+    pub purl: String,
+    pub sbom: String,
+    pub components: Vec<AffectedComponent>,
+}
+
+/// The products affected by a vulnerability.
+#[derive(Clone, Debug, PartialEq, ToSchema, Serialize, Deserialize)]
+#[schema(example = json!({
+    "cve": "CVE-2023-0286",
+    "components": ["pkg:rpm/redhat/openssl@3.0.1-47.el9_1?arch=s390x&epoch=1"],
+    "products": [{
+        "purl": "pkg:oci/ubi9@sha256:23879d61ecb319c10e60741d04b5f2f121e62333662df2439f338da5f457a4e7",
+        "sbom": "/api/v1/package/sbom?purl=pkg%3Aoci%2Fubi9%40sha256%3A23879d61ecb319c10e60741d04b5f2f121e62333662df2439f338da5f457a4e7",
+        "components": [{
+            "purl": "pkg:rpm/redhat/openssl@3.0.1-47.el9_1?arch=s390x&epoch=1",
+            "paths": [[
+                "pkg:oci/ubi9@sha256:23879d61ecb319c10e60741d04b5f2f121e62333662df2439f338da5f457a4e7",
+                "pkg:rpm/redhat/openssl@3.0.1-47.el9_1?arch=s390x&epoch=1"
+            ]]
+        }]
+    }]
+}))]
+pub struct AffectedReport {
+    pub cve: String,
+    /// The packages the vulnerability affects
+    pub components: Vec<String>,
+    pub products: Vec<AffectedProduct>,
+}
@@ -0,0 +1,50 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::pkg::PackageBatchItem;
+
+/// A vulnerability found in the components of an SBOM.
+#[derive(Clone, Debug, PartialEq, ToSchema, Serialize, Deserialize)]
+pub struct AffectingVulnerability {
+    pub cve: String,
+    pub href: String,
+    /// The components affected by the vulnerability
+    pub purls: Vec<String>,
+}
+
+/// The consolidated result of analyzing all components of an SBOM.
+#[derive(Clone, Debug, PartialEq, ToSchema, Serialize, Deserialize)]
+#[schema(example = json!({
+    "components": 2,
+    "vulnerable": 1,
+    "vulnerabilities": [{
+        "cve": "cve-2023-0286",
+        "href": "https://access.redhat.com/security/cve/cve-2023-0286",
+        "purls": ["pkg:rpm/redhat/openssl@1.1.1k-7.el8_6"]
+    }],
+    "packages": [
+        {
+            "purl": "pkg:rpm/redhat/openssl@1.1.1k-7.el8_6",
+            "status": 200,
+            "result": {
+                "purl": "pkg:rpm/redhat/openssl@1.1.1k-7.el8_6",
+                "trusted": true,
+                "vulnerabilities": [{"cve": "cve-2023-0286", "href": "https://access.redhat.com/security/cve/cve-2023-0286", "severity": "high"}]
+            }
+        },
+        {
+            "purl": "pkg:rpm/redhat/zlib@1.2.11-40.el9",
+            "status": 200,
+            "result": {"purl": "pkg:rpm/redhat/zlib@1.2.11-40.el9", "trusted": true, "vulnerabilities": []}
+        }
+    ]
+}))]
+pub struct SbomAnalysis {
+    /// The number of components with a purl
+    pub components: usize,
+    /// The number of components affected by vulnerabilities
+    pub vulnerable: usize,
+    pub vulnerabilities: Vec<AffectingVulnerability>,
+    /// The result of each component, as returned by `POST /api/package`
+    pub packages: Vec<PackageBatchItem>,
+}
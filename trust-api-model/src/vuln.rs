@@ -17,9 +17,16 @@ use super::pkg::PackageRef;
     }),
     packages: vec![PackageRef {
         purl: "pkg:rpm/redhat/openssl@1.1.1k-7.el8_6".to_string(),
-        href: format!("/api/package?purl={}", &urlencoding::encode("pkg:rpm/redhat/openssl@1.1.1k-7.el8_6")),
+        href: format!("/api/v1/package?purl={}", &urlencoding::encode("pkg:rpm/redhat/openssl@1.1.1k-7.el8_6")),
         trusted: Some(true),
+        unverified: false,
         sbom: None,
+        vulnerabilities: None,
+        published: None,
+        fetched: None,
+        relation: None,
+        dependency: None,
+        degraded: vec![],
     }
 ]
 }))]
@@ -0,0 +1,239 @@
+//! A command line client of the trusted content API, for developers and for smoke testing
+//! deployments.
+
+use anyhow::{anyhow, bail};
+use clap::Parser;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use trust_api_client::model::prelude::*;
+use trust_api_client::Client;
+
+mod table;
+
+#[derive(clap::Parser, Debug)]
+#[command(
+    name = "trust",
+    version = env!("CARGO_PKG_VERSION"),
+    about = "Query a trusted content API instance",
+    long_about = None
+)]
+struct Cli {
+    /// Base URL of the instance
+    #[arg(
+        long,
+        global = true,
+        env = "TRUST_API_URL",
+        default_value = "http://localhost:8080"
+    )]
+    url: String,
+
+    /// Sent as bearer token, for instances requiring authentication
+    #[arg(long, global = true, env = "TRUST_API_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+
+    #[arg(short, long, global = true, value_enum, default_value_t = Output::Table)]
+    output: Output,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Output {
+    Table,
+    /// The responses of the API, as they are
+    Json,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Show the trusted versions and vulnerabilities of a package
+    Package { purl: String },
+    /// List the dependencies of a package
+    Deps {
+        purl: String,
+        /// Include the dependencies of dependencies, as deep as the instance allows
+        #[arg(long)]
+        transitive: bool,
+        /// How many levels of dependencies to list
+        #[arg(long, default_value_t = 1, conflicts_with = "transitive")]
+        depth: usize,
+    },
+    #[command(subcommand)]
+    Sbom(SbomCommand),
+}
+
+/// Work with SBOMs
+#[derive(clap::Subcommand, Debug)]
+enum SbomCommand {
+    /// Analyze the components of a CycloneDX or SPDX SBOM
+    Analyze { file: PathBuf },
+}
+
+fn json<T: Serialize>(value: &T) -> anyhow::Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+/// The result of a batch item, failing with its error.
+fn result<T>(item: BatchItem<T>) -> anyhow::Result<T> {
+    match item.result {
+        Some(result) if item.status == 200 => Ok(result),
+        _ => Err(anyhow!(
+            "{} failed with {}: {}",
+            item.purl,
+            item.status,
+            item.error.unwrap_or_default()
+        )),
+    }
+}
+
+fn vulnerability_rows(vulnerabilities: &[VulnerabilityRef]) -> Vec<Vec<String>> {
+    vulnerabilities
+        .iter()
+        .map(|v| {
+            vec![
+                v.cve.clone(),
+                v.severity.map_or("-", |s| s.as_str()).to_string(),
+                v.href.clone(),
+            ]
+        })
+        .collect()
+}
+
+async fn package(client: &Client, output: Output, purl: &str) -> anyhow::Result<()> {
+    let package = client.get_package(purl).await?;
+    if output == Output::Json {
+        return json(&package);
+    }
+
+    println!("Package: {}", package.purl.as_deref().unwrap_or(purl));
+    println!("Trusted: {}", table::flag(package.trusted));
+    for warning in &package.warnings {
+        eprintln!(
+            "Warning: {} is missing, it {}",
+            warning.provider,
+            match warning.reason {
+                WarningReason::Failed => "failed",
+                WarningReason::TimedOut => "timed out",
+            }
+        );
+    }
+
+    if !package.trusted_versions.is_empty() {
+        println!();
+        let rows: Vec<Vec<String>> = package
+            .trusted_versions
+            .iter()
+            .map(|v| vec![v.purl.clone()])
+            .collect();
+        table::print(&["TRUSTED VERSION"], &rows);
+    }
+    println!();
+    if package.vulnerabilities.is_empty() {
+        println!("No known vulnerabilities");
+    } else {
+        table::print(
+            &["VULNERABILITY", "SEVERITY", "HREF"],
+            &vulnerability_rows(&package.vulnerabilities),
+        );
+    }
+    Ok(())
+}
+
+async fn deps(client: &Client, output: Output, purl: &str, depth: usize) -> anyhow::Result<()> {
+    let mut items = client
+        .query_dependencies(&[purl.to_string()], depth)
+        .await?;
+    let item = items
+        .pop()
+        .ok_or_else(|| anyhow!("No result for {}", purl))?;
+    if output == Output::Json {
+        return json(&item);
+    }
+
+    let dependencies = result(item)?;
+    let rows: Vec<Vec<String>> = dependencies
+        .iter()
+        .map(|d| vec![d.purl.clone(), table::flag(d.trusted)])
+        .collect();
+    table::print(&["DEPENDENCY", "TRUSTED"], &rows);
+    Ok(())
+}
+
+async fn analyze(client: &Client, output: Output, file: &Path) -> anyhow::Result<()> {
+    let data = tokio::fs::read(file).await?;
+    let sbom: serde_json::Value = serde_json::from_slice(&data)
+        .map_err(|e| anyhow!("{} is not a JSON SBOM: {}", file.display(), e))?;
+    let analysis = client.upload_sbom(&sbom).await?;
+    if output == Output::Json {
+        return json(&analysis);
+    }
+
+    println!(
+        "{} components, {} vulnerable",
+        analysis.components, analysis.vulnerable
+    );
+    let failed = analysis.packages.iter().filter(|p| p.status != 200).count();
+    if failed > 0 {
+        eprintln!("Warning: {} components could not be analyzed", failed);
+    }
+    if !analysis.vulnerabilities.is_empty() {
+        println!();
+        let rows: Vec<Vec<String>> = analysis
+            .vulnerabilities
+            .iter()
+            .map(|v| vec![v.cve.clone(), v.purls.join(", ")])
+            .collect();
+        table::print(&["VULNERABILITY", "AFFECTED COMPONENTS"], &rows);
+    }
+    Ok(())
+}
+
+/// The largest `depth` of dependency queries the instance accepts, from its capabilities.
+async fn max_depth(client: &Client) -> anyhow::Result<usize> {
+    let capabilities = client.get_capabilities().await?;
+    capabilities["limits"]["maxDepth"]
+        .as_u64()
+        .map(|depth| depth as usize)
+        .ok_or_else(|| anyhow!("The instance doesn't tell its maximum depth"))
+}
+
+async fn run(cli: Cli) -> anyhow::Result<()> {
+    let mut client = Client::new(&cli.url);
+    if let Some(token) = &cli.token {
+        client = client.with_token(token);
+    }
+
+    match &cli.command {
+        Command::Package { purl } => package(&client, cli.output, purl).await,
+        Command::Deps {
+            purl,
+            transitive,
+            depth,
+        } => {
+            let depth = if *transitive {
+                max_depth(&client).await?
+            } else {
+                *depth
+            };
+            if depth == 0 {
+                bail!("The depth must be at least 1");
+            }
+            deps(&client, cli.output, purl, depth).await
+        }
+        Command::Sbom(SbomCommand::Analyze { file }) => analyze(&client, cli.output, file).await,
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    match run(Cli::parse()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}